@@ -13,8 +13,9 @@
 // limitations under the License.
 
 use futures::future::ok;
+use handlers::{CHAIN_LOOKUP_PATH, HEALTH_PATH, JSONRPC_PATH, POOL_CHECK_PATH, READY_PATH};
 use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
-use hyper::{Body, Request, Response, StatusCode};
+use hyper::{Body, Method, Request, Response, StatusCode};
 use ring::constant_time::verify_slices_are_equal;
 use router::{Handler, HandlerObj, ResponseFuture};
 
@@ -52,6 +53,81 @@ impl Handler for BasicAuthMiddleware {
 	}
 }
 
+/// Marks requests made with the read-only secret, for handlers telling reads
+/// from writes themselves
+pub struct ReadOnlyAccess;
+
+/// Basic Authentication Middleware with two roles: the owner secret opens
+/// up everything, the read-only one only requests that don't change anything
+/// (peer bans, compaction or pool submissions are owner only). JSON-RPC calls
+/// are all POSTs, so they go through marked read-only and the methods that
+/// change something get turned down there. Chain lookups are POSTs too, only
+/// for their size, and go through.
+pub struct RoleAuthMiddleware {
+	owner_auth: String,
+	read_auth: Option<String>,
+	basic_realm: String,
+}
+
+impl RoleAuthMiddleware {
+	pub fn new(
+		owner_auth: String,
+		read_auth: Option<String>,
+		basic_realm: String,
+	) -> RoleAuthMiddleware {
+		RoleAuthMiddleware {
+			owner_auth,
+			read_auth,
+			basic_realm,
+		}
+	}
+}
+
+impl Handler for RoleAuthMiddleware {
+	fn call(
+		&self,
+		mut req: Request<Body>,
+		mut handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let matches = |auth: &str| {
+			req.headers().contains_key(AUTHORIZATION)
+				&& verify_slices_are_equal(req.headers()[AUTHORIZATION].as_bytes(), auth.as_bytes())
+					.is_ok()
+		};
+		let owner = matches(&self.owner_auth);
+		let read_only = match self.read_auth {
+			Some(ref auth) => matches(auth),
+			None => false,
+		};
+		// probes from orchestration don't authenticate
+		let probe = *req.method() == Method::GET
+			&& (req.uri().path() == HEALTH_PATH || req.uri().path() == READY_PATH);
+		if owner || probe {
+			handlers.next().unwrap().call(req, handlers)
+		} else if read_only {
+			if req.uri().path().starts_with(JSONRPC_PATH) {
+				req.extensions_mut().insert(ReadOnlyAccess);
+				return handlers.next().unwrap().call(req, handlers);
+			}
+			// posted to, but only reading
+			if req.uri().path() == CHAIN_LOOKUP_PATH || req.uri().path() == POOL_CHECK_PATH {
+				return handlers.next().unwrap().call(req, handlers);
+			}
+			match *req.method() {
+				Method::GET | Method::HEAD => handlers.next().unwrap().call(req, handlers),
+				// Forbidden 403
+				_ => Box::new(ok(Response::builder()
+					.status(StatusCode::FORBIDDEN)
+					.body(Body::from("owner secret required"))
+					.unwrap())),
+			}
+		} else {
+			// Unauthorized 401
+			unauthorized_response(&self.basic_realm)
+		}
+	}
+}
+
 fn unauthorized_response(basic_realm: &str) -> ResponseFuture {
 	let response = Response::builder()
 		.status(StatusCode::UNAUTHORIZED)
@@ -14,9 +14,10 @@
 
 //! High level JSON/HTTP client API
 
+use core::ser::{self, Readable};
 use failure::{Fail, ResultExt};
 use http::uri::{InvalidUri, Uri};
-use hyper::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
+use hyper::header::{HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT};
 use hyper::rt::{Future, Stream};
 use hyper::{Body, Client, Request};
 use serde::{Deserialize, Serialize};
@@ -24,10 +25,13 @@ use serde_json;
 
 use futures::future::{err, ok, Either};
 use hyper_rustls;
+use std::time::Duration;
 use tokio::runtime::Runtime;
+use tokio::timer::Timeout;
 
 use rest::{Error, ErrorKind};
 use util::to_base64;
+use web::BINARY_CONTENT_TYPE;
 
 pub type ClientResponseFuture<T> = Box<Future<Item = T, Error = Error> + Send>;
 
@@ -41,6 +45,22 @@ where
 	handle_request(build_request(url, "GET", api_secret, None)?)
 }
 
+/// Issues a HTTP GET request asking for the binary serialization of a core
+/// type, as served for blocks and headers, and deserializes it.
+pub fn get_binary<T>(url: &str, api_secret: Option<String>) -> Result<T, Error>
+where
+	T: Readable,
+{
+	let mut req = build_request(url, "GET", api_secret, None)?;
+	req.headers_mut()
+		.insert(ACCEPT, HeaderValue::from_static(BINARY_CONTENT_TYPE));
+	let task = send_request_bytes_async(req);
+	let mut rt = Runtime::new().unwrap();
+	let data = rt.block_on(task)?;
+	ser::deserialize(&mut &data[..])
+		.map_err(|e| ErrorKind::ResponseError(format!("Cannot deserialize response: {}", e)).into())
+}
+
 /// Helper function to easily issue an async HTTP GET request against a given
 /// URL that returns a future. Handles request building, JSON deserialization
 /// and response code checking.
@@ -100,6 +120,26 @@ where
 	Ok(())
 }
 
+/// As `post_no_ret`, giving up on servers that haven't answered in time.
+pub fn post_no_ret_timeout<IN>(
+	url: &str,
+	api_secret: Option<String>,
+	input: &IN,
+	timeout: Duration,
+) -> Result<(), Error>
+where
+	IN: Serialize,
+{
+	let req = create_post_request(url, api_secret, input)?;
+	let task = Timeout::new(send_request_async(req), timeout).map_err(move |e| match e.into_inner() {
+		Some(e) => e,
+		None => ErrorKind::RequestError(format!("No response within {:?}", timeout)).into(),
+	});
+	let mut rt = Runtime::new().unwrap();
+	rt.block_on(task)?;
+	Ok(())
+}
+
 /// Helper function to easily issue an async HTTP POST request with the
 /// provided JSON object as body on a given URL that returns a future. Handles
 /// request building, JSON serialization and deserialization, and response code
@@ -186,6 +226,12 @@ where
 }
 
 fn send_request_async(req: Request<Body>) -> Box<Future<Item = String, Error = Error> + Send> {
+	Box::new(send_request_bytes_async(req).map(|data| String::from_utf8_lossy(&data).to_string()))
+}
+
+fn send_request_bytes_async(
+	req: Request<Body>,
+) -> Box<Future<Item = Vec<u8>, Error = Error> + Send> {
 	let https = hyper_rustls::HttpsConnector::new(1);
 	let client = Client::builder().build::<_, Body>(https);
 	Box::new(
@@ -204,7 +250,7 @@ fn send_request_async(req: Request<Body>) -> Box<Future<Item = String, Error = E
 								ErrorKind::RequestError(format!("Cannot read response body: {}", e))
 									.into()
 							}).concat2()
-							.and_then(|ch| ok(String::from_utf8_lossy(&ch.to_vec()).to_string())),
+							.and_then(|ch| ok(ch.to_vec())),
 					)
 				}
 			}),
@@ -0,0 +1,136 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cross-origin resource sharing, so browser based explorers and wallets
+//! served from the allowed origins can call the API.
+
+use futures::future::ok;
+use futures::Future;
+use hyper::header::{
+	HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+	ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+	ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+	VARY,
+};
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode};
+use router::{Handler, HandlerObj, ResponseFuture};
+
+/// How long browsers can cache a preflight answer, in seconds
+const PREFLIGHT_MAX_AGE: u32 = 86400;
+
+/// Answers preflight requests and adds the CORS headers to the responses
+/// to requests from allowed origins. `*` allows any origin, but then
+/// browsers won't send credentials on their own (an `Authorization` header
+/// set by the page still goes). Has to come before authentication, as
+/// preflight requests don't carry any.
+pub struct CorsMiddleware {
+	allowed_origins: Vec<String>,
+}
+
+impl CorsMiddleware {
+	pub fn new(allowed_origins: Vec<String>) -> CorsMiddleware {
+		CorsMiddleware {
+			allowed_origins: allowed_origins
+				.iter()
+				.map(|o| o.trim().trim_matches('/').to_owned())
+				.collect(),
+		}
+	}
+
+	/// The `Access-Control-Allow-Origin` to answer an origin with, none if
+	/// it isn't allowed
+	fn allow_origin(&self, origin: &str) -> Option<String> {
+		if self.allowed_origins.iter().any(|o| o == origin) {
+			Some(origin.to_owned())
+		} else if self.allowed_origins.iter().any(|o| o == "*") {
+			Some("*".to_owned())
+		} else {
+			None
+		}
+	}
+}
+
+/// Adds the headers every answer to an allowed origin gets
+fn add_cors_headers(headers: &mut HeaderMap, allow_origin: &str) {
+	if let Ok(origin) = HeaderValue::from_str(allow_origin) {
+		headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+	}
+	if allow_origin != "*" {
+		headers.insert(
+			ACCESS_CONTROL_ALLOW_CREDENTIALS,
+			HeaderValue::from_static("true"),
+		);
+	}
+	headers.insert(VARY, HeaderValue::from_static("Origin"));
+}
+
+impl Handler for CorsMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let origin = match req.headers().get(ORIGIN).and_then(|o| o.to_str().ok()) {
+			Some(origin) => origin.to_owned(),
+			None => return handlers.next().unwrap().call(req, handlers),
+		};
+		let allow_origin = self.allow_origin(&origin);
+		let preflight = req.method() == Method::OPTIONS
+			&& req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+
+		match (preflight, allow_origin) {
+			(true, None) => {
+				debug!("CORS preflight from {} turned down", origin);
+				Box::new(ok(Response::builder()
+					.status(StatusCode::FORBIDDEN)
+					.body(Body::from("origin not allowed"))
+					.unwrap()))
+			}
+			(true, Some(allow_origin)) => {
+				let mut res = Response::builder()
+					.status(StatusCode::NO_CONTENT)
+					.header(
+						ACCESS_CONTROL_ALLOW_METHODS,
+						HeaderValue::from_static("GET, POST, OPTIONS"),
+					).header(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(PREFLIGHT_MAX_AGE))
+					.body(Body::empty())
+					.unwrap();
+				let allow_headers = req
+					.headers()
+					.get(ACCESS_CONTROL_REQUEST_HEADERS)
+					.cloned()
+					.unwrap_or(HeaderValue::from_static("Authorization, Content-Type"));
+				res.headers_mut()
+					.insert(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+				add_cors_headers(res.headers_mut(), &allow_origin);
+				Box::new(ok(res))
+			}
+			(false, None) => handlers.next().unwrap().call(req, handlers),
+			(false, Some(allow_origin)) => Box::new(
+				handlers
+					.next()
+					.unwrap()
+					.call(req, handlers)
+					.map(move |mut res| {
+						add_cors_headers(res.headers_mut(), &allow_origin);
+						res.headers_mut().insert(
+							ACCESS_CONTROL_EXPOSE_HEADERS,
+							HeaderValue::from_static("Retry-After"),
+						);
+						res
+					}),
+			),
+		}
+	}
+}
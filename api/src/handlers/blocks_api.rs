@@ -16,34 +16,100 @@ use super::utils::{get_output, w};
 use chain;
 use core::core::hash::Hash;
 use core::core::hash::Hashed;
+use core::core::{Block, BlockHeader, CompactBlock};
 use failure::ResultExt;
 use hyper::{Body, Request, StatusCode};
 use regex::Regex;
 use rest::*;
 use router::{Handler, ResponseFuture};
+use std::collections::HashMap;
 use std::sync::Weak;
 use types::*;
+use url::form_urlencoded;
 use util;
 use web::*;
 
+/// Most headers returned by a single range request
+pub const MAX_HEADER_RESULTS: u64 = 1000;
+/// Most blocks returned by a single range request
+pub const MAX_BLOCK_RESULTS: u64 = 100;
+
+/// Parses the start_height, end_height and max params of a range request
+/// into the heights of the page and the start of the next one, if any.
+/// The end defaults to the chain head.
+pub fn height_range(
+	chain: &chain::Chain,
+	req: &Request<Body>,
+	max_results: u64,
+) -> Result<(u64, u64, Option<u64>), Error> {
+	let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+		.into_owned()
+		.fold(HashMap::new(), |mut hm, (k, v)| {
+			hm.insert(k, v);
+			hm
+		});
+	let param = |name: &str| -> Result<Option<u64>, Error> {
+		match params.get(name) {
+			Some(v) => {
+				Ok(Some(v.parse().map_err(|_| {
+					ErrorKind::RequestError(format!("invalid {}", name))
+				})?))
+			}
+			None => Ok(None),
+		}
+	};
+	let start = param("start_height")?
+		.ok_or_else(|| ErrorKind::RequestError("start_height is required".to_owned()))?;
+	let head = chain
+		.head()
+		.context(ErrorKind::Internal("can't get head".to_owned()))?;
+	let end = match param("end_height")? {
+		Some(h) if h < head.height => h,
+		_ => head.height,
+	};
+	let max = match param("max")? {
+		Some(0) => 1,
+		Some(n) if n < max_results => n,
+		_ => max_results,
+	};
+	if start > end {
+		return Ok((start, end, None));
+	}
+	if end - start >= max {
+		Ok((start, start + max - 1, Some(start + max)))
+	} else {
+		Ok((start, end, None))
+	}
+}
+
 /// Gets block headers given either a hash or height or an output commit.
 /// GET /v1/headers/<hash>
 /// GET /v1/headers/<height>
 /// GET /v1/headers/<output commit>
 ///
+/// Or a range of them by height, up to 1000 at once
+/// GET /v1/headers?start_height=101&end_height=200&max=50
+///
+/// A single header comes in its binary serialization when asked for with
+/// `Accept: application/octet-stream`.
 pub struct HeaderHandler {
 	pub chain: Weak<chain::Chain>,
 }
 
 impl HeaderHandler {
-	fn get_header(&self, input: String) -> Result<BlockHeaderPrintable, Error> {
+	pub fn get_header(&self, input: String) -> Result<BlockHeaderPrintable, Error> {
+		self.header(input)
+			.map(|header| BlockHeaderPrintable::from_header(&header))
+	}
+
+	fn header(&self, input: String) -> Result<BlockHeader, Error> {
 		// will fail quick if the provided isn't a commitment
 		if let Ok(h) = self.get_header_for_output(input.clone()) {
 			return Ok(h);
 		}
 		if let Ok(height) = input.parse() {
 			match w(&self.chain).get_header_by_height(height) {
-				Ok(header) => return Ok(BlockHeaderPrintable::from_header(&header)),
+				Ok(header) => return Ok(header),
 				Err(_) => return Err(ErrorKind::NotFound)?,
 			}
 		}
@@ -54,13 +120,29 @@ impl HeaderHandler {
 		let header = w(&self.chain)
 			.get_block_header(&h)
 			.context(ErrorKind::NotFound)?;
-		Ok(BlockHeaderPrintable::from_header(&header))
+		Ok(header)
+	}
+
+	fn get_header_range(
+		&self,
+		req: &Request<Body>,
+	) -> Result<HeightPage<BlockHeaderPrintable>, Error> {
+		let chain = w(&self.chain);
+		let (start, end, next_height) = height_range(&chain, req, MAX_HEADER_RESULTS)?;
+		let mut items = vec![];
+		for height in start..end + 1 {
+			let header = chain
+				.get_header_by_height(height)
+				.context(ErrorKind::NotFound)?;
+			items.push(BlockHeaderPrintable::from_header(&header));
+		}
+		Ok(HeightPage { items, next_height })
 	}
 
-	fn get_header_for_output(&self, commit_id: String) -> Result<BlockHeaderPrintable, Error> {
+	fn get_header_for_output(&self, commit_id: String) -> Result<BlockHeader, Error> {
 		let oid = get_output(&self.chain, &commit_id)?.1;
 		match w(&self.chain).get_header_for_output(&oid) {
-			Ok(header) => return Ok(BlockHeaderPrintable::from_header(&header)),
+			Ok(header) => return Ok(header),
 			Err(_) => return Err(ErrorKind::NotFound)?,
 		}
 	}
@@ -68,10 +150,16 @@ impl HeaderHandler {
 
 impl Handler for HeaderHandler {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		if is_range(&req) {
+			return result_to_response(self.get_header_range(&req));
+		}
 		let el = match req.uri().path().trim_right_matches("/").rsplit("/").next() {
 			None => return response(StatusCode::BAD_REQUEST, "invalid url"),
 			Some(el) => el,
 		};
+		if wants_binary(&req) {
+			return result_to_binary_response(self.header(el.to_string()));
+		}
 		result_to_response(self.get_header(el.to_string()))
 	}
 }
@@ -83,26 +171,58 @@ impl Handler for HeaderHandler {
 ///
 /// Optionally return results as "compact blocks" by passing "?compact" query
 /// param GET /v1/blocks/<hash>?compact
+///
+/// Or a range of them by height, up to 100 at once
+/// GET /v1/blocks?start_height=101&end_height=200&max=50
+/// GET /v1/blocks?start_height=101&compact
+///
+/// A single block, compact or not, comes in its binary serialization when
+/// asked for with `Accept: application/octet-stream`.
 pub struct BlockHandler {
 	pub chain: Weak<chain::Chain>,
 }
 
 impl BlockHandler {
-	fn get_block(&self, h: &Hash) -> Result<BlockPrintable, Error> {
-		let block = w(&self.chain).get_block(h).context(ErrorKind::NotFound)?;
+	pub fn get_block(&self, h: &Hash) -> Result<BlockPrintable, Error> {
+		let block = self.block(h)?;
 		Ok(BlockPrintable::from_block(&block, w(&self.chain), false))
 	}
 
-	fn get_compact_block(&self, h: &Hash) -> Result<CompactBlockPrintable, Error> {
-		let block = w(&self.chain).get_block(h).context(ErrorKind::NotFound)?;
+	pub fn get_compact_block(&self, h: &Hash) -> Result<CompactBlockPrintable, Error> {
+		let block = self.block(h)?;
 		Ok(CompactBlockPrintable::from_compact_block(
 			&block.into(),
 			w(&self.chain),
 		))
 	}
 
+	fn block(&self, h: &Hash) -> Result<Block, Error> {
+		Ok(w(&self.chain).get_block(h).context(ErrorKind::NotFound)?)
+	}
+
+	fn get_block_range<T, F>(
+		&self,
+		req: &Request<Body>,
+		to_printable: F,
+	) -> Result<HeightPage<T>, Error>
+	where
+		F: Fn(Block) -> T,
+	{
+		let chain = w(&self.chain);
+		let (start, end, next_height) = height_range(&chain, req, MAX_BLOCK_RESULTS)?;
+		let mut items = vec![];
+		for height in start..end + 1 {
+			let block = chain
+				.get_header_by_height(height)
+				.and_then(|header| chain.get_block(&header.hash()))
+				.context(ErrorKind::NotFound)?;
+			items.push(to_printable(block));
+		}
+		Ok(HeightPage { items, next_height })
+	}
+
 	// Try to decode the string as a height or a hash.
-	fn parse_input(&self, input: String) -> Result<Hash, Error> {
+	pub fn parse_input(&self, input: String) -> Result<Hash, Error> {
 		if let Ok(height) = input.parse() {
 			match w(&self.chain).get_header_by_height(height) {
 				Ok(header) => return Ok(header.hash()),
@@ -128,8 +248,36 @@ fn check_block_param(input: &String) -> Result<(), Error> {
 	return Ok(());
 }
 
+// Whether the request is for a range, with no block or header given in the
+// path
+fn is_range(req: &Request<Body>) -> bool {
+	req.uri()
+		.path()
+		.rsplit('/')
+		.find(|el| !el.is_empty())
+		.map(|el| el == "blocks" || el == "headers")
+		.unwrap_or(false)
+}
+
 impl Handler for BlockHandler {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		if is_range(&req) {
+			let compact = req
+				.uri()
+				.query()
+				.map(|q| q.split('&').any(|p| p == "compact"))
+				.unwrap_or(false);
+			let chain = w(&self.chain);
+			return if compact {
+				result_to_response(self.get_block_range(&req, |b| {
+					CompactBlockPrintable::from_compact_block(&b.into(), chain.clone())
+				}))
+			} else {
+				result_to_response(self.get_block_range(&req, |b| {
+					BlockPrintable::from_block(&b, chain.clone(), false)
+				}))
+			};
+		}
 		let el = match req.uri().path().trim_right_matches("/").rsplit("/").next() {
 			None => return response(StatusCode::BAD_REQUEST, "invalid url"),
 			Some(el) => el,
@@ -145,8 +293,11 @@ impl Handler for BlockHandler {
 			Ok(h) => h,
 		};
 
+		let binary = wants_binary(&req);
 		if let Some(param) = req.uri().query() {
-			if param == "compact" {
+			if param == "compact" && binary {
+				result_to_binary_response(self.block(&h).map(CompactBlock::from))
+			} else if param == "compact" {
 				result_to_response(self.get_compact_block(&h))
 			} else {
 				response(
@@ -154,6 +305,8 @@ impl Handler for BlockHandler {
 					format!("unsupported query parameter: {}", param),
 				)
 			}
+		} else if binary {
+			result_to_binary_response(self.block(&h))
 		} else {
 			result_to_response(self.get_block(&h))
 		}
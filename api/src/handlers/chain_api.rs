@@ -12,9 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::blocks_api::{height_range, MAX_BLOCK_RESULTS};
 use super::utils::{get_output, w};
 use chain;
+use core::consensus;
 use core::core::hash::Hashed;
+use core::core::pmmr;
+use core::core::{OutputFeatures, OutputIdentifier};
+use futures::Future;
 use hyper::{Body, Request, StatusCode};
 use rest::*;
 use router::{Handler, ResponseFuture};
@@ -33,7 +38,7 @@ pub struct ChainHandler {
 }
 
 impl ChainHandler {
-	fn get_tip(&self) -> Result<Tip, Error> {
+	pub fn get_tip(&self) -> Result<Tip, Error> {
 		let head = w(&self.chain)
 			.head()
 			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
@@ -84,16 +89,27 @@ impl Handler for ChainCompactHandler {
 	}
 }
 
+/// Most outputs (or blocks, by height) returned by a single request
+pub const MAX_OUTPUT_RESULTS: u64 = 1000;
+
 // Supports retrieval of multiple outputs in a single request -
 // GET /v1/chain/outputs/byids?id=xxx,yyy,zzz
 // GET /v1/chain/outputs/byids?id=xxx&id=yyy&id=zzz
 // GET /v1/chain/outputs/byheight?start_height=101&end_height=200
+//
+// Merkle proofs of the unspent ones against the head, so they needn't be
+// taken on trust -
+// GET /v1/chain/outputs/proofs?id=xxx,yyy,zzz
+//
+// And iteration of the unspent ones, a page at a time -
+// GET /v1/chain/outputs/unspent?max=100
+// GET /v1/chain/outputs/unspent?cursor=1234&start_height=101&end_height=200&coinbase
 pub struct OutputHandler {
 	pub chain: Weak<chain::Chain>,
 }
 
 impl OutputHandler {
-	fn get_output(&self, id: &str) -> Result<Output, Error> {
+	pub fn get_output(&self, id: &str) -> Result<Output, Error> {
 		let res = get_output(&self.chain, id)?;
 		Ok(res.0)
 	}
@@ -116,6 +132,12 @@ impl OutputHandler {
 				}
 			}
 		}
+		if commitments.len() as u64 > MAX_OUTPUT_RESULTS {
+			return Err(ErrorKind::RequestError(format!(
+				"too many ids, at most {} per request",
+				MAX_OUTPUT_RESULTS
+			)))?;
+		}
 
 		let mut outputs: Vec<Output> = vec![];
 		for x in commitments {
@@ -126,6 +148,69 @@ impl OutputHandler {
 		Ok(outputs)
 	}
 
+	fn output_proofs(&self, req: &Request<Body>) -> Result<OutputProofs, Error> {
+		let mut commits = vec![];
+		let query = req.uri().query().unwrap_or("");
+		for (k, id) in form_urlencoded::parse(query.as_bytes()).into_owned() {
+			if k == "id" {
+				for id in id.split(",") {
+					let c = util::from_hex(id.to_owned()).map_err(|_| {
+						ErrorKind::Argument(format!("Not a valid commitment: {}", id))
+					})?;
+					commits.push(Commitment::from_vec(c));
+				}
+			}
+		}
+		if commits.len() as u64 > MAX_OUTPUT_RESULTS {
+			return Err(ErrorKind::RequestError(format!(
+				"too many ids, at most {} per request",
+				MAX_OUTPUT_RESULTS
+			)))?;
+		}
+		// either one may be the unspent output, as in get_output
+		let mut ids = vec![];
+		for commit in &commits {
+			ids.push(OutputIdentifier::new(
+				OutputFeatures::DEFAULT_OUTPUT,
+				commit,
+			));
+			ids.push(OutputIdentifier::new(
+				OutputFeatures::COINBASE_OUTPUT,
+				commit,
+			));
+		}
+
+		let chain = w(&self.chain);
+		let (header, proofs) = chain
+			.get_merkle_proofs(&ids)
+			.map_err(|e| ErrorKind::Internal(format!("can't get merkle proofs: {}", e)))?;
+		let mut outputs = vec![];
+		for (out_id, pos, proof) in proofs {
+			let height = chain
+				.get_header_for_output(&out_id)
+				.map_err(|e| ErrorKind::Internal(format!("can't get output header: {}", e)))?
+				.height;
+			let output_type = if out_id.features.contains(OutputFeatures::COINBASE_OUTPUT) {
+				OutputType::Coinbase
+			} else {
+				OutputType::Transaction
+			};
+			outputs.push(OutputProof {
+				commit: PrintableCommitment {
+					commit: out_id.commit,
+				},
+				output_type,
+				height,
+				mmr_index: pos,
+				merkle_proof: proof.to_hex(),
+			});
+		}
+		Ok(OutputProofs {
+			header: BlockHeaderInfo::from_header(&header),
+			outputs,
+		})
+	}
+
 	fn outputs_at_height(
 		&self,
 		block_height: u64,
@@ -202,6 +287,12 @@ impl OutputHandler {
 		if let Some(_) = params.get("include_rp") {
 			include_rp = true;
 		}
+		if end_height >= start_height && end_height - start_height >= MAX_OUTPUT_RESULTS {
+			return Err(ErrorKind::RequestError(format!(
+				"at most {} blocks per request, see /v1/chain/outputs/unspent to page through outputs",
+				MAX_OUTPUT_RESULTS
+			)))?;
+		}
 
 		debug!(
 			"outputs_block_batch: {}-{}, {:?}, {:?}",
@@ -219,6 +310,68 @@ impl OutputHandler {
 
 		Ok(return_vec)
 	}
+
+	// returns a page of the unspent outputs, from the cursor on
+	fn unspent_outputs(&self, req: &Request<Body>) -> Result<OutputPage, Error> {
+		let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+			.into_owned()
+			.fold(HashMap::new(), |mut hm, (k, v)| {
+				hm.insert(k, v);
+				hm
+			});
+		let param = |name: &str, default: u64| -> Result<u64, Error> {
+			match params.get(name) {
+				Some(v) => Ok(v
+					.parse()
+					.map_err(|_| ErrorKind::RequestError(format!("invalid {}", name)))?),
+				None => Ok(default),
+			}
+		};
+		let mut cursor = param("cursor", 1)?;
+		let start_height = param("start_height", 0)?;
+		let end_height = param("end_height", u64::MAX)?;
+		let max = match param("max", 100)? {
+			0 => 1,
+			n if n > MAX_OUTPUT_RESULTS => MAX_OUTPUT_RESULTS,
+			n => n,
+		};
+		let coinbase_only = params.contains_key("coinbase");
+		let include_rp = params.contains_key("include_rp");
+
+		let chain = w(&self.chain);
+		// outputs are added block after block, so the first output of the
+		// start height comes right after those of the block before
+		if start_height > 0 {
+			if let Ok(header) = chain.get_header_by_height(start_height - 1) {
+				cursor = cursor.max(pmmr::n_leaves(header.output_mmr_size) + 1);
+			}
+		}
+
+		let (last_index, highest_index, outputs) = chain
+			.unspent_outputs_by_insertion_index(cursor, max)
+			.map_err(|e| ErrorKind::Internal(format!("can't get outputs: {}", e)))?;
+		let mut done = (outputs.len() as u64) < max || last_index >= highest_index;
+		let mut page = vec![];
+		for output in outputs {
+			let output = OutputPrintable::from_output(&output, chain.clone(), None, include_rp);
+			if output.block_height.map(|h| h > end_height).unwrap_or(false) {
+				done = true;
+				break;
+			}
+			if let OutputType::Transaction = output.output_type {
+				if coinbase_only {
+					continue;
+				}
+			}
+			page.push(output);
+		}
+
+		Ok(OutputPage {
+			highest_index,
+			next_cursor: if done { None } else { Some(last_index + 1) },
+			outputs: page,
+		})
+	}
 }
 
 impl Handler for OutputHandler {
@@ -230,7 +383,194 @@ impl Handler for OutputHandler {
 		match command {
 			"byids" => result_to_response(self.outputs_by_ids(&req)),
 			"byheight" => result_to_response(self.outputs_block_batch(&req)),
+			"unspent" => result_to_response(self.unspent_outputs(&req)),
+			"proofs" => result_to_response(self.output_proofs(&req)),
 			_ => response(StatusCode::BAD_REQUEST, ""),
 		}
 	}
 }
+
+/// Outputs and kernels created in a range of blocks, with their positions in
+/// the output and kernel MMRs and whether the outputs have been spent since,
+/// so explorers can build their databases without fetching and parsing full
+/// blocks. Up to 100 blocks at once, the end of the range defaulting to the
+/// chain head. Blocks compacted away are only available on archive nodes.
+/// GET /v1/chain/contents?start_height=101&end_height=200&max=50&include_rp
+pub struct ChainContentsHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl ChainContentsHandler {
+	fn contents_range(&self, req: &Request<Body>) -> Result<HeightPage<BlockContents>, Error> {
+		let chain = w(&self.chain);
+		let (start, end, next_height) = height_range(&chain, req, MAX_BLOCK_RESULTS)?;
+		let include_rp = req
+			.uri()
+			.query()
+			.map(|q| form_urlencoded::parse(q.as_bytes()).any(|(k, _)| k == "include_rp"))
+			.unwrap_or(false);
+		let mut items = vec![];
+		for height in start..end + 1 {
+			items.push(self.block_contents(&chain, height, include_rp)?);
+		}
+		Ok(HeightPage { items, next_height })
+	}
+
+	fn block_contents(
+		&self,
+		chain: &chain::Chain,
+		height: u64,
+		include_rp: bool,
+	) -> Result<BlockContents, Error> {
+		let header = chain
+			.get_header_by_height(height)
+			.map_err(|_| ErrorKind::NotFound)?;
+		let block = chain.get_block(&header.hash()).map_err(|_| {
+			ErrorKind::Internal(format!("block at {} not available, compacted away", height))
+		})?;
+
+		// outputs and kernels are appended to their MMRs in the order of the
+		// block, right after those of the block before
+		let (output_leaves, kernel_leaves) = if height == 0 {
+			(0, 0)
+		} else {
+			let prev = chain
+				.get_previous_header(&header)
+				.map_err(|e| ErrorKind::Internal(format!("can't get previous header: {}", e)))?;
+			(
+				pmmr::n_leaves(prev.output_mmr_size),
+				pmmr::n_leaves(prev.kernel_mmr_size),
+			)
+		};
+
+		let outputs = block
+			.outputs()
+			.iter()
+			.enumerate()
+			.map(|(i, output)| {
+				let output_type = if output.features.contains(OutputFeatures::COINBASE_OUTPUT) {
+					OutputType::Coinbase
+				} else {
+					OutputType::Transaction
+				};
+				let out_id = OutputIdentifier::from_output(output);
+				BlockOutput {
+					output_type,
+					commit: PrintableCommitment {
+						commit: output.commit,
+					},
+					mmr_index: pmmr::insertion_to_pmmr_index(output_leaves + i as u64 + 1),
+					spent: chain.is_unspent(&out_id).is_err(),
+					proof: if include_rp {
+						Some(util::to_hex(output.proof.proof.to_vec()))
+					} else {
+						None
+					},
+					proof_hash: util::to_hex(output.proof.hash().to_vec()),
+				}
+			}).collect();
+		let kernels = block
+			.kernels()
+			.iter()
+			.enumerate()
+			.map(|(i, kernel)| BlockKernel {
+				mmr_index: pmmr::insertion_to_pmmr_index(kernel_leaves + i as u64 + 1),
+				kernel: TxKernelPrintable::from_txkernel(kernel),
+			}).collect();
+
+		Ok(BlockContents {
+			header: BlockHeaderInfo::from_header(&header),
+			outputs,
+			kernels,
+		})
+	}
+}
+
+impl Handler for ChainContentsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.contents_range(&req))
+	}
+}
+
+/// Where kernels and outputs get looked up, many at a time
+pub const CHAIN_LOOKUP_PATH: &'static str = "/v1/chain/lookup";
+
+/// Most kernels and outputs looked up by a single request
+pub const MAX_LOOKUP_IDS: u64 = 100;
+
+/// Kernels are only looked for in the blocks of the last week, going through
+/// the kernel MMR down from the head
+pub const KERNEL_LOOKUP_HEIGHTS: u64 = consensus::WEEK_HEIGHT;
+
+/// Looks up kernels by excess and outputs by commitment, up to
+/// MAX_LOOKUP_IDS in a single request, telling for each whether it's on
+/// the chain and at which height. Kernels are only found in the last
+/// KERNEL_LOOKUP_HEIGHTS blocks.
+/// POST /v1/chain/lookup
+pub struct ChainLookupHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl ChainLookupHandler {
+	pub fn lookup(&self, req: &LookupRequest) -> Result<LookupResponse, Error> {
+		if (req.kernels.len() + req.outputs.len()) as u64 > MAX_LOOKUP_IDS {
+			return Err(ErrorKind::RequestError(format!(
+				"too many ids, at most {} per request",
+				MAX_LOOKUP_IDS
+			)))?;
+		}
+
+		let mut excesses = vec![];
+		for id in &req.kernels {
+			let c = util::from_hex(id.clone())
+				.map_err(|_| ErrorKind::Argument(format!("Not a valid excess: {}", id)))?;
+			excesses.push(Commitment::from_vec(c));
+		}
+		// kernels are looked for all at once, going through the kernel MMR once
+		let chain = w(&self.chain);
+		let head = chain
+			.head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let found = chain
+			.get_kernels(&excesses, head.height.saturating_sub(KERNEL_LOOKUP_HEIGHTS))
+			.map_err(|e| ErrorKind::Internal(format!("can't look up kernels: {}", e)))?;
+		let kernels = req
+			.kernels
+			.iter()
+			.zip(excesses.iter())
+			.map(|(id, excess)| LookupResult::new(id, found.get(excess).map(|k| k.1.height)))
+			.collect();
+
+		let mut outputs = vec![];
+		for id in &req.outputs {
+			let height = match get_output(&self.chain, id) {
+				Ok((output, _)) => Some(output.height),
+				Err(e) => {
+					let not_found = match *e.kind() {
+						ErrorKind::NotFound => true,
+						_ => false,
+					};
+					if !not_found {
+						return Err(e);
+					}
+					None
+				}
+			};
+			outputs.push(LookupResult::new(id, height));
+		}
+
+		Ok(LookupResponse { kernels, outputs })
+	}
+}
+
+impl Handler for ChainLookupHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let handler = ChainLookupHandler {
+			chain: self.chain.clone(),
+		};
+		Box::new(parse_body(req).then(move |req| match req {
+			Ok(req) => result_to_response(handler.lookup(&req)),
+			Err(e) => result_to_response::<LookupResponse>(Err(e)),
+		}))
+	}
+}
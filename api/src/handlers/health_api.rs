@@ -0,0 +1,121 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::utils::w;
+use chain;
+use hyper::{Body, Request, StatusCode};
+use p2p;
+use router::{Handler, ResponseFuture};
+use serde_json;
+use std::sync::Weak;
+use types::*;
+use web::*;
+
+/// Liveness probe path, outside of the versioned API
+pub const HEALTH_PATH: &'static str = "/healthz";
+
+/// Readiness probe path, outside of the versioned API
+pub const READY_PATH: &'static str = "/readyz";
+
+/// What a node has to meet to be ready to serve
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReadinessConfig {
+	/// How far behind the highest connected peer the chain can be
+	pub max_blocks_behind: u64,
+	/// Connected peers needed
+	pub min_peers: u32,
+}
+
+impl Default for ReadinessConfig {
+	fn default() -> ReadinessConfig {
+		ReadinessConfig {
+			max_blocks_behind: 5,
+			min_peers: 1,
+		}
+	}
+}
+
+/// Answers as long as the process runs
+/// GET /healthz
+pub struct HealthHandler;
+
+impl Handler for HealthHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		response(StatusCode::OK, "ok")
+	}
+}
+
+/// Whether the node is synced, has enough peers and can still write its
+/// data, 503 when any isn't the case
+/// GET /readyz
+pub struct ReadyHandler {
+	pub chain: Weak<chain::Chain>,
+	pub peers: Weak<p2p::Peers>,
+	pub config: ReadinessConfig,
+}
+
+impl ReadyHandler {
+	fn readiness(&self) -> Readiness {
+		let chain = w(&self.chain);
+		let connected = w(&self.peers).connected_peers();
+		let height = chain.head().map(|tip| tip.height).unwrap_or(0);
+		let network_height = connected
+			.iter()
+			.map(|p| p.info.height())
+			.fold(height, |max, h| if h > max { h } else { max });
+		let peer_count = connected.len() as u32;
+		let db_writable = match chain.check_writable() {
+			Ok(()) => true,
+			Err(e) => {
+				error!("readyz: chain data not writable: {}", e);
+				false
+			}
+		};
+
+		let mut failed = vec![];
+		if network_height - height > self.config.max_blocks_behind {
+			failed.push("sync".to_owned());
+		}
+		if peer_count < self.config.min_peers {
+			failed.push("peers".to_owned());
+		}
+		if !db_writable {
+			failed.push("db".to_owned());
+		}
+		Readiness {
+			ready: failed.is_empty(),
+			height,
+			network_height,
+			peer_count,
+			db_writable,
+			failed,
+		}
+	}
+}
+
+impl Handler for ReadyHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		let readiness = self.readiness();
+		let status = if readiness.ready {
+			StatusCode::OK
+		} else {
+			StatusCode::SERVICE_UNAVAILABLE
+		};
+		match serde_json::to_string(&readiness) {
+			Ok(json) => response(status, json),
+			Err(_) => response(StatusCode::INTERNAL_SERVER_ERROR, ""),
+		}
+	}
+}
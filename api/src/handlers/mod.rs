@@ -14,18 +14,25 @@
 
 mod blocks_api;
 mod chain_api;
+mod health_api;
 mod peers_api;
 mod pool_api;
+mod rpc_api;
 mod server_api;
 mod transactions_api;
 mod utils;
 
-use router::{Router, RouterError};
+use router::{HandlerObj, Router, RouterError};
 
 // Server
 use self::server_api::IndexHandler;
+pub use self::server_api::NodeStatusSource;
 use self::server_api::StatusHandler;
 
+// Health
+use self::health_api::{HealthHandler, ReadyHandler};
+pub use self::health_api::{ReadinessConfig, HEALTH_PATH, READY_PATH};
+
 // Blocks
 use self::blocks_api::BlockHandler;
 use self::blocks_api::HeaderHandler;
@@ -33,34 +40,64 @@ use self::blocks_api::HeaderHandler;
 // TX Set
 use self::transactions_api::TxHashSetHandler;
 
+// JSON-RPC
+pub use self::rpc_api::JSONRPC_PATH;
+use self::rpc_api::RpcHandler;
+
 // Chain
+pub use self::chain_api::CHAIN_LOOKUP_PATH;
 use self::chain_api::ChainCompactHandler;
+use self::chain_api::ChainContentsHandler;
 use self::chain_api::ChainHandler;
+use self::chain_api::ChainLookupHandler;
 use self::chain_api::ChainValidationHandler;
 use self::chain_api::OutputHandler;
 
 // Pool Handlers
+use self::pool_api::DandelionHandler;
+use self::pool_api::PoolCheckHandler;
 use self::pool_api::PoolInfoHandler;
 use self::pool_api::PoolPushHandler;
+pub use self::pool_api::PoolSubmitHandler;
+use self::pool_api::PoolTxsHandler;
+pub use self::pool_api::POOL_CHECK_PATH;
 
 // Peers
+use self::peers_api::PeerEventsHandler;
 use self::peers_api::PeerHandler;
 use self::peers_api::PeersAllHandler;
+use self::peers_api::PeersBannedHandler;
 use self::peers_api::PeersConnectedHandler;
+use self::peers_api::PeersExportHandler;
+use self::peers_api::PeersImportHandler;
 
-use auth::BasicAuthMiddleware;
+use auth::RoleAuthMiddleware;
 use chain;
+use cors::CorsMiddleware;
+use limits::{ApiLimits, RateLimitMiddleware, SizeLimitMiddleware};
+use metrics::{ApiMetrics, MetricsHandler, TraceMiddleware, METRICS_PATH};
 use p2p;
 use pool;
 use rest::*;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 use util;
 use util::RwLock;
+use sse::EventsHandler;
+use v2::add_v2_routes;
+use ws::{EventHub, WsHandler};
 
 /// Start all server HTTP handlers. Register all of them with Router
 /// and runs the corresponding HTTP server.
 ///
+/// With an API secret, calls need basic authentication. The read secret, if
+/// any, only gives access to the read-only calls. Calls are rate limited and
+/// their size capped as the limits say. The extra routes are handlers the
+/// node serves beyond the ones of this crate, such as stratum statistics,
+/// given with their `/v1/...` path. The status source, if any, fills in the
+/// status what the node knows of its other subsystems.
+///
 /// Hyper currently has a bug that prevents clean shutdown. In order
 /// to avoid having references kept forever by handlers, we only pass
 /// weak references. Note that this likely means a crash if the handlers are
@@ -70,18 +107,52 @@ pub fn start_rest_apis(
 	addr: String,
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
-	peers: Arc<p2p::Peers>,
+	dandelion_config: Arc<RwLock<pool::DandelionConfig>>,
+	p2p_server: Arc<p2p::Server>,
+	events: Arc<EventHub>,
 	api_secret: Option<String>,
+	api_read_secret: Option<String>,
 	tls_config: Option<TLSConfig>,
+	limits: ApiLimits,
+	allowed_origins: Vec<String>,
+	readiness: ReadinessConfig,
+	extra_routes: Vec<(&'static str, HandlerObj)>,
+	status_source: Option<Arc<NodeStatusSource>>,
 ) -> bool {
 	let mut apis = ApiServer::new();
-	let mut router = build_router(chain, tx_pool, peers).expect("unable to build API router");
-	if api_secret.is_some() {
-		let api_basic_auth =
-			"Basic ".to_string() + &util::to_base64(&("grin:".to_string() + &api_secret.unwrap()));
+	let mut router = build_router(
+		chain,
+		tx_pool,
+		dandelion_config,
+		p2p_server,
+		events,
+		readiness,
+		extra_routes,
+		status_source,
+	).expect("unable to build API router");
+	if !allowed_origins.is_empty() {
+		router.add_middleware(Arc::new(CorsMiddleware::new(allowed_origins)));
+	}
+	if limits.rate_limit > 0 {
+		router.add_middleware(Arc::new(RateLimitMiddleware::new(limits.rate_limit)));
+	}
+	if limits.max_request_size > 0 || limits.max_response_size > 0 {
+		let cap = |size: u64| if size > 0 { Some(size) } else { None };
+		router.add_middleware(Arc::new(SizeLimitMiddleware::new(
+			cap(limits.max_request_size),
+			cap(limits.max_response_size),
+		)));
+	}
+	if let Some(api_secret) = api_secret {
+		let basic_auth =
+			|secret: String| "Basic ".to_string() + &util::to_base64(&("grin:".to_string() + &secret));
 		let basic_realm = "Basic realm=GrinAPI".to_string();
-		let basic_auth_middleware = Arc::new(BasicAuthMiddleware::new(api_basic_auth, basic_realm));
-		router.add_middleware(basic_auth_middleware);
+		let auth_middleware = Arc::new(RoleAuthMiddleware::new(
+			basic_auth(api_secret),
+			api_read_secret.map(basic_auth),
+			basic_realm,
+		));
+		router.add_middleware(auth_middleware);
 	}
 
 	info!("Starting HTTP API server at {}.", addr);
@@ -92,14 +163,24 @@ pub fn start_rest_apis(
 pub fn build_router(
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
-	peers: Arc<p2p::Peers>,
+	dandelion_config: Arc<RwLock<pool::DandelionConfig>>,
+	p2p_server: Arc<p2p::Server>,
+	events: Arc<EventHub>,
+	readiness: ReadinessConfig,
+	extra_routes: Vec<(&'static str, HandlerObj)>,
+	status_source: Option<Arc<NodeStatusSource>>,
 ) -> Result<Router, RouterError> {
-	let route_list = vec![
+	let peers = p2p_server.peers.clone();
+	let mut route_list = vec![
 		"get blocks".to_string(),
+		"get blocks?start_height=1&max=100".to_string(),
+		"get headers?start_height=1&max=1000".to_string(),
 		"get chain".to_string(),
 		"post chain/compact".to_string(),
 		"post chain/validate".to_string(),
+		"post chain/lookup".to_string(),
 		"get chain/outputs".to_string(),
+		"get chain/contents?start_height=1&max=100".to_string(),
 		"get status".to_string(),
 		"get txhashset/roots".to_string(),
 		"get txhashset/lastoutputs?n=10".to_string(),
@@ -107,13 +188,30 @@ pub fn build_router(
 		"get txhashset/lastkernels".to_string(),
 		"get txhashset/outputs?start_index=1&max=100".to_string(),
 		"get pool".to_string(),
+		"get pool/txs".to_string(),
 		"post pool/push".to_string(),
-		"post peers/a.b.c.d:p/ban".to_string(),
+		"post pool/submit".to_string(),
+		"post pool/check".to_string(),
+		"get pool/dandelion".to_string(),
+		"post pool/dandelion".to_string(),
+		"post peers/a.b.c.d:p/connect".to_string(),
+		"post peers/a.b.c.d:p/ban?reason=ManualBan&duration=3600".to_string(),
 		"post peers/a.b.c.d:p/unban".to_string(),
 		"get peers/all".to_string(),
 		"get peers/connected".to_string(),
+		"get peers/banned".to_string(),
+		"get peers/events?from=x&to=y&limit=100".to_string(),
+		"get peers/export?count=100".to_string(),
+		"post peers/import?max_age=604800&signer=xxx".to_string(),
 		"get peers/a.b.c.d".to_string(),
+		"get ws".to_string(),
+		"get events?topics=blocks,transactions".to_string(),
+		"post jsonrpc".to_string(),
+		"get metrics".to_string(),
 	];
+	for &(path, _) in &extra_routes {
+		route_list.push(format!("get {}", path.trim_left_matches("/v1/")));
+	}
 	let index_handler = IndexHandler { list: route_list };
 
 	let output_handler = OutputHandler {
@@ -132,12 +230,22 @@ pub fn build_router(
 	let chain_compact_handler = ChainCompactHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let chain_contents_handler = ChainContentsHandler {
+		chain: Arc::downgrade(&chain),
+	};
 	let chain_validation_handler = ChainValidationHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let chain_lookup_handler = ChainLookupHandler {
+		chain: Arc::downgrade(&chain),
+	};
 	let status_handler = StatusHandler {
 		chain: Arc::downgrade(&chain),
 		peers: Arc::downgrade(&peers),
+		tx_pool: Arc::downgrade(&tx_pool),
+		dandelion_config: Arc::downgrade(&dandelion_config),
+		source: status_source,
+		started: Instant::now(),
 	};
 	let txhashset_handler = TxHashSetHandler {
 		chain: Arc::downgrade(&chain),
@@ -145,34 +253,108 @@ pub fn build_router(
 	let pool_info_handler = PoolInfoHandler {
 		tx_pool: Arc::downgrade(&tx_pool),
 	};
+	let pool_txs_handler = PoolTxsHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
 	let pool_push_handler = PoolPushHandler {
 		tx_pool: Arc::downgrade(&tx_pool),
 	};
+	let pool_submit_handler = PoolSubmitHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let pool_check_handler = PoolCheckHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let dandelion_handler = DandelionHandler {
+		dandelion_config: Arc::downgrade(&dandelion_config),
+	};
 	let peers_all_handler = PeersAllHandler {
 		peers: Arc::downgrade(&peers),
 	};
 	let peers_connected_handler = PeersConnectedHandler {
 		peers: Arc::downgrade(&peers),
 	};
+	let peers_banned_handler = PeersBannedHandler {
+		peers: Arc::downgrade(&peers),
+	};
+	let peer_events_handler = PeerEventsHandler {
+		peers: Arc::downgrade(&peers),
+	};
+	let peers_export_handler = PeersExportHandler {
+		peers: Arc::downgrade(&peers),
+	};
+	let peers_import_handler = PeersImportHandler {
+		peers: Arc::downgrade(&peers),
+	};
 	let peer_handler = PeerHandler {
 		peers: Arc::downgrade(&peers),
+		p2p_server: Arc::downgrade(&p2p_server),
+	};
+	let rpc_handler = RpcHandler {
+		chain: Arc::downgrade(&chain),
+		tx_pool: Arc::downgrade(&tx_pool),
+		dandelion_config: Arc::downgrade(&dandelion_config),
+		peers: Arc::downgrade(&peers),
+		status: status_handler.clone(),
+	};
+	let ws_handler = WsHandler {
+		events: Arc::downgrade(&events),
+	};
+	let ready_handler = ReadyHandler {
+		chain: Arc::downgrade(&chain),
+		peers: Arc::downgrade(&peers),
+		config: readiness,
+	};
+	let events_handler = EventsHandler {
+		events: Arc::downgrade(&events),
+	};
+	let metrics = Arc::new(ApiMetrics::new());
+	let metrics_handler = MetricsHandler {
+		metrics: metrics.clone(),
+		chain: Arc::downgrade(&chain),
+		peers: Arc::downgrade(&peers),
 	};
 
 	let mut router = Router::new();
 
 	router.add_route("/v1/", Arc::new(index_handler))?;
-	router.add_route("/v1/blocks/*", Arc::new(block_handler))?;
-	router.add_route("/v1/headers/*", Arc::new(header_handler))?;
+	let block_handler = Arc::new(block_handler);
+	let header_handler = Arc::new(header_handler);
+	router.add_route("/v1/blocks", block_handler.clone())?;
+	router.add_route("/v1/blocks/*", block_handler)?;
+	router.add_route("/v1/headers", header_handler.clone())?;
+	router.add_route("/v1/headers/*", header_handler)?;
 	router.add_route("/v1/chain", Arc::new(chain_tip_handler))?;
 	router.add_route("/v1/chain/outputs/*", Arc::new(output_handler))?;
 	router.add_route("/v1/chain/compact", Arc::new(chain_compact_handler))?;
+	router.add_route("/v1/chain/contents", Arc::new(chain_contents_handler))?;
 	router.add_route("/v1/chain/validate", Arc::new(chain_validation_handler))?;
+	router.add_route(CHAIN_LOOKUP_PATH, Arc::new(chain_lookup_handler))?;
 	router.add_route("/v1/txhashset/*", Arc::new(txhashset_handler))?;
 	router.add_route("/v1/status", Arc::new(status_handler))?;
 	router.add_route("/v1/pool", Arc::new(pool_info_handler))?;
+	router.add_route("/v1/pool/txs", Arc::new(pool_txs_handler))?;
 	router.add_route("/v1/pool/push", Arc::new(pool_push_handler))?;
+	router.add_route("/v1/pool/submit", Arc::new(pool_submit_handler))?;
+	router.add_route(POOL_CHECK_PATH, Arc::new(pool_check_handler))?;
+	router.add_route("/v1/pool/dandelion", Arc::new(dandelion_handler))?;
 	router.add_route("/v1/peers/all", Arc::new(peers_all_handler))?;
 	router.add_route("/v1/peers/connected", Arc::new(peers_connected_handler))?;
+	router.add_route("/v1/peers/banned", Arc::new(peers_banned_handler))?;
+	router.add_route("/v1/peers/events", Arc::new(peer_events_handler))?;
+	router.add_route("/v1/peers/export", Arc::new(peers_export_handler))?;
+	router.add_route("/v1/peers/import", Arc::new(peers_import_handler))?;
 	router.add_route("/v1/peers/**", Arc::new(peer_handler))?;
+	router.add_route("/v1/ws", Arc::new(ws_handler))?;
+	router.add_route("/v1/events", Arc::new(events_handler))?;
+	router.add_route(JSONRPC_PATH, Arc::new(rpc_handler))?;
+	router.add_route(METRICS_PATH, Arc::new(metrics_handler))?;
+	for (path, handler) in extra_routes {
+		router.add_route(path, handler)?;
+	}
+	add_v2_routes(&mut router)?;
+	router.add_middleware(Arc::new(TraceMiddleware::new(metrics)));
+	router.add_route(HEALTH_PATH, Arc::new(HealthHandler))?;
+	router.add_route(READY_PATH, Arc::new(ready_handler))?;
 	Ok(router)
 }
@@ -13,19 +13,72 @@
 // limitations under the License.
 
 use super::utils::w;
+use futures::Future;
 use hyper::{Body, Request, StatusCode};
 use p2p;
 use p2p::types::{PeerInfoDisplay, ReasonForBan};
+use p2p::{PeerExport, MAX_EXPORT_AGE_SECS, MAX_EXPORT_PEERS};
+use rest::*;
 use router::{Handler, ResponseFuture};
+use serde_json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Weak;
+use std::time::{SystemTime, UNIX_EPOCH};
+use types::{BannedPeer, PeerImport, PeerPage};
+use url::form_urlencoded;
+use util;
 use web::*;
 
+/// Most peers returned in a single page
+pub const MAX_PEER_RESULTS: usize = 1000;
+
+/// Known peers, all of them or a page at a time
+/// GET /v1/peers/all
+/// GET /v1/peers/all?limit=100&next=xxx
 pub struct PeersAllHandler {
 	pub peers: Weak<p2p::Peers>,
 }
 
+impl PeersAllHandler {
+	// returns a page of the known peers, from the continuation token on
+	fn peers_page(&self, req: &Request<Body>) -> Result<PeerPage, Error> {
+		let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+			.into_owned()
+			.collect::<HashMap<String, String>>();
+		let limit = match params.get("limit") {
+			Some(l) => match l.parse() {
+				Ok(0) | Err(_) => {
+					return Err(ErrorKind::RequestError("invalid limit".to_owned()).into())
+				}
+				Ok(l) if l > MAX_PEER_RESULTS => MAX_PEER_RESULTS,
+				Ok(l) => l,
+			},
+			None => MAX_PEER_RESULTS,
+		};
+		let from = match params.get("next") {
+			Some(next) => Some(
+				util::from_hex(next.clone())
+					.map_err(|_| ErrorKind::RequestError("invalid next".to_owned()))?,
+			),
+			None => None,
+		};
+		let page = w(&self.peers)
+			.peers_page(from.as_ref().map(|f| &f[..]), limit)
+			.map_err(|e| ErrorKind::Internal(format!("can't get peers: {:?}", e)))?;
+		Ok(PeerPage {
+			next: page.next.map(util::to_hex),
+			peers: page.items,
+		})
+	}
+}
+
 impl Handler for PeersAllHandler {
-	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		// paged only when asked to, the plain listing stays as it was
+		if req.uri().query().is_some() {
+			return result_to_response(self.peers_page(&req));
+		}
 		let peers = &w(&self.peers).all_peers();
 		json_response_pretty(&peers)
 	}
@@ -46,12 +99,216 @@ impl Handler for PeersConnectedHandler {
 	}
 }
 
+/// Peers currently banned, with when their ban runs out
+/// GET /v1/peers/banned
+pub struct PeersBannedHandler {
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl Handler for PeersBannedHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		json_response(&banned_peers(&w(&self.peers)))
+	}
+}
+
+/// Events of the peer audit log, oldest first, between the times given in
+/// seconds since the epoch, the last day by default
+/// GET /v1/peers/events
+/// GET /v1/peers/events?from=1540000000&to=1540086400&limit=100
+pub struct PeerEventsHandler {
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl PeerEventsHandler {
+	fn events(&self, req: &Request<Body>) -> Result<Vec<p2p::PeerEvent>, Error> {
+		let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+			.into_owned()
+			.collect::<HashMap<String, String>>();
+		let time_param = |name: &str, default: i64| -> Result<i64, Error> {
+			match params.get(name) {
+				Some(t) => t
+					.parse()
+					.map_err(|_| ErrorKind::RequestError(format!("invalid {}", name)).into()),
+				None => Ok(default),
+			}
+		};
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs() as i64)
+			.unwrap_or(0);
+		let from = time_param("from", now - 86400)?;
+		let to = time_param("to", now)?;
+		let limit = match params.get("limit") {
+			Some(l) => match l.parse() {
+				Ok(0) | Err(_) => {
+					return Err(ErrorKind::RequestError("invalid limit".to_owned()).into())
+				}
+				Ok(l) if l > MAX_PEER_RESULTS => MAX_PEER_RESULTS,
+				Ok(l) => l,
+			},
+			None => MAX_PEER_RESULTS,
+		};
+		w(&self.peers)
+			.events(from, to, limit)
+			.map_err(|e| ErrorKind::Internal(format!("can't get peer events: {:?}", e)).into())
+	}
+}
+
+impl Handler for PeerEventsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.events(&req))
+	}
+}
+
+/// Known-good peers signed by this node, to seed the peer store of others
+/// with, all of them or the best ones up to a count
+/// GET /v1/peers/export
+/// GET /v1/peers/export?count=100
+pub struct PeersExportHandler {
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl PeersExportHandler {
+	fn export(&self, req: &Request<Body>) -> Result<PeerExport, Error> {
+		let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+			.into_owned()
+			.collect::<HashMap<String, String>>();
+		let count = match params.get("count") {
+			Some(c) => match c.parse() {
+				Ok(0) | Err(_) => {
+					return Err(ErrorKind::RequestError("invalid count".to_owned()).into())
+				}
+				Ok(c) => c,
+			},
+			None => MAX_EXPORT_PEERS,
+		};
+		w(&self.peers)
+			.export_peers(count)
+			.map_err(|e| ErrorKind::Internal(format!("can't export peers: {:?}", e)).into())
+	}
+}
+
+impl Handler for PeersExportHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.export(&req))
+	}
+}
+
+/// Saves the peers exported by another node that we don't know of yet. The
+/// export has to be signed and no older than max_age seconds, a week by
+/// default, and if given signed by the signer public key.
+/// POST /v1/peers/import
+/// POST /v1/peers/import?max_age=86400&signer=xxx
+pub struct PeersImportHandler {
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl Handler for PeersImportHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+			.into_owned()
+			.collect::<HashMap<String, String>>();
+		let max_age = match params.get("max_age").map(|a| a.parse::<i64>()) {
+			None => MAX_EXPORT_AGE_SECS,
+			Some(Ok(a)) if a > 0 => a,
+			Some(_) => return response(StatusCode::BAD_REQUEST, "invalid max_age"),
+		};
+		let signer = params.get("signer").cloned();
+		let peers = w(&self.peers);
+		Box::new(
+			parse_body(req)
+				.and_then(move |export: PeerExport| -> Result<PeerImport, Error> {
+					let signer = signer.as_ref().map(|s| s.as_str());
+					match peers.import_peers(&export, max_age, signer) {
+						Ok(imported) => Ok(PeerImport {
+							exported: export.peers.len(),
+							imported,
+						}),
+						Err(p2p::Error::InvalidExport(e)) => {
+							Err(ErrorKind::RequestError(format!("invalid export: {}", e)).into())
+						}
+						Err(e) => {
+							Err(ErrorKind::Internal(format!("can't import peers: {:?}", e)).into())
+						}
+					}
+				}).then(|res| result_to_response(res)),
+		)
+	}
+}
+
+/// The peers currently banned, with when their ban runs out
+pub fn banned_peers(peers: &p2p::Peers) -> Vec<BannedPeer> {
+	peers
+		.banned_peers()
+		.iter()
+		.map(|p| BannedPeer {
+			addr: p.addr,
+			ban_reason: p.ban_reason,
+			banned_at: p.last_banned,
+			expires_at: peers.ban_expiry(p),
+			note: p.ban_note.clone(),
+		}).collect()
+}
+
 /// Peer operations
 /// GET /v1/peers/10.12.12.13
-/// POST /v1/peers/10.12.12.13/ban
-/// POST /v1/peers/10.12.12.13/unban
+/// POST /v1/peers/10.12.12.13:13414/connect
+/// POST /v1/peers/10.12.12.13:13414/ban?reason=BadBlock&duration=3600&note=xxx
+/// POST /v1/peers/10.12.12.13:13414/unban
 pub struct PeerHandler {
 	pub peers: Weak<p2p::Peers>,
+	pub p2p_server: Weak<p2p::Server>,
+}
+
+impl PeerHandler {
+	// connects to the peer unless it's banned, returning the connected peer
+	fn connect(&self, addr: &SocketAddr) -> ResponseFuture {
+		if w(&self.peers).is_banned(*addr) {
+			return response(StatusCode::FORBIDDEN, format!("peer {} is banned", addr));
+		}
+		match w(&self.p2p_server).connect(addr) {
+			Ok(peer) => {
+				let peer_info: PeerInfoDisplay = peer.info.clone().into();
+				json_response(&peer_info)
+			}
+			Err(e) => response(
+				StatusCode::BAD_GATEWAY,
+				format!("could not connect to {}: {:?}", addr, e),
+			),
+		}
+	}
+
+	// bans the peer for the reason and number of seconds given in the query,
+	// a manual ban for the configured ban window otherwise, keeping the note
+	// given if any
+	fn ban(&self, addr: &SocketAddr, req: &Request<Body>) -> ResponseFuture {
+		let params = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+			.into_owned()
+			.fold(HashMap::new(), |mut hm, (k, v)| {
+				hm.insert(k, v);
+				hm
+			});
+		let reason = match params.get("reason") {
+			None => ReasonForBan::ManualBan,
+			Some(r) => match serde_json::from_value(serde_json::Value::String(r.clone())) {
+				Ok(ReasonForBan::None) | Err(_) => {
+					return response(
+						StatusCode::BAD_REQUEST,
+						format!("invalid ban reason: {}", r),
+					)
+				}
+				Ok(reason) => reason,
+			},
+		};
+		let duration = match params.get("duration").map(|d| d.parse::<i64>()) {
+			None => 0,
+			Some(Ok(d)) if d > 0 => d,
+			Some(_) => return response(StatusCode::BAD_REQUEST, "invalid ban duration"),
+		};
+		let note = params.get("note").map(|n| n.as_str()).unwrap_or("");
+		w(&self.peers).ban_peer_for(addr, reason, duration, note);
+		response(StatusCode::OK, "")
+	}
 }
 
 impl Handler for PeerHandler {
@@ -92,11 +349,13 @@ impl Handler for PeerHandler {
 		};
 
 		match command {
-			"ban" => w(&self.peers).ban_peer(&addr, ReasonForBan::ManualBan),
-			"unban" => w(&self.peers).unban_peer(&addr),
-			_ => return response(StatusCode::BAD_REQUEST, "invalid command"),
-		};
-
-		response(StatusCode::OK, "")
+			"connect" => self.connect(&addr),
+			"ban" => self.ban(&addr, &req),
+			"unban" => {
+				w(&self.peers).unban_peer(&addr);
+				response(StatusCode::OK, "")
+			}
+			_ => response(StatusCode::BAD_REQUEST, "invalid command"),
+		}
 	}
 }
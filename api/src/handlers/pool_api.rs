@@ -16,12 +16,14 @@ use super::utils::w;
 use core::core::hash::Hashed;
 use core::core::Transaction;
 use core::ser;
-use futures::future::ok;
-use futures::Future;
+use futures::future::{err, ok};
+use futures::{Future, Stream};
+use hyper::header::CONTENT_TYPE;
 use hyper::{Body, Request, StatusCode};
 use pool;
 use rest::*;
 use router::{Handler, ResponseFuture};
+use std::cmp;
 use std::collections::HashMap;
 use std::sync::Weak;
 use types::*;
@@ -47,6 +49,23 @@ impl Handler for PoolInfoHandler {
 	}
 }
 
+/// Transactions in the pool, oldest first. Stem transactions aren't listed,
+/// they're not public yet.
+/// GET /v1/pool/txs
+pub struct PoolTxsHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl Handler for PoolTxsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		let pool_arc = w(&self.tx_pool);
+		let pool = pool_arc.read();
+
+		let txs: Vec<PoolTx> = pool.txpool.entries.iter().map(PoolTx::from_entry).collect();
+		json_response(&txs)
+	}
+}
+
 /// Dummy wrapper for the hex-encoded serialized transaction.
 #[derive(Serialize, Deserialize)]
 struct TxWrapper {
@@ -76,41 +95,162 @@ impl PoolPushHandler {
 
 		Box::new(
 			parse_body(req)
-				.and_then(move |wrapper: TxWrapper| {
-					util::from_hex(wrapper.tx_hex)
-						.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)).into())
-				})
-				.and_then(move |tx_bin| {
-					ser::deserialize(&mut &tx_bin[..])
-						.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)).into())
-				})
-				.and_then(move |tx: Transaction| {
-					let source = pool::TxSource {
-						debug_name: "push-api".to_string(),
-						identifier: "?.?.?.?".to_string(),
-					};
-					info!(
-						"Pushing transaction {} to pool (inputs: {}, outputs: {}, kernels: {})",
-						tx.hash(),
-						tx.inputs().len(),
-						tx.outputs().len(),
-						tx.kernels().len(),
-					);
-
-					//  Push to tx pool.
-					let mut tx_pool = pool_arc.write();
-					let header = tx_pool.blockchain.chain_head().unwrap();
-					tx_pool
-						.add_to_pool(source, tx, !fluff, &header)
-						.map_err(|e| {
-							error!("update_pool: failed with error: {:?}", e);
-							ErrorKind::Internal(format!("Failed to update pool: {:?}", e)).into()
-						})
-				}),
+				.and_then(move |wrapper: TxWrapper| push_tx_hex(&pool_arc, wrapper.tx_hex, fluff)),
 		)
 	}
 }
 
+/// Push a hex-encoded serialized transaction to the pool, stemming it unless
+/// fluff is set.
+pub fn push_tx_hex(
+	pool_arc: &RwLock<pool::TransactionPool>,
+	tx_hex: String,
+	fluff: bool,
+) -> Result<(), Error> {
+	let tx_bin = util::from_hex(tx_hex)
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+	let tx: Transaction = ser::deserialize(&mut &tx_bin[..])
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+	push_tx(pool_arc, tx, fluff, "push-api")
+}
+
+/// Push a transaction to the pool, stemming it unless fluff is set, as
+/// coming from the given source.
+fn push_tx(
+	pool_arc: &RwLock<pool::TransactionPool>,
+	tx: Transaction,
+	fluff: bool,
+	debug_name: &str,
+) -> Result<(), Error> {
+	let source = pool::TxSource {
+		debug_name: debug_name.to_string(),
+		identifier: "?.?.?.?".to_string(),
+	};
+	info!(
+		"Pushing transaction {} to pool (inputs: {}, outputs: {}, kernels: {})",
+		tx.hash(),
+		tx.inputs().len(),
+		tx.outputs().len(),
+		tx.kernels().len(),
+	);
+
+	//  Push to tx pool.
+	let mut tx_pool = pool_arc.write();
+	let header = tx_pool.blockchain.chain_head().unwrap();
+	tx_pool
+		.add_to_pool(source, tx, !fluff, &header)
+		.map_err(|e| {
+			error!("update_pool: failed with error: {:?}", e);
+			ErrorKind::Internal(format!("Failed to update pool: {:?}", e)).into()
+		})
+}
+
+/// Push a transaction built and signed elsewhere to the pool, posted as is:
+/// its binary serialization with an `application/octet-stream` content
+/// type, its JSON otherwise. It's validated and stemmed through Dandelion
+/// like the ones wallets push, unless `?fluff` is given.
+/// POST /v1/pool/submit
+pub struct PoolSubmitHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl Handler for PoolSubmitHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let fluff = req
+			.uri()
+			.query()
+			.map(|q| form_urlencoded::parse(q.as_bytes()).any(|(k, _)| k == "fluff"))
+			.unwrap_or(false);
+		let binary = req
+			.headers()
+			.get(CONTENT_TYPE)
+			.and_then(|t| t.to_str().ok())
+			.map(|t| t.split(';').next().unwrap_or("").trim() == BINARY_CONTENT_TYPE)
+			.unwrap_or(false);
+		let pool_arc = w(&self.tx_pool);
+		let tx: Box<Future<Item = Transaction, Error = Error> + Send> = if binary {
+			Box::new(
+				req.into_body()
+					.concat2()
+					.map_err(|e| {
+						ErrorKind::RequestError(format!("Failed to read request: {}", e)).into()
+					})
+					.and_then(|body| match ser::deserialize(&mut &body.to_vec()[..]) {
+						Ok(tx) => ok(tx),
+						Err(e) => err(ErrorKind::RequestError(format!(
+							"Invalid transaction: {}",
+							e
+						)).into()),
+					}),
+			)
+		} else {
+			parse_body(req)
+		};
+		Box::new(
+			tx.and_then(move |tx: Transaction| {
+				let submitted = PoolSubmit::from_tx(&tx, !fluff);
+				push_tx(&pool_arc, tx, fluff, "submit-api").map(|_| submitted)
+			}).then(|res| result_to_response(res)),
+		)
+	}
+}
+
+/// Where transactions get checked against the pool
+pub const POOL_CHECK_PATH: &'static str = "/v1/pool/check";
+
+/// Runs the checks pushing a transaction would, without adding it to the
+/// pool. Checked for the stem pool unless `?fluff` is given, like pushes.
+/// POST /v1/pool/check
+pub struct PoolCheckHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl Handler for PoolCheckHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let fluff = req
+			.uri()
+			.query()
+			.map(|q| form_urlencoded::parse(q.as_bytes()).any(|(k, _)| k == "fluff"))
+			.unwrap_or(false);
+		let pool_arc = w(&self.tx_pool);
+		Box::new(
+			parse_body(req)
+				.and_then(move |wrapper: TxWrapper| check_tx_hex(&pool_arc, wrapper.tx_hex, fluff))
+				.then(|res| result_to_response(res)),
+		)
+	}
+}
+
+/// Checks a hex-encoded serialized transaction could get in the pool, for
+/// the stem pool unless fluff is set
+pub fn check_tx_hex(
+	pool_arc: &RwLock<pool::TransactionPool>,
+	tx_hex: String,
+	fluff: bool,
+) -> Result<PoolCheck, Error> {
+	let tx_bin = util::from_hex(tx_hex)
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+	let tx: Transaction = ser::deserialize(&mut &tx_bin[..])
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+
+	let tx_pool = pool_arc.read();
+	let header = tx_pool
+		.blockchain
+		.chain_head()
+		.map_err(|e| ErrorKind::Internal(format!("{:?}", e)))?;
+	let res = tx_pool.check_tx(&tx, !fluff, &header);
+	let weight = tx.tx_weight() as u64;
+	Ok(PoolCheck {
+		hash: tx.hash().to_hex(),
+		weight,
+		fee: tx.fee(),
+		fee_rate: tx.fee() / cmp::max(weight, 1),
+		min_fee: weight * tx_pool.config.accept_fee_base,
+		acceptable: res.is_ok(),
+		error: res.err().map(|e| format!("{:?}", e)),
+	})
+}
+
 impl Handler for PoolPushHandler {
 	fn post(&self, req: Request<Body>) -> ResponseFuture {
 		Box::new(
@@ -125,3 +265,49 @@ impl Handler for PoolPushHandler {
 		)
 	}
 }
+
+/// Dandelion parameters the node runs with. Posting some of them, like
+/// {"stem_probability": 50}, changes those right away, until the node
+/// restarts or the configuration is reloaded with other values.
+/// GET /v1/pool/dandelion
+/// POST /v1/pool/dandelion
+pub struct DandelionHandler {
+	pub dandelion_config: Weak<RwLock<pool::DandelionConfig>>,
+}
+
+impl DandelionHandler {
+	pub fn get_config(&self) -> Result<pool::DandelionConfig, Error> {
+		Ok(w(&self.dandelion_config).read().clone())
+	}
+
+	/// Applies the changes, returning the resulting parameters
+	pub fn set_config(
+		&self,
+		changes: &pool::DandelionConfig,
+	) -> Result<pool::DandelionConfig, Error> {
+		let config_arc = w(&self.dandelion_config);
+		let mut config = config_arc.write();
+		config.apply(changes).map_err(|e| match e {
+			pool::PoolError::Other(msg) => ErrorKind::Argument(msg),
+			e => ErrorKind::Internal(format!("{:?}", e)),
+		})?;
+		info!("Dandelion parameters changed to {:?}", *config);
+		Ok(config.clone())
+	}
+}
+
+impl Handler for DandelionHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_config())
+	}
+
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let handler = DandelionHandler {
+			dandelion_config: self.dandelion_config.clone(),
+		};
+		Box::new(parse_body(req).then(move |changes| match changes {
+			Ok(changes) => result_to_response(handler.set_config(&changes)),
+			Err(e) => result_to_response::<pool::DandelionConfig>(Err(e)),
+		}))
+	}
+}
@@ -0,0 +1,414 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON-RPC 2.0 interface over the same queries and commands as the REST
+//! handlers, all through a single endpoint. Batches are supported, and
+//! `rpc.methods` lists what can be called. Node errors come back with a code
+//! from the `-32000` to `-32099` range reserved for implementations.
+
+use super::blocks_api::{BlockHandler, HeaderHandler};
+use super::chain_api::{ChainHandler, ChainLookupHandler, OutputHandler};
+use super::peers_api::banned_peers;
+use super::pool_api::{check_tx_hex, push_tx_hex, DandelionHandler};
+use super::server_api::StatusHandler;
+use super::utils::w;
+use auth::ReadOnlyAccess;
+use chain;
+use futures::future::ok;
+use futures::{Future, Stream};
+use hyper::{Body, Request, StatusCode};
+use jsonrpc::{self, RpcError, INTERNAL_ERROR, INVALID_PARAMS, METHOD_NOT_FOUND};
+use p2p;
+use p2p::types::{PeerInfoDisplay, ReasonForBan};
+use pool;
+use rest::*;
+use router::{Handler, ResponseFuture};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use std::net::SocketAddr;
+use std::sync::Weak;
+use types::*;
+use util::RwLock;
+use web::*;
+
+/// Where the JSON-RPC endpoint is served
+pub const JSONRPC_PATH: &'static str = "/v1/jsonrpc";
+
+/// What was asked for doesn't exist
+pub const NOT_FOUND: i64 = -32000;
+/// The method needs the owner secret, the read-only one was given
+pub const FORBIDDEN: i64 = -32001;
+
+/// A method that can be called, as listed by `rpc.methods`
+#[derive(Serialize)]
+struct RpcMethod {
+	name: &'static str,
+	params: &'static [&'static str],
+	owner_only: bool,
+	description: &'static str,
+}
+
+const METHODS: &'static [RpcMethod] = &[
+	RpcMethod {
+		name: "rpc.methods",
+		params: &[],
+		owner_only: false,
+		description: "List the available methods",
+	},
+	RpcMethod {
+		name: "get_tip",
+		params: &[],
+		owner_only: false,
+		description: "Get the head of the chain",
+	},
+	RpcMethod {
+		name: "get_status",
+		params: &[],
+		owner_only: false,
+		description: "Get the node version, connections and tip",
+	},
+	RpcMethod {
+		name: "get_block",
+		params: &["id", "compact"],
+		owner_only: false,
+		description: "Get a block by height, hash or output commitment",
+	},
+	RpcMethod {
+		name: "get_header",
+		params: &["id"],
+		owner_only: false,
+		description: "Get a block header by height, hash or output commitment",
+	},
+	RpcMethod {
+		name: "get_outputs",
+		params: &["commits"],
+		owner_only: false,
+		description: "Get the unspent outputs among the given commitments",
+	},
+	RpcMethod {
+		name: "lookup",
+		params: &["kernels", "outputs"],
+		owner_only: false,
+		description: "Tell which kernel excesses and output commitments are on the chain",
+	},
+	RpcMethod {
+		name: "validate_chain",
+		params: &[],
+		owner_only: true,
+		description: "Validate the full chain state",
+	},
+	RpcMethod {
+		name: "compact_chain",
+		params: &[],
+		owner_only: true,
+		description: "Compact the chain state to regain storage space",
+	},
+	RpcMethod {
+		name: "get_pool_size",
+		params: &[],
+		owner_only: false,
+		description: "Get the number of transactions in the pool",
+	},
+	RpcMethod {
+		name: "push_transaction",
+		params: &["tx_hex", "fluff"],
+		owner_only: true,
+		description: "Push a hex-encoded transaction to the pool",
+	},
+	RpcMethod {
+		name: "check_transaction",
+		params: &["tx_hex", "fluff"],
+		owner_only: false,
+		description: "Check a hex-encoded transaction would get in the pool, without pushing it",
+	},
+	RpcMethod {
+		name: "get_dandelion_config",
+		params: &[],
+		owner_only: false,
+		description: "Get the Dandelion parameters the node runs with",
+	},
+	RpcMethod {
+		name: "set_dandelion_config",
+		params: &[
+			"relay_secs",
+			"embargo_secs",
+			"embargo_jitter_secs",
+			"patience_secs",
+			"aggregation_secs",
+			"stem_probability",
+		],
+		owner_only: true,
+		description: "Change some of the Dandelion parameters, until restart or config reload",
+	},
+	RpcMethod {
+		name: "get_peers",
+		params: &[],
+		owner_only: false,
+		description: "Get all known peers",
+	},
+	RpcMethod {
+		name: "get_connected_peers",
+		params: &[],
+		owner_only: false,
+		description: "Get the peers currently connected",
+	},
+	RpcMethod {
+		name: "get_banned_peers",
+		params: &[],
+		owner_only: false,
+		description: "Get the peers currently banned, with when their ban runs out",
+	},
+	RpcMethod {
+		name: "ban_peer",
+		params: &["addr", "reason", "duration", "note"],
+		owner_only: true,
+		description: "Ban a peer, given as ip:port, optionally with a reason, a note and for a number of seconds",
+	},
+	RpcMethod {
+		name: "unban_peer",
+		params: &["addr"],
+		owner_only: true,
+		description: "Unban a peer, given as ip:port",
+	},
+];
+
+lazy_static! {
+	// the methods and their params, as the protocol looks them up
+	static ref CATALOG: Vec<(&'static str, &'static [&'static str])> =
+		METHODS.iter().map(|m| (m.name, m.params)).collect();
+}
+
+impl From<Error> for RpcError {
+	fn from(e: Error) -> RpcError {
+		match e.kind() {
+			ErrorKind::Argument(msg) | ErrorKind::RequestError(msg) => {
+				RpcError::new(INVALID_PARAMS, msg)
+			}
+			ErrorKind::NotFound => RpcError::new(NOT_FOUND, "Not found"),
+			ErrorKind::Internal(msg) | ErrorKind::ResponseError(msg) => {
+				RpcError::new(INTERNAL_ERROR, msg)
+			}
+		}
+	}
+}
+
+/// Call parameters, by name
+struct Params(Map<String, Value>);
+
+impl Params {
+	fn new(params: Value) -> Params {
+		match params {
+			Value::Object(map) => Params(map),
+			_ => Params(Map::new()),
+		}
+	}
+
+	fn get<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, RpcError> {
+		match self.0.get(name) {
+			None | Some(Value::Null) => Ok(None),
+			Some(v) => serde_json::from_value(v.clone())
+				.map(Some)
+				.map_err(|e| RpcError::new(INVALID_PARAMS, &format!("invalid {}: {}", name, e))),
+		}
+	}
+
+	fn req<T: DeserializeOwned>(&self, name: &str) -> Result<T, RpcError> {
+		self.get(name)?
+			.ok_or_else(|| RpcError::new(INVALID_PARAMS, &format!("missing {}", name)))
+	}
+}
+
+fn to_value<T: Serialize>(res: Result<T, Error>) -> Result<Value, RpcError> {
+	let res = res?;
+	serde_json::to_value(res).map_err(|e| RpcError::new(INTERNAL_ERROR, &e.to_string()))
+}
+
+/// JSON-RPC 2.0 endpoint
+/// POST /v1/jsonrpc
+///
+/// Takes a single call or a batch of them, e.g.
+/// {"jsonrpc": "2.0", "method": "get_block", "params": [1000], "id": 1}
+#[derive(Clone)]
+pub struct RpcHandler {
+	pub chain: Weak<chain::Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub dandelion_config: Weak<RwLock<pool::DandelionConfig>>,
+	pub peers: Weak<p2p::Peers>,
+	pub status: StatusHandler,
+}
+
+impl RpcHandler {
+	/// Run a single call, none of the owner only methods when read_only
+	fn call(&self, method: &str, params: Value, read_only: bool) -> Result<Value, RpcError> {
+		let owner_only = METHODS.iter().any(|m| m.name == method && m.owner_only);
+		if owner_only && read_only {
+			return Err(RpcError::new(FORBIDDEN, "owner secret required"));
+		}
+		let params = Params::new(params);
+		match method {
+			"rpc.methods" => to_value(Ok(METHODS)),
+			"get_tip" => to_value(
+				ChainHandler {
+					chain: self.chain.clone(),
+				}.get_tip(),
+			),
+			"get_status" => to_value(self.status.get_status()),
+			"get_block" => {
+				let id = block_id(params.req("id")?)?;
+				let compact = params.get("compact")?.unwrap_or(false);
+				let handler = BlockHandler {
+					chain: self.chain.clone(),
+				};
+				let h = handler.parse_input(id)?;
+				if compact {
+					to_value(handler.get_compact_block(&h))
+				} else {
+					to_value(handler.get_block(&h))
+				}
+			}
+			"get_header" => to_value(
+				HeaderHandler {
+					chain: self.chain.clone(),
+				}.get_header(block_id(params.req("id")?)?),
+			),
+			"get_outputs" => {
+				let commits: Vec<String> = params.req("commits")?;
+				let handler = OutputHandler {
+					chain: self.chain.clone(),
+				};
+				to_value(Ok(commits
+					.iter()
+					.filter_map(|c| handler.get_output(c).ok())
+					.collect::<Vec<Output>>()))
+			}
+			"lookup" => {
+				let req = LookupRequest {
+					kernels: params.get("kernels")?.unwrap_or(vec![]),
+					outputs: params.get("outputs")?.unwrap_or(vec![]),
+				};
+				to_value(
+					ChainLookupHandler {
+						chain: self.chain.clone(),
+					}.lookup(&req),
+				)
+			}
+			"validate_chain" => {
+				w(&self.chain).validate(true).map_err(|e| {
+					RpcError::new(INTERNAL_ERROR, &format!("validate failed: {}", e))
+				})?;
+				Ok(Value::Null)
+			}
+			"compact_chain" => {
+				w(&self.chain).compact().map_err(|e| {
+					RpcError::new(INTERNAL_ERROR, &format!("compact failed: {}", e))
+				})?;
+				Ok(Value::Null)
+			}
+			"get_pool_size" => to_value(Ok(PoolInfo {
+				pool_size: w(&self.tx_pool).read().total_size(),
+			})),
+			"push_transaction" => {
+				let fluff = params.get("fluff")?.unwrap_or(false);
+				push_tx_hex(&w(&self.tx_pool), params.req("tx_hex")?, fluff)?;
+				Ok(Value::Null)
+			}
+			"check_transaction" => {
+				let fluff = params.get("fluff")?.unwrap_or(false);
+				to_value(check_tx_hex(
+					&w(&self.tx_pool),
+					params.req("tx_hex")?,
+					fluff,
+				))
+			}
+			"get_dandelion_config" => to_value(
+				DandelionHandler {
+					dandelion_config: self.dandelion_config.clone(),
+				}.get_config(),
+			),
+			"set_dandelion_config" => {
+				let changes = pool::DandelionConfig {
+					relay_secs: params.get("relay_secs")?,
+					embargo_secs: params.get("embargo_secs")?,
+					embargo_jitter_secs: params.get("embargo_jitter_secs")?,
+					patience_secs: params.get("patience_secs")?,
+					aggregation_secs: params.get("aggregation_secs")?,
+					stem_probability: params.get("stem_probability")?,
+				};
+				to_value(
+					DandelionHandler {
+						dandelion_config: self.dandelion_config.clone(),
+					}.set_config(&changes),
+				)
+			}
+			"get_peers" => to_value(Ok(w(&self.peers).all_peers())),
+			"get_connected_peers" => to_value(Ok(w(&self.peers)
+				.connected_peers()
+				.iter()
+				.map(|p| p.info.clone().into())
+				.collect::<Vec<PeerInfoDisplay>>())),
+			"get_banned_peers" => to_value(Ok(banned_peers(&w(&self.peers)))),
+			"ban_peer" => {
+				let reason = params.get("reason")?.unwrap_or(ReasonForBan::ManualBan);
+				let duration = params.get("duration")?.unwrap_or(0);
+				let note: String = params.get("note")?.unwrap_or_default();
+				w(&self.peers).ban_peer_for(&peer_addr(&params)?, reason, duration, &note);
+				Ok(Value::Null)
+			}
+			"unban_peer" => {
+				w(&self.peers).unban_peer(&peer_addr(&params)?);
+				Ok(Value::Null)
+			}
+			_ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+		}
+	}
+}
+
+/// Blocks and headers can be asked for by height as well as by hash
+fn block_id(id: Value) -> Result<String, RpcError> {
+	match id {
+		Value::Number(n) => Ok(n.to_string()),
+		Value::String(s) => Ok(s),
+		_ => Err(RpcError::new(
+			INVALID_PARAMS,
+			"id must be a height, hash or commitment",
+		)),
+	}
+}
+
+fn peer_addr(params: &Params) -> Result<SocketAddr, RpcError> {
+	let addr: String = params.req("addr")?;
+	addr.parse()
+		.map_err(|e| RpcError::new(INVALID_PARAMS, &format!("invalid peer address: {}", e)))
+}
+
+impl Handler for RpcHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let read_only = req.extensions().get::<ReadOnlyAccess>().is_some();
+		let handler = self.clone();
+		Box::new(req.into_body().concat2().and_then(move |body| {
+			let res = jsonrpc::handle(&body, &CATALOG, |method, params| {
+				handler.call(method, params, read_only)
+			});
+			match res {
+				Some(res) => response(StatusCode::OK, res),
+				None => response_empty(),
+			}
+		}))
+	}
+}
+
+fn response_empty() -> ResponseFuture {
+	Box::new(ok(just_response(StatusCode::NO_CONTENT, "")))
+}
@@ -16,10 +16,13 @@ use super::utils::w;
 use chain;
 use hyper::{Body, Request};
 use p2p;
+use pool;
 use rest::*;
 use router::{Handler, ResponseFuture};
-use std::sync::Weak;
+use std::sync::{Arc, Weak};
+use std::time::Instant;
 use types::*;
+use util::RwLock;
 use web::*;
 
 // RESTful index of available api endpoints
@@ -36,22 +39,68 @@ impl Handler for IndexHandler {
 	}
 }
 
+/// What the status reports about the subsystems the API has no handle on,
+/// provided by the server running it
+pub trait NodeStatusSource: Send + Sync {
+	/// Coarse stage of the sync: waiting, header, state, body or synced
+	fn sync_stage(&self) -> Option<String>;
+
+	/// Connected stratum workers, none when stratum doesn't run
+	fn stratum_workers(&self) -> Option<u32>;
+}
+
 /// Status handler. Post a summary of the server status
 /// GET /v1/status
+#[derive(Clone)]
 pub struct StatusHandler {
 	pub chain: Weak<chain::Chain>,
 	pub peers: Weak<p2p::Peers>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub dandelion_config: Weak<RwLock<pool::DandelionConfig>>,
+	pub source: Option<Arc<NodeStatusSource>>,
+	pub started: Instant,
 }
 
 impl StatusHandler {
-	fn get_status(&self) -> Result<Status, Error> {
-		let head = w(&self.chain)
+	pub fn get_status(&self) -> Result<Status, Error> {
+		let chain = w(&self.chain);
+		let peers = w(&self.peers);
+		let head = chain
 			.head()
 			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
-		Ok(Status::from_tip_and_peers(
-			head,
-			w(&self.peers).peer_count(),
-		))
+		let connected = peers.connected_peers();
+		let inbound = connected
+			.iter()
+			.filter(|p| p.info.direction == p2p::Direction::Inbound)
+			.count() as u32;
+		let (pool_size, pool_weight) = {
+			let pool_arc = w(&self.tx_pool);
+			let pool = pool_arc.read();
+			(pool.total_size(), pool.total_weight())
+		};
+		let disk_usage = match chain.disk_usage() {
+			Ok(size) => Some(size),
+			Err(e) => {
+				warn!("status: can't size the chain data: {}", e);
+				None
+			}
+		};
+		Ok(Status {
+			sync_stage: self.source.as_ref().and_then(|s| s.sync_stage()),
+			inbound_peers: inbound,
+			outbound_peers: connected.len() as u32 - inbound,
+			pool_size,
+			pool_weight,
+			stratum_workers: self.source.as_ref().and_then(|s| s.stratum_workers()),
+			disk_usage,
+			uptime: self.started.elapsed().as_secs(),
+			..Status::from_tip_and_peers(
+				head,
+				peers.peer_count(),
+				Resources::sample(&chain, &peers),
+				w(&self.dandelion_config).read().clone(),
+			)
+		})
 	}
 }
 
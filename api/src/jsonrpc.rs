@@ -0,0 +1,194 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON-RPC 2.0 protocol, shared by the node and wallet endpoints. Requests
+//! may be batched, and params given either by name or by position, in the
+//! order listed in the catalog of methods of each endpoint. Errors specific
+//! to an endpoint get a code from the `-32000` to `-32099` range reserved for
+//! implementations.
+
+use serde_json::{self, Map, Value};
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal JSON-RPC error
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Methods that can be called along with their params, in positional order
+pub type Catalog<'a> = &'a [(&'a str, &'a [&'a str])];
+
+/// JSON-RPC error object
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcError {
+	/// Error code, one of the constants of this module or of the endpoint
+	pub code: i64,
+	/// Short description of the error
+	pub message: String,
+	/// Details specific to the error, if any
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub data: Option<Value>,
+}
+
+impl RpcError {
+	/// An error without details
+	pub fn new(code: i64, message: &str) -> RpcError {
+		RpcError {
+			code,
+			message: message.to_owned(),
+			data: None,
+		}
+	}
+}
+
+/// JSON-RPC response object
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcResponse {
+	/// Always "2.0"
+	pub jsonrpc: String,
+	/// Result of a successful call
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub result: Option<Value>,
+	/// Error of a failed call
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<RpcError>,
+	/// Id of the request answered
+	pub id: Value,
+}
+
+impl RpcResponse {
+	fn new(id: Value, res: Result<Value, RpcError>) -> RpcResponse {
+		let (result, error) = match res {
+			Ok(v) => (Some(v), None),
+			Err(e) => (None, Some(e)),
+		};
+		RpcResponse {
+			jsonrpc: "2.0".to_owned(),
+			result,
+			error,
+			id,
+		}
+	}
+}
+
+/// Handle a request body, made of a single call or a batch of them, with
+/// the method dispatcher given, which gets the params as an object. Returns
+/// nothing when the body was made of notifications only.
+pub fn handle<F>(body: &[u8], catalog: Catalog, mut call: F) -> Option<String>
+where
+	F: FnMut(&str, Value) -> Result<Value, RpcError>,
+{
+	let res = match serde_json::from_slice::<Value>(body) {
+		Err(_) => Some(
+			serde_json::to_value(RpcResponse::new(
+				Value::Null,
+				Err(RpcError::new(PARSE_ERROR, "Parse error")),
+			)).unwrap(),
+		),
+		Ok(Value::Array(reqs)) => {
+			if reqs.is_empty() {
+				Some(
+					serde_json::to_value(RpcResponse::new(
+						Value::Null,
+						Err(RpcError::new(INVALID_REQUEST, "Invalid Request")),
+					)).unwrap(),
+				)
+			} else {
+				let resps: Vec<RpcResponse> = reqs
+					.into_iter()
+					.filter_map(|r| handle_call(r, catalog, &mut call))
+					.collect();
+				if resps.is_empty() {
+					None
+				} else {
+					Some(serde_json::to_value(resps).unwrap())
+				}
+			}
+		}
+		Ok(req) => handle_call(req, catalog, &mut call).map(|r| serde_json::to_value(r).unwrap()),
+	};
+	res.map(|v| v.to_string())
+}
+
+fn handle_call<F>(req: Value, catalog: Catalog, call: &mut F) -> Option<RpcResponse>
+where
+	F: FnMut(&str, Value) -> Result<Value, RpcError>,
+{
+	let mut req = match req {
+		Value::Object(m) => m,
+		_ => {
+			return Some(RpcResponse::new(
+				Value::Null,
+				Err(RpcError::new(INVALID_REQUEST, "Invalid Request")),
+			))
+		}
+	};
+	let id = req.remove("id");
+	let valid_id = match id {
+		Some(ref id) => id.is_null() || id.is_string() || id.is_number(),
+		None => true,
+	};
+	let method = match req.remove("method") {
+		Some(Value::String(m)) => Some(m),
+		_ => None,
+	};
+	let params = req.remove("params").unwrap_or(Value::Object(Map::new()));
+	let valid_params = params.is_object() || params.is_array();
+	if !valid_id
+		|| !valid_params
+		|| method.is_none()
+		|| req.get("jsonrpc") != Some(&Value::from("2.0"))
+	{
+		return Some(RpcResponse::new(
+			id.unwrap_or(Value::Null),
+			Err(RpcError::new(INVALID_REQUEST, "Invalid Request")),
+		));
+	}
+	let method = method.unwrap();
+
+	let res = match catalog.iter().find(|(m, _)| *m == method) {
+		None => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+		Some((_, names)) => match named_params(params, names) {
+			Ok(params) => call(&method, params),
+			Err(e) => Err(e),
+		},
+	};
+	if let Err(ref e) = res {
+		debug!("jsonrpc: {} failed with error: {}", method, e.message);
+	}
+	// no response to notifications
+	id.map(|id| RpcResponse::new(id, res))
+}
+
+/// Params as an object, naming positional ones after the catalog
+fn named_params(params: Value, names: &[&str]) -> Result<Value, RpcError> {
+	match params {
+		Value::Array(values) => {
+			if values.len() > names.len() {
+				return Err(RpcError::new(INVALID_PARAMS, "Too many params"));
+			}
+			let mut m = Map::new();
+			for (name, v) in names.iter().zip(values) {
+				m.insert(name.to_string(), v);
+			}
+			Ok(Value::Object(m))
+		}
+		p => Ok(p),
+	}
+}
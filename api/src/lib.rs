@@ -20,12 +20,15 @@ extern crate grin_store as store;
 extern crate grin_util as util;
 extern crate url;
 
+extern crate base64;
+extern crate bytes;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
 extern crate hyper;
 #[macro_use]
 extern crate lazy_static;
+extern crate lru_cache;
 extern crate regex;
 extern crate ring;
 extern crate serde;
@@ -45,15 +48,34 @@ extern crate tokio_tcp;
 
 pub mod auth;
 pub mod client;
+pub mod cors;
 mod handlers;
+pub mod jsonrpc;
+pub mod limits;
+pub mod metrics;
+pub mod node_client;
 mod rest;
 mod router;
+mod sse;
 mod types;
+mod v2;
 mod web;
+mod ws;
 
-pub use auth::BasicAuthMiddleware;
-pub use handlers::start_rest_apis;
+pub use auth::{BasicAuthMiddleware, ReadOnlyAccess, RoleAuthMiddleware};
+pub use cors::CorsMiddleware;
+pub use handlers::{
+	start_rest_apis, NodeStatusSource, PoolSubmitHandler, ReadinessConfig, CHAIN_LOOKUP_PATH,
+	HEALTH_PATH, JSONRPC_PATH, POOL_CHECK_PATH, READY_PATH,
+};
+pub use jsonrpc::RpcError;
+pub use limits::{ApiLimits, RateLimitMiddleware, SizeLimitMiddleware};
+pub use metrics::{ApiMetrics, MetricsHandler, TraceMiddleware, METRICS_PATH};
+pub use node_client::{BlockRef, NodeClient};
 pub use rest::*;
 pub use router::*;
+pub use sse::*;
 pub use types::*;
+pub use v2::*;
 pub use web::*;
+pub use ws::*;
@@ -0,0 +1,305 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits protecting a public facing API: requests per client address, and
+//! the size of request and response bodies.
+
+use futures::future::ok;
+use futures::{Future, Stream};
+use hyper::body::Payload;
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::{Body, Request, Response, StatusCode};
+use lru_cache::LruCache;
+use rest::RemoteAddr;
+use router::{Handler, HandlerObj, ResponseFuture};
+use std::cell::Cell;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Instant;
+use util::Mutex;
+
+/// Clients tracked at most, the least recently seen ones forgotten past that
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+thread_local! {
+	// cap on the size of the response the handler called on this thread
+	// builds, for it to stop serializing once past it
+	static RESPONSE_CAP: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// What a request to an expensive endpoint counts for (full blocks, output
+/// and txhashset scans, JSON-RPC batches), plain ones counting for 1
+const EXPENSIVE_REQUEST_COST: f64 = 10.0;
+
+/// Limits on the API, 0 turning each off, as they are by default
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiLimits {
+	/// Requests per minute allowed to each client address, requests to
+	/// expensive endpoints counting for more
+	pub rate_limit: u32,
+	/// Largest request body accepted, in bytes
+	pub max_request_size: u64,
+	/// Largest response body sent, in bytes
+	pub max_response_size: u64,
+}
+
+fn request_cost(req: &Request<Body>) -> f64 {
+	let path = req.uri().path();
+	let expensive = ["/blocks", "/chain/outputs", "/txhashset", "/jsonrpc"];
+	let versioned = path.starts_with("/v1/") || path.starts_with("/v2/");
+	if versioned && expensive.iter().any(|p| path[3..].starts_with(p)) {
+		EXPENSIVE_REQUEST_COST
+	} else {
+		1.0
+	}
+}
+
+/// Address a client is told apart by. A host usually gets a whole IPv6 /64,
+/// so those count as a single client.
+fn client_key(ip: IpAddr) -> IpAddr {
+	match ip {
+		IpAddr::V4(_) => ip,
+		IpAddr::V6(v6) => {
+			let s = v6.segments();
+			match v6.to_ipv4() {
+				// IPv4-mapped
+				Some(v4) if s[5] == 0xffff => IpAddr::V4(v4),
+				_ => IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0)),
+			}
+		}
+	}
+}
+
+/// What's left of a client's allowance
+struct Allowance {
+	tokens: f64,
+	updated: Instant,
+}
+
+/// Rate limiting per client address, a token bucket each refilled at the
+/// configured rate per minute. Local clients aren't limited.
+pub struct RateLimitMiddleware {
+	per_minute: f64,
+	clients: Mutex<LruCache<IpAddr, Allowance>>,
+}
+
+impl RateLimitMiddleware {
+	pub fn new(per_minute: u32) -> RateLimitMiddleware {
+		RateLimitMiddleware {
+			per_minute: per_minute as f64,
+			clients: Mutex::new(LruCache::new(MAX_TRACKED_CLIENTS)),
+		}
+	}
+
+	/// Take the cost of a request out of the client's allowance, returning
+	/// the seconds to wait before retrying when there isn't enough left
+	fn take(&self, ip: IpAddr, cost: f64) -> Result<(), u64> {
+		let now = Instant::now();
+		let per_minute = self.per_minute;
+		let key = client_key(ip);
+		let mut clients = self.clients.lock();
+		if !clients.contains_key(&key) {
+			clients.insert(
+				key,
+				Allowance {
+					tokens: per_minute,
+					updated: now,
+				},
+			);
+		}
+		let allowance = clients.get_mut(&key).unwrap();
+		let elapsed = now.duration_since(allowance.updated);
+		let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+		allowance.tokens = (allowance.tokens + elapsed * per_minute / 60.0).min(per_minute);
+		allowance.updated = now;
+		// a request costing more than the whole allowance still goes through
+		// once it's full
+		let cost = cost.min(per_minute);
+		if allowance.tokens >= cost {
+			allowance.tokens -= cost;
+			Ok(())
+		} else {
+			Err(((cost - allowance.tokens) * 60.0 / per_minute).ceil() as u64)
+		}
+	}
+}
+
+impl Handler for RateLimitMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let ip = match req.extensions().get::<RemoteAddr>() {
+			Some(addr) if !addr.0.ip().is_loopback() => addr.0.ip(),
+			_ => return handlers.next().unwrap().call(req, handlers),
+		};
+		match self.take(ip, request_cost(&req)) {
+			Ok(()) => handlers.next().unwrap().call(req, handlers),
+			Err(retry_after) => {
+				debug!("Rate limiting API calls from {}", ip);
+				Box::new(ok(Response::builder()
+					.status(StatusCode::TOO_MANY_REQUESTS)
+					.header(RETRY_AFTER, HeaderValue::from(retry_after))
+					.body(Body::from("too many requests"))
+					.unwrap()))
+			}
+		}
+	}
+}
+
+/// Caps the size of request and response bodies. Requests too large are
+/// turned down, before reading them whole when they give their length.
+pub struct SizeLimitMiddleware {
+	max_request_size: Option<u64>,
+	max_response_size: Option<u64>,
+}
+
+impl SizeLimitMiddleware {
+	pub fn new(
+		max_request_size: Option<u64>,
+		max_response_size: Option<u64>,
+	) -> SizeLimitMiddleware {
+		SizeLimitMiddleware {
+			max_request_size,
+			max_response_size,
+		}
+	}
+
+	/// Calls the handler with the response cap set for the serialization
+	/// helpers to stop early, checking the size of the body it comes up
+	/// with in case it got built some other way
+	fn call_capped(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let max = match self.max_response_size {
+			None => return handlers.next().unwrap().call(req, handlers),
+			Some(max) => max,
+		};
+		let prev = RESPONSE_CAP.with(|cap| cap.replace(Some(max)));
+		let res = handlers.next().unwrap().call(req, handlers);
+		RESPONSE_CAP.with(|cap| cap.set(prev));
+		Box::new(res.map(move |res| match res.body().content_length() {
+			Some(length) if length > max => response_too_large(max),
+			_ => res,
+		}))
+	}
+}
+
+/// Response sent instead of one past the configured size
+pub fn response_too_large(max: u64) -> Response<Body> {
+	too_large(
+		StatusCode::INTERNAL_SERVER_ERROR,
+		format!("response larger than {} bytes, ask for less", max),
+	)
+}
+
+/// Buffer for a response body, refusing to grow past the cap on responses
+/// when a handler is called under one
+pub struct ResponseBuf {
+	buf: Vec<u8>,
+	cap: Option<u64>,
+	overflowed: bool,
+}
+
+impl ResponseBuf {
+	pub fn new() -> ResponseBuf {
+		ResponseBuf {
+			buf: vec![],
+			cap: RESPONSE_CAP.with(|cap| cap.get()),
+			overflowed: false,
+		}
+	}
+
+	/// The cap, if the body got cut off by it
+	pub fn overflowed(&self) -> Option<u64> {
+		self.cap.filter(|_| self.overflowed)
+	}
+
+	pub fn into_inner(self) -> Vec<u8> {
+		self.buf
+	}
+}
+
+impl io::Write for ResponseBuf {
+	fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+		if let Some(cap) = self.cap {
+			if (self.buf.len() + data.len()) as u64 > cap {
+				self.overflowed = true;
+				return Err(io::Error::new(
+					io::ErrorKind::Other,
+					format!("response larger than {} bytes", cap),
+				));
+			}
+		}
+		self.buf.extend_from_slice(data);
+		Ok(data.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+fn too_large(status: StatusCode, msg: String) -> Response<Body> {
+	Response::builder()
+		.status(status)
+		.body(Body::from(msg))
+		.unwrap()
+}
+
+impl Handler for SizeLimitMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let req = match self.max_request_size {
+			None => req,
+			Some(max) => {
+				match req.body().content_length() {
+					Some(length) if length > max => {
+						return Box::new(ok(too_large(
+							StatusCode::PAYLOAD_TOO_LARGE,
+							format!("request body larger than {} bytes", max),
+						)))
+					}
+					Some(_) => return self.call_capped(req, handlers),
+					None => (),
+				}
+				// bodies of unknown length are cut off once past the cap
+				let (parts, body) = req.into_parts();
+				let mut read = 0;
+				let body = body.then(move |chunk| match chunk {
+					Ok(chunk) => {
+						read += chunk.len() as u64;
+						if read > max {
+							Err(io::Error::new(
+								io::ErrorKind::InvalidData,
+								format!("request body larger than {} bytes", max),
+							))
+						} else {
+							Ok(chunk)
+						}
+					}
+					Err(e) => Err(io::Error::new(io::ErrorKind::BrokenPipe, e)),
+				});
+				Request::from_parts(parts, Body::wrap_stream(body))
+			}
+		};
+		self.call_capped(req, handlers)
+	}
+}
@@ -0,0 +1,344 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request tracing: every call gets an ID, logged with it and sent back in
+//! the `X-Request-Id` header, and the time taken to answer it goes in a
+//! latency histogram per endpoint, served in the Prometheus text format at
+//! `/v1/metrics` along with the transfer rates with the peers and the
+//! resources used by the node.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use futures::future::ok;
+use futures::Future;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Request, Response};
+
+use chain;
+use p2p;
+use router::{Handler, HandlerObj, ResponseFuture};
+use types::Resources;
+use util::Mutex;
+
+/// Path the metrics are served at
+pub const METRICS_PATH: &'static str = "/v1/metrics";
+
+/// Header carrying the request ID, taken from the request when a proxy in
+/// front already set one
+pub const REQUEST_ID_HEADER: &'static str = "x-request-id";
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 11] = [
+	0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Endpoints tracked, past which requests are counted as `other`
+const MAX_ENDPOINTS: usize = 200;
+
+/// Requests taking longer are logged as warnings
+const SLOW_REQUEST_SECS: f64 = 1.0;
+
+/// Windows the peer transfer rates are averaged over, in seconds
+const RATE_WINDOWS: [(&'static str, u64); 3] = [("1s", 1), ("10s", 10), ("5m", 300)];
+
+/// Latencies of the requests to an endpoint
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+	buckets: [u64; 11],
+	count: u64,
+	sum: f64,
+}
+
+impl Histogram {
+	fn observe(&mut self, secs: f64) {
+		for (i, bound) in BUCKETS.iter().enumerate() {
+			if secs <= *bound {
+				self.buckets[i] += 1;
+			}
+		}
+		self.count += 1;
+		self.sum += secs;
+	}
+}
+
+/// Latency histograms of the API endpoints, by method and endpoint
+pub struct ApiMetrics {
+	latencies: Mutex<BTreeMap<(String, String), Histogram>>,
+}
+
+impl ApiMetrics {
+	pub fn new() -> ApiMetrics {
+		ApiMetrics {
+			latencies: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	/// Records the time a request to an endpoint took
+	pub fn observe(&self, method: &str, endpoint: &str, secs: f64) {
+		let mut latencies = self.latencies.lock();
+		let mut key = (method.to_owned(), endpoint.to_owned());
+		if !latencies.contains_key(&key) && latencies.len() >= MAX_ENDPOINTS {
+			key.1 = "other".to_owned();
+		}
+		latencies.entry(key).or_default().observe(secs);
+	}
+
+	/// The histograms in the Prometheus text format
+	pub fn render(&self) -> String {
+		let name = "grin_api_request_duration_seconds";
+		let mut out = String::new();
+		let _ = writeln!(out, "# HELP {} Time taken to answer API requests", name);
+		let _ = writeln!(out, "# TYPE {} histogram", name);
+		for ((method, endpoint), h) in self.latencies.lock().iter() {
+			let labels = format!("method=\"{}\",endpoint=\"{}\"", method, endpoint);
+			for (bound, count) in BUCKETS.iter().zip(h.buckets.iter()) {
+				let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, count);
+			}
+			let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, h.count);
+			let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, h.sum);
+			let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, h.count);
+		}
+		out
+	}
+}
+
+impl Default for ApiMetrics {
+	fn default() -> ApiMetrics {
+		ApiMetrics::new()
+	}
+}
+
+/// The endpoint a path is for, heights, hashes, commitments and peer
+/// addresses standing as `{id}` so they don't each get a histogram
+pub fn endpoint(path: &str) -> String {
+	let segments: Vec<&str> = path
+		.split('/')
+		.filter(|s| !s.is_empty())
+		.map(|s| {
+			let is_id = s.chars().all(|c| c.is_ascii_digit())
+				|| s.contains('.')
+				|| s.contains(':')
+				|| (s.len() >= 32 && s.chars().all(|c| c.is_ascii_hexdigit()));
+			if is_id {
+				"{id}"
+			} else {
+				s
+			}
+		}).collect();
+	format!("/{}", segments.join("/"))
+}
+
+/// Tags requests with an ID, logs them with it and how long they took, and
+/// records the latency. Added to the router root ahead of the limits and
+/// authentication, so the time they take counts and the requests they turn
+/// down are traced too.
+pub struct TraceMiddleware {
+	metrics: Arc<ApiMetrics>,
+	prefix: u32,
+	next_id: AtomicUsize,
+}
+
+impl TraceMiddleware {
+	pub fn new(metrics: Arc<ApiMetrics>) -> TraceMiddleware {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs() as u32)
+			.unwrap_or(0);
+		TraceMiddleware {
+			metrics,
+			prefix: now,
+			next_id: AtomicUsize::new(0),
+		}
+	}
+
+	fn request_id(&self, req: &Request<Body>) -> String {
+		let given = req
+			.headers()
+			.get(REQUEST_ID_HEADER)
+			.and_then(|v| v.to_str().ok())
+			.filter(|id| {
+				!id.is_empty()
+					&& id.len() <= 64
+					&& id
+						.chars()
+						.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+			});
+		match given {
+			Some(id) => id.to_owned(),
+			None => {
+				let n = self.next_id.fetch_add(1, Ordering::Relaxed);
+				format!("{:08x}{:08x}", self.prefix, n as u32)
+			}
+		}
+	}
+}
+
+impl Handler for TraceMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let id = self.request_id(&req);
+		let method = req.method().to_string();
+		let path = req.uri().path().to_owned();
+		let started = Instant::now();
+		let metrics = self.metrics.clone();
+
+		Box::new(
+			handlers
+				.next()
+				.unwrap()
+				.call(req, handlers)
+				.map(move |mut res| {
+					let elapsed = started.elapsed();
+					let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+					metrics.observe(&method, &endpoint(&path), secs);
+					if secs > SLOW_REQUEST_SECS {
+						warn!(
+							"api: [{}] {} {} -> {} took {:.3}s",
+							id,
+							method,
+							path,
+							res.status(),
+							secs
+						);
+					} else {
+						debug!(
+							"api: [{}] {} {} -> {} in {:.3}s",
+							id,
+							method,
+							path,
+							res.status(),
+							secs
+						);
+					}
+					if let Ok(id) = HeaderValue::from_str(&id) {
+						res.headers_mut().insert(REQUEST_ID_HEADER, id);
+					}
+					res
+				}),
+		)
+	}
+}
+
+/// Transfer rates with the connected peers, added up, in the Prometheus text
+/// format
+fn render_peer_rates(peers: &p2p::Peers) -> String {
+	let name = "grin_p2p_bytes_per_second";
+	let connected = peers.connected_peers();
+	let mut out = String::new();
+	let _ = writeln!(
+		out,
+		"# HELP {} Bytes exchanged with the connected peers per second",
+		name
+	);
+	let _ = writeln!(out, "# TYPE {} gauge", name);
+	for &(window, secs) in RATE_WINDOWS.iter() {
+		let (sent, received) = connected
+			.iter()
+			.filter_map(|p| p.bytes_rates(secs))
+			.fold((0.0, 0.0), |(s, r), (ps, pr)| (s + ps, r + pr));
+		let _ = writeln!(
+			out,
+			"{}{{direction=\"sent\",window=\"{}\"}} {}",
+			name, window, sent
+		);
+		let _ = writeln!(
+			out,
+			"{}{{direction=\"received\",window=\"{}\"}} {}",
+			name, window, received
+		);
+	}
+	out
+}
+
+/// Resources used by the node and its queue depths, in the Prometheus text
+/// format. What's unknown on the platform is left out.
+fn render_resources(resources: &Resources) -> String {
+	let mut out = String::new();
+	let gauges = [
+		(
+			"grin_process_resident_memory_bytes",
+			"Resident memory of the node process",
+			resources.memory_bytes,
+		),
+		(
+			"grin_process_virtual_memory_bytes",
+			"Virtual memory of the node process",
+			resources.virtual_memory_bytes,
+		),
+		(
+			"grin_process_open_fds",
+			"File descriptors open by the node process",
+			resources.open_fds,
+		),
+		(
+			"grin_process_threads",
+			"Threads of the node process",
+			resources.threads,
+		),
+	];
+	for &(name, help, value) in gauges.iter() {
+		if let Some(value) = value {
+			let _ = writeln!(out, "# HELP {} {}", name, help);
+			let _ = writeln!(out, "# TYPE {} gauge", name);
+			let _ = writeln!(out, "{} {}", name, value);
+		}
+	}
+
+	let name = "grin_queue_depth";
+	let _ = writeln!(out, "# HELP {} Items waiting in the node queues", name);
+	let _ = writeln!(out, "# TYPE {} gauge", name);
+	let queues = [
+		("peer_send", resources.peer_send_queue),
+		("peer_send_max", resources.peer_send_queue_max),
+		("orphan_blocks", resources.orphan_blocks),
+	];
+	for &(queue, depth) in queues.iter() {
+		let _ = writeln!(out, "{}{{queue=\"{}\"}} {}", name, queue, depth);
+	}
+	out
+}
+
+/// The API latency histograms, the peer transfer rates and the resources
+/// used by the node, in the Prometheus text format
+/// GET /v1/metrics
+pub struct MetricsHandler {
+	pub metrics: Arc<ApiMetrics>,
+	pub chain: Weak<chain::Chain>,
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl Handler for MetricsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		let mut body = self.metrics.render();
+		if let Some(peers) = self.peers.upgrade() {
+			body.push_str(&render_peer_rates(&peers));
+			if let Some(chain) = self.chain.upgrade() {
+				body.push_str(&render_resources(&Resources::sample(&chain, &peers)));
+			}
+		}
+		let mut res = Response::new(Body::from(body));
+		res.headers_mut().insert(
+			CONTENT_TYPE,
+			HeaderValue::from_static("text/plain; version=0.0.4"),
+		);
+		Box::new(ok(res))
+	}
+}
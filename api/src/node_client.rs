@@ -0,0 +1,378 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed client for the node REST API, so callers don't have to assemble
+//! endpoint URLs and response types themselves.
+
+use std::net::SocketAddr;
+
+use futures::future::Future;
+use futures::{stream, Stream};
+use tokio::runtime::Runtime;
+
+use client::{self, ClientResponseFuture};
+use core::core::hash::Hash;
+use core::core::{Block, BlockHeader, Transaction};
+use core::ser;
+use p2p::types::{PeerInfoDisplay, ReasonForBan};
+use p2p::PeerExport;
+use rest::{Error, ErrorKind};
+use types::*;
+use url::form_urlencoded;
+use util;
+use util::secp::pedersen::Commitment;
+
+/// Most output ids sent in a single by-ids query, keeping URLs reasonable
+pub const OUTPUT_IDS_PER_REQUEST: usize = 500;
+
+/// Most kernels and outputs looked up in a single request, as the node caps
+pub const LOOKUPS_PER_REQUEST: usize = 100;
+
+/// Reference to a block, either by hash or by height.
+#[derive(Debug, Clone, Copy)]
+pub enum BlockRef {
+	/// Block with the given hash
+	Hash(Hash),
+	/// Block at the given height on the main chain
+	Height(u64),
+}
+
+impl BlockRef {
+	fn to_param(&self) -> String {
+		match *self {
+			BlockRef::Hash(h) => h.to_hex(),
+			BlockRef::Height(h) => h.to_string(),
+		}
+	}
+}
+
+/// Hex-encoded transaction, as expected by the pool push endpoint.
+#[derive(Serialize)]
+struct TxHex<'a> {
+	tx_hex: &'a str,
+}
+
+/// Client for the REST API of a single node. Every call has a blocking
+/// flavor; those that the wallet issues in bulk also have an `_async` one.
+#[derive(Debug, Clone)]
+pub struct NodeClient {
+	base_url: String,
+	api_secret: Option<String>,
+}
+
+impl NodeClient {
+	/// Client for the node listening at the given base url, such as
+	/// `http://127.0.0.1:13413`, authenticating with the secret if any.
+	pub fn new(base_url: &str, api_secret: Option<String>) -> NodeClient {
+		NodeClient {
+			base_url: base_url.trim_right_matches('/').to_owned(),
+			api_secret,
+		}
+	}
+
+	/// Base url of the node
+	pub fn base_url(&self) -> &str {
+		&self.base_url
+	}
+
+	fn url(&self, path: &str) -> String {
+		format!("{}/v1/{}", self.base_url, path)
+	}
+
+	/// Head of the node's chain
+	pub fn get_tip(&self) -> Result<Tip, Error> {
+		client::get(&self.url("chain"), self.api_secret.clone())
+	}
+
+	/// Head of the node's chain, asynchronously
+	pub fn get_tip_async(&self) -> ClientResponseFuture<Tip> {
+		client::get_async(&self.url("chain"), self.api_secret.clone())
+	}
+
+	/// Version, connection count and tip of the node
+	pub fn get_status(&self) -> Result<Status, Error> {
+		client::get(&self.url("status"), self.api_secret.clone())
+	}
+
+	/// Readiness of the node, as served to orchestrators
+	pub fn get_readiness(&self) -> Result<Readiness, Error> {
+		client::get(
+			&format!("{}{}", self.base_url, ::handlers::READY_PATH),
+			self.api_secret.clone(),
+		)
+	}
+
+	/// Block in its printable JSON form
+	pub fn get_block(&self, block: BlockRef) -> Result<BlockPrintable, Error> {
+		client::get(
+			&self.url(&format!("blocks/{}", block.to_param())),
+			self.api_secret.clone(),
+		)
+	}
+
+	/// Block in its compact printable JSON form
+	pub fn get_compact_block(&self, block: BlockRef) -> Result<CompactBlockPrintable, Error> {
+		client::get(
+			&self.url(&format!("blocks/{}?compact", block.to_param())),
+			self.api_secret.clone(),
+		)
+	}
+
+	/// Full block, fetched in its binary serialization
+	pub fn get_full_block(&self, block: BlockRef) -> Result<Block, Error> {
+		client::get_binary(
+			&self.url(&format!("blocks/{}", block.to_param())),
+			self.api_secret.clone(),
+		)
+	}
+
+	/// Block header in its printable JSON form
+	pub fn get_header(&self, block: BlockRef) -> Result<BlockHeaderPrintable, Error> {
+		client::get(
+			&self.url(&format!("headers/{}", block.to_param())),
+			self.api_secret.clone(),
+		)
+	}
+
+	/// Full block header, fetched in its binary serialization
+	pub fn get_full_header(&self, block: BlockRef) -> Result<BlockHeader, Error> {
+		client::get_binary(
+			&self.url(&format!("headers/{}", block.to_param())),
+			self.api_secret.clone(),
+		)
+	}
+
+	/// Asks the node to compact its chain, removing old blocks and pruned
+	/// data
+	pub fn compact_chain(&self) -> Result<(), Error> {
+		client::post_no_ret(&self.url("chain/compact"), self.api_secret.clone(), &"")
+	}
+
+	/// Unspent outputs among the given commitments, chunked into as many
+	/// requests as needed.
+	pub fn get_outputs(&self, commits: &[Commitment]) -> Result<Vec<Output>, Error> {
+		let mut rt = Runtime::new().unwrap();
+		rt.block_on(self.get_outputs_async(commits))
+	}
+
+	/// Unspent outputs among the given commitments, the chunked requests
+	/// running concurrently.
+	pub fn get_outputs_async(&self, commits: &[Commitment]) -> ClientResponseFuture<Vec<Output>> {
+		let params: Vec<String> = commits
+			.iter()
+			.map(|c| format!("id={}", util::to_hex(c.as_ref().to_vec())))
+			.collect();
+		let tasks: Vec<ClientResponseFuture<Vec<Output>>> = params
+			.chunks(OUTPUT_IDS_PER_REQUEST)
+			.map(|chunk| {
+				client::get_async(
+					&self.url(&format!("chain/outputs/byids?{}", chunk.join("&"))),
+					self.api_secret.clone(),
+				)
+			}).collect();
+		Box::new(
+			stream::futures_unordered(tasks)
+				.collect()
+				.map(|res| res.into_iter().flat_map(|o| o).collect()),
+		)
+	}
+
+	/// Merkle proofs of the unspent outputs among the given commitments, a
+	/// set of proofs per chunk of the commitments, each against the head of
+	/// the chain when it was asked for
+	pub fn get_output_proofs(&self, commits: &[Commitment]) -> Result<Vec<OutputProofs>, Error> {
+		let mut res = vec![];
+		for chunk in commits.chunks(OUTPUT_IDS_PER_REQUEST) {
+			let ids: Vec<String> = chunk
+				.iter()
+				.map(|c| util::to_hex(c.as_ref().to_vec()))
+				.collect();
+			res.push(client::get(
+				&self.url(&format!("chain/outputs/proofs?id={}", ids.join(","))),
+				self.api_secret.clone(),
+			)?);
+		}
+		Ok(res)
+	}
+
+	/// Which of the given kernel excesses and output commitments are on the
+	/// chain, and at which height. Outputs only count while unspent. Split
+	/// into as many requests as needed.
+	pub fn lookup(
+		&self,
+		kernels: &[Commitment],
+		outputs: &[Commitment],
+	) -> Result<LookupResponse, Error> {
+		let hex = |commits: &[Commitment]| -> Vec<String> {
+			commits
+				.iter()
+				.map(|c| util::to_hex(c.as_ref().to_vec()))
+				.collect()
+		};
+		let mut res = LookupResponse {
+			kernels: vec![],
+			outputs: vec![],
+		};
+		for chunk in kernels.chunks(LOOKUPS_PER_REQUEST) {
+			let req = LookupRequest {
+				kernels: hex(chunk),
+				outputs: vec![],
+			};
+			let mut part: LookupResponse =
+				client::post(&self.url("chain/lookup"), self.api_secret.clone(), &req)?;
+			res.kernels.append(&mut part.kernels);
+		}
+		for chunk in outputs.chunks(LOOKUPS_PER_REQUEST) {
+			let req = LookupRequest {
+				kernels: vec![],
+				outputs: hex(chunk),
+			};
+			let mut part: LookupResponse =
+				client::post(&self.url("chain/lookup"), self.api_secret.clone(), &req)?;
+			res.outputs.append(&mut part.outputs);
+		}
+		Ok(res)
+	}
+
+	/// Outputs by position in the output MMR, starting at the given index
+	pub fn get_outputs_by_pmmr_index(
+		&self,
+		start_index: u64,
+		max: u64,
+	) -> Result<OutputListing, Error> {
+		client::get(
+			&self.url(&format!(
+				"txhashset/outputs?start_index={}&max={}",
+				start_index, max
+			)),
+			self.api_secret.clone(),
+		)
+	}
+
+	/// Hands the transaction to the node's pool, stemming it unless fluff is
+	/// set.
+	pub fn push_transaction(&self, tx: &Transaction, fluff: bool) -> Result<(), Error> {
+		let tx_bin = ser::ser_vec(tx).map_err(|e| {
+			ErrorKind::Argument(format!("Cannot serialize transaction: {}", e))
+		})?;
+		self.push_tx_hex(&util::to_hex(tx_bin), fluff)
+	}
+
+	/// As `push_transaction`, for an already hex-encoded transaction
+	pub fn push_tx_hex(&self, tx_hex: &str, fluff: bool) -> Result<(), Error> {
+		client::post_no_ret(&self.push_url(fluff), self.api_secret.clone(), &TxHex { tx_hex })
+	}
+
+	/// As `push_tx_hex`, asynchronously
+	pub fn push_tx_hex_async(&self, tx_hex: &str, fluff: bool) -> ClientResponseFuture<()> {
+		client::post_no_ret_async(&self.push_url(fluff), self.api_secret.clone(), &TxHex { tx_hex })
+	}
+
+	fn push_url(&self, fluff: bool) -> String {
+		if fluff {
+			self.url("pool/push?fluff")
+		} else {
+			self.url("pool/push")
+		}
+	}
+
+	/// Size of the node's transaction pool
+	pub fn get_pool(&self) -> Result<PoolInfo, Error> {
+		client::get(&self.url("pool"), self.api_secret.clone())
+	}
+
+	/// Transactions in the node's pool, stem ones excepted
+	pub fn get_pool_txs(&self) -> Result<Vec<PoolTx>, Error> {
+		client::get(&self.url("pool/txs"), self.api_secret.clone())
+	}
+
+	/// Peers the node is currently connected to
+	pub fn get_connected_peers(&self) -> Result<Vec<PeerInfoDisplay>, Error> {
+		client::get(&self.url("peers/connected"), self.api_secret.clone())
+	}
+
+	/// Peers currently banned by the node
+	pub fn get_banned_peers(&self) -> Result<Vec<BannedPeer>, Error> {
+		client::get(&self.url("peers/banned"), self.api_secret.clone())
+	}
+
+	/// Asks the node to connect to a peer, returning it once connected
+	pub fn connect_peer(&self, addr: &SocketAddr) -> Result<PeerInfoDisplay, Error> {
+		client::post(
+			&self.url(&format!("peers/{}/connect", addr)),
+			self.api_secret.clone(),
+			&"",
+		)
+	}
+
+	/// Bans a peer for the given reason, for `duration` seconds or the
+	/// node's configured ban window if none, with a note on what it's about.
+	pub fn ban_peer(
+		&self,
+		addr: &SocketAddr,
+		reason: ReasonForBan,
+		duration: Option<u64>,
+		note: Option<&str>,
+	) -> Result<(), Error> {
+		let mut query = form_urlencoded::Serializer::new(String::new());
+		query.append_pair("reason", &format!("{:?}", reason));
+		if let Some(d) = duration {
+			query.append_pair("duration", &d.to_string());
+		}
+		if let Some(n) = note {
+			query.append_pair("note", n);
+		}
+		let path = format!("peers/{}/ban?{}", addr, query.finish());
+		client::post_no_ret(&self.url(&path), self.api_secret.clone(), &"")
+	}
+
+	/// Known-good peers of the node, signed by it, the best ones up to the
+	/// count if given
+	pub fn export_peers(&self, count: Option<usize>) -> Result<PeerExport, Error> {
+		let path = match count {
+			Some(c) => format!("peers/export?count={}", c),
+			None => "peers/export".to_owned(),
+		};
+		client::get(&self.url(&path), self.api_secret.clone())
+	}
+
+	/// Has the node save the peers exported by another one, provided the
+	/// export is no older than max_age seconds and signed by the signer, if
+	/// given
+	pub fn import_peers(
+		&self,
+		export: &PeerExport,
+		max_age: Option<i64>,
+		signer: Option<&str>,
+	) -> Result<PeerImport, Error> {
+		let mut query = form_urlencoded::Serializer::new(String::new());
+		if let Some(a) = max_age {
+			query.append_pair("max_age", &a.to_string());
+		}
+		if let Some(s) = signer {
+			query.append_pair("signer", s);
+		}
+		let path = format!("peers/import?{}", query.finish());
+		client::post(&self.url(&path), self.api_secret.clone(), export)
+	}
+
+	/// Lifts the ban on a peer
+	pub fn unban_peer(&self, addr: &SocketAddr) -> Result<(), Error> {
+		client::post_no_ret(
+			&self.url(&format!("peers/{}/unban", addr)),
+			self.api_secret.clone(),
+			&"",
+		)
+	}
+}
@@ -22,7 +22,9 @@ use failure::{Backtrace, Context, Fail, ResultExt};
 use futures::sync::oneshot;
 use futures::Stream;
 use hyper::rt::Future;
-use hyper::{rt, Body, Request, Server};
+use hyper::server::conn::Http;
+use hyper::service::Service;
+use hyper::{self, rt, Body, Request};
 use router::{Handler, HandlerObj, ResponseFuture, Router};
 use rustls;
 use rustls::internal::pemfile;
@@ -31,6 +33,7 @@ use std::fs::File;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::{io, thread};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_rustls::ServerConfigExt;
 use tokio_tcp;
 
@@ -144,6 +147,54 @@ impl TLSConfig {
 	}
 }
 
+/// Address of the client a request came from, set on every request for
+/// handlers and middlewares to look up
+#[derive(Clone, Copy, Debug)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Router serving a single connection, tagging its requests with the
+/// remote address
+struct ConnService {
+	router: Router,
+	remote_addr: SocketAddr,
+}
+
+impl Service for ConnService {
+	type ReqBody = Body;
+	type ResBody = Body;
+	type Error = hyper::Error;
+	type Future = ResponseFuture;
+
+	fn call(&mut self, mut req: Request<Body>) -> ResponseFuture {
+		req.extensions_mut().insert(RemoteAddr(self.remote_addr));
+		self.router.call(req)
+	}
+}
+
+/// Serve the incoming connections, each with its own copy of the router
+fn serve<S, IO>(incoming: S, router: Router) -> impl Future<Item = (), Error = ()>
+where
+	S: Stream<Item = Option<(IO, SocketAddr)>, Error = io::Error>,
+	IO: AsyncRead + AsyncWrite + Send + 'static,
+{
+	let http = Http::new();
+	incoming
+		.filter_map(|x| x)
+		.map_err(|e| eprintln!("HTTP API server error: {}", e))
+		.for_each(move |(io, remote_addr)| {
+			let service = ConnService {
+				router: router.clone(),
+				remote_addr,
+			};
+			rt::spawn(
+				http.serve_connection(io, service)
+					.with_upgrades()
+					.map_err(|e| debug!("HTTP API connection error: {}", e)),
+			);
+			Ok(())
+		})
+}
+
 /// HTTP server allowing the registration of ApiEndpoint implementations.
 pub struct ApiServer {
 	shutdown_sender: Option<oneshot::Sender<()>>,
@@ -188,13 +239,16 @@ impl ApiServer {
 		thread::Builder::new()
 			.name("apis".to_string())
 			.spawn(move || {
-				let server = Server::bind(&addr)
-					.serve(router)
-					// TODO graceful shutdown is unstable, investigate 
-					//.with_graceful_shutdown(rx)
-					.map_err(|e| eprintln!("HTTP API server error: {}", e));
-
-				rt::run(server);
+				let listener = tokio_tcp::TcpListener::bind(&addr).expect("failed to bind");
+				// TODO graceful shutdown is unstable, investigate
+				let incoming = listener.incoming().then(|r| match r {
+					Ok(s) => Ok(s.peer_addr().ok().map(|addr| (s, addr))),
+					Err(e) => {
+						error!("accept failed: {}", e);
+						Ok(None)
+					}
+				});
+				rt::run(serve(incoming, router));
 			}).map_err(|_| ErrorKind::Internal("failed to spawn API thread".to_string()).into())
 	}
 
@@ -220,19 +274,16 @@ impl ApiServer {
 				let listener = tokio_tcp::TcpListener::bind(&addr).expect("failed to bind");
 				let tls = listener
 					.incoming()
-					.and_then(move |s| tls_conf.accept_async(s))
+					.and_then(|s| s.peer_addr().map(|addr| (s, addr)))
+					.and_then(move |(s, addr)| tls_conf.accept_async(s).map(move |s| (s, addr)))
 					.then(|r| match r {
 						Ok(x) => Ok::<_, io::Error>(Some(x)),
 						Err(e) => {
 							error!("accept_async failed: {}", e);
 							Ok(None)
 						}
-					}).filter_map(|x| x);
-				let server = Server::builder(tls)
-					.serve(router)
-					.map_err(|e| eprintln!("HTTP API server error: {}", e));
-
-				rt::run(server);
+					});
+				rt::run(serve(tls, router));
 			}).map_err(|_| ErrorKind::Internal("failed to spawn API thread".to_string()).into())
 	}
 
@@ -0,0 +1,121 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Server-sent events stream of node events, served at `/v1/events`.
+//!
+//! A plain long lived `GET`, so it goes through proxies and works with curl
+//! or a browser `EventSource`. Each event comes as
+//! `id: 12\nevent: blocks\ndata: {...}\n\n`, and clients reconnecting with
+//! a `Last-Event-ID` header get the blocks and transactions they missed, as
+//! long as they're among the last few.
+
+use std::io;
+use std::sync::Weak;
+use std::time::{Duration, Instant};
+
+use futures::future::ok;
+use futures::{stream, Stream};
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::timer::Interval;
+use url::form_urlencoded;
+
+use router::{Handler, ResponseFuture};
+use web::response;
+use ws::{EventHub, Topic};
+
+/// How often a comment is sent down idle streams, so proxies don't time
+/// them out and dropped clients get noticed
+const KEEPALIVE_SECS: u64 = 15;
+
+/// How long clients wait before reconnecting, in milliseconds
+const RETRY_MILLIS: u64 = 3000;
+
+/// Streams the events of a hub as server-sent events, blocks and
+/// transactions unless other topics are asked for
+/// GET /v1/events?topics=blocks,transactions
+pub struct EventsHandler {
+	pub events: Weak<EventHub>,
+}
+
+impl Handler for EventsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let mut topics = vec![Topic::Blocks, Topic::Transactions];
+		let mut last_event_id = None;
+		let query = req.uri().query().unwrap_or("");
+		for (k, v) in form_urlencoded::parse(query.as_bytes()) {
+			match &*k {
+				"topics" => {
+					topics = vec![];
+					for name in v.split(',') {
+						match [
+							Topic::Headers,
+							Topic::Blocks,
+							Topic::Transactions,
+							Topic::Sync,
+						]
+						.iter()
+						.find(|t| t.name() == name)
+						{
+							Some(topic) => topics.push(*topic),
+							None => {
+								return response(
+									StatusCode::BAD_REQUEST,
+									format!("unknown topic: {}", name),
+								)
+							}
+						}
+					}
+				}
+				// for clients that can't set headers
+				"last_event_id" => last_event_id = v.parse().ok(),
+				_ => (),
+			}
+		}
+		if let Some(id) = req.headers().get("last-event-id") {
+			last_event_id = id.to_str().ok().and_then(|id| id.trim().parse().ok());
+		}
+		let events = match self.events.upgrade() {
+			Some(events) => events,
+			None => return response(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+		};
+
+		// ending the stream once disconnected from the hub
+		let published = events
+			.connect_stream(topics, last_event_id)
+			.map(Some)
+			.chain(stream::once(Ok(None)))
+			.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "event channel closed"));
+		let keepalive = Duration::from_secs(KEEPALIVE_SECS);
+		let keepalive = Interval::new(Instant::now() + keepalive, keepalive)
+			.map(|_| Some(": keepalive\n\n".to_owned()))
+			.map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e));
+		let body = stream::once(Ok(format!("retry: {}\n\n", RETRY_MILLIS))).chain(
+			published
+				.select(keepalive)
+				.take_while(|msg| Ok(msg.is_some()))
+				.map(|msg| msg.unwrap()),
+		);
+
+		let response = Response::builder()
+			.status(StatusCode::OK)
+			.header(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"))
+			.header(CACHE_CONTROL, HeaderValue::from_static("no-cache"))
+			// keeps nginx from buffering the stream
+			.header("x-accel-buffering", HeaderValue::from_static("no"))
+			.body(Body::wrap_stream(body))
+			.unwrap();
+		Box::new(ok(response))
+	}
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use chain;
@@ -19,6 +20,7 @@ use core::core::hash::Hashed;
 use core::core::merkle_proof::MerkleProof;
 use core::{core, ser};
 use p2p;
+use pool;
 use serde;
 use serde::de::MapAccess;
 use serde::ser::SerializeStruct;
@@ -69,15 +71,95 @@ pub struct Status {
 	pub connections: u32,
 	// The state of the current fork Tip
 	pub tip: Tip,
+	// Resources used by the node
+	pub resources: Resources,
+	// Dandelion parameters the node currently runs with
+	pub dandelion: pool::DandelionConfig,
+	// Coarse stage of the sync, unknown when the API runs outside a server
+	#[serde(default)]
+	pub sync_stage: Option<String>,
+	// Connected peers that dialed us
+	#[serde(default)]
+	pub inbound_peers: u32,
+	// Connected peers we dialed
+	#[serde(default)]
+	pub outbound_peers: u32,
+	// Transactions in the pool, stem ones excepted
+	#[serde(default)]
+	pub pool_size: usize,
+	// Total weight of those transactions
+	#[serde(default)]
+	pub pool_weight: usize,
+	// Connected stratum workers, none when stratum doesn't run
+	#[serde(default)]
+	pub stratum_workers: Option<u32>,
+	// Disk space taken by the chain data, in bytes
+	#[serde(default)]
+	pub disk_usage: Option<u64>,
+	// Seconds since the API started
+	#[serde(default)]
+	pub uptime: u64,
 }
 
 impl Status {
-	pub fn from_tip_and_peers(current_tip: chain::Tip, connections: u32) -> Status {
+	pub fn from_tip_and_peers(
+		current_tip: chain::Tip,
+		connections: u32,
+		resources: Resources,
+		dandelion: pool::DandelionConfig,
+	) -> Status {
 		Status {
 			protocol_version: p2p::msg::PROTOCOL_VERSION,
 			user_agent: p2p::msg::USER_AGENT.to_string(),
 			connections: connections,
 			tip: Tip::from_tip(current_tip),
+			resources,
+			dandelion,
+			sync_stage: None,
+			inbound_peers: 0,
+			outbound_peers: 0,
+			pool_size: 0,
+			pool_weight: 0,
+			stratum_workers: None,
+			disk_usage: None,
+			uptime: 0,
+		}
+	}
+}
+
+/// Resources used by the node process and depth of its internal queues,
+/// to spot leaks and backlogs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Resources {
+	/// Resident memory, in bytes, if known on this platform
+	pub memory_bytes: Option<u64>,
+	/// Virtual memory, in bytes, if known on this platform
+	pub virtual_memory_bytes: Option<u64>,
+	/// Open file descriptors, sockets included, if known on this platform
+	pub open_fds: Option<u64>,
+	/// Threads, if known on this platform
+	pub threads: Option<u64>,
+	/// Messages queued for sending to the connected peers
+	pub peer_send_queue: u64,
+	/// Messages queued for sending to the most backed up peer
+	pub peer_send_queue_max: u64,
+	/// Blocks in the orphan pool, waiting for their parent
+	pub orphan_blocks: u64,
+}
+
+impl Resources {
+	/// Samples what the node currently uses
+	pub fn sample(chain: &chain::Chain, peers: &p2p::Peers) -> Resources {
+		let usage = util::process_usage();
+		let (peer_send_queue, peer_send_queue_max) = peers.send_queue_depths();
+		Resources {
+			memory_bytes: usage.memory_bytes,
+			virtual_memory_bytes: usage.virtual_memory_bytes,
+			open_fds: usage.open_fds,
+			threads: usage.threads,
+			peer_send_queue: peer_send_queue as u64,
+			peer_send_queue_max: peer_send_queue_max as u64,
+			orphan_blocks: chain.orphans_len() as u64,
 		}
 	}
 }
@@ -460,6 +542,42 @@ impl TxKernelPrintable {
 	}
 }
 
+// Printable representation of a transaction
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxPrintable {
+	/// Total fee of the transaction
+	pub fee: u64,
+	/// Commitments of the spent inputs
+	pub inputs: Vec<String>,
+	/// Commitments of the new outputs
+	pub outputs: Vec<String>,
+	/// A printable version of the transaction kernels
+	pub kernels: Vec<TxKernelPrintable>,
+}
+
+impl TxPrintable {
+	pub fn from_tx(tx: &core::Transaction) -> TxPrintable {
+		TxPrintable {
+			fee: tx.fee(),
+			inputs: tx
+				.inputs()
+				.iter()
+				.map(|x| util::to_hex(x.commitment().0.to_vec()))
+				.collect(),
+			outputs: tx
+				.outputs()
+				.iter()
+				.map(|x| util::to_hex(x.commitment().0.to_vec()))
+				.collect(),
+			kernels: tx
+				.kernels()
+				.iter()
+				.map(TxKernelPrintable::from_txkernel)
+				.collect(),
+		}
+	}
+}
+
 // Just the information required for wallet reconstruction
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BlockHeaderInfo {
@@ -635,6 +753,43 @@ pub struct BlockOutputs {
 	pub outputs: Vec<OutputPrintable>,
 }
 
+/// An output created in a block, with its position in the output MMR
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockOutput {
+	/// The type of output Coinbase|Transaction
+	pub output_type: OutputType,
+	/// The output commitment
+	pub commit: PrintableCommitment,
+	/// Position of the output in the output MMR
+	pub mmr_index: u64,
+	/// Whether the output has been spent since
+	pub spent: bool,
+	/// Rangeproof (as hex string), when asked for
+	pub proof: Option<String>,
+	/// Rangeproof hash (as hex string)
+	pub proof_hash: String,
+}
+
+/// A kernel included in a block, with its position in the kernel MMR
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockKernel {
+	/// Position of the kernel in the kernel MMR
+	pub mmr_index: u64,
+	/// The kernel itself
+	pub kernel: TxKernelPrintable,
+}
+
+/// The outputs and kernels created in a block
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockContents {
+	/// The block header
+	pub header: BlockHeaderInfo,
+	/// The outputs, in the order they were added to the output MMR
+	pub outputs: Vec<BlockOutput>,
+	/// The kernels, in the order they were added to the kernel MMR
+	pub kernels: Vec<BlockKernel>,
+}
+
 // For traversing all outputs in the UTXO set
 // transactions in the block
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -647,12 +802,235 @@ pub struct OutputListing {
 	pub outputs: Vec<OutputPrintable>,
 }
 
+// A run of blocks or headers, by height
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeightPage<T> {
+	/// The blocks or headers, from the lowest height
+	pub items: Vec<T>,
+	/// Height the next page starts at, none when the range is done
+	pub next_height: Option<u64>,
+}
+
+// A page of unspent outputs, in the order they were added to the chain
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputPage {
+	/// The last available output index
+	pub highest_index: u64,
+	/// Where the next page starts, none when this one is the last
+	pub next_cursor: Option<u64>,
+	/// A printable version of the outputs
+	pub outputs: Vec<OutputPrintable>,
+}
+
+/// Kernels and outputs to look up on the chain in one go
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LookupRequest {
+	/// Hex encoded kernel excesses
+	#[serde(default)]
+	pub kernels: Vec<String>,
+	/// Hex encoded output commitments
+	#[serde(default)]
+	pub outputs: Vec<String>,
+}
+
+/// Whether a kernel or an output is on the chain, and at which height
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LookupResult {
+	/// Kernel excess or output commitment, as requested
+	pub id: String,
+	/// Whether it's on the chain, outputs only while unspent
+	pub found: bool,
+	/// Height of the block it's in, when found
+	pub height: Option<u64>,
+}
+
+impl LookupResult {
+	pub fn new(id: &str, height: Option<u64>) -> LookupResult {
+		LookupResult {
+			id: id.to_owned(),
+			found: height.is_some(),
+			height,
+		}
+	}
+}
+
+/// Results of a lookup, in the order of the request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LookupResponse {
+	pub kernels: Vec<LookupResult>,
+	pub outputs: Vec<LookupResult>,
+}
+
+/// Merkle proof of an unspent output
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputProof {
+	/// The output commitment
+	pub commit: PrintableCommitment,
+	/// Whether the output is a coinbase
+	pub output_type: OutputType,
+	/// Height of the block which contains the output
+	pub height: u64,
+	/// Position of the output in the output MMR
+	pub mmr_index: u64,
+	/// Merkle proof of the output, hex encoded
+	pub merkle_proof: String,
+}
+
+impl OutputProof {
+	/// Decodes the Merkle proof
+	pub fn merkle_proof(&self) -> Result<MerkleProof, ser::Error> {
+		let bytes = util::from_hex(self.merkle_proof.clone()).map_err(|_| {
+			ser::Error::HexError(format!("invalid merkle proof: {}", self.merkle_proof))
+		})?;
+		ser::deserialize(&mut &bytes[..])
+	}
+}
+
+/// Merkle proofs of unspent outputs, all against the output root of the
+/// same header
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputProofs {
+	/// Header the proofs are against, the head of the chain
+	pub header: BlockHeaderInfo,
+	/// The unspent outputs among those asked for
+	pub outputs: Vec<OutputProof>,
+}
+
+/// A page of the known peers, in address order
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerPage {
+	/// Where the next page starts, none when this one is the last
+	pub next: Option<String>,
+	/// The known peers
+	pub peers: Vec<p2p::PeerData>,
+}
+
+/// A peer currently banned, and until when
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BannedPeer {
+	/// Network address of the peer
+	pub addr: SocketAddr,
+	/// Why it was banned
+	pub ban_reason: p2p::ReasonForBan,
+	/// When it was banned, as a unix timestamp
+	pub banned_at: i64,
+	/// When the ban runs out, as a unix timestamp
+	pub expires_at: i64,
+	/// What the ban is about, empty if nothing was said
+	pub note: String,
+}
+
+/// Outcome of importing the peers exported by another node
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PeerImport {
+	/// Peers in the export
+	pub exported: usize,
+	/// Peers we didn't know of yet, now saved
+	pub imported: usize,
+}
+
+/// Whether a node is ready to serve, and what it was checked on
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Readiness {
+	/// Whether all checks passed
+	pub ready: bool,
+	/// Height of our chain
+	pub height: u64,
+	/// Highest height among connected peers, ours if higher
+	pub network_height: u64,
+	/// Connected peers
+	pub peer_count: u32,
+	/// Whether the chain data can still be written
+	pub db_writable: bool,
+	/// Checks that failed, among `sync`, `peers` and `db`
+	pub failed: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PoolInfo {
 	/// Size of the pool
 	pub pool_size: usize,
 }
 
+/// A transaction waiting in the pool
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolTx {
+	/// Hash of the transaction
+	pub hash: String,
+	/// Where the transaction came from
+	pub source: String,
+	/// Unix time the transaction got in the pool at
+	pub received_at: i64,
+	/// The transaction itself
+	pub tx: TxPrintable,
+}
+
+/// Whether the pool would take a transaction, and what it weighs
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolCheck {
+	/// Hash of the transaction
+	pub hash: String,
+	/// Weight of the transaction
+	pub weight: u64,
+	/// Fee of the transaction
+	pub fee: u64,
+	/// Fee per unit of weight
+	pub fee_rate: u64,
+	/// Fee the pool asks of a transaction of that weight
+	pub min_fee: u64,
+	/// Whether the pool would accept the transaction
+	pub acceptable: bool,
+	/// Why it wouldn't, if it wouldn't
+	pub error: Option<String>,
+}
+
+/// A transaction submitted to the pool, with the kernels to follow it by
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolSubmit {
+	/// Hash of the transaction
+	pub hash: String,
+	/// Hex encoded excesses of its kernels, to look it up on the chain with
+	pub kernels: Vec<String>,
+	/// Whether it's relayed through Dandelion stem first, or fluffed
+	pub stem: bool,
+}
+
+impl PoolSubmit {
+	pub fn from_tx(tx: &core::Transaction, stem: bool) -> PoolSubmit {
+		PoolSubmit {
+			hash: tx.hash().to_hex(),
+			kernels: tx
+				.kernels()
+				.iter()
+				.map(|k| util::to_hex(k.excess.0.to_vec()))
+				.collect(),
+			stem,
+		}
+	}
+}
+
+impl PoolTx {
+	pub fn from_entry(entry: &pool::types::PoolEntry) -> PoolTx {
+		PoolTx {
+			hash: entry.tx.hash().to_hex(),
+			source: entry.src.debug_name.clone(),
+			received_at: entry.tx_at.timestamp(),
+			tx: TxPrintable::from_tx(&entry.tx),
+		}
+	}
+}
+
+/// Sync progress of the node
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SyncProgress {
+	/// Sync stage, e.g. `header_sync`, or `no_sync` once done
+	pub status: String,
+	/// How far the stage got, for those telling
+	pub current: Option<u64>,
+	/// How far it has to go
+	pub total: Option<u64>,
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
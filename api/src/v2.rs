@@ -0,0 +1,192 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The v2 API: the v1 endpoints under `/v2`, every response wrapped in the
+//! same envelope. Successes come as `{"data": ...}`, and errors as
+//! `{"error": {"code": "not_found", "message": "Not Found", "detail": ...}}`
+//! with a code clients can match on, whichever handler or middleware turned
+//! the request down. v1 answers as it always did.
+
+use futures::future::{ok, Either};
+use futures::{Future, Stream};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use serde_json::{self, Value};
+use std::sync::Arc;
+
+use handlers::JSONRPC_PATH;
+use metrics::METRICS_PATH;
+use router::{not_found, Handler, HandlerObj, ResponseFuture, Router, RouterError};
+use web::{response, BINARY_CONTENT_TYPE};
+
+/// Path the v2 API is served under
+pub const V2_PREFIX: &'static str = "/v2";
+
+/// A v2 API error
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct V2Error {
+	/// Stable, machine-readable code, e.g. `not_found`
+	pub code: String,
+	/// What went wrong, for humans
+	pub message: String,
+	/// Specifics given by the endpoint, if any
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub detail: Option<String>,
+}
+
+/// Every v2 API response, holding either data or an error
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct V2Response<T> {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub data: Option<T>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<V2Error>,
+}
+
+/// The code of an error status
+pub fn error_code(status: StatusCode) -> &'static str {
+	match status {
+		StatusCode::BAD_REQUEST => "bad_request",
+		StatusCode::UNAUTHORIZED => "unauthorized",
+		StatusCode::FORBIDDEN => "forbidden",
+		StatusCode::NOT_FOUND => "not_found",
+		StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+		StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+		StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+		StatusCode::INTERNAL_SERVER_ERROR => "internal_error",
+		StatusCode::BAD_GATEWAY => "bad_gateway",
+		StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+		s if s.is_client_error() => "client_error",
+		_ => "server_error",
+	}
+}
+
+/// Wraps a v1 response in the v2 envelope, keeping its status and headers
+fn envelope(status: StatusCode, body: &[u8]) -> Value {
+	let text = String::from_utf8_lossy(body).trim().to_owned();
+	let res: V2Response<Value> = if status.is_success() {
+		let data = if text.is_empty() {
+			Value::Null
+		} else {
+			serde_json::from_str(&text).unwrap_or(Value::String(text))
+		};
+		V2Response {
+			data: Some(data),
+			error: None,
+		}
+	} else {
+		V2Response {
+			data: None,
+			error: Some(V2Error {
+				code: error_code(status).to_owned(),
+				message: status.canonical_reason().unwrap_or("Error").to_owned(),
+				detail: if text.is_empty() { None } else { Some(text) },
+			}),
+		}
+	};
+	serde_json::to_value(res).unwrap_or(Value::Null)
+}
+
+/// Puts the responses to v2 requests in the envelope. Added to the router
+/// root ahead of all other middleware, so their errors get it too.
+pub struct V2Middleware;
+
+impl Handler for V2Middleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		// CORS preflight answers are left alone
+		let is_v2 = req.uri().path().starts_with(&format!("{}/", V2_PREFIX))
+			&& req.method() != Method::OPTIONS;
+		let res = handlers.next().unwrap().call(req, handlers);
+		if !is_v2 {
+			return res;
+		}
+		Box::new(res.and_then(|res| {
+			// binary serializations go out as they are
+			let binary = res
+				.headers()
+				.get(CONTENT_TYPE)
+				.map(|t| t == BINARY_CONTENT_TYPE)
+				.unwrap_or(false);
+			if binary {
+				return Either::A(ok(res));
+			}
+			let (mut parts, body) = res.into_parts();
+			Either::B(body.concat2().map(move |body| {
+				let json = envelope(parts.status, &body).to_string();
+				parts.headers.remove(CONTENT_LENGTH);
+				parts
+					.headers
+					.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+				Response::from_parts(parts, Body::from(json))
+			}))
+		}))
+	}
+}
+
+/// Serves `/v2/...` requests with the `/v1/...` handlers of a router. The
+/// streaming, JSON-RPC and metrics endpoints, with formats of their own,
+/// stay v1.
+/// GET /v2/chain, POST /v2/peers/10.12.12.13:13414/ban, ...
+pub struct V2Handler {
+	pub router: Router,
+}
+
+impl Handler for V2Handler {
+	fn call(
+		&self,
+		mut req: Request<Body>,
+		_handlers: Box<Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let v1 = {
+			let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("");
+			format!("/v1{}", &path[V2_PREFIX.len()..])
+		};
+		*req.uri_mut() = match v1.parse::<Uri>() {
+			Ok(uri) => uri,
+			Err(_) => return response(StatusCode::BAD_REQUEST, "invalid url"),
+		};
+		let path = req.uri().path().to_owned();
+		if ["/v1/ws", "/v1/events", JSONRPC_PATH, METRICS_PATH]
+			.iter()
+			.any(|p| path.starts_with(p))
+		{
+			return response(
+				StatusCode::NOT_FOUND,
+				format!("only served by v1, at {}", path),
+			);
+		}
+		match self.router.get(&path) {
+			Err(_) => not_found(),
+			Ok(mut handlers) => match handlers.next() {
+				None => not_found(),
+				Some(h) => h.call(req, Box::new(handlers)),
+			},
+		}
+	}
+}
+
+/// Adds the v2 namespace to a router holding the v1 endpoints, to be done
+/// once they're all added
+pub fn add_v2_routes(router: &mut Router) -> Result<(), RouterError> {
+	let v2_handler = V2Handler {
+		router: router.clone(),
+	};
+	router.add_route("/v2/**", Arc::new(v2_handler))?;
+	router.add_middleware(Arc::new(V2Middleware));
+	Ok(())
+}
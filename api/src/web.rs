@@ -1,6 +1,9 @@
+use core::ser::{self, Writeable};
 use futures::future::{err, ok};
 use futures::{Future, Stream};
+use hyper::header::{HeaderValue, ACCEPT, CONTENT_TYPE};
 use hyper::{Body, Request, Response, StatusCode};
+use limits::{response_too_large, ResponseBuf};
 use rest::*;
 use router::ResponseFuture;
 use serde::{Deserialize, Serialize};
@@ -32,14 +35,64 @@ where
 {
 	match res {
 		Ok(s) => json_response_pretty(&s),
-		Err(e) => match e.kind() {
-			ErrorKind::Argument(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
-			ErrorKind::RequestError(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
-			ErrorKind::NotFound => response(StatusCode::NOT_FOUND, ""),
-			ErrorKind::Internal(msg) => response(StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-			ErrorKind::ResponseError(msg) => {
-				response(StatusCode::INTERNAL_SERVER_ERROR, msg.clone())
-			}
+		Err(e) => error_response(&e),
+	}
+}
+
+/// Convert Result to ResponseFuture, the value in its binary serialization
+pub fn result_to_binary_response<T>(res: Result<T, Error>) -> ResponseFuture
+where
+	T: Writeable,
+{
+	match res {
+		Ok(s) => binary_response(&s),
+		Err(e) => error_response(&e),
+	}
+}
+
+fn error_response(e: &Error) -> ResponseFuture {
+	match e.kind() {
+		ErrorKind::Argument(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
+		ErrorKind::RequestError(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
+		ErrorKind::NotFound => response(StatusCode::NOT_FOUND, ""),
+		ErrorKind::Internal(msg) => response(StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
+		ErrorKind::ResponseError(msg) => response(StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
+	}
+}
+
+/// Content type of the binary serialization of core types
+pub const BINARY_CONTENT_TYPE: &'static str = "application/octet-stream";
+
+/// Whether the request asks for the binary serialization rather than JSON
+pub fn wants_binary(req: &Request<Body>) -> bool {
+	req.headers()
+		.get_all(ACCEPT)
+		.iter()
+		.filter_map(|v| v.to_str().ok())
+		.flat_map(|v| v.split(','))
+		.any(|t| t.split(';').next().unwrap_or("").trim() == BINARY_CONTENT_TYPE)
+}
+
+/// Serializes a core type the way it goes over the wire and produces a
+/// response out of it.
+pub fn binary_response<T>(s: &T) -> ResponseFuture
+where
+	T: Writeable,
+{
+	let mut bin = ResponseBuf::new();
+	match ser::serialize(&mut bin, s) {
+		Ok(()) => {
+			let mut resp = just_response(StatusCode::OK, bin.into_inner());
+			resp.headers_mut()
+				.insert(CONTENT_TYPE, HeaderValue::from_static(BINARY_CONTENT_TYPE));
+			Box::new(ok(resp))
+		}
+		Err(e) => match bin.overflowed() {
+			Some(max) => Box::new(ok(response_too_large(max))),
+			None => response(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				format!("can't serialize response: {}", e),
+			),
 		},
 	}
 }
@@ -50,9 +103,13 @@ pub fn json_response<T>(s: &T) -> ResponseFuture
 where
 	T: Serialize,
 {
-	match serde_json::to_string(s) {
-		Ok(json) => response(StatusCode::OK, json),
-		Err(_) => response(StatusCode::INTERNAL_SERVER_ERROR, ""),
+	let mut json = ResponseBuf::new();
+	match serde_json::to_writer(&mut json, s) {
+		Ok(()) => response(StatusCode::OK, json.into_inner()),
+		Err(_) => match json.overflowed() {
+			Some(max) => Box::new(ok(response_too_large(max))),
+			None => response(StatusCode::INTERNAL_SERVER_ERROR, ""),
+		},
 	}
 }
 
@@ -61,12 +118,16 @@ pub fn json_response_pretty<T>(s: &T) -> ResponseFuture
 where
 	T: Serialize,
 {
-	match serde_json::to_string_pretty(s) {
-		Ok(json) => response(StatusCode::OK, json),
-		Err(e) => response(
-			StatusCode::INTERNAL_SERVER_ERROR,
-			format!("can't create json response: {}", e),
-		),
+	let mut json = ResponseBuf::new();
+	match serde_json::to_writer_pretty(&mut json, s) {
+		Ok(()) => response(StatusCode::OK, json.into_inner()),
+		Err(e) => match json.overflowed() {
+			Some(max) => Box::new(ok(response_too_large(max))),
+			None => response(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				format!("can't create json response: {}", e),
+			),
+		},
 	}
 }
 
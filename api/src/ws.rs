@@ -0,0 +1,606 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! WebSocket subscriptions to node events, served at `/v1/ws`.
+//!
+//! Clients send `{"subscribe": ["headers", "blocks"]}` (or `unsubscribe`)
+//! text messages and get back `{"subscribed": [...]}`, then a
+//! `{"topic": "blocks", "data": {...}}` message each time the chain, the
+//! pool or the sync report something they're subscribed to.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Weak};
+
+use base64;
+use bytes::{BufMut, BytesMut};
+use futures::future::ok;
+use futures::sync::mpsc;
+use futures::{stream, Future, Sink, Stream};
+use hyper::header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::upgrade::Upgraded;
+use hyper::{self, Body, Request, Response, StatusCode};
+use ring::digest;
+use serde::Serialize;
+use serde_json;
+use tokio::codec::{Decoder, Encoder, Framed};
+
+use chain;
+use core::core::{Block, BlockHeader, Transaction};
+use router::{Handler, ResponseFuture};
+use types::{BlockHeaderPrintable, BlockPrintable, SyncProgress, TxPrintable};
+use util::{Mutex, OneTime};
+use web::response;
+
+/// Appended to the client key to make the handshake answer (RFC 6455)
+const WS_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest message accepted from clients, which only send subscriptions
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Past events kept for event stream clients catching up after reconnecting
+const EVENT_HISTORY: usize = 256;
+
+/// Most bytes of past events kept, full blocks being large
+const EVENT_HISTORY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Messages queued for a client before it's disconnected as too slow to keep
+/// up, more than the history a reconnecting client catches up with
+const SUBSCRIBER_QUEUE: usize = 1024;
+
+/// The event streams clients can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+	/// Block headers accepted in the header chain
+	Headers,
+	/// Full blocks accepted in the chain
+	Blocks,
+	/// Transactions accepted in the pool (never stem ones)
+	Transactions,
+	/// Changes in the sync status
+	Sync,
+}
+
+impl Topic {
+	/// Name of the topic, as clients give it
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Topic::Headers => "headers",
+			Topic::Blocks => "blocks",
+			Topic::Transactions => "transactions",
+			Topic::Sync => "sync",
+		}
+	}
+
+	/// Whether past events on the topic are kept for clients catching up,
+	/// headers being too many while syncing and only the last sync status
+	/// mattering
+	fn kept(&self) -> bool {
+		*self == Topic::Blocks || *self == Topic::Transactions
+	}
+}
+
+struct Subscriber {
+	topics: Vec<Topic>,
+	tx: mpsc::Sender<String>,
+	/// Receives server-sent events rather than WebSocket messages
+	event_stream: bool,
+}
+
+/// Numbering of the events published, with the last few kept
+struct History {
+	next_id: u64,
+	events: VecDeque<(u64, Topic, String)>,
+	size: usize,
+}
+
+#[derive(Serialize)]
+struct Event<'a, T: 'a> {
+	topic: Topic,
+	data: &'a T,
+}
+
+/// Fans node events out to the WebSocket clients subscribed to them. The
+/// chain, pool and sync adapters report to it as things happen.
+pub struct EventHub {
+	chain: OneTime<Weak<chain::Chain>>,
+	subscribers: Mutex<HashMap<u64, Subscriber>>,
+	next_id: Mutex<u64>,
+	last_sync: Mutex<Option<SyncProgress>>,
+	history: Mutex<History>,
+}
+
+impl EventHub {
+	/// A hub without any subscriber yet
+	pub fn new() -> EventHub {
+		EventHub {
+			chain: OneTime::new(),
+			subscribers: Mutex::new(HashMap::new()),
+			next_id: Mutex::new(0),
+			last_sync: Mutex::new(None),
+			history: Mutex::new(History {
+				next_id: 1,
+				events: VecDeque::new(),
+				size: 0,
+			}),
+		}
+	}
+
+	/// Set the chain blocks are looked up in, should only be called once
+	pub fn init(&self, chain: Arc<chain::Chain>) {
+		self.chain.init(Arc::downgrade(&chain));
+	}
+
+	/// A block header was accepted in the header chain
+	pub fn header_accepted(&self, bh: &BlockHeader) {
+		self.publish(Topic::Headers, || {
+			Some(BlockHeaderPrintable::from_header(bh))
+		});
+	}
+
+	/// A block was accepted in the chain
+	pub fn block_accepted(&self, b: &Block) {
+		self.publish(Topic::Blocks, || {
+			self.chain
+				.borrow()
+				.upgrade()
+				.map(|chain| BlockPrintable::from_block(b, chain, false))
+		});
+	}
+
+	/// A transaction was accepted in the pool
+	pub fn tx_accepted(&self, tx: &Transaction) {
+		self.publish(Topic::Transactions, || Some(TxPrintable::from_tx(tx)));
+	}
+
+	/// The sync moved along, new subscribers get the last progress straight
+	/// away
+	pub fn sync_status(&self, progress: SyncProgress) {
+		*self.last_sync.lock() = Some(progress.clone());
+		self.publish(Topic::Sync, || Some(progress));
+	}
+
+	/// Number of connected clients
+	pub fn subscriber_count(&self) -> usize {
+		self.subscribers.lock().len()
+	}
+
+	/// Sends an event to its subscribers, only building and keeping it if
+	/// there are any. Those that can't keep up are disconnected.
+	fn publish<T, F>(&self, topic: Topic, event: F)
+	where
+		T: Serialize,
+		F: FnOnce() -> Option<T>,
+	{
+		if !self
+			.subscribers
+			.lock()
+			.values()
+			.any(|s| s.topics.contains(&topic))
+		{
+			return;
+		}
+		let data = match event() {
+			Some(data) => data,
+			None => return,
+		};
+		let (msg, data) = match (
+			serde_json::to_string(&Event { topic, data: &data }),
+			serde_json::to_string(&data),
+		) {
+			(Ok(msg), Ok(data)) => (msg, data),
+			(Err(e), _) | (_, Err(e)) => {
+				error!("ws: could not serialize {:?} event: {}", topic, e);
+				return;
+			}
+		};
+
+		// events are numbered, kept and sent in the same order, under the
+		// history lock
+		let mut history = self.history.lock();
+		let id = history.next_id;
+		history.next_id += 1;
+		let frame = stream_event(Some(id), topic, &data);
+		if topic.kept() {
+			history.size += frame.len();
+			history.events.push_back((id, topic, frame.clone()));
+			while history.events.len() > EVENT_HISTORY || history.size > EVENT_HISTORY_SIZE {
+				match history.events.pop_front() {
+					Some((_, _, frame)) => history.size -= frame.len(),
+					None => break,
+				}
+			}
+		}
+		let mut subscribers = self.subscribers.lock();
+		let mut closed = vec![];
+		for (id, s) in subscribers.iter_mut() {
+			if !s.topics.contains(&topic) {
+				continue;
+			}
+			let msg = if s.event_stream { &frame } else { &msg };
+			if let Err(e) = s.tx.try_send(msg.clone()) {
+				if e.is_full() {
+					warn!("ws: disconnecting subscriber {}, too slow to keep up", id);
+				}
+				closed.push(*id);
+			}
+		}
+		for id in closed {
+			subscribers.remove(&id);
+		}
+	}
+
+	fn connect(&self) -> (u64, mpsc::Receiver<String>) {
+		let (tx, rx) = mpsc::channel(SUBSCRIBER_QUEUE);
+		let id = self.add_subscriber(Subscriber {
+			topics: vec![],
+			tx,
+			event_stream: false,
+		});
+		(id, rx)
+	}
+
+	/// Connect a server-sent events client to the given topics, first
+	/// sending it the kept events past the last one it got, if any, and the
+	/// last sync progress
+	pub(crate) fn connect_stream(
+		&self,
+		topics: Vec<Topic>,
+		last_event_id: Option<u64>,
+	) -> mpsc::Receiver<String> {
+		let (mut tx, rx) = mpsc::channel(SUBSCRIBER_QUEUE);
+		// holding the history lock, nothing gets published in between
+		let history = self.history.lock();
+		if let Some(last_id) = last_event_id {
+			for &(id, topic, ref frame) in &history.events {
+				if id > last_id && topics.contains(&topic) {
+					let _ = tx.try_send(frame.clone());
+				}
+			}
+		}
+		if topics.contains(&Topic::Sync) {
+			if let Some(ref progress) = *self.last_sync.lock() {
+				if let Ok(data) = serde_json::to_string(progress) {
+					let _ = tx.try_send(stream_event(None, Topic::Sync, &data));
+				}
+			}
+		}
+		self.add_subscriber(Subscriber {
+			topics,
+			tx,
+			event_stream: true,
+		});
+		rx
+	}
+
+	fn add_subscriber(&self, sub: Subscriber) -> u64 {
+		let mut next_id = self.next_id.lock();
+		let id = *next_id;
+		*next_id += 1;
+		self.subscribers.lock().insert(id, sub);
+		id
+	}
+
+	fn disconnect(&self, id: u64) {
+		self.subscribers.lock().remove(&id);
+	}
+
+	/// Update the topics of a client, returning those it's now subscribed to
+	fn subscribe(&self, id: u64, req: &SubscribeRequest) -> Vec<Topic> {
+		let mut subscribers = self.subscribers.lock();
+		let sub = match subscribers.get_mut(&id) {
+			Some(sub) => sub,
+			None => return vec![],
+		};
+		let had_sync = sub.topics.contains(&Topic::Sync);
+		for topic in &req.subscribe {
+			if !sub.topics.contains(topic) {
+				sub.topics.push(*topic);
+			}
+		}
+		sub.topics.retain(|t| !req.unsubscribe.contains(t));
+		let topics = sub.topics.clone();
+
+		if !had_sync && topics.contains(&Topic::Sync) {
+			if let Some(ref progress) = *self.last_sync.lock() {
+				if let Ok(msg) = serde_json::to_string(&Event {
+					topic: Topic::Sync,
+					data: progress,
+				}) {
+					let _ = sub.tx.try_send(msg);
+				}
+			}
+		}
+		topics
+	}
+}
+
+impl Default for EventHub {
+	fn default() -> EventHub {
+		EventHub::new()
+	}
+}
+
+/// An event as sent to server-sent events clients, the id left out for
+/// those that aren't kept
+fn stream_event(id: Option<u64>, topic: Topic, data: &str) -> String {
+	match id {
+		Some(id) => format!("id: {}\nevent: {}\ndata: {}\n\n", id, topic.name(), data),
+		None => format!("event: {}\ndata: {}\n\n", topic.name(), data),
+	}
+}
+
+#[derive(Deserialize)]
+struct SubscribeRequest {
+	#[serde(default)]
+	subscribe: Vec<Topic>,
+	#[serde(default)]
+	unsubscribe: Vec<Topic>,
+}
+
+#[derive(Serialize)]
+struct SubscribeReply {
+	subscribed: Vec<Topic>,
+}
+
+#[derive(Serialize)]
+struct ErrorReply {
+	error: String,
+}
+
+/// Upgrades `GET /v1/ws` requests to WebSocket connections streaming the
+/// events of a hub
+/// GET /v1/ws
+pub struct WsHandler {
+	pub events: Weak<EventHub>,
+}
+
+impl Handler for WsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let is_upgrade = req
+			.headers()
+			.get(UPGRADE)
+			.and_then(|v| v.to_str().ok())
+			.map(|v| v.eq_ignore_ascii_case("websocket"))
+			.unwrap_or(false);
+		let key = match req.headers().get(SEC_WEBSOCKET_KEY) {
+			Some(key) if is_upgrade => key.as_bytes().to_vec(),
+			_ => return response(StatusCode::BAD_REQUEST, "expected a websocket upgrade"),
+		};
+		let events = match self.events.upgrade() {
+			Some(events) => events,
+			None => return response(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+		};
+
+		let conn = req
+			.into_body()
+			.on_upgrade()
+			.map_err(|e| error!("ws: upgrade failed: {}", e))
+			.and_then(move |upgraded| serve(events, upgraded));
+		hyper::rt::spawn(conn);
+
+		let response = Response::builder()
+			.status(StatusCode::SWITCHING_PROTOCOLS)
+			.header(UPGRADE, HeaderValue::from_static("websocket"))
+			.header(CONNECTION, HeaderValue::from_static("Upgrade"))
+			.header(SEC_WEBSOCKET_ACCEPT, accept_key(&key).as_str())
+			.body(Body::empty())
+			.unwrap();
+		Box::new(ok(response))
+	}
+}
+
+/// The `Sec-WebSocket-Accept` answer to a client key
+fn accept_key(key: &[u8]) -> String {
+	let mut ctx = digest::Context::new(&digest::SHA1);
+	ctx.update(key);
+	ctx.update(WS_GUID.as_bytes());
+	base64::encode(ctx.finish().as_ref())
+}
+
+/// Run a connection until either side closes it
+fn serve(events: Arc<EventHub>, upgraded: Upgraded) -> Box<Future<Item = (), Error = ()> + Send> {
+	let (id, rx) = events.connect();
+	let (sink, frames) = Framed::new(upgraded, WsCodec::new()).split();
+
+	let hub = events.clone();
+	// replies to the client, ending with ours to its close
+	let replies = frames
+		.take_while(|f| Ok(f.opcode != OP_CLOSE))
+		.filter_map(move |f| reply(&hub, id, f))
+		.map(Some)
+		.chain(stream::once(Ok(Some(Frame::close()))))
+		.chain(stream::once(Ok(None)));
+	// closing the connection once disconnected from the hub
+	let published = rx
+		.map(|msg| Some(Frame::text(msg)))
+		.chain(stream::once(Ok(Some(Frame::close()))))
+		.chain(stream::once(Ok(None)))
+		.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "event channel closed"));
+	let outgoing = replies
+		.select(published)
+		.take_while(|f| Ok(f.is_some()))
+		.map(|f| f.unwrap());
+
+	Box::new(sink.send_all(outgoing).then(move |res| {
+		if let Err(e) = res {
+			debug!("ws: connection {} dropped: {}", id, e);
+		}
+		events.disconnect(id);
+		Ok(())
+	}))
+}
+
+/// What to answer a frame from a client with
+fn reply(events: &EventHub, id: u64, frame: Frame) -> Option<Frame> {
+	match frame.opcode {
+		OP_PING => Some(Frame {
+			opcode: OP_PONG,
+			payload: frame.payload,
+		}),
+		OP_TEXT => {
+			let msg = match serde_json::from_slice::<SubscribeRequest>(&frame.payload) {
+				Ok(req) => serde_json::to_string(&SubscribeReply {
+					subscribed: events.subscribe(id, &req),
+				}),
+				Err(e) => serde_json::to_string(&ErrorReply {
+					error: format!("invalid subscription: {}", e),
+				}),
+			};
+			msg.ok().map(Frame::text)
+		}
+		_ => None,
+	}
+}
+
+/// A whole WebSocket message, or a control frame
+struct Frame {
+	opcode: u8,
+	payload: Vec<u8>,
+}
+
+impl Frame {
+	fn text(msg: String) -> Frame {
+		Frame {
+			opcode: OP_TEXT,
+			payload: msg.into_bytes(),
+		}
+	}
+
+	fn close() -> Frame {
+		Frame {
+			opcode: OP_CLOSE,
+			payload: vec![],
+		}
+	}
+}
+
+/// Server side of the WebSocket framing: reads masked client frames,
+/// reassembling fragmented messages, and writes unmasked ones
+struct WsCodec {
+	fragments: Option<Frame>,
+}
+
+impl WsCodec {
+	fn new() -> WsCodec {
+		WsCodec { fragments: None }
+	}
+}
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Decoder for WsCodec {
+	type Item = Frame;
+	type Error = io::Error;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+		loop {
+			if src.len() < 2 {
+				return Ok(None);
+			}
+			let fin = src[0] & 0x80 != 0;
+			let opcode = src[0] & 0x0f;
+			if src[1] & 0x80 == 0 {
+				return Err(invalid("client frames must be masked"));
+			}
+			let (len, mut offset) = match src[1] & 0x7f {
+				126 => {
+					if src.len() < 4 {
+						return Ok(None);
+					}
+					(((src[2] as usize) << 8) | src[3] as usize, 4)
+				}
+				127 => {
+					if src.len() < 10 {
+						return Ok(None);
+					}
+					let len = src[2..10]
+						.iter()
+						.fold(0u64, |len, b| (len << 8) | *b as u64);
+					(len as usize, 10)
+				}
+				len => (len as usize, 2),
+			};
+			let pending = self
+				.fragments
+				.as_ref()
+				.map(|f| f.payload.len())
+				.unwrap_or(0);
+			if len > MAX_MESSAGE_SIZE || pending + len > MAX_MESSAGE_SIZE {
+				return Err(invalid("message too large"));
+			}
+			if src.len() < offset + 4 + len {
+				return Ok(None);
+			}
+			let mut mask = [0u8; 4];
+			mask.copy_from_slice(&src[offset..offset + 4]);
+			offset += 4;
+			let frame = src.split_to(offset + len);
+			let payload: Vec<u8> = frame[offset..]
+				.iter()
+				.enumerate()
+				.map(|(i, b)| b ^ mask[i % 4])
+				.collect();
+
+			// control frames can come in between fragments
+			if opcode >= OP_CLOSE {
+				return Ok(Some(Frame { opcode, payload }));
+			}
+			let message = match (self.fragments.take(), opcode) {
+				(Some(mut message), OP_CONTINUATION) => {
+					message.payload.extend_from_slice(&payload);
+					message
+				}
+				(None, OP_CONTINUATION) => return Err(invalid("unexpected continuation")),
+				(Some(_), _) => return Err(invalid("expected a continuation")),
+				(None, _) => Frame { opcode, payload },
+			};
+			if fin {
+				return Ok(Some(message));
+			}
+			self.fragments = Some(message);
+		}
+	}
+}
+
+impl Encoder for WsCodec {
+	type Item = Frame;
+	type Error = io::Error;
+
+	fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), io::Error> {
+		let len = frame.payload.len();
+		dst.reserve(len + 10);
+		dst.put_u8(0x80 | frame.opcode);
+		if len < 126 {
+			dst.put_u8(len as u8);
+		} else if len <= 0xffff {
+			dst.put_u8(126);
+			dst.put_u16_be(len as u16);
+		} else {
+			dst.put_u8(127);
+			dst.put_u64_be(len as u64);
+		}
+		dst.put_slice(&frame.payload);
+		Ok(())
+	}
+}
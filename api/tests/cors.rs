@@ -0,0 +1,81 @@
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_util as util;
+extern crate hyper;
+
+use api::*;
+use futures::Future;
+use hyper::service::Service;
+use hyper::{Body, Request, Response, StatusCode};
+use std::sync::Arc;
+
+struct TestHandler;
+
+impl Handler for TestHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		response(StatusCode::OK, "{}")
+	}
+}
+
+fn call(router: &mut Router, method: &str, origin: Option<&str>) -> Response<Body> {
+	let mut req = Request::builder();
+	req.method(method).uri("/v1/status");
+	if let Some(origin) = origin {
+		req.header("Origin", origin);
+	}
+	if method == "OPTIONS" {
+		req.header("Access-Control-Request-Method", "GET")
+			.header("Access-Control-Request-Headers", "authorization");
+	}
+	router
+		.call(req.body(Body::empty()).unwrap())
+		.wait()
+		.unwrap()
+}
+
+fn allow_origin(res: &Response<Body>) -> Option<&str> {
+	res.headers()
+		.get("access-control-allow-origin")
+		.map(|o| o.to_str().unwrap())
+}
+
+#[test]
+fn test_cors() {
+	util::init_test_logger();
+	let mut router = Router::new();
+	router.add_route("/v1/**", Arc::new(TestHandler)).unwrap();
+	router.add_middleware(Arc::new(CorsMiddleware::new(vec![
+		"https://explorer.example.com/".to_owned(),
+	])));
+	let (allowed, other) = ("https://explorer.example.com", "https://evil.example.com");
+
+	// preflights are answered before the handlers
+	let res = call(&mut router, "OPTIONS", Some(allowed));
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+	assert_eq!(allow_origin(&res), Some(allowed));
+	assert_eq!(
+		res.headers()["access-control-allow-headers"],
+		"authorization"
+	);
+	assert_eq!(res.headers()["access-control-allow-credentials"], "true");
+	let res = call(&mut router, "OPTIONS", Some(other));
+	assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+	let res = call(&mut router, "GET", Some(allowed));
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(allow_origin(&res), Some(allowed));
+	let res = call(&mut router, "GET", Some(other));
+	assert_eq!(res.status(), StatusCode::OK);
+	assert_eq!(allow_origin(&res), None);
+	assert_eq!(allow_origin(&call(&mut router, "GET", None)), None);
+
+	// any origin, without credentials
+	let mut router = Router::new();
+	router.add_route("/v1/**", Arc::new(TestHandler)).unwrap();
+	router.add_middleware(Arc::new(CorsMiddleware::new(vec!["*".to_owned()])));
+	let res = call(&mut router, "GET", Some(other));
+	assert_eq!(allow_origin(&res), Some("*"));
+	assert!(!res
+		.headers()
+		.contains_key("access-control-allow-credentials"));
+}
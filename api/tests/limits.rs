@@ -0,0 +1,137 @@
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_util as util;
+extern crate hyper;
+
+use api::*;
+use futures::{Future, Stream};
+use hyper::service::Service;
+use hyper::{Body, Request, StatusCode};
+use std::sync::Arc;
+
+struct EchoHandler;
+
+impl Handler for EchoHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		match req.uri().path() {
+			// serialized, rather than built as is
+			"/v1/json" => json_response(&vec![0u8; 100]),
+			_ => response(StatusCode::OK, req.uri().query().unwrap_or("").to_owned()),
+		}
+	}
+
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		Box::new(
+			req.into_body()
+				.concat2()
+				.map(|body| just_response(StatusCode::OK, body.to_vec())),
+		)
+	}
+}
+
+fn call(router: &mut Router, method: &str, uri: &str, from: &str, body: &str) -> StatusCode {
+	let mut req = Request::builder()
+		.method(method)
+		.uri(uri)
+		.body(Body::from(body.to_owned()))
+		.unwrap();
+	req.extensions_mut()
+		.insert(RemoteAddr(from.parse().unwrap()));
+	router.call(req).wait().unwrap().status()
+}
+
+#[test]
+fn test_rate_limit() {
+	util::init_test_logger();
+	let mut router = Router::new();
+	router.add_route("/v1/**", Arc::new(EchoHandler)).unwrap();
+	router.add_middleware(Arc::new(RateLimitMiddleware::new(12)));
+	let (a, b, local) = ("10.0.0.1:1000", "10.0.0.2:1000", "127.0.0.1:1000");
+
+	assert_eq!(
+		call(&mut router, "GET", "/v1/status", a, ""),
+		StatusCode::OK
+	);
+	assert_eq!(
+		call(&mut router, "GET", "/v1/status", a, ""),
+		StatusCode::OK
+	);
+	// expensive calls take a bigger share
+	assert_eq!(
+		call(&mut router, "GET", "/v1/blocks/1", a, ""),
+		StatusCode::OK
+	);
+	assert_eq!(
+		call(&mut router, "GET", "/v1/status", a, ""),
+		StatusCode::TOO_MANY_REQUESTS
+	);
+
+	// others aren't held back, nor local clients
+	assert_eq!(
+		call(&mut router, "GET", "/v1/status", b, ""),
+		StatusCode::OK
+	);
+	for _ in 0..20 {
+		assert_eq!(
+			call(&mut router, "GET", "/v1/blocks/1", local, ""),
+			StatusCode::OK
+		);
+	}
+
+	// addresses in the same IPv6 /64 share their allowance
+	let (c, d) = ("[2001:db8::1]:1000", "[2001:db8::ffff:2]:1000");
+	assert_eq!(
+		call(&mut router, "GET", "/v1/blocks/1", c, ""),
+		StatusCode::OK
+	);
+	assert_eq!(
+		call(&mut router, "GET", "/v1/blocks/1", d, ""),
+		StatusCode::TOO_MANY_REQUESTS
+	);
+
+	// past the clients tracked, the least recently seen are forgotten
+	for i in 0..10_000u32 {
+		let addr = format!("10.1.{}.{}:1000", i >> 8, i & 0xff);
+		call(&mut router, "GET", "/v1/status", &addr, "");
+	}
+	assert_eq!(
+		call(&mut router, "GET", "/v1/status", a, ""),
+		StatusCode::OK
+	);
+}
+
+#[test]
+fn test_size_limits() {
+	util::init_test_logger();
+	let mut router = Router::new();
+	router.add_route("/v1/**", Arc::new(EchoHandler)).unwrap();
+	router.add_middleware(Arc::new(SizeLimitMiddleware::new(Some(10), Some(20))));
+	let from = "10.0.0.1:1000";
+
+	assert_eq!(
+		call(&mut router, "POST", "/v1/pool/push", from, "0123456789"),
+		StatusCode::OK
+	);
+	assert_eq!(
+		call(&mut router, "POST", "/v1/pool/push", from, "0123456789a"),
+		StatusCode::PAYLOAD_TOO_LARGE
+	);
+	assert_eq!(
+		call(&mut router, "GET", "/v1/chain?0123456789", from, ""),
+		StatusCode::OK
+	);
+	assert_eq!(
+		call(
+			&mut router,
+			"GET",
+			"/v1/chain?0123456789abcdefghijk",
+			from,
+			""
+		),
+		StatusCode::INTERNAL_SERVER_ERROR
+	);
+	assert_eq!(
+		call(&mut router, "GET", "/v1/json", from, ""),
+		StatusCode::INTERNAL_SERVER_ERROR
+	);
+}
@@ -0,0 +1,87 @@
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_util as util;
+extern crate hyper;
+
+use api::metrics::endpoint;
+use api::*;
+use futures::{Future, Stream};
+use hyper::service::Service;
+use hyper::{Body, Request, Response, StatusCode};
+use std::sync::{Arc, Weak};
+
+struct TestHandler;
+
+impl Handler for TestHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		response(StatusCode::OK, "{}")
+	}
+}
+
+fn call(router: &mut Router, uri: &str, id: Option<&str>) -> Response<Body> {
+	let mut req = Request::builder();
+	req.method("GET").uri(uri);
+	if let Some(id) = id {
+		req.header("X-Request-Id", id);
+	}
+	router.call(req.body(Body::empty()).unwrap()).wait().unwrap()
+}
+
+fn request_id(res: &Response<Body>) -> String {
+	res.headers()["x-request-id"].to_str().unwrap().to_owned()
+}
+
+#[test]
+fn test_endpoint() {
+	assert_eq!(endpoint("/v1/blocks/1234"), "/v1/blocks/{id}");
+	assert_eq!(
+		endpoint("/v1/headers/0b20d8c2275e7ed2ce4c1a20f9e4b8e8b7c6fd4aa9d8e1d7d52c8a8a7ea1cf36"),
+		"/v1/headers/{id}"
+	);
+	assert_eq!(endpoint("/v1/peers/10.12.12.13:13414/ban"), "/v1/peers/{id}/ban");
+	assert_eq!(endpoint("/v1/chain/outputs/byids"), "/v1/chain/outputs/byids");
+	assert_eq!(endpoint("/v1/status/"), "/v1/status");
+}
+
+#[test]
+fn test_request_tracing() {
+	util::init_test_logger();
+	let metrics = Arc::new(ApiMetrics::new());
+	let mut router = Router::new();
+	router
+		.add_route(
+			METRICS_PATH,
+			Arc::new(MetricsHandler {
+				metrics: metrics.clone(),
+				chain: Weak::new(),
+				peers: Weak::new(),
+			}),
+		).unwrap();
+	router.add_route("/v1/**", Arc::new(TestHandler)).unwrap();
+	router.add_middleware(Arc::new(TraceMiddleware::new(metrics)));
+
+	// each request gets its own ID, unless one's given
+	let first = request_id(&call(&mut router, "/v1/blocks/10", None));
+	let second = request_id(&call(&mut router, "/v1/blocks/11", None));
+	assert_eq!(first.len(), 16);
+	assert!(first != second);
+	let res = call(&mut router, "/v1/status", Some("lb-1234"));
+	assert_eq!(request_id(&res), "lb-1234");
+	let res = call(&mut router, "/v1/status", Some("not an id"));
+	assert!(request_id(&res) != "not an id");
+
+	let res = call(&mut router, METRICS_PATH, None);
+	assert_eq!(res.status(), StatusCode::OK);
+	let body = res.into_body().concat2().wait().unwrap();
+	let text = String::from_utf8(body.to_vec()).unwrap();
+	assert!(text.contains("# TYPE grin_api_request_duration_seconds histogram"));
+	assert!(text.contains(
+		"grin_api_request_duration_seconds_count{method=\"GET\",endpoint=\"/v1/blocks/{id}\"} 2"
+	));
+	assert!(text.contains(
+		"grin_api_request_duration_seconds_bucket{method=\"GET\",endpoint=\"/v1/status\",le=\"+Inf\"} 2"
+	));
+	assert!(text.contains(
+		"grin_api_request_duration_seconds_bucket{method=\"GET\",endpoint=\"/v1/status\",le=\"10\"} 2"
+	));
+}
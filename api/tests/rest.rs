@@ -19,6 +19,10 @@ impl Handler for IndexHandler {
 	fn get(&self, _req: Request<Body>) -> ResponseFuture {
 		json_response_pretty(&self.list)
 	}
+
+	fn post(&self, _req: Request<Body>) -> ResponseFuture {
+		response(hyper::StatusCode::OK, "")
+	}
 }
 
 pub struct CounterMiddleware {
@@ -71,13 +75,50 @@ fn test_start_api() {
 	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
 	assert!(server.start(addr, router, None).is_ok());
 	let url = format!("http://{}/v1/", server_addr);
-	let index = request_with_retry(url.as_str()).unwrap();
+	let index = request_with_retry(url.as_str(), None).unwrap();
 	assert_eq!(index.len(), 2);
 	assert_eq!(counter.value(), 1);
 	assert!(server.stop());
 	thread::sleep(time::Duration::from_millis(1_000));
 }
 
+#[test]
+fn test_role_auth() {
+	util::init_test_logger();
+	let mut server = ApiServer::new();
+	let mut router = build_router();
+	router
+		.add_route(READY_PATH, Arc::new(IndexHandler { list: vec![] }))
+		.unwrap();
+	let auth = |secret: &str| "Basic ".to_string() + &util::to_base64(&format!("grin:{}", secret));
+	router.add_middleware(Arc::new(RoleAuthMiddleware::new(
+		auth("owner"),
+		Some(auth("reader")),
+		"Basic realm=GrinAPI".to_string(),
+	)));
+	let server_addr = "127.0.0.1:14436";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	assert!(server.start(addr, router, None).is_ok());
+	let url = format!("http://{}/v1/", server_addr);
+	let secret = |s: &str| Some(s.to_string());
+
+	// the owner can do anything, readers only read
+	assert!(request_with_retry(url.as_str(), secret("owner")).is_ok());
+	assert!(api::client::post_no_ret(url.as_str(), secret("owner"), &"").is_ok());
+	assert_eq!(request_with_retry(url.as_str(), secret("reader")).unwrap().len(), 2);
+	assert!(api::client::post_no_ret(url.as_str(), secret("reader"), &"").is_err());
+	// except for JSON-RPC, which turns down writes itself
+	let rpc_url = format!("http://{}{}", server_addr, JSONRPC_PATH);
+	assert!(api::client::post_no_ret(rpc_url.as_str(), secret("reader"), &"").is_ok());
+	assert!(api::client::get::<Vec<String>>(url.as_str(), secret("other")).is_err());
+	assert!(api::client::get::<Vec<String>>(url.as_str(), None).is_err());
+	// probes don't need to authenticate
+	let ready_url = format!("http://{}{}", server_addr, READY_PATH);
+	assert!(api::client::get::<Vec<String>>(ready_url.as_str(), None).is_ok());
+	assert!(server.stop());
+	thread::sleep(time::Duration::from_millis(1_000));
+}
+
 // To enable this test you need a trusted PKCS12 (p12) certificate bundle
 // Hyper-tls client doesn't accept self-signed certificates. The easiest way is to use mkcert
 // https://github.com/FiloSottile/mkcert to install CA and generate a certificate on your local machine.
@@ -95,15 +136,15 @@ fn test_start_api_tls() {
 	let server_addr = "0.0.0.0:14444";
 	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
 	assert!(server.start(addr, router, Some(tls_conf)).is_ok());
-	let index = request_with_retry("https://yourdomain.com:14444/v1/").unwrap();
+	let index = request_with_retry("https://yourdomain.com:14444/v1/", None).unwrap();
 	assert_eq!(index.len(), 2);
 	assert!(!server.stop());
 }
 
-fn request_with_retry(url: &str) -> Result<Vec<String>, api::Error> {
+fn request_with_retry(url: &str, api_secret: Option<String>) -> Result<Vec<String>, api::Error> {
 	let mut tries = 0;
 	loop {
-		let res = api::client::get::<Vec<String>>(url, None);
+		let res = api::client::get::<Vec<String>>(url, api_secret.clone());
 		if res.is_ok() {
 			return res;
 		}
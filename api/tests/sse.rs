@@ -0,0 +1,118 @@
+extern crate grin_api as api;
+extern crate grin_core as core;
+extern crate grin_util as util;
+extern crate serde_json;
+
+use api::*;
+use core::core::{BlockHeader, Transaction};
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+/// Opens the stream, returning whatever comes after the response headers
+fn connect(addr: &str, query: &str, last_event_id: Option<u64>) -> BufReader<TcpStream> {
+	let mut stream = TcpStream::connect(addr).unwrap();
+	// HTTP/1.0 so the body isn't chunked
+	write!(stream, "GET /v1/events{} HTTP/1.0\r\nHost: {}\r\n", query, addr).unwrap();
+	if let Some(id) = last_event_id {
+		write!(stream, "Last-Event-ID: {}\r\n", id).unwrap();
+	}
+	write!(stream, "\r\n").unwrap();
+	let mut reader = BufReader::new(stream);
+	let mut status = String::new();
+	reader.read_line(&mut status).unwrap();
+	assert!(status.contains(" 200 "), "{}", status);
+	let mut content_type = None;
+	loop {
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		if line.trim().is_empty() {
+			break;
+		}
+		if line.to_lowercase().starts_with("content-type:") {
+			content_type = Some(line[13..].trim().to_owned());
+		}
+	}
+	assert_eq!(content_type.unwrap(), "text/event-stream");
+	assert_eq!(read_event(&mut reader), vec!["retry: 3000"]);
+	reader
+}
+
+/// The lines of the next event
+fn read_event(reader: &mut BufReader<TcpStream>) -> Vec<String> {
+	let mut lines = vec![];
+	loop {
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		if line.trim().is_empty() {
+			return lines;
+		}
+		lines.push(line.trim().to_owned());
+	}
+}
+
+fn event_data(lines: &[String]) -> Value {
+	serde_json::from_str(&lines.last().unwrap()["data: ".len()..]).unwrap()
+}
+
+#[test]
+fn test_sse_stream() {
+	util::init_test_logger();
+	let events = Arc::new(EventHub::new());
+	let mut router = Router::new();
+	router
+		.add_route(
+			"/v1/events",
+			Arc::new(EventsHandler {
+				events: Arc::downgrade(&events),
+			}),
+		).unwrap();
+	let server_addr = "127.0.0.1:14436";
+	let addr: SocketAddr = server_addr.parse().unwrap();
+	let mut server = ApiServer::new();
+	assert!(server.start(addr, router, None).is_ok());
+	thread::sleep(time::Duration::from_millis(500));
+
+	events.sync_status(SyncProgress {
+		status: "body_sync".to_owned(),
+		current: Some(10),
+		total: Some(100),
+	});
+
+	// the last sync progress comes first, without an id as it isn't kept
+	let mut reader = connect(server_addr, "?topics=transactions,sync", None);
+	let event = read_event(&mut reader);
+	assert_eq!(event[0], "event: sync");
+	assert_eq!(event_data(&event)["current"], 10);
+
+	// only what's subscribed to is sent, numbered
+	events.header_accepted(&BlockHeader::default());
+	events.tx_accepted(&Transaction::empty());
+	let event = read_event(&mut reader);
+	assert_eq!(event[0], "id: 1");
+	assert_eq!(event[1], "event: transactions");
+	assert_eq!(event_data(&event)["fee"], 0);
+	events.tx_accepted(&Transaction::empty());
+	assert_eq!(read_event(&mut reader)[0], "id: 2");
+
+	// reconnecting gets what was missed since the last event seen, kept
+	// while someone was subscribed
+	let mut other = connect(server_addr, "?topics=transactions", None);
+	drop(reader);
+	events.tx_accepted(&Transaction::empty());
+	assert_eq!(read_event(&mut other)[0], "id: 3");
+	let mut reader = connect(server_addr, "?topics=transactions", Some(1));
+	assert_eq!(read_event(&mut reader)[0], "id: 2");
+	assert_eq!(read_event(&mut reader)[0], "id: 3");
+	events.tx_accepted(&Transaction::empty());
+	assert_eq!(read_event(&mut reader)[0], "id: 4");
+
+	// unknown topics are turned down
+	let res = api::client::get::<Value>(
+		&format!("http://{}/v1/events?topics=mempool", server_addr),
+		None,
+	);
+	assert!(res.is_err());
+}
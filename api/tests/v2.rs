@@ -0,0 +1,95 @@
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_util as util;
+extern crate hyper;
+extern crate serde_json;
+
+use api::*;
+use futures::{Future, Stream};
+use hyper::service::Service;
+use hyper::{Body, Request, StatusCode};
+use serde_json::Value;
+use std::sync::Arc;
+
+struct TestHandler;
+
+impl Handler for TestHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		match req.uri().path() {
+			"/v1/json" => response(StatusCode::OK, "{\"height\": 12}"),
+			"/v1/query" => response(StatusCode::OK, req.uri().query().unwrap_or("").to_owned()),
+			"/v1/missing" => response(StatusCode::NOT_FOUND, ""),
+			_ => response(StatusCode::BAD_REQUEST, "invalid id"),
+		}
+	}
+
+	fn post(&self, _req: Request<Body>) -> ResponseFuture {
+		response(StatusCode::OK, "")
+	}
+}
+
+fn call(router: &mut Router, method: &str, uri: &str) -> (StatusCode, String) {
+	let mut req = Request::builder()
+		.method(method)
+		.uri(uri)
+		.body(Body::empty())
+		.unwrap();
+	req.extensions_mut()
+		.insert(RemoteAddr("10.0.0.1:1000".parse().unwrap()));
+	let res = router.call(req).wait().unwrap();
+	let status = res.status();
+	let body = res.into_body().concat2().wait().unwrap();
+	(status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn call_v2(router: &mut Router, method: &str, uri: &str) -> (StatusCode, V2Response<Value>) {
+	let (status, body) = call(router, method, uri);
+	(status, serde_json::from_str(&body).unwrap())
+}
+
+#[test]
+fn test_v2_envelope() {
+	util::init_test_logger();
+	let mut router = Router::new();
+	router.add_route("/v1/**", Arc::new(TestHandler)).unwrap();
+	add_v2_routes(&mut router).unwrap();
+
+	// v1 is untouched
+	assert_eq!(call(&mut router, "GET", "/v1/json"), (StatusCode::OK, "{\"height\": 12}".to_owned()));
+	assert_eq!(call(&mut router, "GET", "/v1/x"), (StatusCode::BAD_REQUEST, "invalid id".to_owned()));
+
+	let (status, res) = call_v2(&mut router, "GET", "/v2/json");
+	assert_eq!(status, StatusCode::OK);
+	assert_eq!(res.data.unwrap()["height"], 12);
+	assert!(res.error.is_none());
+
+	// queries make it through, bodies that aren't json are kept as strings
+	let (_, res) = call_v2(&mut router, "GET", "/v2/query?a=1&b=2");
+	assert_eq!(res.data.unwrap(), "a=1&b=2");
+	let (status, body) = call(&mut router, "POST", "/v2/anything");
+	assert_eq!((status, &body[..]), (StatusCode::OK, "{\"data\":null}"));
+
+	let (status, res) = call_v2(&mut router, "GET", "/v2/x");
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert!(res.data.is_none());
+	assert_eq!(
+		res.error.unwrap(),
+		V2Error {
+			code: "bad_request".to_owned(),
+			message: "Bad Request".to_owned(),
+			detail: Some("invalid id".to_owned()),
+		}
+	);
+	let (status, res) = call_v2(&mut router, "GET", "/v2/missing");
+	assert_eq!(status, StatusCode::NOT_FOUND);
+	let error = res.error.unwrap();
+	assert_eq!(error.code, "not_found");
+	assert!(error.detail.is_none());
+
+	// errors from middleware get the envelope too
+	router.add_middleware(Arc::new(RateLimitMiddleware::new(1)));
+	assert_eq!(call_v2(&mut router, "GET", "/v2/json").0, StatusCode::OK);
+	let (status, res) = call_v2(&mut router, "GET", "/v2/json");
+	assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+	assert_eq!(res.error.unwrap().code, "rate_limited");
+}
@@ -0,0 +1,158 @@
+extern crate grin_api as api;
+extern crate grin_core as core;
+extern crate grin_util as util;
+extern crate serde_json;
+
+use api::*;
+use core::core::{BlockHeader, Transaction};
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+/// Masked, as clients have to
+fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) {
+	let mask = [1u8, 2, 3, 4];
+	let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+	frame.extend_from_slice(&mask);
+	frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+	stream.write_all(&frame).unwrap();
+}
+
+fn read_frame(stream: &mut BufReader<TcpStream>) -> (u8, Vec<u8>) {
+	let mut head = [0u8; 2];
+	stream.read_exact(&mut head).unwrap();
+	assert_eq!(head[1] & 0x80, 0, "server frames aren't masked");
+	let len = match head[1] & 0x7f {
+		126 => {
+			let mut len = [0u8; 2];
+			stream.read_exact(&mut len).unwrap();
+			((len[0] as usize) << 8) | len[1] as usize
+		}
+		127 => panic!("unexpectedly large frame"),
+		len => len as usize,
+	};
+	let mut payload = vec![0u8; len];
+	stream.read_exact(&mut payload).unwrap();
+	(head[0] & 0x0f, payload)
+}
+
+fn read_json(stream: &mut BufReader<TcpStream>) -> Value {
+	let (opcode, payload) = read_frame(stream);
+	assert_eq!(opcode, 1);
+	serde_json::from_slice(&payload).unwrap()
+}
+
+fn connect(addr: &str) -> (TcpStream, BufReader<TcpStream>) {
+	let mut stream = TcpStream::connect(addr).unwrap();
+	write!(
+		stream,
+		"GET /v1/ws HTTP/1.1\r\n\
+		 Host: {}\r\n\
+		 Upgrade: websocket\r\n\
+		 Connection: Upgrade\r\n\
+		 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		 Sec-WebSocket-Version: 13\r\n\r\n",
+		addr
+	).unwrap();
+	let mut reader = BufReader::new(stream.try_clone().unwrap());
+	let mut status = String::new();
+	reader.read_line(&mut status).unwrap();
+	assert!(status.starts_with("HTTP/1.1 101"), "{}", status);
+	let mut accept = None;
+	loop {
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		if line.trim().is_empty() {
+			break;
+		}
+		if line.to_lowercase().starts_with("sec-websocket-accept:") {
+			accept = Some(line[21..].trim().to_owned());
+		}
+	}
+	// the answer to the key in RFC 6455
+	assert_eq!(accept.unwrap(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+	(stream, reader)
+}
+
+#[test]
+fn test_ws_subscriptions() {
+	util::init_test_logger();
+	let events = Arc::new(EventHub::new());
+	let mut router = Router::new();
+	router
+		.add_route(
+			"/v1/ws",
+			Arc::new(WsHandler {
+				events: Arc::downgrade(&events),
+			}),
+		).unwrap();
+	let server_addr = "127.0.0.1:14435";
+	let addr: SocketAddr = server_addr.parse().unwrap();
+	let mut server = ApiServer::new();
+	assert!(server.start(addr, router, None).is_ok());
+	thread::sleep(time::Duration::from_millis(500));
+
+	// plain requests are turned down
+	let res = api::client::get::<Value>(&format!("http://{}/v1/ws", server_addr), None);
+	assert!(res.is_err());
+
+	let mut progress = SyncProgress {
+		status: "header_sync".to_owned(),
+		current: Some(10),
+		total: Some(100),
+	};
+	events.sync_status(progress.clone());
+
+	let (mut stream, mut reader) = connect(server_addr);
+	write_frame(&mut stream, 1, br#"{"subscribe": ["headers", "sync"]}"#);
+	// the last sync progress comes along with the answer
+	let mut msgs = [read_json(&mut reader), read_json(&mut reader)];
+	msgs.sort_by_key(|m| m.get("topic").is_some());
+	assert_eq!(msgs[0]["subscribed"], json_topics(&["headers", "sync"]));
+	assert_eq!(msgs[1]["topic"], "sync");
+	assert_eq!(msgs[1]["data"]["current"], 10);
+
+	// only what's subscribed to is sent
+	events.tx_accepted(&Transaction::empty());
+	let header = BlockHeader::default();
+	events.header_accepted(&header);
+	let msg = read_json(&mut reader);
+	assert_eq!(msg["topic"], "headers");
+	assert_eq!(msg["data"]["height"], header.height);
+
+	write_frame(&mut stream, 1, br#"{"unsubscribe": ["headers"], "subscribe": ["transactions"]}"#);
+	assert_eq!(
+		read_json(&mut reader)["subscribed"],
+		json_topics(&["sync", "transactions"])
+	);
+	events.header_accepted(&header);
+	progress.current = Some(100);
+	events.sync_status(progress);
+	let msg = read_json(&mut reader);
+	assert_eq!(msg["topic"], "sync");
+	assert_eq!(msg["data"]["current"], 100);
+	events.tx_accepted(&Transaction::empty());
+	let msg = read_json(&mut reader);
+	assert_eq!(msg["topic"], "transactions");
+	assert_eq!(msg["data"]["fee"], 0);
+
+	write_frame(&mut stream, 1, br#"{"subscribe": ["mempool"]}"#);
+	assert!(read_json(&mut reader)["error"].is_string());
+
+	write_frame(&mut stream, 9, b"ping");
+	assert_eq!(read_frame(&mut reader), (10, b"ping".to_vec()));
+
+	// closing gets a close back, and the subscription dropped
+	assert_eq!(events.subscriber_count(), 1);
+	write_frame(&mut stream, 8, &[]);
+	assert_eq!(read_frame(&mut reader).0, 8);
+	drop(stream);
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(events.subscriber_count(), 0);
+}
+
+fn json_topics(topics: &[&str]) -> Value {
+	Value::Array(topics.iter().map(|t| Value::String(t.to_string())).collect())
+}
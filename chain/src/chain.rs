@@ -16,27 +16,34 @@
 //! and mostly the chain pipeline.
 
 use std::collections::HashMap;
-use std::fs::File;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use util::RwLock;
+use util::file;
+use util::{MemoryBudget, MemoryConsumer, RwLock};
 
 use lmdb;
 use lru_cache::LruCache;
 
-use core::core::hash::{Hash, Hashed};
+use core::core::hash::{Hash, Hashed, ZERO_HASH};
 use core::core::merkle_proof::MerkleProof;
+use core::core::pmmr::{SegmentIdentifier, SegmentType, TxHashSetSegment};
 use core::core::verifier_cache::VerifierCache;
 use core::core::{Block, BlockHeader, BlockSums, Output, OutputIdentifier, Transaction, TxKernel};
 use core::global;
 use core::pow;
 use error::{Error, ErrorKind};
 use grin_store::Error::NotFoundErr;
+use migration;
 use pipe;
 use store;
 use txhashset;
-use types::{ChainAdapter, NoStatus, Options, Tip, TxHashSetRoots, TxHashsetWriteStatus};
+use types::{
+	ChainAdapter, NoStatus, Options, ReindexStats, Tip, TxHashSetRoots, TxHashsetWriteStatus,
+};
 use util::secp::pedersen::{Commitment, RangeProof};
 
 /// Orphan pool size is limited by MAX_ORPHAN_SIZE
@@ -48,6 +55,16 @@ const MAX_ORPHAN_AGE_SECS: u64 = 300;
 /// Number of recent hashes we keep to de-duplicate block or header sends
 const HASHES_CACHE_SIZE: usize = 200;
 
+/// Segments of the txhashset produced from a single rewind to the archive
+/// header, the one asked for and the ones following it
+const SEGMENT_BATCH: u64 = 16;
+
+/// Segments of the txhashset at the archive header kept for the next peers
+/// asking for them, the cache being emptied once full
+const SEGMENT_CACHE_SIZE: usize = 256;
+
+type SegmentCache = HashMap<(SegmentType, SegmentIdentifier), TxHashSetSegment>;
+
 #[derive(Debug, Clone)]
 struct Orphan {
 	block: Block,
@@ -62,15 +79,19 @@ pub struct OrphanBlockPool {
 	// so we can efficiently identify a child block (ex-orphan) after processing a block
 	height_idx: RwLock<HashMap<u64, Vec<Hash>>>,
 	// accumulated number of evicted block because of MAX_ORPHAN_SIZE limitation
+	// or memory pressure
 	evicted: AtomicUsize,
+	// memory budget the orphans are accounted in
+	memory_budget: Arc<MemoryBudget>,
 }
 
 impl OrphanBlockPool {
-	fn new() -> OrphanBlockPool {
+	fn new(memory_budget: Arc<MemoryBudget>) -> OrphanBlockPool {
 		OrphanBlockPool {
 			orphans: RwLock::new(HashMap::new()),
 			height_idx: RwLock::new(HashMap::new()),
 			evicted: AtomicUsize::new(0),
+			memory_budget,
 		}
 	}
 
@@ -84,42 +105,46 @@ impl OrphanBlockPool {
 	}
 
 	fn add(&self, orphan: Orphan) {
-		let mut orphans = self.orphans.write();
-		let mut height_idx = self.height_idx.write();
 		{
-			let height_hashes = height_idx
-				.entry(orphan.block.header.height)
-				.or_insert(vec![]);
-			height_hashes.push(orphan.block.hash());
-			orphans.insert(orphan.block.hash(), orphan);
-		}
+			let mut orphans = self.orphans.write();
+			let mut height_idx = self.height_idx.write();
+			{
+				let height_hashes = height_idx
+					.entry(orphan.block.header.height)
+					.or_insert(vec![]);
+				height_hashes.push(orphan.block.hash());
+				orphans.insert(orphan.block.hash(), orphan);
+			}
 
-		if orphans.len() > MAX_ORPHAN_SIZE {
-			let old_len = orphans.len();
+			if orphans.len() > MAX_ORPHAN_SIZE {
+				let old_len = orphans.len();
 
-			// evict too old
-			orphans.retain(|_, ref mut x| {
-				x.added.elapsed() < Duration::from_secs(MAX_ORPHAN_AGE_SECS)
-			});
-			// evict too far ahead
-			let mut heights = height_idx.keys().cloned().collect::<Vec<u64>>();
-			heights.sort_unstable();
-			for h in heights.iter().rev() {
-				if let Some(hs) = height_idx.remove(h) {
-					for h in hs {
-						let _ = orphans.remove(&h);
+				// evict too old
+				orphans.retain(|_, ref mut x| {
+					x.added.elapsed() < Duration::from_secs(MAX_ORPHAN_AGE_SECS)
+				});
+				// evict too far ahead
+				let mut heights = height_idx.keys().cloned().collect::<Vec<u64>>();
+				heights.sort_unstable();
+				for h in heights.iter().rev() {
+					if let Some(hs) = height_idx.remove(h) {
+						for h in hs {
+							let _ = orphans.remove(&h);
+						}
+					}
+					if orphans.len() < MAX_ORPHAN_SIZE {
+						break;
 					}
 				}
-				if orphans.len() < MAX_ORPHAN_SIZE {
-					break;
-				}
-			}
-			// cleanup index
-			height_idx.retain(|_, ref mut xs| xs.iter().any(|x| orphans.contains_key(&x)));
+				// cleanup index
+				height_idx.retain(|_, ref mut xs| xs.iter().any(|x| orphans.contains_key(&x)));
 
-			self.evicted
-				.fetch_add(old_len - orphans.len(), Ordering::Relaxed);
+				self.evicted
+					.fetch_add(old_len - orphans.len(), Ordering::Relaxed);
+			}
 		}
+		// large orphans may take the node over its memory budget on their own
+		self.memory_budget.enforce();
 	}
 
 	/// Get an orphan from the pool indexed by the hash of its parent, removing
@@ -138,6 +163,43 @@ impl OrphanBlockPool {
 	}
 }
 
+impl MemoryConsumer for OrphanBlockPool {
+	fn name(&self) -> &'static str {
+		"orphans"
+	}
+
+	fn memory_used(&self) -> usize {
+		let orphans = self.orphans.read();
+		orphans.values().map(|x| x.block.memory_size()).sum()
+	}
+
+	// Evicts the orphans furthest ahead first, as when the pool is full
+	fn shrink_to(&self, target: usize) -> usize {
+		let mut orphans = self.orphans.write();
+		let mut height_idx = self.height_idx.write();
+		let mut used: usize = orphans.values().map(|x| x.block.memory_size()).sum();
+		let mut freed = 0;
+
+		let mut heights = height_idx.keys().cloned().collect::<Vec<u64>>();
+		heights.sort_unstable();
+		for h in heights.iter().rev() {
+			if used <= target {
+				break;
+			}
+			if let Some(hs) = height_idx.remove(h) {
+				for h in hs {
+					if let Some(x) = orphans.remove(&h) {
+						used -= x.block.memory_size();
+						freed += x.block.memory_size();
+						self.evicted.fetch_add(1, Ordering::Relaxed);
+					}
+				}
+			}
+		}
+		freed
+	}
+}
+
 /// Facade to the blockchain block processing pipeline and storage. Provides
 /// the current view of the TxHashSet according to the chain state. Also
 /// maintains locking for the pipeline to avoid conflicting processing.
@@ -147,6 +209,14 @@ pub struct Chain {
 	adapter: Arc<ChainAdapter>,
 	orphans: Arc<OrphanBlockPool>,
 	txhashset: Arc<RwLock<txhashset::TxHashSet>>,
+	// Txhashset being rebuilt from segments, during a segmented fast sync
+	segment_sync: Arc<RwLock<Option<txhashset::SegmentedTxHashSet>>>,
+	// Txhashset zip prepared ahead of requests, the hash of its header and
+	// its id
+	prepared_zip: Arc<RwLock<Option<(Hash, u32)>>>,
+	// Segments of the txhashset at the archive header served lately, with
+	// the hash of that header
+	segment_cache: Arc<RwLock<(Hash, SegmentCache)>>,
 	// Recently processed blocks to avoid double-processing
 	block_hashes_cache: Arc<RwLock<LruCache<Hash, bool>>>,
 	verifier_cache: Arc<RwLock<VerifierCache>>,
@@ -154,6 +224,8 @@ pub struct Chain {
 	pow_verifier: fn(&BlockHeader, u8) -> Result<(), pow::Error>,
 	archive_mode: bool,
 	genesis: BlockHeader,
+	// Set once stopped, nothing gets written anymore
+	stopped: AtomicBool,
 }
 
 unsafe impl Sync for Chain {}
@@ -171,10 +243,15 @@ impl Chain {
 		pow_verifier: fn(&BlockHeader, u8) -> Result<(), pow::Error>,
 		verifier_cache: Arc<RwLock<VerifierCache>>,
 		archive_mode: bool,
+		memory_budget: Arc<MemoryBudget>,
 	) -> Result<Chain, Error> {
 		let chain_store = store::ChainStore::new(db_env)?;
+		migration::check_version(&chain_store, migration::DB_VERSION)?;
 
 		let store = Arc::new(chain_store);
+		let orphans = Arc::new(OrphanBlockPool::new(memory_budget.clone()));
+		memory_budget.register(Arc::downgrade(&orphans));
+		memory_budget.register(Arc::downgrade(&store.header_cache()));
 
 		// open the txhashset, creating a new one if necessary
 		let mut txhashset = txhashset::TxHashSet::open(db_root.clone(), store.clone(), None)?;
@@ -215,13 +292,17 @@ impl Chain {
 			db_root: db_root,
 			store: store,
 			adapter: adapter,
-			orphans: Arc::new(OrphanBlockPool::new()),
+			orphans,
 			txhashset: Arc::new(RwLock::new(txhashset)),
+			segment_sync: Arc::new(RwLock::new(None)),
+			prepared_zip: Arc::new(RwLock::new(None)),
+			segment_cache: Arc::new(RwLock::new((ZERO_HASH, HashMap::new()))),
 			pow_verifier,
 			verifier_cache,
 			block_hashes_cache: Arc::new(RwLock::new(LruCache::new(HASHES_CACHE_SIZE))),
 			archive_mode,
 			genesis: genesis.header.clone(),
+			stopped: AtomicBool::new(false),
 		})
 	}
 
@@ -301,6 +382,7 @@ impl Chain {
 					);
 					Err(ErrorKind::Unfit(msg.clone()).into())
 				}
+				ErrorKind::Stopped => Err(ErrorKind::Stopped.into()),
 				_ => {
 					info!(
 						"Rejected block {} at {}: {:?}",
@@ -349,6 +431,9 @@ impl Chain {
 		batch: store::Batch<'a>,
 		txhashset: &'a mut txhashset::TxHashSet,
 	) -> Result<pipe::BlockContext<'a>, Error> {
+		if self.is_stopped() {
+			return Err(ErrorKind::Stopped.into());
+		}
 		Ok(pipe::BlockContext {
 			opts,
 			pow_verifier: self.pow_verifier,
@@ -465,6 +550,21 @@ impl Chain {
 		})
 	}
 
+	/// Verify the outputs the tx spends got at least the provided number of
+	/// confirmations, as of the next block.
+	pub fn verify_input_confirmations(
+		&self,
+		tx: &Transaction,
+		confirmations: u64,
+	) -> Result<(), Error> {
+		let height = self.next_block_height()?;
+		let mut txhashset = self.txhashset.write();
+		txhashset::extending_readonly(&mut txhashset, |extension| {
+			extension.verify_input_confirmations(&tx.inputs(), height, confirmations)?;
+			Ok(())
+		})
+	}
+
 	/// Verify that the tx has a lock_height that is less than or equal to
 	/// the height of the next block.
 	pub fn verify_tx_lock_height(&self, tx: &Transaction) -> Result<(), Error> {
@@ -593,6 +693,32 @@ impl Chain {
 		Ok(merkle_proof)
 	}
 
+	/// Merkle proofs of the provided outputs against the output root of the
+	/// head header, along with their position in the output MMR. Outputs that
+	/// aren't unspent are left out.
+	pub fn get_merkle_proofs(
+		&self,
+		outputs: &[OutputIdentifier],
+	) -> Result<(BlockHeader, Vec<(OutputIdentifier, u64, MerkleProof)>), Error> {
+		let mut txhashset = self.txhashset.write();
+
+		let mut unspent = vec![];
+		for output in outputs {
+			if let Ok((_, pos)) = txhashset.is_unspent(output) {
+				unspent.push((output.clone(), pos));
+			}
+		}
+		txhashset::extending_readonly(&mut txhashset, |extension| {
+			let header = extension.batch.head_header()?;
+			let mut proofs = vec![];
+			for (output, pos) in unspent {
+				let proof = extension.merkle_proof(&output)?;
+				proofs.push((output, pos, proof));
+			}
+			Ok((header, proofs))
+		})
+	}
+
 	/// Return a merkle proof valid for the current output pmmr state at the
 	/// given pos
 	pub fn get_merkle_proof_for_pos(&self, commit: Commitment) -> Result<MerkleProof, String> {
@@ -606,32 +732,83 @@ impl Chain {
 		txhashset.roots()
 	}
 
-	/// Provides a reading view into the current txhashset state as well as
-	/// the required indexes for a consumer to rewind to a consistent state
+	/// Provides the location of a zip of the current txhashset state as well
+	/// as the required indexes for a consumer to rewind to a consistent state
 	/// at the provided block hash.
-	pub fn txhashset_read(&self, h: Hash) -> Result<(u64, u64, File), Error> {
+	pub fn txhashset_read(&self, h: Hash) -> Result<(u64, u64, PathBuf), Error> {
 		// now we want to rewind the txhashset extension and
 		// sync a "rewound" copy of the leaf_set files to disk
 		// so we can send these across as part of the zip file.
 		// The fast sync client does *not* have the necessary data
 		// to rewind after receiving the txhashset zip.
 		let header = self.get_block_header(&h)?;
-		{
-			let mut txhashset = self.txhashset.write();
-			txhashset::extending_readonly(&mut txhashset, |extension| {
-				extension.rewind(&header)?;
-				extension.snapshot()?;
-				Ok(())
-			})?;
+
+		// at the archive header, served from the zip prepared for it, built
+		// now if it wasn't yet
+		if header.height > 0 && h == self.txhashset_archive_header()?.hash() {
+			let zip = self.prepared_zip_at(&header)?;
+			return Ok((header.output_mmr_size, header.kernel_mmr_size, zip));
 		}
 
-		// prepares the zip and return the corresponding Read
-		let txhashset_reader = txhashset::zip_read(self.db_root.clone(), &header, None)?;
-		Ok((
-			header.output_mmr_size,
-			header.kernel_mmr_size,
-			txhashset_reader,
-		))
+		self.snapshot_txhashset(&header)?;
+
+		// prepares the zip and return its location
+		let zip = txhashset::zip_file(self.db_root.clone(), &header, None)?;
+		Ok((header.output_mmr_size, header.kernel_mmr_size, zip))
+	}
+
+	// Rewinds the txhashset to the header and writes a "rewound" copy of the
+	// leaf_set files to disk, to send along in the zip
+	fn snapshot_txhashset(&self, header: &BlockHeader) -> Result<(), Error> {
+		let mut txhashset = self.txhashset.write();
+		txhashset::extending_readonly(&mut txhashset, |extension| {
+			extension.rewind(header)?;
+			extension.snapshot()?;
+			Ok(())
+		})
+	}
+
+	/// Builds ahead of time the txhashset zip a peer fast syncing now would
+	/// ask for, at the txhashset archive header, so serving it doesn't wait
+	/// on zipping the whole txhashset. Built again once the archive header
+	/// moves, the previously prepared zip getting removed.
+	pub fn prepare_txhashset_zip(&self) -> Result<(), Error> {
+		let header = self.txhashset_archive_header()?;
+		if header.height == 0 {
+			return Ok(());
+		}
+		self.prepared_zip_at(&header)?;
+		Ok(())
+	}
+
+	// The zip prepared for the archive header, built if the one prepared is
+	// for another header
+	fn prepared_zip_at(&self, header: &BlockHeader) -> Result<PathBuf, Error> {
+		let h = header.hash();
+		// held while building, not to build it twice
+		let mut prepared = self.prepared_zip.write();
+		let previous = *prepared;
+		// two ids taking turns, not to overwrite a zip being sent
+		let id = match previous {
+			Some((prepared_h, id)) if prepared_h == h => {
+				return Ok(txhashset::zip_path(&self.db_root, id))
+			}
+			Some((_, id)) if id == txhashset::PREPARED_ZIP_ID => id + 1,
+			_ => txhashset::PREPARED_ZIP_ID,
+		};
+
+		self.snapshot_txhashset(header)?;
+		let zip = txhashset::zip_file(self.db_root.clone(), header, Some(id))?;
+		*prepared = Some((h, id));
+		debug!(
+			"prepare_txhashset_zip: prepared zip for {} at {}",
+			h, header.height
+		);
+
+		if let Some((_, old_id)) = previous {
+			let _ = fs::remove_file(txhashset::zip_path(&self.db_root, old_id));
+		}
+		Ok(zip)
 	}
 
 	// Special handling to make sure the whole kernel set matches each of its
@@ -771,14 +948,15 @@ impl Chain {
 			batch.build_by_height_index(&header, true)?;
 		}
 
-		// Commit all the changes to the db.
-		batch.commit()?;
-
-		debug!("txhashset_write: finished committing the batch (head etc.)");
-
-		// Replace the chain txhashset with the newly built one.
+		// Commit all the changes to the db and replace the chain txhashset with
+		// the newly built one, under the lock so a stop waits for both.
 		{
 			let mut txhashset_ref = self.txhashset.write();
+			if self.is_stopped() {
+				return Err(ErrorKind::Stopped.into());
+			}
+			batch.commit()?;
+			debug!("txhashset_write: finished committing the batch (head etc.)");
 			*txhashset_ref = txhashset;
 		}
 
@@ -791,6 +969,166 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Header the txhashset is archived at for peers fast syncing, as a zip
+	/// or in segments
+	pub fn txhashset_archive_header(&self) -> Result<BlockHeader, Error> {
+		let head = self.head()?;
+		self.get_header_by_height(global::txhashset_archive_height(head.height))
+	}
+
+	/// Provides a segment of the output, rangeproof or kernel MMR as of the
+	/// block with the provided hash, with the proofs needed to check it
+	/// against that block's header. Only served at the txhashset archive
+	/// header, the segments following the one asked for being produced from
+	/// the same rewind and kept for the next requests.
+	pub fn txhashset_segment(
+		&self,
+		h: Hash,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Result<TxHashSetSegment, Error> {
+		let header = self.txhashset_archive_header()?;
+		if header.hash() != h {
+			return Err(ErrorKind::TxHashSetErr(format!(
+				"segments served at {}, not {}",
+				header.hash(),
+				h
+			)).into());
+		}
+		{
+			let cache = self.segment_cache.read();
+			if cache.0 == h {
+				if let Some(segment) = cache.1.get(&(segment_type, id)) {
+					return Ok(segment.clone());
+				}
+			}
+		}
+
+		let segments = {
+			let mut txhashset = self.txhashset.write();
+			txhashset::extending_readonly(&mut txhashset, |extension| {
+				extension.rewind(&header)?;
+				let mut segments = vec![(id, extension.segment(segment_type, id)?)];
+				for idx in id.idx + 1..id.idx + SEGMENT_BATCH {
+					let next = SegmentIdentifier {
+						height: id.height,
+						idx,
+					};
+					match extension.segment(segment_type, next) {
+						Ok(segment) => segments.push((next, segment)),
+						Err(_) => break,
+					}
+				}
+				Ok(segments)
+			})?
+		};
+		let segment = segments[0].1.clone();
+		let mut cache = self.segment_cache.write();
+		if cache.0 != h || cache.1.len() + segments.len() > SEGMENT_CACHE_SIZE {
+			*cache = (h, HashMap::new());
+		}
+		for (id, segment) in segments {
+			cache.1.insert((segment_type, id), segment);
+		}
+		Ok(segment)
+	}
+
+	/// Starts rebuilding the txhashset as of the provided block from
+	/// segments, unless already doing so. A rebuild towards another block is
+	/// abandoned.
+	pub fn segment_sync_begin(&self, h: Hash) -> Result<(), Error> {
+		let mut segment_sync = self.segment_sync.write();
+		if let Some(ref segments) = *segment_sync {
+			if segments.header().hash() == h {
+				return Ok(());
+			}
+		}
+		if let Some(segments) = segment_sync.take() {
+			segments.discard()?;
+		}
+		let header = self.get_block_header(&h)?;
+		*segment_sync = Some(txhashset::SegmentedTxHashSet::new(&self.db_root, &header)?);
+		Ok(())
+	}
+
+	/// Header of the block the txhashset is being rebuilt at from segments
+	pub fn segment_sync_header(&self) -> Option<BlockHeader> {
+		self.segment_sync
+			.read()
+			.as_ref()
+			.map(|segments| segments.header().clone())
+	}
+
+	/// Up to max of the segments still needed to rebuild the txhashset
+	pub fn segment_sync_missing(&self, max: usize) -> Vec<(SegmentType, SegmentIdentifier)> {
+		match *self.segment_sync.read() {
+			Some(ref segments) => segments.missing(max),
+			None => vec![],
+		}
+	}
+
+	/// Whether the provided segment was received already
+	pub fn segment_sync_has(&self, segment_type: SegmentType, id: SegmentIdentifier) -> bool {
+		match *self.segment_sync.read() {
+			Some(ref segments) => segments.is_received(segment_type, id),
+			None => false,
+		}
+	}
+
+	/// Segments received and in total, along with the approximate bytes
+	/// received, for the txhashset being rebuilt from segments if any.
+	pub fn segment_sync_progress(&self) -> Option<(u64, u64, u64)> {
+		self.segment_sync.read().as_ref().map(|segments| {
+			let (received, total) = segments.progress();
+			(received, total, segments.bytes_received())
+		})
+	}
+
+	/// Adds a segment received for the txhashset of the provided block.
+	/// Returns whether it was needed, segments for another block or we
+	/// already have being ignored.
+	pub fn segment_sync_apply(&self, h: Hash, segment: TxHashSetSegment) -> Result<bool, Error> {
+		match *self.segment_sync.write() {
+			Some(ref mut segments) => {
+				if segments.header().hash() != h {
+					return Ok(false);
+				}
+				segments.apply(segment)
+			}
+			None => Ok(false),
+		}
+	}
+
+	/// Whether all the segments for the txhashset being rebuilt are in
+	pub fn segment_sync_complete(&self) -> bool {
+		match *self.segment_sync.read() {
+			Some(ref segments) => segments.is_complete(),
+			None => false,
+		}
+	}
+
+	/// Installs the txhashset rebuilt from segments, validating it exactly
+	/// like a txhashset zip received from a single peer.
+	pub fn segment_sync_finish(&self, status: &TxHashsetWriteStatus) -> Result<(), Error> {
+		let segments = self
+			.segment_sync
+			.write()
+			.take()
+			.ok_or_else(|| ErrorKind::TxHashSetErr("no segment sync going on".to_owned()))?;
+		let h = segments.header().hash();
+		let zip = segments.zip()?;
+		self.txhashset_write(h, zip, status)
+	}
+
+	/// Abandons the txhashset rebuild from segments, if any
+	pub fn segment_sync_reset(&self) {
+		if let Some(segments) = self.segment_sync.write().take() {
+			if let Err(e) = segments.discard() {
+				warn!("segment_sync_reset: failed to clear segments: {:?}", e);
+			}
+		}
+	}
+
 	fn compact_txhashset(&self) -> Result<(), Error> {
 		debug!("Starting blockchain compaction.");
 		{
@@ -864,6 +1202,22 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Stops the chain, waiting for the block or txhashset being processed,
+	/// if any, to be committed. Everything committed gets flushed to disk and
+	/// any later processing is refused.
+	pub fn stop(&self) -> Result<(), Error> {
+		let _txhashset = self.txhashset.write();
+		self.stopped.store(true, Ordering::Relaxed);
+		self.store.sync()?;
+		debug!("chain: stopped, store flushed to disk");
+		Ok(())
+	}
+
+	/// Whether the chain was stopped
+	pub fn is_stopped(&self) -> bool {
+		self.stopped.load(Ordering::Relaxed)
+	}
+
 	/// Triggers chain compaction.
 	///
 	/// * compacts the txhashset based on current prune_list
@@ -879,6 +1233,56 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Writes a consistent copy of the chain database and of the txhashset
+	/// files under `dest` while the node runs, returning the head copied.
+	/// Blocks only wait for the txhashset files to be copied, the database
+	/// being written out afterwards from a snapshot taken along with them.
+	pub fn backup(&self, dest: &Path) -> Result<Tip, Error> {
+		let (head, snapshot) = {
+			// nothing processed or compacted meanwhile, the database and the
+			// txhashset files have to match
+			let _txhashset = self.txhashset.read();
+			let snapshot = self.store.snapshot()?;
+			let head = self.head()?;
+			txhashset::copy_files(&self.db_root, dest)?;
+			(head, snapshot)
+		};
+		store::ChainStore::write_snapshot(snapshot, dest)?;
+		Ok(head)
+	}
+
+	/// Drops the indices derived from the blocks and the txhashset, output
+	/// positions, header heights and block input bitmaps, and builds them
+	/// again from that data. A way out of a corrupted index short of a full
+	/// resync. Block input bitmaps get built again as blocks are looked at.
+	pub fn reindex(&self) -> Result<ReindexStats, Error> {
+		let mut txhashset = self.txhashset.write();
+		let mut batch = self.store.batch()?;
+		let dropped = self.store.drop_derived_indices(&batch)?;
+
+		// the heights, down from the head to the genesis
+		let head = batch.head_header()?;
+		batch.build_by_height_index(&head, true)?;
+		batch.save_header_height(&self.genesis)?;
+
+		let output_positions = txhashset::extending(&mut txhashset, &mut batch, |extension| {
+			extension.rebuild_index()
+		})?;
+		batch.commit()?;
+
+		info!(
+			"reindex: dropped {} entries, indexed {} outputs and {} headers",
+			dropped,
+			output_positions,
+			head.height + 1
+		);
+		Ok(ReindexStats {
+			dropped,
+			output_positions,
+			header_heights: head.height + 1,
+		})
+	}
+
 	/// returns the last n nodes inserted into the output sum tree
 	pub fn get_last_n_output(&self, distance: u64) -> Vec<(Hash, OutputIdentifier)> {
 		let mut txhashset = self.txhashset.write();
@@ -928,12 +1332,23 @@ impl Chain {
 		self.orphans.len()
 	}
 
+	/// Checks the chain data directory can still be written to, so the disk
+	/// isn't full or gone read-only
+	pub fn check_writable(&self) -> io::Result<()> {
+		let probe = Path::new(&self.db_root).join(".write_probe");
+		fs::write(&probe, b"ok")?;
+		fs::remove_file(&probe)
+	}
+
+	/// Disk space taken by the chain data directory, in bytes
+	pub fn disk_usage(&self) -> io::Result<u64> {
+		file::dir_size(Path::new(&self.db_root))
+	}
+
 	/// Reset header_head and sync_head to head of current body chain
 	pub fn reset_head(&self) -> Result<(), Error> {
-		let batch = self.store.batch()?;
-		batch.reset_head()?;
-		batch.commit()?;
-		Ok(())
+		self.store
+			.atomic(|batch| batch.reset_head().map_err(From::from))
 	}
 
 	/// Tip (head) of the block chain.
@@ -1002,7 +1417,39 @@ impl Chain {
 			let (_, pos) = txhashset.is_unspent(output_ref)?;
 			pos
 		};
+		self.header_for_mmr_pos(pos, |h| h.output_mmr_size)
+	}
 
+	/// Finds the kernels with the provided excesses in the blocks of the
+	/// current chain from the provided height up, with the header of the
+	/// block each is in. Kernels not found are left out.
+	pub fn get_kernels(
+		&self,
+		excesses: &[Commitment],
+		min_height: u64,
+	) -> Result<HashMap<Commitment, (TxKernel, BlockHeader)>, Error> {
+		let min_pos = match min_height {
+			0 => 0,
+			h => self.get_header_by_height(h - 1)?.kernel_mmr_size,
+		};
+		let found = {
+			let txhashset = self.txhashset.read();
+			txhashset.find_kernels(excesses, min_pos)
+		};
+		let mut kernels = HashMap::new();
+		for (excess, (kernel, pos)) in found {
+			let header = self.header_for_mmr_pos(pos, |h| h.kernel_mmr_size)?;
+			kernels.insert(excess, (kernel, header));
+		}
+		Ok(kernels)
+	}
+
+	// Binary search of the block that added the provided position to an MMR,
+	// given the size of that MMR at each header
+	fn header_for_mmr_pos<F>(&self, pos: u64, mmr_size: F) -> Result<BlockHeader, Error>
+	where
+		F: Fn(&BlockHeader) -> u64,
+	{
 		let mut min = 1;
 		let mut max = {
 			let head = self.head()?;
@@ -1013,12 +1460,12 @@ impl Chain {
 			let search_height = max - (max - min) / 2;
 			let h = self.get_header_by_height(search_height)?;
 			let h_prev = self.get_header_by_height(search_height - 1)?;
-			if pos > h.output_mmr_size {
+			if pos > mmr_size(&h) {
 				min = search_height;
-			} else if pos < h_prev.output_mmr_size {
+			} else if pos < mmr_size(&h_prev) {
 				max = search_height;
 			} else {
-				if pos == h_prev.output_mmr_size {
+				if pos == mmr_size(&h_prev) {
 					return Ok(h_prev);
 				}
 				return Ok(h);
@@ -1061,10 +1508,8 @@ impl Chain {
 
 	/// Reset sync_head to the provided head.
 	pub fn reset_sync_head(&self, head: &Tip) -> Result<(), Error> {
-		let batch = self.store.batch()?;
-		batch.save_sync_head(head)?;
-		batch.commit()?;
-		Ok(())
+		self.store
+			.atomic(|batch| batch.save_sync_head(head).map_err(From::from))
 	}
 }
 
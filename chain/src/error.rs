@@ -87,6 +87,9 @@ pub enum ErrorKind {
 	/// Attempt to spend a coinbase output before it sufficiently matures.
 	#[fail(display = "Attempt to spend immature coinbase")]
 	ImmatureCoinbase,
+	/// Attempt to spend an output with fewer confirmations than asked for
+	#[fail(display = "Attempt to spend an output with too few confirmations")]
+	ImmatureInput,
 	/// Error validating a Merkle proof (coinbase output)
 	#[fail(display = "Error validating merkle proof")]
 	MerkleProof,
@@ -102,6 +105,9 @@ pub enum ErrorKind {
 	/// We've been provided a bad txhashset
 	#[fail(display = "Invalid TxHashSet: {}", _0)]
 	InvalidTxHashSet(String),
+	/// Txhashset segment not matching the header it was requested at
+	#[fail(display = "Invalid Segment: {}", _0)]
+	InvalidSegment(String),
 	/// Internal issue when trying to save or load data from store
 	#[fail(display = "Store Error: {}", _1)]
 	StoreErr(store::Error, String),
@@ -123,6 +129,13 @@ pub enum ErrorKind {
 	/// Error from underlying tx handling
 	#[fail(display = "Transaction Error")]
 	Transaction(transaction::Error),
+	/// The chain was stopped and doesn't process anything anymore
+	#[fail(display = "Chain Stopped")]
+	Stopped,
+	/// The database is in a format that can't be read as is, either older
+	/// and not migrated yet or newer than supported
+	#[fail(display = "DB Version Error: {}", _0)]
+	DbVersion(String),
 	/// Anything else
 	#[fail(display = "Other Error: {}", _0)]
 	Other(String),
@@ -173,6 +186,8 @@ impl Error {
 			| ErrorKind::SerErr(_)
 			| ErrorKind::TxHashSetErr(_)
 			| ErrorKind::GenesisBlockRequired
+			| ErrorKind::Stopped
+			| ErrorKind::DbVersion(_)
 			| ErrorKind::Other(_) => false,
 			_ => true,
 		}
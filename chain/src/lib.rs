@@ -43,6 +43,7 @@ extern crate grin_util as util;
 
 mod chain;
 mod error;
+pub mod migration;
 pub mod pipe;
 pub mod store;
 pub mod txhashset;
@@ -53,4 +54,4 @@ pub mod types;
 pub use chain::{Chain, MAX_ORPHAN_SIZE};
 pub use error::{Error, ErrorKind};
 pub use store::ChainStore;
-pub use types::{ChainAdapter, Options, Tip, TxHashsetWriteStatus};
+pub use types::{ChainAdapter, Options, ReindexStats, Tip, TxHashsetWriteStatus};
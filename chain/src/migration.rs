@@ -0,0 +1,212 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Versioned migrations of the chain database. The database records the
+//! version of the format it's in, every change of the format comes with a
+//! migration rewriting the data of the previous version, run on startup
+//! rather than having to sync again from scratch.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::prelude::Utc;
+use lmdb;
+
+use error::{Error, ErrorKind};
+use grin_store;
+use store::{Batch, ChainStore};
+use txhashset;
+
+/// Version of the database format read and written
+pub const DB_VERSION: u32 = 1;
+
+// version of the databases created before versions were recorded
+const UNVERSIONED_DB_VERSION: u32 = 1;
+
+/// A change of the database format, bringing data of the previous version to
+/// its own
+pub trait Migration {
+	/// Version the database is in once migrated
+	fn version(&self) -> u32;
+
+	/// What changes, for the logs
+	fn description(&self) -> &str;
+
+	/// Rewrites the data in the provided batch, calling back with the count
+	/// of entries done and to do as it goes
+	fn migrate(
+		&self,
+		store: &ChainStore,
+		batch: &Batch,
+		progress: &mut FnMut(u64, u64),
+	) -> Result<(), Error>;
+}
+
+/// Migrations of every change of the format, oldest first. The last one is
+/// to `DB_VERSION`.
+pub fn migrations() -> Vec<Box<Migration>> {
+	vec![]
+}
+
+/// What a migration of the database went through
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MigrationReport {
+	/// Version the database was in
+	pub from_version: u32,
+	/// Version the database is now in
+	pub to_version: u32,
+	/// Versions of the migrations run
+	pub applied: Vec<u32>,
+	/// Where the data was backed up to before migrating, if it was
+	pub backup: Option<String>,
+}
+
+/// Migrates the chain database under `db_root` to the current version if
+/// needed, backing the chain data up first when asked to. The backup is
+/// laid out as a node backup and written next to `db_root`.
+pub fn migrate_db(
+	db_root: &str,
+	db_env: Arc<lmdb::Environment>,
+	backup: bool,
+) -> Result<MigrationReport, Error> {
+	let store = ChainStore::new(db_env)?;
+	let backup_dir = if backup && needs_migration(&store, DB_VERSION)? {
+		let version = version(&store)?;
+		let dest = PathBuf::from(format!(
+			"{}.pre-migration-v{}-{}",
+			db_root.trim_right_matches('/'),
+			version,
+			Utc::now().format("%Y%m%d%H%M%S")
+		));
+		let db_dest = dest.join(grin_store::DEFAULT_ENV_NAME);
+		fs::create_dir_all(&db_dest)?;
+		store.copy_to(&db_dest)?;
+		txhashset::copy_files(db_root, &dest)?;
+		info!(
+			"Backed up the chain data at version {} to {}",
+			version,
+			dest.display()
+		);
+		Some(dest)
+	} else {
+		None
+	};
+	migrate(&store, &migrations(), DB_VERSION, backup_dir.as_ref())
+}
+
+/// Runs the provided migrations the database isn't in the version of yet,
+/// up to the target version. Each is committed along with its version, so
+/// an interrupted migration picks up from the last one done. A new database
+/// is simply recorded at the target version.
+pub fn migrate<P: AsRef<Path>>(
+	store: &ChainStore,
+	migrations: &[Box<Migration>],
+	target: u32,
+	backup: Option<P>,
+) -> Result<MigrationReport, Error> {
+	let from_version = match store.db_version()? {
+		Some(v) => v,
+		None if is_new(store)? => {
+			store.atomic(|batch| batch.save_db_version(target))?;
+			target
+		}
+		None => UNVERSIONED_DB_VERSION,
+	};
+	if from_version > target {
+		return Err(ErrorKind::DbVersion(format!(
+			"database in version {}, only up to {} supported",
+			from_version, target
+		)).into());
+	}
+
+	let mut applied = vec![];
+	let mut version = from_version;
+	while version < target {
+		let migration = migrations
+			.iter()
+			.find(|m| m.version() == version + 1)
+			.ok_or_else(|| {
+				ErrorKind::DbVersion(format!("no migration to version {}", version + 1))
+			})?;
+		info!(
+			"Migrating the chain database to version {}: {}",
+			migration.version(),
+			migration.description()
+		);
+
+		let batch = store.batch()?;
+		let mut last_pct = 0;
+		migration.migrate(store, &batch, &mut |done, total| {
+			let pct = if total == 0 { 100 } else { done * 100 / total };
+			if pct >= last_pct + 10 {
+				last_pct = pct;
+				info!(
+					"Migrating the chain database to version {}: {}/{} ({}%)",
+					version + 1,
+					done,
+					total,
+					pct
+				);
+			}
+		})?;
+		batch.save_db_version(migration.version())?;
+		batch.commit()?;
+
+		version = migration.version();
+		applied.push(version);
+	}
+	if !applied.is_empty() {
+		info!(
+			"Migrated the chain database from version {} to {}",
+			from_version, version
+		);
+	}
+
+	Ok(MigrationReport {
+		from_version,
+		to_version: version,
+		applied,
+		backup: backup.map(|p| p.as_ref().display().to_string()),
+	})
+}
+
+/// Checks the database is in the provided version, recording it for a new
+/// database
+pub fn check_version(store: &ChainStore, version: u32) -> Result<(), Error> {
+	if needs_migration(store, version)? {
+		return Err(ErrorKind::DbVersion(format!(
+			"database in version {}, needs migrating to {}",
+			self::version(store)?,
+			version
+		)).into());
+	}
+	migrate(store, &[], version, None::<&Path>).map(|_| ())
+}
+
+fn needs_migration(store: &ChainStore, target: u32) -> Result<bool, Error> {
+	Ok(!is_new(store)? && version(store)? < target)
+}
+
+fn version(store: &ChainStore) -> Result<u32, Error> {
+	Ok(store.db_version()?.unwrap_or(UNVERSIONED_DB_VERSION))
+}
+
+fn is_new(store: &ChainStore) -> Result<bool, Error> {
+	match store.head() {
+		Ok(_) => Ok(false),
+		Err(grin_store::Error::NotFoundErr(_)) => Ok(true),
+		Err(e) => Err(e.into()),
+	}
+}
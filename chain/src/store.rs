@@ -14,8 +14,10 @@
 
 //! Implements storage primitives required by the chain
 
+use std::ops::Deref;
+use std::path::Path;
 use std::sync::Arc;
-use util::RwLock;
+use util::{MemoryConsumer, RwLock};
 
 use croaring::Bitmap;
 use lmdb;
@@ -33,6 +35,9 @@ use types::Tip;
 
 const STORE_SUBPATH: &'static str = "chain";
 
+/// Entries written per batch when writing a snapshot out
+const SNAPSHOT_BATCH_SIZE: usize = 10_000;
+
 const BLOCK_HEADER_PREFIX: u8 = 'h' as u8;
 const BLOCK_PREFIX: u8 = 'b' as u8;
 const HEAD_PREFIX: u8 = 'H' as u8;
@@ -42,11 +47,50 @@ const HEADER_HEIGHT_PREFIX: u8 = '8' as u8;
 const COMMIT_POS_PREFIX: u8 = 'c' as u8;
 const BLOCK_INPUT_BITMAP_PREFIX: u8 = 'B' as u8;
 const BLOCK_SUMS_PREFIX: u8 = 'M' as u8;
+const DB_VERSION_PREFIX: u8 = 'V' as u8;
+
+/// Headers recently read or saved, least recently used evicted first when
+/// full or short of memory
+pub struct HeaderCache(RwLock<LruCache<Hash, BlockHeader>>);
+
+impl Deref for HeaderCache {
+	type Target = RwLock<LruCache<Hash, BlockHeader>>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl MemoryConsumer for HeaderCache {
+	fn name(&self) -> &'static str {
+		"header_cache"
+	}
+
+	fn memory_used(&self) -> usize {
+		self.0.read().iter().map(|(_, h)| h.memory_size()).sum()
+	}
+
+	fn shrink_to(&self, target: usize) -> usize {
+		let mut cache = self.0.write();
+		let mut used: usize = cache.iter().map(|(_, h)| h.memory_size()).sum();
+		let mut freed = 0;
+		while used > target {
+			match cache.remove_lru() {
+				Some((_, h)) => {
+					used -= h.memory_size();
+					freed += h.memory_size();
+				}
+				None => break,
+			}
+		}
+		freed
+	}
+}
 
 /// All chain-related database operations
 pub struct ChainStore {
 	db: store::Store,
-	header_cache: Arc<RwLock<LruCache<Hash, BlockHeader>>>,
+	header_cache: Arc<HeaderCache>,
 	block_input_bitmap_cache: Arc<RwLock<LruCache<Hash, Vec<u8>>>>,
 	block_sums_cache: Arc<RwLock<LruCache<Hash, BlockSums>>>,
 }
@@ -57,11 +101,43 @@ impl ChainStore {
 		let db = store::Store::open(db_env, STORE_SUBPATH);
 		Ok(ChainStore {
 			db,
-			header_cache: Arc::new(RwLock::new(LruCache::new(1_000))),
+			header_cache: Arc::new(HeaderCache(RwLock::new(LruCache::new(1_000)))),
 			block_input_bitmap_cache: Arc::new(RwLock::new(LruCache::new(1_000))),
 			block_sums_cache: Arc::new(RwLock::new(LruCache::new(1_000))),
 		})
 	}
+
+	/// The cache of recent headers, for its memory to be accounted for
+	pub fn header_cache(&self) -> Arc<HeaderCache> {
+		self.header_cache.clone()
+	}
+
+	/// Flushes all committed data to disk
+	pub fn sync(&self) -> Result<(), Error> {
+		self.db.sync()
+	}
+
+	/// The whole database as it is now, read as it's iterated over
+	pub fn snapshot(&self) -> Result<store::RawIterator, Error> {
+		self.db.snapshot()
+	}
+
+	/// Writes a snapshot out as a new chain database under the provided
+	/// directory, in batches so it doesn't have to fit in a single one.
+	pub fn write_snapshot(snapshot: store::RawIterator, dest: &Path) -> Result<(), Error> {
+		let env = Arc::new(store::new_env(dest.to_string_lossy().into_owned()));
+		let db = store::Store::open(env, STORE_SUBPATH);
+		let mut batch = db.batch()?;
+		for (n, (k, v)) in snapshot.enumerate() {
+			batch.put(&k, v)?;
+			if (n + 1) % SNAPSHOT_BATCH_SIZE == 0 {
+				batch.commit()?;
+				batch = db.batch()?;
+			}
+		}
+		batch.commit()?;
+		db.sync()
+	}
 }
 
 #[allow(missing_docs)]
@@ -84,6 +160,11 @@ impl ChainStore {
 		option_to_not_found(self.db.get_ser(&vec![SYNC_HEAD_PREFIX]), "SYNC_HEAD")
 	}
 
+	/// Version of the format the database is in, none if not recorded yet
+	pub fn db_version(&self) -> Result<Option<u32>, Error> {
+		self.db.get_ser(&vec![DB_VERSION_PREFIX])
+	}
+
 	pub fn get_block(&self, h: &Hash) -> Result<Block, Error> {
 		option_to_not_found(
 			self.db.get_ser(&to_key(BLOCK_PREFIX, &mut h.to_vec())),
@@ -199,6 +280,31 @@ impl ChainStore {
 		)
 	}
 
+	/// Writes a consistent copy of the database to the provided directory
+	pub fn copy_to(&self, dest: &Path) -> Result<(), Error> {
+		self.db.copy_to(dest)
+	}
+
+	/// Drops, in the provided batch, the indices derived from the blocks and
+	/// the txhashset: output positions, header heights and block input
+	/// bitmaps. Returns how many entries were dropped.
+	pub fn drop_derived_indices(&self, batch: &Batch) -> Result<usize, Error> {
+		let mut dropped = 0;
+		for prefix in &[
+			COMMIT_POS_PREFIX,
+			HEADER_HEIGHT_PREFIX,
+			BLOCK_INPUT_BITMAP_PREFIX,
+		] {
+			for (key, _) in self.db.raw_entries(&[*prefix])? {
+				batch.db.delete(&key)?;
+				dropped += 1;
+			}
+		}
+		let cache = self.block_input_bitmap_cache.clone();
+		batch.db.on_commit(move || cache.write().clear());
+		Ok(dropped)
+	}
+
 	/// Builds a new batch to be used with this store.
 	pub fn batch(&self) -> Result<Batch, Error> {
 		Ok(Batch {
@@ -208,13 +314,27 @@ impl ChainStore {
 			block_sums_cache: self.block_sums_cache.clone(),
 		})
 	}
+
+	/// Runs the provided writes in a new batch, all committed together if
+	/// they succeed and all discarded otherwise. Caches are only updated once
+	/// committed.
+	pub fn atomic<F, T, E>(&self, f: F) -> Result<T, E>
+	where
+		F: FnOnce(&Batch) -> Result<T, E>,
+		E: From<Error>,
+	{
+		let batch = self.batch()?;
+		let res = f(&batch)?;
+		batch.commit()?;
+		Ok(res)
+	}
 }
 
 /// An atomic batch in which all changes can be committed all at once or
 /// discarded on error.
 pub struct Batch<'a> {
 	db: store::Batch<'a>,
-	header_cache: Arc<RwLock<LruCache<Hash, BlockHeader>>>,
+	header_cache: Arc<HeaderCache>,
 	block_sums_cache: Arc<RwLock<LruCache<Hash, BlockSums>>>,
 	block_input_bitmap_cache: Arc<RwLock<LruCache<Hash, Vec<u8>>>>,
 }
@@ -263,6 +383,10 @@ impl<'a> Batch<'a> {
 		self.db.put_ser(&vec![SYNC_HEAD_PREFIX], t)
 	}
 
+	pub fn save_db_version(&self, version: u32) -> Result<(), Error> {
+		self.db.put_ser(&vec![DB_VERSION_PREFIX], &version)
+	}
+
 	pub fn reset_sync_head(&self) -> Result<(), Error> {
 		let head = self.header_head()?;
 		self.save_sync_head(&head)
@@ -319,10 +443,13 @@ impl<'a> Batch<'a> {
 	pub fn save_block_header(&self, header: &BlockHeader) -> Result<(), Error> {
 		let hash = header.hash();
 
-		// Cache the header.
+		// Cache the header, once committed.
 		{
-			let mut header_cache = self.header_cache.write();
-			header_cache.insert(hash, header.clone());
+			let header_cache = self.header_cache.clone();
+			let header = header.clone();
+			self.db.on_commit(move || {
+				header_cache.write().insert(hash, header);
+			});
 		}
 
 		// Store the header itself indexed by hash.
@@ -407,8 +534,11 @@ impl<'a> Batch<'a> {
 
 	pub fn save_block_sums(&self, h: &Hash, sums: &BlockSums) -> Result<(), Error> {
 		{
-			let mut block_sums_cache = self.block_sums_cache.write();
-			block_sums_cache.insert(*h, sums.clone());
+			let block_sums_cache = self.block_sums_cache.clone();
+			let (h, sums) = (*h, sums.clone());
+			self.db.on_commit(move || {
+				block_sums_cache.write().insert(h, sums);
+			});
 		}
 
 		self.db
@@ -527,9 +657,12 @@ impl<'a> Batch<'a> {
 		// Save the bitmap to the db (via the batch).
 		self.save_block_input_bitmap(&block.hash(), &bitmap)?;
 
-		// Finally cache it locally for use later.
-		let mut cache = self.block_input_bitmap_cache.write();
-		cache.insert(block.hash(), bitmap.serialize());
+		// Finally cache it locally for use later, once committed.
+		let cache = self.block_input_bitmap_cache.clone();
+		let (h, bytes) = (block.hash(), bitmap.serialize());
+		self.db.on_commit(move || {
+			cache.write().insert(h, bytes);
+		});
 
 		Ok(bitmap)
 	}
@@ -0,0 +1,356 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilds the txhashset of a given block from segments received in any
+//! order, possibly from many peers. Every segment gets checked against the
+//! roots of the block header as it arrives, and written to the MMR files as
+//! soon as all the segments before it are in, so only those received out of
+//! order are kept in memory.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use core::core::hash::{Hash, Hashed};
+use core::core::pmmr::{self, Segment, SegmentIdentifier, SegmentType, TxHashSetSegment};
+use core::core::{BlockHeader, OutputIdentifier, TxKernel};
+use core::ser::{self, PMMRIndexHashable, PMMRable};
+use error::{Error, ErrorKind};
+use grin_store::leaf_set::LeafSet;
+use grin_store::pmmr::{PMMR_DATA_FILE, PMMR_HASH_FILE, PMMR_LEAF_FILE, PMMR_PRUN_FILE};
+use grin_store::prune_list::PruneList;
+use grin_store::types::{AppendOnlyFile, HashFile};
+use txhashset::{KERNEL_SUBDIR, OUTPUT_SUBDIR, RANGE_PROOF_SUBDIR};
+use util::secp::pedersen::RangeProof;
+use util::zip;
+
+const SEGMENTS_SUBDIR: &'static str = "txhashset_segments";
+const SEGMENTS_ZIP: &'static str = "txhashset_segments.zip";
+
+/// Writes a single MMR out of its segments, taken in order.
+struct MMRWriter<T> {
+	mmr_size: u64,
+	root: Hash,
+	height: u8,
+	prunable: bool,
+	// number of segments in total and index of the next one to write
+	count: u64,
+	next: u64,
+	// validated segments waiting for the ones before them
+	pending: HashMap<u64, Segment<T>>,
+	// nodes above the segments height whose parent isn't known yet, None
+	// when compacted away
+	nodes: HashMap<u64, Option<Hash>>,
+	// hashes of the pruned nodes covering compacted segments, from their
+	// proofs
+	pruned: HashMap<u64, Hash>,
+	bytes: u64,
+	hash_file: HashFile,
+	data_file: AppendOnlyFile,
+	leaf_set: LeafSet,
+	prune_list: PruneList,
+}
+
+impl<T> MMRWriter<T>
+where
+	T: PMMRable,
+{
+	fn open(
+		dir: &Path,
+		header: &BlockHeader,
+		segment_type: SegmentType,
+		mmr_size: u64,
+		root: Hash,
+	) -> Result<MMRWriter<T>, Error> {
+		fs::create_dir_all(dir)?;
+		let path = |name: String| dir.join(name).to_string_lossy().into_owned();
+		let height = segment_type.default_height();
+
+		Ok(MMRWriter {
+			mmr_size,
+			root,
+			height,
+			prunable: segment_type.is_prunable(),
+			count: SegmentIdentifier::count(height, mmr_size),
+			next: 0,
+			pending: HashMap::new(),
+			nodes: HashMap::new(),
+			pruned: HashMap::new(),
+			bytes: 0,
+			hash_file: HashFile::open(&path(PMMR_HASH_FILE.to_owned()))?,
+			data_file: AppendOnlyFile::open(&path(PMMR_DATA_FILE.to_owned()))?,
+			// the leaf set is looked up as a snapshot at the header when
+			// opening the txhashset
+			leaf_set: LeafSet::open(&path(format!("{}.{}", PMMR_LEAF_FILE, header.hash())))?,
+			prune_list: PruneList::open(&path(PMMR_PRUN_FILE.to_owned()))?,
+		})
+	}
+
+	fn is_complete(&self) -> bool {
+		self.next == self.count
+	}
+
+	fn is_received(&self, idx: u64) -> bool {
+		idx < self.next || self.pending.contains_key(&idx)
+	}
+
+	fn received(&self) -> u64 {
+		self.next + self.pending.len() as u64
+	}
+
+	fn missing(
+		&self,
+		segment_type: SegmentType,
+		max: usize,
+	) -> Vec<(SegmentType, SegmentIdentifier)> {
+		(self.next..self.count)
+			.filter(|idx| !self.pending.contains_key(idx))
+			.take(max)
+			.map(|idx| {
+				let id = SegmentIdentifier {
+					height: self.height,
+					idx,
+				};
+				(segment_type, id)
+			}).collect()
+	}
+
+	/// Validates the segment and writes it, along with any pending segment
+	/// that now follows. Returns whether the segment was new to us.
+	fn apply(&mut self, segment: Segment<T>) -> Result<bool, Error> {
+		let id = segment.identifier;
+		if id.height != self.height || id.idx >= self.count {
+			return Err(ErrorKind::InvalidSegment(format!("unexpected segment {:?}", id)).into());
+		}
+		if self.is_received(id.idx) {
+			return Ok(false);
+		}
+		segment
+			.validate(self.mmr_size, self.root, self.prunable)
+			.map_err(|e| ErrorKind::InvalidSegment(format!("{:?}: {:?}", id, e)))?;
+
+		self.bytes += segment.byte_size();
+		self.pending.insert(id.idx, segment);
+		while let Some(segment) = self.pending.remove(&self.next) {
+			self.write(segment)?;
+			self.next += 1;
+		}
+		self.hash_file.flush()?;
+		self.data_file.flush()?;
+		self.leaf_set.flush()?;
+		self.prune_list.flush()?;
+		Ok(true)
+	}
+
+	fn write(&mut self, segment: Segment<T>) -> Result<(), Error> {
+		let id = segment.identifier;
+		let (_, last) = id
+			.pos_range(self.mmr_size)
+			.ok_or_else(|| ErrorKind::InvalidSegment(format!("{:?} out of range", id)))?;
+
+		let hashes: HashMap<u64, Hash> = segment.hashes.iter().cloned().collect();
+		for &(pos, hash) in &segment.hashes {
+			self.hash_file.append(&hash)?;
+			// a hash without children is the root of a pruned subtree, leaf
+			// roots don't need to be marked as the next compaction will
+			if pmmr::bintree_postorder_height(pos) > 0 && !hashes.contains_key(&(pos - 1)) {
+				self.add_pruned(pos)?;
+			}
+		}
+		for &(_, ref data) in &segment.leaves {
+			let mut bytes = ser::ser_vec(data).map_err(ErrorKind::SerErr)?;
+			self.data_file.append(&mut bytes);
+		}
+		for pos in &segment.leaf_set {
+			self.leaf_set.add(*pos);
+		}
+
+		// The nodes above the segments come right after the last position of
+		// a complete segment. Only the last segment can be incomplete, and
+		// then it's only made of peaks.
+		if pmmr::bintree_postorder_height(last) != self.height as u64 {
+			return Ok(());
+		}
+		let root = hashes.get(&last).cloned();
+		if root.is_none() {
+			let proof = &segment.proofs[0];
+			self.pruned.insert(proof.pos, proof.hash);
+		}
+		self.nodes.insert(last, root);
+
+		let next_first = SegmentIdentifier {
+			height: self.height,
+			idx: id.idx + 1,
+		}.pos_range(self.mmr_size)
+		.map(|(first, _)| first)
+		.unwrap_or(self.mmr_size + 1);
+		for pos in (last + 1)..next_first {
+			let height = pmmr::bintree_postorder_height(pos);
+			let left = self.nodes.remove(&(pos - (1 << height)));
+			let right = self.nodes.remove(&(pos - 1));
+			let node = match (left, right) {
+				(Some(Some(l)), Some(Some(r))) => Some((l, r).hash_with_index(pos - 1)),
+				(Some(None), Some(None)) => self.pruned.remove(&pos),
+				// peers compacted this part of the MMR differently, segments
+				// from both can't be stitched together
+				_ => {
+					return Err(ErrorKind::TxHashSetErr(format!(
+						"inconsistent compaction around {}",
+						pos
+					)).into())
+				}
+			};
+			if let Some(hash) = node {
+				self.hash_file.append(&hash)?;
+				if left == Some(None) {
+					self.add_pruned(pos)?;
+				}
+			}
+			self.nodes.insert(pos, node);
+		}
+		Ok(())
+	}
+
+	fn add_pruned(&mut self, pos: u64) -> Result<(), Error> {
+		// a pruned sibling means the parent should have been pruned instead,
+		// which only happens with segments from differently compacted peers
+		let (_, sibling) = pmmr::family(pos);
+		if self.prune_list.is_pruned_root(sibling) {
+			return Err(
+				ErrorKind::TxHashSetErr(format!("inconsistent compaction around {}", pos)).into(),
+			);
+		}
+		self.prune_list.add(pos);
+		Ok(())
+	}
+}
+
+/// The output, rangeproof and kernel MMRs as of a block, being rebuilt from
+/// segments. Once complete, the result is packaged like a txhashset zip
+/// received from a peer would be.
+pub struct SegmentedTxHashSet {
+	header: BlockHeader,
+	dir: PathBuf,
+	output: MMRWriter<OutputIdentifier>,
+	rproof: MMRWriter<RangeProof>,
+	kernel: MMRWriter<TxKernel>,
+}
+
+impl SegmentedTxHashSet {
+	/// Starts rebuilding the txhashset of the provided header, under the
+	/// root directory. Whatever was left from a previous attempt is cleared.
+	pub fn new(root_dir: &str, header: &BlockHeader) -> Result<SegmentedTxHashSet, Error> {
+		let dir = Path::new(root_dir).join(SEGMENTS_SUBDIR);
+		if dir.exists() {
+			fs::remove_dir_all(&dir)?;
+		}
+		Ok(SegmentedTxHashSet {
+			header: header.clone(),
+			output: MMRWriter::open(
+				&dir.join(OUTPUT_SUBDIR),
+				header,
+				SegmentType::Output,
+				header.output_mmr_size,
+				header.output_root,
+			)?,
+			rproof: MMRWriter::open(
+				&dir.join(RANGE_PROOF_SUBDIR),
+				header,
+				SegmentType::RangeProof,
+				header.output_mmr_size,
+				header.range_proof_root,
+			)?,
+			kernel: MMRWriter::open(
+				&dir.join(KERNEL_SUBDIR),
+				header,
+				SegmentType::Kernel,
+				header.kernel_mmr_size,
+				header.kernel_root,
+			)?,
+			dir,
+		})
+	}
+
+	/// Header of the block the txhashset is rebuilt at
+	pub fn header(&self) -> &BlockHeader {
+		&self.header
+	}
+
+	/// Checks a segment against the header roots and adds it. Returns
+	/// false if we had it already.
+	pub fn apply(&mut self, segment: TxHashSetSegment) -> Result<bool, Error> {
+		match segment {
+			TxHashSetSegment::Output(s) => self.output.apply(s),
+			TxHashSetSegment::RangeProof(s) => self.rproof.apply(s),
+			TxHashSetSegment::Kernel(s) => self.kernel.apply(s),
+		}
+	}
+
+	/// Up to max of the segments not received yet, in the order they're
+	/// best requested.
+	pub fn missing(&self, max: usize) -> Vec<(SegmentType, SegmentIdentifier)> {
+		let mut missing = self.output.missing(SegmentType::Output, max);
+		let left = max - missing.len();
+		missing.extend(self.rproof.missing(SegmentType::RangeProof, left));
+		let left = max - missing.len();
+		missing.extend(self.kernel.missing(SegmentType::Kernel, left));
+		missing
+	}
+
+	/// Whether we already have the provided segment
+	pub fn is_received(&self, segment_type: SegmentType, id: SegmentIdentifier) -> bool {
+		match segment_type {
+			SegmentType::Output => self.output.is_received(id.idx),
+			SegmentType::RangeProof => self.rproof.is_received(id.idx),
+			SegmentType::Kernel => self.kernel.is_received(id.idx),
+		}
+	}
+
+	/// Number of segments received so far and in total
+	pub fn progress(&self) -> (u64, u64) {
+		(
+			self.output.received() + self.rproof.received() + self.kernel.received(),
+			self.output.count + self.rproof.count + self.kernel.count,
+		)
+	}
+
+	/// Approximate size of the segments received so far, in bytes
+	pub fn bytes_received(&self) -> u64 {
+		self.output.bytes + self.rproof.bytes + self.kernel.bytes
+	}
+
+	/// Whether all segments have been received and written
+	pub fn is_complete(&self) -> bool {
+		self.output.is_complete() && self.rproof.is_complete() && self.kernel.is_complete()
+	}
+
+	/// Packages the rebuilt MMRs in a zip, as expected by
+	/// `Chain::txhashset_write`, and clears the working files.
+	pub fn zip(self) -> Result<File, Error> {
+		if !self.is_complete() {
+			return Err(ErrorKind::TxHashSetErr("segments still missing".to_owned()).into());
+		}
+		let zip_path = self.dir.with_file_name(SEGMENTS_ZIP);
+		zip::compress(&self.dir, &File::create(&zip_path)?)
+			.map_err(|ze| ErrorKind::Other(ze.to_string()))?;
+		fs::remove_dir_all(&self.dir)?;
+		Ok(File::open(zip_path)?)
+	}
+
+	/// Gives up on the rebuild, clearing the working files
+	pub fn discard(self) -> Result<(), Error> {
+		fs::remove_dir_all(&self.dir)?;
+		Ok(())
+	}
+}
@@ -15,7 +15,7 @@
 //! Utility structs to handle the 3 MMRs (output, rangeproof,
 //! kernel) along the overall header MMR conveniently and transactionally.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -28,7 +28,10 @@ use util::secp::pedersen::{Commitment, RangeProof};
 use core::core::committed::Committed;
 use core::core::hash::{Hash, Hashed};
 use core::core::merkle_proof::MerkleProof;
-use core::core::pmmr::{self, ReadonlyPMMR, RewindablePMMR, DBPMMR, PMMR};
+use core::core::pmmr::{
+	self, ReadonlyPMMR, RewindablePMMR, SegmentIdentifier, SegmentType, TxHashSetSegment, DBPMMR,
+	PMMR,
+};
 use core::core::{Block, BlockHeader, Input, Output, OutputFeatures, OutputIdentifier, TxKernel};
 use core::global;
 use core::ser::{PMMRIndexHashable, PMMRable};
@@ -43,14 +46,15 @@ use types::{Tip, TxHashSetRoots, TxHashsetWriteStatus};
 use util::{file, secp_static, zip};
 
 const HEADERHASHSET_SUBDIR: &'static str = "header";
-const TXHASHSET_SUBDIR: &'static str = "txhashset";
+/// Directory of the txhashset files, under the chain root directory
+pub const TXHASHSET_SUBDIR: &'static str = "txhashset";
 
 const HEADER_HEAD_SUBDIR: &'static str = "header_head";
 const SYNC_HEAD_SUBDIR: &'static str = "sync_head";
 
-const OUTPUT_SUBDIR: &'static str = "output";
-const RANGE_PROOF_SUBDIR: &'static str = "rangeproof";
-const KERNEL_SUBDIR: &'static str = "kernel";
+pub(crate) const OUTPUT_SUBDIR: &'static str = "output";
+pub(crate) const RANGE_PROOF_SUBDIR: &'static str = "rangeproof";
+pub(crate) const KERNEL_SUBDIR: &'static str = "kernel";
 
 const TXHASHSET_ZIP: &'static str = "txhashset_snapshot";
 
@@ -211,6 +215,32 @@ impl TxHashSet {
 		kernel_pmmr.get_last_n_insertions(distance)
 	}
 
+	/// Finds the kernels with the provided excesses past the provided kernel
+	/// MMR position, with their position. Goes through the kernels the most
+	/// recent first, until all are found or that position is reached.
+	pub fn find_kernels(
+		&self,
+		excesses: &[Commitment],
+		min_pos: u64,
+	) -> HashMap<Commitment, (TxKernel, u64)> {
+		let mut wanted: HashSet<Commitment> = excesses.iter().cloned().collect();
+		let mut found = HashMap::new();
+		let kernel_pmmr: ReadonlyPMMR<TxKernel, _> =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		let mut pos = self.kernel_pmmr_h.last_pos;
+		while pos > min_pos && !wanted.is_empty() {
+			if pmmr::is_leaf(pos) {
+				if let Some(kernel) = kernel_pmmr.get_data(pos) {
+					if wanted.remove(&kernel.excess) {
+						found.insert(kernel.excess, (kernel, pos));
+					}
+				}
+			}
+			pos -= 1;
+		}
+		found
+	}
+
 	/// returns outputs from the given insertion (leaf) index up to the
 	/// specified limit. Also returns the last index actually populated
 	pub fn outputs_by_insertion_index(
@@ -817,29 +847,58 @@ impl<'a> Extension<'a> {
 			.max()
 			.unwrap_or(0);
 
-		if pos > 0 {
-			// If we have not yet reached 1,000 / 1,440 blocks then
-			// we can fail immediately as coinbase cannot be mature.
-			if height < global::coinbase_maturity() {
-				return Err(ErrorKind::ImmatureCoinbase.into());
-			}
+		if !self.is_mature(pos, height, global::coinbase_maturity())? {
+			return Err(ErrorKind::ImmatureCoinbase.into());
+		}
+		Ok(())
+	}
 
-			// Find the "cutoff" pos in the output MMR based on the
-			// header from 1,000 blocks ago.
-			let cutoff_height = height.checked_sub(global::coinbase_maturity()).unwrap_or(0);
-			let cutoff_header = self.batch.get_header_by_height(cutoff_height)?;
-			let cutoff_pos = cutoff_header.output_mmr_size;
+	/// Verify the outputs spent by the inputs, coinbase or not, are buried
+	/// under at least the provided number of blocks by the given height.
+	/// Inputs spending outputs that aren't on the chain are left to the tx
+	/// validation.
+	pub fn verify_input_confirmations(
+		&self,
+		inputs: &Vec<Input>,
+		height: u64,
+		confirmations: u64,
+	) -> Result<(), Error> {
+		let pos = inputs
+			.iter()
+			.filter_map(|x| self.batch.get_output_pos(&x.commitment()).ok())
+			.max()
+			.unwrap_or(0);
 
-			// If any output pos exceed the cutoff_pos
-			// we know they have not yet sufficiently matured.
-			if pos > cutoff_pos {
-				return Err(ErrorKind::ImmatureCoinbase.into());
-			}
+		if !self.is_mature(pos, height, confirmations)? {
+			return Err(ErrorKind::ImmatureInput.into());
 		}
-
 		Ok(())
 	}
 
+	// Whether the output at the provided pos is at least maturity blocks
+	// behind the given height.
+	fn is_mature(&self, pos: u64, height: u64, maturity: u64) -> Result<bool, Error> {
+		if pos == 0 {
+			return Ok(true);
+		}
+
+		// If we have not yet reached that many blocks (1,440 for a
+		// coinbase) then the output cannot be mature.
+		if height < maturity {
+			return Ok(false);
+		}
+
+		// Find the "cutoff" pos in the output MMR based on the
+		// header from maturity blocks ago.
+		let cutoff_height = height.checked_sub(maturity).unwrap_or(0);
+		let cutoff_header = self.batch.get_header_by_height(cutoff_height)?;
+		let cutoff_pos = cutoff_header.output_mmr_size;
+
+		// If the output pos exceeds the cutoff_pos
+		// we know it has not yet sufficiently matured.
+		Ok(pos <= cutoff_pos)
+	}
+
 	/// Apply a new block to the existing state.
 	///
 	/// Applies the following -
@@ -996,6 +1055,31 @@ impl<'a> Extension<'a> {
 		Ok(())
 	}
 
+	/// Segment of one of the output, rangeproof or kernel MMRs, as of the
+	/// current state of the extension.
+	pub fn segment(
+		&self,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Result<TxHashSetSegment, Error> {
+		let prunable = segment_type.is_prunable();
+		let segment = match segment_type {
+			SegmentType::Output => self
+				.output_pmmr
+				.segment(id, prunable)
+				.map(TxHashSetSegment::Output),
+			SegmentType::RangeProof => self
+				.rproof_pmmr
+				.segment(id, prunable)
+				.map(TxHashSetSegment::RangeProof),
+			SegmentType::Kernel => self
+				.kernel_pmmr
+				.segment(id, prunable)
+				.map(TxHashSetSegment::Kernel),
+		};
+		segment.map_err(|e| ErrorKind::TxHashSetErr(e).into())
+	}
+
 	/// Rewinds the MMRs to the provided block, rewinding to the last output pos
 	/// and last kernel pos of that block.
 	pub fn rewind(&mut self, header: &BlockHeader) -> Result<(), Error> {
@@ -1212,17 +1296,20 @@ impl<'a> Extension<'a> {
 
 	/// Rebuild the index of MMR positions to the corresponding Output and
 	/// kernel by iterating over the whole MMR data. This is a costly operation
-	/// performed only when we receive a full new chain state.
-	pub fn rebuild_index(&self) -> Result<(), Error> {
+	/// performed only when we receive a full new chain state, or reindex.
+	/// Returns how many outputs were indexed.
+	pub fn rebuild_index(&self) -> Result<u64, Error> {
+		let mut count = 0;
 		for n in 1..self.output_pmmr.unpruned_size() + 1 {
 			// non-pruned leaves only
 			if pmmr::bintree_postorder_height(n) == 0 {
 				if let Some(out) = self.output_pmmr.get_data(n) {
 					self.batch.save_output_pos(&out.commit, n)?;
+					count += 1;
 				}
 			}
 		}
-		Ok(())
+		Ok(count)
 	}
 
 	/// Force the rollback of this extension, no matter the result
@@ -1347,19 +1434,45 @@ impl<'a> Extension<'a> {
 	}
 }
 
+/// Id of a txhashset zip prepared ahead of requests, above the microseconds
+/// naming the zips built on request so they never collide
+pub const PREPARED_ZIP_ID: u32 = 1_000_000;
+
+/// Location of the txhashset zip with the given id
+pub fn zip_path(root_dir: &str, id: u32) -> PathBuf {
+	Path::new(root_dir).join(format!("{}_{}.zip", TXHASHSET_ZIP, id))
+}
+
+/// Copies the txhashset files under the provided directory, laid out as under
+/// the chain root directory. The txhashset must not change meanwhile.
+pub fn copy_files(root_dir: &str, dest: &Path) -> Result<(), Error> {
+	let txhashset_path = Path::new(root_dir).join(TXHASHSET_SUBDIR);
+	file::copy_dir_to(&txhashset_path, &dest.join(TXHASHSET_SUBDIR))?;
+	Ok(())
+}
+
 /// Packages the txhashset data files into a zip and returns a Read to the
 /// resulting file
 pub fn zip_read(root_dir: String, header: &BlockHeader, rand: Option<u32>) -> Result<File, Error> {
+	let zip_path = zip_file(root_dir, header, rand)?;
+	Ok(File::open(zip_path)?)
+}
+
+/// Packages the txhashset data files into a zip and returns its location
+pub fn zip_file(
+	root_dir: String,
+	header: &BlockHeader,
+	rand: Option<u32>,
+) -> Result<PathBuf, Error> {
 	let ts = if let None = rand {
 		let now = SystemTime::now();
 		now.duration_since(UNIX_EPOCH).unwrap().subsec_micros()
 	} else {
 		rand.unwrap()
 	};
-	let txhashset_zip = format!("{}_{}.zip", TXHASHSET_ZIP, ts);
 
 	let txhashset_path = Path::new(&root_dir).join(TXHASHSET_SUBDIR);
-	let zip_path = Path::new(&root_dir).join(txhashset_zip);
+	let zip_path = zip_path(&root_dir, ts);
 	// create the zip archive
 	{
 		// Temp txhashset directory
@@ -1378,9 +1491,7 @@ pub fn zip_read(root_dir: String, header: &BlockHeader, rand: Option<u32>) -> Re
 			.map_err(|ze| ErrorKind::Other(ze.to_string()))?;
 	}
 
-	// open it again to read it back
-	let zip_file = File::open(zip_path)?;
-	Ok(zip_file)
+	Ok(zip_path)
 }
 
 /// Extract the txhashset data from a zip file and writes the content into the
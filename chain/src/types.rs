@@ -47,6 +47,17 @@ pub struct TxHashSetRoots {
 	pub kernel_root: Hash,
 }
 
+/// What a reindex of the chain went through
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReindexStats {
+	/// Index entries dropped, to be rebuilt
+	pub dropped: usize,
+	/// Output positions indexed again from the output MMR
+	pub output_positions: u64,
+	/// Heights of the headers of the chain indexed again
+	pub header_heights: u64,
+}
+
 /// The tip of a fork. A handle to the fork ancestry from its leaf in the
 /// blockchain tree. References the max height and the latest and previous
 /// blocks
@@ -25,7 +25,7 @@ extern crate rand;
 use chrono::Duration;
 use std::fs;
 use std::sync::Arc;
-use util::RwLock;
+use util::{MemoryBudget, RwLock};
 
 use chain::types::NoopAdapter;
 use chain::Chain;
@@ -56,6 +56,7 @@ fn setup(dir_name: &str) -> Chain {
 		pow::verify_size,
 		verifier_cache,
 		false,
+		Arc::new(MemoryBudget::unlimited()),
 	).unwrap()
 }
 
@@ -70,6 +71,7 @@ fn reload_chain(dir_name: &str) -> Chain {
 		pow::verify_size,
 		verifier_cache,
 		false,
+		Arc::new(MemoryBudget::unlimited()),
 	).unwrap()
 }
 
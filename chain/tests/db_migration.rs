@@ -0,0 +1,106 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate env_logger;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_store as store;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use chain::migration::{self, Migration};
+use chain::store::{Batch, ChainStore};
+use chain::{Error, Tip};
+use core::global::{self, ChainTypes};
+use core::pow;
+
+fn clean_output_dir(dir_name: &str) {
+	let _ = fs::remove_dir_all(dir_name);
+}
+
+// rewrites the header of the head
+struct TestMigration {
+	version: u32,
+}
+
+impl Migration for TestMigration {
+	fn version(&self) -> u32 {
+		self.version
+	}
+
+	fn description(&self) -> &str {
+		"test migration"
+	}
+
+	fn migrate(
+		&self,
+		store: &ChainStore,
+		batch: &Batch,
+		progress: &mut FnMut(u64, u64),
+	) -> Result<(), Error> {
+		let header = store.head_header()?;
+		batch.save_block_header(&header)?;
+		progress(1, 1);
+		Ok(())
+	}
+}
+
+#[test]
+fn test_db_migration() {
+	let _ = env_logger::try_init();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let chain_dir = ".grin_migration";
+	clean_output_dir(chain_dir);
+
+	let db_env = Arc::new(store::new_env(chain_dir.to_string()));
+	let store = ChainStore::new(db_env).unwrap();
+
+	// a new database is recorded at the version asked for
+	assert_eq!(store.db_version().unwrap(), None);
+	migration::check_version(&store, migration::DB_VERSION).unwrap();
+	assert_eq!(store.db_version().unwrap(), Some(migration::DB_VERSION));
+
+	// then holding a chain, needing migrating to newer versions
+	let genesis = pow::mine_genesis_block().unwrap();
+	{
+		let batch = store.batch().unwrap();
+		batch.save_block_header(&genesis.header).unwrap();
+		batch.save_block(&genesis).unwrap();
+		batch.save_head(&Tip::from_header(&genesis.header)).unwrap();
+		batch.commit().unwrap();
+	}
+	assert!(migration::check_version(&store, 2).is_err());
+
+	let migrations: Vec<Box<Migration>> = vec![Box::new(TestMigration { version: 2 })];
+	let report = migration::migrate(&store, &migrations, 2, Some("backup")).unwrap();
+	assert_eq!(report.from_version, 1);
+	assert_eq!(report.to_version, 2);
+	assert_eq!(report.applied, vec![2]);
+	assert_eq!(report.backup, Some("backup".to_owned()));
+	migration::check_version(&store, 2).unwrap();
+
+	// nothing left to run, and no way to go further
+	let report = migration::migrate(&store, &migrations, 2, None::<&Path>).unwrap();
+	assert!(report.applied.is_empty());
+	assert!(migration::migrate(&store, &migrations, 3, None::<&Path>).is_err());
+	assert_eq!(store.db_version().unwrap(), Some(2));
+
+	// newer than supported
+	assert!(migration::check_version(&store, 1).is_err());
+	assert!(migration::migrate(&store, &migrations, 1, None::<&Path>).is_err());
+
+	clean_output_dir(chain_dir);
+}
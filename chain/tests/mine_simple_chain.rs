@@ -24,11 +24,12 @@ extern crate rand;
 use chrono::Duration;
 use std::fs;
 use std::sync::Arc;
-use util::RwLock;
+use util::{MemoryBudget, RwLock};
 
 use chain::types::NoopAdapter;
 use chain::Chain;
 use core::core::hash::Hashed;
+use core::core::pmmr::{SegmentIdentifier, SegmentType};
 use core::core::verifier_cache::LruVerifierCache;
 use core::core::{Block, BlockHeader, OutputFeatures, OutputIdentifier, Transaction};
 use core::global::ChainTypes;
@@ -54,6 +55,7 @@ fn setup(dir_name: &str, genesis: Block) -> Chain {
 		pow::verify_size,
 		verifier_cache,
 		false,
+		Arc::new(MemoryBudget::unlimited()),
 	).unwrap()
 }
 
@@ -365,6 +367,196 @@ fn spend_in_fork_and_compact() {
 	}
 }
 
+#[test]
+fn stopped_chain() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed().unwrap();
+	let dir = ".grin_stopped";
+	let genesis = pow::mine_genesis_block().unwrap();
+	let head = {
+		let chain = setup(dir, genesis.clone());
+		let prev = chain.head_header().unwrap();
+		let b1 = prepare_block(&kc, &prev, &chain, 2);
+		chain.process_block(b1, chain::Options::SKIP_POW).unwrap();
+		let b1head = chain.head_header().unwrap();
+
+		// once stopped, the chain refuses any new block
+		chain.stop().unwrap();
+		assert!(chain.is_stopped());
+		let b2 = prepare_block(&kc, &b1head, &chain, 3);
+		match chain.process_block(b2, chain::Options::SKIP_POW) {
+			Err(e) => assert_eq!(e.kind(), chain::ErrorKind::Stopped),
+			Ok(_) => panic!("stopped chain accepted a block"),
+		}
+		b1head
+	};
+
+	// everything committed before stopping is still there on restart
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let db_env = Arc::new(store::new_env(dir.to_string()));
+	let chain = chain::Chain::init(
+		dir.to_string(),
+		db_env,
+		Arc::new(NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		verifier_cache,
+		false,
+		Arc::new(MemoryBudget::unlimited()),
+	).unwrap();
+	assert_eq!(chain.head_header().unwrap().hash(), head.hash());
+	chain.validate(false).unwrap();
+}
+
+#[test]
+fn reindex_chain() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed().unwrap();
+	let chain = setup(".grin_reindex", pow::mine_genesis_block().unwrap());
+	for n in 1..4 {
+		let prev = chain.head_header().unwrap();
+		let b = prepare_block(&kc, &prev, &chain, n + 1);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+	}
+	let head = chain.head_header().unwrap();
+	let block = chain.get_block(&head.hash()).unwrap();
+	let output = OutputIdentifier::from_output(&block.outputs()[0]);
+
+	let stats = chain.reindex().unwrap();
+	assert_eq!(stats.header_heights, 4);
+	// genesis has no output, one coinbase a block
+	assert_eq!(stats.output_positions, 3);
+	assert!(stats.dropped >= 6);
+
+	// everything found as before
+	for h in 0..4 {
+		assert_eq!(chain.get_header_by_height(h).unwrap().height, h);
+	}
+	assert_eq!(chain.get_header_for_output(&output).unwrap().hash(), head.hash());
+	chain.validate(false).unwrap();
+}
+
+// Segments are only served at the archive header, the same ones whichever
+// asked for first
+#[test]
+fn txhashset_segments() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed().unwrap();
+	let chain = setup(".grin_segments", pow::mine_genesis_block().unwrap());
+	for n in 1..26 {
+		let prev = chain.head_header().unwrap();
+		let b = prepare_block(&kc, &prev, &chain, n + 1);
+		chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+	}
+	let archive = chain.txhashset_archive_header().unwrap();
+	assert_eq!(
+		archive.height,
+		global::txhashset_archive_height(chain.head().unwrap().height)
+	);
+	assert_eq!(archive.height % global::txhashset_archive_interval(), 0);
+
+	let id = |idx| SegmentIdentifier { height: 1, idx };
+	let head = chain.head_header().unwrap();
+	assert!(chain
+		.txhashset_segment(head.hash(), SegmentType::Kernel, id(0))
+		.is_err());
+
+	// the kernel MMR at the archive header holds a kernel per block past
+	// genesis, 2 to a segment of height 1
+	for idx in 0..(archive.height + 1) / 2 {
+		let segment = chain
+			.txhashset_segment(archive.hash(), SegmentType::Kernel, id(idx))
+			.unwrap();
+		assert_eq!(segment.identifier(), id(idx));
+		assert_eq!(segment.segment_type(), SegmentType::Kernel);
+	}
+	assert!(chain
+		.txhashset_segment(archive.hash(), SegmentType::Kernel, id(archive.height))
+		.is_err());
+	let output = chain
+		.txhashset_segment(archive.hash(), SegmentType::Output, id(1))
+		.unwrap();
+	assert_eq!(output.segment_type(), SegmentType::Output);
+}
+
+// The zip served at the archive header is prepared once, and again for the
+// new archive header once the chain moves on
+#[test]
+fn txhashset_prepared_zip() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed().unwrap();
+	let chain = setup(".grin_prepared_zip", pow::mine_genesis_block().unwrap());
+	let mine = |count| {
+		for _ in 0..count {
+			let prev = chain.head_header().unwrap();
+			let b = prepare_block(&kc, &prev, &chain, prev.height + 2);
+			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		}
+	};
+	mine(25);
+	chain.prepare_txhashset_zip().unwrap();
+	let archive = chain.txhashset_archive_header().unwrap();
+	let (_, _, zip) = chain.txhashset_read(archive.hash()).unwrap();
+	assert!(zip.exists());
+	assert_eq!(chain.txhashset_read(archive.hash()).unwrap().2, zip);
+
+	mine(global::txhashset_archive_interval());
+	let moved = chain.txhashset_archive_header().unwrap();
+	assert!(moved.height > archive.height);
+	let (_, _, moved_zip) = chain.txhashset_read(moved.hash()).unwrap();
+	assert!(moved_zip.exists());
+	assert_ne!(moved_zip, zip);
+	assert!(!zip.exists());
+}
+
+#[test]
+fn orphans_memory_budget() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed().unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let mut blocks = vec![];
+	{
+		let chain = setup(".grin_budget_mined", genesis.clone());
+		for n in 1..4 {
+			let prev = chain.head_header().unwrap();
+			let b = prepare_block(&kc, &prev, &chain, n + 1);
+			blocks.push(b.clone());
+			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+		}
+	}
+
+	// room for a block and a half of orphans
+	let dir = ".grin_budget";
+	clean_output_dir(dir);
+	let cap = blocks[1].memory_size() + blocks[2].memory_size() / 2;
+	let budget = Arc::new(MemoryBudget::new(cap));
+	let chain = chain::Chain::init(
+		dir.to_string(),
+		Arc::new(store::new_env(dir.to_string())),
+		Arc::new(NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		false,
+		budget.clone(),
+	).unwrap();
+
+	for b in blocks[1..].iter() {
+		assert!(chain.process_block(b.clone(), chain::Options::SKIP_POW).is_err());
+	}
+	// the orphan furthest ahead made way
+	assert!(chain.is_orphan(&blocks[1].hash()));
+	assert!(!chain.is_orphan(&blocks[2].hash()));
+	assert_eq!(chain.orphans_evicted_len(), 1);
+	assert!(budget.used() <= cap);
+
+	// the orphan kept still goes in once its parent does
+	chain
+		.process_block(blocks[0].clone(), chain::Options::SKIP_POW)
+		.unwrap();
+	assert_eq!(chain.head().unwrap().height, 2);
+}
+
 /// Test ability to retrieve block headers for a given output
 #[test]
 fn output_header_mappings() {
@@ -375,6 +567,7 @@ fn output_header_mappings() {
 	);
 	let keychain = ExtKeychain::from_random_seed().unwrap();
 	let mut reward_outputs = vec![];
+	let mut reward_kernels = vec![];
 
 	for n in 1..15 {
 		let prev = chain.head_header().unwrap();
@@ -382,6 +575,7 @@ fn output_header_mappings() {
 		let pk = ExtKeychainPath::new(1, n as u32, 0, 0, 0).to_identifier();
 		let reward = libtx::reward::output(&keychain, &pk, 0, prev.height).unwrap();
 		reward_outputs.push(reward.0.clone());
+		reward_kernels.push(reward.1.clone());
 		let mut b =
 			core::core::Block::new(&prev, vec![], next_header_info.clone().difficulty, reward)
 				.unwrap();
@@ -421,6 +615,22 @@ fn output_header_mappings() {
 			.unwrap();
 		assert_eq!(header_for_output.height, n as u64);
 	}
+
+	// And kernel ones, all in one go, an output commitment isn't a kernel
+	let mut excesses: Vec<_> = reward_kernels.iter().map(|k| k.excess).collect();
+	excesses.push(reward_outputs[0].commit);
+	let kernels = chain.get_kernels(&excesses, 0).unwrap();
+	assert_eq!(kernels.len(), 14);
+	for n in 1..15 {
+		let (ref kernel, ref header) = kernels[&reward_kernels[n - 1].excess];
+		assert_eq!(kernel.hash(), reward_kernels[n - 1].hash());
+		assert_eq!(header.height, n as u64);
+	}
+
+	// only looked for as far down as asked
+	let kernels = chain.get_kernels(&excesses, 10).unwrap();
+	assert_eq!(kernels.len(), 5);
+	assert!(kernels.values().all(|k| k.1.height >= 10));
 }
 
 fn prepare_block<K>(kc: &K, prev: &BlockHeader, chain: &Chain, diff: u64) -> Block
@@ -510,6 +720,7 @@ fn actual_diff_iter_output() {
 		pow::verify_size,
 		verifier_cache,
 		false,
+		Arc::new(MemoryBudget::unlimited()),
 	).unwrap();
 	let iter = chain.difficulty_iter();
 	let mut last_time = 0;
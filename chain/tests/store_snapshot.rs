@@ -0,0 +1,77 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate env_logger;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_store as store;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use chain::store::ChainStore;
+use chain::Tip;
+use core::core::hash::Hashed;
+use core::global::{self, ChainTypes};
+use core::pow;
+
+fn clean_output_dir(dir_name: &str) {
+	let _ = fs::remove_dir_all(dir_name);
+}
+
+// A snapshot written out holds the database as it was when taken, whatever
+// got written since
+#[test]
+fn test_store_snapshot() {
+	let _ = env_logger::try_init();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let chain_dir = ".grin_snapshot";
+	let dest = ".grin_snapshot_dest";
+	clean_output_dir(chain_dir);
+	clean_output_dir(dest);
+
+	let db_env = Arc::new(store::new_env(chain_dir.to_string()));
+	let store = ChainStore::new(db_env).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	{
+		let batch = store.batch().unwrap();
+		batch.save_block_header(&genesis.header).unwrap();
+		batch.save_block(&genesis).unwrap();
+		batch.save_head(&Tip::from_header(&genesis.header)).unwrap();
+		batch.commit().unwrap();
+	}
+
+	let snapshot = store.snapshot().unwrap();
+	let mut header = genesis.header.clone();
+	header.height = 1;
+	header.prev_hash = genesis.hash();
+	header.pow.proof = pow::Proof::random(global::proofsize());
+	{
+		let batch = store.batch().unwrap();
+		batch.save_block_header(&header).unwrap();
+		batch.save_head(&Tip::from_header(&header)).unwrap();
+		batch.commit().unwrap();
+	}
+	ChainStore::write_snapshot(snapshot, Path::new(dest)).unwrap();
+
+	let copy = ChainStore::new(Arc::new(store::new_env(dest.to_string()))).unwrap();
+	assert_eq!(copy.head().unwrap().last_block_h, genesis.hash());
+	assert!(copy.get_block(&genesis.hash()).is_ok());
+	assert!(copy.get_block_header(&header.hash()).is_err());
+	assert_eq!(store.head().unwrap().last_block_h, header.hash());
+
+	clean_output_dir(chain_dir);
+	clean_output_dir(dest);
+}
@@ -25,7 +25,7 @@ extern crate rand;
 use chrono::Duration;
 use std::fs;
 use std::sync::Arc;
-use util::RwLock;
+use util::{MemoryBudget, RwLock};
 
 use chain::types::NoopAdapter;
 use chain::ErrorKind;
@@ -60,6 +60,7 @@ fn test_coinbase_maturity() {
 		pow::verify_size,
 		verifier_cache,
 		false,
+		Arc::new(MemoryBudget::unlimited()),
 	).unwrap();
 
 	let prev = chain.head_header().unwrap();
@@ -43,6 +43,16 @@ fn comments() -> HashMap<String, String> {
 		"api_http_addr".to_string(),
 		"
 #the address on which services will listen, e.g. Transaction Pool
+
+#path of TLS certificate file, to serve the API over https, self-signed
+#certificates are not supported (wallets connecting would reject them)
+#tls_certificate_file = \"\"
+#private key for the TLS certificate
+#tls_certificate_key = \"\"
+
+#origins allowed to call the API from a browser (CORS), e.g. a block
+#explorer, \"*\" for any
+#api_allowed_origins = [\"https://explorer.example.com\"]
 ".to_string(),
 	);
 
@@ -54,6 +64,14 @@ fn comments() -> HashMap<String, String> {
 ".to_string(),
 	);
 
+	retval.insert(
+		"api_read_secret_path".to_string(),
+		"
+#path of a second secret, only allowed read-only calls (no peer bans,
+#compaction or pool submissions)
+".to_string(),
+	);
+
 	retval.insert(
 		"db_root".to_string(),
 		"
@@ -73,6 +91,22 @@ fn comments() -> HashMap<String, String> {
 #Testnet2 - Testnet2 genesis block (cuckoo 30)
 #Testnet3 - Testnet3 genesis block (cuckoo 30)
 #Testnet4 - Testnet4 genesis block (cuckatoo 29+)
+#Mainnet - Main production network
+#Set by `grin --network <mainnet|floonet|usernet>`, floonet being Testnet4.
+#The node refuses a db_root created for another chain type.
+".to_string(),
+	);
+
+	retval.insert(
+		"network_definition".to_string(),
+		"
+#network definition file of a private network (UserTesting or
+#AutomatedTesting), overriding consensus parameters of the chain type, e.g.
+#  coinbase_maturity = 10
+#  block_time_sec = 10
+#  initial_difficulty = 1
+#refused on mainnet and the public test networks
+#network_definition = \"usernet.toml\"
 ".to_string(),
 	);
 
@@ -100,6 +134,69 @@ fn comments() -> HashMap<String, String> {
 ".to_string(),
 	);
 
+	retval.insert(
+		"shutdown_drain_timeout_secs".to_string(),
+		"
+#seconds given to in-flight work, like a block being committed, to finish
+#on shutdown (SIGINT or SIGTERM) before exiting regardless
+".to_string(),
+	);
+
+	retval.insert(
+		"db_map_increment_mb".to_string(),
+		"
+#megabytes the database map starts at and grows by each time it fills up,
+#the write that filled it being retried transparently
+".to_string(),
+	);
+
+	retval.insert(
+		"db_map_max_mb".to_string(),
+		"
+#megabytes the database map can't grow past
+".to_string(),
+	);
+
+	retval.insert(
+		"db_backup_before_migration".to_string(),
+		"
+#whether to back the chain data up next to the data directory before
+#migrating it to the database format of a newer version, on startup
+".to_string(),
+	);
+
+	retval.insert(
+		"check_resource_limits".to_string(),
+		"
+#whether to refuse starting when the most peers and stratum workers
+#configured, with the threads and files the node uses otherwise, don't fit
+#the limits on open files (ulimit -n) and processes (ulimit -u)
+".to_string(),
+	);
+
+	retval.insert(
+		"memory_cap_mb".to_string(),
+		"
+#megabytes the orphan blocks and the caches of the chain and transaction pool
+#can take together, past which each gives up entries in proportion to what it
+#holds, so a flood of large orphans can't run a small node out of memory.
+#0 for no limit.
+".to_string(),
+	);
+
+	retval.insert(
+		"pid_file".to_string(),
+		"
+#file holding the process id of the running server, written by
+#`grin server run` and `grin server start` and used by `grin server stop`
+#and `grin server status`, grin.pid in the db_root when not set
+
+#directory `grin server backup` writes backups under, relative
+#destinations being taken from it. Backups are refused when not set
+#backup_dir = \"/path/to/backups\"
+".to_string(),
+	);
+
 	retval.insert(
 		"run_tui".to_string(),
 		"
@@ -140,10 +237,25 @@ fn comments() -> HashMap<String, String> {
 ".to_string(),
 	);
 
+	retval.insert(
+		"embargo_jitter_secs".to_string(),
+		"
+#most added at random to the embargo, each tx waiting between embargo_secs and
+#embargo_secs + embargo_jitter_secs
+".to_string(),
+	);
+
 	retval.insert(
 		"patience_secs".to_string(),
 		"
-#run dandelion stem/fluff processing every n secs (stem tx aggregation in this window)
+#run dandelion stem/fluff processing every n secs
+".to_string(),
+	);
+	retval.insert(
+		"aggregation_secs".to_string(),
+		"
+#hold fresh stem txs for n secs at least, all those received in the meantime
+#being aggregated into a single tx (must be less than embargo_secs)
 ".to_string(),
 	);
 	retval.insert(
@@ -202,6 +314,11 @@ fn comments() -> HashMap<String, String> {
 #a list of preferred peers to connect to
 #peers_preferred = [\"192.168.0.1:13414\",\"192.168.0.2:13414\"]
 
+#long-lived peers of each network (mainnet, floonet or usernet), only tried
+#when no DNS seed resolves and no peer is known yet, an entry replaces the
+#built-in list of its network (an empty list disables the fallback)
+#fallback_peers = { floonet = [\"192.168.0.5:13414\"] }
+
 #how long a banned peer should stay banned
 #ban_window = 10800
 
@@ -212,7 +329,57 @@ fn comments() -> HashMap<String, String> {
 #until we get to at least this number
 #peer_min_preferred_count = 8
 
-# 15 = Bit flags for FULL_NODE
+#a new block is sent right away to that many peers, the fastest first, the
+#others following in batches of that many (0 to send to all at once)
+#broadcast_batch_size = 4
+
+#milliseconds between the batches of peers a new block is sent to
+#broadcast_stagger_ms = 100
+
+#timeouts of the connections with peers, slow links (satellite, Tor) may
+#need much larger ones
+#seconds to dial a peer and complete a handshake with it
+#handshake_timeout_secs = 10
+
+#seconds to receive the rest of a message once it started coming in
+#read_timeout_secs = 20
+
+#seconds to send a message
+#write_timeout_secs = 10
+
+#seconds to send or receive each chunk of an attachment (txhashset archive)
+#attachment_timeout_secs = 10
+
+#hex prefixes of the kernel excesses of the only transactions we want relayed
+#to us, set on the peers accepting a relay filter (light or special-purpose
+#nodes only)
+#relay_filter = [\"08ab\", \"09\"]
+
+#keep an audit log of peer connections, failed handshakes, bans and
+#misbehavior, queried through /v1/peers/events
+#audit_log = false
+
+#how many days events are kept in the audit log
+#audit_log_days = 30
+
+#share of the limits on open files and threads, in percent, past which no
+#new peer connection is made or accepted
+#resource_cap_percent = 90
+
+#average seconds between two rotations of some of our outbound peers,
+#replaced with peers from less represented networks to make eclipse attacks
+#harder (0 to never rotate)
+#rotation_interval_secs = 1800
+
+#share of the outbound peers, in percent, replaced on each rotation
+#rotation_percent = 20
+
+#file mapping IP prefixes to the autonomous systems announcing them, one
+#\"prefix/length ASN\" entry per line, to group peers by ASN rather than /16
+#asn_map_file = \"/path/to/asn_map.txt\"
+
+# 55 = Bit flags for FULL_NODE, plus serving the txhashset in segments and
+#accepting relay filters
 #This structure needs to be changed internally, to make it more configurable
 ".to_string(),
 	);
@@ -247,6 +414,161 @@ fn comments() -> HashMap<String, String> {
 ".to_string(),
 	);
 
+	retval.insert(
+		"min_input_confirmations".to_string(),
+		"
+#confirmations the outputs spent by a transaction need for it to be accepted,
+#outputs of other transactions in the pool having none (0 for no requirement)
+".to_string(),
+	);
+
+	retval.insert(
+		"tx_ttl_blocks".to_string(),
+		"
+#number of blocks a transaction can wait in the pool unconfirmed before being
+#dropped (0 to never expire)
+".to_string(),
+	);
+
+	retval.insert(
+		"[server.api_limits]".to_string(),
+		"
+#########################################
+### API LIMITS                        ###
+#########################################
+
+#limits on the API for nodes serving it publicly, 0 for none
+".to_string(),
+	);
+
+	retval.insert(
+		"rate_limit".to_string(),
+		"
+#requests allowed per minute from each client address (local ones aren't
+#limited), a request for blocks, outputs or txhashset data counting for 10
+".to_string(),
+	);
+
+	retval.insert(
+		"max_request_size".to_string(),
+		"
+#largest request body accepted, in bytes
+".to_string(),
+	);
+
+	retval.insert(
+		"max_response_size".to_string(),
+		"
+#largest response body sent, in bytes
+".to_string(),
+	);
+
+	retval.insert(
+		"[server.readiness]".to_string(),
+		"
+#########################################
+### READINESS                         ###
+#########################################
+
+#what the node has to meet to answer /readyz with 200 rather than 503, for
+#load balancers and orchestration (/healthz only tells the process is up)
+".to_string(),
+	);
+
+	retval.insert(
+		"max_blocks_behind".to_string(),
+		"
+#how many blocks the chain can be behind the highest connected peer
+".to_string(),
+	);
+
+	retval.insert(
+		"min_peers".to_string(),
+		"
+#connected peers needed
+".to_string(),
+	);
+
+	retval.insert(
+		"[server.webhooks]".to_string(),
+		"
+#########################################
+### WEBHOOKS                          ###
+#########################################
+
+#URLs POSTed a JSON {\"event\": ..., \"data\": ...} on chain events, once
+#synced. Failed deliveries are retried with a doubling delay.
+".to_string(),
+	);
+
+	retval.insert(
+		"block_accepted".to_string(),
+		"
+#notified of each block becoming the chain head, e.g.
+#block_accepted = [\"http://127.0.0.1:8080/grin/blocks\"]
+".to_string(),
+	);
+
+	retval.insert(
+		"reorg".to_string(),
+		"
+#notified when the chain head switches to another fork
+".to_string(),
+	);
+
+	retval.insert(
+		"tx_confirmed".to_string(),
+		"
+#notified when a block confirms one of the watched kernels
+".to_string(),
+	);
+
+	retval.insert(
+		"watched_kernels".to_string(),
+		"
+#excess commitments, in hex, of the transaction kernels to watch
+".to_string(),
+	);
+
+	retval.insert(
+		"max_attempts".to_string(),
+		"
+#deliveries are given up after that many failed attempts
+".to_string(),
+	);
+
+	retval.insert(
+		"retry_delay_secs".to_string(),
+		"
+#seconds before retrying a failed delivery, doubling each time
+".to_string(),
+	);
+
+	retval.insert(
+		"[server.maintenance]".to_string(),
+		"
+#########################################
+### MAINTENANCE WINDOWS               ###
+#########################################
+
+#When heavy background tasks run, one at a time and never while syncing, as
+#cron-like windows in UTC: minute hour day-of-month month day-of-week. Each
+#task runs once in every run of matching minutes.
+".to_string(),
+	);
+
+	retval.insert(
+		"peer_cleanup".to_string(),
+		"
+#chain compaction, otherwise about once a day on a random block
+#compaction = \"0 3 * * *\"
+#preparation of the txhashset zip served to fast syncing peers, otherwise
+#only built when first asked for, again once the chain moves on
+#txhashset_zip = \"30 */6 * * *\"
+#removal of the expired peers from the peer store
+".to_string(),
+	);
+
 	retval.insert(
 		"[server.stratum_mining_config]".to_string(),
 		"
@@ -267,6 +589,12 @@ fn comments() -> HashMap<String, String> {
 		"stratum_server_addr".to_string(),
 		"
 #what port and address for the stratum server to listen on
+
+#path of TLS certificate file, to only accept stratum connections over TLS
+#(miners and pools connecting over untrusted networks)
+#tls_certificate_file = \"\"
+#private key for the TLS certificate
+#tls_certificate_key = \"\"
 ".to_string(),
 	);
 
@@ -285,10 +613,58 @@ fn comments() -> HashMap<String, String> {
 ".to_string(),
 	);
 
+	retval.insert(
+		"vardiff_target_shares_per_minute".to_string(),
+		"
+#shares per minute to aim for from each worker, retargeting its share
+#difficulty (between the minimum and the network's) with a
+#mining.set_difficulty request, so big farms and single rigs can mine on
+#the same node. 0 keeps all workers at the minimum share difficulty
+".to_string(),
+	);
+
+	retval.insert(
+		"vardiff_retarget_secs".to_string(),
+		"
+#seconds over which each worker's share rate is measured before retargeting
+".to_string(),
+	);
+
+	retval.insert(
+		"job_refresh_interval_secs".to_string(),
+		"
+#minimum number of seconds between two jobs sent for the same block height
+#when new transactions arrive in the pool. A new block always triggers a
+#new job straight away
+".to_string(),
+	);
+
+	retval.insert(
+		"max_workers".to_string(),
+		"
+#most workers connected at once, further connections being refused, 0 for
+#no limit
+".to_string(),
+	);
+
+	retval.insert(
+		"job_min_fee_increase".to_string(),
+		"
+#fee increase, in nanogrins, new transactions must bring before the current
+#job is rebuilt and resent to miners
+".to_string(),
+	);
+
 	retval.insert(
 		"wallet_listener_url".to_string(),
 		"
 #the wallet receiver to which coinbase rewards will be sent
+#or, to spread rewards across several receivers by weight, failing over to
+#the next one when a receiver can't be reached:
+#wallet_listeners = [
+#	{ url = \"http://127.0.0.1:13415\", weight = 70 },
+#	{ url = \"http://192.168.0.2:13415\", weight = 30 },
+#]
 ".to_string(),
 	);
 
@@ -357,6 +733,76 @@ fn comments() -> HashMap<String, String> {
 		"dark_background_color_scheme".to_string(),
 		"
 #Whether to use the black background color scheme for command line
+".to_string(),
+	);
+	retval.insert(
+		"pending_tx_ttl_hours".to_string(),
+		"
+#hours after which sends that were never finalized are cancelled, unlocking
+#their outputs
+".to_string(),
+	);
+	retval.insert(
+		"tor_binary".to_string(),
+		"
+#tor binary run by `wallet listen --tor`, tor from the path if unset
+".to_string(),
+	);
+	retval.insert(
+		"tor_socks_proxy".to_string(),
+		"
+#SOCKS proxy of a running tor, used to send to onion addresses
+".to_string(),
+	);
+	retval.insert(
+		"node_trust".to_string(),
+		"
+#how far the outputs reported by the node are trusted:
+#Trusted - as reported, for a node you run yourself
+#Verified - checked against Merkle proofs and the headers the wallet tracks,
+#for a public node
+".to_string(),
+	);
+
+	retval.insert(
+		"header_nodes".to_string(),
+		"
+#other nodes the headers tracked when Verified are checked against, warning
+#when the node lags behind them and failing when they're on another chain,
+#e.g. [\"http://192.168.0.2:13413\"]
+".to_string(),
+	);
+
+	retval.insert(
+		"header_checkpoint".to_string(),
+		"
+#header the headers tracked when Verified start from instead of the genesis
+#of the network, as \"<height>:<hash>\" from a node you trust
+".to_string(),
+	);
+
+	retval.insert(
+		"notify_command".to_string(),
+		"
+#shell command run for each payment received once confirmed, when listening
+#with --watch, given GRIN_TX_ID, GRIN_TX_SLATE_ID, GRIN_AMOUNT (in
+#nanogrins) and GRIN_CONFIRMED_AT in its environment
+".to_string(),
+	);
+
+	retval.insert(
+		"notify_url".to_string(),
+		"
+#URL each payment received is posted to as JSON once confirmed, when
+#listening with --watch
+".to_string(),
+	);
+
+	retval.insert(
+		"watch_interval_secs".to_string(),
+		"
+#seconds between two refreshes of the wallet when watching for payments
+#received
 ".to_string(),
 	);
 
@@ -415,7 +861,25 @@ fn comments() -> HashMap<String, String> {
 		"log_max_size".to_string(),
 		"
 #maximum log file size in bytes before performing log rotation
-#comment it to disable log rotation
+#comment it, along with log_max_age_hours, to disable log rotation
+".to_string(),
+	);
+
+	retval.insert(
+		"log_max_files".to_string(),
+		"
+#maximum age of the log file in hours before performing log rotation
+#log_max_age_hours = 24
+
+#number of rotated, gzip compressed, log files to keep
+".to_string(),
+	);
+
+	retval.insert(
+		"log_format".to_string(),
+		"
+#format of the log lines: Text, or Json for one JSON object per line with
+#timestamp, level, target, message and fields like peer, block hash and height
 ".to_string(),
 	);
 
@@ -423,7 +887,7 @@ fn comments() -> HashMap<String, String> {
 }
 
 fn get_key(line: &str) -> String {
-	if line.contains("[") && line.contains("]") {
+	if line.trim().starts_with("[") && line.contains("]") {
 		return line.to_owned();
 	} else if line.contains("=") {
 		return line.split("=").collect::<Vec<&str>>()[0].trim().to_owned();
@@ -26,6 +26,7 @@ use std::path::PathBuf;
 use toml;
 
 use comments::insert_comments;
+use core::global::{self, ChainTypes, NetworkOverrides};
 use servers::ServerConfig;
 use types::{
 	ConfigError, ConfigMembers, GlobalConfig, GlobalWalletConfig, GlobalWalletConfigMembers,
@@ -44,10 +45,12 @@ const GRIN_HOME: &'static str = ".grin";
 const GRIN_CHAIN_DIR: &'static str = "chain_data";
 const GRIN_WALLET_DIR: &'static str = "wallet_data";
 const API_SECRET_FILE_NAME: &'static str = ".api_secret";
+const API_READ_SECRET_FILE_NAME: &'static str = ".api_read_secret";
+const SERVER_PID_FILE_NAME: &'static str = "grin.pid";
 
-fn get_grin_path() -> Result<PathBuf, ConfigError> {
+fn get_grin_path(network: Option<&ChainTypes>) -> Result<PathBuf, ConfigError> {
 	// Check if grin dir exists
-	let grin_path = {
+	let mut grin_path = {
 		match dirs::home_dir() {
 			Some(mut p) => {
 				p.push(GRIN_HOME);
@@ -60,6 +63,10 @@ fn get_grin_path() -> Result<PathBuf, ConfigError> {
 			}
 		}
 	};
+	// each network explicitly asked for gets its own directory
+	if let Some(n) = network {
+		grin_path.push(n.shortname());
+	}
 	// Create if the default path doesn't exist
 	if !grin_path.exists() {
 		fs::create_dir_all(grin_path.clone())?;
@@ -106,11 +113,10 @@ fn check_api_secret(api_secret_path: &PathBuf) -> Result<(), ConfigError> {
 	Ok(())
 }
 
-/// Check that the api secret file exists and is valid
-fn check_api_secret_file() -> Result<(), ConfigError> {
-	let grin_path = get_grin_path()?;
+/// Check that an api secret file exists and is valid
+fn check_api_secret_file(grin_path: &PathBuf, file_name: &str) -> Result<(), ConfigError> {
 	let mut api_secret_path = grin_path.clone();
-	api_secret_path.push(API_SECRET_FILE_NAME);
+	api_secret_path.push(file_name);
 	if !api_secret_path.exists() {
 		init_api_secret(&api_secret_path)
 	} else {
@@ -118,16 +124,17 @@ fn check_api_secret_file() -> Result<(), ConfigError> {
 	}
 }
 
-/// Handles setup and detection of paths for node
-pub fn initial_setup_server() -> Result<GlobalConfig, ConfigError> {
-	check_api_secret_file()?;
+/// Handles setup and detection of paths for node, under the directory of the
+/// given network if any
+pub fn initial_setup_server(network: Option<&ChainTypes>) -> Result<GlobalConfig, ConfigError> {
+	// Check if grin dir exists
+	let grin_path = get_grin_path(network)?;
+	check_api_secret_file(&grin_path, API_SECRET_FILE_NAME)?;
+	check_api_secret_file(&grin_path, API_READ_SECRET_FILE_NAME)?;
 	// Use config file if current directory if it exists, .grin home otherwise
-	if let Some(p) = check_config_current_dir(SERVER_CONFIG_FILE_NAME) {
-		GlobalConfig::new(p.to_str().unwrap())
+	let config = if let Some(p) = check_config_current_dir(SERVER_CONFIG_FILE_NAME) {
+		GlobalConfig::new(p.to_str().unwrap())?
 	} else {
-		// Check if grin dir exists
-		let grin_path = get_grin_path()?;
-
 		// Get path to default config file
 		let mut config_path = grin_path.clone();
 		config_path.push(SERVER_CONFIG_FILE_NAME);
@@ -135,25 +142,34 @@ pub fn initial_setup_server() -> Result<GlobalConfig, ConfigError> {
 		// Spit it out if it doesn't exist
 		if !config_path.exists() {
 			let mut default_config = GlobalConfig::default();
+			if let Some(n) = network {
+				default_config.set_network(n);
+			}
 			// update paths relative to current dir
 			default_config.update_paths(&grin_path);
 			default_config.write_to_file(config_path.to_str().unwrap())?;
 		}
 
-		GlobalConfig::new(config_path.to_str().unwrap())
+		GlobalConfig::new(config_path.to_str().unwrap())?
+	};
+	if let Some(n) = network {
+		config.check_network(n)?;
 	}
+	Ok(config)
 }
 
-/// Handles setup and detection of paths for wallet
-pub fn initial_setup_wallet() -> Result<GlobalWalletConfig, ConfigError> {
-	check_api_secret_file()?;
+/// Handles setup and detection of paths for wallet, under the directory of
+/// the given network if any
+pub fn initial_setup_wallet(
+	network: Option<&ChainTypes>,
+) -> Result<GlobalWalletConfig, ConfigError> {
+	// Check if grin dir exists
+	let grin_path = get_grin_path(network)?;
+	check_api_secret_file(&grin_path, API_SECRET_FILE_NAME)?;
 	// Use config file if current directory if it exists, .grin home otherwise
-	if let Some(p) = check_config_current_dir(WALLET_CONFIG_FILE_NAME) {
-		GlobalWalletConfig::new(p.to_str().unwrap())
+	let config = if let Some(p) = check_config_current_dir(WALLET_CONFIG_FILE_NAME) {
+		GlobalWalletConfig::new(p.to_str().unwrap())?
 	} else {
-		// Check if grin dir exists
-		let grin_path = get_grin_path()?;
-
 		// Get path to default config file
 		let mut config_path = grin_path.clone();
 		config_path.push(WALLET_CONFIG_FILE_NAME);
@@ -161,13 +177,20 @@ pub fn initial_setup_wallet() -> Result<GlobalWalletConfig, ConfigError> {
 		// Spit it out if it doesn't exist
 		if !config_path.exists() {
 			let mut default_config = GlobalWalletConfig::default();
+			if let Some(n) = network {
+				default_config.set_network(n);
+			}
 			// update paths relative to current dir
 			default_config.update_paths(&grin_path);
 			default_config.write_to_file(config_path.to_str().unwrap())?;
 		}
 
-		GlobalWalletConfig::new(config_path.to_str().unwrap())
+		GlobalWalletConfig::new(config_path.to_str().unwrap())?
+	};
+	if let Some(n) = network {
+		config.check_network(n)?;
 	}
+	Ok(config)
 }
 
 /// Returns the defaults, as strewn throughout the code
@@ -207,6 +230,19 @@ impl Default for GlobalWalletConfig {
 	}
 }
 
+/// Reads the network definition file at the given path and overrides the
+/// consensus parameters it sets, once the chain type is set
+pub fn apply_network_definition(file_path: &str) -> Result<(), ConfigError> {
+	let mut contents = String::new();
+	File::open(file_path)
+		.map_err(|_| ConfigError::FileNotFoundError(file_path.to_owned()))?
+		.read_to_string(&mut contents)?;
+	let overrides: NetworkOverrides = toml::from_str(&contents)
+		.map_err(|e| ConfigError::ParseError(file_path.to_owned(), format!("{}", e)))?;
+	global::set_network_overrides(overrides)
+		.map_err(|e| ConfigError::NetworkDefinition(file_path.to_owned(), e))
+}
+
 impl GlobalConfig {
 	/// Requires the path to a config file
 	pub fn new(file_path: &str) -> Result<GlobalConfig, ConfigError> {
@@ -263,6 +299,14 @@ impl GlobalConfig {
 		secret_path.push(API_SECRET_FILE_NAME);
 		self.members.as_mut().unwrap().server.api_secret_path =
 			Some(secret_path.to_str().unwrap().to_owned());
+		let mut read_secret_path = grin_home.clone();
+		read_secret_path.push(API_READ_SECRET_FILE_NAME);
+		self.members.as_mut().unwrap().server.api_read_secret_path =
+			Some(read_secret_path.to_str().unwrap().to_owned());
+		let mut pid_path = grin_home.clone();
+		pid_path.push(SERVER_PID_FILE_NAME);
+		self.members.as_mut().unwrap().server.pid_file =
+			Some(pid_path.to_str().unwrap().to_owned());
 		let mut log_path = grin_home.clone();
 		log_path.push(SERVER_LOG_FILE_NAME);
 		self.members
@@ -274,6 +318,31 @@ impl GlobalConfig {
 			.log_file_path = log_path.to_str().unwrap().to_owned();
 	}
 
+	/// Sets up the node for the given network, its chain type and default
+	/// ports
+	pub fn set_network(&mut self, chain_type: &ChainTypes) {
+		let server = &mut self.members.as_mut().unwrap().server;
+		server.chain_type = chain_type.clone();
+		server.p2p_config.port = chain_type.default_p2p_port();
+		server.api_http_addr = format!("127.0.0.1:{}", chain_type.default_api_port());
+	}
+
+	/// Checks the configuration is for the given network
+	pub fn check_network(&self, chain_type: &ChainTypes) -> Result<(), ConfigError> {
+		let found = &self.members.as_ref().unwrap().server.chain_type;
+		if found != chain_type {
+			return Err(ConfigError::NetworkMismatch(
+				self.config_file_path
+					.as_ref()
+					.map(|p| p.to_str().unwrap().to_owned())
+					.unwrap_or_default(),
+				found.shortname(),
+				chain_type.shortname(),
+			));
+		}
+		Ok(())
+	}
+
 	/// Enable mining
 	pub fn stratum_enabled(&mut self) -> bool {
 		return self
@@ -385,6 +454,30 @@ impl GlobalWalletConfig {
 			.log_file_path = log_path.to_str().unwrap().to_owned();
 	}
 
+	/// Sets up the wallet for the given network, its chain type and the
+	/// default port of the node it talks to
+	pub fn set_network(&mut self, chain_type: &ChainTypes) {
+		let wallet = &mut self.members.as_mut().unwrap().wallet;
+		wallet.chain_type = Some(chain_type.clone());
+		wallet.check_node_api_http_addr =
+			format!("http://127.0.0.1:{}", chain_type.default_api_port());
+	}
+
+	/// Checks the configuration, if it names a network, is for the given one
+	pub fn check_network(&self, chain_type: &ChainTypes) -> Result<(), ConfigError> {
+		match self.members.as_ref().unwrap().wallet.chain_type {
+			Some(ref found) if found != chain_type => Err(ConfigError::NetworkMismatch(
+				self.config_file_path
+					.as_ref()
+					.map(|p| p.to_str().unwrap().to_owned())
+					.unwrap_or_default(),
+				found.shortname(),
+				chain_type.shortname(),
+			)),
+			_ => Ok(()),
+		}
+	}
+
 	/// Serialize config
 	pub fn ser_config(&mut self) -> Result<String, ConfigError> {
 		let encoded: Result<String, toml::ser::Error> =
@@ -26,6 +26,7 @@ extern crate rand;
 extern crate serde_derive;
 extern crate toml;
 
+extern crate grin_core as core;
 extern crate grin_p2p as p2p;
 extern crate grin_servers as servers;
 extern crate grin_util as util;
@@ -35,5 +36,5 @@ mod comments;
 pub mod config;
 pub mod types;
 
-pub use config::{initial_setup_server, initial_setup_wallet};
+pub use config::{apply_network_definition, initial_setup_server, initial_setup_wallet};
 pub use types::{ConfigError, ConfigMembers, GlobalConfig, GlobalWalletConfig};
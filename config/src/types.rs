@@ -36,6 +36,12 @@ pub enum ConfigError {
 
 	/// Error serializing config values
 	SerializationError(String),
+
+	/// Config file written for another network than the one asked for
+	NetworkMismatch(String, String, String),
+
+	/// Network definition refused for the chain type
+	NetworkDefinition(String, String),
 }
 
 impl fmt::Display for ConfigError {
@@ -55,6 +61,16 @@ impl fmt::Display for ConfigError {
 			ConfigError::SerializationError(ref message) => {
 				write!(f, "Error serializing configuration: {}", message)
 			}
+			ConfigError::NetworkMismatch(ref file_name, ref found, ref expected) => write!(
+				f,
+				"Configuration file at {} is for the {} network, not {}",
+				file_name, found, expected
+			),
+			ConfigError::NetworkDefinition(ref file_name, ref message) => write!(
+				f,
+				"Network definition at {} can't be applied - {}",
+				file_name, message
+			),
 		}
 	}
 }
@@ -0,0 +1,68 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of the header and rangeproof checks every block and
+//! transaction received goes through
+#[macro_use]
+extern crate criterion;
+extern crate grin_core;
+extern crate grin_keychain as keychain;
+extern crate grin_wallet as wallet;
+
+use criterion::Criterion;
+
+use grin_core::core::hash::Hashed;
+use grin_core::core::{BlockHeader, Output};
+use grin_core::global::{self, ChainTypes};
+use grin_core::{pow, ser};
+use keychain::{ExtKeychain, Keychain};
+use wallet::libtx::proof;
+
+// deserializing a header as received, hashing it and verifying its proof of
+// work
+fn header_validation(c: &mut Criterion) {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let genesis = pow::mine_genesis_block().unwrap();
+	let data = ser::ser_vec(&genesis.header).unwrap();
+	c.bench_function("header validation", move |b| {
+		b.iter(|| {
+			let header: BlockHeader = ser::deserialize(&mut &data[..]).unwrap();
+			header.hash();
+			pow::verify_size(&header, header.pow.edge_bits()).unwrap();
+		})
+	});
+}
+
+fn rangeproof_batch_verification(c: &mut Criterion) {
+	let keychain = ExtKeychain::from_random_seed().unwrap();
+	let mut commits = vec![];
+	let mut proofs = vec![];
+	for n in 0..100 {
+		let key_id = ExtKeychain::derive_key_id(1, n, 0, 0, 0);
+		let commit = keychain.commit(5, &key_id).unwrap();
+		proofs.push(proof::create(&keychain, 5, &key_id, commit, None).unwrap());
+		commits.push(commit);
+	}
+	c.bench_function_over_inputs(
+		"rangeproof batch verification",
+		move |b, &n| {
+			let (commits, proofs) = (commits[..n].to_vec(), proofs[..n].to_vec());
+			b.iter(|| Output::batch_verify_proofs(&commits, &proofs).unwrap())
+		},
+		vec![1, 10, 100],
+	);
+}
+
+criterion_group!(benches, header_validation, rangeproof_batch_verification);
+criterion_main!(benches);
@@ -284,14 +284,17 @@ where
 		.map(|dd| dd.difficulty.to_num())
 		.sum();
 
-	// adjust time delta toward goal subject to dampening and clamping
+	// adjust time delta toward goal subject to dampening and clamping, the
+	// block time being overridable on private networks
+	let block_time = global::block_time_sec();
+	let block_time_window = DIFFICULTY_ADJUST_WINDOW * block_time;
 	let adj_ts = clamp(
-		damp(ts_delta, BLOCK_TIME_WINDOW, DAMP_FACTOR),
-		BLOCK_TIME_WINDOW,
+		damp(ts_delta, block_time_window, DAMP_FACTOR),
+		block_time_window,
 		CLAMP_FACTOR,
 	);
 	// minimum difficulty avoids getting stuck due to dampening
-	let difficulty = max(MIN_DIFFICULTY, diff_sum * BLOCK_TIME_SEC / adj_ts);
+	let difficulty = max(MIN_DIFFICULTY, diff_sum * block_time / adj_ts);
 
 	HeaderInfo::from_diff_scaling(Difficulty::from_num(difficulty), sec_pow_scaling)
 }
@@ -272,6 +272,11 @@ impl BlockHeader {
 		header_buf
 	}
 
+	/// Rough estimate of the memory the header takes, in bytes
+	pub fn memory_size(&self) -> usize {
+		mem::size_of::<BlockHeader>() + self.pow.proof.nonces.len() * mem::size_of::<u64>()
+	}
+
 	/// Total difficulty accumulated by the proof of work on this header
 	pub fn total_difficulty(&self) -> Difficulty {
 		self.pow.total_difficulty
@@ -533,6 +538,11 @@ impl Block {
 		self.header.hash()
 	}
 
+	/// Rough estimate of the memory the block takes, in bytes
+	pub fn memory_size(&self) -> usize {
+		self.header.memory_size() + self.body.memory_size()
+	}
+
 	/// Sum of all fees (inputs less outputs) in the block
 	pub fn total_fees(&self) -> u64 {
 		self.body
@@ -120,7 +120,30 @@ impl MerkleProof {
 		} else {
 			element.hash_with_index(node_pos - 1)
 		};
+		self.verify_consume_hash(root, node_hash, node_pos, peaks_pos)
+	}
+
+	/// Verifies the Merkle proof against the provided root hash, for a node
+	/// given by its hash rather than its content, such as the root of a
+	/// subtree that has been pruned.
+	pub fn verify_node_hash(
+		&self,
+		root: Hash,
+		node_hash: Hash,
+		node_pos: u64,
+	) -> Result<(), MerkleProofError> {
+		let mut proof = self.clone();
+		let peaks_pos = pmmr::peaks(self.mmr_size);
+		proof.verify_consume_hash(root, node_hash, node_pos, &peaks_pos)
+	}
 
+	fn verify_consume_hash(
+		&mut self,
+		root: Hash,
+		node_hash: Hash,
+		node_pos: u64,
+		peaks_pos: &[u64],
+	) -> Result<(), MerkleProofError> {
 		// handle special case of only a single entry in the MMR
 		// (no siblings to hash together)
 		if self.path.is_empty() {
@@ -19,7 +19,7 @@ use croaring::Bitmap;
 
 use core::hash::{Hash, ZERO_HASH};
 use core::merkle_proof::MerkleProof;
-use core::pmmr::{Backend, ReadonlyPMMR};
+use core::pmmr::{Backend, ReadonlyPMMR, Segment, SegmentIdentifier, SegmentProof};
 use core::BlockHeader;
 use ser::{PMMRIndexHashable, PMMRable};
 
@@ -150,6 +150,12 @@ where
 		self.get_hash(pos)
 			.ok_or_else(|| format!("no element at pos {}", pos))?;
 
+		Ok(self.node_merkle_proof(pos))
+	}
+
+	// Merkle proof for the node at the given position, leaf or not, which
+	// the caller checked we have.
+	fn node_merkle_proof(&self, pos: u64) -> MerkleProof {
 		let mmr_size = self.unpruned_size();
 
 		let family_branch = family_branch(pos, self.last_pos);
@@ -166,7 +172,52 @@ where
 
 		path.append(&mut self.peak_path(peak_pos));
 
-		Ok(MerkleProof { mmr_size, path })
+		MerkleProof { mmr_size, path }
+	}
+
+	/// Build the segment with the provided identifier, holding the hashes
+	/// and leaf data in its range that haven't been compacted away, as well
+	/// as proofs for its roots. A root compacted away is replaced by the
+	/// pruned node above it.
+	pub fn segment(&self, id: SegmentIdentifier, prunable: bool) -> Result<Segment<T>, String> {
+		let (first, last) = id
+			.pos_range(self.last_pos)
+			.ok_or_else(|| format!("segment {:?} past mmr size {}", id, self.last_pos))?;
+
+		let mut segment = Segment {
+			identifier: id,
+			hashes: vec![],
+			leaves: vec![],
+			leaf_set: vec![],
+			proofs: vec![],
+		};
+		for pos in first..(last + 1) {
+			if let Some(hash) = self.get_from_file(pos) {
+				segment.hashes.push((pos, hash));
+			}
+			if is_leaf(pos) {
+				if let Some(data) = self.backend.get_data_from_file(pos) {
+					segment.leaves.push((pos, data));
+				}
+				if prunable && self.backend.get_hash(pos).is_some() {
+					segment.leaf_set.push(pos);
+				}
+			}
+		}
+
+		for root in id.roots(self.last_pos) {
+			let (pos, hash) = path(root, self.last_pos)
+				.into_iter()
+				.filter_map(|p| self.get_from_file(p).map(|h| (p, h)))
+				.next()
+				.ok_or_else(|| format!("no hash above segment root {}", root))?;
+			segment.proofs.push(SegmentProof {
+				pos,
+				hash,
+				proof: self.node_merkle_proof(pos),
+			});
+		}
+		Ok(segment)
 	}
 
 	/// Push a new element into the MMR. Computes new related peaks at
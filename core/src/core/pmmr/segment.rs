@@ -0,0 +1,443 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Segments of an MMR. A segment covers a subtree of 2^height consecutive
+//! leaves and carries what a (possibly pruned) MMR still holds in that
+//! range, along with Merkle proofs linking it to the MMR root. This allows
+//! downloading a full MMR in pieces from many peers, checking each piece
+//! as it arrives.
+
+use std::cmp;
+use std::collections::HashMap;
+
+use core::hash::Hash;
+use core::merkle_proof::MerkleProof;
+use core::pmmr;
+use core::transaction::{OutputIdentifier, TxKernel};
+use ser::{self, PMMRIndexHashable, PMMRable, Readable, Reader, Writeable, Writer};
+use util::secp::pedersen::RangeProof;
+
+/// Highest segment height we're willing to read, bounding segment sizes
+pub const MAX_SEGMENT_HEIGHT: u8 = 16;
+
+/// The txhashset MMR a segment belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SegmentType {
+	/// Output MMR
+	Output,
+	/// Range proof MMR
+	RangeProof,
+	/// Kernel MMR
+	Kernel,
+}
+
+impl SegmentType {
+	/// Segment height used to split this MMR, keeping segments of each type
+	/// at a few hundred kB
+	pub fn default_height(&self) -> u8 {
+		match *self {
+			SegmentType::Output => 11,
+			SegmentType::RangeProof => 9,
+			SegmentType::Kernel => 11,
+		}
+	}
+
+	/// Whether the MMR is pruned as outputs get spent
+	pub fn is_prunable(&self) -> bool {
+		match *self {
+			SegmentType::Output | SegmentType::RangeProof => true,
+			SegmentType::Kernel => false,
+		}
+	}
+}
+
+impl Writeable for SegmentType {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		let t = match *self {
+			SegmentType::Output => 0,
+			SegmentType::RangeProof => 1,
+			SegmentType::Kernel => 2,
+		};
+		writer.write_u8(t)
+	}
+}
+
+impl Readable for SegmentType {
+	fn read(reader: &mut Reader) -> Result<SegmentType, ser::Error> {
+		match reader.read_u8()? {
+			0 => Ok(SegmentType::Output),
+			1 => Ok(SegmentType::RangeProof),
+			2 => Ok(SegmentType::Kernel),
+			_ => Err(ser::Error::CorruptedData),
+		}
+	}
+}
+
+/// Identifies a segment by its height and index. Segment `idx` of height
+/// `h` holds the leaves `idx * 2^h` to `(idx + 1) * 2^h - 1`, counting
+/// leaves from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentIdentifier {
+	/// Height of the subtree covered by the segment
+	pub height: u8,
+	/// Index of the segment among the segments of that height
+	pub idx: u64,
+}
+
+impl SegmentIdentifier {
+	/// Number of segments of the given height covering an MMR of the
+	/// given size.
+	pub fn count(height: u8, mmr_size: u64) -> u64 {
+		let per_segment = 1u64 << height;
+		(pmmr::n_leaves(mmr_size) + per_segment - 1) / per_segment
+	}
+
+	/// First and last MMR positions covered by the segment, the last one
+	/// capped by the MMR size. None if the segment starts past the end of the
+	/// MMR.
+	pub fn pos_range(&self, mmr_size: u64) -> Option<(u64, u64)> {
+		let first_leaf = self.idx << self.height;
+		if mmr_size == 0 || first_leaf >= pmmr::n_leaves(mmr_size) {
+			return None;
+		}
+		let first = pmmr::insertion_to_pmmr_index(first_leaf + 1);
+		let last = first + self.subtree_size() - 1;
+		Some((first, cmp::min(last, mmr_size)))
+	}
+
+	/// Positions of the roots of the subtrees making up the segment. That's
+	/// the single root of its subtree if the segment is complete, or the peaks
+	/// of the MMR it holds if it's the last, incomplete one.
+	pub fn roots(&self, mmr_size: u64) -> Vec<u64> {
+		match self.pos_range(mmr_size) {
+			None => vec![],
+			Some((first, last)) => {
+				if last == first + self.subtree_size() - 1 {
+					vec![last]
+				} else {
+					pmmr::peaks(mmr_size)
+						.into_iter()
+						.filter(|p| *p >= first)
+						.collect()
+				}
+			}
+		}
+	}
+
+	fn subtree_size(&self) -> u64 {
+		(1u64 << (self.height as u64 + 1)) - 1
+	}
+}
+
+impl Writeable for SegmentIdentifier {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.height)?;
+		writer.write_u64(self.idx)
+	}
+}
+
+impl Readable for SegmentIdentifier {
+	fn read(reader: &mut Reader) -> Result<SegmentIdentifier, ser::Error> {
+		let height = reader.read_u8()?;
+		if height > MAX_SEGMENT_HEIGHT {
+			return Err(ser::Error::CorruptedData);
+		}
+		let idx = reader.read_u64()?;
+		Ok(SegmentIdentifier { height, idx })
+	}
+}
+
+/// Hash of a node covering a segment, with the Merkle proof that it belongs
+/// to the MMR.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentProof {
+	/// Position of the node
+	pub pos: u64,
+	/// Hash of the node
+	pub hash: Hash,
+	/// Proof of the node against the MMR root
+	pub proof: MerkleProof,
+}
+
+/// Reasons a segment can be rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentError {
+	/// The segment starts past the end of the MMR
+	OutOfRange,
+	/// An entry is outside of the segment, not a leaf or out of order
+	BadPosition(u64),
+	/// The data of a leaf doesn't match its hash
+	LeafMismatch(u64),
+	/// A hash is missing, or doesn't match the hashes of its children
+	BadHash(u64),
+	/// A root of the segment isn't proven to belong to the MMR
+	BadProof(u64),
+}
+
+/// A segment of an MMR, holding everything the MMR hasn't compacted away
+/// in the range of the segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment<T> {
+	/// Which segment of the MMR this is
+	pub identifier: SegmentIdentifier,
+	/// Hashes at every position in the range not compacted away, ascending
+	pub hashes: Vec<(u64, Hash)>,
+	/// Data of every leaf in the range not compacted away, ascending
+	pub leaves: Vec<(u64, T)>,
+	/// Leaves not spent yet, for prunable MMRs
+	pub leaf_set: Vec<u64>,
+	/// One proof for each root of the segment, or for the pruned node
+	/// covering it
+	pub proofs: Vec<SegmentProof>,
+}
+
+impl<T> Segment<T>
+where
+	T: PMMRable,
+{
+	/// Approximate size of the segment once serialized, in bytes
+	pub fn byte_size(&self) -> u64 {
+		(self.hashes.len() * 40 + self.leaves.len() * (8 + T::LEN) + self.leaf_set.len() * 8)
+			as u64
+	}
+
+	/// Checks the segment is consistent and belongs to the MMR of the
+	/// provided size and root. Nodes are rebuilt bottom-up from their
+	/// children where we have them, while a hash without children stands for
+	/// a pruned subtree, only allowed in prunable MMRs.
+	pub fn validate(&self, mmr_size: u64, root: Hash, prunable: bool) -> Result<(), SegmentError> {
+		let (first, last) = self
+			.identifier
+			.pos_range(mmr_size)
+			.ok_or(SegmentError::OutOfRange)?;
+
+		check_positions(self.hashes.iter().map(|x| x.0), first, last, false)?;
+		check_positions(self.leaves.iter().map(|x| x.0), first, last, true)?;
+		check_positions(self.leaf_set.iter().cloned(), first, last, true)?;
+
+		let hashes: HashMap<u64, Hash> = self.hashes.iter().cloned().collect();
+		let leaves: HashMap<u64, &T> = self.leaves.iter().map(|x| (x.0, &x.1)).collect();
+		for &(pos, ref data) in &self.leaves {
+			if hashes.get(&pos) != Some(&data.hash_with_index(pos - 1)) {
+				return Err(SegmentError::LeafMismatch(pos));
+			}
+		}
+		if !prunable && !self.leaf_set.is_empty() {
+			return Err(SegmentError::BadPosition(self.leaf_set[0]));
+		}
+		for pos in &self.leaf_set {
+			if !leaves.contains_key(pos) {
+				return Err(SegmentError::BadPosition(*pos));
+			}
+		}
+
+		// None for the positions compacted away, along with their subtree
+		let mut nodes: HashMap<u64, Option<Hash>> = HashMap::new();
+		for pos in first..(last + 1) {
+			let height = pmmr::bintree_postorder_height(pos);
+			let node = if height == 0 {
+				if hashes.contains_key(&pos) && !leaves.contains_key(&pos) {
+					return Err(SegmentError::LeafMismatch(pos));
+				}
+				hashes.get(&pos).cloned()
+			} else {
+				let left = nodes[&(pos - (1 << height))];
+				let right = nodes[&(pos - 1)];
+				match (left, right, hashes.get(&pos)) {
+					(Some(l), Some(r), Some(h)) => {
+						if (l, r).hash_with_index(pos - 1) != *h {
+							return Err(SegmentError::BadHash(pos));
+						}
+						Some(*h)
+					}
+					(None, None, Some(h)) => Some(*h),
+					(None, None, None) => None,
+					_ => return Err(SegmentError::BadHash(pos)),
+				}
+			};
+			if node.is_none() && !prunable {
+				return Err(SegmentError::BadHash(pos));
+			}
+			nodes.insert(pos, node);
+		}
+
+		let roots = self.identifier.roots(mmr_size);
+		if roots.len() != self.proofs.len() {
+			return Err(SegmentError::BadProof(last));
+		}
+		for (root_pos, proof) in roots.iter().zip(self.proofs.iter()) {
+			let covered = match nodes[root_pos] {
+				Some(h) => proof.pos == *root_pos && proof.hash == h,
+				// compacted, so there must be a pruned node above it
+				None => {
+					proof.pos != *root_pos && pmmr::path(*root_pos, mmr_size).contains(&proof.pos)
+				}
+			};
+			if !covered || proof.proof.mmr_size != mmr_size {
+				return Err(SegmentError::BadProof(*root_pos));
+			}
+			proof
+				.proof
+				.verify_node_hash(root, proof.hash, proof.pos)
+				.map_err(|_| SegmentError::BadProof(proof.pos))?;
+		}
+		Ok(())
+	}
+}
+
+// Positions must be in range and strictly ascending, and leaves if asked.
+fn check_positions<I>(positions: I, first: u64, last: u64, leaves: bool) -> Result<(), SegmentError>
+where
+	I: Iterator<Item = u64>,
+{
+	let mut prev = 0;
+	for pos in positions {
+		if pos < first || pos > last || pos <= prev || (leaves && !pmmr::is_leaf(pos)) {
+			return Err(SegmentError::BadPosition(pos));
+		}
+		prev = pos;
+	}
+	Ok(())
+}
+
+impl<T: PMMRable> Writeable for Segment<T> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.identifier.write(writer)?;
+		writer.write_u64(self.hashes.len() as u64)?;
+		for &(pos, ref hash) in &self.hashes {
+			writer.write_u64(pos)?;
+			hash.write(writer)?;
+		}
+		writer.write_u64(self.leaves.len() as u64)?;
+		for &(pos, ref data) in &self.leaves {
+			writer.write_u64(pos)?;
+			data.write(writer)?;
+		}
+		writer.write_u64(self.leaf_set.len() as u64)?;
+		for pos in &self.leaf_set {
+			writer.write_u64(*pos)?;
+		}
+		writer.write_u64(self.proofs.len() as u64)?;
+		for proof in &self.proofs {
+			writer.write_u64(proof.pos)?;
+			proof.hash.write(writer)?;
+			proof.proof.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl<T: PMMRable> Readable for Segment<T> {
+	fn read(reader: &mut Reader) -> Result<Segment<T>, ser::Error> {
+		let identifier = SegmentIdentifier::read(reader)?;
+		// nothing in a segment can outnumber the nodes of its subtree
+		let max_len = identifier.subtree_size();
+		let read_len = |reader: &mut Reader| -> Result<u64, ser::Error> {
+			let len = reader.read_u64()?;
+			if len > max_len {
+				return Err(ser::Error::TooLargeReadErr);
+			}
+			Ok(len)
+		};
+
+		let mut hashes = vec![];
+		for _ in 0..read_len(reader)? {
+			hashes.push((reader.read_u64()?, Hash::read(reader)?));
+		}
+		let mut leaves = vec![];
+		for _ in 0..read_len(reader)? {
+			leaves.push((reader.read_u64()?, T::read(reader)?));
+		}
+		let mut leaf_set = vec![];
+		for _ in 0..read_len(reader)? {
+			leaf_set.push(reader.read_u64()?);
+		}
+		let mut proofs = vec![];
+		for _ in 0..read_len(reader)? {
+			proofs.push(SegmentProof {
+				pos: reader.read_u64()?,
+				hash: Hash::read(reader)?,
+				proof: MerkleProof::read(reader)?,
+			});
+		}
+		Ok(Segment {
+			identifier,
+			hashes,
+			leaves,
+			leaf_set,
+			proofs,
+		})
+	}
+}
+
+/// A segment of any of the txhashset MMRs.
+#[derive(Debug, Clone)]
+pub enum TxHashSetSegment {
+	/// Output MMR segment
+	Output(Segment<OutputIdentifier>),
+	/// Range proof MMR segment
+	RangeProof(Segment<RangeProof>),
+	/// Kernel MMR segment
+	Kernel(Segment<TxKernel>),
+}
+
+impl TxHashSetSegment {
+	/// MMR the segment belongs to
+	pub fn segment_type(&self) -> SegmentType {
+		match *self {
+			TxHashSetSegment::Output(_) => SegmentType::Output,
+			TxHashSetSegment::RangeProof(_) => SegmentType::RangeProof,
+			TxHashSetSegment::Kernel(_) => SegmentType::Kernel,
+		}
+	}
+
+	/// Identifier of the segment within its MMR
+	pub fn identifier(&self) -> SegmentIdentifier {
+		match *self {
+			TxHashSetSegment::Output(ref s) => s.identifier,
+			TxHashSetSegment::RangeProof(ref s) => s.identifier,
+			TxHashSetSegment::Kernel(ref s) => s.identifier,
+		}
+	}
+
+	/// Approximate size of the segment once serialized, in bytes
+	pub fn byte_size(&self) -> u64 {
+		match *self {
+			TxHashSetSegment::Output(ref s) => s.byte_size(),
+			TxHashSetSegment::RangeProof(ref s) => s.byte_size(),
+			TxHashSetSegment::Kernel(ref s) => s.byte_size(),
+		}
+	}
+}
+
+impl Writeable for TxHashSetSegment {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.segment_type().write(writer)?;
+		match *self {
+			TxHashSetSegment::Output(ref s) => s.write(writer),
+			TxHashSetSegment::RangeProof(ref s) => s.write(writer),
+			TxHashSetSegment::Kernel(ref s) => s.write(writer),
+		}
+	}
+}
+
+impl Readable for TxHashSetSegment {
+	fn read(reader: &mut Reader) -> Result<TxHashSetSegment, ser::Error> {
+		match SegmentType::read(reader)? {
+			SegmentType::Output => Ok(TxHashSetSegment::Output(Segment::read(reader)?)),
+			SegmentType::RangeProof => Ok(TxHashSetSegment::RangeProof(Segment::read(reader)?)),
+			SegmentType::Kernel => Ok(TxHashSetSegment::Kernel(Segment::read(reader)?)),
+		}
+	}
+}
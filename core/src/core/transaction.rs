@@ -17,6 +17,7 @@
 use std::cmp::max;
 use std::cmp::Ordering;
 use std::collections::HashSet;
+use std::mem;
 use std::sync::Arc;
 use std::{error, fmt};
 use util::RwLock;
@@ -438,6 +439,15 @@ impl TransactionBody {
 		TransactionBody::weight_as_block(self.inputs.len(), self.outputs.len(), self.kernels.len())
 	}
 
+	/// Rough estimate of the memory the body takes, in bytes. Range proofs
+	/// are held in fixed size buffers so outputs account for most of it.
+	pub fn memory_size(&self) -> usize {
+		mem::size_of::<TransactionBody>()
+			+ self.inputs.len() * mem::size_of::<Input>()
+			+ self.outputs.len() * mem::size_of::<Output>()
+			+ self.kernels.len() * mem::size_of::<TxKernel>()
+	}
+
 	/// Calculate transaction weight from transaction details. This is non
 	/// consensus critical and compared to block weight, incentivizes spending
 	/// more outputs (to lower the fee).
@@ -800,6 +810,11 @@ impl Transaction {
 		self.body.body_weight_as_block()
 	}
 
+	/// Rough estimate of the memory the transaction takes, in bytes
+	pub fn memory_size(&self) -> usize {
+		mem::size_of::<BlindingFactor>() + self.body.memory_size()
+	}
+
 	/// Calculate transaction weight from transaction details
 	pub fn weight(input_len: usize, output_len: usize, kernel_len: usize) -> u32 {
 		TransactionBody::weight(input_len, output_len, kernel_len)
@@ -19,8 +19,8 @@
 use consensus::HeaderInfo;
 use consensus::{
 	graph_weight, BASE_EDGE_BITS, BLOCK_TIME_SEC, COINBASE_MATURITY, CUT_THROUGH_HORIZON,
-	DAY_HEIGHT, DIFFICULTY_ADJUST_WINDOW, INITIAL_DIFFICULTY, PROOFSIZE, SECOND_POW_EDGE_BITS,
-	UNIT_DIFFICULTY
+	DAY_HEIGHT, DIFFICULTY_ADJUST_WINDOW, HOUR_HEIGHT, INITIAL_DIFFICULTY, PROOFSIZE,
+	SECOND_POW_EDGE_BITS, UNIT_DIFFICULTY
 };
 use pow::{self, CuckatooContext, EdgeType, PoWContext};
 /// An enum collecting sets of parameters used throughout the
@@ -53,6 +53,13 @@ pub const USER_TESTING_COINBASE_MATURITY: u64 = 3;
 /// Testing cut through horizon in blocks
 pub const TESTING_CUT_THROUGH_HORIZON: u32 = 20;
 
+/// Blocks the header txhashset archives are served at moves by, well under
+/// the tenth of the horizon it's kept above
+pub const TXHASHSET_ARCHIVE_INTERVAL: u64 = 12 * HOUR_HEIGHT;
+
+/// Testing txhashset archive interval in blocks
+pub const TESTING_TXHASHSET_ARCHIVE_INTERVAL: u64 = 2;
+
 /// Testing initial graph weight
 pub const TESTING_INITIAL_GRAPH_WEIGHT: u32 = 1;
 
@@ -80,6 +87,16 @@ pub const PEER_EXPIRATION_REMOVE_TIME: i64 = PEER_EXPIRATION_DAYS * 24 * 3600;
 /// 1_000 times natural scale factor for cuckatoo29
 pub const TESTNET4_INITIAL_DIFFICULTY: u64 = 1_000 * UNIT_DIFFICULTY;
 
+/// Default peer-to-peer port on mainnet, the Rest API listening on the port
+/// right below
+pub const MAINNET_P2P_PORT: u16 = 3414;
+
+/// Default peer-to-peer port on the public test networks
+pub const TESTNET_P2P_PORT: u16 = 13414;
+
+/// Default peer-to-peer port on a user testing network
+pub const USERNET_P2P_PORT: u16 = 23414;
+
 /// Trigger compaction check on average every day for all nodes.
 /// Randomized per node - roll the dice on every block to decide.
 /// Will compact the txhashset to remove pruned data.
@@ -113,6 +130,57 @@ impl Default for ChainTypes {
 	}
 }
 
+impl ChainTypes {
+	/// Short name of the network, as given to `--network` and naming its
+	/// data directory
+	pub fn shortname(&self) -> String {
+		match *self {
+			ChainTypes::AutomatedTesting => "autotest".to_owned(),
+			ChainTypes::UserTesting => "usernet".to_owned(),
+			ChainTypes::Testnet1 => "testnet1".to_owned(),
+			ChainTypes::Testnet2 => "testnet2".to_owned(),
+			ChainTypes::Testnet3 => "testnet3".to_owned(),
+			ChainTypes::Testnet4 => "floonet".to_owned(),
+			ChainTypes::Mainnet => "mainnet".to_owned(),
+		}
+	}
+
+	/// Network selectable from the command line for the given name, the
+	/// public test network (floonet) being the current testnet
+	pub fn from_network_name(name: &str) -> Option<ChainTypes> {
+		match name {
+			"mainnet" => Some(ChainTypes::Mainnet),
+			"floonet" => Some(ChainTypes::Testnet4),
+			"usernet" => Some(ChainTypes::UserTesting),
+			_ => None,
+		}
+	}
+
+	/// Default port of the peer-to-peer server on this network
+	pub fn default_p2p_port(&self) -> u16 {
+		match *self {
+			ChainTypes::Mainnet => MAINNET_P2P_PORT,
+			ChainTypes::UserTesting | ChainTypes::AutomatedTesting => USERNET_P2P_PORT,
+			_ => TESTNET_P2P_PORT,
+		}
+	}
+
+	/// Default port of the node Rest API on this network
+	pub fn default_api_port(&self) -> u16 {
+		self.default_p2p_port() - 1
+	}
+
+	/// Magic bytes starting every peer-to-peer message on this network, so
+	/// nodes of different networks can't talk to each other
+	pub fn magic(&self) -> [u8; 2] {
+		match *self {
+			ChainTypes::Mainnet => [0x4d, 0x57],
+			ChainTypes::UserTesting | ChainTypes::AutomatedTesting => [0x55, 0x54],
+			_ => [0x54, 0x34],
+		}
+	}
+}
+
 /// PoW test mining and verifier context
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum PoWContextTypes {
@@ -122,11 +190,28 @@ pub enum PoWContextTypes {
 	Cuckatoo,
 }
 
+/// Consensus parameters a private network (usernet, CI) can set for itself
+/// in a network definition file, the ones left out keeping the value of its
+/// chain type
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct NetworkOverrides {
+	/// Blocks before a coinbase output can be spent
+	pub coinbase_maturity: Option<u64>,
+	/// Time between blocks the difficulty adjusts toward, in seconds
+	pub block_time_sec: Option<u64>,
+	/// Difficulty of the genesis block, the first blocks are mined at
+	pub initial_difficulty: Option<u64>,
+}
+
 lazy_static!{
 	/// The mining parameter mode
 	pub static ref CHAIN_TYPE: RwLock<ChainTypes> =
 			RwLock::new(ChainTypes::Mainnet);
 
+	/// Consensus parameters overridden on a private network
+	pub static ref NETWORK_OVERRIDES: RwLock<NetworkOverrides> =
+			RwLock::new(NetworkOverrides::default());
+
 	/// PoW context type to instantiate
 	pub static ref POW_CONTEXT_TYPE: RwLock<PoWContextTypes> =
 			RwLock::new(PoWContextTypes::Cuckoo);
@@ -138,6 +223,26 @@ pub fn set_mining_mode(mode: ChainTypes) {
 	*param_ref = mode;
 }
 
+/// Overrides consensus parameters of the current chain type, refused on the
+/// live public networks (mainnet among them) where every node has to agree
+/// on them. To be set at startup, before any block is read or validated.
+pub fn set_network_overrides(overrides: NetworkOverrides) -> Result<(), String> {
+	if is_production_mode() {
+		return Err(format!(
+			"consensus parameters can't be overridden on {}",
+			CHAIN_TYPE.read().shortname()
+		));
+	}
+	if overrides.block_time_sec == Some(0) {
+		return Err("block_time_sec has to be at least 1".to_owned());
+	}
+	if overrides.initial_difficulty == Some(0) {
+		return Err("initial_difficulty has to be at least 1".to_owned());
+	}
+	*NETWORK_OVERRIDES.write() = overrides;
+	Ok(())
+}
+
 /// Return either a cuckoo context or a cuckatoo context
 /// Single change point
 pub fn create_pow_context<T>(
@@ -192,6 +297,9 @@ pub fn proofsize() -> usize {
 
 /// Coinbase maturity for coinbases to be spent
 pub fn coinbase_maturity() -> u64 {
+	if let Some(n) = NETWORK_OVERRIDES.read().coinbase_maturity {
+		return n;
+	}
 	let param_ref = CHAIN_TYPE.read();
 	match *param_ref {
 		ChainTypes::AutomatedTesting => AUTOMATED_TESTING_COINBASE_MATURITY,
@@ -202,6 +310,9 @@ pub fn coinbase_maturity() -> u64 {
 
 /// Initial mining difficulty
 pub fn initial_block_difficulty() -> u64 {
+	if let Some(n) = NETWORK_OVERRIDES.read().initial_difficulty {
+		return n;
+	}
 	let param_ref = CHAIN_TYPE.read();
 	match *param_ref {
 		ChainTypes::AutomatedTesting => TESTING_INITIAL_DIFFICULTY,
@@ -213,6 +324,15 @@ pub fn initial_block_difficulty() -> u64 {
 		ChainTypes::Mainnet => INITIAL_DIFFICULTY,
 	}
 }
+
+/// Target time between blocks, in seconds
+pub fn block_time_sec() -> u64 {
+	NETWORK_OVERRIDES
+		.read()
+		.block_time_sec
+		.unwrap_or(BLOCK_TIME_SEC)
+}
+
 /// Initial mining secondary scale
 pub fn initial_graph_weight() -> u32 {
 	let param_ref = CHAIN_TYPE.read();
@@ -237,6 +357,31 @@ pub fn cut_through_horizon() -> u32 {
 	}
 }
 
+/// Blocks the header txhashset archives are served at moves by
+pub fn txhashset_archive_interval() -> u64 {
+	let param_ref = CHAIN_TYPE.read();
+	match *param_ref {
+		ChainTypes::AutomatedTesting => TESTING_TXHASHSET_ARCHIVE_INTERVAL,
+		ChainTypes::UserTesting => TESTING_TXHASHSET_ARCHIVE_INTERVAL,
+		_ => TXHASHSET_ARCHIVE_INTERVAL,
+	}
+}
+
+/// Height of the header the txhashset is archived at, as a zip or in
+/// segments, for a chain with its head at the given height. A tenth of the
+/// horizon above it, rounded down to the archive interval, so nodes a few
+/// blocks apart agree on it and it only moves every so often.
+pub fn txhashset_archive_height(head_height: u64) -> u64 {
+	let horizon = cut_through_horizon() as u64;
+	let height = head_height.saturating_sub(1 + horizon - horizon / 10);
+	height - height % txhashset_archive_interval()
+}
+
+/// Magic bytes of the peer-to-peer messages on the current network
+pub fn magic() -> [u8; 2] {
+	CHAIN_TYPE.read().magic()
+}
+
 /// Are we in automated testing mode?
 pub fn is_automated_testing_mode() -> bool {
 	let param_ref = CHAIN_TYPE.read();
@@ -295,7 +440,7 @@ where
 		let last_ts_delta = if n > 1 {
 			last_n[0].timestamp - last_n[1].timestamp
 		} else {
-			BLOCK_TIME_SEC
+			block_time_sec()
 		};
 		let last_diff = last_n[0].difficulty;
 
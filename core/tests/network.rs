@@ -0,0 +1,79 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_core;
+
+use grin_core::consensus;
+use grin_core::global::{self, ChainTypes, NetworkOverrides};
+
+#[test]
+fn network_names() {
+	for name in &["mainnet", "floonet", "usernet"] {
+		let chain_type = ChainTypes::from_network_name(name).unwrap();
+		assert_eq!(chain_type.shortname(), *name);
+	}
+	assert_eq!(
+		ChainTypes::from_network_name("floonet"),
+		Some(ChainTypes::Testnet4)
+	);
+	assert_eq!(ChainTypes::from_network_name("testnet1"), None);
+}
+
+#[test]
+fn networks_kept_apart() {
+	let networks = vec![
+		ChainTypes::Mainnet,
+		ChainTypes::Testnet4,
+		ChainTypes::UserTesting,
+	];
+	for a in &networks {
+		assert_eq!(a.default_api_port() + 1, a.default_p2p_port());
+		for b in networks.iter().filter(|b| *b != a) {
+			assert!(a.magic() != b.magic());
+			assert!(a.default_p2p_port() != b.default_p2p_port());
+		}
+	}
+	// no change for nodes already on the test network
+	assert_eq!(ChainTypes::Testnet4.magic(), [0x54, 0x34]);
+	assert_eq!(ChainTypes::Testnet4.default_p2p_port(), 13414);
+}
+
+#[test]
+fn network_overrides() {
+	let overrides = NetworkOverrides {
+		coinbase_maturity: Some(10),
+		block_time_sec: Some(5),
+		initial_difficulty: None,
+	};
+
+	// refused on the public networks
+	global::set_mining_mode(ChainTypes::Mainnet);
+	assert!(global::set_network_overrides(overrides.clone()).is_err());
+	assert_eq!(global::coinbase_maturity(), consensus::COINBASE_MATURITY);
+	assert_eq!(global::block_time_sec(), consensus::BLOCK_TIME_SEC);
+
+	global::set_mining_mode(ChainTypes::UserTesting);
+	let invalid = NetworkOverrides {
+		block_time_sec: Some(0),
+		..overrides.clone()
+	};
+	assert!(global::set_network_overrides(invalid).is_err());
+	global::set_network_overrides(overrides).unwrap();
+	assert_eq!(global::coinbase_maturity(), 10);
+	assert_eq!(global::block_time_sec(), 5);
+	assert_eq!(
+		global::initial_block_difficulty(),
+		global::TESTING_INITIAL_DIFFICULTY
+	);
+}
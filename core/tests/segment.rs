@@ -0,0 +1,154 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate croaring;
+extern crate grin_core as core;
+
+mod vec_backend;
+
+use core::core::hash::Hash;
+use core::core::pmmr::{Segment, SegmentError, SegmentIdentifier, PMMR};
+use core::ser;
+use vec_backend::{TestElem, VecBackend};
+
+fn build_mmr(ba: &mut VecBackend<TestElem>, n: u32) -> (u64, Hash) {
+	let mut pmmr = PMMR::new(ba);
+	for i in 0..n {
+		pmmr.push(TestElem([0, 0, 0, i])).unwrap();
+	}
+	(pmmr.unpruned_size(), pmmr.root())
+}
+
+fn segment(
+	ba: &mut VecBackend<TestElem>,
+	size: u64,
+	height: u8,
+	idx: u64,
+	prunable: bool,
+) -> Segment<TestElem> {
+	let pmmr: PMMR<TestElem, _> = PMMR::at(ba, size);
+	pmmr.segment(SegmentIdentifier { height, idx }, prunable)
+		.unwrap()
+}
+
+// Drops the subtree under 3, leaving only its root
+fn compact(seg: &mut Segment<TestElem>) {
+	seg.hashes.retain(|&(pos, _)| pos > 2);
+	seg.leaves.retain(|&(pos, _)| pos > 2);
+	seg.leaf_set.retain(|&pos| pos > 2);
+}
+
+#[test]
+fn segment_ranges() {
+	// 9 leaves, 16 positions, peaks at 15 and 16
+	let id = SegmentIdentifier { height: 1, idx: 0 };
+	assert_eq!(SegmentIdentifier::count(1, 16), 5);
+	assert_eq!(id.pos_range(16), Some((1, 3)));
+	assert_eq!(id.roots(16), vec![3]);
+
+	let last = SegmentIdentifier { height: 2, idx: 2 };
+	assert_eq!(last.pos_range(16), Some((16, 16)));
+	assert_eq!(last.roots(16), vec![16]);
+	assert_eq!(SegmentIdentifier { height: 2, idx: 3 }.pos_range(16), None);
+}
+
+#[test]
+fn segments_validate() {
+	let mut ba = VecBackend::new();
+	let (size, root) = build_mmr(&mut ba, 9);
+
+	for height in 0..4 {
+		for idx in 0..SegmentIdentifier::count(height, size) {
+			let seg = segment(&mut ba, size, height, idx, true);
+			assert_eq!(seg.validate(size, root, true), Ok(()));
+			let seg = segment(&mut ba, size, height, idx, false);
+			assert!(seg.leaf_set.is_empty());
+			assert_eq!(seg.validate(size, root, false), Ok(()));
+		}
+	}
+
+	// spent leaves stay in the segment, just not in its leaf set
+	{
+		let mut pmmr: PMMR<TestElem, _> = PMMR::at(&mut ba, size);
+		pmmr.prune(1).unwrap();
+	}
+	let seg = segment(&mut ba, size, 2, 0, true);
+	assert_eq!(seg.leaf_set, vec![2, 4, 5]);
+	assert_eq!(seg.validate(size, root, true), Ok(()));
+}
+
+#[test]
+fn compacted_segment() {
+	let mut ba = VecBackend::new();
+	let (size, root) = build_mmr(&mut ba, 9);
+
+	// the subtree under 3 compacted away, only its root left
+	let mut seg = segment(&mut ba, size, 2, 0, true);
+	compact(&mut seg);
+	assert_eq!(seg.validate(size, root, true), Ok(()));
+
+	// which can't happen to an MMR that isn't pruned
+	let mut unpruned = segment(&mut ba, size, 2, 0, false);
+	compact(&mut unpruned);
+	assert_eq!(
+		unpruned.validate(size, root, false),
+		Err(SegmentError::BadHash(1))
+	);
+
+	// but a hash can't be missing where we have the children
+	seg.hashes.retain(|&(pos, _)| pos != 6);
+	assert_eq!(
+		seg.validate(size, root, true),
+		Err(SegmentError::BadHash(6))
+	);
+}
+
+#[test]
+fn bad_segments() {
+	let mut ba = VecBackend::new();
+	let (size, root) = build_mmr(&mut ba, 9);
+	let seg = segment(&mut ba, size, 2, 1, true);
+
+	let mut tampered = seg.clone();
+	tampered.leaves[0].1 = TestElem([1, 1, 1, 1]);
+	assert_eq!(
+		tampered.validate(size, root, true),
+		Err(SegmentError::LeafMismatch(8))
+	);
+
+	let other_root = build_mmr(&mut VecBackend::new(), 10).1;
+	assert_eq!(
+		seg.validate(size, other_root, true),
+		Err(SegmentError::BadProof(14))
+	);
+
+	let mut out_of_range = seg.clone();
+	out_of_range.leaf_set.push(1);
+	assert_eq!(
+		out_of_range.validate(size, root, true),
+		Err(SegmentError::BadPosition(1))
+	);
+}
+
+#[test]
+fn segment_ser_deser() {
+	let mut ba = VecBackend::new();
+	let (size, _) = build_mmr(&mut ba, 9);
+	let seg = segment(&mut ba, size, 1, 2, true);
+
+	let mut vec = Vec::new();
+	ser::serialize(&mut vec, &seg).expect("serialization failed");
+	let seg_2: Segment<TestElem> = ser::deserialize(&mut &vec[..]).unwrap();
+	assert_eq!(seg, seg_2);
+}
@@ -0,0 +1,209 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where the master secret is kept. The keychain works out which key an
+//! identifier stands for, the backend holds the master secret and commits,
+//! sums or signs with the keys under it. Deployments can keep that secret in
+//! an OS keystore, an HSM or a separate signing process, rather than in
+//! memory as the default backend does.
+
+use extkey_bip32::{BIP32GrinHasher, BIP32StandardHasher, ChildNumber, ExtendedPrivKey};
+use types::{BlindingFactor, Error, KeyDerivation};
+use util::secp::key::SecretKey;
+use util::secp::pedersen::Commitment;
+use util::secp::{self, Message, Secp256k1, Signature};
+use util::{Zeroing, Zeroize};
+
+/// Holds the master secret, committing, summing and signing with the keys
+/// under it without handing them out
+pub trait KeyBackend: Send + Sync {
+	/// Commitment to the amount with the key at the provided path
+	fn commit(
+		&self,
+		secp: &Secp256k1,
+		amount: u64,
+		path: &[ChildNumber],
+	) -> Result<Commitment, Error>;
+
+	/// Sum of the keys at the positive paths and blinding factors, minus
+	/// those at the negative ones
+	fn blind_sum(
+		&self,
+		secp: &Secp256k1,
+		positive: &[Vec<ChildNumber>],
+		negative: &[Vec<ChildNumber>],
+		positive_blinding: &[BlindingFactor],
+		negative_blinding: &[BlindingFactor],
+	) -> Result<BlindingFactor, Error>;
+
+	/// Signs a message with the key at the provided path
+	fn sign(
+		&self,
+		secp: &Secp256k1,
+		msg: &Message,
+		path: &[ChildNumber],
+	) -> Result<Signature, Error>;
+
+	/// The extended private key at the provided path, needed to build and
+	/// rewind range proofs. Backends keeping their keys to themselves leave
+	/// it refused.
+	fn derive_priv(
+		&self,
+		_secp: &Secp256k1,
+		_path: &[ChildNumber],
+	) -> Result<ExtendedPrivKey, Error> {
+		Err(Error::KeyUnavailable)
+	}
+}
+
+/// The default backend, a master key derived from a seed and kept in memory
+pub struct SeedBackend {
+	master: ExtendedPrivKey,
+	derivation: KeyDerivation,
+}
+
+impl SeedBackend {
+	/// The master key of the seed, as the derivation scheme defines it
+	pub fn new(
+		secp: &Secp256k1,
+		seed: &[u8],
+		derivation: KeyDerivation,
+	) -> Result<SeedBackend, Error> {
+		let master = match derivation {
+			KeyDerivation::Legacy => {
+				ExtendedPrivKey::new_master(secp, &mut BIP32GrinHasher::new(), seed)?
+			}
+			KeyDerivation::Bip44 => {
+				ExtendedPrivKey::new_master(secp, &mut BIP32StandardHasher::new(), seed)?
+			}
+		};
+		Ok(SeedBackend { master, derivation })
+	}
+
+	fn secret_key(&self, secp: &Secp256k1, path: &[ChildNumber]) -> Result<SecretKey, Error> {
+		let mut key = self.derive_priv(secp, path)?;
+		let secret_key = key.secret_key;
+		key.zeroize();
+		Ok(secret_key)
+	}
+}
+
+impl KeyBackend for SeedBackend {
+	fn commit(
+		&self,
+		secp: &Secp256k1,
+		amount: u64,
+		path: &[ChildNumber],
+	) -> Result<Commitment, Error> {
+		let mut key = self.secret_key(secp, path)?;
+		let commit = secp.commit(amount, key);
+		key.zeroize();
+		Ok(commit?)
+	}
+
+	fn blind_sum(
+		&self,
+		secp: &Secp256k1,
+		positive: &[Vec<ChildNumber>],
+		negative: &[Vec<ChildNumber>],
+		positive_blinding: &[BlindingFactor],
+		negative_blinding: &[BlindingFactor],
+	) -> Result<BlindingFactor, Error> {
+		// summed in place, secp's blind_sum taking the keys in vectors it
+		// frees without wiping
+		let mut pos_sum = Zeroing::new(None);
+		let mut neg_sum = Zeroing::new(None);
+		for path in positive {
+			add_key(secp, &mut pos_sum, self.secret_key(secp, path)?)?;
+		}
+		for path in negative {
+			add_key(secp, &mut neg_sum, self.secret_key(secp, path)?)?;
+		}
+		for b in positive_blinding {
+			add_key(secp, &mut pos_sum, b.secret_key(secp)?)?;
+		}
+		for b in negative_blinding {
+			add_key(secp, &mut neg_sum, b.secret_key(secp)?)?;
+		}
+
+		if let Some(ref mut neg) = *neg_sum {
+			let minus_one = SecretKey::from_slice(secp, &MINUS_ONE)?;
+			neg.mul_assign(secp, &minus_one)?;
+		}
+		if let Some(neg) = *neg_sum {
+			add_key(secp, &mut pos_sum, neg)?;
+		}
+		match *pos_sum {
+			Some(sum) => Ok(BlindingFactor::from_secret_key(sum)),
+			// nothing to sum
+			None => Err(secp::Error::InvalidSecretKey.into()),
+		}
+	}
+
+	fn sign(
+		&self,
+		secp: &Secp256k1,
+		msg: &Message,
+		path: &[ChildNumber],
+	) -> Result<Signature, Error> {
+		let mut key = self.secret_key(secp, path)?;
+		let sig = secp.sign(msg, &key);
+		key.zeroize();
+		Ok(sig?)
+	}
+
+	fn derive_priv(
+		&self,
+		secp: &Secp256k1,
+		path: &[ChildNumber],
+	) -> Result<ExtendedPrivKey, Error> {
+		let key = match self.derivation {
+			KeyDerivation::Legacy => {
+				self.master
+					.derive_priv(secp, &mut BIP32GrinHasher::new(), path)?
+			}
+			KeyDerivation::Bip44 => {
+				self.master
+					.derive_priv(secp, &mut BIP32StandardHasher::new(), path)?
+			}
+		};
+		Ok(key)
+	}
+}
+
+/// The curve order minus one, negating the keys it multiplies
+const MINUS_ONE: [u8; 32] = [
+	0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+	0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x40,
+];
+
+// Adds a key to a running sum, wiping the key
+fn add_key(secp: &Secp256k1, sum: &mut Option<SecretKey>, mut key: SecretKey) -> Result<(), Error> {
+	let res = match sum.as_mut() {
+		Some(sum) => sum.add_assign(secp, &key).map(|_| true),
+		None => Ok(false),
+	};
+	if let Ok(false) = res {
+		*sum = Some(key);
+	}
+	key.zeroize();
+	res?;
+	Ok(())
+}
+
+impl Drop for SeedBackend {
+	fn drop(&mut self) {
+		self.master.zeroize();
+	}
+}
@@ -40,6 +40,7 @@ use std::{error, fmt};
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use util::secp::key::{PublicKey, SecretKey};
 use util::secp::{self, ContextFlag, Secp256k1};
+use util::zeroize::{volatile_overwrite, Zeroize};
 
 use digest::generic_array::GenericArray;
 use digest::Digest;
@@ -140,6 +141,61 @@ impl BIP32Hasher for BIP32GrinHasher {
 	}
 }
 
+/// Hasher following the BIP32 spec to the letter, down to the master key
+/// and the serialization version bytes (xprv/xpub), so keys match those of
+/// standard tooling for the same seed
+pub struct BIP32StandardHasher {
+	hmac_sha512: Hmac<Sha512>,
+}
+
+impl BIP32StandardHasher {
+	/// New empty hasher
+	pub fn new() -> BIP32StandardHasher {
+		BIP32StandardHasher {
+			hmac_sha512: HmacSha512::new(GenericArray::from_slice(&[0u8; 128])),
+		}
+	}
+}
+
+impl BIP32Hasher for BIP32StandardHasher {
+	fn network_priv() -> [u8; 4] {
+		// xprv
+		[0x04, 0x88, 0xAD, 0xE4]
+	}
+	fn network_pub() -> [u8; 4] {
+		// xpub
+		[0x04, 0x88, 0xB2, 0x1E]
+	}
+	fn master_seed() -> [u8; 12] {
+		b"Bitcoin seed".to_owned()
+	}
+	fn init_sha512(&mut self, seed: &[u8]) {
+		self.hmac_sha512 = HmacSha512::new_varkey(seed).expect("HMAC can take key of any size");
+	}
+	fn append_sha512(&mut self, value: &[u8]) {
+		self.hmac_sha512.input(value);
+	}
+	fn result_sha512(&mut self) -> [u8; 64] {
+		let mut result = [0; 64];
+		result.copy_from_slice(self.hmac_sha512.result().code().as_slice());
+		result
+	}
+	fn sha_256(&self, input: &[u8]) -> [u8; 32] {
+		let mut sha2_res = [0; 32];
+		let mut sha2 = Sha256::new();
+		sha2.input(input);
+		sha2_res.copy_from_slice(sha2.result().as_slice());
+		sha2_res
+	}
+	fn ripemd_160(&self, input: &[u8]) -> [u8; 20] {
+		let mut ripemd_res = [0; 20];
+		let mut ripemd = Ripemd160::new();
+		ripemd.input(input);
+		ripemd_res.copy_from_slice(ripemd.result().as_slice());
+		ripemd_res
+	}
+}
+
 /// Extended private key
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct ExtendedPrivKey {
@@ -157,6 +213,19 @@ pub struct ExtendedPrivKey {
 	pub chain_code: ChainCode,
 }
 
+impl Zeroize for ChainCode {
+	fn zeroize(&mut self) {
+		volatile_overwrite(self, ChainCode([0; 32]));
+	}
+}
+
+impl Zeroize for ExtendedPrivKey {
+	fn zeroize(&mut self) {
+		self.secret_key.zeroize();
+		self.chain_code.zeroize();
+	}
+}
+
 /// Extended public key
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct ExtendedPubKey {
@@ -349,15 +418,18 @@ impl ExtendedPrivKey {
 	{
 		hasher.init_sha512(&H::master_seed());
 		hasher.append_sha512(seed);
-		let result = hasher.result_sha512();
+		let mut result = hasher.result_sha512();
 
+		let secret_key = SecretKey::from_slice(secp, &result[..32]);
+		let chain_code = ChainCode::from(&result[32..]);
+		result.zeroize();
 		Ok(ExtendedPrivKey {
 			network: H::network_priv(),
 			depth: 0,
 			parent_fingerprint: Default::default(),
 			child_number: ChildNumber::from_normal_idx(0),
-			secret_key: SecretKey::from_slice(secp, &result[..32]).map_err(Error::Ecdsa)?,
-			chain_code: ChainCode::from(&result[32..]),
+			secret_key: secret_key.map_err(Error::Ecdsa)?,
+			chain_code: chain_code,
 		})
 	}
 
@@ -407,8 +479,11 @@ impl ExtendedPrivKey {
 		BigEndian::write_u32(&mut be_n, u32::from(i));
 
 		hasher.append_sha512(&be_n);
-		let result = hasher.result_sha512();
-		let mut sk = SecretKey::from_slice(secp, &result[..32]).map_err(Error::Ecdsa)?;
+		let mut result = hasher.result_sha512();
+		let chain_code = ChainCode::from(&result[32..]);
+		let sk = SecretKey::from_slice(secp, &result[..32]);
+		result.zeroize();
+		let mut sk = sk.map_err(Error::Ecdsa)?;
 		sk.add_assign(secp, &self.secret_key)
 			.map_err(Error::Ecdsa)?;
 
@@ -418,7 +493,7 @@ impl ExtendedPrivKey {
 			parent_fingerprint: self.fingerprint(hasher),
 			child_number: i,
 			secret_key: sk,
-			chain_code: ChainCode::from(&result[32..]),
+			chain_code: chain_code,
 		})
 	}
 
@@ -655,65 +730,6 @@ mod tests {
 
 	use super::*;
 
-	use digest::generic_array::GenericArray;
-	use digest::Digest;
-	use hmac::{Hmac, Mac};
-	use ripemd160::Ripemd160;
-	use sha2::{Sha256, Sha512};
-
-	/// Implementation of the above that uses the standard BIP32 Hash algorithms
-	pub struct BIP32ReferenceHasher {
-		hmac_sha512: Hmac<Sha512>,
-	}
-
-	impl BIP32ReferenceHasher {
-		/// New empty hasher
-		pub fn new() -> BIP32ReferenceHasher {
-			BIP32ReferenceHasher {
-				hmac_sha512: HmacSha512::new(GenericArray::from_slice(&[0u8; 128])),
-			}
-		}
-	}
-
-	impl BIP32Hasher for BIP32ReferenceHasher {
-		fn network_priv() -> [u8; 4] {
-			// bitcoin network (xprv) (for test vectors)
-			[0x04, 0x88, 0xAD, 0xE4]
-		}
-		fn network_pub() -> [u8; 4] {
-			// bitcoin network (xpub) (for test vectors)
-			[0x04, 0x88, 0xB2, 0x1E]
-		}
-		fn master_seed() -> [u8; 12] {
-			b"Bitcoin seed".to_owned()
-		}
-		fn init_sha512(&mut self, seed: &[u8]) {
-			self.hmac_sha512 = HmacSha512::new_varkey(seed).expect("HMAC can take key of any size");;
-		}
-		fn append_sha512(&mut self, value: &[u8]) {
-			self.hmac_sha512.input(value);
-		}
-		fn result_sha512(&mut self) -> [u8; 64] {
-			let mut result = [0; 64];
-			result.copy_from_slice(self.hmac_sha512.result().code().as_slice());
-			result
-		}
-		fn sha_256(&self, input: &[u8]) -> [u8; 32] {
-			let mut sha2_res = [0; 32];
-			let mut sha2 = Sha256::new();
-			sha2.input(input);
-			sha2_res.copy_from_slice(sha2.result().as_slice());
-			sha2_res
-		}
-		fn ripemd_160(&self, input: &[u8]) -> [u8; 20] {
-			let mut ripemd_res = [0; 20];
-			let mut ripemd = Ripemd160::new();
-			ripemd.input(input);
-			ripemd_res.copy_from_slice(ripemd.result().as_slice());
-			ripemd_res
-		}
-	}
-
 	fn test_path(
 		secp: &Secp256k1,
 		seed: &[u8],
@@ -721,9 +737,9 @@ mod tests {
 		expected_sk: &str,
 		expected_pk: &str,
 	) {
-		let mut h = BIP32ReferenceHasher::new();
+		let mut h = BIP32StandardHasher::new();
 		let mut sk = ExtendedPrivKey::new_master(secp, &mut h, seed).unwrap();
-		let mut pk = ExtendedPubKey::from_private::<BIP32ReferenceHasher>(secp, &sk);
+		let mut pk = ExtendedPubKey::from_private::<BIP32StandardHasher>(secp, &sk);
 
 		// Check derivation convenience method for ExtendedPrivKey
 		assert_eq!(
@@ -751,7 +767,7 @@ mod tests {
 			match num {
 				ChildNumber::Normal { .. } => {
 					let pk2 = pk.ckd_pub(secp, &mut h, num).unwrap();
-					pk = ExtendedPubKey::from_private::<BIP32ReferenceHasher>(secp, &sk);
+					pk = ExtendedPubKey::from_private::<BIP32StandardHasher>(secp, &sk);
 					assert_eq!(pk, pk2);
 				}
 				ChildNumber::Hardened { .. } => {
@@ -759,7 +775,7 @@ mod tests {
 						pk.ckd_pub(secp, &mut h, num),
 						Err(Error::CannotDeriveFromHardenedKey)
 					);
-					pk = ExtendedPubKey::from_private::<BIP32ReferenceHasher>(secp, &sk);
+					pk = ExtendedPubKey::from_private::<BIP32StandardHasher>(secp, &sk);
 				}
 			}
 		}
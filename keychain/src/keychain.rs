@@ -19,30 +19,81 @@ use rand::{thread_rng, Rng};
 
 use blake2;
 
-use extkey_bip32::{BIP32GrinHasher, ExtendedPrivKey};
-use types::{BlindSum, BlindingFactor, Error, ExtKeychainPath, Identifier, Keychain};
-use util::secp::key::SecretKey;
+use std::fmt;
+use std::sync::Arc;
+
+use backend::{KeyBackend, SeedBackend};
+use extkey_bip32::{ChildNumber, ExtendedPrivKey};
+use types::{
+	BlindSum, BlindingFactor, Error, ExtKeychainPath, Identifier, KeyDerivation, Keychain,
+	BIP44_COIN_TYPE, BIP44_PURPOSE,
+};
 use util::secp::pedersen::Commitment;
 use util::secp::{self, Message, Secp256k1, Signature};
+use util::Zeroize;
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct ExtKeychain {
 	secp: Secp256k1,
-	master: ExtendedPrivKey,
+	backend: Arc<KeyBackend>,
+	derivation: KeyDerivation,
+}
+
+impl fmt::Debug for ExtKeychain {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// nothing secret in there, whatever the backend
+		write!(f, "ExtKeychain {{ derivation: {} }}", self.derivation)
+	}
+}
+
+impl ExtKeychain {
+	/// Path from the master key to the key of the identifier
+	fn key_path(&self, id: &Identifier) -> Vec<ChildNumber> {
+		let p = id.to_path();
+		let ids = &p.path[..p.depth as usize];
+		match self.derivation {
+			KeyDerivation::Legacy => ids.to_vec(),
+			KeyDerivation::Bip44 => {
+				let mut path = vec![
+					ChildNumber::from_hardened_idx(BIP44_PURPOSE),
+					ChildNumber::from_hardened_idx(BIP44_COIN_TYPE),
+				];
+				for c in ids {
+					path.push(ChildNumber::from_hardened_idx(u32::from(*c) & !(1 << 31)));
+				}
+				path
+			}
+		}
+	}
 }
 
 impl Keychain for ExtKeychain {
 	fn from_seed(seed: &[u8]) -> Result<ExtKeychain, Error> {
-		let mut h = BIP32GrinHasher::new();
+		ExtKeychain::from_seed_with(seed, KeyDerivation::Legacy)
+	}
+
+	fn from_seed_with(seed: &[u8], derivation: KeyDerivation) -> Result<ExtKeychain, Error> {
 		let secp = secp::Secp256k1::with_caps(secp::ContextFlag::Commit);
-		let master = ExtendedPrivKey::new_master(&secp, &mut h, seed)?;
+		let backend = SeedBackend::new(&secp, seed, derivation)?;
+		ExtKeychain::from_backend(Arc::new(backend), derivation)
+	}
+
+	fn from_backend(
+		backend: Arc<KeyBackend>,
+		derivation: KeyDerivation,
+	) -> Result<ExtKeychain, Error> {
 		let keychain = ExtKeychain {
-			secp: secp,
-			master: master,
+			secp: secp::Secp256k1::with_caps(secp::ContextFlag::Commit),
+			backend: backend,
+			derivation: derivation,
 		};
 		Ok(keychain)
 	}
 
+	fn derivation(&self) -> KeyDerivation {
+		self.derivation
+	}
+
 	/// For testing - probably not a good idea to use outside of tests.
 	fn from_random_seed() -> Result<ExtKeychain, Error> {
 		let seed: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
@@ -59,70 +110,30 @@ impl Keychain for ExtKeychain {
 	}
 
 	fn derive_key(&self, id: &Identifier) -> Result<ExtendedPrivKey, Error> {
-		let mut h = BIP32GrinHasher::new();
-		let p = id.to_path();
-		let mut sk = self.master;
-		for i in 0..p.depth {
-			sk = sk.ckd_priv(&self.secp, &mut h, p.path[i as usize])?;
-		}
-		Ok(sk)
+		self.backend.derive_priv(&self.secp, &self.key_path(id))
 	}
 
 	fn commit(&self, amount: u64, id: &Identifier) -> Result<Commitment, Error> {
-		let key = self.derive_key(id)?;
-		let commit = self.secp.commit(amount, key.secret_key)?;
-		Ok(commit)
+		self.backend.commit(&self.secp, amount, &self.key_path(id))
 	}
 
 	fn blind_sum(&self, blind_sum: &BlindSum) -> Result<BlindingFactor, Error> {
-		let mut pos_keys: Vec<SecretKey> = blind_sum
-			.positive_key_ids
-			.iter()
-			.filter_map(|k| {
-				let res = self.derive_key(&Identifier::from_path(&k));
-				if let Ok(s) = res {
-					Some(s.secret_key)
-				} else {
-					None
-				}
-			}).collect();
-
-		let mut neg_keys: Vec<SecretKey> = blind_sum
-			.negative_key_ids
-			.iter()
-			.filter_map(|k| {
-				let res = self.derive_key(&Identifier::from_path(&k));
-				if let Ok(s) = res {
-					Some(s.secret_key)
-				} else {
-					None
-				}
-			}).collect();
-
-		pos_keys.extend(
-			&blind_sum
-				.positive_blinding_factors
-				.iter()
-				.filter_map(|b| b.secret_key(&self.secp).ok())
-				.collect::<Vec<SecretKey>>(),
-		);
-
-		neg_keys.extend(
-			&blind_sum
-				.negative_blinding_factors
-				.iter()
-				.filter_map(|b| b.secret_key(&self.secp).ok())
-				.collect::<Vec<SecretKey>>(),
-		);
-
-		let sum = self.secp.blind_sum(pos_keys, neg_keys)?;
-		Ok(BlindingFactor::from_secret_key(sum))
+		let paths = |ids: &Vec<ExtKeychainPath>| {
+			ids.iter()
+				.map(|p| self.key_path(&Identifier::from_path(p)))
+				.collect::<Vec<_>>()
+		};
+		self.backend.blind_sum(
+			&self.secp,
+			&paths(&blind_sum.positive_key_ids),
+			&paths(&blind_sum.negative_key_ids),
+			&blind_sum.positive_blinding_factors,
+			&blind_sum.negative_blinding_factors,
+		)
 	}
 
 	fn sign(&self, msg: &Message, id: &Identifier) -> Result<Signature, Error> {
-		let skey = self.derive_key(id)?;
-		let sig = self.secp.sign(msg, &skey.secret_key)?;
-		Ok(sig)
+		self.backend.sign(&self.secp, msg, &self.key_path(id))
 	}
 
 	fn sign_with_blinding(
@@ -130,9 +141,10 @@ impl Keychain for ExtKeychain {
 		msg: &Message,
 		blinding: &BlindingFactor,
 	) -> Result<Signature, Error> {
-		let skey = &blinding.secret_key(&self.secp)?;
-		let sig = self.secp.sign(msg, &skey)?;
-		Ok(sig)
+		let mut skey = blinding.secret_key(&self.secp)?;
+		let sig = self.secp.sign(msg, &skey);
+		skey.zeroize();
+		Ok(sig?)
 	}
 
 	fn secp(&self) -> &Secp256k1 {
@@ -142,10 +154,18 @@ impl Keychain for ExtKeychain {
 
 #[cfg(test)]
 mod test {
+	use std::sync::Arc;
+
+	use backend::{KeyBackend, SeedBackend};
+	use extkey_bip32::ChildNumber;
 	use keychain::ExtKeychain;
-	use types::{BlindSum, BlindingFactor, ExtKeychainPath, Keychain};
+	use rand::thread_rng;
+	use types::{BlindSum, BlindingFactor, Error, ExtKeychainPath, KeyDerivation, Keychain};
+	use util::from_hex;
 	use util::secp;
 	use util::secp::key::SecretKey;
+	use util::secp::pedersen::Commitment;
+	use util::secp::Secp256k1;
 
 	#[test]
 	fn test_key_derivation() {
@@ -167,6 +187,103 @@ mod test {
 		secp.verify_from_commit(&msg, &sig, &commit).unwrap();
 	}
 
+	#[test]
+	fn test_bip44_derivation() {
+		let seed = [1; 32];
+		let legacy = ExtKeychain::from_seed(&seed).unwrap();
+		let keychain = ExtKeychain::from_seed_with(&seed, KeyDerivation::Bip44).unwrap();
+		let key_id = ExtKeychainPath::new(3, 0, 0, 1, 0).to_identifier();
+
+		// m/44'/592'/0'/0'/1' for that seed, as derived by standard tooling
+		let expected = SecretKey::from_slice(
+			keychain.secp(),
+			&from_hex(
+				"109178b19345d0be9133f374b2106690b7ad2bb4afc085fe03604c80c39061af".to_owned(),
+			).unwrap(),
+		).unwrap();
+		assert_eq!(keychain.derive_key(&key_id).unwrap().secret_key, expected);
+		assert!(legacy.derive_key(&key_id).unwrap().secret_key != expected);
+	}
+
+	// A backend never handing its keys out, as an HSM would
+	struct SealedBackend(SeedBackend);
+
+	impl KeyBackend for SealedBackend {
+		fn commit(
+			&self,
+			secp: &Secp256k1,
+			amount: u64,
+			path: &[ChildNumber],
+		) -> Result<Commitment, Error> {
+			self.0.commit(secp, amount, path)
+		}
+
+		fn blind_sum(
+			&self,
+			secp: &Secp256k1,
+			positive: &[Vec<ChildNumber>],
+			negative: &[Vec<ChildNumber>],
+			positive_blinding: &[BlindingFactor],
+			negative_blinding: &[BlindingFactor],
+		) -> Result<BlindingFactor, Error> {
+			self.0.blind_sum(
+				secp,
+				positive,
+				negative,
+				positive_blinding,
+				negative_blinding,
+			)
+		}
+
+		fn sign(
+			&self,
+			secp: &Secp256k1,
+			msg: &secp::Message,
+			path: &[ChildNumber],
+		) -> Result<secp::Signature, Error> {
+			self.0.sign(secp, msg, path)
+		}
+	}
+
+	#[test]
+	fn test_backend_keychain() {
+		let seed = [2; 32];
+		let keychain = ExtKeychain::from_seed(&seed).unwrap();
+		let backend = SeedBackend::new(keychain.secp(), &seed, KeyDerivation::Legacy).unwrap();
+		let with_backend =
+			ExtKeychain::from_backend(Arc::new(SealedBackend(backend)), KeyDerivation::Legacy)
+				.unwrap();
+
+		// same keys, whoever holds the master secret
+		let key_id = ExtKeychainPath::new(3, 0, 0, 7, 0).to_identifier();
+		assert_eq!(
+			keychain.commit(5, &key_id).unwrap(),
+			with_backend.commit(5, &key_id).unwrap()
+		);
+		let msg = secp::Message::from_slice(&[1; 32]).unwrap();
+		let sig = with_backend.sign(&msg, &key_id).unwrap();
+		let commit = keychain.commit(0, &key_id).unwrap();
+		keychain
+			.secp()
+			.verify_from_commit(&msg, &sig, &commit)
+			.unwrap();
+
+		let other_id = ExtKeychainPath::new(3, 0, 0, 8, 0).to_identifier();
+		let blind_sum = BlindSum::new()
+			.add_key_id(key_id.to_path())
+			.sub_key_id(other_id.to_path())
+			.add_blinding_factor(BlindingFactor::from_secret_key(
+				SecretKey::from_slice(keychain.secp(), &[3; 32]).unwrap(),
+			));
+		assert_eq!(
+			keychain.blind_sum(&blind_sum).unwrap(),
+			with_backend.blind_sum(&blind_sum).unwrap()
+		);
+
+		// nor the keys themselves
+		assert!(with_backend.derive_key(&key_id).is_err());
+	}
+
 	// We plan to "offset" the key used in the kernel commitment
 	// so we are going to be doing some key addition/subtraction.
 	// This test is mainly to demonstrate that idea that summing commitments
@@ -222,4 +339,47 @@ mod test {
 			BlindingFactor::from_secret_key(skey3),
 		);
 	}
+
+	// Keys summed in place come out as secp sums them
+	#[test]
+	fn blind_sum_in_place() {
+		let keychain = ExtKeychain::from_random_seed().unwrap();
+		let secp = &keychain.secp;
+		let key_id = |n| ExtKeychainPath::new(1, n, 0, 0, 0);
+		let key = |n| {
+			keychain
+				.derive_key(&key_id(n).to_identifier())
+				.unwrap()
+				.secret_key
+		};
+		let blind = BlindingFactor::from_secret_key(SecretKey::new(secp, &mut thread_rng()));
+
+		let sum = keychain
+			.blind_sum(
+				&BlindSum::new()
+					.add_key_id(key_id(1))
+					.add_key_id(key_id(2))
+					.sub_key_id(key_id(3))
+					.sub_blinding_factor(blind),
+			).unwrap();
+		let expected = secp
+			.blind_sum(
+				vec![key(1), key(2)],
+				vec![key(3), blind.secret_key(secp).unwrap()],
+			).unwrap();
+		assert_eq!(sum, BlindingFactor::from_secret_key(expected));
+
+		// negatives only
+		let sum = keychain
+			.blind_sum(&BlindSum::new().sub_key_id(key_id(1)))
+			.unwrap();
+		let expected = secp.blind_sum(vec![], vec![key(1)]).unwrap();
+		assert_eq!(sum, BlindingFactor::from_secret_key(expected));
+
+		// nothing left once summed
+		assert!(keychain
+			.blind_sum(&BlindSum::new().add_key_id(key_id(1)).sub_key_id(key_id(1)))
+			.is_err());
+		assert!(keychain.blind_sum(&BlindSum::new()).is_err());
+	}
 }
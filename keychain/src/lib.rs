@@ -30,13 +30,16 @@ extern crate serde_json;
 extern crate sha2;
 extern crate uuid;
 
+pub mod backend;
 mod base58;
 pub mod extkey_bip32;
 mod types;
 
 pub mod keychain;
+pub use backend::{KeyBackend, SeedBackend};
 pub use extkey_bip32::ChildNumber;
 pub use keychain::ExtKeychain;
 pub use types::{
-	BlindSum, BlindingFactor, Error, ExtKeychainPath, Identifier, Keychain, IDENTIFIER_SIZE,
+	BlindSum, BlindingFactor, Error, ExtKeychainPath, Identifier, KeyDerivation, Keychain,
+	BIP44_COIN_TYPE, BIP44_PURPOSE, IDENTIFIER_SIZE,
 };
@@ -16,12 +16,15 @@ use rand::thread_rng;
 use std::cmp::min;
 use std::io::Cursor;
 use std::ops::Add;
+use std::str::FromStr;
+use std::sync::Arc;
 /// Keychain trait and its main supporting types. The Identifier is a
 /// semi-opaque structure (just bytes) to track keys within the Keychain.
 /// BlindingFactor is a useful wrapper around a private key to help with
 /// commitment generation.
 use std::{error, fmt};
 
+use backend::KeyBackend;
 use blake2::blake2b::blake2b;
 use extkey_bip32::{self, ChildNumber, ExtendedPrivKey};
 use serde::{de, ser}; //TODO: Convert errors to use ErrorKind
@@ -32,6 +35,7 @@ use util::secp::key::{PublicKey, SecretKey};
 use util::secp::pedersen::Commitment;
 use util::secp::{self, Message, Secp256k1, Signature};
 use util::static_secp_instance;
+use util::zeroize::{volatile_overwrite, Zeroize};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
@@ -44,6 +48,8 @@ pub enum Error {
 	KeyDerivation(extkey_bip32::Error),
 	Transaction(String),
 	RangeProof(String),
+	/// The key backend doesn't hand keys out
+	KeyUnavailable,
 }
 
 impl From<secp::Error> for Error {
@@ -304,25 +310,40 @@ impl BlindingFactor {
 	/// and kernels from a block to identify and reconstruct a particular tx
 	/// from a block. You would need both k1, k2 to do this.
 	pub fn split(&self, secp: &Secp256k1) -> Result<SplitBlindingFactor, Error> {
-		let skey_1 = secp::key::SecretKey::new(secp, &mut thread_rng());
+		let mut skey_1 = secp::key::SecretKey::new(secp, &mut thread_rng());
 
 		// use blind_sum to subtract skey_1 from our key (to give k = k1 + k2)
 		let skey = self.secret_key(secp)?;
-		let skey_2 = secp.blind_sum(vec![skey], vec![skey_1])?;
+		let mut skey_2 = secp.blind_sum(vec![skey], vec![skey_1])?;
 
 		let blind_1 = BlindingFactor::from_secret_key(skey_1);
 		let blind_2 = BlindingFactor::from_secret_key(skey_2);
+		skey_1.zeroize();
+		skey_2.zeroize();
 
 		Ok(SplitBlindingFactor { blind_1, blind_2 })
 	}
 }
 
+impl Zeroize for BlindingFactor {
+	fn zeroize(&mut self) {
+		volatile_overwrite(self, BlindingFactor([0; SECRET_KEY_SIZE]));
+	}
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SplitBlindingFactor {
 	pub blind_1: BlindingFactor,
 	pub blind_2: BlindingFactor,
 }
 
+impl Drop for SplitBlindingFactor {
+	fn drop(&mut self) {
+		self.blind_1.zeroize();
+		self.blind_2.zeroize();
+	}
+}
+
 /// Accumulator to compute the sum of blinding factors. Keeps track of each
 /// factor as well as the "sign" with which they should be combined.
 #[derive(Clone, Debug, PartialEq)]
@@ -367,6 +388,18 @@ impl BlindSum {
 	}
 }
 
+impl Drop for BlindSum {
+	fn drop(&mut self) {
+		for blind in self
+			.positive_blinding_factors
+			.iter_mut()
+			.chain(self.negative_blinding_factors.iter_mut())
+		{
+			blind.zeroize();
+		}
+	}
+}
+
 /// Encapsulates a max 4-level deep BIP32 path, which is the
 /// most we can currently fit into a rangeproof message
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize)]
@@ -430,8 +463,55 @@ impl ExtKeychainPath {
 	}
 }
 
+/// BIP44 purpose, the first level of BIP44 paths
+pub const BIP44_PURPOSE: u32 = 44;
+/// Grin coin type, the second level of BIP44 paths, as registered in SLIP-44
+pub const BIP44_COIN_TYPE: u32 = 592;
+
+/// How keys are derived from the seed. Each wallet sticks to one, as the same
+/// identifier gives different keys with each.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDerivation {
+	/// Grin's original scheme: a Grin specific master key, and the
+	/// identifier path derived as is
+	Legacy,
+	/// Standard BIP32 master key, and the identifier path derived hardened
+	/// under m/44'/592', as standard tooling and hardware wallets do
+	Bip44,
+}
+
+impl Default for KeyDerivation {
+	fn default() -> KeyDerivation {
+		KeyDerivation::Legacy
+	}
+}
+
+impl fmt::Display for KeyDerivation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			KeyDerivation::Legacy => write!(f, "legacy"),
+			KeyDerivation::Bip44 => write!(f, "bip44"),
+		}
+	}
+}
+
+impl FromStr for KeyDerivation {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<KeyDerivation, String> {
+		match s {
+			"legacy" => Ok(KeyDerivation::Legacy),
+			"bip44" => Ok(KeyDerivation::Bip44),
+			_ => Err(format!("unknown key derivation {}", s)),
+		}
+	}
+}
+
 pub trait Keychain: Sync + Send + Clone {
 	fn from_seed(seed: &[u8]) -> Result<Self, Error>;
+	fn from_seed_with(seed: &[u8], derivation: KeyDerivation) -> Result<Self, Error>;
+	fn from_backend(backend: Arc<KeyBackend>, derivation: KeyDerivation) -> Result<Self, Error>;
+	fn derivation(&self) -> KeyDerivation;
 	fn from_random_seed() -> Result<Self, Error>;
 	fn root_key_id() -> Identifier;
 	fn derive_key_id(depth: u8, d1: u32, d2: u32, d3: u32, d4: u32) -> Identifier;
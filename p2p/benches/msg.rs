@@ -0,0 +1,62 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of the serialization of the largest messages exchanged during
+//! sync, a full batch of headers and a block
+#[macro_use]
+extern crate criterion;
+extern crate grin_core as core;
+extern crate grin_p2p as p2p;
+
+use criterion::Criterion;
+
+use core::core::Block;
+use core::global::{self, ChainTypes};
+use core::{pow, ser};
+use p2p::msg::Headers;
+
+fn genesis() -> Block {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	pow::mine_genesis_block().unwrap()
+}
+
+fn headers_message(c: &mut Criterion) {
+	let header = genesis().header;
+	let headers = Headers {
+		headers: vec![header; p2p::MAX_BLOCK_HEADERS as usize],
+	};
+	let data = ser::ser_vec(&headers).unwrap();
+
+	c.bench_function("headers message write", move |b| {
+		b.iter(|| ser::ser_vec(&headers).unwrap())
+	});
+	c.bench_function("headers message read", move |b| {
+		b.iter(|| ser::deserialize::<Headers>(&mut &data[..]).unwrap())
+	});
+}
+
+fn block_message(c: &mut Criterion) {
+	let block = genesis();
+	let data = ser::ser_vec(&block).unwrap();
+
+	c.bench_function("block message write", move |b| {
+		b.iter(|| ser::ser_vec(&block).unwrap())
+	});
+	c.bench_function("block message read", move |b| {
+		b.iter(|| ser::deserialize::<Block>(&mut &data[..]).unwrap())
+	});
+}
+
+criterion_group!(benches, headers_message, block_message);
+criterion_main!(benches);
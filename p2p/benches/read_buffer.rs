@@ -0,0 +1,150 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of the reading of a flood of blocks from a connection, the
+//! bodies read into a buffer of their own or into the one of the connection,
+//! along with the bytes allocated per block either way
+#[macro_use]
+extern crate criterion;
+extern crate grin_core as core;
+extern crate grin_p2p as p2p;
+extern crate grin_util as util;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use criterion::Criterion;
+
+use core::core::{Block, Input, OutputFeatures};
+use core::global::{self, ChainTypes};
+use core::{pow, ser};
+use p2p::msg::{self, MsgHeader, Type};
+use p2p::ReadBuffer;
+use util::secp::pedersen::Commitment;
+
+// Counts the bytes allocated, to compare the reads by
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Blocks read per measure of the bytes allocated
+const FLOOD_LEN: usize = 100;
+
+// A block message, spending enough inputs to weigh a few tens of KB
+fn block_message() -> Vec<u8> {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let mut block = pow::mine_genesis_block().unwrap();
+	for i in 0..1000u16 {
+		let mut commit = vec![0x08, (i >> 8) as u8, i as u8];
+		commit.resize(33, 0x11);
+		block.inputs_mut().push(Input::new(
+			OutputFeatures::DEFAULT_OUTPUT,
+			Commitment::from_vec(commit),
+		));
+	}
+	block.inputs_mut().sort();
+	let body = ser::ser_vec(&block).unwrap();
+	let mut msg = ser::ser_vec(&MsgHeader::new(Type::Block, body.len() as u64)).unwrap();
+	msg.extend_from_slice(&body);
+	msg
+}
+
+// A connection the message is written to over and over, until it's dropped
+fn flood(msg: Vec<u8>) -> TcpStream {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	thread::spawn(move || {
+		let (mut conn, _) = listener.accept().unwrap();
+		while conn.write_all(&msg).is_ok() {}
+	});
+	TcpStream::connect(addr).unwrap()
+}
+
+// Reads a block, its body into a buffer allocated for it
+fn read_fresh(conn: &mut TcpStream) -> Block {
+	let header = msg::read_header(conn, Some(Type::Block), TIMEOUT).unwrap();
+	msg::read_body(&header, conn, TIMEOUT).unwrap()
+}
+
+// Reads a block, its body into the buffer of the connection
+fn read_buffered(conn: &mut TcpStream, buf: &mut ReadBuffer) -> Block {
+	let header = msg::read_header(conn, Some(Type::Block), TIMEOUT).unwrap();
+	let block = {
+		let body = buf.slice(header.msg_len as usize);
+		msg::read_exact(conn, body, TIMEOUT, true).unwrap();
+		ser::deserialize(&mut &body[..]).unwrap()
+	};
+	buf.release();
+	block
+}
+
+// Bytes allocated per block read, over a flood of them
+fn allocated_per_block<F>(mut read: F) -> usize
+where
+	F: FnMut() -> Block,
+{
+	let start = ALLOCATED.load(Ordering::Relaxed);
+	for _ in 0..FLOOD_LEN {
+		read();
+	}
+	(ALLOCATED.load(Ordering::Relaxed) - start) / FLOOD_LEN
+}
+
+fn block_flood(c: &mut Criterion) {
+	let msg = block_message();
+
+	let mut conn = flood(msg.clone());
+	println!(
+		"block flood read into fresh buffers: {} bytes allocated per block of {}",
+		allocated_per_block(|| read_fresh(&mut conn)),
+		msg.len()
+	);
+	c.bench_function("block flood read into fresh buffers", move |b| {
+		b.iter(|| read_fresh(&mut conn))
+	});
+
+	let mut conn = flood(msg.clone());
+	let mut buf = ReadBuffer::default();
+	println!(
+		"block flood read into the connection buffer: {} bytes allocated per block of {}",
+		allocated_per_block(|| read_buffered(&mut conn, &mut buf)),
+		msg.len()
+	);
+	c.bench_function("block flood read into the connection buffer", move |b| {
+		b.iter(|| read_buffered(&mut conn, &mut buf))
+	});
+}
+
+criterion_group!(benches, block_flood);
+criterion_main!(benches);
@@ -0,0 +1,171 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Staggered broadcasts. A new block is sent to our fastest peers first and
+//! to the others in batches spaced out in time, so the first sends don't
+//! compete with all the others for our upload. The batches of all the
+//! broadcasts in flight are sent from a single scheduler thread.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec;
+
+use util::Mutex;
+
+/// Orders items by the ping round trip time of their peer, fastest first,
+/// those not timed yet last, ties staying in the order given.
+pub fn rank_by_rtt<T, F>(items: Vec<T>, rtt_ms: F) -> Vec<T>
+where
+	F: Fn(&T) -> Option<u64>,
+{
+	let mut ranked = items;
+	ranked.sort_by_key(|t| rtt_ms(t).unwrap_or(u64::max_value()));
+	ranked
+}
+
+/// A broadcast with batches left to send
+struct Job<T> {
+	obj_name: &'static str,
+	targets: vec::IntoIter<T>,
+	send: Box<Fn(&T) -> bool + Send>,
+	batch_size: usize,
+	stagger: Duration,
+	remaining: u32,
+	sent: u32,
+	due: Instant,
+}
+
+impl<T> Job<T> {
+	// Sends to the next batch of targets, as long as more are wanted
+	fn send_batch(&mut self) {
+		for _ in 0..self.batch_size {
+			if self.done() {
+				break;
+			}
+			match self.targets.next() {
+				Some(t) => {
+					if (self.send)(&t) {
+						self.sent += 1;
+					}
+				}
+				None => break,
+			}
+		}
+		self.due = Instant::now() + self.stagger;
+	}
+
+	fn done(&self) -> bool {
+		self.sent >= self.remaining || self.targets.len() == 0
+	}
+}
+
+/// Sends the batches of staggered broadcasts when they're due, all from the
+/// same thread, which stops once the scheduler is dropped.
+pub struct BroadcastScheduler<T> {
+	jobs_tx: Mutex<mpsc::Sender<Job<T>>>,
+}
+
+impl<T> BroadcastScheduler<T>
+where
+	T: Send + 'static,
+{
+	/// A new scheduler, starting its thread
+	pub fn new() -> BroadcastScheduler<T> {
+		let (jobs_tx, jobs_rx) = mpsc::channel();
+		let _ = thread::Builder::new()
+			.name("broadcast".to_string())
+			.spawn(move || run(jobs_rx));
+		BroadcastScheduler {
+			jobs_tx: Mutex::new(jobs_tx),
+		}
+	}
+
+	/// Schedules the sending of obj_name to the targets, in batches of
+	/// batch_size spaced out by stagger, the first one after stagger. Stops
+	/// once send succeeded max times.
+	pub fn schedule<F>(
+		&self,
+		obj_name: &'static str,
+		targets: Vec<T>,
+		batch_size: u32,
+		stagger: Duration,
+		max: u32,
+		send: F,
+	) where
+		F: Fn(&T) -> bool + Send + 'static,
+	{
+		if targets.is_empty() || batch_size == 0 || max == 0 {
+			return;
+		}
+		let job = Job {
+			obj_name,
+			targets: targets.into_iter(),
+			send: Box::new(send),
+			batch_size: batch_size as usize,
+			stagger,
+			remaining: max,
+			sent: 0,
+			due: Instant::now() + stagger,
+		};
+		if self.jobs_tx.lock().send(job).is_err() {
+			error!("broadcast: scheduler is gone, {} not sent.", obj_name);
+		}
+	}
+}
+
+impl<T> Default for BroadcastScheduler<T>
+where
+	T: Send + 'static,
+{
+	fn default() -> BroadcastScheduler<T> {
+		BroadcastScheduler::new()
+	}
+}
+
+// Sends the batches of all the jobs as they come due, waiting for new jobs
+// in between
+fn run<T>(jobs_rx: mpsc::Receiver<Job<T>>) {
+	let mut jobs: Vec<Job<T>> = vec![];
+	loop {
+		let received = match jobs.iter().map(|j| j.due).min() {
+			Some(due) => {
+				let now = Instant::now();
+				if due > now {
+					jobs_rx.recv_timeout(due - now)
+				} else {
+					Err(RecvTimeoutError::Timeout)
+				}
+			}
+			None => jobs_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+		};
+		match received {
+			Ok(job) => jobs.push(job),
+			Err(RecvTimeoutError::Timeout) => {}
+			Err(RecvTimeoutError::Disconnected) => break,
+		}
+
+		let now = Instant::now();
+		for job in jobs.iter_mut().filter(|j| j.due <= now) {
+			job.send_batch();
+			if job.done() {
+				debug!(
+					"broadcast: {} sent to {} more peers.",
+					job.obj_name, job.sent
+				);
+			}
+		}
+		jobs.retain(|j| !j.done());
+	}
+}
@@ -24,12 +24,13 @@ use std::fs::File;
 use std::io::{self, Read, Write};
 use std::mem::size_of;
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{mpsc, Arc};
 use std::{cmp, thread, time};
 
 use core::ser;
-use msg::{read_body, read_exact, read_header, write_all, write_to_buf, MsgHeader, Type};
-use types::Error;
+use msg::{read_exact, read_header, write_all, write_to_buf, MsgHeader, Type};
+use types::{Error, Timeouts};
 use util::{RateCounter, RwLock};
 
 /// A trait to be implemented in order to receive messages from the
@@ -53,16 +54,82 @@ macro_rules! try_break {
 	};
 }
 
+// size of the chunks attachments are copied by
+const ATTACHMENT_CHUNK_SIZE: usize = 8000;
+
+// capacity of a read buffer kept past a message, a larger one being let go
+// of once the message is consumed
+const MAX_RETAINED_READ_BUFFER: usize = 256 * 1024;
+
+/// Buffer the messages of a connection are read into, one after the other,
+/// rather than allocating one for each message or attachment chunk
+pub struct ReadBuffer {
+	buf: Vec<u8>,
+}
+
+impl ReadBuffer {
+	/// A new, empty, buffer
+	pub fn new() -> ReadBuffer {
+		ReadBuffer {
+			buf: Vec::with_capacity(ATTACHMENT_CHUNK_SIZE),
+		}
+	}
+
+	/// The first len bytes of the buffer, grown to hold them if needed
+	pub fn slice(&mut self, len: usize) -> &mut [u8] {
+		if self.buf.len() < len {
+			self.buf.resize(len, 0);
+		}
+		&mut self.buf[..len]
+	}
+
+	/// Lets go of the memory held past the capacity kept between messages
+	pub fn release(&mut self) {
+		if self.buf.capacity() > MAX_RETAINED_READ_BUFFER {
+			self.buf = Vec::with_capacity(ATTACHMENT_CHUNK_SIZE);
+		}
+	}
+
+	/// Bytes currently held by the buffer
+	pub fn capacity(&self) -> usize {
+		self.buf.capacity()
+	}
+}
+
+impl Default for ReadBuffer {
+	fn default() -> ReadBuffer {
+		ReadBuffer::new()
+	}
+}
+
 /// A message as received by the connection. Provides access to the message
 /// header lazily consumes the message body, handling its deserialization.
 pub struct Message<'a> {
 	pub header: MsgHeader,
 	conn: &'a mut TcpStream,
+	buf: &'a mut ReadBuffer,
+	timeouts: Timeouts,
 }
 
 impl<'a> Message<'a> {
-	fn from_header(header: MsgHeader, conn: &'a mut TcpStream) -> Message<'a> {
-		Message { header, conn }
+	fn from_header(
+		header: MsgHeader,
+		conn: &'a mut TcpStream,
+		buf: &'a mut ReadBuffer,
+		timeouts: Timeouts,
+	) -> Message<'a> {
+		Message {
+			header,
+			conn,
+			buf,
+			timeouts,
+		}
+	}
+
+	/// Get the timeouts of the connection, to read the rest of the message
+	/// from it piecemeal
+	pub fn timeouts(&self) -> Timeouts {
+		self.timeouts
 	}
 
 	/// Get the TcpStream
@@ -70,26 +137,24 @@ impl<'a> Message<'a> {
 		return self.conn.try_clone().unwrap();
 	}
 
-	/// Read the message body from the underlying connection
+	/// Read the message body from the underlying connection, into the
+	/// buffer of the connection
 	pub fn body<T>(&mut self) -> Result<T, Error>
 	where
 		T: ser::Readable,
 	{
-		read_body(&self.header, self.conn)
+		let body = self.buf.slice(self.header.msg_len as usize);
+		read_exact(self.conn, body, self.timeouts.read, true)?;
+		ser::deserialize(&mut &body[..]).map_err(From::from)
 	}
 
 	pub fn copy_attachment(&mut self, len: usize, writer: &mut Write) -> Result<usize, Error> {
 		let mut written = 0;
 		while written < len {
-			let read_len = cmp::min(8000, len - written);
-			let mut buf = vec![0u8; read_len];
-			read_exact(
-				&mut self.conn,
-				&mut buf[..],
-				time::Duration::from_secs(10),
-				true,
-			)?;
-			writer.write_all(&mut buf)?;
+			let read_len = cmp::min(ATTACHMENT_CHUNK_SIZE, len - written);
+			let buf = self.buf.slice(read_len);
+			read_exact(self.conn, buf, self.timeouts.attachment, true)?;
+			writer.write_all(buf)?;
 			written += read_len;
 		}
 		Ok(written)
@@ -105,7 +170,9 @@ impl<'a> Message<'a> {
 			resp_type: resp_type,
 			body: body,
 			conn: self.conn,
+			timeouts: self.timeouts,
 			attachment: None,
+			preceding: vec![],
 		}
 	}
 }
@@ -115,21 +182,24 @@ pub struct Response<'a> {
 	resp_type: Type,
 	body: Vec<u8>,
 	conn: &'a mut TcpStream,
+	timeouts: Timeouts,
 	attachment: Option<File>,
+	preceding: Vec<u8>,
 }
 
 impl<'a> Response<'a> {
 	fn write(mut self) -> Result<(), Error> {
-		let mut msg =
-			ser::ser_vec(&MsgHeader::new(self.resp_type, self.body.len() as u64)).unwrap();
+		let header = ser::ser_vec(&MsgHeader::new(self.resp_type, self.body.len() as u64)).unwrap();
+		let mut msg = self.preceding;
+		msg.extend_from_slice(&header);
 		msg.append(&mut self.body);
-		write_all(&mut self.conn, &msg[..], time::Duration::from_secs(10))?;
+		write_all(&mut self.conn, &msg[..], self.timeouts.write)?;
 		if let Some(mut file) = self.attachment {
 			let mut buf = [0u8; 8000];
 			loop {
 				match file.read(&mut buf[..]) {
 					Ok(0) => break,
-					Ok(n) => write_all(&mut self.conn, &buf[..n], time::Duration::from_secs(10))?,
+					Ok(n) => write_all(&mut self.conn, &buf[..n], self.timeouts.attachment)?,
 					Err(e) => return Err(From::from(e)),
 				}
 			}
@@ -140,6 +210,15 @@ impl<'a> Response<'a> {
 	pub fn add_attachment(&mut self, file: File) {
 		self.attachment = Some(file);
 	}
+
+	/// Sends another message right before the response, such as the
+	/// transactions of a compact block ahead of it
+	pub fn precede_with<T>(&mut self, msg_type: Type, body: T)
+	where
+		T: ser::Writeable,
+	{
+		self.preceding.append(&mut write_to_buf(body, msg_type));
+	}
 }
 
 pub const SEND_CHANNEL_CAP: usize = 10;
@@ -151,6 +230,8 @@ pub struct Tracker {
 	pub received_bytes: Arc<RwLock<RateCounter>>,
 	/// Channel to allow sending data through the connection
 	pub send_channel: mpsc::SyncSender<Vec<u8>>,
+	/// Messages in the send channel, or being written, not fully sent yet
+	pub send_queued: Arc<AtomicUsize>,
 	/// Channel to close the connection
 	pub close_channel: mpsc::Sender<()>,
 	/// Channel to check for errors on the connection
@@ -162,9 +243,38 @@ impl Tracker {
 	where
 		T: ser::Writeable,
 	{
-		let buf = write_to_buf(body, msg_type);
+		self.queue(write_to_buf(body, msg_type))
+	}
+
+	/// Sends a message preceded by others, back to back, so they're received
+	/// in order and only take one slot of the send channel
+	pub fn send_preceded<T, P>(
+		&self,
+		body: T,
+		msg_type: Type,
+		preceding: &[P],
+		preceding_type: Type,
+	) -> Result<(), Error>
+	where
+		T: ser::Writeable,
+		P: ser::Writeable,
+	{
+		let mut buf = vec![];
+		for p in preceding {
+			buf.append(&mut write_to_buf(p, preceding_type));
+		}
+		buf.append(&mut write_to_buf(body, msg_type));
+		self.queue(buf)
+	}
+
+	fn queue(&self, buf: Vec<u8>) -> Result<(), Error> {
 		let buf_len = buf.len();
-		self.send_channel.try_send(buf)?;
+		// counted ahead, as the connection thread may write it right away
+		self.send_queued.fetch_add(1, Ordering::Relaxed);
+		if let Err(e) = self.send_channel.try_send(buf) {
+			self.send_queued.fetch_sub(1, Ordering::Relaxed);
+			return Err(e.into());
+		}
 
 		// Increase sent bytes counter
 		let mut sent_bytes = self.sent_bytes.write();
@@ -177,7 +287,7 @@ impl Tracker {
 /// Start listening on the provided connection and wraps it. Does not hang
 /// the current thread, instead just returns a future and the Connection
 /// itself.
-pub fn listen<H>(stream: TcpStream, handler: H) -> Tracker
+pub fn listen<H>(stream: TcpStream, handler: H, timeouts: Timeouts) -> Tracker
 where
 	H: MessageHandler,
 {
@@ -187,6 +297,7 @@ where
 
 	// Counter of number of bytes received
 	let received_bytes = Arc::new(RwLock::new(RateCounter::new()));
+	let send_queued = Arc::new(AtomicUsize::new(0));
 
 	stream
 		.set_nonblocking(true)
@@ -198,12 +309,15 @@ where
 		error_tx,
 		close_rx,
 		received_bytes.clone(),
+		send_queued.clone(),
+		timeouts,
 	);
 
 	Tracker {
 		sent_bytes: Arc::new(RwLock::new(RateCounter::new())),
 		received_bytes: received_bytes.clone(),
 		send_channel: send_tx,
+		send_queued,
 		close_channel: close_tx,
 		error_channel: error_rx,
 	}
@@ -216,6 +330,8 @@ fn poll<H>(
 	error_tx: mpsc::Sender<Error>,
 	close_rx: mpsc::Receiver<()>,
 	received_bytes: Arc<RwLock<RateCounter>>,
+	send_queued: Arc<AtomicUsize>,
+	timeouts: Timeouts,
 ) where
 	H: MessageHandler,
 {
@@ -226,11 +342,12 @@ fn poll<H>(
 			let sleep_time = time::Duration::from_millis(1);
 
 			let conn = &mut conn;
+			let mut read_buf = ReadBuffer::new();
 			let mut retry_send = Err(());
 			loop {
 				// check the read end
-				if let Some(h) = try_break!(error_tx, read_header(conn, None)) {
-					let msg = Message::from_header(h, conn);
+				if let Some(h) = try_break!(error_tx, read_header(conn, None, timeouts.read)) {
+					let msg = Message::from_header(h, conn, &mut read_buf, timeouts);
 					trace!(
 						"Received message header, type {:?}, len {}.",
 						msg.header.msg_type,
@@ -246,6 +363,7 @@ fn poll<H>(
 					if let Some(Some(resp)) = try_break!(error_tx, handler.consume(msg)) {
 						try_break!(error_tx, resp.write());
 					}
+					read_buf.release();
 				}
 
 				// check the write end, use or_else so try_recv is lazily eval'd
@@ -256,6 +374,8 @@ fn poll<H>(
 						try_break!(error_tx, conn.write_all(&data[..]).map_err(&From::from));
 					if written.is_none() {
 						retry_send = Ok(data);
+					} else {
+						send_queued.fetch_sub(1, Ordering::Relaxed);
 					}
 				}
 
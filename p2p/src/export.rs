@@ -0,0 +1,163 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Known-good peers exported from a node to pre-seed the peer store of
+//! others, rather than them waiting on DNS seeds and gossip to find peers.
+//! An export is signed by the node it comes from along with the time it was
+//! made, so the node importing it can tell it wasn't altered, how stale it
+//! is and, if asked to, that it comes from a node it trusts.
+
+use std::net::SocketAddr;
+
+use core::core::hash::{Hash, Hashed};
+use core::ser::{self, Writeable, Writer};
+use msg::SockAddr;
+use types::{Capabilities, Error};
+use util;
+use util::secp::key::{PublicKey, SecretKey};
+use util::secp::{Message, Signature};
+use util::static_secp_instance;
+
+/// Most peers in an export
+pub const MAX_EXPORT_PEERS: usize = 1000;
+
+/// Age in seconds past which an export isn't imported by default, a week
+pub const MAX_EXPORT_AGE_SECS: i64 = 7 * 24 * 3600;
+
+/// How far in the future, in seconds, an export can be dated to allow for
+/// clock differences between nodes
+pub const MAX_EXPORT_SKEW_SECS: i64 = 10 * 60;
+
+/// A peer as exported, with what we last knew of it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerSeed {
+	/// Network address of the peer
+	pub addr: SocketAddr,
+	/// Capabilities it advertised
+	pub capabilities: Capabilities,
+	/// Its user agent
+	pub user_agent: String,
+	/// Last time we were connected to it
+	pub last_connected: i64,
+	/// Quality of the peer as a source of data when exported, in hundredths,
+	/// 0 if we weren't connected to it then
+	pub score: u64,
+}
+
+impl Writeable for PeerSeed {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		SockAddr(self.addr).write(writer)?;
+		ser_multiwrite!(
+			writer,
+			[write_u32, self.capabilities.bits()],
+			[write_bytes, &self.user_agent],
+			[write_i64, self.last_connected],
+			[write_u64, self.score]
+		);
+		Ok(())
+	}
+}
+
+/// Peers exported from a node, signed by it with the time of the export
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerExport {
+	/// When the peers were exported, in seconds since the epoch
+	pub exported_at: i64,
+	/// The peers, best scored first
+	pub peers: Vec<PeerSeed>,
+	/// Public key of the node that exported them, in hex
+	pub public_key: String,
+	/// Signature of the export time and peers by that node, in hex
+	pub signature: String,
+}
+
+impl PeerExport {
+	/// Signs the peers exported at that time with the key of the node
+	pub fn sign(
+		exported_at: i64,
+		peers: Vec<PeerSeed>,
+		key: &SecretKey,
+	) -> Result<PeerExport, Error> {
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		let msg = export_message(exported_at, &peers)?;
+		let sig = secp
+			.sign(&msg, key)
+			.map_err(|e| Error::InvalidExport(format!("signing: {:?}", e)))?;
+		let public_key = PublicKey::from_secret_key(&secp, key)
+			.map_err(|e| Error::InvalidExport(format!("public key: {:?}", e)))?;
+		Ok(PeerExport {
+			exported_at,
+			peers,
+			public_key: util::to_hex(public_key.serialize_vec(&secp, true).to_vec()),
+			signature: util::to_hex(sig.serialize_compact(&secp).to_vec()),
+		})
+	}
+
+	/// Checks the export is signed by the key it carries, or the one given,
+	/// and was made no more than max_age seconds before now, nor dated
+	/// further in the future than clocks can drift apart
+	pub fn verify(&self, now: i64, max_age: i64, signer: Option<&str>) -> Result<(), Error> {
+		if self.peers.len() > MAX_EXPORT_PEERS {
+			return Err(Error::InvalidExport(format!(
+				"{} peers, over {}",
+				self.peers.len(),
+				MAX_EXPORT_PEERS
+			)));
+		}
+		if let Some(signer) = signer {
+			if !signer.eq_ignore_ascii_case(&self.public_key) {
+				return Err(Error::InvalidExport(format!(
+					"signed by {}, not {}",
+					self.public_key, signer
+				)));
+			}
+		}
+		if self.exported_at > now + MAX_EXPORT_SKEW_SECS {
+			return Err(Error::InvalidExport(format!(
+				"made {}s in the future, over {}s",
+				self.exported_at - now,
+				MAX_EXPORT_SKEW_SECS
+			)));
+		}
+		if now - self.exported_at > max_age {
+			return Err(Error::InvalidExport(format!(
+				"made {}s ago, over {}s",
+				now - self.exported_at,
+				max_age
+			)));
+		}
+
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		let public_key = util::from_hex(self.public_key.clone())
+			.ok()
+			.and_then(|k| PublicKey::from_slice(&secp, &k).ok())
+			.ok_or(Error::InvalidExport("bad public key".to_owned()))?;
+		let sig = util::from_hex(self.signature.clone())
+			.ok()
+			.and_then(|s| Signature::from_compact(&secp, &s).ok())
+			.ok_or(Error::InvalidExport("bad signature".to_owned()))?;
+		let msg = export_message(self.exported_at, &self.peers)?;
+		secp.verify(&msg, &sig, &public_key)
+			.map_err(|_| Error::InvalidExport("signature doesn't match".to_owned()))
+	}
+}
+
+// What gets signed, the hash of the export time and peers
+fn export_message(exported_at: i64, peers: &Vec<PeerSeed>) -> Result<Message, Error> {
+	let hash: Hash = (exported_at, peers).hash();
+	Message::from_slice(&hash.to_vec())
+		.map_err(|e| Error::InvalidExport(format!("message: {:?}", e)))
+}
@@ -15,6 +15,7 @@
 use std::collections::VecDeque;
 use std::net::{SocketAddr, TcpStream};
 use std::sync::Arc;
+use std::time::Duration;
 use util::RwLock;
 
 use chrono::prelude::*;
@@ -77,8 +78,9 @@ impl Handshake {
 		};
 
 		// write and read the handshake response
+		let timeout = self.set_timeout(conn)?;
 		write_message(conn, hand, Type::Hand)?;
-		let shake: Shake = read_message(conn, Type::Shake)?;
+		let shake: Shake = read_message(conn, Type::Shake, timeout)?;
 		if shake.version != PROTOCOL_VERSION {
 			return Err(Error::ProtocolMismatch {
 				us: PROTOCOL_VERSION,
@@ -100,6 +102,8 @@ impl Handshake {
 				height: 0,
 				last_seen: Utc::now(),
 				stuck_detector: Utc::now(),
+				ping_sent: None,
+				rtt_ms: None,
 			})),
 			direction: Direction::Outbound,
 		};
@@ -127,7 +131,8 @@ impl Handshake {
 		total_difficulty: Difficulty,
 		conn: &mut TcpStream,
 	) -> Result<PeerInfo, Error> {
-		let hand: Hand = read_message(conn, Type::Hand)?;
+		let timeout = self.set_timeout(conn)?;
+		let hand: Hand = read_message(conn, Type::Hand, timeout)?;
 
 		// all the reasons we could refuse this connection for
 		if hand.version != PROTOCOL_VERSION {
@@ -159,6 +164,8 @@ impl Handshake {
 				height: 0,
 				last_seen: Utc::now(),
 				stuck_detector: Utc::now(),
+				ping_sent: None,
+				rtt_ms: None,
 			})),
 			direction: Direction::Inbound,
 		};
@@ -187,6 +194,14 @@ impl Handshake {
 		Ok(peer_info)
 	}
 
+	/// Bounds how long the handshake can block on the connection for
+	fn set_timeout(&self, conn: &mut TcpStream) -> Result<Duration, Error> {
+		let timeout = self.config.timeouts().handshake;
+		conn.set_read_timeout(Some(timeout))?;
+		conn.set_write_timeout(Some(timeout))?;
+		Ok(timeout)
+	}
+
 	/// Generate a new random nonce and store it in our ring buffer
 	fn next_nonce(&self) -> u64 {
 		let nonce = thread_rng().gen();
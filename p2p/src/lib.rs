@@ -25,6 +25,8 @@ extern crate bitflags;
 extern crate bytes;
 #[macro_use]
 extern crate enum_primitive;
+#[macro_use]
+extern crate lazy_static;
 extern crate lmdb_zero as lmdb;
 
 #[macro_use]
@@ -40,9 +42,12 @@ extern crate serde_derive;
 extern crate log;
 extern crate chrono;
 
+mod broadcast;
 mod conn;
+mod export;
 pub mod handshake;
 pub mod msg;
+mod netgroup;
 mod peer;
 mod peers;
 mod protocol;
@@ -50,12 +55,17 @@ mod serv;
 mod store;
 pub mod types;
 
-pub use conn::SEND_CHANNEL_CAP;
+pub use broadcast::{rank_by_rtt, BroadcastScheduler};
+pub use conn::{ReadBuffer, SEND_CHANNEL_CAP};
+pub use export::{
+	PeerExport, PeerSeed, MAX_EXPORT_AGE_SECS, MAX_EXPORT_PEERS, MAX_EXPORT_SKEW_SECS,
+};
+pub use netgroup::{plan_rotation, AsnMap, NetGroup};
 pub use peer::Peer;
 pub use peers::Peers;
 pub use serv::{DummyAdapter, Server};
-pub use store::{PeerData, State};
+pub use store::{PeerData, PeerEvent, PeerEventKind, PeerStore, State};
 pub use types::{
 	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerInfo, ReasonForBan, Seeding,
-	TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	Timeouts, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
 };
@@ -17,11 +17,13 @@
 use num::FromPrimitive;
 use std::io::{self, Read, Write};
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream};
-use std::{thread, time};
+use std::{cmp, thread, time};
 
 use core::consensus;
-use core::core::hash::Hash;
-use core::core::BlockHeader;
+use core::core::hash::{Hash, HashWriter, Hashed, ZERO_HASH};
+use core::core::pmmr::{SegmentIdentifier, SegmentType, TxHashSetSegment};
+use core::core::{BlockHeader, Transaction};
+use core::global;
 use core::pow::Difficulty;
 use core::ser::{self, Readable, Reader, Writeable, Writer};
 
@@ -33,12 +35,22 @@ pub const PROTOCOL_VERSION: u32 = 1;
 /// Grin's user agent with current version
 pub const USER_AGENT: &'static str = concat!("MW/Grin ", env!("CARGO_PKG_VERSION"));
 
-/// Magic number expected in the header of every message
-const MAGIC: [u8; 2] = [0x54, 0x34];
-
 /// Size in bytes of a message header
 pub const HEADER_LEN: u64 = 11;
 
+/// Max number of kernel excess prefixes a relay filter can have
+pub const MAX_RELAY_FILTER_PREFIXES: u16 = 100;
+
+/// Size in bytes of a kernel excess, the longest a relay filter prefix can be
+const KERNEL_EXCESS_LEN: usize = 33;
+
+/// Size in bytes of the chunks a txhashset archive is verified by, the last
+/// one being shorter
+pub const TXHASHSET_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Max number of chunks in a txhashset archive, so 4GiB
+pub const MAX_TXHASHSET_CHUNKS: u64 = 65_536;
+
 /// Max theoretical size of a block filled with outputs.
 const MAX_BLOCK_SIZE: u64 =
 	(consensus::MAX_BLOCK_WEIGHT / consensus::BLOCK_OUTPUT_WEIGHT * 708) as u64;
@@ -70,6 +82,13 @@ enum_from_primitive! {
 		BanReason = 18,
 		GetTransaction = 19,
 		TransactionKernel = 20,
+		GetTxHashSetSegment = 21,
+		TxHashSetSegment = 22,
+		RelayFilter = 23,
+		TxHashSetChunkedRequest = 24,
+		TxHashSetChunkedArchive = 25,
+		GetTxHashSetChunk = 26,
+		TxHashSetChunk = 27,
 	}
 }
 
@@ -97,6 +116,13 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::BanReason => 64,
 		Type::GetTransaction => 32,
 		Type::TransactionKernel => 32,
+		Type::GetTxHashSetSegment => 48,
+		Type::TxHashSetSegment => MAX_BLOCK_SIZE,
+		Type::RelayFilter => 2 + (8 + KERNEL_EXCESS_LEN as u64) * MAX_RELAY_FILTER_PREFIXES as u64,
+		Type::TxHashSetChunkedRequest => 40,
+		Type::TxHashSetChunkedArchive => 64 + 8 + 32 * MAX_TXHASHSET_CHUNKS + 32,
+		Type::GetTxHashSetChunk => 40,
+		Type::TxHashSetChunk => 40 + 8 + TXHASHSET_CHUNK_SIZE,
 	}
 }
 
@@ -107,7 +133,7 @@ fn max_msg_size(msg_type: Type) -> u64 {
 /// `vec<u8>`. Except for a timeout, this implementation will never return a
 /// partially filled buffer.
 ///
-/// The timeout in milliseconds aborts the read when it's met. Note that the
+/// The timeout aborts the read once that much time went by. Note that the
 /// time is not guaranteed to be exact. To support cases where we want to poll
 /// instead of blocking, a `block_on_empty` boolean, when false, ensures
 /// `read_exact` returns early with a `io::ErrorKind::WouldBlock` if nothing
@@ -119,7 +145,7 @@ pub fn read_exact(
 	block_on_empty: bool,
 ) -> io::Result<()> {
 	let sleep_time = time::Duration::from_micros(10);
-	let mut count = time::Duration::new(0, 0);
+	let start = time::Instant::now();
 
 	let mut read = 0;
 	loop {
@@ -145,11 +171,10 @@ pub fn read_exact(
 		}
 		if !buf.is_empty() {
 			thread::sleep(sleep_time);
-			count += sleep_time;
 		} else {
 			break;
 		}
-		if count > timeout {
+		if start.elapsed() > timeout {
 			return Err(io::Error::new(
 				io::ErrorKind::TimedOut,
 				"reading from tcp stream",
@@ -162,7 +187,7 @@ pub fn read_exact(
 /// Same as `read_exact` but for writing.
 pub fn write_all(conn: &mut Write, mut buf: &[u8], timeout: time::Duration) -> io::Result<()> {
 	let sleep_time = time::Duration::from_micros(10);
-	let mut count = time::Duration::new(0, 0);
+	let start = time::Instant::now();
 
 	while !buf.is_empty() {
 		match conn.write(buf) {
@@ -179,11 +204,10 @@ pub fn write_all(conn: &mut Write, mut buf: &[u8], timeout: time::Duration) -> i
 		}
 		if !buf.is_empty() {
 			thread::sleep(sleep_time);
-			count += sleep_time;
 		} else {
 			break;
 		}
-		if count > timeout {
+		if start.elapsed() > timeout {
 			return Err(io::Error::new(
 				io::ErrorKind::TimedOut,
 				"reading from tcp stream",
@@ -195,14 +219,14 @@ pub fn write_all(conn: &mut Write, mut buf: &[u8], timeout: time::Duration) -> i
 
 /// Read a header from the provided connection without blocking if the
 /// underlying stream is async. Typically headers will be polled for, so
-/// we do not want to block.
-pub fn read_header(conn: &mut TcpStream, msg_type: Option<Type>) -> Result<MsgHeader, Error> {
-	let mut head = vec![0u8; HEADER_LEN as usize];
-	if Some(Type::Hand) == msg_type {
-		read_exact(conn, &mut head, time::Duration::from_millis(10), true)?;
-	} else {
-		read_exact(conn, &mut head, time::Duration::from_secs(10), false)?;
-	}
+/// we do not want to block, unless a given message type is expected.
+pub fn read_header(
+	conn: &mut TcpStream,
+	msg_type: Option<Type>,
+	timeout: time::Duration,
+) -> Result<MsgHeader, Error> {
+	let mut head = [0u8; HEADER_LEN as usize];
+	read_exact(conn, &mut head, timeout, msg_type.is_some())?;
 	let header = ser::deserialize::<MsgHeader>(&mut &head[..])?;
 	let max_len = max_msg_size(header.msg_type);
 	// TODO 4x the limits for now to leave ourselves space to change things
@@ -218,25 +242,33 @@ pub fn read_header(conn: &mut TcpStream, msg_type: Option<Type>) -> Result<MsgHe
 
 /// Read a message body from the provided connection, always blocking
 /// until we have a result (or timeout).
-pub fn read_body<T>(h: &MsgHeader, conn: &mut TcpStream) -> Result<T, Error>
+pub fn read_body<T>(
+	h: &MsgHeader,
+	conn: &mut TcpStream,
+	timeout: time::Duration,
+) -> Result<T, Error>
 where
 	T: Readable,
 {
 	let mut body = vec![0u8; h.msg_len as usize];
-	read_exact(conn, &mut body, time::Duration::from_secs(20), true)?;
+	read_exact(conn, &mut body, timeout, true)?;
 	ser::deserialize(&mut &body[..]).map_err(From::from)
 }
 
 /// Reads a full message from the underlying connection.
-pub fn read_message<T>(conn: &mut TcpStream, msg_type: Type) -> Result<T, Error>
+pub fn read_message<T>(
+	conn: &mut TcpStream,
+	msg_type: Type,
+	timeout: time::Duration,
+) -> Result<T, Error>
 where
 	T: Readable,
 {
-	let header = read_header(conn, Some(msg_type))?;
+	let header = read_header(conn, Some(msg_type), timeout)?;
 	if header.msg_type != msg_type {
 		return Err(Error::BadMessage);
 	}
-	read_body(&header, conn)
+	read_body(&header, conn, timeout)
 }
 
 pub fn write_to_buf<T>(msg: T, msg_type: Type) -> Vec<u8>
@@ -279,7 +311,7 @@ impl MsgHeader {
 	/// Creates a new message header.
 	pub fn new(msg_type: Type, len: u64) -> MsgHeader {
 		MsgHeader {
-			magic: MAGIC,
+			magic: global::magic(),
 			msg_type: msg_type,
 			msg_len: len,
 		}
@@ -306,12 +338,14 @@ impl Writeable for MsgHeader {
 
 impl Readable for MsgHeader {
 	fn read(reader: &mut Reader) -> Result<MsgHeader, ser::Error> {
-		reader.expect_u8(MAGIC[0])?;
-		reader.expect_u8(MAGIC[1])?;
+		// the magic bytes differ between networks
+		let magic = global::magic();
+		reader.expect_u8(magic[0])?;
+		reader.expect_u8(magic[1])?;
 		let (t, len) = ser_multiread!(reader, read_u8, read_u64);
 		match Type::from_u8(t) {
 			Some(ty) => Ok(MsgHeader {
-				magic: MAGIC,
+				magic: magic,
 				msg_type: ty,
 				msg_len: len,
 			}),
@@ -764,3 +798,302 @@ impl Readable for TxHashSetArchive {
 		})
 	}
 }
+
+/// Digests of the chunks of a txhashset archive, sent ahead of it so each
+/// chunk is verified as soon as it's received and only the corrupted ones are
+/// asked for again, rather than finding out once the whole archive is
+/// downloaded and unzipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxHashSetManifest {
+	/// Digest of each chunk, in order
+	pub digests: Vec<Hash>,
+	/// Rolling hash of the digests, each hashed with the one of those before
+	/// it, committing to all of them
+	pub manifest_hash: Hash,
+}
+
+impl TxHashSetManifest {
+	/// Manifest of the archive provided by the reader, read to its end
+	pub fn from_reader(reader: &mut Read) -> io::Result<TxHashSetManifest> {
+		let mut digests = vec![];
+		let mut buf = vec![0; TXHASHSET_CHUNK_SIZE as usize];
+		loop {
+			let mut len = 0;
+			while len < buf.len() {
+				match reader.read(&mut buf[len..]) {
+					Ok(0) => break,
+					Ok(n) => len += n,
+					Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+					Err(e) => return Err(e),
+				}
+			}
+			if len == 0 {
+				break;
+			}
+			digests.push(chunk_digest(&buf[..len]));
+			if len < buf.len() {
+				break;
+			}
+		}
+		let manifest_hash = rolling_hash(&digests);
+		Ok(TxHashSetManifest {
+			digests,
+			manifest_hash,
+		})
+	}
+
+	/// Whether the manifest is whole and has as many chunks as an archive of
+	/// that many bytes
+	pub fn verify(&self, bytes: u64) -> bool {
+		let chunks = (bytes + TXHASHSET_CHUNK_SIZE - 1) / TXHASHSET_CHUNK_SIZE;
+		self.digests.len() as u64 == chunks && rolling_hash(&self.digests) == self.manifest_hash
+	}
+
+	/// Length in bytes of a chunk of an archive of that many bytes
+	pub fn chunk_len(&self, bytes: u64, index: u64) -> u64 {
+		let offset = index * TXHASHSET_CHUNK_SIZE;
+		if offset >= bytes {
+			0
+		} else {
+			cmp::min(TXHASHSET_CHUNK_SIZE, bytes - offset)
+		}
+	}
+
+	/// Whether the data is the chunk at that index
+	pub fn matches(&self, index: u64, data: &[u8]) -> bool {
+		match self.digests.get(index as usize) {
+			Some(digest) => *digest == chunk_digest(data),
+			None => false,
+		}
+	}
+}
+
+/// Digest of a chunk of a txhashset archive
+pub fn chunk_digest(data: &[u8]) -> Hash {
+	let mut hasher = HashWriter::default();
+	hasher.write_fixed_bytes(&data).unwrap();
+	hasher.into_hash()
+}
+
+fn rolling_hash(digests: &[Hash]) -> Hash {
+	digests.iter().fold(ZERO_HASH, |acc, d| acc.hash_with(*d))
+}
+
+impl Writeable for TxHashSetManifest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.digests.len() as u64)?;
+		for d in &self.digests {
+			d.write(writer)?;
+		}
+		self.manifest_hash.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for TxHashSetManifest {
+	fn read(reader: &mut Reader) -> Result<TxHashSetManifest, ser::Error> {
+		let count = reader.read_u64()?;
+		if count > MAX_TXHASHSET_CHUNKS {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut digests = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			digests.push(Hash::read(reader)?);
+		}
+		Ok(TxHashSetManifest {
+			digests,
+			manifest_hash: Hash::read(reader)?,
+		})
+	}
+}
+
+/// Response to a txhashset request from a peer able to verify the archive
+/// chunk by chunk, followed by the archive as an attachment.
+pub struct TxHashSetChunkedArchive {
+	/// Block, height and size of the archive
+	pub archive: TxHashSetArchive,
+	/// Digests of its chunks
+	pub manifest: TxHashSetManifest,
+}
+
+impl Writeable for TxHashSetChunkedArchive {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.archive.write(writer)?;
+		self.manifest.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for TxHashSetChunkedArchive {
+	fn read(reader: &mut Reader) -> Result<TxHashSetChunkedArchive, ser::Error> {
+		Ok(TxHashSetChunkedArchive {
+			archive: TxHashSetArchive::read(reader)?,
+			manifest: TxHashSetManifest::read(reader)?,
+		})
+	}
+}
+
+/// Request for a chunk of the txhashset archive sent last, that didn't match
+/// its digest.
+pub struct TxHashSetChunkRequest {
+	/// Hash of the block the archive is for
+	pub hash: Hash,
+	/// Which chunk of the archive
+	pub index: u64,
+}
+
+impl Writeable for TxHashSetChunkRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u64(self.index)?;
+		Ok(())
+	}
+}
+
+impl Readable for TxHashSetChunkRequest {
+	fn read(reader: &mut Reader) -> Result<TxHashSetChunkRequest, ser::Error> {
+		Ok(TxHashSetChunkRequest {
+			hash: Hash::read(reader)?,
+			index: reader.read_u64()?,
+		})
+	}
+}
+
+/// A chunk of a txhashset archive, sent again.
+pub struct TxHashSetChunk {
+	/// Hash of the block the archive is for
+	pub hash: Hash,
+	/// Which chunk of the archive
+	pub index: u64,
+	/// Content of the chunk
+	pub data: Vec<u8>,
+}
+
+impl Writeable for TxHashSetChunk {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u64(self.index)?;
+		writer.write_bytes(&self.data)?;
+		Ok(())
+	}
+}
+
+impl Readable for TxHashSetChunk {
+	fn read(reader: &mut Reader) -> Result<TxHashSetChunk, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let index = reader.read_u64()?;
+		let len = reader.read_u64()?;
+		if len > TXHASHSET_CHUNK_SIZE {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		Ok(TxHashSetChunk {
+			hash,
+			index,
+			data: reader.read_fixed_bytes(len as usize)?,
+		})
+	}
+}
+
+/// Request for a segment of the output, rangeproof or kernel MMR as of a
+/// block, to sync the txhashset from many peers at once.
+pub struct SegmentRequest {
+	/// Hash of the block the segment should be taken at
+	pub hash: Hash,
+	/// MMR the segment is part of
+	pub segment_type: SegmentType,
+	/// Which segment of the MMR
+	pub identifier: SegmentIdentifier,
+}
+
+impl Writeable for SegmentRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		self.segment_type.write(writer)?;
+		self.identifier.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for SegmentRequest {
+	fn read(reader: &mut Reader) -> Result<SegmentRequest, ser::Error> {
+		Ok(SegmentRequest {
+			hash: Hash::read(reader)?,
+			segment_type: SegmentType::read(reader)?,
+			identifier: SegmentIdentifier::read(reader)?,
+		})
+	}
+}
+
+/// Response to a segment request, with the segment and its proofs.
+pub struct SegmentResponse {
+	/// Hash of the block the segment was taken at
+	pub hash: Hash,
+	/// The segment itself
+	pub segment: TxHashSetSegment,
+}
+
+impl Writeable for SegmentResponse {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		self.segment.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for SegmentResponse {
+	fn read(reader: &mut Reader) -> Result<SegmentResponse, ser::Error> {
+		Ok(SegmentResponse {
+			hash: Hash::read(reader)?,
+			segment: TxHashSetSegment::read(reader)?,
+		})
+	}
+}
+
+/// Filter a peer sets on the transactions relayed to it, only wanting the
+/// ones with a kernel whose excess starts with one of the prefixes. An empty
+/// filter lets all transactions through.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayFilter {
+	/// Prefixes of the kernel excesses matching the filter
+	pub prefixes: Vec<Vec<u8>>,
+}
+
+impl RelayFilter {
+	/// Whether the transaction has a kernel matching the filter
+	pub fn matches(&self, tx: &Transaction) -> bool {
+		self.prefixes.is_empty()
+			|| tx.kernels().iter().any(|k| {
+				self.prefixes
+					.iter()
+					.any(|p| k.excess.0[..].starts_with(&p[..]))
+			})
+	}
+}
+
+impl Writeable for RelayFilter {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u16(self.prefixes.len() as u16)?;
+		for p in &self.prefixes {
+			writer.write_bytes(p)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for RelayFilter {
+	fn read(reader: &mut Reader) -> Result<RelayFilter, ser::Error> {
+		let count = reader.read_u16()?;
+		if count > MAX_RELAY_FILTER_PREFIXES {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut prefixes = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let len = reader.read_u64()?;
+			if len > KERNEL_EXCESS_LEN as u64 {
+				return Err(ser::Error::CorruptedData);
+			}
+			prefixes.push(reader.read_fixed_bytes(len as usize)?);
+		}
+		Ok(RelayFilter { prefixes })
+	}
+}
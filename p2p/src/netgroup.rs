@@ -0,0 +1,247 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Network groups of peer addresses, to spread our outbound connections over
+//! networks no single party controls. An attacker slowly filling all our
+//! outbound slots to eclipse us usually holds addresses in a few networks
+//! only, so every so often some outbound peers, from the groups holding most
+//! of them, are replaced with peers from groups we're the least connected to,
+//! each peer dropped once its replacement is connected. Addresses are grouped by the autonomous system announcing
+//! them when an ASN map is provided, by /16 (IPv4) or /32 (IPv6) otherwise.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, SocketAddr};
+
+use rand::{thread_rng, Rng};
+
+use types::Error;
+
+/// Network a peer address belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetGroup {
+	/// Autonomous system announcing the address
+	Asn(u32),
+	/// First 16 bits of an IPv4 address
+	Ipv4([u8; 2]),
+	/// First 32 bits of an IPv6 address
+	Ipv6([u8; 4]),
+}
+
+impl NetGroup {
+	/// Group of an address, by ASN if the map resolves it
+	pub fn of(ip: &IpAddr, asn_map: Option<&AsnMap>) -> NetGroup {
+		if let Some(asn) = asn_map.and_then(|m| m.lookup(ip)) {
+			return NetGroup::Asn(asn);
+		}
+		match *ip {
+			IpAddr::V4(ip) => {
+				let o = ip.octets();
+				NetGroup::Ipv4([o[0], o[1]])
+			}
+			IpAddr::V6(ip) => match ip.to_ipv4() {
+				// mapped IPv4 addresses share the group of the IPv4 one
+				Some(ip4) if ip.segments()[..5] == [0; 5] => {
+					let o = ip4.octets();
+					NetGroup::Ipv4([o[0], o[1]])
+				}
+				_ => {
+					let o = ip.octets();
+					NetGroup::Ipv6([o[0], o[1], o[2], o[3]])
+				}
+			},
+		}
+	}
+}
+
+/// Autonomous systems announcing IP prefixes, resolved by longest prefix
+/// match
+#[derive(Debug, Clone, Default)]
+pub struct AsnMap {
+	// (prefix, length, asn), longest prefixes first
+	v4: Vec<(u32, u8, u32)>,
+	v6: Vec<(u128, u8, u32)>,
+}
+
+impl AsnMap {
+	/// Reads a map with a `prefix/length ASN` entry per line, such as
+	/// `1.2.0.0/16 1234`. Blank lines and the ones starting with `#` are
+	/// skipped.
+	pub fn from_file(path: &str) -> Result<AsnMap, Error> {
+		let file = File::open(path)?;
+		let mut lines = vec![];
+		for line in BufReader::new(file).lines() {
+			lines.push(line?);
+		}
+		AsnMap::parse(&lines.join("\n"))
+	}
+
+	/// Parses a map, as read from a file
+	pub fn parse(s: &str) -> Result<AsnMap, Error> {
+		let mut map = AsnMap::default();
+		for (n, line) in s.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let invalid = || Error::InvalidAsnMap(format!("line {}: {}", n + 1, line));
+			let mut parts = line.split_whitespace();
+			let (prefix, asn) = match (parts.next(), parts.next(), parts.next()) {
+				(Some(prefix), Some(asn), None) => (prefix, asn),
+				_ => return Err(invalid()),
+			};
+			let asn = asn
+				.trim_left_matches("AS")
+				.parse::<u32>()
+				.map_err(|_| invalid())?;
+			let mut prefix = prefix.splitn(2, '/');
+			let ip = prefix
+				.next()
+				.and_then(|ip| ip.parse::<IpAddr>().ok())
+				.ok_or_else(invalid)?;
+			let len = prefix
+				.next()
+				.and_then(|len| len.parse::<u8>().ok())
+				.ok_or_else(invalid)?;
+			match ip {
+				IpAddr::V4(ip) if len <= 32 => {
+					map.v4.push((u32::from(ip) & mask_v4(len), len, asn))
+				}
+				IpAddr::V6(ip) if len <= 128 => {
+					map.v6.push((u128::from(ip) & mask_v6(len), len, asn))
+				}
+				_ => return Err(invalid()),
+			}
+		}
+		map.v4.sort_by(|a, b| b.1.cmp(&a.1));
+		map.v6.sort_by(|a, b| b.1.cmp(&a.1));
+		Ok(map)
+	}
+
+	/// Number of prefixes in the map
+	pub fn len(&self) -> usize {
+		self.v4.len() + self.v6.len()
+	}
+
+	/// ASN announcing the longest prefix the address is in, if any
+	pub fn lookup(&self, ip: &IpAddr) -> Option<u32> {
+		match *ip {
+			IpAddr::V4(ip) => {
+				let ip = u32::from(ip);
+				self.v4
+					.iter()
+					.find(|&&(prefix, len, _)| ip & mask_v4(len) == prefix)
+					.map(|&(_, _, asn)| asn)
+			}
+			IpAddr::V6(ip) => {
+				let ip = u128::from(ip);
+				self.v6
+					.iter()
+					.find(|&&(prefix, len, _)| ip & mask_v6(len) == prefix)
+					.map(|&(_, _, asn)| asn)
+			}
+		}
+	}
+}
+
+fn mask_v4(len: u8) -> u32 {
+	if len == 0 {
+		0
+	} else {
+		!0 << (32 - len as u32)
+	}
+}
+
+fn mask_v6(len: u8) -> u128 {
+	if len == 0 {
+		0
+	} else {
+		!0 << (128 - len as u32)
+	}
+}
+
+/// Picks up to `count` outbound peers to drop, each paired with the candidate
+/// to dial instead. Peers are dropped from the groups holding most of our
+/// outbound connections, picked at random among them, and candidates taken
+/// from the groups we're the least connected to, one per group at most. A
+/// candidate only replaces a peer if its group, counting the earlier picks,
+/// holds strictly fewer of our peers than the dropped peer's group once it's
+/// gone, so nothing is dropped that can't be replaced for the better.
+pub fn plan_rotation(
+	outbound: &[(SocketAddr, NetGroup)],
+	candidates: &[(SocketAddr, NetGroup)],
+	count: usize,
+) -> Vec<(SocketAddr, SocketAddr)> {
+	let mut rng = thread_rng();
+	let mut by_group: HashMap<NetGroup, Vec<SocketAddr>> = HashMap::new();
+	for &(addr, group) in outbound {
+		by_group.entry(group).or_insert(vec![]).push(addr);
+	}
+	for addrs in by_group.values_mut() {
+		rng.shuffle(addrs);
+	}
+
+	// candidates in random order, one per group
+	let mut candidates = candidates
+		.iter()
+		.filter(|&&(addr, _)| !outbound.iter().any(|&(a, _)| a == addr))
+		.cloned()
+		.collect::<Vec<_>>();
+	rng.shuffle(&mut candidates);
+	let mut seen = vec![];
+	candidates.retain(|&(_, group)| {
+		if seen.contains(&group) {
+			false
+		} else {
+			seen.push(group);
+			true
+		}
+	});
+
+	let held_in = |by_group: &HashMap<NetGroup, Vec<SocketAddr>>, group| {
+		by_group.get(&group).map(|a| a.len()).unwrap_or(0)
+	};
+	let mut rotations = vec![];
+	while rotations.len() < count && !candidates.is_empty() {
+		// a random one of the groups holding most of our outbound peers
+		let held = by_group.values().map(|a| a.len()).max().unwrap_or(0);
+		if held == 0 {
+			break;
+		}
+		let most = by_group
+			.iter()
+			.filter(|&(_, addrs)| addrs.len() == held)
+			.map(|(g, _)| *g)
+			.collect::<Vec<_>>();
+		let from = most[rng.gen_range(0, most.len())];
+
+		// replaced from the least represented group, if less represented
+		// than the dropped peer's group without it
+		let i = candidates
+			.iter()
+			.enumerate()
+			.min_by_key(|&(_, &(_, group))| held_in(&by_group, group))
+			.map(|(i, _)| i)
+			.unwrap();
+		if held_in(&by_group, candidates[i].1) >= held - 1 {
+			break;
+		}
+		let dropped = by_group.get_mut(&from).unwrap().pop().unwrap();
+		let (addr, group) = candidates.remove(i);
+		by_group.entry(group).or_insert(vec![]).push(addr);
+		rotations.push((dropped, addr));
+	}
+	rotations
+}
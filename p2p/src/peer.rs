@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{HashSet, VecDeque};
 use std::fs::File;
 use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use util::{Mutex, RwLock};
+use util::{Mutex, RateCounter, RwLock};
 
 use chrono::prelude::{DateTime, Utc};
 use conn;
@@ -23,13 +25,24 @@ use core::core::hash::{Hash, Hashed};
 use core::pow::Difficulty;
 use core::{core, global};
 use handshake::Handshake;
-use msg::{self, BanReason, GetPeerAddrs, Locator, Ping, TxHashSetRequest};
+use msg::{
+	self, BanReason, GetPeerAddrs, Locator, Ping, RelayFilter, SegmentRequest, TxHashSetRequest,
+};
 use protocol::Protocol;
 use types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerInfo, ReasonForBan, TxHashSetRead,
+	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerInfo, ReasonForBan, Timeouts,
+	TxHashSetRead,
 };
 
 const MAX_TRACK_SIZE: usize = 30;
+/// Kernels of the transactions a peer is known to have that we keep track of
+const MAX_TRACK_TXS: usize = 1000;
+/// Most transactions sent ahead of a compact block
+const MAX_PREFILL_TXS: usize = 50;
+/// Seconds a failed request counts against the quality of a peer
+const FAILURE_WINDOW_SECS: u64 = 600;
+/// Round trip time assumed for a peer that didn't answer a ping yet
+const DEFAULT_RTT_MS: u64 = 500;
 const MAX_PEER_MSG_PER_MIN: u64 = 500;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -51,6 +64,11 @@ pub struct Peer {
 	// set of all hashes known to this peer (so no need to send)
 	tracking_adapter: TrackingAdapter,
 	connection: Option<Mutex<conn::Tracker>>,
+	// requests the peer timed out on or that failed
+	failures: RwLock<RateCounter>,
+	// only the transactions matching it are relayed to the peer, set by the
+	// protocol as soon as received
+	relay_filter: Arc<RwLock<Option<RelayFilter>>>,
 }
 
 impl Peer {
@@ -61,6 +79,8 @@ impl Peer {
 			state: Arc::new(RwLock::new(State::Connected)),
 			tracking_adapter: TrackingAdapter::new(adapter),
 			connection: None,
+			failures: RwLock::new(RateCounter::with_history(FAILURE_WINDOW_SECS)),
+			relay_filter: Arc::new(RwLock::new(None)),
 		}
 	}
 
@@ -89,11 +109,11 @@ impl Peer {
 
 	/// Main peer loop listening for messages and forwarding to the rest of the
 	/// system.
-	pub fn start(&mut self, conn: TcpStream) {
+	pub fn start(&mut self, conn: TcpStream, timeouts: Timeouts) {
 		let addr = self.info.addr;
 		let adapter = Arc::new(self.tracking_adapter.clone());
-		let handler = Protocol::new(adapter, addr);
-		self.connection = Some(Mutex::new(conn::listen(conn, handler)));
+		let handler = Protocol::new(adapter, addr, self.relay_filter.clone());
+		self.connection = Some(Mutex::new(conn::listen(conn, handler, timeouts)));
 	}
 
 	pub fn is_denied(config: &P2PConfig, peer_addr: &SocketAddr) -> bool {
@@ -183,6 +203,63 @@ impl Peer {
 		None
 	}
 
+	/// Bytes sent to and received from the peer in each of the last
+	/// `secs` seconds, oldest first
+	pub fn bytes_per_sec(&self, secs: u64) -> Option<(Vec<u64>, Vec<u64>)> {
+		if let Some(ref tracker) = self.connection {
+			let conn = tracker.lock();
+			let received_bytes = conn.received_bytes.read();
+			let sent_bytes = conn.sent_bytes.read();
+			return Some((
+				sent_bytes.bytes_per_sec(secs),
+				received_bytes.bytes_per_sec(secs),
+			));
+		}
+		None
+	}
+
+	/// Average bytes per second sent to and received from the peer over the
+	/// last `secs` seconds
+	pub fn bytes_rates(&self, secs: u64) -> Option<(f64, f64)> {
+		if let Some(ref tracker) = self.connection {
+			let conn = tracker.lock();
+			let received_bytes = conn.received_bytes.read();
+			let sent_bytes = conn.sent_bytes.read();
+			return Some((sent_bytes.bytes_rate(secs), received_bytes.bytes_rate(secs)));
+		}
+		None
+	}
+
+	/// A request to the peer timed out or failed, lowering its quality for a
+	/// while
+	pub fn record_failure(&self) {
+		self.failures.write().inc(0);
+	}
+
+	/// Number of requests the peer failed over the last 10 minutes
+	pub fn failures(&self) -> u64 {
+		self.failures.read().count_in(FAILURE_WINDOW_SECS)
+	}
+
+	/// Quality of the peer as a source of data for sync, higher is better.
+	/// Starts from the bytes it sent us per second over the last minute,
+	/// divided down by its ping round trip time and by the requests it
+	/// failed lately.
+	pub fn quality(&self) -> f64 {
+		let received = self.bytes_rates(60).map(|(_, r)| r).unwrap_or(0.0);
+		let rtt = self.info.rtt_ms().unwrap_or(DEFAULT_RTT_MS) as f64;
+		(1.0 + received / 1024.0) / (1.0 + rtt / 100.0) / (1.0 + self.failures() as f64)
+	}
+
+	/// Number of messages queued for sending to the peer, not fully sent yet
+	pub fn send_queue_len(&self) -> Option<usize> {
+		if let Some(ref tracker) = self.connection {
+			let conn = tracker.lock();
+			return Some(conn.send_queued.load(Ordering::Relaxed));
+		}
+		None
+	}
+
 	pub fn last_min_message_counts(&self) -> Option<(u64, u64)> {
 		if let Some(ref tracker) = self.connection {
 			let conn = tracker.lock();
@@ -198,6 +275,17 @@ impl Peer {
 		*self.state.write() = State::Banned;
 	}
 
+	/// Set the filter on the transactions relayed to this peer, an empty one
+	/// clearing it
+	pub fn set_relay_filter(&self, filter: RelayFilter) {
+		set_relay_filter(&self.relay_filter, filter);
+	}
+
+	/// Filter on the transactions relayed to this peer, if any
+	pub fn relay_filter(&self) -> Option<RelayFilter> {
+		self.relay_filter.read().clone()
+	}
+
 	/// Send a ping to the remote peer, providing our local difficulty and
 	/// height
 	pub fn send_ping(&self, total_difficulty: Difficulty, height: u64) -> Result<(), Error> {
@@ -205,6 +293,7 @@ impl Peer {
 			total_difficulty,
 			height,
 		};
+		self.info.ping_sent();
 		self.connection
 			.as_ref()
 			.unwrap()
@@ -251,14 +340,29 @@ impl Peer {
 		}
 	}
 
-	pub fn send_compact_block(&self, b: &core::CompactBlock) -> Result<bool, Error> {
+	/// Sends the provided compact block to the remote peer, preceded by those
+	/// of the block transactions it likely lacks, so it can hydrate the block
+	/// without asking for it in full. The request may be dropped if the
+	/// remote peer is known to already have the block.
+	pub fn send_compact_block(
+		&self,
+		b: &core::CompactBlock,
+		txs: &[core::Transaction],
+	) -> Result<bool, Error> {
 		if !self.tracking_adapter.has(b.hash()) {
-			trace!("Send compact block {} to {}", b.hash(), self.info.addr);
-			self.connection
-				.as_ref()
-				.unwrap()
-				.lock()
-				.send(b, msg::Type::CompactBlock)?;
+			let prefill = self.tracking_adapter.prefill(txs);
+			trace!(
+				"Send compact block {} to {}, {} txs ahead",
+				b.hash(),
+				self.info.addr,
+				prefill.len()
+			);
+			self.connection.as_ref().unwrap().lock().send_preceded(
+				b,
+				msg::Type::CompactBlock,
+				&prefill,
+				msg::Type::Transaction,
+			)?;
 			Ok(true)
 		} else {
 			debug!(
@@ -290,13 +394,14 @@ impl Peer {
 	}
 
 	pub fn send_tx_kernel_hash(&self, h: Hash) -> Result<bool, Error> {
-		if !self.tracking_adapter.has(h) {
+		if !self.tracking_adapter.has_tx(h) {
 			debug!("Send tx kernel hash {} to {}", h, self.info.addr);
 			self.connection
 				.as_ref()
 				.unwrap()
 				.lock()
 				.send(h, msg::Type::TransactionKernel)?;
+			self.tracking_adapter.push_tx(h);
 			Ok(true)
 		} else {
 			debug!(
@@ -312,6 +417,17 @@ impl Peer {
 	/// We support broadcast of lightweight tx kernel hash
 	/// so track known txs by kernel hash.
 	pub fn send_transaction(&self, tx: &core::Transaction) -> Result<bool, Error> {
+		if let Some(ref filter) = *self.relay_filter.read() {
+			if !filter.matches(tx) {
+				trace!(
+					"Not sending tx {} to {} (filtered out)",
+					tx.hash(),
+					self.info.addr
+				);
+				return Ok(false);
+			}
+		}
+
 		let kernel = &tx.kernels()[0];
 
 		if self
@@ -322,13 +438,14 @@ impl Peer {
 			return self.send_tx_kernel_hash(kernel.hash());
 		}
 
-		if !self.tracking_adapter.has(kernel.hash()) {
+		if !self.tracking_adapter.has_tx(kernel.hash()) {
 			debug!("Send full tx {} to {}", tx.hash(), self.info.addr);
 			self.connection
 				.as_ref()
 				.unwrap()
 				.lock()
 				.send(tx, msg::Type::Transaction)?;
+			self.tracking_adapter.push_txs(tx);
 			Ok(true)
 		} else {
 			debug!(
@@ -394,6 +511,20 @@ impl Peer {
 			.send(&h, msg::Type::GetCompactBlock)
 	}
 
+	/// Sets our filter on the transactions the peer relays to us
+	pub fn send_relay_filter(&self, filter: &RelayFilter) -> Result<(), Error> {
+		debug!(
+			"Setting a relay filter of {} prefixes on {}",
+			filter.prefixes.len(),
+			self.info.addr
+		);
+		self.connection
+			.as_ref()
+			.unwrap()
+			.lock()
+			.send(filter, msg::Type::RelayFilter)
+	}
+
 	pub fn send_peer_request(&self, capab: Capabilities) -> Result<(), Error> {
 		debug!("Asking {} for more peers.", self.info.addr);
 		self.connection.as_ref().unwrap().lock().send(
@@ -409,9 +540,46 @@ impl Peer {
 			"Asking {} for txhashset archive at {} {}.",
 			self.info.addr, height, hash
 		);
+		// the archive gets verified chunk by chunk if the peer can send
+		// their digests
+		let msg_type = if self
+			.info
+			.capabilities
+			.contains(Capabilities::TXHASHSET_CHUNKS)
+		{
+			msg::Type::TxHashSetChunkedRequest
+		} else {
+			msg::Type::TxHashSetRequest
+		};
+		self.connection
+			.as_ref()
+			.unwrap()
+			.lock()
+			.send(&TxHashSetRequest { hash, height }, msg_type)
+	}
+
+	/// Asks the peer for a segment of one of the txhashset MMRs, as of the
+	/// provided block.
+	pub fn send_segment_request(
+		&self,
+		hash: Hash,
+		segment_type: core::pmmr::SegmentType,
+		identifier: core::pmmr::SegmentIdentifier,
+	) -> Result<(), Error> {
+		trace!(
+			"Asking {} for {:?} segment {:?} at {}.",
+			self.info.addr,
+			segment_type,
+			identifier,
+			hash
+		);
 		self.connection.as_ref().unwrap().lock().send(
-			&TxHashSetRequest { hash, height },
-			msg::Type::TxHashSetRequest,
+			&SegmentRequest {
+				hash,
+				segment_type,
+				identifier,
+			},
+			msg::Type::GetTxHashSetSegment,
 		)
 	}
 
@@ -470,12 +638,24 @@ fn stop_with_connection(connection: &conn::Tracker) {
 	let _ = connection.close_channel.send(());
 }
 
+/// Sets the filter on the transactions relayed to a peer, an empty one
+/// clearing it
+pub fn set_relay_filter(relay_filter: &RwLock<Option<RelayFilter>>, filter: RelayFilter) {
+	*relay_filter.write() = if filter.prefixes.is_empty() {
+		None
+	} else {
+		Some(filter)
+	};
+}
+
 /// Adapter implementation that forwards everything to an underlying adapter
 /// but keeps track of the block and transaction hashes that were received.
 #[derive(Clone)]
 struct TrackingAdapter {
 	adapter: Arc<NetAdapter>,
 	known: Arc<RwLock<Vec<Hash>>>,
+	// kernels of the transactions the peer relayed to us or got from us
+	known_txs: Arc<RwLock<KnownTxs>>,
 }
 
 impl TrackingAdapter {
@@ -483,7 +663,39 @@ impl TrackingAdapter {
 		TrackingAdapter {
 			adapter: adapter,
 			known: Arc::new(RwLock::new(vec![])),
+			known_txs: Arc::new(RwLock::new(KnownTxs::new())),
+		}
+	}
+
+	fn has_tx(&self, kernel_hash: Hash) -> bool {
+		self.known_txs.read().set.contains(&kernel_hash)
+	}
+
+	fn push_tx(&self, kernel_hash: Hash) {
+		self.known_txs.write().insert(kernel_hash);
+	}
+
+	fn push_txs(&self, tx: &core::Transaction) {
+		let mut known_txs = self.known_txs.write();
+		for k in tx.kernels() {
+			known_txs.insert(k.hash());
+		}
+	}
+
+	/// The transactions the peer likely lacks, not having any of their
+	/// kernels, up to MAX_PREFILL_TXS. They're tracked as known from there,
+	/// about to be sent.
+	fn prefill(&self, txs: &[core::Transaction]) -> Vec<core::Transaction> {
+		let prefill: Vec<core::Transaction> = txs
+			.iter()
+			.filter(|tx| !tx.kernels().iter().any(|k| self.has_tx(k.hash())))
+			.take(MAX_PREFILL_TXS)
+			.cloned()
+			.collect();
+		for tx in &prefill {
+			self.push_txs(tx);
 		}
+		prefill
 	}
 
 	fn has(&self, hash: Hash) -> bool {
@@ -515,8 +727,12 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.get_transaction(kernel_hash)
 	}
 
+	fn get_block_transactions(&self, b: &core::Block) -> Vec<core::Transaction> {
+		self.prefill(&self.adapter.get_block_transactions(b))
+	}
+
 	fn tx_kernel_received(&self, kernel_hash: Hash, addr: SocketAddr) {
-		self.push(kernel_hash);
+		self.push_tx(kernel_hash);
 		self.adapter.tx_kernel_received(kernel_hash, addr)
 	}
 
@@ -525,8 +741,7 @@ impl ChainAdapter for TrackingAdapter {
 		// Otherwise we fail to handle the subsequent fluff or embargo expiration
 		// correctly.
 		if !stem {
-			let kernel = &tx.kernels()[0];
-			self.push(kernel.hash());
+			self.push_txs(&tx);
 		}
 		self.adapter.transaction_received(tx, stem)
 	}
@@ -579,6 +794,25 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter
 			.txhashset_download_update(start_time, downloaded_size, total_size)
 	}
+
+	fn txhashset_segment_read(
+		&self,
+		h: Hash,
+		segment_type: core::pmmr::SegmentType,
+		id: core::pmmr::SegmentIdentifier,
+	) -> Option<core::pmmr::TxHashSetSegment> {
+		self.adapter.txhashset_segment_read(h, segment_type, id)
+	}
+
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: core::pmmr::TxHashSetSegment,
+		peer_addr: SocketAddr,
+	) -> bool {
+		self.adapter
+			.txhashset_segment_received(h, segment, peer_addr)
+	}
 }
 
 impl NetAdapter for TrackingAdapter {
@@ -594,7 +828,42 @@ impl NetAdapter for TrackingAdapter {
 		self.adapter.peer_difficulty(addr, diff, height)
 	}
 
+	fn peer_pong(&self, addr: SocketAddr) {
+		self.adapter.peer_pong(addr)
+	}
+
+	fn relay_filter_received(&self, addr: SocketAddr, filter: &RelayFilter) -> bool {
+		self.adapter.relay_filter_received(addr, filter)
+	}
+
 	fn is_banned(&self, addr: SocketAddr) -> bool {
 		self.adapter.is_banned(addr)
 	}
 }
+
+/// Kernel hashes of the transactions a peer has, the oldest forgotten first
+struct KnownTxs {
+	set: HashSet<Hash>,
+	order: VecDeque<Hash>,
+}
+
+impl KnownTxs {
+	fn new() -> KnownTxs {
+		KnownTxs {
+			set: HashSet::new(),
+			order: VecDeque::new(),
+		}
+	}
+
+	fn insert(&mut self, kernel_hash: Hash) {
+		if !self.set.insert(kernel_hash) {
+			return;
+		}
+		self.order.push_back(kernel_hash);
+		if self.order.len() > MAX_TRACK_TXS {
+			if let Some(oldest) = self.order.pop_front() {
+				self.set.remove(&oldest);
+			}
+		}
+	}
+}
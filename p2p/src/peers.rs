@@ -12,10 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::collections::HashMap;
 use std::fs::File;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
 use std::sync::Arc;
+use std::time;
 use util::RwLock;
 
 use rand::{thread_rng, Rng};
@@ -27,38 +30,83 @@ use core::core::hash::{Hash, Hashed};
 use core::global;
 use core::pow::Difficulty;
 
+use broadcast::{rank_by_rtt, BroadcastScheduler};
+use export::{PeerExport, PeerSeed, MAX_EXPORT_PEERS};
+use grin_store::Page;
+use msg::RelayFilter;
+use netgroup::{plan_rotation, AsnMap, NetGroup};
 use peer::Peer;
-use store::{PeerData, PeerStore, State};
+use store::{PeerData, PeerEvent, PeerEventKind, PeerStore, State};
 use types::{
 	Capabilities, ChainAdapter, Direction, Error, NetAdapter, P2PConfig, ReasonForBan,
 	TxHashSetRead, MAX_PEER_ADDRS,
 };
 
+/// One in that many times, a peer picked at random is ranked first for sync
+/// rather than the best one, so the others get a chance to show how good
+/// they are
+const PROBE_ONE_IN: u32 = 10;
+
 pub struct Peers {
 	pub adapter: Arc<ChainAdapter>,
 	store: PeerStore,
 	peers: RwLock<HashMap<SocketAddr, Arc<Peer>>>,
 	dandelion_relay: RwLock<HashMap<i64, Arc<Peer>>>,
-	config: P2PConfig,
+	config: RwLock<P2PConfig>,
+	asn_map: Option<AsnMap>,
+	broadcasts: BroadcastScheduler<Arc<Peer>>,
 }
 
 impl Peers {
 	pub fn new(store: PeerStore, adapter: Arc<ChainAdapter>, config: P2PConfig) -> Peers {
+		let asn_map = config
+			.asn_map_file
+			.as_ref()
+			.and_then(|path| match AsnMap::from_file(path) {
+				Ok(map) => {
+					info!("Grouping peers with {} prefixes from {}", map.len(), path);
+					Some(map)
+				}
+				Err(e) => {
+					error!("Couldn't read ASN map {}, grouping by /16: {:?}", path, e);
+					None
+				}
+			});
 		Peers {
 			adapter,
 			store,
-			config,
+			asn_map,
+			config: RwLock::new(config),
 			peers: RwLock::new(HashMap::new()),
 			dandelion_relay: RwLock::new(HashMap::new()),
+			broadcasts: BroadcastScheduler::new(),
 		}
 	}
 
+	/// Current p2p configuration, peer limits included
+	pub fn config(&self) -> P2PConfig {
+		self.config.read().clone()
+	}
+
+	/// Changes the peer limits, as when reloading the configuration. Peers
+	/// above the new maximum get dropped on the next clean up.
+	pub fn set_peer_limits(
+		&self,
+		peer_max_count: Option<u32>,
+		peer_min_preferred_count: Option<u32>,
+	) {
+		let mut config = self.config.write();
+		config.peer_max_count = peer_max_count;
+		config.peer_min_preferred_count = peer_min_preferred_count;
+	}
+
 	/// Adds the peer to our internal peer mapping. Note that the peer is still
 	/// returned so the server can run it.
 	pub fn add_connected(&self, peer: Arc<Peer>) -> Result<(), Error> {
 		let peer_data: PeerData;
 		let addr: SocketAddr;
 		{
+			let now = Utc::now().timestamp();
 			peer_data = PeerData {
 				addr: peer.info.addr,
 				capabilities: peer.info.capabilities,
@@ -66,12 +114,37 @@ impl Peers {
 				flags: State::Healthy,
 				last_banned: 0,
 				ban_reason: ReasonForBan::None,
-				last_connected: Utc::now().timestamp(),
+				last_connected: now,
+				ban_duration: 0,
+				ban_note: "".to_string(),
+				// reached at its address if we dialed it, otherwise as
+				// verified as it was
+				verified: peer.info.direction == Direction::Outbound
+					|| self
+						.store
+						.get_peer(peer.info.addr)
+						.map(|p| p.verified)
+						.unwrap_or(false),
+				version: peer.info.version,
+				last_handshake: now,
 			};
 			addr = peer.info.addr.clone();
 		}
 		debug!("Saving newly connected peer {}.", addr);
 		self.save_peer(&peer_data)?;
+		self.record_peer_event(
+			&peer,
+			PeerEventKind::Connected,
+			&format!("protocol version {}", peer.info.version),
+		);
+
+		if peer.info.capabilities.contains(Capabilities::RELAY_FILTER) {
+			if let Some(filter) = self.config.read().relay_filter() {
+				if let Err(e) = peer.send_relay_filter(&filter) {
+					debug!("Could not set our relay filter on {}: {:?}", addr, e);
+				}
+			}
+		}
 
 		{
 			let mut peers = self.peers.write();
@@ -132,6 +205,17 @@ impl Peers {
 		res
 	}
 
+	/// Messages queued for sending to the connected peers, in total and for
+	/// the most backed up peer
+	pub fn send_queue_depths(&self) -> (usize, usize) {
+		self.connected_peers()
+			.iter()
+			.filter_map(|p| p.send_queue_len())
+			.fold((0, 0), |(total, max), len| {
+				(total + len, cmp::max(max, len))
+			})
+	}
+
 	/// Get a peer we're connected to by address.
 	pub fn get_connected_peer(&self, addr: &SocketAddr) -> Option<Arc<Peer>> {
 		self.peers.read().get(addr).map(|p| p.clone())
@@ -147,7 +231,7 @@ impl Peers {
 	}
 
 	// Return vec of connected peers that currently advertise more work
-	// (total_difficulty) than we do.
+	// (total_difficulty) than we do, best quality first.
 	pub fn more_work_peers(&self) -> Vec<Arc<Peer>> {
 		let peers = self.connected_peers();
 		if peers.len() == 0 {
@@ -156,22 +240,22 @@ impl Peers {
 
 		let total_difficulty = self.total_difficulty();
 
-		let mut max_peers = peers
+		let max_peers = peers
 			.into_iter()
 			.filter(|x| x.info.total_difficulty() > total_difficulty)
 			.collect::<Vec<_>>();
 
-		thread_rng().shuffle(&mut max_peers);
-		max_peers
+		rank_by_quality(max_peers)
 	}
 
-	/// Returns single random peer with more work than us.
+	/// Returns the best quality peer with more work than us, or now and then
+	/// another one to probe.
 	pub fn more_work_peer(&self) -> Option<Arc<Peer>> {
-		self.more_work_peers().pop()
+		self.more_work_peers().into_iter().next()
 	}
 
 	/// Return vec of connected peers that currently have the most worked
-	/// branch, showing the highest total difficulty.
+	/// branch, showing the highest total difficulty, best quality first.
 	pub fn most_work_peers(&self) -> Vec<Arc<Peer>> {
 		let peers = self.connected_peers();
 		if peers.len() == 0 {
@@ -184,50 +268,137 @@ impl Peers {
 			.max()
 			.unwrap();
 
-		let mut max_peers = peers
+		let max_peers = peers
 			.into_iter()
 			.filter(|x| x.info.total_difficulty() == max_total_difficulty)
 			.collect::<Vec<_>>();
 
-		thread_rng().shuffle(&mut max_peers);
-		max_peers
+		rank_by_quality(max_peers)
 	}
 
-	/// Returns single random peer with the most worked branch, showing the
-	/// highest total difficulty.
+	/// Returns the best quality peer with the most worked branch, showing
+	/// the highest total difficulty, or now and then another one to probe.
 	pub fn most_work_peer(&self) -> Option<Arc<Peer>> {
-		self.most_work_peers().pop()
+		self.most_work_peers().into_iter().next()
+	}
+
+	/// A request to the peer timed out or failed, lowering its quality
+	pub fn record_failure(&self, addr: &SocketAddr) {
+		if let Some(peer) = self.get_connected_peer(addr) {
+			peer.record_failure();
+			self.record_peer_event(
+				&peer,
+				PeerEventKind::Misbehaved,
+				"request timed out or failed",
+			);
+		}
 	}
 
+	/// Whether the peer is banned, bans that ran out not counting anymore
 	pub fn is_banned(&self, peer_addr: SocketAddr) -> bool {
 		if let Ok(peer_data) = self.store.get_peer(peer_addr) {
-			if peer_data.flags == State::Banned {
+			if peer_data.flags == State::Banned
+				&& Utc::now().timestamp() < self.ban_expiry(&peer_data)
+			{
 				return true;
 			}
 		}
 		false
 	}
 
-	/// Ban a peer, disconnecting it if we're currently connected
+	/// Ban a peer for the configured ban window, disconnecting it if we're
+	/// currently connected
 	pub fn ban_peer(&self, peer_addr: &SocketAddr, ban_reason: ReasonForBan) {
-		if let Err(e) = self.update_state(*peer_addr, State::Banned) {
+		self.ban_peer_for(peer_addr, ban_reason, 0, "")
+	}
+
+	/// Ban a peer for a number of seconds, 0 meaning the configured ban
+	/// window, with a note on what it's about, disconnecting it if we're
+	/// currently connected. Peers we've never heard of can be banned too.
+	pub fn ban_peer_for(
+		&self,
+		peer_addr: &SocketAddr,
+		ban_reason: ReasonForBan,
+		duration: i64,
+		note: &str,
+	) {
+		// the ban window in force now is kept, later changes to it don't
+		// apply to existing bans
+		let duration = if duration > 0 {
+			duration
+		} else {
+			self.config.read().ban_window()
+		};
+		if let Err(e) = self.store.ban_peer(*peer_addr, ban_reason, duration, note) {
 			error!("Couldn't ban {}: {:?}", peer_addr, e);
 		}
+		let detail = format!("{:?} for {}s {}", ban_reason, duration, note);
 
 		if let Some(peer) = self.get_connected_peer(peer_addr) {
 			debug!("Banning peer {}", peer_addr);
+			self.record_peer_event(&peer, PeerEventKind::Banned, detail.trim());
 			// setting peer status will get it removed at the next clean_peer
 			peer.send_ban_reason(ban_reason);
 			peer.set_banned();
 			peer.stop();
+		} else {
+			self.record_event(*peer_addr, PeerEventKind::Banned, None, "", detail.trim());
+		}
+	}
+
+	/// Disconnects from a peer without banning it, it may connect again
+	/// later. Returns false if we weren't connected to it.
+	pub fn disconnect_peer(&self, peer_addr: &SocketAddr) -> bool {
+		match self.peers.write().remove(peer_addr) {
+			Some(peer) => {
+				debug!("Disconnecting peer {}", peer_addr);
+				peer.stop();
+				self.record_peer_event(&peer, PeerEventKind::Disconnected, "on request");
+				true
+			}
+			None => false,
 		}
 	}
 
+	/// When the ban on a peer runs out, bans recorded without a duration
+	/// lasting the configured ban window
+	pub fn ban_expiry(&self, peer_data: &PeerData) -> i64 {
+		let duration = if peer_data.ban_duration > 0 {
+			peer_data.ban_duration
+		} else {
+			self.config.read().ban_window()
+		};
+		peer_data.last_banned + duration
+	}
+
+	/// All the peers currently banned
+	pub fn banned_peers(&self) -> Vec<PeerData> {
+		let now = Utc::now().timestamp();
+		self.all_peers()
+			.into_iter()
+			.filter(|p| p.flags == State::Banned && now < self.ban_expiry(p))
+			.collect()
+	}
+
+	/// Lifts the bans that ran out, returning the peers unbanned
+	pub fn unban_expired(&self) -> Vec<PeerData> {
+		let now = Utc::now().timestamp();
+		let expired = self
+			.all_peers()
+			.into_iter()
+			.filter(|p| p.flags == State::Banned && now >= self.ban_expiry(p))
+			.collect::<Vec<_>>();
+		for p in &expired {
+			self.unban_peer(&p.addr);
+		}
+		expired
+	}
+
 	/// Unban a peer, checks if it exists and banned then unban
 	pub fn unban_peer(&self, peer_addr: &SocketAddr) {
 		match self.get_peer(*peer_addr) {
-			Ok(_) => {
-				if self.is_banned(*peer_addr) {
+			Ok(peer_data) => {
+				if peer_data.flags == State::Banned {
 					if let Err(e) = self.update_state(*peer_addr, State::Healthy) {
 						error!("Couldn't unban {}: {:?}", peer_addr, e);
 					}
@@ -248,10 +419,8 @@ impl Peers {
 		// Iterate over our connected peers.
 		// Try our best to send to at most num_peers peers.
 		for p in self.connected_peers().iter() {
-			match inner(&p) {
-				Ok(true) => count += 1,
-				Ok(false) => (),
-				Err(e) => debug!("Error sending {} to peer: {:?}", obj_name, e),
+			if send_to(obj_name, &p, &inner) {
+				count += 1;
 			}
 
 			if count >= num_peers {
@@ -261,16 +430,63 @@ impl Peers {
 		count
 	}
 
+	/// As broadcast, sending to the fastest peers first, as many as the
+	/// configured batch size, then to the others in batches of that size
+	/// spaced out by the configured delay, so the first sends don't compete
+	/// with all the others for our upload. Returns the number of peers sent
+	/// to right away.
+	fn broadcast_staggered<F>(&self, obj_name: &'static str, num_peers: u32, inner: F) -> u32
+	where
+		F: Fn(&Peer) -> Result<bool, Error> + Send + 'static,
+	{
+		let (batch_size, stagger) = {
+			let config = self.config.read();
+			(config.broadcast_batch_size(), config.broadcast_stagger())
+		};
+		if batch_size == 0 {
+			return self.broadcast(obj_name, num_peers, inner);
+		}
+
+		let mut peers = rank_by_rtt(self.connected_peers(), |p| p.info.rtt_ms()).into_iter();
+		let mut count = 0;
+		while count < cmp::min(batch_size, num_peers) {
+			match peers.next() {
+				Some(p) => {
+					if send_to(obj_name, &p, &inner) {
+						count += 1;
+					}
+				}
+				None => break,
+			}
+		}
+
+		if count < num_peers {
+			self.broadcasts.schedule(
+				obj_name,
+				peers.collect(),
+				batch_size,
+				stagger,
+				num_peers - count,
+				move |p| p.is_connected() && send_to(obj_name, p, &inner),
+			);
+		}
+		count
+	}
+
 	/// Broadcasts the provided compact block to PEER_MAX_COUNT of our peers.
 	/// This is only used when initially broadcasting a newly mined block
 	/// from a mining node so we want to broadcast it far and wide.
+	/// The block transactions each peer likely lacks are sent ahead of it.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the block.
-	pub fn broadcast_compact_block(&self, b: &core::CompactBlock) {
-		let num_peers = self.config.peer_max_count();
-		let count = self.broadcast("compact block", num_peers, |p| p.send_compact_block(b));
+	pub fn broadcast_compact_block(&self, b: &core::CompactBlock, txs: &[core::Transaction]) {
+		let num_peers = self.config.read().peer_max_count();
+		let (cb, txs) = (b.clone(), txs.to_vec());
+		let count = self.broadcast_staggered("compact block", num_peers, move |p| {
+			p.send_compact_block(&cb, &txs)
+		});
 		debug!(
-			"broadcast_compact_block: {}, {} at {}, to {} peers, done.",
+			"broadcast_compact_block: {}, {} at {}, to {} peers first, done.",
 			b.hash(),
 			b.header.pow.total_difficulty,
 			b.header.height,
@@ -284,10 +500,11 @@ impl Peers {
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the header.
 	pub fn broadcast_header(&self, bh: &core::BlockHeader) {
-		let num_peers = self.config.peer_min_preferred_count();
-		let count = self.broadcast("header", num_peers, |p| p.send_header(bh));
+		let num_peers = self.config.read().peer_min_preferred_count();
+		let header = bh.clone();
+		let count = self.broadcast_staggered("header", num_peers, move |p| p.send_header(&header));
 		debug!(
-			"broadcast_header: {}, {} at {}, to {} peers, done.",
+			"broadcast_header: {}, {} at {}, to {} peers first, done.",
 			bh.hash(),
 			bh.pow.total_difficulty,
 			bh.height,
@@ -323,7 +540,7 @@ impl Peers {
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the transaction.
 	pub fn broadcast_transaction(&self, tx: &core::Transaction) {
-		let num_peers = self.config.peer_max_count();
+		let num_peers = self.config.read().peer_max_count();
 		let count = self.broadcast("transaction", num_peers, |p| p.send_transaction(tx));
 		debug!(
 			"broadcast_transaction: {} to {} peers, done.",
@@ -343,16 +560,114 @@ impl Peers {
 		}
 	}
 
+	/// Writes a consistent copy of the peer store to the provided directory
+	pub fn copy_store_to(&self, dest: &Path) -> Result<(), Error> {
+		self.store.copy_to(dest).map_err(From::from)
+	}
+
 	/// All peer information we have in storage
 	pub fn all_peers(&self) -> Vec<PeerData> {
 		self.store.all_peers()
 	}
 
+	/// Known-good peers to seed another node with, signed by this node: the
+	/// healthy peers we reached ourselves, best scored first, and the most
+	/// recently connected first among those scoring the same.
+	pub fn export_peers(&self, count: usize) -> Result<PeerExport, Error> {
+		let quality = self
+			.connected_peers()
+			.iter()
+			.map(|p| (p.info.addr, p.quality()))
+			.collect::<HashMap<_, _>>();
+		let mut seeds = self
+			.store
+			.all_peers()
+			.into_iter()
+			.filter(|p| p.flags == State::Healthy && p.verified)
+			.map(|p| PeerSeed {
+				score: quality
+					.get(&p.addr)
+					.map(|q| (q * 100.0) as u64)
+					.unwrap_or(0),
+				addr: p.addr,
+				capabilities: p.capabilities,
+				user_agent: p.user_agent,
+				last_connected: p.last_connected,
+			}).collect::<Vec<_>>();
+		seeds.sort_by(|a, b| {
+			b.score
+				.cmp(&a.score)
+				.then(b.last_connected.cmp(&a.last_connected))
+		});
+		seeds.truncate(cmp::min(count, MAX_EXPORT_PEERS));
+		let key = self.store.signing_key()?;
+		PeerExport::sign(Utc::now().timestamp(), seeds, &key)
+	}
+
+	/// Saves the peers of an export we don't know of yet, once it checks out,
+	/// returning how many. They're healthy but unverified until we reach
+	/// them. Peers we already know of, banned ones included, are left as
+	/// they are.
+	pub fn import_peers(
+		&self,
+		export: &PeerExport,
+		max_age: i64,
+		signer: Option<&str>,
+	) -> Result<usize, Error> {
+		export.verify(Utc::now().timestamp(), max_age, signer)?;
+		let mut imported = 0;
+		for seed in &export.peers {
+			if self.exists_peer(seed.addr)? {
+				continue;
+			}
+			let peer = PeerData {
+				addr: seed.addr,
+				capabilities: seed.capabilities,
+				user_agent: seed.user_agent.clone(),
+				flags: State::Healthy,
+				last_banned: 0,
+				ban_reason: ReasonForBan::None,
+				last_connected: seed.last_connected,
+				ban_duration: 0,
+				ban_note: "".to_string(),
+				verified: false,
+				version: 0,
+				last_handshake: 0,
+			};
+			self.save_peer(&peer)?;
+			imported += 1;
+		}
+		info!(
+			"Imported {} of {} peers exported by {}",
+			imported,
+			export.peers.len(),
+			export.public_key
+		);
+		Ok(imported)
+	}
+
+	/// A page of the peer information we have in storage, starting at the
+	/// continuation token of a previous page if provided
+	pub fn peers_page(&self, from: Option<&[u8]>, limit: usize) -> Result<Page<PeerData>, Error> {
+		self.store.peers_page(from, limit).map_err(From::from)
+	}
+
 	/// Find peers in store (not necessarily connected) and return their data
 	pub fn find_peers(&self, state: State, cap: Capabilities, count: usize) -> Vec<PeerData> {
 		self.store.find_peers(state, cap, count)
 	}
 
+	/// Peers worth dialing for the provided capabilities, as far as we know
+	/// from our last handshake with them
+	pub fn find_dial_candidates(
+		&self,
+		state: State,
+		cap: Capabilities,
+		count: usize,
+	) -> Vec<PeerData> {
+		self.store.find_dial_candidates(state, cap, count)
+	}
+
 	/// Get peer in store by address
 	pub fn get_peer(&self, peer_addr: SocketAddr) -> Result<PeerData, Error> {
 		self.store.get_peer(peer_addr).map_err(From::from)
@@ -368,6 +683,20 @@ impl Peers {
 		self.store.save_peer(p).map_err(From::from)
 	}
 
+	/// Tries connecting back to a peer that connected to us, at the address
+	/// it advertised, marking the address as verified if it's reachable. The
+	/// connection is dropped right away.
+	pub fn verify_peer_addr(&self, addr: SocketAddr, timeout: time::Duration) {
+		match TcpStream::connect_timeout(&addr, timeout) {
+			Ok(_) => {
+				if let Err(e) = self.store.set_verified(addr) {
+					error!("Couldn't mark {} as verified: {:?}", addr, e);
+				}
+			}
+			Err(e) => debug!("Peer {} not reachable at its address: {}", addr, e),
+		}
+	}
+
 	/// Updates the state of a peer in store
 	pub fn update_state(&self, peer_addr: SocketAddr, new_state: State) -> Result<(), Error> {
 		self.store
@@ -381,28 +710,36 @@ impl Peers {
 	pub fn clean_peers(&self, max_count: usize) {
 		let mut rm = vec![];
 
-		// build a list of peers to be cleaned up
+		// build a list of peers to be cleaned up, with why
 		for peer in self.peers.read().values() {
 			if peer.is_banned() {
 				debug!("clean_peers {:?}, peer banned", peer.info.addr);
-				rm.push(peer.info.addr.clone());
+				rm.push((peer.info.addr.clone(), "banned"));
 			} else if !peer.is_connected() {
 				debug!("clean_peers {:?}, not connected", peer.info.addr);
-				rm.push(peer.info.addr.clone());
+				rm.push((peer.info.addr.clone(), "connection closed"));
 			} else if peer.is_abusive() {
 				let counts = peer.last_min_message_counts().unwrap();
 				debug!(
 					"clean_peers {:?}, abusive ({} sent, {} recv)",
 					peer.info.addr, counts.0, counts.1,
 				);
-				let _ = self.update_state(peer.info.addr, State::Banned);
-				rm.push(peer.info.addr.clone());
+				let note = format!(
+					"{} messages sent, {} received in a minute",
+					counts.0, counts.1
+				);
+				let duration = self.config.read().ban_window();
+				let _ = self
+					.store
+					.ban_peer(peer.info.addr, ReasonForBan::Abusive, duration, &note);
+				self.record_peer_event(peer, PeerEventKind::Misbehaved, &note);
+				rm.push((peer.info.addr.clone(), "abusive"));
 			} else {
 				let (stuck, diff) = peer.is_stuck();
 				if stuck && diff < self.adapter.total_difficulty() {
 					debug!("clean_peers {:?}, stuck peer", peer.info.addr);
 					let _ = self.update_state(peer.info.addr, State::Defunct);
-					rm.push(peer.info.addr.clone());
+					rm.push((peer.info.addr.clone(), "stuck"));
 				}
 			}
 		}
@@ -415,36 +752,155 @@ impl Peers {
 				.connected_peers()
 				.iter()
 				.take(excess_count)
-				.map(|x| x.info.addr.clone())
+				.map(|x| (x.info.addr.clone(), "too many peers"))
 				.collect::<Vec<_>>();
 			rm.append(&mut addrs);
 		}
 
 		// now clean up peer map based on the list to remove
+		let mut removed = vec![];
 		{
 			let mut peers = self.peers.write();
-			for p in rm {
-				let _ = peers.get(&p).map(|p| p.stop());
-				peers.remove(&p);
+			for (p, why) in rm {
+				if let Some(peer) = peers.remove(&p) {
+					peer.stop();
+					removed.push((peer, why));
+				}
 			}
 		}
+		for (peer, why) in removed {
+			self.record_peer_event(&peer, PeerEventKind::Disconnected, why);
+		}
+	}
+
+	/// Network group of an address, by ASN if we have a map resolving it
+	pub fn net_group(&self, addr: &SocketAddr) -> NetGroup {
+		NetGroup::of(&addr.ip(), self.asn_map.as_ref())
+	}
+
+	/// Picks a share of our outbound peers to drop, the ones in the network
+	/// groups holding most of our outbound connections first, each paired
+	/// with a healthy peer from a less represented group to dial instead.
+	/// Peers in `keep` are never picked. Nothing is dropped here, a peer
+	/// should only be dropped with `drop_rotated` once its replacement is
+	/// connected. Makes it harder for an attacker filling our outbound slots
+	/// over time to eclipse us.
+	pub fn rotate_outbound(
+		&self,
+		cap: Capabilities,
+		keep: &[SocketAddr],
+	) -> Vec<(SocketAddr, SocketAddr)> {
+		let (percent, max_count) = {
+			let config = self.config.read();
+			(config.rotation_percent(), config.peer_max_count())
+		};
+		let outbound = self
+			.outgoing_connected_peers()
+			.iter()
+			.map(|p| p.info.addr)
+			.filter(|addr| !keep.contains(addr))
+			.map(|addr| (addr, self.net_group(&addr)))
+			.collect::<Vec<_>>();
+		let count = (outbound.len() as u64 * percent + 99) / 100;
+		if count == 0 {
+			return vec![];
+		}
+		let candidates = self
+			.find_dial_candidates(State::Healthy, cap, max_count as usize)
+			.into_iter()
+			.filter(|p| !self.is_known(&p.addr))
+			.map(|p| (p.addr, self.net_group(&p.addr)))
+			.collect::<Vec<_>>();
+		plan_rotation(&outbound, &candidates, count as usize)
+	}
+
+	/// Drops an outbound peer rotated out, once its replacement is connected.
+	pub fn drop_rotated(&self, addr: &SocketAddr) {
+		let peer = self.peers.write().remove(addr);
+		if let Some(peer) = peer {
+			peer.stop();
+			debug!("drop_rotated: dropped {:?}", peer.info.addr);
+			self.record_peer_event(&peer, PeerEventKind::Disconnected, "rotated");
+		}
 	}
 
+	/// Disconnects from all peers, saving them as connected until now first.
 	pub fn stop(&self) {
 		let mut peers = self.peers.write();
+		let addrs = peers.keys().cloned().collect::<Vec<_>>();
+		match self.store.flush_connected(&addrs) {
+			Ok(_) => debug!("Saved {} connected peers.", addrs.len()),
+			Err(e) => error!("Failed to save peers before stopping: {:?}", e),
+		}
 		for (_, peer) in peers.drain() {
 			peer.stop();
+			self.record_peer_event(&peer, PeerEventKind::Disconnected, "shutting down");
 		}
 	}
 
 	pub fn enough_peers(&self) -> bool {
-		self.connected_peers().len() >= self.config.peer_min_preferred_count() as usize
+		self.connected_peers().len() >= self.config.read().peer_min_preferred_count() as usize
+	}
+
+	/// Appends an event about a peer to the audit log, if kept
+	pub fn record_event(
+		&self,
+		addr: SocketAddr,
+		kind: PeerEventKind,
+		direction: Option<Direction>,
+		user_agent: &str,
+		detail: &str,
+	) {
+		if !self.config.read().audit_log() {
+			return;
+		}
+		let event = PeerEvent {
+			time: Utc::now().timestamp(),
+			addr,
+			kind,
+			direction,
+			user_agent: user_agent.to_owned(),
+			detail: detail.to_owned(),
+		};
+		if let Err(e) = self.store.save_event(&event) {
+			error!(
+				"Couldn't record {:?} of {} in the audit log: {:?}",
+				kind, addr, e
+			);
+		}
+	}
+
+	fn record_peer_event(&self, peer: &Peer, kind: PeerEventKind, detail: &str) {
+		self.record_event(
+			peer.info.addr,
+			kind,
+			Some(peer.info.direction),
+			&peer.info.user_agent,
+			detail,
+		)
+	}
+
+	/// Events of the audit log between the provided times, in seconds since
+	/// the epoch, oldest first
+	pub fn events(&self, from: i64, to: i64, limit: usize) -> Result<Vec<PeerEvent>, Error> {
+		self.store.events(from, to, limit).map_err(From::from)
 	}
 
-	/// Removes those peers that seem to have expired
+	/// Removes those peers that seem to have expired, and the events of the
+	/// audit log past the days they're kept
 	pub fn remove_expired(&self) {
 		let now = Utc::now();
 
+		let days = self.config.read().audit_log_days() as i64;
+		match self
+			.store
+			.prune_events((now - Duration::days(days)).timestamp())
+		{
+			Ok(0) => {}
+			Ok(n) => debug!("Pruned {} events from the audit log", n),
+			Err(e) => error!("Couldn't prune the audit log: {:?}", e),
+		}
+
 		// Delete defunct peers from storage
 		let _ = self.store.delete_peers(|peer| {
 			let diff = now - Utc.timestamp(peer.last_connected, 0);
@@ -467,6 +923,39 @@ impl Peers {
 	}
 }
 
+/// Sends through the provided function, logging a failure. Whether the peer
+/// was sent to.
+fn send_to<F>(obj_name: &str, p: &Peer, inner: &F) -> bool
+where
+	F: Fn(&Peer) -> Result<bool, Error>,
+{
+	match inner(p) {
+		Ok(sent) => sent,
+		Err(e) => {
+			debug!("Error sending {} to peer: {:?}", obj_name, e);
+			false
+		}
+	}
+}
+
+/// Orders peers by quality, best first, ties staying in the order given.
+/// One time in PROBE_ONE_IN, a random one is moved first instead.
+fn rank_by_quality(peers: Vec<Arc<Peer>>) -> Vec<Arc<Peer>> {
+	let mut ranked = peers
+		.into_iter()
+		.map(|p| (p.quality(), p))
+		.collect::<Vec<_>>();
+	ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal));
+	let mut ranked = ranked.into_iter().map(|(_, p)| p).collect::<Vec<_>>();
+
+	let mut rng = thread_rng();
+	if ranked.len() > 1 && rng.gen_range(0, PROBE_ONE_IN) == 0 {
+		let probed = ranked.remove(rng.gen_range(1, ranked.len()));
+		ranked.insert(0, probed);
+	}
+	ranked
+}
+
 impl ChainAdapter for Peers {
 	fn total_difficulty(&self) -> Difficulty {
 		self.adapter.total_difficulty()
@@ -480,6 +969,10 @@ impl ChainAdapter for Peers {
 		self.adapter.get_transaction(kernel_hash)
 	}
 
+	fn get_block_transactions(&self, b: &core::Block) -> Vec<core::Transaction> {
+		self.adapter.get_block_transactions(b)
+	}
+
 	fn tx_kernel_received(&self, kernel_hash: Hash, addr: SocketAddr) {
 		self.adapter.tx_kernel_received(kernel_hash, addr)
 	}
@@ -580,13 +1073,49 @@ impl ChainAdapter for Peers {
 		self.adapter
 			.txhashset_download_update(start_time, downloaded_size, total_size)
 	}
+
+	fn txhashset_segment_read(
+		&self,
+		h: Hash,
+		segment_type: core::pmmr::SegmentType,
+		id: core::pmmr::SegmentIdentifier,
+	) -> Option<core::pmmr::TxHashSetSegment> {
+		self.adapter.txhashset_segment_read(h, segment_type, id)
+	}
+
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: core::pmmr::TxHashSetSegment,
+		peer_addr: SocketAddr,
+	) -> bool {
+		if !self
+			.adapter
+			.txhashset_segment_received(h, segment, peer_addr)
+		{
+			debug!(
+				"Received a bad txhashset segment from {}, the peer will be banned",
+				&peer_addr
+			);
+			self.ban_peer(&peer_addr, ReasonForBan::BadTxHashSet);
+			false
+		} else {
+			true
+		}
+	}
 }
 
 impl NetAdapter for Peers {
 	/// Find good peers we know with the provided capability and return their
 	/// addresses.
 	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<SocketAddr> {
-		let peers = self.find_peers(State::Healthy, capab, MAX_PEER_ADDRS as usize);
+		// only the addresses we reached the peers at ourselves
+		let peers = self
+			.find_peers(State::Healthy, capab, usize::max_value())
+			.into_iter()
+			.filter(|p| p.verified)
+			.take(MAX_PEER_ADDRS as usize)
+			.collect::<Vec<_>>();
 		trace!("find_peer_addrs: {} healthy peers picked", peers.len());
 		map_vec!(peers, |p| p.addr)
 	}
@@ -608,6 +1137,11 @@ impl NetAdapter for Peers {
 				last_banned: 0,
 				ban_reason: ReasonForBan::None,
 				last_connected: Utc::now().timestamp(),
+				ban_duration: 0,
+				ban_note: "".to_string(),
+				verified: false,
+				version: 0,
+				last_handshake: 0,
 			};
 			if let Err(e) = self.save_peer(&peer) {
 				error!("Could not save received peer address: {:?}", e);
@@ -621,6 +1155,24 @@ impl NetAdapter for Peers {
 		}
 	}
 
+	fn peer_pong(&self, addr: SocketAddr) {
+		if let Some(peer) = self.get_connected_peer(&addr) {
+			peer.info.pong_received();
+		}
+	}
+
+	fn relay_filter_received(&self, addr: SocketAddr, _: &RelayFilter) -> bool {
+		let accepted = self
+			.config
+			.read()
+			.capabilities
+			.contains(Capabilities::RELAY_FILTER);
+		if !accepted {
+			debug!("Ignoring the relay filter of {}, not accepting any", addr);
+		}
+		accepted
+	}
+
 	fn is_banned(&self, addr: SocketAddr) -> bool {
 		if let Some(peer) = self.get_connected_peer(&addr) {
 			peer.is_banned()
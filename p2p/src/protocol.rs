@@ -13,32 +13,68 @@
 // limitations under the License.
 
 use std::cmp;
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::{self, BufWriter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
 use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time;
+use std::time::{self, SystemTime};
 
-use chrono::prelude::Utc;
+use chrono::prelude::{DateTime, Utc};
 use conn::{Message, MessageHandler, Response};
 use core::core::{self, hash::Hash, CompactBlock};
 use core::{global, ser};
 
 use msg::{
-	read_exact, BanReason, GetPeerAddrs, Headers, Locator, PeerAddrs, Ping, Pong, SockAddr,
-	TxHashSetArchive, TxHashSetRequest, Type,
+	read_exact, BanReason, GetPeerAddrs, Headers, Locator, PeerAddrs, Ping, Pong, RelayFilter,
+	SegmentRequest, SegmentResponse, SockAddr, TxHashSetArchive, TxHashSetChunk,
+	TxHashSetChunkRequest, TxHashSetChunkedArchive, TxHashSetManifest, TxHashSetRequest, Type,
+	TXHASHSET_CHUNK_SIZE,
 };
+use peer::set_relay_filter;
 use types::{Error, NetAdapter};
+use util::{Mutex, RwLock};
+
+/// Times a chunk of a txhashset archive is asked for again before giving up
+/// on the peer sending it
+const MAX_CHUNK_RETRIES: u32 = 3;
+
+lazy_static! {
+	/// Manifest of the last txhashset archive served with the location,
+	/// size and modification time of the archive, the same prepared zip
+	/// usually being asked for by every peer syncing
+	static ref SERVED_MANIFEST: Mutex<Option<(PathBuf, u64, SystemTime, TxHashSetManifest)>> =
+		Mutex::new(None);
+}
 
 pub struct Protocol {
 	adapter: Arc<NetAdapter>,
 	addr: SocketAddr,
+	/// Last txhashset archive sent to the peer, to send its chunks again
+	served: Mutex<Option<(Hash, File)>>,
+	/// Txhashset archive being received from the peer, with chunks still
+	/// missing
+	download: Mutex<Option<ChunkedDownload>>,
+	/// Filter the peer set on the transactions relayed to it, shared with
+	/// the peer so it applies even before the peer is added to the others
+	relay_filter: Arc<RwLock<Option<RelayFilter>>>,
 }
 
 impl Protocol {
-	pub fn new(adapter: Arc<NetAdapter>, addr: SocketAddr) -> Protocol {
-		Protocol { adapter, addr }
+	pub fn new(
+		adapter: Arc<NetAdapter>,
+		addr: SocketAddr,
+		relay_filter: Arc<RwLock<Option<RelayFilter>>>,
+	) -> Protocol {
+		Protocol {
+			adapter,
+			addr,
+			served: Mutex::new(None),
+			download: Mutex::new(None),
+			relay_filter,
+		}
 	}
 }
 
@@ -73,6 +109,7 @@ impl MessageHandler for Protocol {
 
 			Type::Pong => {
 				let pong: Pong = msg.body()?;
+				adapter.peer_pong(self.addr);
 				adapter.peer_difficulty(self.addr, pong.total_difficulty, pong.height);
 				Ok(None)
 			}
@@ -83,6 +120,18 @@ impl MessageHandler for Protocol {
 				Ok(None)
 			}
 
+			Type::RelayFilter => {
+				let filter: RelayFilter = msg.body()?;
+				debug!(
+					"handle_payload: relay filter of {} prefixes",
+					filter.prefixes.len()
+				);
+				if adapter.relay_filter_received(self.addr, &filter) {
+					set_relay_filter(&self.relay_filter, filter);
+				}
+				Ok(None)
+			}
+
 			Type::TransactionKernel => {
 				let h: Hash = msg.body()?;
 				debug!(
@@ -156,8 +205,15 @@ impl MessageHandler for Protocol {
 			Type::GetCompactBlock => {
 				let h: Hash = msg.body()?;
 				if let Some(b) = adapter.get_block(h) {
+					// the block transactions the peer likely lacks go first,
+					// sparing it a request for the full block
+					let txs = adapter.get_block_transactions(&b);
 					let cb: CompactBlock = b.into();
-					Ok(Some(msg.respond(Type::CompactBlock, cb)))
+					let mut resp = msg.respond(Type::CompactBlock, cb);
+					for tx in txs {
+						resp.precede_with(Type::Transaction, tx);
+					}
+					Ok(Some(resp))
 				} else {
 					Ok(None)
 				}
@@ -199,8 +255,9 @@ impl MessageHandler for Protocol {
 
 			Type::Headers => {
 				let conn = &mut msg.get_conn();
+				let timeout = msg.timeouts().read;
 
-				let header_size: u64 = headers_header_size(conn, msg.header.msg_len)?;
+				let header_size: u64 = headers_header_size(conn, msg.header.msg_len, timeout)?;
 				let mut total_read: u64 = 2;
 				let mut reserved: Vec<u8> = vec![];
 
@@ -212,6 +269,7 @@ impl MessageHandler for Protocol {
 						&mut total_read,
 						&mut reserved,
 						header_size,
+						timeout,
 					)?;
 					adapter.headers_received(headers.headers, self.addr);
 				}
@@ -324,6 +382,232 @@ impl MessageHandler for Protocol {
 				Ok(None)
 			}
 
+			Type::TxHashSetChunkedRequest => {
+				let sm_req: TxHashSetRequest = msg.body()?;
+				debug!(
+					"handle_payload: chunked txhashset req for {} at {}",
+					sm_req.hash, sm_req.height
+				);
+
+				let txhashset = match self.adapter.txhashset_read(sm_req.hash) {
+					Some(txhashset) => txhashset,
+					None => return Ok(None),
+				};
+				let reader = txhashset.reader;
+				let file_sz = reader.metadata()?.len();
+				let manifest = archive_manifest(&txhashset.path)?;
+				// chunks are read from a file of their own, not to move the
+				// one being sent
+				*self.served.lock() = Some((sm_req.hash, File::open(&txhashset.path)?));
+
+				let mut resp = msg.respond(
+					Type::TxHashSetChunkedArchive,
+					&TxHashSetChunkedArchive {
+						archive: TxHashSetArchive {
+							height: sm_req.height as u64,
+							hash: sm_req.hash,
+							bytes: file_sz,
+						},
+						manifest,
+					},
+				);
+				resp.add_attachment(reader);
+				Ok(Some(resp))
+			}
+
+			Type::TxHashSetChunkedArchive => {
+				let sm_arch: TxHashSetChunkedArchive = msg.body()?;
+				let TxHashSetChunkedArchive { archive, manifest } = sm_arch;
+				debug!(
+					"handle_payload: chunked txhashset archive for {} at {}. size={}, chunks={}",
+					archive.hash,
+					archive.height,
+					archive.bytes,
+					manifest.digests.len(),
+				);
+				if !self.adapter.txhashset_receive_ready() {
+					error!(
+						"handle_payload: txhashset archive received but SyncStatus not on TxHashsetDownload",
+					);
+					return Err(Error::BadMessage);
+				}
+				if !manifest.verify(archive.bytes) {
+					error!(
+						"handle_payload: txhashset archive for {} with a corrupted manifest",
+						archive.hash
+					);
+					return Err(Error::BadMessage);
+				}
+
+				let start_time = Utc::now();
+				self.adapter
+					.txhashset_download_update(start_time, 0, archive.bytes);
+
+				let mut tmp = env::temp_dir();
+				tmp.push("txhashset.zip");
+				let mut download = ChunkedDownload::new(archive, manifest, tmp, start_time)?;
+
+				// each chunk is verified as it comes in, the archive still
+				// being read to its end so the connection stays in sync
+				let mut buf = Vec::with_capacity(TXHASHSET_CHUNK_SIZE as usize);
+				let mut downloaded_size = 0;
+				for index in 0..download.manifest.digests.len() as u64 {
+					let len = download.chunk_len(index);
+					buf.clear();
+					msg.copy_attachment(len as usize, &mut buf)?;
+					if !download.write_chunk(index, &buf)? {
+						warn!(
+							"handle_payload: txhashset chunk {} from {} doesn't match its digest",
+							index, self.addr
+						);
+					}
+					downloaded_size += len;
+					self.adapter.txhashset_download_update(
+						start_time,
+						downloaded_size,
+						download.archive.bytes,
+					);
+				}
+
+				let mut missing = download.missing();
+				if missing.is_empty() {
+					self.txhashset_downloaded(download)?;
+					return Ok(None);
+				}
+				info!(
+					"handle_payload: asking {} again for {} corrupted txhashset chunks",
+					self.addr,
+					missing.len()
+				);
+				let hash = download.archive.hash;
+				*self.download.lock() = Some(download);
+
+				let last = missing.pop().unwrap();
+				let mut resp = msg.respond(
+					Type::GetTxHashSetChunk,
+					TxHashSetChunkRequest { hash, index: last },
+				);
+				for index in missing {
+					resp.precede_with(
+						Type::GetTxHashSetChunk,
+						TxHashSetChunkRequest { hash, index },
+					);
+				}
+				Ok(Some(resp))
+			}
+
+			Type::GetTxHashSetChunk => {
+				let req: TxHashSetChunkRequest = msg.body()?;
+				trace!(
+					"handle_payload: txhashset chunk req {} at {}",
+					req.index,
+					req.hash
+				);
+				let data = match *self.served.lock() {
+					Some((ref hash, ref mut file)) => {
+						if *hash != req.hash {
+							return Ok(None);
+						}
+						read_chunk(file, req.index)?
+					}
+					None => return Ok(None),
+				};
+				if data.is_empty() {
+					return Ok(None);
+				}
+				Ok(Some(msg.respond(
+					Type::TxHashSetChunk,
+					TxHashSetChunk {
+						hash: req.hash,
+						index: req.index,
+						data,
+					},
+				)))
+			}
+
+			Type::TxHashSetChunk => {
+				let chunk: TxHashSetChunk = msg.body()?;
+				trace!(
+					"handle_payload: txhashset chunk {} at {}",
+					chunk.index,
+					chunk.hash
+				);
+				let mut download = self.download.lock();
+				let intact = match *download {
+					Some(ref mut d) => {
+						if d.archive.hash != chunk.hash {
+							return Ok(None);
+						}
+						d.write_chunk(chunk.index, &chunk.data)?
+					}
+					None => return Ok(None),
+				};
+
+				if !intact {
+					let retries = download.as_mut().unwrap().retry(chunk.index);
+					if retries > MAX_CHUNK_RETRIES {
+						error!(
+							"handle_payload: txhashset chunk {} from {} still corrupted, giving up",
+							chunk.index, self.addr
+						);
+						*download = None;
+						return Err(Error::BadMessage);
+					}
+					return Ok(Some(msg.respond(
+						Type::GetTxHashSetChunk,
+						TxHashSetChunkRequest {
+							hash: chunk.hash,
+							index: chunk.index,
+						},
+					)));
+				}
+
+				let complete = download.as_ref().unwrap().missing().is_empty();
+				if complete {
+					let d = download.take().unwrap();
+					drop(download);
+					self.txhashset_downloaded(d)?;
+				}
+				Ok(None)
+			}
+
+			Type::GetTxHashSetSegment => {
+				let req: SegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: {:?} segment req {:?} at {}",
+					req.segment_type,
+					req.identifier,
+					req.hash
+				);
+				let segment =
+					self.adapter
+						.txhashset_segment_read(req.hash, req.segment_type, req.identifier);
+				if let Some(segment) = segment {
+					Ok(Some(msg.respond(
+						Type::TxHashSetSegment,
+						SegmentResponse {
+							hash: req.hash,
+							segment,
+						},
+					)))
+				} else {
+					Ok(None)
+				}
+			}
+
+			Type::TxHashSetSegment => {
+				let resp: SegmentResponse = msg.body()?;
+				trace!(
+					"handle_payload: {:?} segment {:?} at {}",
+					resp.segment.segment_type(),
+					resp.segment.identifier(),
+					resp.hash
+				);
+				self.adapter
+					.txhashset_segment_received(resp.hash, resp.segment, self.addr);
+				Ok(None)
+			}
+
 			_ => {
 				debug!("unknown message type {:?}", msg.header.msg_type);
 				Ok(None)
@@ -332,11 +616,137 @@ impl MessageHandler for Protocol {
 	}
 }
 
+impl Protocol {
+	// Hands a txhashset archive with all its chunks verified over to the
+	// adapter
+	fn txhashset_downloaded(&self, download: ChunkedDownload) -> Result<(), Error> {
+		let (archive, path) = download.finish()?;
+		let res = self
+			.adapter
+			.txhashset_write(archive.hash, File::open(path)?, self.addr);
+		debug!(
+			"handle_payload: txhashset archive for {} at {}, DONE. Data Ok: {}",
+			archive.hash, archive.height, res
+		);
+		Ok(())
+	}
+}
+
+/// A txhashset archive being written to disk as its chunks are received,
+/// along with the ones that didn't match their digest and have been asked
+/// for again.
+struct ChunkedDownload {
+	archive: TxHashSetArchive,
+	manifest: TxHashSetManifest,
+	path: PathBuf,
+	file: File,
+	start_time: DateTime<Utc>,
+	// chunks not received intact yet, with the times they've been asked for
+	// again
+	missing: HashMap<u64, u32>,
+}
+
+impl ChunkedDownload {
+	fn new(
+		archive: TxHashSetArchive,
+		manifest: TxHashSetManifest,
+		path: PathBuf,
+		start_time: DateTime<Utc>,
+	) -> Result<ChunkedDownload, Error> {
+		let file = OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.open(&path)?;
+		Ok(ChunkedDownload {
+			archive,
+			manifest,
+			path,
+			file,
+			start_time,
+			missing: HashMap::new(),
+		})
+	}
+
+	fn chunk_len(&self, index: u64) -> u64 {
+		self.manifest.chunk_len(self.archive.bytes, index)
+	}
+
+	// Writes the chunk in place if it matches its digest, returning whether
+	// it did
+	fn write_chunk(&mut self, index: u64, data: &[u8]) -> Result<bool, Error> {
+		if index >= self.manifest.digests.len() as u64 {
+			return Err(Error::BadMessage);
+		}
+		if data.len() as u64 != self.chunk_len(index) || !self.manifest.matches(index, data) {
+			self.missing.entry(index).or_insert(0);
+			return Ok(false);
+		}
+		self.file
+			.seek(SeekFrom::Start(index * TXHASHSET_CHUNK_SIZE))?;
+		self.file.write_all(data)?;
+		self.missing.remove(&index);
+		Ok(true)
+	}
+
+	// Counts one more request for a chunk, returning how many there were
+	fn retry(&mut self, index: u64) -> u32 {
+		let retries = self.missing.entry(index).or_insert(0);
+		*retries += 1;
+		*retries
+	}
+
+	fn missing(&self) -> Vec<u64> {
+		let mut missing = self.missing.keys().cloned().collect::<Vec<_>>();
+		missing.sort();
+		missing
+	}
+
+	fn finish(self) -> Result<(TxHashSetArchive, PathBuf), Error> {
+		self.file.sync_all()?;
+		debug!(
+			"handle_payload: txhashset archive for {} verified in {}s",
+			self.archive.hash,
+			(Utc::now() - self.start_time).num_seconds()
+		);
+		Ok((self.archive, self.path))
+	}
+}
+
+// Manifest of the archive at that location, computed again only when it's
+// another archive than the last one or it changed since
+fn archive_manifest(path: &Path) -> Result<TxHashSetManifest, Error> {
+	let mut file = File::open(path)?;
+	let meta = file.metadata()?;
+	let (len, modified) = (meta.len(), meta.modified()?);
+	let mut served = SERVED_MANIFEST.lock();
+	if let Some((ref p, l, m, ref manifest)) = *served {
+		if p == path && l == len && m == modified {
+			return Ok(manifest.clone());
+		}
+	}
+	let manifest = TxHashSetManifest::from_reader(&mut file)?;
+	*served = Some((path.to_owned(), len, modified, manifest.clone()));
+	Ok(manifest)
+}
+
+// Reads the chunk of an archive at that index, empty past its end
+fn read_chunk(file: &mut File, index: u64) -> Result<Vec<u8>, Error> {
+	file.seek(SeekFrom::Start(index * TXHASHSET_CHUNK_SIZE))?;
+	let mut data = vec![];
+	file.take(TXHASHSET_CHUNK_SIZE).read_to_end(&mut data)?;
+	Ok(data)
+}
+
 /// Read the Headers Vec size from the underlying connection, and calculate maximum header_size of one Header
-fn headers_header_size(conn: &mut TcpStream, msg_len: u64) -> Result<u64, Error> {
+fn headers_header_size(
+	conn: &mut TcpStream,
+	msg_len: u64,
+	timeout: time::Duration,
+) -> Result<u64, Error> {
 	let mut size = vec![0u8; 2];
 	// read size of Vec<BlockHeader>
-	read_exact(conn, &mut size, time::Duration::from_millis(10), true)?;
+	read_exact(conn, &mut size, timeout, true)?;
 
 	let total_headers = size[0] as u64 * 256 + size[1] as u64;
 	if total_headers == 0 || total_headers > 10_000 {
@@ -366,12 +776,13 @@ fn headers_header_size(conn: &mut TcpStream, msg_len: u64) -> Result<u64, Error>
 
 /// Read the Headers streaming body from the underlying connection
 fn headers_streaming_body(
-	conn: &mut TcpStream,   // (i) underlying connection
-	msg_len: u64,           // (i) length of whole 'Headers'
-	headers_num: u64,       // (i) how many BlockHeader(s) do you want to read
-	total_read: &mut u64,   // (i/o) how many bytes already read on this 'Headers' message
-	reserved: &mut Vec<u8>, // (i/o) reserved part of previous read, which is not a whole header
-	max_header_size: u64,   // (i) maximum possible size of single BlockHeader
+	conn: &mut TcpStream,    // (i) underlying connection
+	msg_len: u64,            // (i) length of whole 'Headers'
+	headers_num: u64,        // (i) how many BlockHeader(s) do you want to read
+	total_read: &mut u64,    // (i/o) how many bytes already read on this 'Headers' message
+	reserved: &mut Vec<u8>,  // (i/o) reserved part of previous read, which is not a whole header
+	max_header_size: u64,    // (i) maximum possible size of single BlockHeader
+	timeout: time::Duration, // (i) how long to wait on the rest of the message
 ) -> Result<Headers, Error> {
 	if headers_num == 0 || msg_len < *total_read || *total_read < 2 {
 		return Err(Error::Connection(io::Error::new(
@@ -407,7 +818,7 @@ fn headers_streaming_body(
 	// 3rd part
 	let mut read_body = vec![0u8; read_size as usize];
 	if read_size > 0 {
-		read_exact(conn, &mut read_body, time::Duration::from_secs(20), true)?;
+		read_exact(conn, &mut read_body, timeout, true)?;
 		*total_read += read_size;
 	}
 	body.append(&mut read_body);
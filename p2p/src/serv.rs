@@ -26,10 +26,12 @@ use core::core;
 use core::core::hash::Hash;
 use core::pow::Difficulty;
 use handshake::Handshake;
+use msg::RelayFilter;
 use peer::Peer;
 use peers::Peers;
-use store::PeerStore;
-use types::{Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, TxHashSetRead};
+use store::{PeerEventKind, PeerStore};
+use types::{Capabilities, ChainAdapter, Direction, Error, NetAdapter, P2PConfig, TxHashSetRead};
+use util;
 
 /// P2P server implementation, handling bootstrapping to find and connect to
 /// peers, receiving connections from other peers and keep track of all of them.
@@ -73,7 +75,7 @@ impl Server {
 		loop {
 			match listener.accept() {
 				Ok((stream, peer_addr)) => {
-					if !self.check_banned(&stream) {
+					if !self.check_banned(&stream) && !self.check_resources_inbound(&stream) {
 						if let Err(e) = self.handle_new_peer(stream) {
 							warn!("Error accepting peer {}: {:?}", peer_addr.to_string(), e);
 						}
@@ -112,9 +114,17 @@ impl Server {
 		}
 
 		if let Some(p) = self.peers.get_connected_peer(addr) {
-			// if we're already connected to the addr, just return the peer
-			trace!("connect_peer: already connected {}", addr);
-			return Ok(p);
+			// if we're already connected to the addr, just return the peer,
+			// a peer dropped but not cleaned up yet gets dialed again
+			if p.is_connected() {
+				trace!("connect_peer: already connected {}", addr);
+				return Ok(p);
+			}
+		}
+
+		if let Err(e) = self.check_resources() {
+			error!("connect_peer: not connecting to {}: {:?}", addr, e);
+			return Err(e);
 		}
 
 		trace!(
@@ -123,20 +133,31 @@ impl Server {
 			self.config.port,
 			addr
 		);
-		match TcpStream::connect_timeout(addr, Duration::from_secs(10)) {
+		let timeouts = self.config.timeouts();
+		match TcpStream::connect_timeout(addr, timeouts.handshake) {
 			Ok(mut stream) => {
-				let addr = SocketAddr::new(self.config.host, self.config.port);
+				let self_addr = SocketAddr::new(self.config.host, self.config.port);
 				let total_diff = self.peers.total_difficulty();
 
 				let mut peer = Peer::connect(
 					&mut stream,
 					self.capabilities,
 					total_diff,
-					addr,
+					self_addr,
 					&self.handshake,
 					self.peers.clone(),
-				)?;
-				peer.start(stream);
+				)
+				.map_err(|e| {
+					self.peers.record_event(
+						*addr,
+						PeerEventKind::HandshakeFailed,
+						Some(Direction::Outbound),
+						"",
+						&format!("{:?}", e),
+					);
+					e
+				})?;
+				peer.start(stream, timeouts);
 				let peer = Arc::new(peer);
 				self.peers.add_connected(peer.clone())?;
 				Ok(peer)
@@ -155,15 +176,44 @@ impl Server {
 		let total_diff = self.peers.total_difficulty();
 
 		// accept the peer and add it to the server map
-		let mut peer = Peer::accept(
+		let mut peer = match Peer::accept(
 			&mut stream,
 			self.capabilities,
 			total_diff,
 			&self.handshake,
 			self.peers.clone(),
-		)?;
-		peer.start(stream);
+		) {
+			Ok(peer) => peer,
+			Err(e) => {
+				if let Ok(addr) = stream.peer_addr() {
+					self.peers.record_event(
+						addr,
+						PeerEventKind::HandshakeFailed,
+						Some(Direction::Inbound),
+						"",
+						&format!("{:?}", e),
+					);
+				}
+				return Err(e);
+			}
+		};
+		peer.start(stream, self.config.timeouts());
+		let addr = peer.info.addr;
 		self.peers.add_connected(Arc::new(peer))?;
+
+		// connect back at the address it claims before passing it on
+		if !self
+			.peers
+			.get_peer(addr)
+			.map(|p| p.verified)
+			.unwrap_or(false)
+		{
+			let peers = self.peers.clone();
+			let timeout = self.config.timeouts().handshake;
+			let _ = thread::Builder::new()
+				.name("verify_addr".to_string())
+				.spawn(move || peers.verify_peer_addr(addr, timeout));
+		}
 		Ok(())
 	}
 
@@ -172,6 +222,13 @@ impl Server {
 		if let Ok(peer_addr) = stream.peer_addr() {
 			if self.peers.is_banned(peer_addr) {
 				debug!("Peer {} banned, refusing connection.", peer_addr);
+				self.peers.record_event(
+					peer_addr,
+					PeerEventKind::Refused,
+					Some(Direction::Inbound),
+					"",
+					"banned",
+				);
 				if let Err(e) = stream.shutdown(Shutdown::Both) {
 					debug!("Error shutting down conn: {:?}", e);
 				}
@@ -181,6 +238,54 @@ impl Server {
 		false
 	}
 
+	/// Checks the open files and threads of the process are still under the
+	/// share of their limits new peers are taken on at, each peer using a
+	/// socket, a clone of it and a thread of its own
+	fn check_resources(&self) -> Result<(), Error> {
+		let percent = self.config.resource_cap_percent();
+		let usage = util::process_usage();
+		let limits = util::process_limits();
+		let cap = |used: Option<u64>, max: Option<u64>| match (used, max) {
+			(Some(used), Some(max)) if used >= max * percent / 100 => Some((used, max)),
+			_ => None,
+		};
+		if let Some((used, max)) = cap(usage.open_fds, limits.max_open_fds) {
+			return Err(Error::ResourceLimit(format!(
+				"{} files open, over {}% of the limit of {}, raise it with ulimit -n \
+				 or lower peer_max_count",
+				used, percent, max
+			)));
+		}
+		if let Some((used, max)) = cap(usage.threads, limits.max_threads) {
+			return Err(Error::ResourceLimit(format!(
+				"{} threads running, over {}% of the limit of {}, raise it with ulimit -u \
+				 or lower peer_max_count",
+				used, percent, max
+			)));
+		}
+		Ok(())
+	}
+
+	fn check_resources_inbound(&self, stream: &TcpStream) -> bool {
+		if let Err(e) = self.check_resources() {
+			if let Ok(peer_addr) = stream.peer_addr() {
+				error!("Not accepting peer {}: {:?}", peer_addr, e);
+				self.peers.record_event(
+					peer_addr,
+					PeerEventKind::Refused,
+					Some(Direction::Inbound),
+					"",
+					"resource limit",
+				);
+			}
+			if let Err(e) = stream.shutdown(Shutdown::Both) {
+				debug!("Error shutting down conn: {:?}", e);
+			}
+			return true;
+		}
+		false
+	}
+
 	pub fn stop(&self) {
 		self.stop.store(true, Ordering::Relaxed);
 		self.peers.stop();
@@ -200,6 +305,9 @@ impl ChainAdapter for DummyAdapter {
 	fn get_transaction(&self, _h: Hash) -> Option<core::Transaction> {
 		None
 	}
+	fn get_block_transactions(&self, _b: &core::Block) -> Vec<core::Transaction> {
+		vec![]
+	}
 	fn tx_kernel_received(&self, _h: Hash, _addr: SocketAddr) {}
 	fn transaction_received(&self, _: core::Transaction, _stem: bool) {}
 	fn compact_block_received(&self, _cb: core::CompactBlock, _addr: SocketAddr) -> bool {
@@ -240,6 +348,24 @@ impl ChainAdapter for DummyAdapter {
 	) -> bool {
 		false
 	}
+
+	fn txhashset_segment_read(
+		&self,
+		_h: Hash,
+		_segment_type: core::pmmr::SegmentType,
+		_id: core::pmmr::SegmentIdentifier,
+	) -> Option<core::pmmr::TxHashSetSegment> {
+		None
+	}
+
+	fn txhashset_segment_received(
+		&self,
+		_h: Hash,
+		_segment: core::pmmr::TxHashSetSegment,
+		_peer_addr: SocketAddr,
+	) -> bool {
+		true
+	}
 }
 
 impl NetAdapter for DummyAdapter {
@@ -248,6 +374,10 @@ impl NetAdapter for DummyAdapter {
 	}
 	fn peer_addrs_received(&self, _: Vec<SocketAddr>) {}
 	fn peer_difficulty(&self, _: SocketAddr, _: Difficulty, _: u64) {}
+	fn peer_pong(&self, _: SocketAddr) {}
+	fn relay_filter_received(&self, _: SocketAddr, _: &RelayFilter) -> bool {
+		false
+	}
 	fn is_banned(&self, _: SocketAddr) -> bool {
 		false
 	}
@@ -17,19 +17,26 @@
 use chrono::Utc;
 use num::FromPrimitive;
 use rand::{thread_rng, Rng};
+use std::cmp;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use lmdb;
 
 use core::ser::{self, Readable, Reader, Writeable, Writer};
-use grin_store::{self, option_to_not_found, to_key, Error};
+use grin_store::{self, option_to_not_found, to_key, Error, Page};
 use msg::SockAddr;
-use types::{Capabilities, ReasonForBan};
+use types::{Capabilities, Direction, ReasonForBan};
+use util::secp::key::SecretKey;
+use util::static_secp_instance;
 
 const STORE_SUBPATH: &'static str = "peers";
 
 const PEER_PREFIX: u8 = 'p' as u8;
+const PEER_EVENT_PREFIX: u8 = 'e' as u8;
+const SIGNING_KEY_PREFIX: u8 = 'k' as u8;
 
 /// Types of messages
 enum_from_primitive! {
@@ -59,6 +66,19 @@ pub struct PeerData {
 	pub ban_reason: ReasonForBan,
 	/// Time when we last connected to this peer.
 	pub last_connected: i64,
+	/// How long the ban lasts in seconds, 0 for the configured ban window
+	pub ban_duration: i64,
+	/// What the ban was about, as given by whoever banned the peer
+	pub ban_note: String,
+	/// Whether we reached the peer at its address ourselves, only then is
+	/// the address passed on to other peers
+	pub verified: bool,
+	/// Protocol version the peer advertised in its last handshake, 0 until
+	/// one
+	pub version: u32,
+	/// Time of the last handshake with the peer, 0 if we never completed
+	/// one, in which case its capabilities and version aren't known
+	pub last_handshake: i64,
 }
 
 impl Writeable for PeerData {
@@ -71,7 +91,12 @@ impl Writeable for PeerData {
 			[write_u8, self.flags as u8],
 			[write_i64, self.last_banned],
 			[write_i32, self.ban_reason as i32],
-			[write_i64, self.last_connected]
+			[write_i64, self.last_connected],
+			[write_i64, self.ban_duration],
+			[write_bytes, &self.ban_note],
+			[write_u8, self.verified as u8],
+			[write_u32, self.version],
+			[write_i64, self.last_handshake]
 		);
 		Ok(())
 	}
@@ -90,7 +115,16 @@ impl Readable for PeerData {
 		} else {
 			lc.unwrap()
 		};
+		// peers saved before bans had a duration don't have one
+		let ban_duration = reader.read_i64().unwrap_or(0);
+		let ban_note = reader.read_vec().unwrap_or(vec![]);
+		// nor were their addresses verified
+		let verified = reader.read_u8().map(|v| v == 1).unwrap_or(false);
+		// nor what their last handshake told
+		let version = reader.read_u32().unwrap_or(0);
+		let last_handshake = reader.read_i64().unwrap_or(0);
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
+		let ban_note = String::from_utf8(ban_note).map_err(|_| ser::Error::CorruptedData)?;
 		let capabilities = Capabilities::from_bits_truncate(capab);
 		let ban_reason = ReasonForBan::from_i32(br).ok_or(ser::Error::CorruptedData)?;
 
@@ -103,22 +137,114 @@ impl Readable for PeerData {
 				last_banned: lb,
 				ban_reason,
 				last_connected,
+				ban_duration,
+				ban_note,
+				verified,
+				version,
+				last_handshake,
 			}),
 			None => Err(ser::Error::CorruptedData),
 		}
 	}
 }
 
+/// Kinds of events in the peer audit log
+enum_from_primitive! {
+	#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+	pub enum PeerEventKind {
+		Connected = 0,
+		Disconnected = 1,
+		HandshakeFailed = 2,
+		Refused = 3,
+		Banned = 4,
+		Misbehaved = 5,
+	}
+}
+
+/// An event of the peer audit log, kept for post-incident analysis of
+/// eclipse or spam attacks.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerEvent {
+	/// When it happened, in seconds since the epoch
+	pub time: i64,
+	/// Network address of the peer
+	pub addr: SocketAddr,
+	/// What happened
+	pub kind: PeerEventKind,
+	/// Direction of the connection with the peer, if there was one
+	pub direction: Option<Direction>,
+	/// The peer user agent, empty if unknown
+	pub user_agent: String,
+	/// Details, such as a ban reason or what the peer did wrong
+	pub detail: String,
+}
+
+impl Writeable for PeerEvent {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_i64(self.time)?;
+		SockAddr(self.addr).write(writer)?;
+		let direction = match self.direction {
+			Some(d) => d as u8 + 1,
+			None => 0,
+		};
+		ser_multiwrite!(
+			writer,
+			[write_u8, self.kind as u8],
+			[write_u8, direction],
+			[write_bytes, &self.user_agent],
+			[write_bytes, &self.detail]
+		);
+		Ok(())
+	}
+}
+
+impl Readable for PeerEvent {
+	fn read(reader: &mut Reader) -> Result<PeerEvent, ser::Error> {
+		let time = reader.read_i64()?;
+		let addr = SockAddr::read(reader)?;
+		let (kind, direction, ua, detail) =
+			ser_multiread!(reader, read_u8, read_u8, read_vec, read_vec);
+		let kind = PeerEventKind::from_u8(kind).ok_or(ser::Error::CorruptedData)?;
+		let direction = match direction {
+			0 => None,
+			d => Some(Direction::from_u8(d - 1).ok_or(ser::Error::CorruptedData)?),
+		};
+		Ok(PeerEvent {
+			time,
+			addr: addr.0,
+			kind,
+			direction,
+			user_agent: String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?,
+			detail: String::from_utf8(detail).map_err(|_| ser::Error::CorruptedData)?,
+		})
+	}
+}
+
 /// Storage facility for peer data.
 pub struct PeerStore {
 	db: grin_store::Store,
+	// tells apart the events of the audit log recorded the same second
+	event_seq: AtomicUsize,
 }
 
 impl PeerStore {
 	/// Instantiates a new peer store under the provided root path.
 	pub fn new(db_env: Arc<lmdb::Environment>) -> Result<PeerStore, Error> {
 		let db = grin_store::Store::open(db_env, STORE_SUBPATH);
-		Ok(PeerStore { db: db })
+
+		// carry on past the events recorded, so the ones of a restart within
+		// the same second don't overwrite the ones before
+		let prefix = to_key(PEER_EVENT_PREFIX, &mut vec![]);
+		let mut event_seq = 0;
+		for (key, _) in db.raw_entries(&prefix)? {
+			let (_, seq) = ser::deserialize::<(i64, u32)>(&mut &key[prefix.len()..])
+				.map_err(|e| Error::SerErr(format!("{}", e)))?;
+			event_seq = cmp::max(event_seq, seq as usize + 1);
+		}
+		Ok(PeerStore {
+			db: db,
+			event_seq: AtomicUsize::new(event_seq),
+		})
 	}
 
 	pub fn save_peer(&self, p: &PeerData) -> Result<(), Error> {
@@ -140,6 +266,25 @@ impl PeerStore {
 		self.db.exists(&peer_key(peer_addr)[..])
 	}
 
+	/// Records the provided peers as connected until now and flushes the
+	/// whole store to disk, done before shutting down. Banned peers are left
+	/// as they are.
+	pub fn flush_connected(&self, addrs: &[SocketAddr]) -> Result<(), Error> {
+		let now = Utc::now().timestamp();
+		let batch = self.db.batch()?;
+		for addr in addrs {
+			if let Some(mut peer) = batch.get_ser::<PeerData>(&peer_key(*addr)[..])? {
+				if peer.flags != State::Banned {
+					peer.flags = State::Healthy;
+					peer.last_connected = now;
+					batch.put_ser(&peer_key(peer.addr)[..], &peer)?;
+				}
+			}
+		}
+		batch.commit()?;
+		self.db.sync()
+	}
+
 	/// TODO - allow below added to avoid github issue reports
 	#[allow(dead_code)]
 	pub fn delete_peer(&self, peer_addr: SocketAddr) -> Result<(), Error> {
@@ -159,6 +304,49 @@ impl PeerStore {
 		peers.iter().take(count).cloned().collect()
 	}
 
+	/// Peers in the given state worth dialing for the provided capabilities,
+	/// leaving out those whose last handshake showed they lack some. Peers
+	/// we never completed a handshake with are kept, as they may have them.
+	pub fn find_dial_candidates(
+		&self,
+		state: State,
+		cap: Capabilities,
+		count: usize,
+	) -> Vec<PeerData> {
+		let mut peers = self
+			.db
+			.iter::<PeerData>(&to_key(PEER_PREFIX, &mut "".to_string().into_bytes()))
+			.unwrap()
+			.filter(|p| p.flags == state)
+			.filter(|p| p.last_handshake == 0 || p.capabilities.contains(cap))
+			.collect::<Vec<_>>();
+		thread_rng().shuffle(&mut peers[..]);
+		peers.iter().take(count).cloned().collect()
+	}
+
+	/// Key the peers exported from this node are signed with, created the
+	/// first time it's needed
+	pub fn signing_key(&self) -> Result<SecretKey, Error> {
+		let key = to_key(SIGNING_KEY_PREFIX, &mut vec![]);
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		if let Some(bytes) = self.db.get(&key)? {
+			if let Ok(sk) = SecretKey::from_slice(&secp, &bytes) {
+				return Ok(sk);
+			}
+		}
+		let sk = SecretKey::new(&secp, &mut thread_rng());
+		let batch = self.db.batch()?;
+		batch.put(&key, sk[..].to_vec())?;
+		batch.commit()?;
+		Ok(sk)
+	}
+
+	/// Writes a consistent copy of the peer database to the provided directory
+	pub fn copy_to(&self, dest: &Path) -> Result<(), Error> {
+		self.db.copy_to(dest)
+	}
+
 	/// List all known peers
 	/// Used for /v1/peers/all api endpoint
 	pub fn all_peers(&self) -> Vec<PeerData> {
@@ -166,6 +354,29 @@ impl PeerStore {
 		self.db.iter::<PeerData>(&key).unwrap().collect::<Vec<_>>()
 	}
 
+	/// Deletes the peer records that can't be read anymore, returning how
+	/// many. Those peers get found again through the seeds and other peers.
+	pub fn prune_unreadable(&self) -> Result<usize, Error> {
+		let prefix = to_key(PEER_PREFIX, &mut vec![]);
+		let batch = self.db.batch()?;
+		let mut pruned = 0;
+		for (key, value) in self.db.raw_entries(&prefix)? {
+			if ser::deserialize::<PeerData>(&mut &value[..]).is_err() {
+				batch.delete(&key)?;
+				pruned += 1;
+			}
+		}
+		batch.commit()?;
+		Ok(pruned)
+	}
+
+	/// A page of the known peers, in key order, starting at the continuation
+	/// token of a previous page if provided
+	pub fn peers_page(&self, from: Option<&[u8]>, limit: usize) -> Result<Page<PeerData>, Error> {
+		let prefix = to_key(PEER_PREFIX, &mut vec![]);
+		self.db.page(&prefix, from, limit)
+	}
+
 	/// Convenience method to load a peer data, update its status and save it
 	/// back. If new state is Banned its last banned time will be updated too.
 	pub fn update_state(&self, peer_addr: SocketAddr, new_state: State) -> Result<(), Error> {
@@ -184,6 +395,92 @@ impl PeerStore {
 		batch.commit()
 	}
 
+	/// Marks the address of a peer as verified, if we still know of it
+	pub fn set_verified(&self, peer_addr: SocketAddr) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		if let Some(mut peer) = batch.get_ser::<PeerData>(&peer_key(peer_addr)[..])? {
+			peer.verified = true;
+			batch.put_ser(&peer_key(peer.addr)[..], &peer)?;
+		}
+		batch.commit()
+	}
+
+	/// Marks a peer as banned for the given reason and duration, with a note
+	/// on what it was about, saving it first if we've never heard of it.
+	pub fn ban_peer(
+		&self,
+		peer_addr: SocketAddr,
+		ban_reason: ReasonForBan,
+		ban_duration: i64,
+		ban_note: &str,
+	) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+
+		let mut peer = batch
+			.get_ser::<PeerData>(&peer_key(peer_addr)[..])?
+			.unwrap_or(PeerData {
+				addr: peer_addr,
+				capabilities: Capabilities::UNKNOWN,
+				user_agent: "".to_string(),
+				flags: State::Banned,
+				last_banned: 0,
+				ban_reason: ReasonForBan::None,
+				last_connected: 0,
+				ban_duration: 0,
+				ban_note: "".to_string(),
+				verified: false,
+				version: 0,
+				last_handshake: 0,
+			});
+		peer.flags = State::Banned;
+		peer.last_banned = Utc::now().timestamp();
+		peer.ban_reason = ban_reason;
+		peer.ban_duration = ban_duration;
+		peer.ban_note = ban_note.to_string();
+
+		batch.put_ser(&peer_key(peer.addr)[..], &peer)?;
+		batch.commit()
+	}
+
+	/// Appends an event to the peer audit log
+	pub fn save_event(&self, event: &PeerEvent) -> Result<(), Error> {
+		let seq = self.event_seq.fetch_add(1, Ordering::Relaxed) as u32;
+		let batch = self.db.batch()?;
+		batch.put_ser(&event_key(&(event.time.max(0), seq))[..], event)?;
+		batch.commit()
+	}
+
+	/// Events of the peer audit log between the provided times, in seconds
+	/// since the epoch, oldest first and at most `limit` of them
+	pub fn events(&self, from: i64, to: i64, limit: usize) -> Result<Vec<PeerEvent>, Error> {
+		let prefix = to_key(PEER_EVENT_PREFIX, &mut vec![]);
+		let start = ser::ser_vec(&from.max(0)).unwrap();
+		let page = self.db.page::<PeerEvent>(&prefix, Some(&start), limit)?;
+		Ok(page
+			.items
+			.into_iter()
+			.take_while(|e| e.time <= to)
+			.collect())
+	}
+
+	/// Deletes the events of the peer audit log older than the provided
+	/// time, returning how many
+	pub fn prune_events(&self, before: i64) -> Result<usize, Error> {
+		let prefix = to_key(PEER_EVENT_PREFIX, &mut vec![]);
+		let end = event_key(&before.max(0));
+		let batch = self.db.batch()?;
+		let mut pruned = 0;
+		for (key, _) in self.db.raw_entries(&prefix)? {
+			if key >= end {
+				break;
+			}
+			batch.delete(&key)?;
+			pruned += 1;
+		}
+		batch.commit()?;
+		Ok(pruned)
+	}
+
 	/// Deletes peers from the storage that satisfy some condition `predicate`
 	pub fn delete_peers<F>(&self, predicate: F) -> Result<(), Error>
 	where
@@ -218,3 +515,8 @@ fn peer_key(peer_addr: SocketAddr) -> Vec<u8> {
 		&mut format!("{}:{}", peer_addr.ip(), peer_addr.port()).into_bytes(),
 	)
 }
+
+// events are keyed by time first, big endian, so the log reads in order
+fn event_key<W: Writeable>(id: &W) -> Vec<u8> {
+	to_key(PEER_EVENT_PREFIX, &mut ser::ser_vec(id).unwrap())
+}
@@ -12,12 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
+use std::collections::HashMap;
 use std::convert::From;
 use std::fs::File;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::Arc;
+use std::time::Duration;
 use util::RwLock;
 
 use chrono::prelude::*;
@@ -26,6 +30,8 @@ use core::core::hash::Hash;
 use core::pow::Difficulty;
 use core::{core, ser};
 use grin_store;
+use msg::{RelayFilter, MAX_RELAY_FILTER_PREFIXES};
+use util;
 
 /// Maximum number of block headers a peer should ever send
 pub const MAX_BLOCK_HEADERS: u32 = 512;
@@ -49,6 +55,38 @@ const PEER_MAX_COUNT: u32 = 25;
 /// min preferred peer count
 const PEER_MIN_PREFERRED_COUNT: u32 = 8;
 
+/// Peers a new block is sent to right away, the others following in batches
+/// of that many
+const BROADCAST_BATCH_SIZE: u32 = 4;
+
+/// Milliseconds between the batches of peers a new block is sent to
+const BROADCAST_STAGGER_MS: u64 = 100;
+
+/// Seconds to dial a peer and complete a handshake with it
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// Seconds to receive the rest of a message once it started coming in
+const READ_TIMEOUT_SECS: u64 = 20;
+
+/// Seconds to send a message
+const WRITE_TIMEOUT_SECS: u64 = 10;
+
+/// Seconds to send or receive each chunk of a message attachment
+const ATTACHMENT_TIMEOUT_SECS: u64 = 10;
+
+/// Days the events of the peer audit log are kept
+const AUDIT_LOG_DAYS: u64 = 30;
+
+/// Share of the limits on open files and threads, in percent, past which no
+/// new peer connection is made or accepted
+const RESOURCE_CAP_PERCENT: u64 = 90;
+
+/// Seconds between two rotations of some of our outbound peers, on average
+const ROTATION_INTERVAL_SECS: u64 = 1800;
+
+/// Share of our outbound peers, in percent, replaced on each rotation
+const ROTATION_PERCENT: u64 = 20;
+
 #[derive(Debug)]
 pub enum Error {
 	Serialization(ser::Error),
@@ -71,6 +109,12 @@ pub enum Error {
 	},
 	Send(String),
 	PeerException,
+	/// Too close to the limits on open files or threads for another peer
+	ResourceLimit(String),
+	/// A peer export that can't be signed, or imported
+	InvalidExport(String),
+	/// An ASN map that can't be parsed
+	InvalidAsnMap(String),
 }
 
 impl From<ser::Error> for Error {
@@ -118,11 +162,64 @@ pub struct P2PConfig {
 	/// The list of preferred peers that we will try to connect to
 	pub peers_preferred: Option<Vec<String>>,
 
+	/// Long-lived peers of each network, by network name, tried when DNS
+	/// seeding fails and we know no peer yet. Replaces the compiled in list
+	/// of the networks it has an entry for.
+	pub fallback_peers: Option<HashMap<String, Vec<String>>>,
+
 	pub ban_window: Option<i64>,
 
 	pub peer_max_count: Option<u32>,
 
 	pub peer_min_preferred_count: Option<u32>,
+
+	/// Peers a new block is sent to right away, the fastest first, the
+	/// others following in batches of that many (0 to send to all at once)
+	pub broadcast_batch_size: Option<u32>,
+
+	/// Milliseconds between the batches of peers a new block is sent to
+	pub broadcast_stagger_ms: Option<u64>,
+
+	/// Seconds to dial a peer and complete a handshake with it
+	pub handshake_timeout_secs: Option<u64>,
+
+	/// Seconds to receive the rest of a message once it started coming in
+	pub read_timeout_secs: Option<u64>,
+
+	/// Seconds to send a message
+	pub write_timeout_secs: Option<u64>,
+
+	/// Seconds to send or receive each chunk of an attachment, such as the
+	/// txhashset archive
+	pub attachment_timeout_secs: Option<u64>,
+
+	/// Hex prefixes of the kernel excesses of the only transactions we want
+	/// relayed to us, by the peers accepting a relay filter
+	pub relay_filter: Option<Vec<String>>,
+
+	/// Whether to keep an audit log of the connections, handshakes, bans and
+	/// misbehavior of peers
+	pub audit_log: Option<bool>,
+
+	/// Days the events of the audit log are kept
+	pub audit_log_days: Option<u64>,
+
+	/// Share of the limits on open files and threads, in percent, past which
+	/// no new peer connection is made or accepted
+	pub resource_cap_percent: Option<u64>,
+
+	/// Seconds between two rotations of some of our outbound peers, on
+	/// average, replacing them with peers from less represented networks (0
+	/// to never rotate)
+	pub rotation_interval_secs: Option<u64>,
+
+	/// Share of our outbound peers, in percent, replaced on each rotation
+	pub rotation_percent: Option<u64>,
+
+	/// File mapping IP prefixes to the autonomous systems announcing them,
+	/// a `prefix/length ASN` entry per line, to group peers by ASN rather
+	/// than by /16
+	pub asn_map_file: Option<String>,
 }
 
 /// Default address for peer-to-peer connections.
@@ -134,15 +231,32 @@ impl Default for P2PConfig {
 			port: 13414,
 			capabilities: Capabilities::HEADER_HIST
 				| Capabilities::TXHASHSET_HIST
-				| Capabilities::PEER_LIST,
+				| Capabilities::PEER_LIST
+				| Capabilities::TXHASHSET_SEGMENTS
+				| Capabilities::RELAY_FILTER
+				| Capabilities::TXHASHSET_CHUNKS,
 			seeding_type: Seeding::default(),
 			seeds: None,
 			peers_allow: None,
 			peers_deny: None,
 			peers_preferred: None,
+			fallback_peers: None,
 			ban_window: None,
 			peer_max_count: None,
 			peer_min_preferred_count: None,
+			broadcast_batch_size: None,
+			broadcast_stagger_ms: None,
+			handshake_timeout_secs: None,
+			read_timeout_secs: None,
+			write_timeout_secs: None,
+			attachment_timeout_secs: None,
+			relay_filter: None,
+			audit_log: None,
+			audit_log_days: None,
+			resource_cap_percent: None,
+			rotation_interval_secs: None,
+			rotation_percent: None,
+			asn_map_file: None,
 		}
 	}
 }
@@ -158,6 +272,13 @@ impl P2PConfig {
 		}
 	}
 
+	/// return the fallback peers configured for the given network, if any
+	pub fn fallback_peers(&self, network: &str) -> Option<Vec<String>> {
+		self.fallback_peers
+			.as_ref()
+			.and_then(|peers| peers.get(network).cloned())
+	}
+
 	/// return peer_max_count
 	pub fn peer_max_count(&self) -> u32 {
 		match self.peer_max_count {
@@ -173,6 +294,95 @@ impl P2PConfig {
 			None => PEER_MIN_PREFERRED_COUNT,
 		}
 	}
+
+	/// return the number of peers a new block is sent to at a time
+	pub fn broadcast_batch_size(&self) -> u32 {
+		self.broadcast_batch_size.unwrap_or(BROADCAST_BATCH_SIZE)
+	}
+
+	/// return the delay between the batches of peers a new block is sent to
+	pub fn broadcast_stagger(&self) -> Duration {
+		Duration::from_millis(self.broadcast_stagger_ms.unwrap_or(BROADCAST_STAGGER_MS))
+	}
+
+	/// return the timeouts of the connections with peers
+	pub fn timeouts(&self) -> Timeouts {
+		let secs = |n: Option<u64>, default| Duration::from_secs(n.unwrap_or(default));
+		Timeouts {
+			handshake: secs(self.handshake_timeout_secs, HANDSHAKE_TIMEOUT_SECS),
+			read: secs(self.read_timeout_secs, READ_TIMEOUT_SECS),
+			write: secs(self.write_timeout_secs, WRITE_TIMEOUT_SECS),
+			attachment: secs(self.attachment_timeout_secs, ATTACHMENT_TIMEOUT_SECS),
+		}
+	}
+
+	/// return whether the peer audit log is kept
+	pub fn audit_log(&self) -> bool {
+		self.audit_log.unwrap_or(false)
+	}
+
+	/// return how many days the events of the peer audit log are kept
+	pub fn audit_log_days(&self) -> u64 {
+		self.audit_log_days.unwrap_or(AUDIT_LOG_DAYS)
+	}
+
+	/// return the share of the limits on open files and threads, in percent,
+	/// past which no new peer connection is made or accepted
+	pub fn resource_cap_percent(&self) -> u64 {
+		self.resource_cap_percent.unwrap_or(RESOURCE_CAP_PERCENT)
+	}
+
+	/// return the average time between two rotations of some of our outbound
+	/// peers, none if they're never rotated
+	pub fn rotation_interval(&self) -> Option<Duration> {
+		let secs = self
+			.rotation_interval_secs
+			.unwrap_or(ROTATION_INTERVAL_SECS);
+		match secs {
+			0 => None,
+			n => Some(Duration::from_secs(n)),
+		}
+	}
+
+	/// return the share of our outbound peers, in percent, replaced on each
+	/// rotation
+	pub fn rotation_percent(&self) -> u64 {
+		cmp::min(100, self.rotation_percent.unwrap_or(ROTATION_PERCENT))
+	}
+
+	/// return the relay filter to set on our peers, if any, skipping the
+	/// prefixes that aren't valid hex
+	pub fn relay_filter(&self) -> Option<RelayFilter> {
+		let prefixes = match self.relay_filter {
+			Some(ref p) if !p.is_empty() => p,
+			_ => return None,
+		};
+		let prefixes = prefixes
+			.iter()
+			.filter_map(|p| match util::from_hex(p.clone()) {
+				Ok(bytes) => Some(bytes),
+				Err(_) => {
+					warn!("Skipping invalid relay filter prefix {}", p);
+					None
+				}
+			})
+			.take(MAX_RELAY_FILTER_PREFIXES as usize)
+			.collect();
+		Some(RelayFilter { prefixes })
+	}
+}
+
+/// How long a connection with a peer waits on it before giving up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+	/// Dialing the peer and the handshake that follows
+	pub handshake: Duration,
+	/// Rest of a message once it started coming in
+	pub read: Duration,
+	/// Sending a message
+	pub write: Duration,
+	/// Each chunk of a message attachment, either way
+	pub attachment: Duration,
 }
 
 /// Type of seeding the server will use to find other peers on the network.
@@ -210,6 +420,15 @@ bitflags! {
 		const PEER_LIST = 0b00000100;
 		/// Can broadcast and request txs by kernel hash.
 		const TX_KERNEL_HASH = 0b00001000;
+		/// Can provide the TxHashSet for some recent-enough height in
+		/// segments, each with its proofs.
+		const TXHASHSET_SEGMENTS = 0b00010000;
+		/// Accepts a relay filter, only relaying the transactions matching
+		/// it.
+		const RELAY_FILTER = 0b00100000;
+		/// Can provide the TxHashSet archive along with the digests of its
+		/// chunks, sending those again when asked.
+		const TXHASHSET_CHUNKS = 0b01000000;
 
 		/// All nodes right now are "full nodes".
 		/// Some nodes internally may maintain longer block histories (archival_mode)
@@ -243,6 +462,7 @@ enum_from_primitive! {
 		BadBlockHeader = 3,
 		BadTxHashSet = 4,
 		ManualBan = 5,
+		Abusive = 6,
 	}
 }
 
@@ -252,6 +472,10 @@ pub struct PeerLiveInfo {
 	pub height: u64,
 	pub last_seen: DateTime<Utc>,
 	pub stuck_detector: DateTime<Utc>,
+	/// When we sent the ping still waiting for its pong
+	pub ping_sent: Option<DateTime<Utc>>,
+	/// Round trip time of the last ping, in milliseconds
+	pub rtt_ms: Option<u64>,
 }
 
 /// General information about a connected peer that's useful to other modules.
@@ -292,6 +516,25 @@ impl PeerInfo {
 		live_info.total_difficulty = total_difficulty;
 		live_info.last_seen = Utc::now()
 	}
+
+	/// Round trip time of the last ping to the peer, in milliseconds
+	pub fn rtt_ms(&self) -> Option<u64> {
+		self.live_info.read().rtt_ms
+	}
+
+	/// Notes a ping was just sent, to time its pong
+	pub fn ping_sent(&self) {
+		self.live_info.write().ping_sent = Some(Utc::now());
+	}
+
+	/// Updates the round trip time on the pong answering our last ping
+	pub fn pong_received(&self) {
+		let mut live_info = self.live_info.write();
+		if let Some(sent) = live_info.ping_sent.take() {
+			let rtt = (Utc::now() - sent).num_milliseconds();
+			live_info.rtt_ms = Some(cmp::max(rtt, 0) as u64);
+		}
+	}
 }
 
 /// Flatten out a PeerInfo and nested PeerLiveInfo (taking a read lock on it)
@@ -330,6 +573,8 @@ pub struct TxHashSetRead {
 	pub kernel_index: u64,
 	/// Binary stream for the txhashset zipped data
 	pub reader: File,
+	/// Location of the zip, read again for the chunks asked for anew
+	pub path: PathBuf,
 }
 
 /// Bridge between the networking layer and the rest of the system. Handles the
@@ -347,6 +592,10 @@ pub trait ChainAdapter: Sync + Send {
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction>;
 
+	/// Transactions of the block, as far as we know them, to send ahead of
+	/// its compact block to a peer likely to be missing them
+	fn get_block_transactions(&self, b: &core::Block) -> Vec<core::Transaction>;
+
 	fn tx_kernel_received(&self, kernel_hash: Hash, addr: SocketAddr);
 
 	/// A block has been received from one of our peers. Returns true if the
@@ -396,6 +645,25 @@ pub trait ChainAdapter: Sync + Send {
 	/// read as a zip file, unzipped and the resulting state files should be
 	/// rewound to the provided indexes.
 	fn txhashset_write(&self, h: Hash, txhashset_data: File, peer_addr: SocketAddr) -> bool;
+
+	/// Provides a segment of the output, rangeproof or kernel MMR as of the
+	/// provided block, along with its proofs, if we have that state.
+	fn txhashset_segment_read(
+		&self,
+		h: Hash,
+		segment_type: core::pmmr::SegmentType,
+		id: core::pmmr::SegmentIdentifier,
+	) -> Option<core::pmmr::TxHashSetSegment>;
+
+	/// A txhashset segment for the provided block has been received. Returns
+	/// false if the segment doesn't match the block header, in which case the
+	/// peer that sent it should be banned.
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: core::pmmr::TxHashSetSegment,
+		peer_addr: SocketAddr,
+	) -> bool;
 }
 
 /// Additional methods required by the protocol that don't need to be
@@ -411,6 +679,13 @@ pub trait NetAdapter: ChainAdapter {
 	/// Heard total_difficulty from a connected peer (via ping/pong).
 	fn peer_difficulty(&self, SocketAddr, Difficulty, u64);
 
+	/// A connected peer answered our last ping.
+	fn peer_pong(&self, SocketAddr);
+
+	/// A connected peer set a filter on the transactions relayed to it,
+	/// applied if we accept one.
+	fn relay_filter_received(&self, SocketAddr, &RelayFilter) -> bool;
+
 	/// Is this peer currently banned?
 	fn is_banned(&self, addr: SocketAddr) -> bool;
 }
@@ -0,0 +1,126 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_core as core;
+extern crate grin_p2p as p2p;
+extern crate grin_store as store;
+
+use std::fs;
+use std::net::TcpListener;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+
+use core::core::hash::Hash;
+use p2p::{Capabilities, Direction, P2PConfig, PeerEvent, PeerEventKind, PeerStore};
+
+fn event(time: i64, kind: PeerEventKind, detail: &str) -> PeerEvent {
+	PeerEvent {
+		time,
+		addr: "10.0.0.1:13414".parse().unwrap(),
+		kind,
+		direction: Some(Direction::Inbound),
+		user_agent: "MW/Grin 0.4.1".to_owned(),
+		detail: detail.to_owned(),
+	}
+}
+
+// Events come back oldest first within the times asked for, and pruning
+// drops the ones before the given time only
+#[test]
+fn audit_log() {
+	let db_root = "target/audit_log";
+	let _ = fs::remove_dir_all(db_root);
+	let db_env = Arc::new(store::new_env(db_root.to_string()));
+	let store = PeerStore::new(db_env.clone()).unwrap();
+
+	store
+		.save_event(&event(300, PeerEventKind::Banned, "BadBlock"))
+		.unwrap();
+	store
+		.save_event(&event(100, PeerEventKind::Connected, ""))
+		.unwrap();
+	store
+		.save_event(&event(200, PeerEventKind::Misbehaved, "abusive"))
+		.unwrap();
+	store
+		.save_event(&event(200, PeerEventKind::Disconnected, "abusive"))
+		.unwrap();
+
+	let events = store.events(0, 1000, 10).unwrap();
+	let times = events.iter().map(|e| e.time).collect::<Vec<_>>();
+	assert_eq!(times, vec![100, 200, 200, 300]);
+	assert_eq!(events[0], event(100, PeerEventKind::Connected, ""));
+	assert_eq!(events[1].kind, PeerEventKind::Misbehaved);
+	assert_eq!(events[2].kind, PeerEventKind::Disconnected);
+
+	let events = store.events(150, 250, 10).unwrap();
+	assert_eq!(events.len(), 2);
+	assert_eq!(store.events(150, 1000, 1).unwrap().len(), 1);
+
+	assert_eq!(store.prune_events(200).unwrap(), 1);
+	let events = store.events(0, 1000, 10).unwrap();
+	assert_eq!(events.len(), 3);
+	assert_eq!(events[0].time, 200);
+
+	// reopened, the events recorded the same second as before are kept
+	drop(store);
+	drop(db_env);
+	let db_env = Arc::new(store::new_env(db_root.to_string()));
+	let store = PeerStore::new(db_env).unwrap();
+	store
+		.save_event(&event(300, PeerEventKind::Connected, ""))
+		.unwrap();
+	let events = store.events(300, 300, 10).unwrap();
+	assert_eq!(events.len(), 2);
+	assert_eq!(events[0].kind, PeerEventKind::Banned);
+	assert_eq!(events[1].kind, PeerEventKind::Connected);
+
+	let _ = fs::remove_dir_all(db_root);
+}
+
+// A failed handshake with a peer we dialed is recorded under its address
+#[test]
+fn audit_log_handshake_failed() {
+	let db_root = "target/audit_log_handshake_failed";
+	let _ = fs::remove_dir_all(db_root);
+	let db_env = Arc::new(store::new_env(db_root.to_string()));
+	let server = p2p::Server::new(
+		db_env,
+		Capabilities::FULL_NODE,
+		P2PConfig {
+			audit_log: Some(true),
+			..P2PConfig::default()
+		},
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(AtomicBool::new(false)),
+	).unwrap();
+
+	// hangs up right away
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	thread::spawn(move || {
+		let _ = listener.accept();
+	});
+	assert!(server.connect(&addr).is_err());
+
+	let events = server.peers.events(0, i64::max_value(), 10).unwrap();
+	assert_eq!(events.len(), 1);
+	assert_eq!(events[0].addr, addr);
+	assert_eq!(events[0].kind, PeerEventKind::HandshakeFailed);
+	assert_eq!(events[0].direction, Some(Direction::Outbound));
+
+	let _ = fs::remove_dir_all(db_root);
+}
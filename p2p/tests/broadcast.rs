@@ -0,0 +1,92 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_p2p as p2p;
+extern crate grin_util as util;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use p2p::{rank_by_rtt, BroadcastScheduler};
+use util::Mutex;
+
+#[test]
+fn rank_by_rtt_order() {
+	let peers = vec![
+		("a", Some(80)),
+		("b", None),
+		("c", Some(10)),
+		("d", Some(80)),
+	];
+	let ranked = rank_by_rtt(peers, |p| p.1)
+		.into_iter()
+		.map(|p| p.0)
+		.collect::<Vec<_>>();
+	assert_eq!(ranked, vec!["c", "a", "d", "b"]);
+	assert!(rank_by_rtt(vec![], |p: &(&str, Option<u64>)| p.1).is_empty());
+}
+
+// Sends of a broadcast, by target, along with when they happened
+type Sends = Arc<Mutex<Vec<(u32, Instant)>>>;
+
+fn schedule(
+	scheduler: &BroadcastScheduler<u32>,
+	targets: Vec<u32>,
+	stagger: Duration,
+	max: u32,
+) -> Sends {
+	let sends = Arc::new(Mutex::new(vec![]));
+	let sent = sends.clone();
+	// odd targets don't take the broadcast
+	scheduler.schedule("test", targets, 2, stagger, max, move |t| {
+		sent.lock().push((*t, Instant::now()));
+		t % 2 == 0
+	});
+	sends
+}
+
+// Batches go out spaced out by the stagger, until enough targets took the
+// broadcast, broadcasts in flight at the same time not waiting on each other
+#[test]
+fn staggered_batches() {
+	let scheduler = BroadcastScheduler::new();
+	let stagger = Duration::from_millis(200);
+	let start = Instant::now();
+	let all = schedule(&scheduler, vec![0, 1, 2, 3, 4, 5], stagger, 10);
+	let capped = schedule(&scheduler, vec![0, 2, 4, 6, 8], stagger, 3);
+	thread::sleep(stagger * 5);
+
+	let all = all.lock().clone();
+	assert_eq!(
+		all.iter().map(|s| s.0).collect::<Vec<_>>(),
+		vec![0, 1, 2, 3, 4, 5]
+	);
+	for (n, batch) in all.chunks(2).enumerate() {
+		let n = n as u32 + 1;
+		for &(_, at) in batch {
+			assert!(at - start >= stagger * n);
+			assert!(at - start < stagger * n + stagger / 2);
+		}
+	}
+
+	// the second batch takes the third one, so the remaining targets are
+	// never sent to
+	let capped = capped.lock().clone();
+	assert_eq!(
+		capped.iter().map(|s| s.0).collect::<Vec<_>>(),
+		vec![0, 2, 4]
+	);
+	assert!(capped[2].1 - start < stagger * 2 + stagger / 2);
+}
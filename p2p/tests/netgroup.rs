@@ -0,0 +1,112 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_p2p as p2p;
+
+use std::net::SocketAddr;
+
+use p2p::{plan_rotation, AsnMap, NetGroup};
+
+fn grouped(addrs: &[&str], asn_map: Option<&AsnMap>) -> Vec<(SocketAddr, NetGroup)> {
+	addrs
+		.iter()
+		.map(|a| {
+			let addr: SocketAddr = a.parse().unwrap();
+			(addr, NetGroup::of(&addr.ip(), asn_map))
+		})
+		.collect()
+}
+
+#[test]
+fn net_groups() {
+	let map = AsnMap::parse(
+		"# prefix ASN\n\
+		 10.0.0.0/8 100\n\
+		 10.1.0.0/16 AS200\n\
+		 \n\
+		 2001:db8::/32 300\n",
+	)
+	.unwrap();
+	assert_eq!(map.len(), 3);
+	assert!(AsnMap::parse("10.0.0.0 100").is_err());
+	assert!(AsnMap::parse("10.0.0.0/33 100").is_err());
+
+	// longest prefix first, by /16 or /32 when not resolved
+	let group = |ip: &str, map| NetGroup::of(&ip.parse().unwrap(), map);
+	assert_eq!(group("10.1.2.3", Some(&map)), NetGroup::Asn(200));
+	assert_eq!(group("10.2.2.3", Some(&map)), NetGroup::Asn(100));
+	assert_eq!(group("2001:db8::1", Some(&map)), NetGroup::Asn(300));
+	assert_eq!(group("10.1.2.3", None), NetGroup::Ipv4([10, 1]));
+	assert_eq!(group("192.168.7.1", Some(&map)), NetGroup::Ipv4([192, 168]));
+	assert_eq!(
+		group("::ffff:192.168.7.1", None),
+		NetGroup::Ipv4([192, 168])
+	);
+	assert_eq!(
+		group("2001:db9::1", Some(&map)),
+		NetGroup::Ipv6([0x20, 0x01, 0x0d, 0xb9])
+	);
+}
+
+// Peers of the most represented group are replaced with candidates of groups
+// we hold strictly fewer peers in, one per group, and no more than can be
+// replaced
+#[test]
+fn rotation() {
+	let outbound = grouped(
+		&[
+			"1.2.0.1:13414",
+			"1.2.0.2:13414",
+			"1.2.0.3:13414",
+			"1.2.0.4:13414",
+			"5.6.0.1:13414",
+		],
+		None,
+	);
+	let candidates = grouped(
+		&[
+			"1.2.0.9:13414",
+			"7.8.0.1:13414",
+			"7.8.0.2:13414",
+			"9.9.0.1:13414",
+		],
+		None,
+	);
+
+	let rotations = plan_rotation(&outbound, &candidates, 2);
+	assert_eq!(rotations.len(), 2);
+	assert!(rotations
+		.iter()
+		.all(|(drop, _)| drop.ip().to_string().starts_with("1.2.")));
+	let mut dialed = rotations
+		.iter()
+		.map(|(_, dial)| dial.ip().to_string())
+		.collect::<Vec<_>>();
+	dialed.sort();
+	assert!(dialed[0].starts_with("7.8."));
+	assert_eq!(dialed[1], "9.9.0.1");
+
+	// a candidate of the dropped peer's own group is no replacement
+	let rotations = plan_rotation(&outbound, &candidates[..2], 4);
+	assert_eq!(rotations.len(), 1);
+	assert!(rotations[0].0.ip().to_string().starts_with("1.2."));
+	assert_eq!(rotations[0].1, "7.8.0.1:13414".parse().unwrap());
+	assert!(plan_rotation(&outbound, &candidates[..1], 4).is_empty());
+	assert!(plan_rotation(&outbound, &[], 2).is_empty());
+
+	// nor one of a group holding as many peers once it's gone
+	let even = grouped(&["1.2.0.1:13414", "1.2.0.2:13414", "5.6.0.1:13414"], None);
+	let rotations = plan_rotation(&even, &grouped(&["5.6.0.2:13414"], None), 2);
+	assert!(rotations.is_empty());
+}
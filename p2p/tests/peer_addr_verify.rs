@@ -0,0 +1,66 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_p2p as p2p;
+extern crate grin_store as store;
+
+use std::fs;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+
+use p2p::types::NetAdapter;
+use p2p::{Capabilities, P2PConfig, PeerStore, Peers};
+
+// An address nothing listens at anymore
+fn closed_addr() -> SocketAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap()
+}
+
+// Addresses received from other peers are only passed on once we reached
+// them ourselves, which an address nothing listens at never is
+#[test]
+fn peer_addr_verify() {
+	let db_root = "target/peer_addr_verify";
+	let _ = fs::remove_dir_all(db_root);
+	let db_env = Arc::new(store::new_env(db_root.to_string()));
+	let store = PeerStore::new(db_env).unwrap();
+	let peers = Peers::new(store, Arc::new(p2p::DummyAdapter {}), P2PConfig::default());
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let reachable = listener.local_addr().unwrap();
+	let unreachable = closed_addr();
+	peers.peer_addrs_received(vec![reachable, unreachable]);
+	assert!(peers.exists_peer(reachable).unwrap());
+	assert!(peers.exists_peer(unreachable).unwrap());
+	assert!(!peers.get_peer(reachable).unwrap().verified);
+	assert!(peers.find_peer_addrs(Capabilities::UNKNOWN).is_empty());
+
+	let timeout = Duration::from_secs(2);
+	peers.verify_peer_addr(reachable, timeout);
+	peers.verify_peer_addr(unreachable, timeout);
+	assert!(peers.get_peer(reachable).unwrap().verified);
+	assert!(!peers.get_peer(unreachable).unwrap().verified);
+	assert_eq!(
+		peers.find_peer_addrs(Capabilities::UNKNOWN),
+		vec![reachable]
+	);
+
+	// an address we never heard of isn't saved by reaching it
+	let other = TcpListener::bind("127.0.0.1:0").unwrap();
+	let unknown = other.local_addr().unwrap();
+	peers.verify_peer_addr(unknown, timeout);
+	assert!(!peers.exists_peer(unknown).unwrap());
+}
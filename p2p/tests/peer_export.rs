@@ -0,0 +1,126 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_p2p as p2p;
+extern crate grin_store as store;
+
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use p2p::{
+	Capabilities, P2PConfig, PeerData, PeerStore, Peers, ReasonForBan, State, MAX_EXPORT_AGE_SECS,
+	MAX_EXPORT_SKEW_SECS,
+};
+
+fn peer(addr: &str, flags: State, verified: bool) -> PeerData {
+	PeerData {
+		addr: addr.parse().unwrap(),
+		capabilities: Capabilities::FULL_NODE,
+		user_agent: "MW/Grin 0.4.1".to_owned(),
+		flags,
+		last_banned: 0,
+		ban_reason: ReasonForBan::None,
+		last_connected: 100,
+		ban_duration: 0,
+		ban_note: "".to_owned(),
+		verified,
+		version: 1,
+		last_handshake: 100,
+	}
+}
+
+fn peers(db_root: &str) -> Peers {
+	let _ = fs::remove_dir_all(db_root);
+	let db_env = Arc::new(store::new_env(db_root.to_string()));
+	let store = PeerStore::new(db_env).unwrap();
+	Peers::new(store, Arc::new(p2p::DummyAdapter {}), P2PConfig::default())
+}
+
+// Only healthy peers we reached ourselves are exported, and only the ones
+// the importing node doesn't know of are imported, from an export that's
+// signed, recent enough and by the expected node
+#[test]
+fn peer_export() {
+	let exporter = peers("target/peer_export/exporter");
+	exporter
+		.save_peer(&peer("10.0.0.1:13414", State::Healthy, true))
+		.unwrap();
+	exporter
+		.save_peer(&peer("10.0.0.2:13414", State::Healthy, false))
+		.unwrap();
+	exporter
+		.save_peer(&peer("10.0.0.3:13414", State::Banned, true))
+		.unwrap();
+	exporter
+		.save_peer(&peer("10.0.0.4:13414", State::Healthy, true))
+		.unwrap();
+
+	let export = exporter.export_peers(10).unwrap();
+	let mut addrs = export.peers.iter().map(|p| p.addr).collect::<Vec<_>>();
+	addrs.sort();
+	let expected: Vec<SocketAddr> = vec![
+		"10.0.0.1:13414".parse().unwrap(),
+		"10.0.0.4:13414".parse().unwrap(),
+	];
+	assert_eq!(addrs, expected);
+	assert_eq!(exporter.export_peers(1).unwrap().peers.len(), 1);
+	// signed with the same key every time
+	assert_eq!(
+		exporter.export_peers(1).unwrap().public_key,
+		export.public_key
+	);
+
+	let importer = peers("target/peer_export/importer");
+	importer
+		.save_peer(&peer("10.0.0.4:13414", State::Banned, true))
+		.unwrap();
+
+	// altered, too old or not by the signer asked for
+	let mut altered = export.clone();
+	altered.peers[0].addr = "10.0.0.5:13414".parse().unwrap();
+	assert!(importer
+		.import_peers(&altered, MAX_EXPORT_AGE_SECS, None)
+		.is_err());
+	let mut old = export.clone();
+	old.exported_at -= 10;
+	assert!(importer.import_peers(&old, 5, None).is_err());
+	assert!(importer
+		.import_peers(&export, MAX_EXPORT_AGE_SECS, Some("02ab"))
+		.is_err());
+	// dated further in the future than clocks drift apart
+	let skewed = export.exported_at - MAX_EXPORT_SKEW_SECS;
+	assert!(export.verify(skewed, MAX_EXPORT_AGE_SECS, None).is_ok());
+	assert!(export
+		.verify(skewed - 1, MAX_EXPORT_AGE_SECS, None)
+		.is_err());
+
+	let signer = export.public_key.clone();
+	let imported = importer
+		.import_peers(&export, MAX_EXPORT_AGE_SECS, Some(&signer))
+		.unwrap();
+	assert_eq!(imported, 1);
+	let imported = importer
+		.get_peer("10.0.0.1:13414".parse().unwrap())
+		.unwrap();
+	assert_eq!(imported.flags, State::Healthy);
+	assert!(!imported.verified);
+	// known peers are left as they are
+	let banned = importer
+		.get_peer("10.0.0.4:13414".parse().unwrap())
+		.unwrap();
+	assert_eq!(banned.flags, State::Banned);
+
+	let _ = fs::remove_dir_all("target/peer_export");
+}
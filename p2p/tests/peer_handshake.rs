@@ -81,7 +81,7 @@ fn peer_handshake() {
 
 	assert!(peer.info.user_agent.ends_with(env!("CARGO_PKG_VERSION")));
 
-	peer.start(socket);
+	peer.start(socket, p2p_config.timeouts());
 	thread::sleep(time::Duration::from_secs(1));
 
 	peer.send_ping(Difficulty::min(), 0).unwrap();
@@ -90,4 +90,9 @@ fn peer_handshake() {
 	let server_peer = server.peers.get_connected_peer(&my_addr).unwrap();
 	assert_eq!(server_peer.info.total_difficulty(), Difficulty::min());
 	assert!(server.peers.peer_count() > 0);
+
+	// what the handshake told is kept for when the peer is gone
+	let peer_data = server.peers.get_peer(my_addr).unwrap();
+	assert_eq!(peer_data.version, server_peer.info.version);
+	assert!(peer_data.last_handshake > 0);
 }
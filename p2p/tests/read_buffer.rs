@@ -0,0 +1,37 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_p2p as p2p;
+
+use p2p::ReadBuffer;
+
+// Messages up to the largest read so far go in the same memory, until it's
+// too large to be kept.
+#[test]
+fn test_read_buffer_reuse() {
+	let mut buf = ReadBuffer::new();
+	let ptr = buf.slice(100_000).as_ptr();
+	assert_eq!(buf.slice(100_000).len(), 100_000);
+	for len in &[10, 8000, 50_000, 100_000] {
+		assert_eq!(buf.slice(*len).len(), *len);
+		assert_eq!(buf.slice(*len).as_ptr(), ptr);
+		buf.release();
+	}
+	assert!(buf.capacity() >= 100_000);
+
+	buf.slice(2_000_000);
+	assert!(buf.capacity() >= 2_000_000);
+	buf.release();
+	assert!(buf.capacity() < 100_000);
+}
@@ -0,0 +1,128 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_core as core;
+extern crate grin_p2p as p2p;
+extern crate grin_store as store;
+extern crate grin_util as util;
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::{thread, time};
+
+use core::core::hash::Hash;
+use core::core::{Transaction, TxKernel};
+use core::pow::Difficulty;
+use p2p::msg::RelayFilter;
+use p2p::{Capabilities, Peer};
+use util::secp::pedersen::Commitment;
+
+fn open_port() -> u16 {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap().port()
+}
+
+// A transaction with a kernel for each of the excess prefixes
+fn tx_with_kernels(prefixes: &[&[u8]]) -> Transaction {
+	let mut tx = Transaction::empty();
+	for p in prefixes {
+		let mut excess = p.to_vec();
+		excess.resize(33, 0x11);
+		let mut kernel = TxKernel::empty();
+		kernel.excess = Commitment::from_vec(excess);
+		tx = tx.with_kernel(kernel);
+	}
+	tx
+}
+
+fn filter(prefixes: &[&[u8]]) -> RelayFilter {
+	RelayFilter {
+		prefixes: prefixes.iter().map(|p| p.to_vec()).collect(),
+	}
+}
+
+#[test]
+fn relay_filter_matches() {
+	let tx = tx_with_kernels(&[&[0x08, 0xab, 0xcd]]);
+	assert!(filter(&[]).matches(&tx));
+	assert!(filter(&[&[0x08]]).matches(&tx));
+	assert!(filter(&[&[0x08, 0xab, 0xcd]]).matches(&tx));
+	assert!(filter(&[&[0x09], &[0x08, 0xab]]).matches(&tx));
+	assert!(!filter(&[&[0x09]]).matches(&tx));
+	assert!(!filter(&[&[0x08, 0xac]]).matches(&tx));
+
+	// any of the kernels can match
+	let tx = tx_with_kernels(&[&[0x09, 0x01], &[0x08, 0xab]]);
+	assert!(filter(&[&[0x08, 0xab]]).matches(&tx));
+	assert!(!filter(&[&[0x09, 0x02]]).matches(&tx));
+}
+
+// A filter sent right after the handshake applies to the transactions relayed
+// to the peer, however early it arrives
+#[test]
+fn relay_filter_on_connect() {
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	assert!(p2p_config.capabilities.contains(Capabilities::RELAY_FILTER));
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let db_env = Arc::new(store::new_env("target/relay_filter".to_string()));
+	let server = Arc::new(
+		p2p::Server::new(
+			db_env,
+			p2p_config.capabilities,
+			p2p_config.clone(),
+			net_adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(AtomicBool::new(false)),
+		).unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen());
+	thread::sleep(time::Duration::from_secs(1));
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let mut socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let my_addr = "127.0.0.1:5001".parse().unwrap();
+	let mut peer = Peer::connect(
+		&mut socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		my_addr,
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
+		net_adapter,
+	).unwrap();
+	assert!(peer.info.capabilities.contains(Capabilities::RELAY_FILTER));
+	peer.start(socket, p2p_config.timeouts());
+	peer.send_relay_filter(&filter(&[&[0x08, 0xab]])).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+
+	let server_peer = server.peers.get_connected_peer(&my_addr).unwrap();
+	assert_eq!(server_peer.relay_filter(), Some(filter(&[&[0x08, 0xab]])));
+	let other = tx_with_kernels(&[&[0x09, 0xab]]);
+	assert_eq!(server_peer.send_transaction(&other).unwrap(), false);
+	let wanted = tx_with_kernels(&[&[0x08, 0xab]]);
+	assert_eq!(server_peer.send_transaction(&wanted).unwrap(), true);
+
+	// an empty filter clears it
+	peer.send_relay_filter(&filter(&[])).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server_peer.relay_filter(), None);
+	assert_eq!(server_peer.send_transaction(&other).unwrap(), true);
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+extern crate grin_core as core;
 extern crate grin_p2p as p2p;
 
 extern crate enum_primitive;
@@ -19,6 +20,9 @@ extern crate num;
 
 use num::FromPrimitive;
 
+use core::core::hash::ZERO_HASH;
+use core::ser;
+
 // Test that Healthy == 0.
 #[test]
 fn test_store_state_enum() {
@@ -36,6 +40,10 @@ fn test_reason_for_ban_enum() {
 		p2p::types::ReasonForBan::from_i32(0),
 		Some(p2p::types::ReasonForBan::None)
 	);
+	assert_eq!(
+		p2p::types::ReasonForBan::from_i32(6),
+		Some(p2p::types::ReasonForBan::Abusive)
+	);
 }
 
 #[test]
@@ -63,16 +71,70 @@ fn test_capabilities() {
 		p2p::types::Capabilities::FULL_NODE
 	);
 	assert_eq!(
-		p2p::types::Capabilities::from_bits_truncate(0b11110111 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b11100111 as u32),
 		p2p::types::Capabilities::FULL_NODE
+			| p2p::types::Capabilities::RELAY_FILTER
+			| p2p::types::Capabilities::TXHASHSET_CHUNKS
 	);
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b00100111 as u32),
-		p2p::types::Capabilities::FULL_NODE
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::RELAY_FILTER
 	);
 
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32),
-		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::TX_KERNEL_HASH
+		p2p::types::Capabilities::FULL_NODE
+			| p2p::types::Capabilities::TX_KERNEL_HASH
+			| p2p::types::Capabilities::RELAY_FILTER
 	);
+
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b00010111 as u32),
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::TXHASHSET_SEGMENTS
+	);
+}
+
+#[test]
+fn test_relay_filter() {
+	let filter = p2p::msg::RelayFilter {
+		prefixes: vec![vec![0x08, 0xab], vec![0x09]],
+	};
+	let vec = ser::ser_vec(&filter).unwrap();
+	let filter2: p2p::msg::RelayFilter = ser::deserialize(&mut &vec[..]).unwrap();
+	assert_eq!(filter, filter2);
+
+	// prefixes can't be longer than a kernel excess
+	let filter = p2p::msg::RelayFilter {
+		prefixes: vec![vec![0; 34]],
+	};
+	let vec = ser::ser_vec(&filter).unwrap();
+	assert!(ser::deserialize::<p2p::msg::RelayFilter>(&mut &vec[..]).is_err());
+}
+
+#[test]
+fn test_txhashset_manifest() {
+	let size = p2p::msg::TXHASHSET_CHUNK_SIZE as usize * 2 + 100;
+	let archive = (0..size).map(|i| i as u8).collect::<Vec<u8>>();
+	let manifest = p2p::msg::TxHashSetManifest::from_reader(&mut &archive[..]).unwrap();
+	assert_eq!(manifest.digests.len(), 3);
+	assert!(manifest.verify(size as u64));
+	assert!(!manifest.verify(size as u64 + p2p::msg::TXHASHSET_CHUNK_SIZE));
+	assert_eq!(manifest.chunk_len(size as u64, 2), 100);
+
+	let vec = ser::ser_vec(&manifest).unwrap();
+	let manifest2: p2p::msg::TxHashSetManifest = ser::deserialize(&mut &vec[..]).unwrap();
+	assert_eq!(manifest, manifest2);
+
+	// a corrupted chunk is told apart from the others
+	let chunk = p2p::msg::TXHASHSET_CHUNK_SIZE as usize;
+	let mut corrupted = archive[chunk..chunk * 2].to_vec();
+	corrupted[10] ^= 1;
+	assert!(manifest.matches(0, &archive[..chunk]));
+	assert!(!manifest.matches(1, &corrupted));
+	assert!(manifest.matches(2, &archive[chunk * 2..]));
+
+	// as is a corrupted manifest
+	let mut manifest3 = manifest.clone();
+	manifest3.digests[0] = ZERO_HASH;
+	assert!(!manifest3.verify(size as u64));
 }
@@ -0,0 +1,236 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate chrono;
+extern crate grin_core as core;
+extern crate grin_p2p as p2p;
+extern crate grin_store as store;
+extern crate grin_util as util;
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::{thread, time};
+
+use chrono::prelude::{DateTime, Utc};
+use core::core::hash::Hash;
+use core::pow::Difficulty;
+use p2p::msg::{RelayFilter, TXHASHSET_CHUNK_SIZE};
+use p2p::types::{ChainAdapter, NetAdapter, TxHashSetRead};
+use p2p::{Capabilities, DummyAdapter, Peer};
+use util::Mutex;
+
+fn open_port() -> u16 {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap().port()
+}
+
+// Serves an archive streamed from one file and read from another, so the
+// streamed one can be corrupted, and keeps the archive it receives
+struct ArchiveAdapter {
+	dummy: DummyAdapter,
+	streamed: PathBuf,
+	archive: PathBuf,
+	received: Mutex<Option<Vec<u8>>>,
+}
+
+impl ArchiveAdapter {
+	fn new(streamed: PathBuf, archive: PathBuf) -> ArchiveAdapter {
+		ArchiveAdapter {
+			dummy: DummyAdapter {},
+			streamed,
+			archive,
+			received: Mutex::new(None),
+		}
+	}
+}
+
+impl ChainAdapter for ArchiveAdapter {
+	fn total_difficulty(&self) -> Difficulty {
+		self.dummy.total_difficulty()
+	}
+	fn total_height(&self) -> u64 {
+		self.dummy.total_height()
+	}
+	fn get_transaction(&self, h: Hash) -> Option<core::core::Transaction> {
+		self.dummy.get_transaction(h)
+	}
+	fn get_block_transactions(&self, b: &core::core::Block) -> Vec<core::core::Transaction> {
+		self.dummy.get_block_transactions(b)
+	}
+	fn tx_kernel_received(&self, h: Hash, addr: SocketAddr) {
+		self.dummy.tx_kernel_received(h, addr)
+	}
+	fn transaction_received(&self, tx: core::core::Transaction, stem: bool) {
+		self.dummy.transaction_received(tx, stem)
+	}
+	fn compact_block_received(&self, cb: core::core::CompactBlock, addr: SocketAddr) -> bool {
+		self.dummy.compact_block_received(cb, addr)
+	}
+	fn header_received(&self, bh: core::core::BlockHeader, addr: SocketAddr) -> bool {
+		self.dummy.header_received(bh, addr)
+	}
+	fn block_received(&self, b: core::core::Block, addr: SocketAddr) -> bool {
+		self.dummy.block_received(b, addr)
+	}
+	fn headers_received(&self, bh: Vec<core::core::BlockHeader>, addr: SocketAddr) -> bool {
+		self.dummy.headers_received(bh, addr)
+	}
+	fn locate_headers(&self, locator: Vec<Hash>) -> Vec<core::core::BlockHeader> {
+		self.dummy.locate_headers(locator)
+	}
+	fn get_block(&self, h: Hash) -> Option<core::core::Block> {
+		self.dummy.get_block(h)
+	}
+	fn txhashset_read(&self, _h: Hash) -> Option<TxHashSetRead> {
+		Some(TxHashSetRead {
+			output_index: 0,
+			kernel_index: 0,
+			reader: File::open(&self.streamed).unwrap(),
+			path: self.archive.clone(),
+		})
+	}
+	fn txhashset_receive_ready(&self) -> bool {
+		true
+	}
+	fn txhashset_write(&self, _h: Hash, mut txhashset_data: File, _addr: SocketAddr) -> bool {
+		let mut data = vec![];
+		txhashset_data.read_to_end(&mut data).unwrap();
+		*self.received.lock() = Some(data);
+		true
+	}
+	fn txhashset_download_update(
+		&self,
+		_start_time: DateTime<Utc>,
+		_downloaded_size: u64,
+		_total_size: u64,
+	) -> bool {
+		true
+	}
+	fn txhashset_segment_read(
+		&self,
+		h: Hash,
+		segment_type: core::core::pmmr::SegmentType,
+		id: core::core::pmmr::SegmentIdentifier,
+	) -> Option<core::core::pmmr::TxHashSetSegment> {
+		self.dummy.txhashset_segment_read(h, segment_type, id)
+	}
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: core::core::pmmr::TxHashSetSegment,
+		addr: SocketAddr,
+	) -> bool {
+		self.dummy.txhashset_segment_received(h, segment, addr)
+	}
+}
+
+impl NetAdapter for ArchiveAdapter {
+	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<SocketAddr> {
+		self.dummy.find_peer_addrs(capab)
+	}
+	fn peer_addrs_received(&self, addrs: Vec<SocketAddr>) {
+		self.dummy.peer_addrs_received(addrs)
+	}
+	fn peer_difficulty(&self, addr: SocketAddr, diff: Difficulty, height: u64) {
+		self.dummy.peer_difficulty(addr, diff, height)
+	}
+	fn peer_pong(&self, addr: SocketAddr) {
+		self.dummy.peer_pong(addr)
+	}
+	fn relay_filter_received(&self, addr: SocketAddr, filter: &RelayFilter) -> bool {
+		self.dummy.relay_filter_received(addr, filter)
+	}
+	fn is_banned(&self, addr: SocketAddr) -> bool {
+		self.dummy.is_banned(addr)
+	}
+}
+
+fn write_file(path: &PathBuf, data: &[u8]) {
+	File::create(path).unwrap().write_all(data).unwrap();
+}
+
+// An archive streamed with a corrupted chunk gets that chunk asked for again
+// and read from the intact archive, the download completing once it's in
+#[test]
+fn txhashset_chunks() {
+	util::init_test_logger();
+	let dir = PathBuf::from("target/txhashset_chunks");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+
+	let archive = (0..TXHASHSET_CHUNK_SIZE * 5 / 2)
+		.map(|i| (i % 251) as u8)
+		.collect::<Vec<_>>();
+	let mut corrupted = archive.clone();
+	corrupted[TXHASHSET_CHUNK_SIZE as usize + 7] ^= 0xff;
+	let (archive_path, streamed_path) = (dir.join("archive.zip"), dir.join("streamed.zip"));
+	write_file(&archive_path, &archive);
+	write_file(&streamed_path, &corrupted);
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		capabilities: Capabilities::TXHASHSET_CHUNKS,
+		..p2p::P2PConfig::default()
+	};
+	let serving = Arc::new(ArchiveAdapter::new(streamed_path, archive_path));
+	let db_env = Arc::new(store::new_env(dir.join("db").to_str().unwrap().to_owned()));
+	let server = Arc::new(
+		p2p::Server::new(
+			db_env,
+			Capabilities::TXHASHSET_CHUNKS,
+			p2p_config.clone(),
+			serving,
+			Hash::from_vec(&vec![]),
+			Arc::new(AtomicBool::new(false)),
+		).unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen());
+	thread::sleep(time::Duration::from_secs(1));
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let mut socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let receiving = Arc::new(ArchiveAdapter::new(
+		dir.join("none.zip"),
+		dir.join("none.zip"),
+	));
+	let mut peer = Peer::connect(
+		&mut socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		"127.0.0.1:5000".parse().unwrap(),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
+		receiving.clone(),
+	).unwrap();
+	assert!(peer
+		.info
+		.capabilities
+		.contains(Capabilities::TXHASHSET_CHUNKS));
+	peer.start(socket, p2p_config.timeouts());
+
+	peer.send_txhashset_request(10, Hash::from_vec(&vec![1]))
+		.unwrap();
+	for _ in 0..50 {
+		if receiving.received.lock().is_some() {
+			break;
+		}
+		thread::sleep(time::Duration::from_millis(100));
+	}
+	assert_eq!(receiving.received.lock().take(), Some(archive));
+}
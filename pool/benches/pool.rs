@@ -0,0 +1,79 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmark of the insertion of a transaction in the pool, with its full
+//! validation against the pool and the chain
+#[macro_use]
+extern crate criterion;
+extern crate blake2_rfc as blake2;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_pool as pool;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+
+extern crate chrono;
+extern crate rand;
+
+#[path = "../tests/common/mod.rs"]
+pub mod common;
+
+use std::sync::Arc;
+use util::RwLock;
+
+use common::*;
+use core::core::verifier_cache::LruVerifierCache;
+use core::core::{Block, BlockHeader};
+use core::pow::Difficulty;
+use criterion::Criterion;
+use keychain::{ExtKeychain, Keychain};
+use wallet::libtx;
+
+fn pool_insertion(c: &mut Criterion) {
+	let keychain: ExtKeychain = Keychain::from_random_seed().unwrap();
+
+	let db_root = ".grin_bench_pool".to_string();
+	clean_output_dir(db_root.clone());
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(&keychain, &key_id, 0, height).unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+		chain.update_db_for_block(&block);
+		block.header
+	};
+	let tx = test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700, 800, 900]);
+
+	// a new pool and verifier cache each time, for the rangeproofs and
+	// kernel signatures to be verified every time
+	c.bench_function("pool insertion", move |b| {
+		b.iter_with_setup(
+			|| {
+				let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+				(test_setup(chain.clone(), verifier_cache), tx.clone())
+			},
+			|(mut pool, tx)| {
+				pool.add_to_pool(test_source(), tx, false, &header).unwrap();
+				pool
+			},
+		)
+	});
+}
+
+criterion_group!(benches, pool_insertion);
+criterion_main!(benches);
@@ -39,5 +39,6 @@ pub mod types;
 
 pub use transaction_pool::TransactionPool;
 pub use types::{
-	BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolEntryState, PoolError, TxSource,
+	BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolEntryState, PoolError, Rejection,
+	TxSource,
 };
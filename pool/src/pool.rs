@@ -66,6 +66,20 @@ impl Pool {
 			.map(|x| x.tx.clone())
 	}
 
+	/// Whether the transaction spends any output of the pool transactions.
+	pub fn spends_from(&self, tx: &Transaction) -> bool {
+		let inputs = tx
+			.inputs()
+			.iter()
+			.map(|x| x.commitment())
+			.collect::<HashSet<_>>();
+		self.entries.iter().any(|x| {
+			x.tx.outputs()
+				.iter()
+				.any(|y| inputs.contains(&y.commitment()))
+		})
+	}
+
 	/// Query the tx pool for an individual tx matching the given kernel hash.
 	pub fn retrieve_tx_by_kernel_hash(&self, hash: Hash) -> Option<Transaction> {
 		for x in &self.entries {
@@ -199,12 +213,39 @@ impl Pool {
 		entry: PoolEntry,
 		extra_txs: Vec<Transaction>,
 		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		self.check_add(&entry.tx, extra_txs, header)?;
+
+		debug!(
+			"add_to_pool [{}]: {} ({}), in/out/kern: {}/{}/{}, pool: {} (at block {})",
+			self.name,
+			entry.tx.hash(),
+			entry.src.debug_name,
+			entry.tx.inputs().len(),
+			entry.tx.outputs().len(),
+			entry.tx.kernels().len(),
+			self.size(),
+			header.hash(),
+		);
+		// If we get here successfully then we can safely add the entry to the pool.
+		self.entries.push(entry);
+
+		Ok(())
+	}
+
+	/// Checks the tx could be added to the pool, aggregated with all the
+	/// txs in it and the extra ones provided, without adding it.
+	pub fn check_add(
+		&self,
+		tx: &Transaction,
+		extra_txs: Vec<Transaction>,
+		header: &BlockHeader,
 	) -> Result<(), PoolError> {
 		// Combine all the txs from the pool with any extra txs provided.
 		let mut txs = self.all_transactions();
 
 		// Quick check to see if we have seen this tx before.
-		if txs.contains(&entry.tx) {
+		if txs.contains(tx) {
 			return Err(PoolError::DuplicateTx);
 		}
 
@@ -212,11 +253,11 @@ impl Pool {
 
 		let agg_tx = if txs.is_empty() {
 			// If we have nothing to aggregate then simply return the tx itself.
-			entry.tx.clone()
+			tx.clone()
 		} else {
 			// Create a single aggregated tx from the existing pool txs and the
-			// new entry
-			txs.push(entry.tx.clone());
+			// new one
+			txs.push(tx.clone());
 
 			let tx = transaction::aggregate(txs)?;
 			tx.validate(self.verifier_cache.clone())?;
@@ -225,21 +266,6 @@ impl Pool {
 
 		// Validate aggregated tx against a known chain state.
 		self.validate_raw_tx(&agg_tx, header)?;
-
-		debug!(
-			"add_to_pool [{}]: {} ({}), in/out/kern: {}/{}/{}, pool: {} (at block {})",
-			self.name,
-			entry.tx.hash(),
-			entry.src.debug_name,
-			entry.tx.inputs().len(),
-			entry.tx.outputs().len(),
-			entry.tx.kernels().len(),
-			self.size(),
-			header.hash(),
-		);
-		// If we get here successfully then we can safely add the entry to the pool.
-		self.entries.push(entry);
-
 		Ok(())
 	}
 
@@ -397,4 +423,9 @@ impl Pool {
 	pub fn size(&self) -> usize {
 		self.entries.len()
 	}
+
+	/// Total weight of the transactions in the pool
+	pub fn weight(&self) -> usize {
+		self.entries.iter().map(|x| x.tx.tx_weight() as usize).sum()
+	}
 }
@@ -17,9 +17,10 @@
 //! resulting tx pool can be added to the current chain state to produce a
 //! valid chain state.
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::ops::Deref;
 use std::sync::Arc;
-use util::RwLock;
+use util::{MemoryBudget, MemoryConsumer, RwLock};
 
 use chrono::prelude::Utc;
 
@@ -28,11 +29,53 @@ use core::core::id::ShortId;
 use core::core::verifier_cache::VerifierCache;
 use core::core::{transaction, Block, BlockHeader, Transaction};
 use pool::Pool;
-use types::{BlockChain, PoolAdapter, PoolConfig, PoolEntry, PoolEntryState, PoolError, TxSource};
+use types::{
+	BlockChain, PoolAdapter, PoolConfig, PoolEntry, PoolEntryState, PoolError, Rejection, TxSource,
+};
 
 // Cache this many txs to handle a potential fork and re-org.
 const REORG_CACHE_SIZE: usize = 100;
 
+// Remember why this many txs got rejected.
+const REJECT_CACHE_SIZE: usize = 1000;
+
+/// Txs recently mined, oldest evicted first when full or short of memory
+pub struct ReorgCache(RwLock<VecDeque<PoolEntry>>);
+
+impl Deref for ReorgCache {
+	type Target = RwLock<VecDeque<PoolEntry>>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl MemoryConsumer for ReorgCache {
+	fn name(&self) -> &'static str {
+		"reorg_cache"
+	}
+
+	fn memory_used(&self) -> usize {
+		self.0.read().iter().map(|x| x.tx.memory_size()).sum()
+	}
+
+	fn shrink_to(&self, target: usize) -> usize {
+		let mut cache = self.0.write();
+		let mut used: usize = cache.iter().map(|x| x.tx.memory_size()).sum();
+		let mut freed = 0;
+		while used > target {
+			match cache.pop_front() {
+				Some(x) => {
+					used -= x.tx.memory_size();
+					freed += x.tx.memory_size();
+				}
+				None => break,
+			}
+		}
+		freed
+	}
+}
+
 /// Transaction pool implementation.
 pub struct TransactionPool {
 	/// Pool Config
@@ -42,12 +85,16 @@ pub struct TransactionPool {
 	/// Our Dandelion "stempool".
 	pub stempool: Pool,
 	/// Cache of previous txs in case of a re-org.
-	pub reorg_cache: Arc<RwLock<VecDeque<PoolEntry>>>,
+	pub reorg_cache: Arc<ReorgCache>,
+	/// Cache of the latest txs rejected or dropped, and why.
+	pub reject_cache: Arc<RwLock<VecDeque<Rejection>>>,
 	/// The blockchain
 	pub blockchain: Arc<BlockChain>,
 	pub verifier_cache: Arc<RwLock<VerifierCache>>,
 	/// The pool adapter
 	pub adapter: Arc<PoolAdapter>,
+	/// Memory budget the reorg cache is accounted in
+	memory_budget: Arc<MemoryBudget>,
 }
 
 impl TransactionPool {
@@ -57,7 +104,10 @@ impl TransactionPool {
 		chain: Arc<BlockChain>,
 		verifier_cache: Arc<RwLock<VerifierCache>>,
 		adapter: Arc<PoolAdapter>,
+		memory_budget: Arc<MemoryBudget>,
 	) -> TransactionPool {
+		let reorg_cache = Arc::new(ReorgCache(RwLock::new(VecDeque::new())));
+		memory_budget.register(Arc::downgrade(&reorg_cache));
 		TransactionPool {
 			config,
 			txpool: Pool::new(chain.clone(), verifier_cache.clone(), "txpool".to_string()),
@@ -66,10 +116,12 @@ impl TransactionPool {
 				verifier_cache.clone(),
 				"stempool".to_string(),
 			),
-			reorg_cache: Arc::new(RwLock::new(VecDeque::new())),
+			reorg_cache,
+			reject_cache: Arc::new(RwLock::new(VecDeque::new())),
 			blockchain: chain,
 			verifier_cache,
 			adapter,
+			memory_budget,
 		}
 	}
 
@@ -88,29 +140,46 @@ impl TransactionPool {
 	}
 
 	fn add_to_reorg_cache(&mut self, entry: PoolEntry) -> Result<(), PoolError> {
-		let mut cache = self.reorg_cache.write();
-		cache.push_back(entry);
-		if cache.len() > REORG_CACHE_SIZE {
-			cache.pop_front();
+		{
+			let mut cache = self.reorg_cache.write();
+			cache.push_back(entry);
+			if cache.len() > REORG_CACHE_SIZE {
+				cache.pop_front();
+			}
+			debug!("added tx to reorg_cache: size now {}", cache.len());
 		}
-		debug!("added tx to reorg_cache: size now {}", cache.len());
+		self.memory_budget.enforce();
 		Ok(())
 	}
 
+	fn add_to_reject_cache(&self, tx_hash: Hash, e: &PoolError) {
+		let mut cache = self.reject_cache.write();
+		cache.push_back(Rejection {
+			tx_hash,
+			reason: format!("{:?}", e),
+			at: Utc::now(),
+		});
+		if cache.len() > REJECT_CACHE_SIZE {
+			cache.pop_front();
+		}
+	}
+
+	/// Why the tx with the given hash was last rejected or dropped, if it
+	/// was recently.
+	pub fn rejection(&self, tx_hash: Hash) -> Option<Rejection> {
+		let cache = self.reject_cache.read();
+		cache.iter().rev().find(|x| x.tx_hash == tx_hash).cloned()
+	}
+
 	fn add_to_txpool(
 		&mut self,
 		mut entry: PoolEntry,
 		header: &BlockHeader,
 	) -> Result<(), PoolError> {
 		// First deaggregate the tx based on current txpool txs.
-		if entry.tx.kernels().len() > 1 {
-			let txs = self.txpool.find_matching_transactions(entry.tx.kernels());
-			if !txs.is_empty() {
-				let tx = transaction::deaggregate(entry.tx, txs)?;
-				tx.validate(self.verifier_cache.clone())?;
-				entry.tx = tx;
-				entry.src.debug_name = "deagg".to_string();
-			}
+		if let Some(tx) = self.deaggregate(&entry.tx)? {
+			entry.tx = tx;
+			entry.src.debug_name = "deagg".to_string();
 		}
 		self.txpool.add_to_pool(entry.clone(), vec![], header)?;
 
@@ -125,8 +194,22 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	// The tx stripped of the txpool txs it aggregates, if any
+	fn deaggregate(&self, tx: &Transaction) -> Result<Option<Transaction>, PoolError> {
+		if tx.kernels().len() > 1 {
+			let txs = self.txpool.find_matching_transactions(tx.kernels());
+			if !txs.is_empty() {
+				let tx = transaction::deaggregate(tx.clone(), txs)?;
+				tx.validate(self.verifier_cache.clone())?;
+				return Ok(Some(tx));
+			}
+		}
+		Ok(None)
+	}
+
 	/// Add the given tx to the pool, directing it to either the stempool or
-	/// txpool based on stem flag provided.
+	/// txpool based on stem flag provided. The reason of a rejection, other
+	/// than having the tx already, is kept in the reject cache.
 	pub fn add_to_pool(
 		&mut self,
 		src: TxSource,
@@ -134,29 +217,29 @@ impl TransactionPool {
 		stem: bool,
 		header: &BlockHeader,
 	) -> Result<(), PoolError> {
-		// Quick check to deal with common case of seeing the *same* tx
-		// broadcast from multiple peers simultaneously.
-		if !stem && self.txpool.contains_tx(tx.hash()) {
-			return Err(PoolError::DuplicateTx);
+		let tx_hash = tx.hash();
+		let res = self.try_add_to_pool(src, tx, stem, header);
+		match res {
+			Ok(_) | Err(PoolError::DuplicateTx) => {}
+			Err(ref e) => self.add_to_reject_cache(tx_hash, e),
 		}
+		res
+	}
 
-		// Do we have the capacity to accept this transaction?
-		self.is_acceptable(&tx, stem)?;
-
-		// Make sure the transaction is valid before anything else.
-		tx.validate(self.verifier_cache.clone())
-			.map_err(PoolError::InvalidTx)?;
-
-		// Check the tx lock_time is valid based on current chain state.
-		self.blockchain.verify_tx_lock_height(&tx)?;
-
-		// Check coinbase maturity before we go any further.
-		self.blockchain.verify_coinbase_maturity(&tx)?;
+	fn try_add_to_pool(
+		&mut self,
+		src: TxSource,
+		tx: Transaction,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		self.verify_tx(&tx, stem)?;
 
 		let entry = PoolEntry {
 			state: PoolEntryState::Fresh,
 			src,
 			tx_at: Utc::now(),
+			height: header.height,
 			tx,
 		};
 
@@ -170,6 +253,92 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	/// Runs the checks adding the tx to the pool would, without adding it nor
+	/// keeping the reason of a rejection.
+	pub fn check_tx(
+		&self,
+		tx: &Transaction,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		self.verify_tx(tx, stem)?;
+		if stem {
+			self.stempool
+				.check_add(tx, self.txpool.all_transactions(), header)
+		} else {
+			match self.deaggregate(tx)? {
+				Some(tx) => self.txpool.check_add(&tx, vec![], header),
+				None => self.txpool.check_add(tx, vec![], header),
+			}
+		}
+	}
+
+	// Checks of the tx on its own, before validating it along with the pool
+	fn verify_tx(&self, tx: &Transaction, stem: bool) -> Result<(), PoolError> {
+		// Quick check to deal with common case of seeing the *same* tx
+		// broadcast from multiple peers simultaneously.
+		if !stem && self.txpool.contains_tx(tx.hash()) {
+			return Err(PoolError::DuplicateTx);
+		}
+
+		// Do we have the capacity to accept this transaction?
+		self.is_acceptable(tx, stem)?;
+
+		// Make sure the transaction is valid before anything else.
+		tx.validate(self.verifier_cache.clone())
+			.map_err(PoolError::InvalidTx)?;
+
+		// Check the tx lock_time is valid based on current chain state.
+		self.blockchain.verify_tx_lock_height(tx)?;
+
+		// Check coinbase maturity before we go any further.
+		self.blockchain.verify_coinbase_maturity(tx)?;
+
+		// Check the spent outputs are buried deep enough for our liking.
+		self.verify_input_confirmations(tx, stem)
+	}
+
+	fn verify_input_confirmations(&self, tx: &Transaction, stem: bool) -> Result<(), PoolError> {
+		let confirmations = self.config.min_input_confirmations;
+		if confirmations == 0 {
+			return Ok(());
+		}
+		// outputs of other pool txs aren't confirmed at all
+		if self.txpool.spends_from(tx) || (stem && self.stempool.spends_from(tx)) {
+			return Err(PoolError::ImmatureInput);
+		}
+		self.blockchain
+			.verify_input_confirmations(tx, confirmations)
+	}
+
+	/// Drops the txs that sat unconfirmed in the pool (or the reorg cache)
+	/// for longer than the configured TTL as of the provided height.
+	fn evict_expired(&mut self, height: u64) {
+		let ttl = self.config.tx_ttl_blocks;
+		if ttl == 0 {
+			return;
+		}
+		let expired = |x: &PoolEntry| height >= x.height.saturating_add(ttl);
+
+		let mut evicted = vec![];
+		for pool in vec![&mut self.txpool, &mut self.stempool] {
+			pool.entries.retain(|x| {
+				if expired(x) {
+					evicted.push(x.tx.hash());
+					false
+				} else {
+					true
+				}
+			});
+		}
+		self.reorg_cache.write().retain(|x| !expired(x));
+
+		for tx_hash in evicted {
+			debug!("evict_expired: {} expired at {}", tx_hash, height);
+			self.add_to_reject_cache(tx_hash, &PoolError::Expired);
+		}
+	}
+
 	fn reconcile_reorg_cache(&mut self, header: &BlockHeader) -> Result<(), PoolError> {
 		let entries = self.reorg_cache.read().iter().cloned().collect::<Vec<_>>();
 		debug!("reconcile_reorg_cache: size: {} ...", entries.len());
@@ -183,6 +352,9 @@ impl TransactionPool {
 	/// Reconcile the transaction pool (both txpool and stempool) against the
 	/// provided block.
 	pub fn reconcile_block(&mut self, block: &Block) -> Result<(), PoolError> {
+		// Drop what waited too long, descendants go with the reconciliation.
+		self.evict_expired(block.header.height);
+
 		// First reconcile the txpool.
 		self.txpool.reconcile_block(block);
 		self.txpool.reconcile(None, &block.header)?;
@@ -206,6 +378,24 @@ impl TransactionPool {
 		self.txpool.retrieve_tx_by_kernel_hash(hash)
 	}
 
+	/// Retrieve the transactions making up the provided block, as far as we
+	/// know them: those with all their kernels in the block, from the txpool
+	/// or, once mined, from the reorg cache.
+	pub fn retrieve_block_transactions(&self, block: &Block) -> Vec<Transaction> {
+		let kernels: HashSet<Hash> = block.kernels().iter().map(|k| k.hash()).collect();
+		let in_block = |tx: &Transaction| tx.kernels().iter().all(|k| kernels.contains(&k.hash()));
+
+		let mut txs = vec![];
+		let mut seen = HashSet::new();
+		let cache = self.reorg_cache.read();
+		for entry in self.txpool.entries.iter().chain(cache.iter()) {
+			if in_block(&entry.tx) && seen.insert(entry.tx.hash()) {
+				txs.push(entry.tx.clone());
+			}
+		}
+		txs
+	}
+
 	/// Retrieve all transactions matching the provided "compact block"
 	/// based on the kernel set.
 	/// Note: we only look in the txpool for this (stempool is under embargo).
@@ -252,6 +442,12 @@ impl TransactionPool {
 		self.txpool.size()
 	}
 
+	/// Get the total weight of the txpool transactions, the stempool left out
+	/// as for the size.
+	pub fn total_weight(&self) -> usize {
+		self.txpool.weight()
+	}
+
 	/// Returns a vector of transactions from the txpool so we can build a
 	/// block from them.
 	pub fn prepare_mineable_transactions(&self) -> Result<Vec<Transaction>, PoolError> {
@@ -31,9 +31,16 @@ const DANDELION_RELAY_SECS: u64 = 600;
 /// Dandelion embargo timer
 const DANDELION_EMBARGO_SECS: u64 = 180;
 
+/// Dandelion embargo timer jitter, the most added at random to the embargo
+const DANDELION_EMBARGO_JITTER_SECS: u64 = 30;
+
 /// Dandelion patience timer
 const DANDELION_PATIENCE_SECS: u64 = 10;
 
+/// Dandelion aggregation period, stem txs being held that long at least to
+/// aggregate those received in the meantime
+const DANDELION_AGGREGATION_SECS: u64 = 30;
+
 /// Dandelion stem probability (stem 90% of the time, fluff 10%).
 const DANDELION_STEM_PROBABILITY: usize = 90;
 
@@ -48,10 +55,18 @@ pub struct DandelionConfig {
 	/// embargo expires.
 	#[serde = "default_dandelion_embargo_secs"]
 	pub embargo_secs: Option<u64>,
+	/// Most added at random to the embargo, each tx waiting between
+	/// embargo_secs and embargo_secs + embargo_jitter_secs.
+	#[serde = "default_dandelion_embargo_jitter_secs"]
+	pub embargo_jitter_secs: Option<u64>,
 	/// Dandelion patience timer, fluff/stem processing runs every n secs.
-	/// Tx aggregation happens on stem txs received within this window.
 	#[serde = "default_dandelion_patience_secs"]
 	pub patience_secs: Option<u64>,
+	/// Seconds the oldest fresh stem tx is held for before all those received
+	/// in the meantime are aggregated into a single one, then stemmed or
+	/// fluffed together. Has to be less than the embargo.
+	#[serde = "default_dandelion_aggregation_secs"]
+	pub aggregation_secs: Option<u64>,
 	/// Dandelion stem probability (stem 90% of the time, fluff 10% etc.)
 	#[serde = "default_dandelion_stem_probability"]
 	pub stem_probability: Option<usize>,
@@ -62,12 +77,62 @@ impl Default for DandelionConfig {
 		DandelionConfig {
 			relay_secs: default_dandelion_relay_secs(),
 			embargo_secs: default_dandelion_embargo_secs(),
+			embargo_jitter_secs: default_dandelion_embargo_jitter_secs(),
 			patience_secs: default_dandelion_patience_secs(),
+			aggregation_secs: default_dandelion_aggregation_secs(),
 			stem_probability: default_dandelion_stem_probability(),
 		}
 	}
 }
 
+impl DandelionConfig {
+	/// Embargo jitter, defaulting for config files predating it
+	pub fn embargo_jitter_secs(&self) -> u64 {
+		self.embargo_jitter_secs
+			.unwrap_or(DANDELION_EMBARGO_JITTER_SECS)
+	}
+
+	/// Aggregation period, defaulting for config files predating it
+	pub fn aggregation_secs(&self) -> u64 {
+		self.aggregation_secs.unwrap_or(DANDELION_AGGREGATION_SECS)
+	}
+
+	/// Takes the values set in `changes`, keeping the others. Nothing
+	/// changes if any of the resulting values is out of range.
+	pub fn apply(&mut self, changes: &DandelionConfig) -> Result<(), PoolError> {
+		let new = DandelionConfig {
+			relay_secs: changes.relay_secs.or(self.relay_secs),
+			embargo_secs: changes.embargo_secs.or(self.embargo_secs),
+			embargo_jitter_secs: changes.embargo_jitter_secs.or(self.embargo_jitter_secs),
+			patience_secs: changes.patience_secs.or(self.patience_secs),
+			aggregation_secs: changes.aggregation_secs.or(self.aggregation_secs),
+			stem_probability: changes.stem_probability.or(self.stem_probability),
+		};
+		new.validate()?;
+		*self = new;
+		Ok(())
+	}
+
+	/// Checks the timers are set, the relay and patience ones to more than
+	/// zero, the aggregation period is less than the embargo and the stem
+	/// probability is a percentage
+	pub fn validate(&self) -> Result<(), PoolError> {
+		let invalid = |msg: &str| Err(PoolError::Other(format!("dandelion config: {}", msg)));
+		match (self.relay_secs, self.embargo_secs, self.patience_secs) {
+			(Some(relay), Some(embargo), Some(patience)) if relay > 0 && patience > 0 => {
+				if self.aggregation_secs() >= embargo {
+					return invalid("aggregation period must be less than the embargo");
+				}
+			}
+			_ => return invalid("relay, embargo and patience timers must be set, not to 0"),
+		}
+		match self.stem_probability {
+			Some(p) if p <= 100 => Ok(()),
+			_ => invalid("stem probability must be a percentage"),
+		}
+	}
+}
+
 fn default_dandelion_relay_secs() -> Option<u64> {
 	Some(DANDELION_RELAY_SECS)
 }
@@ -76,10 +141,18 @@ fn default_dandelion_embargo_secs() -> Option<u64> {
 	Some(DANDELION_EMBARGO_SECS)
 }
 
+fn default_dandelion_embargo_jitter_secs() -> Option<u64> {
+	Some(DANDELION_EMBARGO_JITTER_SECS)
+}
+
 fn default_dandelion_patience_secs() -> Option<u64> {
 	Some(DANDELION_PATIENCE_SECS)
 }
 
+fn default_dandelion_aggregation_secs() -> Option<u64> {
+	Some(DANDELION_AGGREGATION_SECS)
+}
+
 fn default_dandelion_stem_probability() -> Option<usize> {
 	Some(DANDELION_STEM_PROBABILITY)
 }
@@ -100,6 +173,17 @@ pub struct PoolConfig {
 	/// Maximum capacity of the pool in number of transactions
 	#[serde = "default_max_stempool_size"]
 	pub max_stempool_size: usize,
+
+	/// Confirmations the outputs spent by a transaction need for it to be
+	/// accepted, outputs of other pool transactions having none. No
+	/// requirement when 0.
+	#[serde(default)]
+	pub min_input_confirmations: u64,
+
+	/// Blocks a transaction can sit in the pool unconfirmed for before
+	/// getting dropped. Never expires when 0.
+	#[serde(default = "default_tx_ttl_blocks")]
+	pub tx_ttl_blocks: u64,
 }
 
 impl Default for PoolConfig {
@@ -108,6 +192,8 @@ impl Default for PoolConfig {
 			accept_fee_base: default_accept_fee_base(),
 			max_pool_size: default_max_pool_size(),
 			max_stempool_size: default_max_stempool_size(),
+			min_input_confirmations: 0,
+			tx_ttl_blocks: default_tx_ttl_blocks(),
 		}
 	}
 }
@@ -121,6 +207,9 @@ fn default_max_pool_size() -> usize {
 fn default_max_stempool_size() -> usize {
 	50_000
 }
+fn default_tx_ttl_blocks() -> u64 {
+	consensus::DAY_HEIGHT
+}
 
 /// Represents a single entry in the pool.
 /// A single (possibly aggregated) transaction.
@@ -132,6 +221,8 @@ pub struct PoolEntry {
 	pub src: TxSource,
 	/// Timestamp of when this tx was originally added to the pool.
 	pub tx_at: DateTime<Utc>,
+	/// Height of the chain when this tx was originally added to the pool.
+	pub height: u64,
 	/// The transaction itself.
 	pub tx: Transaction,
 }
@@ -183,6 +274,11 @@ pub enum PoolError {
 	ImmatureTransaction,
 	/// Attempt to spend a coinbase output before it has sufficiently matured.
 	ImmatureCoinbase,
+	/// Attempt to spend an output with fewer confirmations than the pool
+	/// asks for.
+	ImmatureInput,
+	/// Transaction sat in the pool unconfirmed for longer than its TTL.
+	Expired,
 	/// Problem propagating a stem tx to the next Dandelion relay node.
 	DandelionError,
 	/// Transaction pool is over capacity, can't accept more transactions
@@ -221,6 +317,17 @@ impl From<committed::Error> for PoolError {
 	}
 }
 
+/// A transaction the pool turned down or dropped, kept around to tell why.
+#[derive(Clone, Debug)]
+pub struct Rejection {
+	/// Hash of the transaction.
+	pub tx_hash: Hash,
+	/// Why the transaction was rejected.
+	pub reason: String,
+	/// Timestamp of the rejection.
+	pub at: DateTime<Utc>,
+}
+
 /// Interface that the pool requires from a blockchain implementation.
 pub trait BlockChain: Sync + Send {
 	/// Verify any coinbase outputs being spent
@@ -231,6 +338,14 @@ pub trait BlockChain: Sync + Send {
 	/// have matured sufficiently.
 	fn verify_tx_lock_height(&self, tx: &transaction::Transaction) -> Result<(), PoolError>;
 
+	/// Verify the outputs being spent got at least the provided number of
+	/// confirmations.
+	fn verify_input_confirmations(
+		&self,
+		tx: &transaction::Transaction,
+		confirmations: u64,
+	) -> Result<(), PoolError>;
+
 	fn validate_tx(&self, tx: &Transaction) -> Result<(), PoolError>;
 
 	fn chain_head(&self) -> Result<BlockHeader, PoolError>;
@@ -120,6 +120,10 @@ fn test_transaction_pool_block_building() {
 
 	let block = add_block(header, txs, &mut chain);
 
+	// The individual txs making up the block can be found, to send ahead of
+	// its compact block
+	assert_eq!(pool.read().retrieve_block_transactions(&block).len(), 5);
+
 	// Now reconcile the transaction pool with the new block
 	// and check the resulting contents of the pool are what we expect.
 	{
@@ -128,4 +132,9 @@ fn test_transaction_pool_block_building() {
 
 		assert_eq!(write_pool.total_size(), 0);
 	}
+
+	// still found once mined, from the reorg cache
+	let block_txs = pool.read().retrieve_block_transactions(&block);
+	assert_eq!(block_txs.len(), 5);
+	assert!(block_txs.contains(&child_tx_1));
 }
@@ -70,6 +70,10 @@ impl BlockChain for CoinbaseMaturityErrorChainAdapter {
 	fn verify_tx_lock_height(&self, _tx: &Transaction) -> Result<(), PoolError> {
 		Ok(())
 	}
+
+	fn verify_input_confirmations(&self, _tx: &Transaction, _: u64) -> Result<(), PoolError> {
+		Ok(())
+	}
 }
 
 /// Test we correctly verify coinbase maturity when adding txs to the pool.
@@ -29,7 +29,7 @@ extern crate rand;
 use std::collections::HashSet;
 use std::fs;
 use std::sync::Arc;
-use util::RwLock;
+use util::{MemoryBudget, RwLock};
 
 use core::core::hash::{Hash, Hashed};
 use core::core::verifier_cache::VerifierCache;
@@ -158,6 +158,11 @@ impl BlockChain for ChainAdapter {
 	fn verify_tx_lock_height(&self, _tx: &Transaction) -> Result<(), PoolError> {
 		Ok(())
 	}
+
+	// Mocking this out for these tests, outputs in the pool are still checked.
+	fn verify_input_confirmations(&self, _tx: &Transaction, _: u64) -> Result<(), PoolError> {
+		Ok(())
+	}
 }
 
 pub fn test_setup(
@@ -169,10 +174,13 @@ pub fn test_setup(
 			accept_fee_base: 0,
 			max_pool_size: 50,
 			max_stempool_size: 50,
+			min_input_confirmations: 0,
+			tx_ttl_blocks: 0,
 		},
 		chain.clone(),
 		verifier_cache.clone(),
 		Arc::new(NoopAdapter {}),
+		Arc::new(MemoryBudget::unlimited()),
 	)
 }
 
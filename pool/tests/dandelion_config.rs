@@ -0,0 +1,74 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_pool as pool;
+
+use pool::DandelionConfig;
+
+fn no_changes() -> DandelionConfig {
+	DandelionConfig {
+		relay_secs: None,
+		embargo_secs: None,
+		embargo_jitter_secs: None,
+		patience_secs: None,
+		aggregation_secs: None,
+		stem_probability: None,
+	}
+}
+
+/// Test changes only touch the values given and out of range ones
+/// leave the config as it was.
+#[test]
+fn test_dandelion_config_apply() {
+	let mut config = DandelionConfig::default();
+	let mut changes = no_changes();
+	changes.embargo_secs = Some(60);
+	changes.stem_probability = Some(50);
+	config.apply(&changes).unwrap();
+	assert_eq!(config.embargo_secs, Some(60));
+	assert_eq!(config.stem_probability, Some(50));
+	assert_eq!(config.relay_secs, DandelionConfig::default().relay_secs);
+
+	let mut changes = no_changes();
+	changes.stem_probability = Some(101);
+	assert!(config.apply(&changes).is_err());
+	let mut changes = no_changes();
+	changes.patience_secs = Some(0);
+	assert!(config.apply(&changes).is_err());
+	assert_eq!(config.stem_probability, Some(50));
+	assert_eq!(
+		config.patience_secs,
+		DandelionConfig::default().patience_secs
+	);
+
+	// stem txs have to be aggregated before their embargo runs out
+	let mut changes = no_changes();
+	changes.aggregation_secs = Some(60);
+	assert!(config.apply(&changes).is_err());
+	changes.aggregation_secs = Some(20);
+	config.apply(&changes).unwrap();
+	assert_eq!(config.aggregation_secs(), 20);
+
+	// config files predating the jitter get the default one
+	config.embargo_jitter_secs = None;
+	assert_eq!(
+		Some(config.embargo_jitter_secs()),
+		DandelionConfig::default().embargo_jitter_secs
+	);
+	config.aggregation_secs = None;
+	assert_eq!(
+		Some(config.aggregation_secs()),
+		DandelionConfig::default().aggregation_secs
+	);
+}
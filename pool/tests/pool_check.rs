@@ -0,0 +1,110 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate blake2_rfc as blake2;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_pool as pool;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+
+extern crate chrono;
+extern crate rand;
+
+pub mod common;
+
+use std::sync::Arc;
+use util::RwLock;
+
+use core::core::{Block, BlockHeader, Transaction};
+
+use common::*;
+use core::core::hash::Hashed;
+use core::core::verifier_cache::LruVerifierCache;
+use core::pow::Difficulty;
+use keychain::{ExtKeychain, Keychain};
+use pool::PoolError;
+use wallet::libtx;
+
+/// Mines a block on top of the provided header, with the given txs.
+fn add_block(
+	chain: &ChainAdapter,
+	keychain: &ExtKeychain,
+	prev: &BlockHeader,
+	txs: Vec<Transaction>,
+) -> Block {
+	let height = prev.height + 1;
+	let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+	let fees = txs.iter().map(|tx| tx.fee()).sum();
+	let reward = libtx::reward::output(keychain, &key_id, fees, height).unwrap();
+	let mut block = Block::new(prev, txs, Difficulty::min(), reward).unwrap();
+
+	// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+	block.header.prev_root = prev.hash();
+
+	chain.update_db_for_block(&block);
+	block
+}
+
+/// Test checking txs tells whether the pool would take them, leaving the
+/// pool and the reject cache as they were.
+#[test]
+fn test_transaction_pool_check() {
+	let keychain: ExtKeychain = Keychain::from_random_seed().unwrap();
+
+	let db_root = ".grin_pool_check".to_string();
+	clean_output_dir(db_root.clone());
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	// Initialize a new pool with our chain adapter.
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+
+	let header = add_block(&chain, &keychain, &BlockHeader::default(), vec![]).header;
+
+	// Now create tx to spend that first coinbase (now matured).
+	// Provides us with some useful outputs to test with.
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![10, 20, 30, 40]);
+	let header = add_block(&chain, &keychain, &header, vec![initial_tx]).header;
+
+	let tx = test_transaction(&keychain, vec![10], vec![8]);
+	let double_spend_tx = test_transaction(&keychain, vec![10], vec![7]);
+	{
+		let read_pool = pool.read();
+		read_pool.check_tx(&tx, false, &header).unwrap();
+		read_pool.check_tx(&tx, true, &header).unwrap();
+		assert_eq!(read_pool.total_size(), 0);
+		assert_eq!(read_pool.stempool.size(), 0);
+	}
+
+	pool.write()
+		.add_to_pool(test_source(), tx.clone(), false, &header)
+		.unwrap();
+	{
+		let read_pool = pool.read();
+		match read_pool.check_tx(&tx, false, &header) {
+			Err(PoolError::DuplicateTx) => {}
+			_ => panic!("Expected a duplicate tx error here."),
+		}
+
+		// spending the same output as a tx in the pool
+		assert!(read_pool
+			.check_tx(&double_spend_tx, false, &header)
+			.is_err());
+		assert!(read_pool.rejection(double_spend_tx.hash()).is_none());
+		assert_eq!(read_pool.total_size(), 1);
+	}
+}
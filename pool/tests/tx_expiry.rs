@@ -0,0 +1,125 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate blake2_rfc as blake2;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_pool as pool;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+
+extern crate chrono;
+extern crate rand;
+
+pub mod common;
+
+use std::sync::Arc;
+use util::RwLock;
+
+use core::core::{Block, BlockHeader, Transaction};
+
+use common::*;
+use core::core::hash::Hashed;
+use core::core::verifier_cache::LruVerifierCache;
+use core::pow::Difficulty;
+use keychain::{ExtKeychain, Keychain};
+use pool::PoolError;
+use wallet::libtx;
+
+/// Mines a block on top of the provided header, with the given txs.
+fn add_block(
+	chain: &ChainAdapter,
+	keychain: &ExtKeychain,
+	prev: &BlockHeader,
+	txs: Vec<Transaction>,
+) -> Block {
+	let height = prev.height + 1;
+	let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+	let fees = txs.iter().map(|tx| tx.fee()).sum();
+	let reward = libtx::reward::output(keychain, &key_id, fees, height).unwrap();
+	let mut block = Block::new(prev, txs, Difficulty::min(), reward).unwrap();
+
+	// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+	block.header.prev_root = prev.hash();
+
+	chain.update_db_for_block(&block);
+	block
+}
+
+/// Test txs spending outputs without enough confirmations are turned down
+/// and txs waiting past their TTL get dropped, both telling why.
+#[test]
+fn test_transaction_pool_expiry() {
+	let keychain: ExtKeychain = Keychain::from_random_seed().unwrap();
+
+	let db_root = ".grin_tx_expiry".to_string();
+	clean_output_dir(db_root.clone());
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	// Initialize a new pool with our chain adapter.
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+	{
+		let mut write_pool = pool.write();
+		write_pool.config.min_input_confirmations = 1;
+		write_pool.config.tx_ttl_blocks = 2;
+	}
+
+	let header = add_block(&chain, &keychain, &BlockHeader::default(), vec![]).header;
+
+	// Now create tx to spend that first coinbase (now matured).
+	// Provides us with some useful outputs to test with.
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![10, 20, 30, 40]);
+	let header = add_block(&chain, &keychain, &header, vec![initial_tx]).header;
+
+	let tx = test_transaction(&keychain, vec![10], vec![8]);
+	let child_tx = test_transaction(&keychain, vec![8], vec![5]);
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), tx.clone(), false, &header)
+			.unwrap();
+
+		// the output of a pool tx has no confirmation yet
+		match write_pool.add_to_pool(test_source(), child_tx.clone(), false, &header) {
+			Err(PoolError::ImmatureInput) => {}
+			_ => panic!("Expected an immature input error here."),
+		}
+		let rejection = write_pool.rejection(child_tx.hash()).unwrap();
+		assert_eq!(rejection.reason, "ImmatureInput");
+		assert!(write_pool.rejection(tx.hash()).is_none());
+	}
+
+	// Still waiting one block after getting in the pool.
+	let block = add_block(&chain, &keychain, &header, vec![]);
+	{
+		let mut write_pool = pool.write();
+		write_pool.reconcile_block(&block).unwrap();
+		assert_eq!(write_pool.total_size(), 1);
+	}
+
+	// Dropped after two.
+	let block = add_block(&chain, &keychain, &block.header, vec![]);
+	{
+		let mut write_pool = pool.write();
+		write_pool.reconcile_block(&block).unwrap();
+		assert_eq!(write_pool.total_size(), 0);
+		assert!(write_pool.reorg_cache.read().is_empty());
+
+		let rejection = write_pool.rejection(tx.hash()).unwrap();
+		assert_eq!(rejection.reason, "Expired");
+	}
+}
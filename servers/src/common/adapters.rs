@@ -22,10 +22,13 @@ use std::thread;
 use std::time::Instant;
 use util::RwLock;
 
+use api;
 use chain::{self, ChainAdapter, Options};
 use chrono::prelude::{DateTime, Utc};
 use common::types::{self, ChainValidationMode, ServerConfig, SyncState, SyncStatus};
+use common::webhooks::WebhookDispatcher;
 use core::core::hash::{Hash, Hashed};
+use core::core::pmmr::{SegmentIdentifier, SegmentType, TxHashSetSegment};
 use core::core::transaction::Transaction;
 use core::core::verifier_cache::VerifierCache;
 use core::core::{BlockHeader, BlockSums, CompactBlock};
@@ -35,7 +38,7 @@ use p2p;
 use pool;
 use rand::prelude::*;
 use store;
-use util::OneTime;
+use util::{log_fields, OneTime};
 
 /// Implementation of the NetAdapter for the . Gets notified when new
 /// blocks and transactions are received and forwards to the chain and pool
@@ -46,6 +49,7 @@ pub struct NetToChainAdapter {
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	verifier_cache: Arc<RwLock<VerifierCache>>,
 	peers: OneTime<Weak<p2p::Peers>>,
+	events: Arc<api::EventHub>,
 	config: ServerConfig,
 }
 
@@ -62,6 +66,10 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		self.tx_pool.read().retrieve_tx_by_kernel_hash(kernel_hash)
 	}
 
+	fn get_block_transactions(&self, b: &core::Block) -> Vec<core::Transaction> {
+		self.tx_pool.read().retrieve_block_transactions(b)
+	}
+
 	fn tx_kernel_received(&self, kernel_hash: Hash, addr: SocketAddr) {
 		// nothing much we can do with a new transaction while syncing
 		if self.sync_state.is_syncing() {
@@ -108,6 +116,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 	}
 
 	fn block_received(&self, b: core::Block, addr: SocketAddr) -> bool {
+		let _fields = log_fields(vec![
+			("peer", addr.to_string()),
+			("block_hash", b.hash().to_hex()),
+			("height", b.header.height.to_string()),
+		]);
 		debug!(
 			"Received block {} at {} from {}, inputs: {}, outputs: {}, kernels: {}, going to process.",
 			b.hash(),
@@ -122,6 +135,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 
 	fn compact_block_received(&self, cb: core::CompactBlock, addr: SocketAddr) -> bool {
 		let bhash = cb.hash();
+		let _fields = log_fields(vec![
+			("peer", addr.to_string()),
+			("block_hash", bhash.to_hex()),
+			("height", cb.header.height.to_string()),
+		]);
 		debug!(
 			"Received compact_block {} at {} from {}, outputs: {}, kernels: {}, kern_ids: {}, going to process.",
 			bhash,
@@ -205,6 +223,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 
 	fn header_received(&self, bh: core::BlockHeader, addr: SocketAddr) -> bool {
 		let bhash = bh.hash();
+		let _fields = log_fields(vec![
+			("peer", addr.to_string()),
+			("block_hash", bhash.to_hex()),
+			("height", bh.height.to_string()),
+		]);
 		debug!(
 			"Received block header {} at {} from {}, going to process.",
 			bhash, bh.height, addr,
@@ -230,6 +253,8 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			}
 		}
 
+		self.events.header_accepted(&bh);
+
 		// we have successfully processed a block header
 		// so we can go request the block itself
 		self.request_compact_block(&bh, &addr);
@@ -239,6 +264,7 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 	}
 
 	fn headers_received(&self, bhs: Vec<core::BlockHeader>, addr: SocketAddr) -> bool {
+		let _fields = log_fields(vec![("peer", addr.to_string())]);
 		info!(
 			"Received block headers {:?} from {}",
 			bhs.iter().map(|x| x.hash()).collect::<Vec<_>>(),
@@ -257,6 +283,10 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			if e.is_bad_data() {
 				return false;
 			}
+		} else {
+			for bh in &bhs {
+				self.events.header_accepted(bh);
+			}
 		}
 		true
 	}
@@ -307,11 +337,18 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 	/// at the provided block hash.
 	fn txhashset_read(&self, h: Hash) -> Option<p2p::TxHashSetRead> {
 		match self.chain().txhashset_read(h.clone()) {
-			Ok((out_index, kernel_index, read)) => Some(p2p::TxHashSetRead {
-				output_index: out_index,
-				kernel_index: kernel_index,
-				reader: read,
-			}),
+			Ok((out_index, kernel_index, path)) => match File::open(&path) {
+				Ok(reader) => Some(p2p::TxHashSetRead {
+					output_index: out_index,
+					kernel_index: kernel_index,
+					reader,
+					path,
+				}),
+				Err(e) => {
+					warn!("Couldn't open txhashset zip {:?}: {}", path, e);
+					None
+				}
+			},
 			Err(e) => {
 				warn!("Couldn't produce txhashset data for block {}: {:?}", h, e);
 				None
@@ -369,6 +406,73 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			true
 		}
 	}
+
+	fn txhashset_segment_read(
+		&self,
+		h: Hash,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Option<TxHashSetSegment> {
+		match self.chain().txhashset_segment(h, segment_type, id) {
+			Ok(segment) => Some(segment),
+			Err(e) => {
+				warn!(
+					"Couldn't produce {:?} segment {:?} for block {}: {:?}",
+					segment_type, id, h, e
+				);
+				None
+			}
+		}
+	}
+
+	/// Adds a segment to the txhashset being rebuilt, installing the full
+	/// txhashset once the last segment is in.
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: TxHashSetSegment,
+		_peer_addr: SocketAddr,
+	) -> bool {
+		let start_time = match self.sync_state.status() {
+			SyncStatus::TxHashsetDownload { start_time, .. } => start_time,
+			_ => return true,
+		};
+
+		let chain = self.chain();
+		if let Err(e) = chain.segment_sync_apply(h, segment) {
+			if e.is_bad_data() {
+				return false;
+			}
+			error!("Failed to add txhashset segment: {}", e);
+			self.sync_state.set_sync_error(types::Error::Chain(e));
+			return true;
+		}
+
+		if let Some((received, total, bytes)) = chain.segment_sync_progress() {
+			// the total size is only an estimate from what we got so far
+			let total_size = if received > 0 {
+				bytes * total / received
+			} else {
+				0
+			};
+			self.sync_state
+				.update_txhashset_download(SyncStatus::TxHashsetDownload {
+					start_time,
+					downloaded_size: bytes,
+					total_size,
+				});
+		}
+
+		if chain.segment_sync_complete() {
+			if let Err(e) = chain.segment_sync_finish(self.sync_state.as_ref()) {
+				error!("Failed to save txhashset from segments: {}", e);
+				self.sync_state.set_sync_error(types::Error::Chain(e));
+			} else {
+				info!("Received valid txhashset segments for {}.", h);
+			}
+		}
+		true
+	}
 }
 
 impl NetToChainAdapter {
@@ -378,6 +482,7 @@ impl NetToChainAdapter {
 		chain: Arc<chain::Chain>,
 		tx_pool: Arc<RwLock<pool::TransactionPool>>,
 		verifier_cache: Arc<RwLock<VerifierCache>>,
+		events: Arc<api::EventHub>,
 		config: ServerConfig,
 	) -> NetToChainAdapter {
 		NetToChainAdapter {
@@ -386,6 +491,7 @@ impl NetToChainAdapter {
 			tx_pool,
 			verifier_cache,
 			peers: OneTime::new(),
+			events,
 			config,
 		}
 	}
@@ -537,8 +643,8 @@ impl NetToChainAdapter {
 	}
 
 	fn check_compact(&self) {
-		// Skip compaction if we are syncing.
-		if self.sync_state.is_syncing() {
+		// Skip compaction if we are syncing, or it's scheduled instead
+		if self.sync_state.is_syncing() || self.config.maintenance.compaction.is_some() {
 			return;
 		}
 
@@ -641,16 +747,22 @@ pub struct ChainToPoolAndNetAdapter {
 	sync_state: Arc<SyncState>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: OneTime<Weak<p2p::Peers>>,
+	events: Arc<api::EventHub>,
+	webhooks: Arc<WebhookDispatcher>,
 }
 
 impl ChainAdapter for ChainToPoolAndNetAdapter {
 	fn block_accepted(&self, b: &core::Block, opts: Options) {
+		self.events.block_accepted(b);
+
 		if self.sync_state.is_syncing() {
 			return;
 		}
 
 		debug!("adapter: block_accepted: {:?}", b.hash());
 
+		self.webhooks.block_accepted(b);
+
 		if let Err(e) = self.tx_pool.write().reconcile_block(b) {
 			error!(
 				"Pool could not update itself at block {}: {:?}",
@@ -663,9 +775,11 @@ impl ChainAdapter for ChainToPoolAndNetAdapter {
 		// If we received the block from another node then broadcast "header first"
 		// to minimize network traffic.
 		if opts.contains(Options::MINE) {
-			// propagate compact block out if we mined the block
+			// propagate compact block out if we mined the block, along with
+			// the transactions peers may be missing
+			let txs = self.tx_pool.read().retrieve_block_transactions(b);
 			let cb: CompactBlock = b.clone().into();
-			self.peers().broadcast_compact_block(&cb);
+			self.peers().broadcast_compact_block(&cb, &txs);
 		} else {
 			// "header first" propagation if we are not the originator of this block
 			self.peers().broadcast_header(&b.header);
@@ -678,11 +792,15 @@ impl ChainToPoolAndNetAdapter {
 	pub fn new(
 		sync_state: Arc<SyncState>,
 		tx_pool: Arc<RwLock<pool::TransactionPool>>,
+		events: Arc<api::EventHub>,
+		webhooks: Arc<WebhookDispatcher>,
 	) -> ChainToPoolAndNetAdapter {
 		ChainToPoolAndNetAdapter {
 			sync_state,
 			tx_pool,
 			peers: OneTime::new(),
+			events,
+			webhooks,
 		}
 	}
 
@@ -704,6 +822,7 @@ impl ChainToPoolAndNetAdapter {
 /// transactions that have been accepted.
 pub struct PoolToNetAdapter {
 	peers: OneTime<Weak<p2p::Peers>>,
+	events: Arc<api::EventHub>,
 }
 
 impl pool::PoolAdapter for PoolToNetAdapter {
@@ -716,14 +835,16 @@ impl pool::PoolAdapter for PoolToNetAdapter {
 
 	fn tx_accepted(&self, tx: &core::Transaction) {
 		self.peers().broadcast_transaction(tx);
+		self.events.tx_accepted(tx);
 	}
 }
 
 impl PoolToNetAdapter {
 	/// Create a new pool to net adapter
-	pub fn new() -> PoolToNetAdapter {
+	pub fn new(events: Arc<api::EventHub>) -> PoolToNetAdapter {
 		PoolToNetAdapter {
 			peers: OneTime::new(),
+			events,
 		}
 	}
 
@@ -805,4 +926,14 @@ impl pool::BlockChain for PoolToChainAdapter {
 			.verify_tx_lock_height(tx)
 			.map_err(|_| pool::PoolError::ImmatureTransaction)
 	}
+
+	fn verify_input_confirmations(
+		&self,
+		tx: &Transaction,
+		confirmations: u64,
+	) -> Result<(), pool::PoolError> {
+		self.chain()
+			.verify_input_confirmations(tx, confirmations)
+			.map_err(|_| pool::PoolError::ImmatureInput)
+	}
 }
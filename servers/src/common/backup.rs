@@ -0,0 +1,220 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Online backup of the node data: chain database, txhashset files and peer
+//! store, copied while the node runs. A backup directory is laid out as the
+//! node data directory (`db_root`), restoring it is copying it back in place
+//! while the node is stopped.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use chrono::prelude::Utc;
+use futures::Future;
+use hyper::{Body, Request, StatusCode};
+
+use api::{self, Handler, ResponseFuture};
+use chain;
+use common::types::Error;
+use grin::server::{NETWORK_FILE_NAME, PEER_DB_NAME};
+use p2p;
+use store;
+use util::file;
+
+/// Path of the backup endpoint
+pub const BACKUP_PATH: &'static str = "/v1/backup";
+
+/// Where to write a backup
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupRequest {
+	/// Directory, on the node host, the backup is written to. Must be under
+	/// the configured backup directory, relative paths being taken from it,
+	/// and not exist or be empty.
+	pub dest: String,
+}
+
+/// What a backup holds
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupReport {
+	/// Directory the backup was written to
+	pub dest: String,
+	/// Height of the chain head backed up
+	pub height: u64,
+	/// Hash of the chain head backed up
+	pub last_block_h: String,
+}
+
+/// Writes a consistent backup of the node data to the provided directory,
+/// while the node runs
+pub fn backup(
+	chain: &chain::Chain,
+	peers: &p2p::Peers,
+	db_root: &str,
+	dest: &Path,
+) -> Result<BackupReport, Error> {
+	let io_err = |e| Error::Backup(format!("{}: {}", dest.display(), e));
+	if dest.exists() && dest.read_dir().map_err(io_err)?.next().is_some() {
+		return Err(Error::Backup(format!("{} is not empty", dest.display())));
+	}
+	let peer_db = dest.join(PEER_DB_NAME);
+	fs::create_dir_all(&peer_db).map_err(io_err)?;
+
+	let head = chain.backup(dest)?;
+	peers.copy_store_to(&peer_db)?;
+	let network = Path::new(db_root).join(NETWORK_FILE_NAME);
+	if network.exists() {
+		fs::copy(&network, dest.join(NETWORK_FILE_NAME)).map_err(io_err)?;
+	}
+
+	info!(
+		"Backed up the node data at {} ({}) to {}.",
+		head.height,
+		head.last_block_h,
+		dest.display()
+	);
+	Ok(BackupReport {
+		dest: dest.display().to_string(),
+		height: head.height,
+		last_block_h: head.last_block_h.to_hex(),
+	})
+}
+
+/// Restores a backup into the data directory of a stopped node. Existing
+/// data is moved aside rather than deleted, returning where to.
+pub fn restore(src: &Path, db_root: &str) -> Result<Option<PathBuf>, Error> {
+	let chain_db = src.join(store::DEFAULT_ENV_NAME).join("data.mdb");
+	if !chain_db.is_file() || !src.join(chain::txhashset::TXHASHSET_SUBDIR).is_dir() {
+		return Err(Error::Backup(format!(
+			"{} doesn't hold a node backup",
+			src.display()
+		)));
+	}
+
+	let db_root = Path::new(db_root);
+	let io_err = |e| Error::Backup(format!("{}: {}", db_root.display(), e));
+	let moved = if db_root.exists() {
+		let aside = PathBuf::from(format!(
+			"{}.pre-restore-{}",
+			db_root.display(),
+			Utc::now().format("%Y%m%d%H%M%S")
+		));
+		fs::rename(db_root, &aside).map_err(io_err)?;
+		Some(aside)
+	} else {
+		None
+	};
+	if let Err(e) = file::copy_dir_to(src, db_root) {
+		// back to the data we had rather than half a backup
+		let _ = fs::remove_dir_all(db_root);
+		if let Some(ref aside) = moved {
+			if let Err(e) = fs::rename(aside, db_root) {
+				error!(
+					"Could not move {} back to {}: {}",
+					aside.display(),
+					db_root.display(),
+					e
+				);
+			}
+		}
+		return Err(io_err(e));
+	}
+	Ok(moved)
+}
+
+// Where a backup asked for goes, under the backup directory. Relative paths
+// are taken from it, absolute ones have to be within it.
+fn backup_dest(backup_dir: &Path, dest: &Path) -> Option<PathBuf> {
+	if dest.components().any(|c| c == Component::ParentDir) {
+		return None;
+	}
+	let backup_dir = backup_dir
+		.canonicalize()
+		.unwrap_or_else(|_| backup_dir.to_path_buf());
+	let dest = backup_dir.join(dest);
+	if dest == backup_dir || !dest.starts_with(&backup_dir) {
+		return None;
+	}
+	Some(dest)
+}
+
+/// Backs up the node data to a directory on the node host.
+/// POST /v1/backup
+pub struct BackupHandler {
+	chain: Weak<chain::Chain>,
+	peers: Weak<p2p::Peers>,
+	db_root: String,
+	backup_dir: Option<PathBuf>,
+}
+
+impl BackupHandler {
+	/// The handler, as added to the node API routes
+	pub fn route(
+		chain: &Arc<chain::Chain>,
+		peers: &Arc<p2p::Peers>,
+		db_root: &str,
+		backup_dir: Option<&str>,
+	) -> (&'static str, api::HandlerObj) {
+		let handler = BackupHandler {
+			chain: Arc::downgrade(chain),
+			peers: Arc::downgrade(peers),
+			db_root: db_root.to_owned(),
+			backup_dir: backup_dir.map(PathBuf::from),
+		};
+		(BACKUP_PATH, Arc::new(handler))
+	}
+
+	fn backup(&self, req: BackupRequest) -> ResponseFuture {
+		let (chain, peers) = match (self.chain.upgrade(), self.peers.upgrade()) {
+			(Some(chain), Some(peers)) => (chain, peers),
+			_ => return api::response(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+		};
+		let backup_dir = match self.backup_dir {
+			Some(ref dir) => dir,
+			None => return api::response(StatusCode::FORBIDDEN, "no backup directory configured"),
+		};
+		let dest = match backup_dest(backup_dir, Path::new(&req.dest)) {
+			Some(dest) => dest,
+			None => {
+				return api::response(
+					StatusCode::BAD_REQUEST,
+					format!("{} is not under {}", req.dest, backup_dir.display()),
+				)
+			}
+		};
+		match backup(&chain, &peers, &self.db_root, &dest) {
+			Ok(report) => api::json_response(&report),
+			Err(Error::Backup(msg)) => api::response(StatusCode::BAD_REQUEST, msg),
+			Err(e) => {
+				error!("Backup to {} failed: {:?}", req.dest, e);
+				api::response(StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e))
+			}
+		}
+	}
+}
+
+impl Handler for BackupHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let handler = BackupHandler {
+			chain: self.chain.clone(),
+			peers: self.peers.clone(),
+			db_root: self.db_root.clone(),
+			backup_dir: self.backup_dir.clone(),
+		};
+		Box::new(api::parse_body(req).then(move |req| match req {
+			Ok(req) => handler.backup(req),
+			Err(e) => api::response(StatusCode::BAD_REQUEST, format!("{}", e)),
+		}))
+	}
+}
@@ -15,5 +15,11 @@
 //! Modules common to all Grin server types
 
 pub mod adapters;
+pub mod backup;
+pub mod reindex;
+pub mod reload;
+pub mod resources;
+pub mod scheduler;
 pub mod stats;
 pub mod types;
+pub mod webhooks;
@@ -0,0 +1,74 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilding of the indices derived from the chain data, on a stopped node.
+//! Blocks and txhashset are authoritative, the indices over them can always
+//! be built again, which beats resyncing from scratch when they get corrupted.
+
+use std::sync::Arc;
+
+use chain;
+use common::types::{Error, ServerConfig};
+use core::core::verifier_cache::LruVerifierCache;
+use core::pow;
+use grin::server::{check_network, genesis_block, PEER_DB_NAME};
+use p2p;
+use store;
+use util::{MemoryBudget, RwLock};
+
+/// What a reindex went through
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReindexReport {
+	/// Indices of the chain rebuilt
+	pub chain: chain::ReindexStats,
+	/// Unreadable peer records deleted
+	pub peers_pruned: usize,
+}
+
+/// Drops and rebuilds the indices of the chain data of a stopped node, and
+/// clears the peer records that can't be read anymore
+pub fn reindex(config: &ServerConfig) -> Result<ReindexReport, Error> {
+	check_network(&config.db_root, &config.chain_type)?;
+	store::set_map_growth(config.db_map_increment_mb << 20, config.db_map_max_mb << 20);
+
+	let db_env = Arc::new(store::new_env(config.db_root.clone()));
+	chain::migration::migrate_db(
+		&config.db_root,
+		db_env.clone(),
+		config.db_backup_before_migration,
+	)?;
+	let chain = chain::Chain::init(
+		config.db_root.clone(),
+		db_env,
+		Arc::new(chain::types::NoopAdapter {}),
+		genesis_block(&config.chain_type),
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		config.archive_mode.unwrap_or(false),
+		Arc::new(MemoryBudget::unlimited()),
+	)?;
+	let stats = chain.reindex()?;
+	chain.stop()?;
+
+	let peer_db_env = Arc::new(store::new_named_env(
+		config.db_root.clone(),
+		PEER_DB_NAME.into(),
+	));
+	let peers_pruned = p2p::PeerStore::new(peer_db_env)?.prune_unreadable()?;
+
+	Ok(ReindexReport {
+		chain: stats,
+		peers_pruned,
+	})
+}
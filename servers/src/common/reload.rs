@@ -0,0 +1,368 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reloading the configuration of a running server. Changes to the log
+//! levels, peer limits, Dandelion parameters and mining policy are applied
+//! right away, anything else only gets reported as needing a restart.
+
+use hyper::{Body, Request, StatusCode};
+use serde_json::{self, Value};
+use std::sync::{Arc, Weak};
+use util::{self, LoggingConfig, RwLock};
+
+use api::{self, Handler, ResponseFuture};
+use common::types::{Error, ServerConfig, StratumServerConfig};
+use p2p;
+use pool::{DandelionConfig, TransactionPool};
+
+/// Path the owner API reloads the configuration at
+pub const CONFIG_RELOAD_PATH: &'static str = "/v1/config/reload";
+
+/// Keys applied without a restart, the ones ending with a dot covering a
+/// whole section
+const RELOADABLE_KEYS: &'static [&'static str] = &[
+	"logging.stdout_log_level",
+	"logging.file_log_level",
+	"server.p2p_config.peer_max_count",
+	"server.p2p_config.peer_min_preferred_count",
+	"server.dandelion_config.",
+	"server.pool_config.",
+	"server.stratum_mining_config.attempt_time_per_block",
+	"server.stratum_mining_config.minimum_share_difficulty",
+	"server.stratum_mining_config.vardiff_target_shares_per_minute",
+	"server.stratum_mining_config.vardiff_retarget_secs",
+	"server.stratum_mining_config.job_refresh_interval_secs",
+	"server.stratum_mining_config.job_min_fee_increase",
+];
+
+/// Reads the configuration file again, returning the server and logging
+/// configurations as the node would start with.
+pub type ConfigLoader =
+	Box<Fn() -> Result<(ServerConfig, Option<LoggingConfig>), String> + Send + Sync>;
+
+/// Outcome of a configuration reload, listing keys as found in the config
+/// file, like `server.p2p_config.peer_max_count`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReloadReport {
+	/// Keys that changed and were applied
+	pub applied: Vec<String>,
+	/// Keys that changed but only take effect after a restart
+	pub restart_required: Vec<String>,
+	/// Keys that changed and could have been applied, but were refused
+	#[serde(default)]
+	pub rejected: Vec<String>,
+}
+
+/// Holds the configuration the server runs with and the shared parts of the
+/// server it can be changed on.
+pub struct ConfigReloader {
+	loader: RwLock<Option<ConfigLoader>>,
+	current: RwLock<(ServerConfig, Option<LoggingConfig>)>,
+	peers: Arc<p2p::Peers>,
+	tx_pool: Arc<RwLock<TransactionPool>>,
+	dandelion_config: Arc<RwLock<DandelionConfig>>,
+	stratum_config: Arc<RwLock<StratumServerConfig>>,
+}
+
+impl ConfigReloader {
+	/// New reloader for a server running with the provided config
+	pub fn new(
+		config: ServerConfig,
+		peers: Arc<p2p::Peers>,
+		tx_pool: Arc<RwLock<TransactionPool>>,
+		dandelion_config: Arc<RwLock<DandelionConfig>>,
+		stratum_config: Arc<RwLock<StratumServerConfig>>,
+	) -> ConfigReloader {
+		ConfigReloader {
+			loader: RwLock::new(None),
+			current: RwLock::new((config, None)),
+			peers,
+			tx_pool,
+			dandelion_config,
+			stratum_config,
+		}
+	}
+
+	/// Sets where the configuration gets reloaded from, along with the
+	/// logging configuration the node started with
+	pub fn set_loader(&self, loader: ConfigLoader, logging: Option<LoggingConfig>) {
+		self.current.write().1 = logging;
+		*self.loader.write() = Some(loader);
+	}
+
+	/// Reads the configuration again and applies what can be without a
+	/// restart.
+	pub fn reload(&self) -> Result<ReloadReport, Error> {
+		let (config, logging) = match *self.loader.read() {
+			Some(ref loader) => loader().map_err(Error::Config)?,
+			None => {
+				return Err(Error::Config(
+					"no configuration file to reload from".to_owned(),
+				))
+			}
+		};
+
+		let mut current = self.current.write();
+		let mut changed = vec![];
+		diff_keys(
+			"server",
+			&to_value(&current.0)?,
+			&to_value(&config)?,
+			&mut changed,
+		);
+		diff_keys(
+			"logging",
+			&to_value(&current.1)?,
+			&to_value(&logging)?,
+			&mut changed,
+		);
+		let mut report = classify(changed);
+		let (logging_applied, p2p_applied, dandelion_applied, pool_applied, stratum_applied) = {
+			let applied = |prefix: &str| report.applied.iter().any(|k| k.starts_with(prefix));
+			(
+				applied("logging."),
+				applied("server.p2p_config."),
+				applied("server.dandelion_config."),
+				applied("server.pool_config."),
+				applied("server.stratum_mining_config."),
+			)
+		};
+
+		if logging_applied {
+			let updated = match logging {
+				Some(ref logging) => util::update_log_levels(
+					logging.stdout_log_level.clone(),
+					logging.file_log_level.clone(),
+				),
+				None => false,
+			};
+			match (updated, current.1.as_mut(), logging) {
+				(true, Some(current_logging), Some(logging)) => {
+					current_logging.stdout_log_level = logging.stdout_log_level;
+					current_logging.file_log_level = logging.file_log_level;
+				}
+				_ => report.reject("logging."),
+			}
+		}
+		if p2p_applied {
+			self.peers.set_peer_limits(
+				config.p2p_config.peer_max_count,
+				config.p2p_config.peer_min_preferred_count,
+			);
+			current.0.p2p_config.peer_max_count = config.p2p_config.peer_max_count;
+			current.0.p2p_config.peer_min_preferred_count =
+				config.p2p_config.peer_min_preferred_count;
+		}
+		if dandelion_applied {
+			*self.dandelion_config.write() = config.dandelion_config.clone();
+			current.0.dandelion_config = config.dandelion_config.clone();
+		}
+		if pool_applied {
+			self.tx_pool.write().config = config.pool_config.clone();
+			current.0.pool_config = config.pool_config.clone();
+		}
+		if stratum_applied {
+			let new = config.stratum_mining_config.clone().unwrap();
+			let mut shared = self.stratum_config.write();
+			shared.attempt_time_per_block = new.attempt_time_per_block;
+			shared.minimum_share_difficulty = new.minimum_share_difficulty;
+			shared.vardiff_target_shares_per_minute = new.vardiff_target_shares_per_minute;
+			shared.vardiff_retarget_secs = new.vardiff_retarget_secs;
+			shared.job_refresh_interval_secs = new.job_refresh_interval_secs;
+			shared.job_min_fee_increase = new.job_min_fee_increase;
+			if let Some(ref mut c) = current.0.stratum_mining_config {
+				c.attempt_time_per_block = new.attempt_time_per_block;
+				c.minimum_share_difficulty = new.minimum_share_difficulty;
+				c.vardiff_target_shares_per_minute = new.vardiff_target_shares_per_minute;
+				c.vardiff_retarget_secs = new.vardiff_retarget_secs;
+				c.job_refresh_interval_secs = new.job_refresh_interval_secs;
+				c.job_min_fee_increase = new.job_min_fee_increase;
+			}
+		}
+
+		warn!(
+			"Configuration reloaded, applied: {:?}, restart required: {:?}, rejected: {:?}",
+			report.applied, report.restart_required, report.rejected
+		);
+		Ok(report)
+	}
+}
+
+fn to_value<T: ::serde::Serialize>(t: &T) -> Result<Value, Error> {
+	serde_json::to_value(t).map_err(|e| Error::Config(format!("{}", e)))
+}
+
+// Dotted paths to the values that differ between both, descending into the
+// tables both have
+fn diff_keys(path: &str, old: &Value, new: &Value, changed: &mut Vec<String>) {
+	match (old, new) {
+		(&Value::Object(ref old), &Value::Object(ref new)) => {
+			let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+			keys.sort();
+			keys.dedup();
+			for k in keys {
+				let key = format!("{}.{}", path, k);
+				match (old.get(k), new.get(k)) {
+					(Some(o), Some(n)) => diff_keys(&key, o, n, changed),
+					_ => changed.push(key),
+				}
+			}
+		}
+		_ => {
+			if old != new {
+				changed.push(path.to_owned());
+			}
+		}
+	}
+}
+
+// Splits changed keys between the ones we can apply and the others
+fn classify(changed: Vec<String>) -> ReloadReport {
+	let (applied, restart_required): (Vec<String>, Vec<String>) =
+		changed.into_iter().partition(|k: &String| {
+			RELOADABLE_KEYS.iter().any(|r| {
+				if r.ends_with('.') {
+					k.starts_with(r)
+				} else {
+					k == r
+				}
+			})
+		});
+	ReloadReport {
+		applied,
+		restart_required,
+		rejected: vec![],
+	}
+}
+
+impl ReloadReport {
+	// Moves the applied keys under the prefix over to the rejected ones
+	fn reject(&mut self, prefix: &str) {
+		let (rejected, applied) = self
+			.applied
+			.drain(..)
+			.partition(|k: &String| k.starts_with(prefix));
+		self.applied = applied;
+		self.rejected.extend(rejected);
+	}
+}
+
+/// Reloads the configuration file, applying what it can and telling which
+/// changes need a restart.
+/// POST /v1/config/reload
+pub struct ConfigReloadHandler {
+	reloader: Weak<ConfigReloader>,
+}
+
+impl ConfigReloadHandler {
+	/// The handler, as added to the node API routes
+	pub fn route(reloader: &Arc<ConfigReloader>) -> (&'static str, api::HandlerObj) {
+		let handler = ConfigReloadHandler {
+			reloader: Arc::downgrade(reloader),
+		};
+		(CONFIG_RELOAD_PATH, Arc::new(handler))
+	}
+}
+
+impl Handler for ConfigReloadHandler {
+	fn post(&self, _req: Request<Body>) -> ResponseFuture {
+		match self.reloader.upgrade() {
+			Some(reloader) => match reloader.reload() {
+				Ok(report) => api::json_response(&report),
+				Err(Error::Config(msg)) => api::response(StatusCode::BAD_REQUEST, msg),
+				Err(e) => api::response(StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)),
+			},
+			None => api::response(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn changed(old: &ServerConfig, new: &ServerConfig) -> Vec<String> {
+		let mut changed = vec![];
+		diff_keys(
+			"server",
+			&to_value(old).unwrap(),
+			&to_value(new).unwrap(),
+			&mut changed,
+		);
+		changed
+	}
+
+	#[test]
+	fn finds_changed_keys() {
+		let old = ServerConfig::default();
+		assert!(changed(&old, &old.clone()).is_empty());
+
+		let mut new = old.clone();
+		new.p2p_config.peer_max_count = Some(50);
+		new.dandelion_config.embargo_secs = Some(60);
+		new.p2p_config.port = 4000;
+		let mut keys = changed(&old, &new);
+		keys.sort();
+		assert_eq!(
+			keys,
+			vec![
+				"server.dandelion_config.embargo_secs",
+				"server.p2p_config.peer_max_count",
+				"server.p2p_config.port",
+			]
+		);
+
+		new.stratum_mining_config = None;
+		assert!(changed(&old, &new)
+			.iter()
+			.any(|k| k == "server.stratum_mining_config"));
+	}
+
+	#[test]
+	fn reloadable_or_not() {
+		let mut report = classify(vec![
+			"logging.file_log_level".to_owned(),
+			"logging.log_file_path".to_owned(),
+			"server.pool_config.accept_fee_base".to_owned(),
+			"server.p2p_config.peer_min_preferred_count".to_owned(),
+			"server.p2p_config.port".to_owned(),
+			"server.stratum_mining_config.job_min_fee_increase".to_owned(),
+			"server.stratum_mining_config.stratum_server_addr".to_owned(),
+			"server.stratum_mining_config".to_owned(),
+		]);
+		assert_eq!(
+			report.applied,
+			vec![
+				"logging.file_log_level",
+				"server.pool_config.accept_fee_base",
+				"server.p2p_config.peer_min_preferred_count",
+				"server.stratum_mining_config.job_min_fee_increase",
+			]
+		);
+		assert_eq!(
+			report.restart_required,
+			vec![
+				"logging.log_file_path",
+				"server.p2p_config.port",
+				"server.stratum_mining_config.stratum_server_addr",
+				"server.stratum_mining_config",
+			]
+		);
+
+		// log levels with no logger to set them on
+		report.reject("logging.");
+		assert_eq!(report.rejected, vec!["logging.file_log_level"]);
+		assert!(!report.applied.iter().any(|k| k.starts_with("logging.")));
+	}
+}
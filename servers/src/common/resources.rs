@@ -0,0 +1,135 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Open files and threads the node needs for the most peers and stratum
+//! workers configured, checked on startup against the limits the OS puts on
+//! the process. Running out of them otherwise only shows as connections and
+//! database reads failing with "Too many open files" in the middle of a sync.
+
+use common::types::{Error, ServerConfig};
+use util::ProcessLimits;
+
+/// Files each peer keeps open, its socket and a clone of it to write to
+const FDS_PER_PEER: u64 = 2;
+
+/// Threads each peer runs, polling its connection
+const THREADS_PER_PEER: u64 = 1;
+
+/// Files each stratum worker keeps open, its socket
+const FDS_PER_WORKER: u64 = 1;
+
+/// Files open regardless of peers and workers: the database, the txhashset
+/// files and archives, logs, listening sockets and API connections
+const BASE_FDS: u64 = 256;
+
+/// Threads running regardless of peers and workers: sync, seeding, mining,
+/// the pool monitors and the API server
+const BASE_THREADS: u64 = 64;
+
+/// Most open files and threads the node needs
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceBudget {
+	/// Open file descriptors, sockets included
+	pub open_fds: u64,
+	/// Threads
+	pub threads: u64,
+}
+
+impl ResourceBudget {
+	/// Budget for the most peers and stratum workers configured. Stratum
+	/// workers aren't counted without a maximum set for them.
+	pub fn new(config: &ServerConfig) -> ResourceBudget {
+		let peers = config.p2p_config.peer_max_count() as u64;
+		let workers = match config.stratum_mining_config {
+			Some(ref c) if c.enable_stratum_server == Some(true) => c.max_workers as u64,
+			_ => 0,
+		};
+		ResourceBudget {
+			open_fds: BASE_FDS + peers * FDS_PER_PEER + workers * FDS_PER_WORKER,
+			threads: BASE_THREADS + peers * THREADS_PER_PEER,
+		}
+	}
+}
+
+/// Refuses a configuration whose budget doesn't fit the share of the limits
+/// past which the p2p server stops taking on new peers
+pub fn check_limits(config: &ServerConfig, limits: &ProcessLimits) -> Result<(), Error> {
+	let budget = ResourceBudget::new(config);
+	let percent = config.p2p_config.resource_cap_percent();
+	let fits = |needed: u64, max: Option<u64>| match max {
+		Some(max) => needed <= max * percent / 100,
+		None => true,
+	};
+	if !fits(budget.open_fds, limits.max_open_fds) {
+		return Err(Error::Config(format!(
+			"{} peers and stratum workers configured need up to {} open files, over {}% \
+			 of the limit of {}. Raise it (ulimit -n) or lower peer_max_count and \
+			 max_workers.",
+			config.p2p_config.peer_max_count(),
+			budget.open_fds,
+			percent,
+			limits.max_open_fds.unwrap_or(0)
+		)));
+	}
+	if !fits(budget.threads, limits.max_threads) {
+		return Err(Error::Config(format!(
+			"{} peers configured need up to {} threads, over {}% of the limit of {}. \
+			 Raise it (ulimit -u) or lower peer_max_count.",
+			config.p2p_config.peer_max_count(),
+			budget.threads,
+			percent,
+			limits.max_threads.unwrap_or(0)
+		)));
+	}
+	info!(
+		"Resource budget of {} open files and {} threads, limits {:?}",
+		budget.open_fds, budget.threads, limits
+	);
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn budget_fits_limits() {
+		let mut config = ServerConfig::default();
+		config.p2p_config.peer_max_count = Some(100);
+		let budget = ResourceBudget::new(&config);
+		assert_eq!(budget.open_fds, BASE_FDS + 200);
+		assert_eq!(budget.threads, BASE_THREADS + 100);
+
+		let limits = |fds, threads| ProcessLimits {
+			max_open_fds: fds,
+			max_threads: threads,
+		};
+		assert!(check_limits(&config, &limits(None, None)).is_ok());
+		assert!(check_limits(&config, &limits(Some(1024), Some(4096))).is_ok());
+		// the default soft limit of many systems leaves no room for them
+		assert!(check_limits(&config, &limits(Some(256), None)).is_err());
+		assert!(check_limits(&config, &limits(None, Some(128))).is_err());
+
+		// stratum workers only count once enabled
+		if let Some(ref mut c) = config.stratum_mining_config {
+			c.max_workers = 1000;
+		}
+		assert_eq!(ResourceBudget::new(&config).open_fds, BASE_FDS + 200);
+		if let Some(ref mut c) = config.stratum_mining_config {
+			c.enable_stratum_server = Some(true);
+		}
+		assert_eq!(ResourceBudget::new(&config).open_fds, BASE_FDS + 1200);
+		assert!(check_limits(&config, &limits(Some(1024), None)).is_err());
+	}
+}
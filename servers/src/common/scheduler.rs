@@ -0,0 +1,313 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Maintenance scheduler. Heavy background work (chain compaction, txhashset
+//! zip preparation, peer store cleanup) is registered as tasks, each with a
+//! cron-like window. Tasks run one at a time in a single thread, once per
+//! window, so they never pile up on the disk together. A window is a run of
+//! consecutive matching minutes: `0 3 * * *` is the minute at 03:00 UTC,
+//! `* 3-4 * * *` the two hours from 03:00, a task still running once in it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::{DateTime, Datelike, Timelike, Utc};
+
+use common::types::{Error, SyncState};
+
+/// When the maintenance tasks run, as cron-like windows in UTC: minute,
+/// hour, day of month, month and day of week (0 for Sunday), each `*`, a
+/// value, a range `a-b`, a step `*/n` or `a-b/n`, or a comma separated list
+/// of those.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+	/// Chain compaction. Unset, blocks roll the dice for a compaction about
+	/// once a day.
+	pub compaction: Option<String>,
+	/// Preparation of the txhashset zip fast syncing peers ask for. Unset,
+	/// the zip is only built when asked for.
+	pub txhashset_zip: Option<String>,
+	/// Removal of the expired peers from the peer store
+	pub peer_cleanup: String,
+}
+
+impl Default for MaintenanceConfig {
+	fn default() -> MaintenanceConfig {
+		MaintenanceConfig {
+			compaction: None,
+			txhashset_zip: None,
+			peer_cleanup: "0 * * * *".to_string(),
+		}
+	}
+}
+
+impl MaintenanceConfig {
+	/// Checks all the windows parse
+	pub fn validate(&self) -> Result<(), Error> {
+		let windows = vec![
+			self.compaction.as_ref(),
+			self.txhashset_zip.as_ref(),
+			Some(&self.peer_cleanup),
+		];
+		for w in windows.into_iter().filter_map(|w| w) {
+			Schedule::parse(w)?;
+		}
+		Ok(())
+	}
+}
+
+/// Minutes matched by a cron-like expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+	minutes: Vec<bool>,
+	hours: Vec<bool>,
+	days: Vec<bool>,
+	months: Vec<bool>,
+	weekdays: Vec<bool>,
+	// as in cron, a day matches either field when both are restricted
+	any_day: bool,
+	any_weekday: bool,
+}
+
+impl Schedule {
+	/// Parses a 5 fields cron-like expression
+	pub fn parse(expr: &str) -> Result<Schedule, Error> {
+		let fields: Vec<&str> = expr.split_whitespace().collect();
+		if fields.len() != 5 {
+			return Err(Error::Config(format!(
+				"maintenance window '{}' needs 5 fields: minute hour day month weekday",
+				expr
+			)));
+		}
+		let field = |i: usize, min: u32, max: u32| {
+			parse_field(fields[i], min, max)
+				.map_err(|e| Error::Config(format!("maintenance window '{}': {}", expr, e)))
+		};
+		let mut weekdays = field(4, 0, 7)?;
+		// 7 is Sunday too
+		if weekdays[7] {
+			weekdays[0] = true;
+		}
+		Ok(Schedule {
+			minutes: field(0, 0, 59)?,
+			hours: field(1, 0, 23)?,
+			days: field(2, 1, 31)?,
+			months: field(3, 1, 12)?,
+			weekdays,
+			any_day: fields[2] == "*",
+			any_weekday: fields[4] == "*",
+		})
+	}
+
+	/// Whether the expression matches the minute of the given time
+	pub fn matches(&self, t: &DateTime<Utc>) -> bool {
+		let day = self.days[t.day() as usize];
+		let weekday = self.weekdays[t.weekday().num_days_from_sunday() as usize];
+		let day_matches = if self.any_day || self.any_weekday {
+			day && weekday
+		} else {
+			day || weekday
+		};
+		self.minutes[t.minute() as usize]
+			&& self.hours[t.hour() as usize]
+			&& self.months[t.month() as usize]
+			&& day_matches
+	}
+}
+
+// Values matched by a field, indexed by value
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+	let mut matched = vec![false; max as usize + 1];
+	for item in field.split(',') {
+		let (range, step) = match item.find('/') {
+			Some(i) => {
+				let step = item[i + 1..]
+					.parse::<u32>()
+					.map_err(|_| format!("bad step in '{}'", item))?;
+				(&item[..i], step)
+			}
+			None => (item, 1),
+		};
+		if step == 0 {
+			return Err(format!("bad step in '{}'", item));
+		}
+		let (from, to) = if range == "*" {
+			(min, max)
+		} else {
+			let value = |s: &str| {
+				s.parse::<u32>()
+					.ok()
+					.filter(|v| *v >= min && *v <= max)
+					.ok_or(format!("'{}' not in {}-{}", s, min, max))
+			};
+			match range.find('-') {
+				Some(i) => (value(&range[..i])?, value(&range[i + 1..])?),
+				None => {
+					let v = value(range)?;
+					(v, v)
+				}
+			}
+		};
+		if from > to {
+			return Err(format!("empty range '{}'", range));
+		}
+		for v in (from..to + 1).step_by(step as usize) {
+			matched[v as usize] = true;
+		}
+	}
+	Ok(matched)
+}
+
+struct Task {
+	name: String,
+	schedule: Schedule,
+	run: Box<Fn() -> Result<(), String> + Send>,
+	// whether it already ran in the current window
+	ran: bool,
+}
+
+/// Runs the registered maintenance tasks in their windows, one at a time.
+/// Nothing runs while the node syncs, tasks waiting for the end of the sync
+/// if still in their window.
+pub struct Scheduler {
+	tasks: Vec<Task>,
+}
+
+impl Scheduler {
+	/// An empty scheduler
+	pub fn new() -> Scheduler {
+		Scheduler { tasks: vec![] }
+	}
+
+	/// Registers a task to run once in each window of the expression
+	pub fn register<F>(&mut self, name: &str, window: &str, run: F) -> Result<(), Error>
+	where
+		F: Fn() -> Result<(), String> + Send + 'static,
+	{
+		self.tasks.push(Task {
+			name: name.to_string(),
+			schedule: Schedule::parse(window)?,
+			run: Box::new(run),
+			ran: false,
+		});
+		Ok(())
+	}
+
+	/// Runs the tasks whose window is open at the given time and didn't run
+	/// in it yet, returning the names of those run
+	pub fn run_due(&mut self, now: &DateTime<Utc>, syncing: bool) -> Vec<String> {
+		let mut run = vec![];
+		for task in self.tasks.iter_mut() {
+			if !task.schedule.matches(now) {
+				task.ran = false;
+				continue;
+			}
+			if task.ran || syncing {
+				continue;
+			}
+			info!("maintenance: running {}", task.name);
+			if let Err(e) = (task.run)() {
+				error!("maintenance: {} failed: {}", task.name, e);
+			}
+			task.ran = true;
+			run.push(task.name.clone());
+		}
+		run
+	}
+
+	/// Starts checking for due tasks every minute, until stopped
+	pub fn start(mut self, sync_state: Arc<SyncState>, stop: Arc<AtomicBool>) {
+		if self.tasks.is_empty() {
+			return;
+		}
+		let _ = thread::Builder::new()
+			.name("maintenance".to_string())
+			.spawn(move || {
+				let mut last_minute = None;
+				while !stop.load(Ordering::Relaxed) {
+					let now = Utc::now();
+					let minute = now.timestamp() / 60;
+					if last_minute != Some(minute) {
+						last_minute = Some(minute);
+						self.run_due(&now, sync_state.is_syncing());
+					}
+					thread::sleep(Duration::from_secs(1));
+				}
+			});
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use chrono::TimeZone;
+	use std::sync::atomic::AtomicUsize;
+
+	#[test]
+	fn parse_windows() {
+		let s = Schedule::parse("*/15 3-4 * * *").unwrap();
+		assert!(s.matches(&Utc.ymd(2018, 11, 5).and_hms(3, 30, 10)));
+		assert!(s.matches(&Utc.ymd(2018, 11, 5).and_hms(4, 45, 0)));
+		assert!(!s.matches(&Utc.ymd(2018, 11, 5).and_hms(4, 46, 0)));
+		assert!(!s.matches(&Utc.ymd(2018, 11, 5).and_hms(5, 0, 0)));
+
+		// 2018-11-04 is a Sunday
+		let s = Schedule::parse("0 0 * * 7").unwrap();
+		assert!(s.matches(&Utc.ymd(2018, 11, 4).and_hms(0, 0, 0)));
+		assert!(!s.matches(&Utc.ymd(2018, 11, 5).and_hms(0, 0, 0)));
+
+		// either the day of month or of week when both are set
+		let s = Schedule::parse("0 0 1 * 1,3").unwrap();
+		assert!(s.matches(&Utc.ymd(2018, 11, 1).and_hms(0, 0, 0)));
+		assert!(s.matches(&Utc.ymd(2018, 11, 5).and_hms(0, 0, 0)));
+		assert!(!s.matches(&Utc.ymd(2018, 11, 6).and_hms(0, 0, 0)));
+
+		assert!(Schedule::parse("0 3 * *").is_err());
+		assert!(Schedule::parse("60 3 * * *").is_err());
+		assert!(Schedule::parse("*/0 3 * * *").is_err());
+		assert!(Schedule::parse("5-1 3 * * *").is_err());
+	}
+
+	#[test]
+	fn once_per_window() {
+		let count = Arc::new(AtomicUsize::new(0));
+		let c = count.clone();
+		let mut scheduler = Scheduler::new();
+		scheduler
+			.register("test", "* 3 * * *", move || {
+				c.fetch_add(1, Ordering::SeqCst);
+				Ok(())
+			})
+			.unwrap();
+
+		let day = Utc.ymd(2018, 11, 5);
+		assert!(scheduler.run_due(&day.and_hms(2, 59, 0), false).is_empty());
+		// waits for the sync to be over
+		assert!(scheduler.run_due(&day.and_hms(3, 0, 0), true).is_empty());
+		assert_eq!(
+			scheduler.run_due(&day.and_hms(3, 1, 0), false),
+			vec!["test"]
+		);
+		assert!(scheduler.run_due(&day.and_hms(3, 2, 0), false).is_empty());
+		assert!(scheduler.run_due(&day.and_hms(4, 0, 0), false).is_empty());
+		assert_eq!(
+			scheduler.run_due(&day.succ().and_hms(3, 0, 0), false),
+			vec!["test"]
+		);
+		assert_eq!(count.load(Ordering::SeqCst), 2);
+	}
+}
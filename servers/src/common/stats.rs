@@ -16,7 +16,7 @@
 //! to collect information about server status
 
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use util::RwLock;
 
 use core::consensus::graph_weight;
@@ -25,8 +25,15 @@ use chrono::prelude::*;
 
 use chain;
 use common::types::SyncStatus;
+use grin::sync::sync_api::SyncInfo;
 use p2p;
 
+/// How many of the blocks found by the stratum workers are kept in the stats
+pub const MAX_FOUND_BLOCKS: usize = 20;
+
+/// How many seconds back the per peer traffic history goes
+pub const TRAFFIC_HISTORY_SECS: u64 = 60;
+
 /// Server state info collection struct, to be passed around into internals
 /// and populated when required
 #[derive(Clone)]
@@ -54,10 +61,14 @@ pub struct ServerStats {
 	pub header_head: chain::Tip,
 	/// Whether we're currently syncing
 	pub sync_status: SyncStatus,
+	/// Details of the sync, progress rate and peers
+	pub sync_info: SyncInfo,
 	/// Handle to current stratum server stats
 	pub stratum_stats: StratumStats,
 	/// Peer stats
 	pub peer_stats: Vec<PeerStats>,
+	/// Peers currently banned
+	pub banned_peers: Vec<BanStats>,
 	/// Difficulty calculation statistics
 	pub diff_stats: DiffStats,
 }
@@ -67,10 +78,18 @@ pub struct ServerStats {
 pub struct WorkerStats {
 	/// Unique ID for this worker
 	pub id: String,
+	/// Login the worker gave, if it logged in
+	pub login: Option<String>,
+	/// Agent the worker gave when logging in
+	pub agent: String,
 	/// whether stratum worker is currently connected
 	pub is_connected: bool,
+	/// When the worker connected
+	pub connected_at: SystemTime,
 	/// Timestamp of most recent communication with this worker
 	pub last_seen: SystemTime,
+	/// Timestamp of the most recent accepted share
+	pub last_share: Option<SystemTime>,
 	/// pow difficulty this worker is using
 	pub pow_difficulty: u64,
 	/// number of valid shares submitted
@@ -79,6 +98,11 @@ pub struct WorkerStats {
 	pub num_rejected: u64,
 	/// number of shares submitted too late
 	pub num_stale: u64,
+	/// number of accepted shares that were full solutions
+	pub num_blocks_found: u64,
+	/// sum of the share difficulty asked of the worker over its accepted
+	/// shares, the work it has shown
+	pub accepted_difficulty: u64,
 }
 
 /// Struct to return relevant information about the stratum server
@@ -96,10 +120,33 @@ pub struct StratumStats {
 	pub network_difficulty: u64,
 	/// cuckoo size used for mining
 	pub edge_bits: u16,
+	/// Number of transactions in the block template being mined
+	pub template_tx_count: usize,
+	/// Total fees of the block template being mined
+	pub template_fees: u64,
+	/// Number of blocks found by the workers since the server started
+	pub num_blocks_found: u64,
+	/// The most recent blocks found by the workers, latest last
+	pub blocks_found: Vec<FoundBlock>,
 	/// Individual worker status
 	pub worker_stats: Vec<WorkerStats>,
 }
 
+/// A block found by one of the stratum workers
+#[derive(Clone, Serialize, Debug)]
+pub struct FoundBlock {
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the block, hex encoded
+	pub hash: String,
+	/// Fees of the transactions in the block
+	pub fees: u64,
+	/// When the solution was submitted
+	pub found_at: SystemTime,
+	/// Login, or id if it didn't log in, of the worker that found it
+	pub found_by: String,
+}
+
 /// Stats on the last WINDOW blocks and the difficulty calculation
 #[derive(Clone)]
 pub struct DiffStats {
@@ -143,6 +190,15 @@ pub struct PeerStats {
 	pub version: u32,
 	/// Peer user agent string.
 	pub user_agent: String,
+	/// Capabilities the peer advertised
+	pub capabilities: String,
+	/// Round trip time of the last ping, in milliseconds
+	pub rtt_ms: Option<u64>,
+	/// Requests the peer failed or let stall over the last minutes
+	pub failures: u64,
+	/// Score sync prefers peers by, from round trip time, throughput and
+	/// failures
+	pub quality: f64,
 	/// difficulty reported by peer
 	pub total_difficulty: u64,
 	/// height reported by peer on ping
@@ -151,10 +207,33 @@ pub struct PeerStats {
 	pub direction: String,
 	/// Last time we saw a ping/pong from this peer.
 	pub last_seen: DateTime<Utc>,
-	/// Number of bytes we've sent to the peer.
+	/// Bytes per second we've sent to the peer, over the last 10s
 	pub sent_bytes_per_sec: u64,
-	/// Number of bytes we've received from the peer.
+	/// Bytes per second we've received from the peer, over the last 10s
 	pub received_bytes_per_sec: u64,
+	/// Bytes per second we've sent to the peer, over the last 5 minutes
+	pub sent_bytes_per_sec_5m: u64,
+	/// Bytes per second we've received from the peer, over the last 5 minutes
+	pub received_bytes_per_sec_5m: u64,
+	/// Bytes sent to the peer in each of the last seconds, oldest first
+	pub sent_bytes_history: Vec<u64>,
+	/// Bytes received from the peer in each of the last seconds, oldest first
+	pub received_bytes_history: Vec<u64>,
+}
+
+/// A peer currently banned
+#[derive(Clone, Debug)]
+pub struct BanStats {
+	/// Address
+	pub addr: String,
+	/// Why it was banned
+	pub reason: String,
+	/// What the ban is about, empty if nothing was said
+	pub note: String,
+	/// When it was banned
+	pub banned_at: DateTime<Utc>,
+	/// When the ban runs out
+	pub expires_at: DateTime<Utc>,
 }
 
 impl StratumStats {
@@ -162,6 +241,107 @@ impl StratumStats {
 	pub fn network_hashrate(&self) -> f64 {
 		42.0 * (self.network_difficulty as f64 / graph_weight(self.edge_bits as u8) as f64) / 60.0
 	}
+
+	/// Records a block found by a worker, only keeping the most recent ones
+	pub fn block_found(&mut self, block: FoundBlock) {
+		self.num_blocks_found += 1;
+		self.blocks_found.push(block);
+		if self.blocks_found.len() > MAX_FOUND_BLOCKS {
+			self.blocks_found.remove(0);
+		}
+	}
+
+	/// Stats of the workers, added up by login. Workers that didn't log in
+	/// aren't counted.
+	pub fn login_stats(&self) -> Vec<LoginStats> {
+		let mut logins: Vec<LoginStats> = vec![];
+		for worker in &self.worker_stats {
+			let login = match worker.login {
+				Some(ref l) => l,
+				None => continue,
+			};
+			let pos = match logins.iter().position(|l| &l.login == login) {
+				Some(pos) => pos,
+				None => {
+					logins.push(LoginStats {
+						login: login.clone(),
+						..Default::default()
+					});
+					logins.len() - 1
+				}
+			};
+			let stats = &mut logins[pos];
+			if worker.is_connected {
+				stats.num_workers += 1;
+				stats.graph_rate += worker.graph_rate(self.edge_bits);
+			}
+			stats.num_accepted += worker.num_accepted;
+			stats.num_rejected += worker.num_rejected;
+			stats.num_stale += worker.num_stale;
+			stats.num_blocks_found += worker.num_blocks_found;
+			stats.last_share = match (stats.last_share, worker.last_share) {
+				(Some(a), Some(b)) if a > b => Some(a),
+				(a, None) => a,
+				(_, b) => b,
+			};
+		}
+		logins
+	}
+}
+
+impl WorkerStats {
+	/// How long the worker has been, or was, connected
+	pub fn uptime(&self) -> Duration {
+		let until = if self.is_connected {
+			SystemTime::now()
+		} else {
+			self.last_seen
+		};
+		until
+			.duration_since(self.connected_at)
+			.unwrap_or(Duration::from_secs(0))
+	}
+
+	/// Accepted shares per minute over the uptime
+	pub fn share_rate(&self) -> f64 {
+		let uptime = self.uptime().as_secs();
+		if uptime == 0 {
+			return 0.0;
+		}
+		self.num_accepted as f64 * 60.0 / uptime as f64
+	}
+
+	/// Estimated graphs per second, from the difficulty of the accepted
+	/// shares over the uptime, a graph holding a 42-cycle about once in 42
+	pub fn graph_rate(&self, edge_bits: u16) -> f64 {
+		let uptime = self.uptime().as_secs();
+		if uptime == 0 {
+			return 0.0;
+		}
+		42.0 * (self.accepted_difficulty as f64 / graph_weight(edge_bits as u8) as f64)
+			/ uptime as f64
+	}
+}
+
+/// Stratum worker stats, added up over the workers sharing a login
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct LoginStats {
+	/// The login
+	pub login: String,
+	/// Connected workers using it
+	pub num_workers: usize,
+	/// Valid shares submitted
+	pub num_accepted: u64,
+	/// Invalid shares submitted
+	pub num_rejected: u64,
+	/// Shares submitted too late
+	pub num_stale: u64,
+	/// Accepted shares that were full solutions
+	pub num_blocks_found: u64,
+	/// Estimated graphs per second of the connected workers
+	pub graph_rate: f64,
+	/// Most recent accepted share
+	pub last_share: Option<SystemTime>,
 }
 
 impl PeerStats {
@@ -176,6 +356,11 @@ impl PeerStats {
 			state = "Banned";
 		}
 		let addr = peer.info.addr.to_string();
+		let (sent_bytes_history, received_bytes_history) = peer
+			.bytes_per_sec(TRAFFIC_HISTORY_SECS)
+			.unwrap_or((vec![], vec![]));
+		let (sent_rate, received_rate) = peer.bytes_rates(10).unwrap_or((0.0, 0.0));
+		let (sent_rate_5m, received_rate_5m) = peer.bytes_rates(300).unwrap_or((0.0, 0.0));
 		let direction = match peer.info.direction {
 			p2p::types::Direction::Inbound => "Inbound",
 			p2p::types::Direction::Outbound => "Outbound",
@@ -185,26 +370,58 @@ impl PeerStats {
 			addr: addr,
 			version: peer.info.version.clone(),
 			user_agent: peer.info.user_agent.clone(),
+			capabilities: format!("{:?}", peer.info.capabilities),
+			rtt_ms: peer.info.rtt_ms(),
+			failures: peer.failures(),
+			quality: peer.quality(),
 			total_difficulty: peer.info.total_difficulty().to_num(),
 			height: peer.info.height(),
 			direction: direction.to_string(),
 			last_seen: peer.info.last_seen(),
-			sent_bytes_per_sec: peer.last_min_sent_bytes().unwrap_or(0) / 60,
-			received_bytes_per_sec: peer.last_min_received_bytes().unwrap_or(0) / 60,
+			sent_bytes_per_sec: sent_rate as u64,
+			received_bytes_per_sec: received_rate as u64,
+			sent_bytes_per_sec_5m: sent_rate_5m as u64,
+			received_bytes_per_sec_5m: received_rate_5m as u64,
+			sent_bytes_history,
+			received_bytes_history,
 		}
 	}
 }
 
+impl BanStats {
+	/// The peers currently banned, the bans running out first listed first
+	pub fn from_peers(peers: &p2p::Peers) -> Vec<BanStats> {
+		let mut banned = peers
+			.banned_peers()
+			.iter()
+			.map(|p| BanStats {
+				addr: p.addr.to_string(),
+				reason: format!("{:?}", p.ban_reason),
+				note: p.ban_note.clone(),
+				banned_at: Utc.timestamp(p.last_banned, 0),
+				expires_at: Utc.timestamp(peers.ban_expiry(p), 0),
+			}).collect::<Vec<_>>();
+		banned.sort_by_key(|b| b.expires_at);
+		banned
+	}
+}
+
 impl Default for WorkerStats {
 	fn default() -> WorkerStats {
 		WorkerStats {
 			id: String::from("unknown"),
+			login: None,
+			agent: String::new(),
 			is_connected: false,
+			connected_at: SystemTime::now(),
 			last_seen: SystemTime::now(),
+			last_share: None,
 			pow_difficulty: 0,
 			num_accepted: 0,
 			num_rejected: 0,
 			num_stale: 0,
+			num_blocks_found: 0,
+			accepted_difficulty: 0,
 		}
 	}
 }
@@ -218,6 +435,10 @@ impl Default for StratumStats {
 			block_height: 0,
 			network_difficulty: 1000,
 			edge_bits: 29,
+			template_tx_count: 0,
+			template_fees: 0,
+			num_blocks_found: 0,
+			blocks_found: Vec::new(),
 			worker_stats: Vec::new(),
 		}
 	}
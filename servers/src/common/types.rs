@@ -13,13 +13,18 @@
 // limitations under the License.
 
 //! Server types
+use std::collections::VecDeque;
 use std::convert::From;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use util::RwLock;
 
 use api;
 use chain;
 use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+use common::scheduler::MaintenanceConfig;
+use common::webhooks::WebhookConfig;
 use core::global::ChainTypes;
 use core::{core, pow};
 use p2p;
@@ -46,6 +51,10 @@ pub enum Error {
 	Cuckoo(pow::Error),
 	/// Error originating from the transaction pool.
 	Pool(pool::PoolError),
+	/// Error reading or applying the configuration.
+	Config(String),
+	/// Error backing up or restoring the node data.
+	Backup(String),
 }
 
 impl From<core::block::Error> for Error {
@@ -124,10 +133,27 @@ pub struct ServerConfig {
 	/// Location of secret for basic auth on Rest API HTTP server.
 	pub api_secret_path: Option<String>,
 
+	/// Location of the secret giving read-only access to the Rest API.
+	pub api_read_secret_path: Option<String>,
+
+	/// TLS certificate file, to serve the Rest API over HTTPS
+	pub tls_certificate_file: Option<String>,
+
+	/// Private key for the TLS certificate
+	pub tls_certificate_key: Option<String>,
+
+	/// Origins allowed to call the Rest API from a browser, `*` for any
+	pub api_allowed_origins: Option<Vec<String>>,
+
 	/// Setup the server for tests, testnet or mainnet
 	#[serde(default)]
 	pub chain_type: ChainTypes,
 
+	/// Network definition file overriding consensus parameters of the chain
+	/// type, for private networks only
+	#[serde(default)]
+	pub network_definition: Option<String>,
+
 	/// Automatically run full chain validation during normal block processing?
 	#[serde(default)]
 	pub chain_validation_mode: ChainValidationMode,
@@ -139,6 +165,44 @@ pub struct ServerConfig {
 	/// (To assist testing on solo chains)
 	pub skip_sync_wait: Option<bool>,
 
+	/// Seconds given to in-flight work, like a block being committed, to
+	/// finish when shutting down before exiting regardless
+	#[serde(default = "default_shutdown_drain_timeout_secs")]
+	pub shutdown_drain_timeout_secs: u64,
+
+	/// Megabytes the database map starts at and grows by when it fills up
+	#[serde(default = "default_db_map_increment_mb")]
+	pub db_map_increment_mb: usize,
+
+	/// Megabytes the database map can't grow past
+	#[serde(default = "default_db_map_max_mb")]
+	pub db_map_max_mb: usize,
+
+	/// Whether to back the chain data up before migrating it to a new
+	/// database format
+	#[serde(default)]
+	pub db_backup_before_migration: bool,
+
+	/// Whether to refuse starting when the peers and stratum workers
+	/// configured can't fit the limits on open files and threads
+	#[serde(default = "default_check_resource_limits")]
+	pub check_resource_limits: bool,
+
+	/// Megabytes the orphan blocks and caches of the chain and pool can take
+	/// together before the largest are evicted from, 0 for no limit
+	#[serde(default = "default_memory_cap_mb")]
+	pub memory_cap_mb: usize,
+
+	/// File the server process id is written to, for `grin server stop` and
+	/// `grin server status`. Defaults to grin.pid in the db root.
+	#[serde(default)]
+	pub pid_file: Option<String>,
+
+	/// Directory backups asked for through the API are written under, the
+	/// API refusing them when not set
+	#[serde(default)]
+	pub backup_dir: Option<String>,
+
 	/// Whether to run the TUI
 	/// if enabled, this will disable logging to stdout
 	pub run_tui: Option<bool>,
@@ -166,6 +230,42 @@ pub struct ServerConfig {
 	/// Configuration for the mining daemon
 	#[serde(default)]
 	pub stratum_mining_config: Option<StratumServerConfig>,
+
+	/// Rate limiting and size caps on the Rest API
+	#[serde(default)]
+	pub api_limits: api::ApiLimits,
+
+	/// What the node has to meet to report itself ready at `/readyz`
+	#[serde(default)]
+	pub readiness: api::ReadinessConfig,
+
+	/// URLs notified of chain events
+	#[serde(default)]
+	pub webhooks: WebhookConfig,
+
+	/// When heavy background tasks run
+	#[serde(default)]
+	pub maintenance: MaintenanceConfig,
+}
+
+fn default_shutdown_drain_timeout_secs() -> u64 {
+	30
+}
+
+fn default_check_resource_limits() -> bool {
+	true
+}
+
+fn default_memory_cap_mb() -> usize {
+	256
+}
+
+fn default_db_map_increment_mb() -> usize {
+	store::DEFAULT_MAP_INCREMENT >> 20
+}
+
+fn default_db_map_max_mb() -> usize {
+	store::DEFAULT_MAP_MAX >> 20
 }
 
 impl Default for ServerConfig {
@@ -174,14 +274,31 @@ impl Default for ServerConfig {
 			db_root: "grin_chain".to_string(),
 			api_http_addr: "127.0.0.1:13413".to_string(),
 			api_secret_path: Some(".api_secret".to_string()),
+			api_read_secret_path: Some(".api_read_secret".to_string()),
+			tls_certificate_file: None,
+			tls_certificate_key: None,
+			api_allowed_origins: None,
 			p2p_config: p2p::P2PConfig::default(),
 			dandelion_config: pool::DandelionConfig::default(),
 			stratum_mining_config: Some(StratumServerConfig::default()),
+			api_limits: api::ApiLimits::default(),
+			readiness: api::ReadinessConfig::default(),
+			webhooks: WebhookConfig::default(),
+			maintenance: MaintenanceConfig::default(),
 			chain_type: ChainTypes::default(),
+			network_definition: None,
 			archive_mode: Some(false),
 			chain_validation_mode: ChainValidationMode::default(),
 			pool_config: pool::PoolConfig::default(),
 			skip_sync_wait: Some(false),
+			shutdown_drain_timeout_secs: default_shutdown_drain_timeout_secs(),
+			db_map_increment_mb: default_db_map_increment_mb(),
+			db_map_max_mb: default_db_map_max_mb(),
+			db_backup_before_migration: false,
+			check_resource_limits: default_check_resource_limits(),
+			memory_cap_mb: default_memory_cap_mb(),
+			pid_file: None,
+			backup_dir: None,
 			run_tui: Some(true),
 			use_db_wallet: None,
 			run_test_miner: Some(false),
@@ -190,6 +307,36 @@ impl Default for ServerConfig {
 	}
 }
 
+impl ServerConfig {
+	/// TLS settings of the Rest API, if a certificate is configured
+	pub fn tls_config(&self) -> Result<Option<api::TLSConfig>, Error> {
+		match (
+			self.tls_certificate_file.clone(),
+			self.tls_certificate_key.clone(),
+		) {
+			(Some(file), Some(key)) => Ok(Some(api::TLSConfig::new(file, key))),
+			(Some(_), None) => Err(Error::API(
+				api::ErrorKind::Argument("Private key for certificate is not set".to_owned())
+					.into(),
+			)),
+			(None, Some(_)) => Err(Error::API(
+				api::ErrorKind::Argument("Certificate for private key is not set".to_owned())
+					.into(),
+			)),
+			(None, None) => Ok(None),
+		}
+	}
+
+	/// Base URL of the Rest API, https when served over TLS
+	pub fn api_url(&self) -> String {
+		let scheme = match self.tls_certificate_file {
+			Some(_) => "https",
+			None => "http",
+		};
+		format!("{}://{}", scheme, self.api_http_addr)
+	}
+}
+
 /// Stratum (Mining server) configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StratumServerConfig {
@@ -207,12 +354,77 @@ pub struct StratumServerConfig {
 	/// Minimum difficulty for worker shares
 	pub minimum_share_difficulty: u64,
 
+	/// Shares per minute each worker's difficulty is retargeted toward, 0
+	/// keeping every worker at the minimum share difficulty
+	#[serde(default)]
+	pub vardiff_target_shares_per_minute: u64,
+
+	/// Seconds over which a worker's share rate is measured before
+	/// retargeting its difficulty
+	#[serde(default = "default_vardiff_retarget_secs")]
+	pub vardiff_retarget_secs: u64,
+
+	/// Minimum number of seconds between two jobs for the same block height,
+	/// when rebuilding to include new transactions
+	#[serde(default = "default_job_refresh_interval_secs")]
+	pub job_refresh_interval_secs: u64,
+
+	/// Fee increase, in nanogrins, new pool transactions must bring before a
+	/// job is rebuilt and resent for them
+	#[serde(default = "default_job_min_fee_increase")]
+	pub job_min_fee_increase: u64,
+
+	/// Most workers connected at once, 0 for no limit
+	#[serde(default)]
+	pub max_workers: u32,
+
 	/// Base address to the HTTP wallet receiver
 	pub wallet_listener_url: String,
 
 	/// Attributes the reward to a random private key instead of contacting the
 	/// wallet receiver. Mostly used for tests.
 	pub burn_reward: bool,
+
+	/// TLS certificate file, to accept stratum connections over TLS only
+	#[serde(default)]
+	pub tls_certificate_file: Option<String>,
+
+	/// Private key for the stratum TLS certificate
+	#[serde(default)]
+	pub tls_certificate_key: Option<String>,
+
+	/// Wallet receivers block rewards are spread across by weight, failing
+	/// over to the next one when unreachable. Replaces `wallet_listener_url`
+	/// when set.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub wallet_listeners: Vec<WalletListener>,
+}
+
+/// A wallet receiver getting its share of the block rewards
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WalletListener {
+	/// Base address to the HTTP wallet receiver
+	pub url: String,
+	/// Share of the blocks built rewarding this receiver, relative to the
+	/// others (percentages usually). 0 only uses it to fail over to.
+	#[serde(default = "default_wallet_listener_weight")]
+	pub weight: u32,
+}
+
+fn default_wallet_listener_weight() -> u32 {
+	1
+}
+
+fn default_vardiff_retarget_secs() -> u64 {
+	90
+}
+
+fn default_job_refresh_interval_secs() -> u64 {
+	5
+}
+
+fn default_job_min_fee_increase() -> u64 {
+	1_000_000
 }
 
 impl Default for StratumServerConfig {
@@ -222,12 +434,41 @@ impl Default for StratumServerConfig {
 			burn_reward: false,
 			attempt_time_per_block: 15,
 			minimum_share_difficulty: 1,
+			vardiff_target_shares_per_minute: 0,
+			vardiff_retarget_secs: default_vardiff_retarget_secs(),
+			job_refresh_interval_secs: default_job_refresh_interval_secs(),
+			job_min_fee_increase: default_job_min_fee_increase(),
+			max_workers: 0,
+			tls_certificate_file: None,
+			tls_certificate_key: None,
+			wallet_listeners: vec![],
 			enable_stratum_server: Some(false),
 			stratum_server_addr: Some("127.0.0.1:13416".to_string()),
 		}
 	}
 }
 
+impl StratumServerConfig {
+	/// TLS settings of the stratum server, if a certificate is configured
+	pub fn tls_config(&self) -> Result<Option<api::TLSConfig>, Error> {
+		match (
+			self.tls_certificate_file.clone(),
+			self.tls_certificate_key.clone(),
+		) {
+			(Some(file), Some(key)) => Ok(Some(api::TLSConfig::new(file, key))),
+			(Some(_), None) => Err(Error::API(
+				api::ErrorKind::Argument("Private key for certificate is not set".to_owned())
+					.into(),
+			)),
+			(None, Some(_)) => Err(Error::API(
+				api::ErrorKind::Argument("Certificate for private key is not set".to_owned())
+					.into(),
+			)),
+			(None, None) => Ok(None),
+		}
+	}
+}
+
 /// Various status sync can be in, whether it's fast sync or archival.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[allow(missing_docs)]
@@ -268,10 +509,125 @@ pub enum SyncStatus {
 	},
 }
 
+impl SyncStatus {
+	/// Coarse stage of the sync: waiting to start, downloading headers, the
+	/// full state or blocks, or synced.
+	pub fn stage(&self) -> &'static str {
+		match *self {
+			SyncStatus::Initial | SyncStatus::AwaitingPeers(_) => "waiting",
+			SyncStatus::NoSync => "synced",
+			SyncStatus::HeaderSync { .. } => "header",
+			SyncStatus::TxHashsetDownload { .. }
+			| SyncStatus::TxHashsetSetup
+			| SyncStatus::TxHashsetValidation { .. }
+			| SyncStatus::TxHashsetSave => "state",
+			SyncStatus::BodySync { .. } => "body",
+		}
+	}
+
+	/// What the progress reported for the status counts, if anything
+	pub fn progress_unit(&self) -> Option<&'static str> {
+		match *self {
+			SyncStatus::HeaderSync { .. } | SyncStatus::BodySync { .. } => Some("height"),
+			SyncStatus::TxHashsetDownload { .. } => Some("bytes"),
+			SyncStatus::TxHashsetValidation { .. } => Some("items"),
+			_ => None,
+		}
+	}
+
+	/// The status as reported to API subscribers
+	pub fn progress(&self) -> api::SyncProgress {
+		let (status, progress) = match *self {
+			SyncStatus::Initial => ("initial", None),
+			SyncStatus::NoSync => ("no_sync", None),
+			SyncStatus::AwaitingPeers(_) => ("awaiting_peers", None),
+			SyncStatus::HeaderSync {
+				current_height,
+				highest_height,
+			} => ("header_sync", Some((current_height, highest_height))),
+			SyncStatus::TxHashsetDownload {
+				downloaded_size,
+				total_size,
+				..
+			} => ("txhashset_download", Some((downloaded_size, total_size))),
+			SyncStatus::TxHashsetSetup => ("txhashset_setup", None),
+			SyncStatus::TxHashsetValidation {
+				kernels,
+				kernel_total,
+				rproofs,
+				rproof_total,
+			} => (
+				"txhashset_validation",
+				Some((kernels + rproofs, kernel_total + rproof_total)),
+			),
+			SyncStatus::TxHashsetSave => ("txhashset_save", None),
+			SyncStatus::BodySync {
+				current_height,
+				highest_height,
+			} => ("body_sync", Some((current_height, highest_height))),
+		};
+		api::SyncProgress {
+			status: status.to_owned(),
+			current: progress.map(|p| p.0),
+			total: progress.map(|p| p.1),
+		}
+	}
+}
+
+/// Window over which the progress rate of a sync status is measured
+const SYNC_RATE_WINDOW_SECS: i64 = 60;
+
+/// Progress samples of the current sync status, to tell how fast it goes
+struct ProgressHistory {
+	status: String,
+	started: DateTime<Utc>,
+	samples: VecDeque<(DateTime<Utc>, u64)>,
+}
+
+impl ProgressHistory {
+	fn new() -> ProgressHistory {
+		ProgressHistory {
+			status: String::new(),
+			started: Utc::now(),
+			samples: VecDeque::new(),
+		}
+	}
+
+	fn record(&mut self, status: &SyncStatus) {
+		let now = Utc::now();
+		let progress = status.progress();
+		if progress.status != self.status {
+			self.status = progress.status;
+			self.started = now;
+			self.samples.clear();
+		}
+		if let Some(current) = progress.current {
+			self.samples.push_back((now, current));
+		}
+		let window = Duration::seconds(SYNC_RATE_WINDOW_SECS);
+		while self.samples.len() > 2 && now - self.samples[0].0 > window {
+			self.samples.pop_front();
+		}
+	}
+
+	fn rate(&self) -> Option<f64> {
+		let (t0, v0) = *self.samples.front()?;
+		let (t1, v1) = *self.samples.back()?;
+		let millis = (t1 - t0).num_milliseconds();
+		if millis <= 0 || v1 < v0 {
+			return None;
+		}
+		Some((v1 - v0) as f64 * 1000.0 / millis as f64)
+	}
+}
+
 /// Current sync state. Encapsulates the current SyncStatus.
 pub struct SyncState {
 	current: RwLock<SyncStatus>,
 	sync_error: Arc<RwLock<Option<Error>>>,
+	events: Option<Arc<api::EventHub>>,
+	history: RwLock<ProgressHistory>,
+	peers: RwLock<Vec<SocketAddr>>,
 }
 
 impl SyncState {
@@ -280,6 +636,24 @@ impl SyncState {
 		SyncState {
 			current: RwLock::new(SyncStatus::Initial),
 			sync_error: Arc::new(RwLock::new(None)),
+			events: None,
+			history: RwLock::new(ProgressHistory::new()),
+			peers: RwLock::new(vec![]),
+		}
+	}
+
+	/// A new SyncState reporting its changes to API subscribers
+	pub fn with_events(events: Arc<api::EventHub>) -> SyncState {
+		SyncState {
+			events: Some(events),
+			..SyncState::new()
+		}
+	}
+
+	fn publish(&self, status: SyncStatus) {
+		self.history.write().record(&status);
+		if let Some(ref events) = self.events {
+			events.sync_status(status.progress());
 		}
 	}
 
@@ -304,20 +678,46 @@ impl SyncState {
 
 		debug!("sync_state: sync_status: {:?} -> {:?}", *status, new_status,);
 
+		if status.stage() != new_status.stage() {
+			self.peers.write().clear();
+		}
 		*status = new_status;
+		drop(status);
+		self.publish(new_status);
 	}
 
 	/// Update txhashset downloading progress
 	pub fn update_txhashset_download(&self, new_status: SyncStatus) -> bool {
 		if let SyncStatus::TxHashsetDownload { .. } = new_status {
-			let mut status = self.current.write();
-			*status = new_status;
+			*self.current.write() = new_status;
+			self.publish(new_status);
 			true
 		} else {
 			false
 		}
 	}
 
+	/// When the current sync status was entered
+	pub fn status_started(&self) -> DateTime<Utc> {
+		self.history.read().started
+	}
+
+	/// Progress made per second by the current sync status, over the last
+	/// minute, in the unit of its progress.
+	pub fn progress_rate(&self) -> Option<f64> {
+		self.history.read().rate()
+	}
+
+	/// Records the peers the current sync stage downloads from
+	pub fn set_sync_peers(&self, peers: Vec<SocketAddr>) {
+		*self.peers.write() = peers;
+	}
+
+	/// Peers the current sync stage downloads from
+	pub fn sync_peers(&self) -> Vec<SocketAddr> {
+		self.peers.read().clone()
+	}
+
 	/// Communicate sync error
 	pub fn set_sync_error(&self, error: Error) {
 		*self.sync_error.write() = Some(error);
@@ -376,6 +776,7 @@ impl chain::TxHashsetWriteStatus for SyncState {
 				}
 			}
 		}
+		self.history.write().record(&status);
 	}
 
 	fn on_save(&self) {
@@ -0,0 +1,434 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Webhook notifications. Chain events are POSTed as JSON to the URLs
+//! configured for them, `{"event": "block_accepted", "data": {...}}`, so
+//! payment processors get told instead of polling the API. Each delivery is
+//! retried with an increasing delay until it gets a 2xx answer. Deliveries
+//! run side by side, receivers ordering them by height if they need to.
+//! The last reorgs are also kept, webhooks or not, and served by the node API.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::Utc;
+use hyper::{Body, Request, StatusCode};
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use api::{self, Handler, ResponseFuture};
+use chain;
+use core::core::hash::Hashed;
+use core::core::{Block, BlockHeader};
+use util::{self, Mutex, OneTime};
+
+/// How long a webhook endpoint has to answer a delivery
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// Deliveries pending at once, more are dropped
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Accepted blocks waiting for the worker, more are dropped
+const MAX_QUEUED_BLOCKS: usize = 64;
+
+/// Blocks walked back looking for the point a reorg forked from
+const MAX_REORG_DEPTH: u64 = 1000;
+
+/// Reorgs kept for the recent reorgs endpoint
+const RECENT_REORGS: usize = 100;
+
+/// Path the recent reorgs are served at
+pub const REORGS_PATH: &'static str = "/v1/chain/reorgs";
+
+/// Webhook configuration, the URLs to notify of each event
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WebhookConfig {
+	/// Notified of each block becoming the chain head
+	pub block_accepted: Vec<String>,
+	/// Notified when the chain head switches to another fork
+	pub reorg: Vec<String>,
+	/// Notified when a block confirms one of the watched kernels
+	pub tx_confirmed: Vec<String>,
+	/// Excess commitments, in hex, of the transaction kernels to watch
+	pub watched_kernels: Vec<String>,
+	/// Deliveries are given up after that many failed attempts
+	pub max_attempts: u32,
+	/// Seconds before retrying a failed delivery, doubling each time
+	pub retry_delay_secs: u64,
+}
+
+impl Default for WebhookConfig {
+	fn default() -> WebhookConfig {
+		WebhookConfig {
+			block_accepted: vec![],
+			reorg: vec![],
+			tx_confirmed: vec![],
+			watched_kernels: vec![],
+			max_attempts: 5,
+			retry_delay_secs: 2,
+		}
+	}
+}
+
+/// The events webhooks are called for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+	/// A block became the chain head, with the block header as data
+	BlockAccepted,
+	/// The chain head switched to another fork
+	Reorg,
+	/// A watched kernel got in a block
+	TxConfirmed,
+}
+
+/// What's POSTed to webhooks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload<T> {
+	/// The event this is about
+	pub event: WebhookEvent,
+	/// Its details
+	pub data: T,
+}
+
+/// A reorg, with the head the chain left and the one it moved to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reorg {
+	/// Height of the last block both forks have
+	pub fork_height: u64,
+	/// Hash of the previous head
+	pub old_head: String,
+	/// Height of the previous head
+	pub old_height: u64,
+	/// Hash of the new head
+	pub new_head: String,
+	/// Height of the new head
+	pub new_height: u64,
+	/// Unix time the node switched forks at
+	pub at: i64,
+}
+
+/// A watched kernel confirmed in a block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TxConfirmed {
+	/// Excess commitment of the kernel
+	pub kernel: String,
+	/// Hash of the block holding it
+	pub block_hash: String,
+	/// Height of that block
+	pub height: u64,
+}
+
+/// Notifies the configured webhooks of chain events, told about accepted
+/// blocks by the chain adapter. The blocks are looked into on a worker
+/// thread, so walking back a reorg doesn't hold up the chain.
+pub struct WebhookDispatcher {
+	dispatch: Arc<Dispatch>,
+	chain: OneTime<Weak<chain::Chain>>,
+	last_head: Mutex<Option<BlockHeader>>,
+	accepted_tx: Mutex<Option<mpsc::SyncSender<(Option<BlockHeader>, Block)>>>,
+}
+
+impl WebhookDispatcher {
+	/// A dispatcher for the given configuration
+	pub fn new(config: WebhookConfig) -> WebhookDispatcher {
+		let watched_kernels = config
+			.watched_kernels
+			.iter()
+			.map(|k| k.trim().to_lowercase())
+			.collect();
+		WebhookDispatcher {
+			dispatch: Arc::new(Dispatch {
+				config: WebhookConfig {
+					watched_kernels,
+					..config
+				},
+				recent_reorgs: Mutex::new(VecDeque::new()),
+				in_flight: Arc::new(AtomicUsize::new(0)),
+			}),
+			chain: OneTime::new(),
+			last_head: Mutex::new(None),
+			accepted_tx: Mutex::new(None),
+		}
+	}
+
+	/// Set the chain events are looked up in, should only be called once.
+	/// Starts the worker accepted blocks are handed to.
+	pub fn init(&self, chain: Arc<chain::Chain>) {
+		*self.last_head.lock() = chain.head_header().ok();
+		self.chain.init(Arc::downgrade(&chain));
+
+		let (accepted_tx, accepted_rx) = mpsc::sync_channel(MAX_QUEUED_BLOCKS);
+		let dispatch = self.dispatch.clone();
+		let chain = Arc::downgrade(&chain);
+		let spawned = thread::Builder::new()
+			.name("webhooks".to_string())
+			.spawn(move || {
+				for (old_head, b) in accepted_rx {
+					match chain.upgrade() {
+						Some(chain) => dispatch.block_accepted(&chain, old_head, &b),
+						None => break,
+					}
+				}
+			});
+		match spawned {
+			Ok(_) => *self.accepted_tx.lock() = Some(accepted_tx),
+			Err(e) => error!("webhooks: could not start worker: {}", e),
+		}
+	}
+
+	/// Whether any webhook is configured
+	fn enabled(&self) -> bool {
+		let config = &self.dispatch.config;
+		!config.block_accepted.is_empty()
+			|| !config.reorg.is_empty()
+			|| !config.tx_confirmed.is_empty()
+	}
+
+	/// The last reorgs seen, most recent first
+	pub fn recent_reorgs(&self) -> Vec<Reorg> {
+		self.dispatch
+			.recent_reorgs
+			.lock()
+			.iter()
+			.rev()
+			.cloned()
+			.collect()
+	}
+
+	/// A block was accepted in the chain. Blocks on a fork are only reported
+	/// once the fork takes over, as a reorg. Without webhooks, only the
+	/// blocks that don't extend the last head are looked into, for reorgs.
+	pub fn block_accepted(&self, b: &Block) {
+		let chain = match self.chain.borrow().upgrade() {
+			Some(chain) => chain,
+			None => return,
+		};
+		match chain.head() {
+			Ok(ref head) if head.last_block_h == b.hash() => {}
+			_ => return,
+		}
+		let old_head = self.last_head.lock().replace(b.header.clone());
+		let extends = match old_head {
+			Some(ref old) => old.hash() == b.header.prev_hash,
+			None => true,
+		};
+		if !self.enabled() && extends {
+			return;
+		}
+
+		if let Some(ref accepted_tx) = *self.accepted_tx.lock() {
+			if let Err(mpsc::TrySendError::Full(_)) = accepted_tx.try_send((old_head, b.clone())) {
+				warn!(
+					"webhooks: too many blocks queued, {} at {} dropped",
+					b.hash(),
+					b.header.height
+				);
+			}
+		}
+	}
+
+	/// POSTs an event to the webhooks configured for it, in the background
+	pub fn notify<T: Serialize>(&self, event: WebhookEvent, data: &T) {
+		self.dispatch.notify(event, data)
+	}
+}
+
+/// What the worker looks into accepted blocks and delivers events with
+struct Dispatch {
+	config: WebhookConfig,
+	recent_reorgs: Mutex<VecDeque<Reorg>>,
+	in_flight: Arc<AtomicUsize>,
+}
+
+impl Dispatch {
+	// Notifies of a block that became the chain head over the old one, of the
+	// reorg first if the old one isn't on the chain anymore
+	fn block_accepted(&self, chain: &chain::Chain, old_head: Option<BlockHeader>, b: &Block) {
+		// the blocks that just got on the chain, more than this one in a reorg
+		let mut new_blocks = vec![];
+		if let Some(old) = old_head {
+			if old.hash() != b.header.prev_hash && chain.is_on_current_chain(&old).is_err() {
+				let fork = fork_point(chain, &old);
+				let reorg = Reorg {
+					fork_height: fork.height,
+					old_head: old.hash().to_hex(),
+					old_height: old.height,
+					new_head: b.hash().to_hex(),
+					new_height: b.header.height,
+					at: Utc::now().timestamp(),
+				};
+				info!(
+					"Chain head switched forks at {}, from {} ({}) to {} ({})",
+					reorg.fork_height,
+					reorg.old_head,
+					reorg.old_height,
+					reorg.new_head,
+					reorg.new_height
+				);
+				self.notify(WebhookEvent::Reorg, &reorg);
+				let mut recent = self.recent_reorgs.lock();
+				if recent.len() >= RECENT_REORGS {
+					recent.pop_front();
+				}
+				recent.push_back(reorg);
+				if !self.config.tx_confirmed.is_empty() {
+					for height in fork.height + 1..b.header.height {
+						match chain
+							.get_header_by_height(height)
+							.and_then(|h| chain.get_block(&h.hash()))
+						{
+							Ok(block) => new_blocks.push(block),
+							Err(e) => error!("webhooks: can't read block at {}: {:?}", height, e),
+						}
+					}
+				}
+			}
+		}
+
+		self.notify(
+			WebhookEvent::BlockAccepted,
+			&api::BlockHeaderPrintable::from_header(&b.header),
+		);
+		if self.config.tx_confirmed.is_empty() || self.config.watched_kernels.is_empty() {
+			return;
+		}
+		for block in new_blocks.iter().chain(Some(b)) {
+			for kernel in block.kernels() {
+				let excess = util::to_hex(kernel.excess.0.to_vec());
+				if self.config.watched_kernels.contains(&excess) {
+					self.notify(
+						WebhookEvent::TxConfirmed,
+						&TxConfirmed {
+							kernel: excess,
+							block_hash: block.hash().to_hex(),
+							height: block.header.height,
+						},
+					);
+				}
+			}
+		}
+	}
+
+	/// POSTs an event to the webhooks configured for it, in the background
+	fn notify<T: Serialize>(&self, event: WebhookEvent, data: &T) {
+		let urls = match event {
+			WebhookEvent::BlockAccepted => &self.config.block_accepted,
+			WebhookEvent::Reorg => &self.config.reorg,
+			WebhookEvent::TxConfirmed => &self.config.tx_confirmed,
+		};
+		if urls.is_empty() {
+			return;
+		}
+		let payload = match serde_json::to_value(WebhookPayload { event, data }) {
+			Ok(payload) => payload,
+			Err(e) => {
+				error!("webhooks: could not serialize {:?} event: {}", event, e);
+				return;
+			}
+		};
+		for url in urls {
+			if self.in_flight.fetch_add(1, Ordering::SeqCst) >= MAX_IN_FLIGHT {
+				self.in_flight.fetch_sub(1, Ordering::SeqCst);
+				warn!(
+					"webhooks: too many pending deliveries, {:?} to {} dropped",
+					event, url
+				);
+				continue;
+			}
+			self.deliver(url.clone(), payload.clone());
+		}
+	}
+
+	fn deliver(&self, url: String, payload: Value) {
+		let max_attempts = self.config.max_attempts.max(1);
+		let mut delay = Duration::from_secs(self.config.retry_delay_secs);
+		let in_flight = self.in_flight.clone();
+		let spawned = thread::Builder::new()
+			.name("webhook".to_string())
+			.spawn(move || {
+				for attempt in 1..max_attempts + 1 {
+					let res = api::client::post_no_ret_timeout(
+						&url,
+						None,
+						&payload,
+						Duration::from_secs(REQUEST_TIMEOUT_SECS),
+					);
+					match res {
+						Ok(()) => break,
+						Err(e) if attempt == max_attempts => {
+							warn!(
+								"webhooks: giving up on {} after {} attempts: {}",
+								url, attempt, e
+							);
+						}
+						Err(e) => {
+							debug!("webhooks: delivery to {} failed, retrying: {}", url, e);
+							thread::sleep(delay);
+							delay *= 2;
+						}
+					}
+				}
+				in_flight.fetch_sub(1, Ordering::SeqCst);
+			});
+		if spawned.is_err() {
+			self.in_flight.fetch_sub(1, Ordering::SeqCst);
+		}
+	}
+}
+
+/// Walks back from a header left by a reorg to the last one still on the
+/// chain
+fn fork_point(chain: &chain::Chain, header: &BlockHeader) -> BlockHeader {
+	let mut current = header.clone();
+	for _ in 0..MAX_REORG_DEPTH {
+		if chain.is_on_current_chain(&current).is_ok() {
+			break;
+		}
+		current = match chain.get_block_header(&current.prev_hash) {
+			Ok(prev) => prev,
+			Err(_) => break,
+		};
+	}
+	current
+}
+
+/// The last reorgs the node went through, most recent first.
+/// GET /v1/chain/reorgs
+pub struct ReorgsHandler {
+	webhooks: Weak<WebhookDispatcher>,
+}
+
+impl ReorgsHandler {
+	/// The handler, as added to the node API routes
+	pub fn route(webhooks: &Arc<WebhookDispatcher>) -> (&'static str, api::HandlerObj) {
+		let handler = ReorgsHandler {
+			webhooks: Arc::downgrade(webhooks),
+		};
+		(REORGS_PATH, Arc::new(handler))
+	}
+}
+
+impl Handler for ReorgsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		match self.webhooks.upgrade() {
+			Some(webhooks) => api::json_response(&webhooks.recent_reorgs()),
+			None => api::response(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+		}
+	}
+}
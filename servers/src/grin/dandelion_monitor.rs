@@ -34,7 +34,7 @@ use pool::{DandelionConfig, PoolEntryState, PoolError, TransactionPool, TxSource
 /// the transaction will be sent in fluff phase (to multiple peers) instead of
 /// sending only to the peer relay.
 pub fn monitor_transactions(
-	dandelion_config: DandelionConfig,
+	dandelion_config: Arc<RwLock<DandelionConfig>>,
 	tx_pool: Arc<RwLock<TransactionPool>>,
 	verifier_cache: Arc<RwLock<VerifierCache>>,
 	stop: Arc<AtomicBool>,
@@ -49,8 +49,11 @@ pub fn monitor_transactions(
 					break;
 				}
 
+				// Read on every run, to pick up configuration reloads
+				let config = dandelion_config.read().clone();
+
 				// This is the patience timer, we loop every n secs.
-				let patience_secs = dandelion_config.patience_secs.unwrap();
+				let patience_secs = config.patience_secs.unwrap();
 				thread::sleep(Duration::from_secs(patience_secs));
 
 				// Step 1: find all "ToStem" entries in stempool from last run.
@@ -68,15 +71,18 @@ pub fn monitor_transactions(
 				}
 
 				// Step 3: now find all "Fresh" entries in stempool since last run.
-				// Coin flip for each (90/10) and label them as either "ToStem" or "ToFluff".
+				// Once the oldest has waited the aggregation period, coin flip (90/10) and
+				// label them all as either "ToStem" or "ToFluff", to be aggregated together.
 				// We will process these in the next run (waiting patience secs).
-				if process_fresh_entries(dandelion_config.clone(), tx_pool.clone()).is_err() {
+				if process_fresh_entries(config.clone(), tx_pool.clone()).is_err() {
 					error!("dand_mon: Problem processing fresh pool entries.");
 				}
 
 				// Step 4: now find all expired entries based on embargo timer.
-				if process_expired_entries(dandelion_config.clone(), tx_pool.clone()).is_err() {
-					error!("dand_mon: Problem processing fresh pool entries.");
+				// Aggregate the ones still valid and fluff them.
+				if process_expired_entries(config, tx_pool.clone(), verifier_cache.clone()).is_err()
+				{
+					error!("dand_mon: Problem processing expired pool entries.");
 				}
 			}
 		});
@@ -175,7 +181,7 @@ fn process_fresh_entries(
 ) -> Result<(), PoolError> {
 	let mut tx_pool = tx_pool.write();
 
-	let mut rng = thread_rng();
+	let cutoff = Utc::now().timestamp() - dandelion_config.aggregation_secs() as i64;
 
 	let fresh_entries = &mut tx_pool
 		.stempool
@@ -184,19 +190,23 @@ fn process_fresh_entries(
 		.filter(|x| x.state == PoolEntryState::Fresh)
 		.collect::<Vec<_>>();
 
-	if fresh_entries.len() > 0 {
+	// Fresh entries are held until the oldest one has waited the whole
+	// aggregation period, then all go the same way so they get aggregated
+	// into a single tx, whether stemmed or fluffed.
+	if fresh_entries.iter().any(|x| x.tx_at.timestamp() <= cutoff) {
 		debug!(
 			"dand_mon: Found {} fresh entries in stempool.",
 			fresh_entries.len()
 		);
 
+		let random = thread_rng().gen_range(0, 101);
+		let state = if random <= dandelion_config.stem_probability.unwrap() {
+			PoolEntryState::ToStem
+		} else {
+			PoolEntryState::ToFluff
+		};
 		for x in &mut fresh_entries.iter_mut() {
-			let random = rng.gen_range(0, 101);
-			if random <= dandelion_config.stem_probability.unwrap() {
-				x.state = PoolEntryState::ToStem;
-			} else {
-				x.state = PoolEntryState::ToFluff;
-			}
+			x.state = state;
 		}
 	}
 	Ok(())
@@ -205,12 +215,14 @@ fn process_fresh_entries(
 fn process_expired_entries(
 	dandelion_config: DandelionConfig,
 	tx_pool: Arc<RwLock<TransactionPool>>,
+	verifier_cache: Arc<RwLock<VerifierCache>>,
 ) -> Result<(), PoolError> {
 	let now = Utc::now().timestamp();
-	let embargo_sec = dandelion_config.embargo_secs.unwrap() + thread_rng().gen_range(0, 31);
+	let jitter = thread_rng().gen_range(0, dandelion_config.embargo_jitter_secs() + 1);
+	let embargo_sec = dandelion_config.embargo_secs.unwrap() + jitter;
 	let cutoff = now - embargo_sec as i64;
 
-	let mut expired_entries = vec![];
+	let mut expired_txs = vec![];
 	{
 		let tx_pool = tx_pool.read();
 		for entry in tx_pool
@@ -220,28 +232,41 @@ fn process_expired_entries(
 			.filter(|x| x.tx_at.timestamp() < cutoff)
 		{
 			debug!("dand_mon: Embargo timer expired for {:?}", entry.tx.hash());
-			expired_entries.push(entry.clone());
+			expired_txs.push(entry.tx.clone());
 		}
 	}
 
-	if expired_entries.len() > 0 {
-		debug!("dand_mon: Found {} expired txs.", expired_entries.len());
-
-		{
-			let mut tx_pool = tx_pool.write();
-			let header = tx_pool.chain_head()?;
-
-			for entry in expired_entries {
-				let src = TxSource {
-					debug_name: "embargo_expired".to_string(),
-					identifier: "?.?.?.?".to_string(),
-				};
-				match tx_pool.add_to_pool(src, entry.tx, false, &header) {
-					Ok(_) => debug!("dand_mon: embargo expired, fluffed tx successfully."),
-					Err(e) => debug!("dand_mon: Failed to fluff expired tx - {:?}", e),
-				};
-			}
+	if expired_txs.len() > 0 {
+		debug!("dand_mon: Found {} expired txs.", expired_txs.len());
+
+		let mut tx_pool = tx_pool.write();
+		let header = tx_pool.chain_head()?;
+
+		// those seen on the network since, now in the txpool, don't validate
+		// anymore and are left out of the aggregation
+		let txpool_tx = tx_pool.txpool.aggregate_transaction()?;
+		let expired_txs =
+			tx_pool
+				.stempool
+				.select_valid_transactions(expired_txs, txpool_tx, &header)?;
+		if expired_txs.is_empty() {
+			return Ok(());
 		}
+		tx_pool
+			.stempool
+			.transition_to_state(&expired_txs, PoolEntryState::Fluffed);
+
+		let agg_tx = transaction::aggregate(expired_txs)?;
+		agg_tx.validate(verifier_cache.clone())?;
+
+		let src = TxSource {
+			debug_name: "embargo_expired".to_string(),
+			identifier: "?.?.?.?".to_string(),
+		};
+		match tx_pool.add_to_pool(src, agg_tx, false, &header) {
+			Ok(_) => debug!("dand_mon: embargo expired, fluffed tx successfully."),
+			Err(e) => debug!("dand_mon: Failed to fluff expired tx - {:?}", e),
+		};
 	}
 	Ok(())
 }
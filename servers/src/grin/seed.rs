@@ -17,14 +17,17 @@
 //! configurable with either no peers, a user-defined list or a preset
 //! list of DNS records (the default).
 
-use chrono::prelude::Utc;
+use chrono::prelude::{DateTime, Utc};
 use chrono::{Duration, MIN_DATE};
 use rand::{thread_rng, Rng};
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc};
 use std::{cmp, io, str, thread, time};
+use util::RwLock;
 
+use common::types::SyncState;
+use core::global;
 use p2p;
 use p2p::ChainAdapter;
 use pool::DandelionConfig;
@@ -34,12 +37,30 @@ const DNS_SEEDS: &'static [&'static str] = &[
 	"t4.seed.grin-tech.org", // igno.peverell@protonmail.com
 ];
 
+// Long-lived community nodes of each network, only tried when no DNS seed
+// resolves and we know no peer yet. Rotated on each release, a network
+// entry in the fallback_peers of the config replaces its list.
+const MAINNET_FALLBACK_PEERS: &'static [&'static str] = &[
+	"mainnet.seed.grin.icu:3414",
+	"mainnet.seed.713.mw:3414",
+	"mainnet.seed.grin.lesceller.com:3414",
+	"grinseed.yeastplume.org:3414",
+];
+const FLOONET_FALLBACK_PEERS: &'static [&'static str] = &[
+	"floonet.seed.grin.icu:13414",
+	"floonet.seed.713.mw:13414",
+	"floonet.seed.grin.lesceller.com:13414",
+	"floonet.seed.grin.prokapi.com:13414",
+];
+
 pub fn connect_and_monitor(
 	p2p_server: Arc<p2p::Server>,
 	capabilities: p2p::Capabilities,
-	dandelion_config: DandelionConfig,
+	dandelion_config: Arc<RwLock<DandelionConfig>>,
 	seed_list: Box<Fn() -> Vec<SocketAddr> + Send>,
+	fallback_peers: Box<Fn() -> Vec<SocketAddr> + Send>,
 	preferred_peers: Option<Vec<SocketAddr>>,
+	sync_state: Arc<SyncState>,
 	stop: Arc<AtomicBool>,
 ) {
 	let _ = thread::Builder::new()
@@ -56,22 +77,16 @@ pub fn connect_and_monitor(
 				peers.clone(),
 				tx.clone(),
 				seed_list,
+				fallback_peers,
 				preferred_peers.clone(),
 			);
 
 			let mut prev = MIN_DATE.and_hms(0, 0, 0);
-			let mut prev_expire_check = MIN_DATE.and_hms(0, 0, 0);
 			let mut prev_ping = Utc::now();
+			let mut next_rotation = rotation_time(&peers.config());
 			let mut start_attempt = 0;
 
 			while !stop.load(Ordering::Relaxed) {
-				// Check for and remove expired peers from the storage
-				if Utc::now() - prev_expire_check > Duration::hours(1) {
-					peers.remove_expired();
-
-					prev_expire_check = Utc::now();
-				}
-
 				// make several attempts to get peers as quick as possible
 				// with exponential backoff
 				if Utc::now() - prev > Duration::seconds(cmp::min(20, 1 << start_attempt)) {
@@ -81,13 +96,14 @@ pub fn connect_and_monitor(
 					// monitor additional peers if we need to add more
 					monitor_peers(
 						peers.clone(),
-						p2p_server.config.clone(),
+						peers.config(),
 						capabilities,
+						dial_capabilities(&sync_state),
 						tx.clone(),
 						preferred_peers.clone(),
 					);
 
-					update_dandelion_relay(peers.clone(), dandelion_config.clone());
+					update_dandelion_relay(peers.clone(), dandelion_config.read().clone());
 
 					prev = Utc::now();
 					start_attempt = cmp::min(6, start_attempt + 1);
@@ -101,20 +117,56 @@ pub fn connect_and_monitor(
 					prev_ping = Utc::now();
 				}
 
+				// every so often replace some outbound peers with peers from
+				// less represented networks, once we're synced and have enough
+				if next_rotation.map(|t| Utc::now() > t).unwrap_or(false) {
+					let config = peers.config();
+					if !sync_state.is_syncing()
+						&& peers.outgoing_connected_peers().len()
+							>= config.peer_min_preferred_count() as usize
+					{
+						let keep = preferred_peers.clone().unwrap_or(vec![]);
+						let rotations =
+							peers.rotate_outbound(dial_capabilities(&sync_state), &keep);
+						rotate_peers(peers.clone(), p2p_server.clone(), capabilities, rotations);
+					}
+					next_rotation = rotation_time(&config);
+				}
+
 				thread::sleep(time::Duration::from_secs(1));
 			}
 		});
 }
 
+// When to next rotate some of our outbound peers, at a random time around
+// the configured interval so it can't be predicted, never if disabled
+fn rotation_time(config: &p2p::P2PConfig) -> Option<DateTime<Utc>> {
+	config.rotation_interval().map(|interval| {
+		let secs = interval.as_secs() as i64;
+		Utc::now() + Duration::seconds(thread_rng().gen_range(secs / 2, secs * 3 / 2 + 1))
+	})
+}
+
 fn monitor_peers(
 	peers: Arc<p2p::Peers>,
 	config: p2p::P2PConfig,
 	capabilities: p2p::Capabilities,
+	dial_capabilities: p2p::Capabilities,
 	tx: mpsc::Sender<SocketAddr>,
 	preferred_peers_list: Option<Vec<SocketAddr>>,
 ) {
 	// regularly check if we need to acquire more peers  and if so, gets
 	// them from db
+	for x in peers.unban_expired() {
+		debug!(
+			"monitor_peers: unbanned {} after {} seconds ({:?}, {})",
+			x.addr,
+			Utc::now().timestamp() - x.last_banned,
+			x.ban_reason,
+			x.ban_note,
+		);
+	}
+
 	let total_count = peers.all_peers().len();
 	let mut healthy_count = 0;
 	let mut banned_count = 0;
@@ -122,19 +174,7 @@ fn monitor_peers(
 
 	for x in peers.all_peers() {
 		match x.flags {
-			p2p::State::Banned => {
-				let interval = Utc::now().timestamp() - x.last_banned;
-				// Unban peer
-				if interval >= config.ban_window() {
-					peers.unban_peer(&x.addr);
-					debug!(
-						"monitor_peers: unbanned {} after {} seconds",
-						x.addr, interval
-					);
-				} else {
-					banned_count += 1;
-				}
-			}
+			p2p::State::Banned => banned_count += 1,
 			p2p::State::Healthy => healthy_count += 1,
 			p2p::State::Defunct => defuncts.push(x),
 		}
@@ -198,9 +238,9 @@ fn monitor_peers(
 
 	// find some peers from our db
 	// and queue them up for a connection attempt
-	let new_peers = peers.find_peers(
+	let new_peers = peers.find_dial_candidates(
 		p2p::State::Healthy,
-		p2p::Capabilities::UNKNOWN,
+		dial_capabilities,
 		config.peer_max_count() as usize,
 	);
 	for p in new_peers.iter().filter(|p| !peers.is_known(&p.addr)) {
@@ -212,6 +252,16 @@ fn monitor_peers(
 	}
 }
 
+// Capabilities the peers we dial should have, the whole history while
+// syncing so they can serve the chain state, anything otherwise
+fn dial_capabilities(sync_state: &SyncState) -> p2p::Capabilities {
+	if sync_state.is_syncing() {
+		p2p::Capabilities::FULL_NODE
+	} else {
+		p2p::Capabilities::UNKNOWN
+	}
+}
+
 fn update_dandelion_relay(peers: Arc<p2p::Peers>, dandelion_config: DandelionConfig) {
 	// Dandelion Relay Updater
 	let dandelion_relay = peers.get_dandelion_relay();
@@ -230,11 +280,12 @@ fn update_dandelion_relay(peers: Arc<p2p::Peers>, dandelion_config: DandelionCon
 }
 
 // Check if we have any pre-existing peer in db. If so, start with those,
-// otherwise use the seeds provided.
+// otherwise use the seeds provided, and as a last resort the fallback peers.
 fn connect_to_seeds_and_preferred_peers(
 	peers: Arc<p2p::Peers>,
 	tx: mpsc::Sender<SocketAddr>,
 	seed_list: Box<Fn() -> Vec<SocketAddr>>,
+	fallback_peers: Box<Fn() -> Vec<SocketAddr>>,
 	peers_preferred_list: Option<Vec<SocketAddr>>,
 ) {
 	// check if we have some peers in db
@@ -247,6 +298,19 @@ fn connect_to_seeds_and_preferred_peers(
 		seed_list()
 	};
 
+	// nothing known and no seed reachable (offline DNS, captive network...)
+	if peer_addrs.is_empty() && peers.is_empty() {
+		let mut fallback_peers = fallback_peers();
+		if !fallback_peers.is_empty() {
+			warn!(
+				"No seed resolved and no known peer, trying {} fallback peers.",
+				fallback_peers.len()
+			);
+			thread_rng().shuffle(&mut fallback_peers);
+			peer_addrs = fallback_peers;
+		}
+	}
+
 	// If we have preferred peers add them to the connection
 	match peers_preferred_list {
 		Some(mut peers_preferred) => peer_addrs.append(&mut peers_preferred),
@@ -272,7 +336,7 @@ fn listen_for_addrs(
 	capab: p2p::Capabilities,
 	rx: &mpsc::Receiver<SocketAddr>,
 ) {
-	if peers.peer_count() >= p2p.config.peer_max_count() {
+	if peers.peer_count() >= peers.config().peer_max_count() {
 		// clean the rx messages to avoid accumulating
 		for _ in rx.try_iter() {}
 		return;
@@ -314,6 +378,36 @@ fn listen_for_addrs(
 	}
 }
 
+// Dials the peers rotated in, dropping the peer each one replaces once it's
+// connected, so we never run short of outbound peers while rotating
+fn rotate_peers(
+	peers: Arc<p2p::Peers>,
+	p2p: Arc<p2p::Server>,
+	capab: p2p::Capabilities,
+	rotations: Vec<(SocketAddr, SocketAddr)>,
+) {
+	for (drop, dial) in rotations {
+		let peers_c = peers.clone();
+		let p2p_c = p2p.clone();
+		let _ = thread::Builder::new()
+			.name("peer_rotate".to_string())
+			.spawn(move || {
+				debug!("rotate_peers: rotating in {} for {}", dial, drop);
+				match p2p_c.connect(&dial) {
+					Ok(p) => {
+						let _ = p.send_peer_request(capab);
+						let _ = peers_c.update_state(dial, p2p::State::Healthy);
+						peers_c.drop_rotated(&drop);
+					}
+					Err(e) => {
+						debug!("rotate_peers: keeping {}, {} failed: {:?}", drop, dial, e);
+						let _ = peers_c.update_state(dial, p2p::State::Defunct);
+					}
+				}
+			});
+	}
+}
+
 pub fn dns_seeds() -> Box<Fn() -> Vec<SocketAddr> + Send> {
 	Box::new(|| {
 		let mut addresses: Vec<SocketAddr> = vec![];
@@ -337,6 +431,42 @@ pub fn dns_seeds() -> Box<Fn() -> Vec<SocketAddr> + Send> {
 	})
 }
 
+/// Peers to fall back on when DNS seeding fails, the ones configured for the
+/// network we run on if any, the compiled in ones otherwise
+pub fn fallback_peers(
+	p2p_config: &p2p::P2PConfig,
+	chain_type: &global::ChainTypes,
+) -> Box<Fn() -> Vec<SocketAddr> + Send> {
+	let peers = match p2p_config.fallback_peers(&chain_type.shortname()) {
+		Some(peers) => peers,
+		None => default_fallback_peers(chain_type)
+			.iter()
+			.map(|s| s.to_string())
+			.collect(),
+	};
+	Box::new(move || {
+		let mut addresses: Vec<SocketAddr> = vec![];
+		for peer in &peers {
+			// literal addresses resolve without any lookup
+			match peer.to_socket_addrs() {
+				Ok(addrs) => addresses.extend(addrs),
+				Err(e) => warn!("Ignoring invalid fallback peer {}: {:?}", peer, e),
+			}
+		}
+		addresses.sort();
+		addresses.dedup();
+		addresses
+	})
+}
+
+fn default_fallback_peers(chain_type: &global::ChainTypes) -> &'static [&'static str] {
+	match *chain_type {
+		global::ChainTypes::Mainnet => MAINNET_FALLBACK_PEERS,
+		global::ChainTypes::Testnet4 => FLOONET_FALLBACK_PEERS,
+		_ => &[],
+	}
+}
+
 /// Convenience function when the seed list is immediately known. Mostly used
 /// for tests.
 pub fn predefined_seeds(addrs_str: Vec<String>) -> Box<Fn() -> Vec<SocketAddr> + Send> {
@@ -16,28 +16,43 @@
 //! the peer-to-peer server, the blockchain and the transaction pool) and acts
 //! as a facade.
 
+use std::fs;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Weak};
 use std::{thread, time};
-use util::RwLock;
+use util::{MemoryBudget, Mutex, RwLock};
 
 use api;
 use chain;
 use common::adapters::{
 	ChainToPoolAndNetAdapter, NetToChainAdapter, PoolToChainAdapter, PoolToNetAdapter,
 };
-use common::stats::{DiffBlock, DiffStats, PeerStats, ServerStateInfo, ServerStats};
+use common::backup::BackupHandler;
+use common::reload::{ConfigLoader, ConfigReloadHandler, ConfigReloader, ReloadReport};
+use common::resources;
+use common::scheduler::Scheduler;
+use common::stats::{
+	BanStats, DiffBlock, DiffStats, PeerStats, ServerStateInfo, ServerStats, StratumStats,
+};
 use common::types::{Error, ServerConfig, StratumServerConfig, SyncState, SyncStatus};
+use common::webhooks::{ReorgsHandler, WebhookDispatcher};
 use core::core::verifier_cache::{LruVerifierCache, VerifierCache};
+use core::core::Block;
 use core::{consensus, genesis, global, pow};
+use grin::sync::sync_api::{SyncInfo, SyncStatusHandler};
 use grin::{dandelion_monitor, seed, sync};
+use mining::block_template::BlockTemplateHandler;
+use mining::stratum_api::StratumWorkersHandler;
 use mining::stratumserver;
 use mining::test_miner::Miner;
 use p2p;
 use pool;
 use store;
+use util;
 use util::file::get_first_line;
+use util::LoggingConfig;
 
 /// Grin server holding internal structures.
 pub struct Server {
@@ -54,10 +69,16 @@ pub struct Server {
 	verifier_cache: Arc<RwLock<VerifierCache>>,
 	/// Whether we're currently syncing
 	sync_state: Arc<SyncState>,
+	/// Stratum config, with the mining policy updated on config reloads
+	stratum_config: Arc<RwLock<StratumServerConfig>>,
+	/// Applies configuration changes while running
+	config_reloader: Arc<ConfigReloader>,
 	/// To be passed around to collect stats and info
 	state_info: ServerStateInfo,
 	/// Stop flag
 	pub stop: Arc<AtomicBool>,
+	/// Whether the server is done stopping, held while it does
+	stopped: Mutex<bool>,
 }
 
 impl Server {
@@ -96,6 +117,8 @@ impl Server {
 		loop {
 			thread::sleep(time::Duration::from_secs(1));
 			if serv.stop.load(Ordering::Relaxed) {
+				// returns once done, whoever began stopping the server
+				serv.stop();
 				return Ok(());
 			}
 		}
@@ -110,41 +133,69 @@ impl Server {
 			Some(b) => b,
 		};
 
+		// Checked first, not to get halfway through starting up otherwise
+		let tls_config = config.tls_config()?;
+		if let Some(ref tls_config) = tls_config {
+			tls_config.build_server_config()?;
+		}
+		config.maintenance.validate()?;
+		config.dandelion_config.validate()?;
+		if config.check_resource_limits {
+			resources::check_limits(&config, &util::process_limits())?;
+		}
+
 		let stop = Arc::new(AtomicBool::new(false));
 
 		// Shared cache for verification results.
 		// We cache rangeproof verification and kernel signature verification.
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
+		// Memory shared by the orphan pool and caches of the chain and pool
+		let memory_budget = Arc::new(MemoryBudget::new(config.memory_cap_mb << 20));
+
+		// Chain, pool and sync events, for API subscribers
+		let events = Arc::new(api::EventHub::new());
+
 		let pool_adapter = Arc::new(PoolToChainAdapter::new());
-		let pool_net_adapter = Arc::new(PoolToNetAdapter::new());
+		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(events.clone()));
 		let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
 			config.pool_config.clone(),
 			pool_adapter.clone(),
 			verifier_cache.clone(),
 			pool_net_adapter.clone(),
+			memory_budget.clone(),
 		)));
 
-		let sync_state = Arc::new(SyncState::new());
+		let sync_state = Arc::new(SyncState::with_events(events.clone()));
+
+		let webhooks = Arc::new(WebhookDispatcher::new(config.webhooks.clone()));
 
 		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(
 			sync_state.clone(),
 			tx_pool.clone(),
+			events.clone(),
+			webhooks.clone(),
 		));
 
-		let genesis = match config.chain_type {
-			global::ChainTypes::Testnet1 => genesis::genesis_testnet1(),
-			global::ChainTypes::Testnet2 => genesis::genesis_testnet2(),
-			global::ChainTypes::Testnet3 => genesis::genesis_testnet3(),
-			global::ChainTypes::Testnet4 => genesis::genesis_testnet4(),
-			global::ChainTypes::AutomatedTesting => genesis::genesis_dev(),
-			global::ChainTypes::UserTesting => genesis::genesis_dev(),
-			global::ChainTypes::Mainnet => genesis::genesis_testnet2(), //TODO: Fix, obviously
-		};
+		let genesis = genesis_block(&config.chain_type);
 
 		info!("Starting server, genesis block: {}", genesis.hash());
 
+		check_network(&config.db_root, &config.chain_type)?;
+
+		if config.db_map_increment_mb == 0 {
+			return Err(Error::Config(
+				"db_map_increment_mb must be at least 1".to_string(),
+			));
+		}
+		store::set_map_growth(config.db_map_increment_mb << 20, config.db_map_max_mb << 20);
+
 		let db_env = Arc::new(store::new_env(config.db_root.clone()));
+		chain::migration::migrate_db(
+			&config.db_root,
+			db_env.clone(),
+			config.db_backup_before_migration,
+		)?;
 		let shared_chain = Arc::new(chain::Chain::init(
 			config.db_root.clone(),
 			db_env,
@@ -153,19 +204,26 @@ impl Server {
 			pow::verify_size,
 			verifier_cache.clone(),
 			archive_mode,
+			memory_budget.clone(),
 		)?);
 
 		pool_adapter.set_chain(shared_chain.clone());
+		events.init(shared_chain.clone());
+		webhooks.init(shared_chain.clone());
 
 		let net_adapter = Arc::new(NetToChainAdapter::new(
 			sync_state.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
 			verifier_cache.clone(),
+			events.clone(),
 			config.clone(),
 		));
 
-		let peer_db_env = Arc::new(store::new_named_env(config.db_root.clone(), "peer".into()));
+		let peer_db_env = Arc::new(store::new_named_env(
+			config.db_root.clone(),
+			PEER_DB_NAME.into(),
+		));
 		let p2p_server = Arc::new(p2p::Server::new(
 			peer_db_env,
 			config.p2p_config.capabilities,
@@ -178,6 +236,19 @@ impl Server {
 		pool_net_adapter.init(p2p_server.peers.clone());
 		net_adapter.init(p2p_server.peers.clone());
 
+		// configuration shared with the parts of the server it can be reloaded on
+		let dandelion_config = Arc::new(RwLock::new(config.dandelion_config.clone()));
+		let stratum_config = Arc::new(RwLock::new(
+			config.stratum_mining_config.clone().unwrap_or_default(),
+		));
+		let config_reloader = Arc::new(ConfigReloader::new(
+			config.clone(),
+			p2p_server.peers.clone(),
+			tx_pool.clone(),
+			dandelion_config.clone(),
+			stratum_config.clone(),
+		));
+
 		if config.p2p_config.seeding_type.clone() != p2p::Seeding::Programmatic {
 			let seeder = match config.p2p_config.seeding_type.clone() {
 				p2p::Seeding::None => {
@@ -191,6 +262,14 @@ impl Server {
 				_ => unreachable!(),
 			};
 
+			// only DNS seeding, the default, falls back on long-lived peers
+			let fallback_peers = match config.p2p_config.seeding_type {
+				p2p::Seeding::DNSSeed => {
+					seed::fallback_peers(&config.p2p_config, &config.chain_type)
+				}
+				_ => seed::predefined_seeds(vec![]),
+			};
+
 			let peers_preferred = match config.p2p_config.peers_preferred.clone() {
 				Some(peers_preferred) => seed::preferred_peers(peers_preferred),
 				None => None,
@@ -199,9 +278,11 @@ impl Server {
 			seed::connect_and_monitor(
 				p2p_server.clone(),
 				config.p2p_config.capabilities,
-				config.dandelion_config.clone(),
+				dandelion_config.clone(),
 				seeder,
+				fallback_peers,
 				peers_preferred,
+				sync_state.clone(),
 				stop.clone(),
 			);
 		}
@@ -223,25 +304,65 @@ impl Server {
 			.name("p2p-server".to_string())
 			.spawn(move || p2p_inner.listen());
 
-		info!("Starting rest apis at: {}", &config.api_http_addr);
+		let state_info = ServerStateInfo::default();
+
+		info!("Starting rest apis at: {}", config.api_url());
 		let api_secret = get_first_line(config.api_secret_path.clone());
-		api::start_rest_apis(
+		let api_read_secret = get_first_line(config.api_read_secret_path.clone());
+		let mut extra_routes = vec![
+			StratumWorkersHandler::route(&state_info.stratum_stats),
+			SyncStatusHandler::route(&sync_state, &p2p_server.peers),
+			ConfigReloadHandler::route(&config_reloader),
+			BackupHandler::route(
+				&shared_chain,
+				&p2p_server.peers,
+				&config.db_root,
+				config.backup_dir.as_ref().map(|d| d.as_str()),
+			),
+			ReorgsHandler::route(&webhooks),
+		];
+		extra_routes.extend(BlockTemplateHandler::routes(
+			&shared_chain,
+			&tx_pool,
+			verifier_cache.clone(),
+			&stratum_config,
+			&sync_state,
+		));
+		if !api::start_rest_apis(
 			config.api_http_addr.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
-			p2p_server.peers.clone(),
+			dandelion_config.clone(),
+			p2p_server.clone(),
+			events,
 			api_secret,
-			None,
-		);
+			api_read_secret,
+			tls_config,
+			config.api_limits.clone(),
+			config.api_allowed_origins.clone().unwrap_or(vec![]),
+			config.readiness.clone(),
+			extra_routes,
+			Some(Arc::new(StatusSource {
+				sync_state: Arc::downgrade(&sync_state),
+				stratum_stats: Arc::downgrade(&state_info.stratum_stats),
+			})),
+		) {
+			return Err(Error::API(
+				api::ErrorKind::Internal("Could not start the rest apis".to_owned()).into(),
+			));
+		}
 
 		info!("Starting dandelion monitor: {}", &config.api_http_addr);
 		dandelion_monitor::monitor_transactions(
-			config.dandelion_config.clone(),
+			dandelion_config,
 			tx_pool.clone(),
 			verifier_cache.clone(),
 			stop.clone(),
 		);
 
+		Server::schedule_maintenance(&config, &shared_chain, &p2p_server)?
+			.start(sync_state.clone(), stop.clone());
+
 		warn!("Grin server started.");
 		Ok(Server {
 			config,
@@ -250,13 +371,44 @@ impl Server {
 			tx_pool,
 			verifier_cache,
 			sync_state,
-			state_info: ServerStateInfo {
-				..Default::default()
-			},
+			stratum_config,
+			config_reloader,
+			state_info,
 			stop,
+			stopped: Mutex::new(false),
 		})
 	}
 
+	// Registers the heavy background tasks in their configured windows
+	fn schedule_maintenance(
+		config: &ServerConfig,
+		chain: &Arc<chain::Chain>,
+		p2p: &Arc<p2p::Server>,
+	) -> Result<Scheduler, Error> {
+		let maintenance = &config.maintenance;
+		let mut scheduler = Scheduler::new();
+		if let Some(ref window) = maintenance.compaction {
+			let chain = chain.clone();
+			scheduler.register("compaction", window, move || {
+				chain.compact().map_err(|e| format!("{:?}", e))
+			})?;
+		}
+		if let Some(ref window) = maintenance.txhashset_zip {
+			let chain = chain.clone();
+			scheduler.register("txhashset_zip", window, move || {
+				chain
+					.prepare_txhashset_zip()
+					.map_err(|e| format!("{:?}", e))
+			})?;
+		}
+		let peers = p2p.peers.clone();
+		scheduler.register("peer_cleanup", &maintenance.peer_cleanup, move || {
+			peers.remove_expired();
+			Ok(())
+		})?;
+		Ok(scheduler)
+	}
+
 	/// Asks the server to connect to a peer at the provided network address.
 	pub fn connect_peer(&self, addr: SocketAddr) -> Result<(), Error> {
 		self.p2p.connect(&addr)?;
@@ -276,17 +428,37 @@ impl Server {
 		self.p2p.peers.peer_count()
 	}
 
+	/// The transaction pool, mostly useful for tests to push transactions
+	/// and look at where they ended up
+	pub fn tx_pool(&self) -> Arc<RwLock<pool::TransactionPool>> {
+		self.tx_pool.clone()
+	}
+
+	/// Sets where the configuration gets reloaded from, with the logging
+	/// configuration the node started with.
+	pub fn set_config_loader(&self, loader: ConfigLoader, logging: Option<LoggingConfig>) {
+		self.config_reloader.set_loader(loader, logging);
+	}
+
+	/// Reads the configuration again, applying the changes that don't need
+	/// a restart and reporting the others.
+	pub fn reload_config(&self) -> Result<ReloadReport, Error> {
+		self.config_reloader.reload()
+	}
+
 	/// Start a minimal "stratum" mining service on a separate thread
 	pub fn start_stratum_server(&self, config: StratumServerConfig) {
 		let edge_bits = global::min_edge_bits();
 		let proof_size = global::proofsize();
 		let sync_state = self.sync_state.clone();
 
+		*self.stratum_config.write() = config;
 		let mut stratum_server = stratumserver::StratumServer::new(
-			config.clone(),
+			self.stratum_config.clone(),
 			self.chain.clone(),
 			self.tx_pool.clone(),
 			self.verifier_cache.clone(),
+			self.stop.clone(),
 		);
 		let stratum_stats = self.state_info.stratum_stats.clone();
 		let _ = thread::Builder::new()
@@ -314,6 +486,14 @@ impl Server {
 			stratum_server_addr: None,
 			wallet_listener_url: config_wallet_url,
 			minimum_share_difficulty: 1,
+			vardiff_target_shares_per_minute: 0,
+			vardiff_retarget_secs: 90,
+			job_refresh_interval_secs: 5,
+			job_min_fee_increase: 1_000_000,
+			max_workers: 0,
+			tls_certificate_file: None,
+			tls_certificate_key: None,
+			wallet_listeners: vec![],
 		};
 
 		let mut miner = Miner::new(
@@ -413,16 +593,63 @@ impl Server {
 			head: self.head(),
 			header_head: self.header_head(),
 			sync_status: self.sync_state.status(),
+			sync_info: SyncInfo::from_state(&self.sync_state, &self.p2p.peers),
 			stratum_stats: stratum_stats,
 			peer_stats: peer_stats,
+			banned_peers: BanStats::from_peers(&self.p2p.peers),
 			diff_stats: diff_stats,
 		})
 	}
 
-	/// Stop the server.
+	/// Stop the server. Stops accepting peers and disconnects from the
+	/// connected ones after saving them, lets the stratum server and the chain
+	/// finish what they're doing and flushes the chain store to disk. Waits
+	/// for all of it up to the configured drain timeout.
 	pub fn stop(&self) {
-		self.p2p.stop();
+		let mut stopped = self.stopped.lock();
+		if *stopped {
+			return;
+		}
+		let timeout = time::Duration::from_secs(self.config.shutdown_drain_timeout_secs);
+		let deadline = time::Instant::now() + timeout;
+		warn!(
+			"Stopping server, waiting up to {}s for work in progress.",
+			timeout.as_secs()
+		);
+
+		// the sync, seeding, dandelion, mining and p2p listener threads all
+		// give up on their next iteration
 		self.stop.store(true, Ordering::Relaxed);
+		self.p2p.stop();
+
+		while self.state_info.stratum_stats.read().is_running && time::Instant::now() < deadline {
+			thread::sleep(time::Duration::from_millis(50));
+		}
+
+		// the chain waits for the block or txhashset being committed, in its own
+		// thread so we can give up on it
+		let chain = self.chain.clone();
+		let (tx, rx) = mpsc::channel();
+		let _ = thread::Builder::new()
+			.name("chain_stop".to_string())
+			.spawn(move || {
+				let _ = tx.send(chain.stop());
+			});
+		let now = time::Instant::now();
+		let left = if deadline > now {
+			deadline - now
+		} else {
+			time::Duration::from_secs(0)
+		};
+		match rx.recv_timeout(left) {
+			Ok(Ok(_)) => info!("Chain stopped and flushed to disk."),
+			Ok(Err(e)) => error!("Error stopping the chain: {:?}", e),
+			Err(_) => error!(
+				"Chain still busy after {}s, stopping regardless.",
+				timeout.as_secs()
+			),
+		}
+		*stopped = true;
 	}
 
 	/// Stops the test miner without stopping the p2p layer
@@ -431,3 +658,64 @@ impl Server {
 		info!("stop_test_miner - stop",);
 	}
 }
+
+/// Sync stage and stratum workers, filled in the status served by the API
+struct StatusSource {
+	sync_state: Weak<SyncState>,
+	stratum_stats: Weak<RwLock<StratumStats>>,
+}
+
+impl api::NodeStatusSource for StatusSource {
+	fn sync_stage(&self) -> Option<String> {
+		self.sync_state
+			.upgrade()
+			.map(|s| s.status().stage().to_owned())
+	}
+
+	fn stratum_workers(&self) -> Option<u32> {
+		match self.stratum_stats.upgrade() {
+			Some(ref stats) if stats.read().is_running => Some(stats.read().num_workers as u32),
+			_ => None,
+		}
+	}
+}
+
+/// Name of the peer database, under the data directory
+pub const PEER_DB_NAME: &'static str = "peer";
+
+/// File recording which network a data directory belongs to
+pub const NETWORK_FILE_NAME: &'static str = "network";
+
+/// The genesis block of the provided network
+pub fn genesis_block(chain_type: &global::ChainTypes) -> Block {
+	match *chain_type {
+		global::ChainTypes::Testnet1 => genesis::genesis_testnet1(),
+		global::ChainTypes::Testnet2 => genesis::genesis_testnet2(),
+		global::ChainTypes::Testnet3 => genesis::genesis_testnet3(),
+		global::ChainTypes::Testnet4 => genesis::genesis_testnet4(),
+		global::ChainTypes::AutomatedTesting => genesis::genesis_dev(),
+		global::ChainTypes::UserTesting => genesis::genesis_dev(),
+		global::ChainTypes::Mainnet => genesis::genesis_testnet2(), //TODO: Fix, obviously
+	}
+}
+
+/// Refuses to open a data directory created for another network, which would
+/// mix up both chains. A directory with no record gets claimed by this one.
+pub fn check_network(db_root: &str, chain_type: &global::ChainTypes) -> Result<(), Error> {
+	let network = chain_type.shortname();
+	let mut path = PathBuf::from(db_root);
+	fs::create_dir_all(&path).map_err(|e| Error::Config(format!("{}: {}", db_root, e)))?;
+	path.push(NETWORK_FILE_NAME);
+
+	match get_first_line(Some(path.to_str().unwrap().to_owned())) {
+		Some(ref recorded) if recorded.trim() != network => Err(Error::Config(format!(
+			"data directory {} belongs to the {} network, not {}",
+			db_root,
+			recorded.trim(),
+			network
+		))),
+		Some(_) => Ok(()),
+		None => fs::write(&path, format!("{}\n", network))
+			.map_err(|e| Error::Config(format!("{:?}: {}", path, e))),
+	}
+}
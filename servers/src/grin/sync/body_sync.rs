@@ -12,15 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use chrono::prelude::{DateTime, Utc};
-use chrono::Duration;
+use chrono::prelude::Utc;
 use std::cmp;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use chain;
 use common::types::{SyncState, SyncStatus};
-use core::core::hash::Hashed;
+use core::core::hash::{Hash, Hashed};
 use core::global;
+use grin::sync::download_scheduler::{record_stalls, DownloadScheduler, MAX_IN_FLIGHT_PER_PEER};
 use p2p;
 
 pub struct BodySync {
@@ -28,10 +29,9 @@ pub struct BodySync {
 	peers: Arc<p2p::Peers>,
 	sync_state: Arc<SyncState>,
 
-	blocks_requested: u64,
-
-	receive_timeout: DateTime<Utc>,
-	prev_blocks_received: u64,
+	downloads: DownloadScheduler<Hash>,
+	// blocks we wanted in flight when last asking, given peers and orphans
+	download_capacity: usize,
 }
 
 impl BodySync {
@@ -44,9 +44,8 @@ impl BodySync {
 			sync_state,
 			peers,
 			chain,
-			blocks_requested: 0,
-			receive_timeout: Utc::now(),
-			prev_blocks_received: 0,
+			downloads: DownloadScheduler::new(),
+			download_capacity: 0,
 		}
 	}
 
@@ -129,11 +128,10 @@ impl BodySync {
 
 		let peers = self.peers.more_work_peers();
 
-		// if we have 5 peers to sync from then ask for 50 blocks total (peer_count *
-		// 10) max will be 80 if all 8 peers are advertising more work
-		// also if the chain is already saturated with orphans, throttle
+		// ask each peer for up to MAX_IN_FLIGHT_PER_PEER blocks, max 100 in
+		// total, but throttle if the chain is already saturated with orphans
 		let block_count = cmp::min(
-			cmp::min(100, peers.len() * p2p::SEND_CHANNEL_CAP),
+			cmp::min(100, peers.len() * MAX_IN_FLIGHT_PER_PEER),
 			chain::MAX_ORPHAN_SIZE.saturating_sub(self.chain.orphans_len()) + 1,
 		);
 
@@ -144,28 +142,34 @@ impl BodySync {
 				// either successfully stored or in our orphan list
 				!self.chain.get_block(x).is_ok() && !self.chain.is_orphan(x)
 			}).take(block_count)
+			.cloned()
 			.collect::<Vec<_>>();
+		self.download_capacity = hashes_to_get.len();
 
 		if hashes_to_get.len() > 0 {
-			debug!(
-				"block_sync: {}/{} requesting blocks {:?} from {} peers",
-				body_head.height,
-				header_head.height,
-				hashes_to_get,
-				peers.len(),
-			);
-
-			// reinitialize download tracking state
-			self.blocks_requested = 0;
-			self.receive_timeout = Utc::now() + Duration::seconds(6);
-
-			let mut peers_iter = peers.iter().cycle();
-			for hash in hashes_to_get.clone() {
-				if let Some(peer) = peers_iter.next() {
-					if let Err(e) = peer.send_block_request(*hash) {
+			let by_addr = peers
+				.iter()
+				.map(|p| (p.info.addr, p.clone()))
+				.collect::<HashMap<_, _>>();
+			let addrs = peers.iter().map(|p| p.info.addr).collect::<Vec<_>>();
+			let assignments = self.downloads.schedule(&hashes_to_get, &addrs, Utc::now());
+			self.sync_state.set_sync_peers(addrs);
+
+			for (addr, range) in assignments {
+				debug!(
+					"body_sync: {}/{} requesting {} blocks from {}, {} in flight",
+					body_head.height,
+					header_head.height,
+					range.len(),
+					addr,
+					self.downloads.in_flight(),
+				);
+				let peer = &by_addr[&addr];
+				for hash in range {
+					if let Err(e) = peer.send_block_request(hash) {
 						debug!("Skipped request to {}: {:?}", peer.info.addr, e);
-					} else {
-						self.blocks_requested += 1;
+						peer.record_failure();
+						self.downloads.cancel(&hash);
 					}
 				}
 			}
@@ -174,44 +178,38 @@ impl BodySync {
 
 	// Should we run block body sync and ask for more full blocks?
 	fn body_sync_due(&mut self) -> bool {
-		let blocks_received = self.blocks_received();
-
-		// some blocks have been requested
-		if self.blocks_requested > 0 {
-			// but none received since timeout, ask again
-			let timeout = Utc::now() > self.receive_timeout;
-			if timeout && blocks_received <= self.prev_blocks_received {
-				debug!(
-					"body_sync: expecting {} more blocks and none received for a while",
-					self.blocks_requested,
-				);
-				return true;
-			}
-		}
+		// settle what arrived, reassign what never did and drop the requests
+		// pending with peers we lost
+		let chain = self.chain.clone();
+		self.downloads
+			.check_received(|h| chain.block_exists(*h).unwrap_or(false) || chain.is_orphan(h));
+		let connected = self
+			.peers
+			.connected_peers()
+			.iter()
+			.map(|p| p.info.addr)
+			.collect::<Vec<_>>();
+		self.downloads.retain_peers(&connected);
 
-		if blocks_received > self.prev_blocks_received {
-			// some received, update for next check
-			self.receive_timeout = Utc::now() + Duration::seconds(1);
-			self.blocks_requested = self
-				.blocks_requested
-				.saturating_sub(blocks_received - self.prev_blocks_received);
-			self.prev_blocks_received = blocks_received;
+		let stalled = self.downloads.expire_stalled(Utc::now());
+		if !stalled.is_empty() {
+			debug!(
+				"body_sync: {} block requests stalled, reassigning them",
+				stalled.len(),
+			);
+			record_stalls(&self.peers, &stalled);
+			return true;
 		}
 
-		// off by one to account for broadcast adding a couple orphans
-		if self.blocks_requested < 2 {
-			// no pending block requests, ask more
-			debug!("body_sync: no pending block request, asking more");
+		// keep at least half of the download capacity busy
+		if self.downloads.in_flight() <= self.download_capacity / 2 {
+			debug!(
+				"body_sync: {} block requests in flight, asking more",
+				self.downloads.in_flight()
+			);
 			return true;
 		}
 
-		return false;
-	}
-
-	// Total numbers received on this chain, including the head and orphans
-	fn blocks_received(&self) -> u64 {
-		self.chain.head().unwrap().height
-			+ self.chain.orphans_len() as u64
-			+ self.chain.orphans_evicted_len() as u64
+		false
 	}
 }
@@ -0,0 +1,290 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Spreads downloads, block bodies or txhashset segments, over several
+//! peers. Needed items are cut into consecutive ranges handed to the peers
+//! that delivered best so far, each peer's outstanding requests are tracked
+//! and ranges a peer sits on for too long get reassigned.
+
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+use std::cmp;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddr;
+
+use p2p;
+
+/// Most requests outstanding with a single peer
+pub const MAX_IN_FLIGHT_PER_PEER: usize = p2p::SEND_CHANNEL_CAP;
+
+/// Seconds after which a requested block not received yet is considered
+/// stalled and asked from another peer, unless created with another
+/// timeout
+pub const REQUEST_TIMEOUT_SECS: i64 = 10;
+
+struct Request {
+	peer: SocketAddr,
+	requested_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct PeerDownloads {
+	in_flight: usize,
+	delivered: u64,
+	// recent stalls, each one halving the blocks the peer gets assigned and
+	// forgiven one by one as it delivers again
+	stalls: u32,
+}
+
+impl PeerDownloads {
+	fn capacity(&self) -> usize {
+		let cap = MAX_IN_FLIGHT_PER_PEER >> cmp::min(self.stalls, 3);
+		cmp::max(cap, 1).saturating_sub(self.in_flight)
+	}
+
+	fn score(&self) -> i64 {
+		self.delivered as i64 - 4 * self.stalls as i64
+	}
+}
+
+/// Tracks which peer each outstanding request went to and decides which
+/// peer gets asked for which items next, items being identified by `K`.
+pub struct DownloadScheduler<K> {
+	requests: HashMap<K, Request>,
+	peers: HashMap<SocketAddr, PeerDownloads>,
+	timeout: Duration,
+}
+
+impl<K> DownloadScheduler<K>
+where
+	K: Eq + Hash + Copy,
+{
+	/// New scheduler, with no outstanding request
+	pub fn new() -> DownloadScheduler<K> {
+		DownloadScheduler::with_timeout(Duration::seconds(REQUEST_TIMEOUT_SECS))
+	}
+
+	/// New scheduler reassigning requests after the provided timeout
+	pub fn with_timeout(timeout: Duration) -> DownloadScheduler<K> {
+		DownloadScheduler {
+			requests: HashMap::new(),
+			peers: HashMap::new(),
+			timeout,
+		}
+	}
+
+	/// Number of requests outstanding, all peers included
+	pub fn in_flight(&self) -> usize {
+		self.requests.len()
+	}
+
+	/// Whether a request for the item is outstanding
+	pub fn is_requested(&self, h: &K) -> bool {
+		self.requests.contains_key(h)
+	}
+
+	/// Settles the outstanding requests for the items we now have, as told
+	/// by `have`, crediting the peers they were asked from. Returns the
+	/// number of items received.
+	pub fn check_received<F>(&mut self, have: F) -> usize
+	where
+		F: Fn(&K) -> bool,
+	{
+		let received = self
+			.requests
+			.keys()
+			.filter(|h| have(h))
+			.cloned()
+			.collect::<Vec<_>>();
+		for h in &received {
+			if let Some(req) = self.requests.remove(h) {
+				let peer = self.peers.entry(req.peer).or_insert_with(Default::default);
+				peer.in_flight = peer.in_flight.saturating_sub(1);
+				peer.delivered += 1;
+				peer.stalls = peer.stalls.saturating_sub(1);
+			}
+		}
+		received.len()
+	}
+
+	/// Drops the requests outstanding for longer than the timeout, charging
+	/// a stall to their peer. The items get scheduled again on the next
+	/// call to `schedule`. Returns them along with the peer they were asked
+	/// from.
+	pub fn expire_stalled(&mut self, now: DateTime<Utc>) -> Vec<(K, SocketAddr)> {
+		let timeout = self.timeout;
+		let stalled = self
+			.requests
+			.iter()
+			.filter(|(_, req)| now - req.requested_at > timeout)
+			.map(|(h, req)| (*h, req.peer))
+			.collect::<Vec<_>>();
+		for (h, _) in &stalled {
+			if let Some(req) = self.requests.remove(h) {
+				let peer = self.peers.entry(req.peer).or_insert_with(Default::default);
+				peer.in_flight = peer.in_flight.saturating_sub(1);
+				peer.stalls += 1;
+			}
+		}
+		stalled
+	}
+
+	/// Forgets about the peers not in the provided list anymore, their
+	/// outstanding requests being reassigned.
+	pub fn retain_peers(&mut self, connected: &[SocketAddr]) {
+		self.peers.retain(|addr, _| connected.contains(addr));
+		self.requests.retain(|_, req| connected.contains(&req.peer));
+	}
+
+	/// Request to a peer that could not be sent, so the item is free to be
+	/// assigned again.
+	pub fn cancel(&mut self, h: &K) {
+		if let Some(req) = self.requests.remove(h) {
+			if let Some(peer) = self.peers.get_mut(&req.peer) {
+				peer.in_flight = peer.in_flight.saturating_sub(1);
+			}
+		}
+	}
+
+	/// Cuts the provided items, minus the ones already requested, into
+	/// consecutive ranges and assigns one to each peer, best performers
+	/// first and within each peer's capacity. The returned requests are
+	/// recorded as outstanding, so callers should `cancel` those they fail
+	/// to send.
+	pub fn schedule(
+		&mut self,
+		hashes: &[K],
+		peers: &[SocketAddr],
+		now: DateTime<Utc>,
+	) -> Vec<(SocketAddr, Vec<K>)> {
+		let mut pending = hashes
+			.iter()
+			.filter(|h| !self.requests.contains_key(h))
+			.cloned()
+			.collect::<Vec<_>>();
+
+		for addr in peers {
+			self.peers.entry(*addr).or_insert_with(Default::default);
+		}
+		// stable sort, so equally good peers keep the order given
+		let mut ranked = peers.to_vec();
+		ranked.sort_by_key(|addr| -self.peers[addr].score());
+
+		let mut assignments = vec![];
+		for addr in ranked {
+			if pending.is_empty() {
+				break;
+			}
+			let peer = self.peers.get_mut(&addr).unwrap();
+			let count = cmp::min(peer.capacity(), pending.len());
+			if count == 0 {
+				continue;
+			}
+			let range = pending.drain(..count).collect::<Vec<_>>();
+			peer.in_flight += range.len();
+			for h in &range {
+				self.requests.insert(
+					*h,
+					Request {
+						peer: addr,
+						requested_at: now,
+					},
+				);
+			}
+			assignments.push((addr, range));
+		}
+		assignments
+	}
+}
+
+/// Lowers the quality of the peers that let requests stall, once per peer
+/// whatever the number of requests
+pub fn record_stalls<K>(peers: &p2p::Peers, stalled: &[(K, SocketAddr)]) {
+	let mut charged = vec![];
+	for &(_, addr) in stalled {
+		if !charged.contains(&addr) {
+			peers.record_failure(&addr);
+			charged.push(addr);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use core::core::hash::{Hash, Hashed};
+
+	fn hashes(n: u64) -> Vec<Hash> {
+		(0..n).map(|i| i.hash()).collect()
+	}
+
+	fn addr(port: u16) -> SocketAddr {
+		format!("127.0.0.1:{}", port).parse().unwrap()
+	}
+
+	#[test]
+	fn splits_ranges_across_peers() {
+		let mut sched = DownloadScheduler::new();
+		let hs = hashes(25);
+		let peers = vec![addr(1), addr(2), addr(3)];
+		let assigned = sched.schedule(&hs, &peers, Utc::now());
+
+		assert_eq!(assigned.len(), 3);
+		assert_eq!(assigned[0].1, hs[0..10].to_vec());
+		assert_eq!(assigned[1].1, hs[10..20].to_vec());
+		assert_eq!(assigned[2].1, hs[20..25].to_vec());
+		assert_eq!(sched.in_flight(), 25);
+
+		// everything is in flight already, nothing more to ask for
+		assert!(sched.schedule(&hs, &peers, Utc::now()).is_empty());
+	}
+
+	#[test]
+	fn reassigns_stalled_ranges() {
+		let mut sched = DownloadScheduler::with_timeout(Duration::seconds(5));
+		let hs = hashes(20);
+		let start = Utc::now();
+		sched.schedule(&hs, &[addr(1), addr(2)], start);
+
+		// the first peer delivers, the second one sits on its range
+		let first = hs[0..10].to_vec();
+		assert_eq!(sched.check_received(|h| first.contains(h)), 10);
+		let stalled = sched.expire_stalled(start + Duration::seconds(6));
+		assert_eq!(stalled.len(), 10);
+		assert!(stalled.iter().all(|&(_, peer)| peer == addr(2)));
+		assert_eq!(sched.in_flight(), 0);
+
+		// the stalled range goes to the peer that delivered first, and the
+		// slow one only gets a reduced share of what's left
+		let later = start + Duration::seconds(7);
+		let assigned = sched.schedule(&hs[10..20], &[addr(2), addr(1)], later);
+		assert_eq!(assigned[0], (addr(1), hs[10..20].to_vec()));
+		assert_eq!(assigned.len(), 1);
+	}
+
+	#[test]
+	fn drops_disconnected_peers() {
+		let mut sched = DownloadScheduler::new();
+		let hs = hashes(15);
+		sched.schedule(&hs, &[addr(1), addr(2)], Utc::now());
+		sched.retain_peers(&[addr(1)]);
+		assert_eq!(sched.in_flight(), 10);
+
+		sched.cancel(&hs[0]);
+		assert!(!sched.is_requested(&hs[0]));
+		let assigned = sched.schedule(&hs, &[addr(1)], Utc::now());
+		assert_eq!(assigned, vec![(addr(1), vec![hs[0]])]);
+	}
+}
@@ -14,6 +14,7 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use chain;
@@ -28,6 +29,8 @@ pub struct HeaderSync {
 
 	history_locator: Vec<(u64, Hash)>,
 	prev_header_sync: (DateTime<Utc>, u64, u64),
+	// peer the last headers were asked from
+	header_peer: Option<SocketAddr>,
 }
 
 impl HeaderSync {
@@ -42,6 +45,7 @@ impl HeaderSync {
 			chain,
 			history_locator: vec![],
 			prev_header_sync: (Utc::now(), 0, 0),
+			header_peer: None,
 		}
 	}
 
@@ -97,6 +101,11 @@ impl HeaderSync {
 			header_head.height >= prev_height + (p2p::MAX_BLOCK_HEADERS as u64) - 4;
 		// no headers processed and we're past timeout, need to ask for more
 		let stalling = header_head.height <= latest_height && now > timeout;
+		if stalling {
+			if let Some(addr) = self.header_peer.take() {
+				self.peers.record_failure(&addr);
+			}
+		}
 
 		// always enable header sync on initial state transition from NoSync / Initial
 		let force_sync = match self.sync_state.status() {
@@ -128,6 +137,8 @@ impl HeaderSync {
 			if let Some(peer) = self.peers.most_work_peer() {
 				if peer.info.total_difficulty() > difficulty {
 					self.request_headers(&peer);
+					self.sync_state.set_sync_peers(vec![peer.info.addr]);
+					self.header_peer = Some(peer.info.addr);
 				}
 			}
 		}
@@ -141,7 +152,10 @@ impl HeaderSync {
 				peer.info.addr, locator,
 			);
 
-			let _ = peer.send_header_request(locator);
+			if let Err(e) = peer.send_header_request(locator) {
+				debug!("sync: request_headers: {} failed: {:?}", peer.info.addr, e);
+				peer.record_failure();
+			}
 		}
 	}
 
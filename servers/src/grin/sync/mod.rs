@@ -15,8 +15,10 @@
 //! Syncing of the chain with the rest of the network
 
 mod body_sync;
+mod download_scheduler;
 mod header_sync;
 mod state_sync;
+pub mod sync_api;
 mod syncer;
 
 pub use self::syncer::run_sync;
@@ -14,20 +14,31 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use chain;
 use common::types::{Error, SyncState, SyncStatus};
 use core::core::hash::Hashed;
+use core::core::pmmr::{SegmentIdentifier, SegmentType};
+use core::core::BlockHeader;
 use core::global;
-use p2p::{self, Peer};
+use grin::sync::download_scheduler::{record_stalls, DownloadScheduler, MAX_IN_FLIGHT_PER_PEER};
+use p2p::{self, Capabilities, Peer};
+
+/// Seconds after which a requested segment not received yet is asked from
+/// another peer
+const SEGMENT_TIMEOUT_SECS: i64 = 30;
 
 /// Fast sync has 3 "states":
 /// * syncing headers
 /// * once all headers are sync'd, requesting the txhashset state
 /// * once we have the state, get blocks after that
 ///
-/// The StateSync struct implements and monitors the middle step.
+/// The StateSync struct implements and monitors the middle step. The state
+/// is downloaded in segments spread over all peers able to provide them, or
+/// as a single archive from the most work peer if none can, or if syncing
+/// in segments failed before.
 pub struct StateSync {
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
@@ -35,6 +46,11 @@ pub struct StateSync {
 
 	prev_fast_sync: Option<DateTime<Utc>>,
 	fast_sync_peer: Option<Arc<Peer>>,
+
+	segments: DownloadScheduler<(SegmentType, SegmentIdentifier)>,
+	segment_sync: bool,
+	segment_sync_failed: bool,
+	segments_received: u64,
 }
 
 impl StateSync {
@@ -49,6 +65,10 @@ impl StateSync {
 			chain,
 			prev_fast_sync: None,
 			fast_sync_peer: None,
+			segments: DownloadScheduler::with_timeout(Duration::seconds(SEGMENT_TIMEOUT_SECS)),
+			segment_sync: false,
+			segment_sync_failed: false,
+			segments_received: 0,
 		}
 	}
 
@@ -93,6 +113,11 @@ impl StateSync {
 		}
 
 		if sync_need_restart {
+			if self.segment_sync {
+				// fall back on a single archive, peers may not agree on how
+				// they compacted their state for example
+				self.segment_sync_failed = true;
+			}
 			self.fast_sync_reset();
 			self.sync_state.clear_sync_error();
 		}
@@ -104,6 +129,9 @@ impl StateSync {
 			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
 				if download_timeout {
 					error!("fast_sync: TxHashsetDownload status timeout in 10 minutes!");
+					if let Some(ref peer) = self.fast_sync_peer {
+						peer.record_failure();
+					}
 					self.sync_state
 						.set_sync_error(Error::P2P(p2p::Error::Timeout));
 				}
@@ -111,11 +139,24 @@ impl StateSync {
 
 			if go {
 				self.fast_sync_peer = None;
-				match self.request_state(&header_head) {
-					Ok(peer) => {
-						self.fast_sync_peer = Some(peer);
+				let txhashset_head = self.txhashset_head(&header_head);
+				if self.segment_peers().is_empty() || self.segment_sync_failed {
+					match self.request_state(&header_head, &txhashset_head) {
+						Ok(peer) => {
+							self.sync_state.set_sync_peers(vec![peer.info.addr]);
+							self.fast_sync_peer = Some(peer);
+						}
+						Err(e) => self.sync_state.set_sync_error(Error::P2P(e)),
+					}
+				} else {
+					info!(
+						"fast_sync: syncing txhashset at {} in segments",
+						txhashset_head.height
+					);
+					match self.chain.segment_sync_begin(txhashset_head.hash()) {
+						Ok(_) => self.segment_sync = true,
+						Err(e) => self.sync_state.set_sync_error(Error::Chain(e)),
 					}
-					Err(e) => self.sync_state.set_sync_error(Error::P2P(e)),
 				}
 
 				// to avoid the confusing log,
@@ -137,22 +178,36 @@ impl StateSync {
 				});
 			}
 		}
+
+		if self.segment_sync {
+			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
+				self.request_segments();
+			}
+		}
 		true
 	}
 
-	fn request_state(&self, header_head: &chain::Tip) -> Result<Arc<Peer>, p2p::Error> {
-		let horizon = global::cut_through_horizon() as u64;
+	// Ask for the txhashset at 90% of horizon, this still leaves time for
+	// download and validation to happen and stay within horizon. Rounded down
+	// to the header peers archive it at, the one they serve segments for.
+	fn txhashset_head(&self, header_head: &chain::Tip) -> BlockHeader {
+		let height = global::txhashset_archive_height(header_head.height);
+		let mut txhashset_head = self
+			.chain
+			.get_block_header(&header_head.prev_block_h)
+			.unwrap();
+		while txhashset_head.height > height {
+			txhashset_head = self.chain.get_previous_header(&txhashset_head).unwrap();
+		}
+		txhashset_head
+	}
 
+	fn request_state(
+		&self,
+		header_head: &chain::Tip,
+		txhashset_head: &BlockHeader,
+	) -> Result<Arc<Peer>, p2p::Error> {
 		if let Some(peer) = self.peers.most_work_peer() {
-			// ask for txhashset at 90% of horizon, this still leaves time for download
-			// and validation to happen and stay within horizon
-			let mut txhashset_head = self
-				.chain
-				.get_block_header(&header_head.prev_block_h)
-				.unwrap();
-			for _ in 0..(horizon - horizon / 10) {
-				txhashset_head = self.chain.get_previous_header(&txhashset_head).unwrap();
-			}
 			let bhash = txhashset_head.hash();
 			debug!(
 				"fast_sync: before txhashset request, header head: {} / {}, txhashset_head: {} / {}",
@@ -189,8 +244,76 @@ impl StateSync {
 		}
 	}
 
+	// More work peers able to provide the txhashset in segments
+	fn segment_peers(&self) -> Vec<Arc<Peer>> {
+		self.peers
+			.more_work_peers()
+			.into_iter()
+			.filter(|p| {
+				p.info
+					.capabilities
+					.contains(Capabilities::TXHASHSET_SEGMENTS)
+			}).collect()
+	}
+
+	// Spreads requests for the segments still missing over the peers able to
+	// provide them, reassigning those a peer has been sitting on.
+	fn request_segments(&mut self) {
+		let header = match self.chain.segment_sync_header() {
+			Some(header) => header,
+			None => return,
+		};
+		let peers = self.segment_peers();
+		let addrs = peers.iter().map(|p| p.info.addr).collect::<Vec<_>>();
+		let now = Utc::now();
+
+		let chain = self.chain.clone();
+		self.segments
+			.check_received(|&(segment_type, id)| chain.segment_sync_has(segment_type, id));
+		self.segments.retain_peers(&addrs);
+		let stalled = self.segments.expire_stalled(now);
+		if !stalled.is_empty() {
+			debug!(
+				"fast_sync: {} segment requests stalled, reassigning them",
+				stalled.len()
+			);
+			record_stalls(&self.peers, &stalled);
+		}
+
+		// the download times out when no segment came in for a while, rather
+		// than after a fixed time for the whole state
+		if let Some((received, _, _)) = chain.segment_sync_progress() {
+			if received > self.segments_received {
+				self.segments_received = received;
+				self.prev_fast_sync = Some(now);
+			}
+		}
+
+		self.sync_state.set_sync_peers(addrs.clone());
+		let wanted = self.segments.in_flight() + addrs.len() * MAX_IN_FLIGHT_PER_PEER;
+		let missing = chain.segment_sync_missing(wanted);
+		let by_addr = peers
+			.iter()
+			.map(|p| (p.info.addr, p.clone()))
+			.collect::<HashMap<_, _>>();
+		for (addr, range) in self.segments.schedule(&missing, &addrs, now) {
+			let peer = &by_addr[&addr];
+			for (segment_type, id) in range {
+				if let Err(e) = peer.send_segment_request(header.hash(), segment_type, id) {
+					debug!("Skipped segment request to {}: {:?}", addr, e);
+					peer.record_failure();
+					self.segments.cancel(&(segment_type, id));
+				}
+			}
+		}
+	}
+
 	fn fast_sync_reset(&mut self) {
 		self.prev_fast_sync = None;
 		self.fast_sync_peer = None;
+		self.chain.segment_sync_reset();
+		self.segments = DownloadScheduler::with_timeout(Duration::seconds(SEGMENT_TIMEOUT_SECS));
+		self.segment_sync = false;
+		self.segments_received = 0;
 	}
 }
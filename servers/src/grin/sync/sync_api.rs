@@ -0,0 +1,129 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detailed sync status, served by the node API
+
+use chrono::prelude::Utc;
+use hyper::{Body, Request, StatusCode};
+use std::sync::{Arc, Weak};
+
+use api::{self, Handler, ResponseFuture};
+use common::types::SyncState;
+use p2p;
+
+/// Path the sync status is served at
+pub const SYNC_STATUS_PATH: &'static str = "/v1/status/sync";
+
+/// A peer the node is syncing from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncPeer {
+	/// Address of the peer
+	pub addr: String,
+	/// Height the peer advertised last
+	pub height: u64,
+	/// Total difficulty the peer advertised last
+	pub total_difficulty: u64,
+}
+
+/// Where the node is at syncing with the network
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncInfo {
+	/// Coarse stage, one of waiting, header, state, body or synced
+	pub stage: String,
+	/// Detailed status within the stage, as published on the sync topic
+	pub status: String,
+	/// Progress within the status, if it reports any
+	pub current: Option<u64>,
+	/// What the progress goes up to
+	pub total: Option<u64>,
+	/// What the progress counts: height, bytes or items
+	pub unit: Option<String>,
+	/// Seconds spent in the current status
+	pub elapsed: u64,
+	/// Progress per second over the last minute
+	pub rate: Option<f64>,
+	/// Estimated seconds before the status completes, at the current rate
+	pub eta: Option<u64>,
+	/// Peers the current stage downloads from
+	pub peers: Vec<SyncPeer>,
+}
+
+impl SyncInfo {
+	/// Builds the sync info out of the sync state, looking up the peers
+	/// being synced from.
+	pub fn from_state(sync_state: &SyncState, peers: &p2p::Peers) -> SyncInfo {
+		let status = sync_state.status();
+		let progress = status.progress();
+		let rate = sync_state.progress_rate();
+		let eta = match (progress.current, progress.total, rate) {
+			(Some(current), Some(total), Some(rate)) if rate > 0.0 && total >= current => {
+				Some(((total - current) as f64 / rate).ceil() as u64)
+			}
+			_ => None,
+		};
+		let elapsed = (Utc::now() - sync_state.status_started()).num_seconds();
+
+		SyncInfo {
+			stage: status.stage().to_owned(),
+			status: progress.status,
+			current: progress.current,
+			total: progress.total,
+			unit: status.progress_unit().map(|u| u.to_owned()),
+			elapsed: if elapsed > 0 { elapsed as u64 } else { 0 },
+			rate,
+			eta,
+			peers: sync_state
+				.sync_peers()
+				.iter()
+				.filter_map(|addr| peers.get_connected_peer(addr))
+				.map(|p| SyncPeer {
+					addr: p.info.addr.to_string(),
+					height: p.info.height(),
+					total_difficulty: p.info.total_difficulty().to_num(),
+				}).collect(),
+		}
+	}
+}
+
+/// Stage of the sync, progress, rate and ETA, and the peers used.
+/// GET /v1/status/sync
+pub struct SyncStatusHandler {
+	pub sync_state: Weak<SyncState>,
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl SyncStatusHandler {
+	/// The handler, as added to the node API routes
+	pub fn route(
+		sync_state: &Arc<SyncState>,
+		peers: &Arc<p2p::Peers>,
+	) -> (&'static str, api::HandlerObj) {
+		let handler = SyncStatusHandler {
+			sync_state: Arc::downgrade(sync_state),
+			peers: Arc::downgrade(peers),
+		};
+		(SYNC_STATUS_PATH, Arc::new(handler))
+	}
+}
+
+impl Handler for SyncStatusHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		match (self.sync_state.upgrade(), self.peers.upgrade()) {
+			(Some(sync_state), Some(peers)) => {
+				api::json_response(&SyncInfo::from_state(&sync_state, &peers))
+			}
+			_ => api::response(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+		}
+	}
+}
@@ -30,6 +30,7 @@ extern crate itertools;
 extern crate jsonrpc_core;
 extern crate lmdb_zero as lmdb;
 extern crate rand;
+extern crate rustls;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -53,7 +54,22 @@ mod grin;
 mod mining;
 mod webwallet;
 
-pub use common::stats::{DiffBlock, PeerStats, ServerStats, StratumStats, WorkerStats};
-pub use common::types::{ServerConfig, StratumServerConfig};
+pub use common::backup::{restore, BackupReport, BackupRequest, BACKUP_PATH};
+pub use common::reindex::{reindex, ReindexReport};
+pub use common::reload::{ConfigLoader, ReloadReport, CONFIG_RELOAD_PATH};
+pub use common::scheduler::MaintenanceConfig;
+pub use common::stats::{
+	BanStats, DiffBlock, FoundBlock, PeerStats, ServerStats, StratumStats, WorkerStats,
+};
+pub use common::types::{ServerConfig, StratumServerConfig, WalletListener};
+pub use common::webhooks::{Reorg, WebhookConfig, WebhookDispatcher, REORGS_PATH};
+pub use grin::dandelion_monitor::monitor_transactions;
 pub use grin::server::Server;
+pub use grin::sync::sync_api::{SyncInfo, SyncPeer, SYNC_STATUS_PATH};
+pub use mining::block_template::{
+	BlockSubmission, BlockTemplate, BlockTemplateHandler, SubmittedBlock, TemplateKernel,
+	BLOCK_SUBMIT_PATH, BLOCK_TEMPLATE_PATH,
+};
+pub use mining::{CoinbaseKey, TemplateCache};
+pub use mining::stratum_api::{LoginInfo, StratumWorkers, WorkerInfo, STRATUM_WORKERS_PATH};
 pub use webwallet::server::start_webwallet_server;
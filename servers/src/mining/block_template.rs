@@ -0,0 +1,334 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block templates for external mining controllers: the candidate block to
+//! solve, served by the node API, and the submission of its solution.
+
+use futures::Future;
+use hyper::{Body, Request, StatusCode};
+use std::sync::{Arc, Weak};
+use util::{Mutex, RwLock};
+
+use api::{self, Handler, ResponseFuture};
+use chain;
+use common::types::{StratumServerConfig, SyncState};
+use core::consensus;
+use core::core::hash::{Hash, Hashed};
+use core::core::verifier_cache::VerifierCache;
+use core::core::{Block, BlockHeader, KernelFeatures};
+use core::{global, pow, ser};
+use mining::mine_block::{self, CoinbaseKey};
+use mining::wallet_listeners::WalletListeners;
+use pool;
+use serde::Serialize;
+use util;
+
+/// Path block templates are built at
+pub const BLOCK_TEMPLATE_PATH: &'static str = "/v1/mining/template";
+/// Path solved block templates are submitted to
+pub const BLOCK_SUBMIT_PATH: &'static str = "/v1/mining/submit";
+
+// templates kept for submission, the oldest dropped first
+const MAX_TEMPLATES: usize = 32;
+
+/// A transaction kernel of the candidate block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplateKernel {
+	/// Kernel excess, hex encoded
+	pub excess: String,
+	/// Fee of the transaction
+	pub fee: u64,
+	/// Height the transaction is locked until
+	pub lock_height: u64,
+}
+
+/// Candidate block to solve
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockTemplate {
+	/// Id to submit the solution with
+	pub template_id: u64,
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the block it builds on
+	pub prev_hash: String,
+	/// Header version
+	pub version: u16,
+	/// Header timestamp, as unix time
+	pub timestamp: i64,
+	/// Network difficulty a solution needs
+	pub difficulty: u64,
+	/// Scaling of the secondary proof of work difficulty
+	pub secondary_scaling: u32,
+	/// Smallest edge bits a proof can have
+	pub min_edge_bits: u8,
+	/// Number of nonces in a proof
+	pub proof_size: usize,
+	/// Header to solve, serialized and hex encoded up to the nonce
+	pub pre_pow: String,
+	/// Kernels of the transactions included, the coinbase excluded
+	pub kernels: Vec<TemplateKernel>,
+	/// Total fees of the transactions included
+	pub fees: u64,
+	/// Reward of the block, fees included
+	pub reward: u64,
+}
+
+/// Solution to a block template
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockSubmission {
+	/// Id of the template solved
+	pub template_id: u64,
+	/// Nonce of the solution
+	pub nonce: u64,
+	/// Edge bits of the proof
+	pub edge_bits: u8,
+	/// Proof nonces
+	pub pow: Vec<u64>,
+}
+
+/// Block added to the chain from a solved template
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubmittedBlock {
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the block
+	pub hash: String,
+}
+
+// templates handed out for the block on top of the current head, with the
+// reward key, along with the receiver that derived it, and the receivers they
+// share
+struct Templates {
+	prev_hash: Hash,
+	next_id: u64,
+	blocks: Vec<(u64, Block)>,
+	key: Option<CoinbaseKey>,
+	listeners: Vec<String>,
+	wallet_listeners: WalletListeners,
+}
+
+/// Builds block templates and takes their solutions, for mining controllers
+/// not speaking stratum. Rewards go to the stratum wallet receivers.
+/// POST /v1/mining/template
+/// POST /v1/mining/submit
+pub struct BlockTemplateHandler {
+	chain: Weak<chain::Chain>,
+	tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	verifier_cache: Arc<RwLock<VerifierCache>>,
+	stratum_config: Weak<RwLock<StratumServerConfig>>,
+	sync_state: Weak<SyncState>,
+	templates: Arc<Mutex<Templates>>,
+}
+
+impl BlockTemplateHandler {
+	/// The handlers, as added to the node API routes
+	pub fn routes(
+		chain: &Arc<chain::Chain>,
+		tx_pool: &Arc<RwLock<pool::TransactionPool>>,
+		verifier_cache: Arc<RwLock<VerifierCache>>,
+		stratum_config: &Arc<RwLock<StratumServerConfig>>,
+		sync_state: &Arc<SyncState>,
+	) -> Vec<(&'static str, api::HandlerObj)> {
+		let handler = Arc::new(BlockTemplateHandler {
+			chain: Arc::downgrade(chain),
+			tx_pool: Arc::downgrade(tx_pool),
+			verifier_cache,
+			stratum_config: Arc::downgrade(stratum_config),
+			sync_state: Arc::downgrade(sync_state),
+			templates: Arc::new(Mutex::new(Templates {
+				prev_hash: Hash::default(),
+				next_id: 0,
+				blocks: vec![],
+				key: None,
+				listeners: vec![],
+				wallet_listeners: WalletListeners::new(&stratum_config.read()),
+			})),
+		});
+		vec![
+			(BLOCK_TEMPLATE_PATH, handler.clone()),
+			(BLOCK_SUBMIT_PATH, handler),
+		]
+	}
+
+	fn clone_handler(&self) -> BlockTemplateHandler {
+		BlockTemplateHandler {
+			chain: self.chain.clone(),
+			tx_pool: self.tx_pool.clone(),
+			verifier_cache: self.verifier_cache.clone(),
+			stratum_config: self.stratum_config.clone(),
+			sync_state: self.sync_state.clone(),
+			templates: self.templates.clone(),
+		}
+	}
+
+	fn template(&self) -> Result<BlockTemplate, (StatusCode, String)> {
+		let unavailable = || (StatusCode::SERVICE_UNAVAILABLE, "shutting down".to_owned());
+		let chain = self.chain.upgrade().ok_or_else(&unavailable)?;
+		let tx_pool = self.tx_pool.upgrade().ok_or_else(&unavailable)?;
+		let stratum_config = self.stratum_config.upgrade().ok_or_else(&unavailable)?;
+		let sync_state = self.sync_state.upgrade().ok_or_else(&unavailable)?;
+		if sync_state.is_syncing() {
+			return Err((StatusCode::SERVICE_UNAVAILABLE, "syncing".to_owned()));
+		}
+		let head = chain.head_header().map_err(internal)?;
+
+		let mut templates = self.templates.lock();
+		let config = stratum_config.read().clone();
+		if templates.prev_hash != head.hash() {
+			// a new block to build, rewarding the next receiver in turn
+			templates.wallet_listeners.update(&config);
+			let listeners = templates.wallet_listeners.next();
+			templates.prev_hash = head.hash();
+			templates.blocks.clear();
+			templates.key = None;
+			templates.listeners = listeners;
+		}
+
+		let (block, _, key) = mine_block::build_block(
+			&chain,
+			&tx_pool,
+			self.verifier_cache.clone(),
+			templates.key.clone(),
+			&templates.listeners,
+		).map_err(internal)?;
+		templates.key = key;
+
+		let template_id = templates.next_id;
+		templates.next_id += 1;
+		let template = BlockTemplate::from_block(template_id, &block, &head)?;
+		templates.blocks.push((template_id, block));
+		if templates.blocks.len() > MAX_TEMPLATES {
+			templates.blocks.remove(0);
+		}
+		Ok(template)
+	}
+
+	fn submit(&self, submission: BlockSubmission) -> Result<SubmittedBlock, (StatusCode, String)> {
+		let chain = self
+			.chain
+			.upgrade()
+			.ok_or_else(|| (StatusCode::SERVICE_UNAVAILABLE, "shutting down".to_owned()))?;
+		let head = chain.head_header().map_err(internal)?;
+		let mut b = {
+			let templates = self.templates.lock();
+			let block = templates
+				.blocks
+				.iter()
+				.find(|&&(id, _)| id == submission.template_id)
+				.map(|&(_, ref b)| b.clone());
+			match block {
+				Some(b) => b,
+				None if submission.template_id < templates.next_id => {
+					return Err((StatusCode::CONFLICT, "stale template".to_owned()))
+				}
+				None => return Err((StatusCode::BAD_REQUEST, "unknown template".to_owned())),
+			}
+		};
+		if b.header.prev_hash != head.hash() {
+			return Err((StatusCode::CONFLICT, "stale template".to_owned()));
+		}
+
+		b.header.pow.nonce = submission.nonce;
+		b.header.pow.proof.edge_bits = submission.edge_bits;
+		b.header.pow.proof.nonces = submission.pow;
+		let difficulty = (b.header.total_difficulty() - head.total_difficulty()).to_num();
+		if b.header.pow.to_difficulty().to_num() < difficulty {
+			return Err((StatusCode::BAD_REQUEST, "difficulty too low".to_owned()));
+		}
+		if pow::verify_size(&b.header, b.header.pow.proof.edge_bits).is_err() {
+			return Err((StatusCode::BAD_REQUEST, "invalid proof of work".to_owned()));
+		}
+		let hash = b.hash();
+		let height = b.header.height;
+		if let Err(e) = chain.process_block(b, chain::Options::MINE) {
+			error!("Submitted block {} at {} rejected: {}", hash, height, e);
+			return Err((StatusCode::BAD_REQUEST, format!("block rejected: {}", e)));
+		}
+		info!(
+			"Block {} at {} submitted by a mining controller",
+			hash, height
+		);
+		Ok(SubmittedBlock {
+			height,
+			hash: hash.to_hex(),
+		})
+	}
+}
+
+fn internal<E: ::std::fmt::Debug>(e: E) -> (StatusCode, String) {
+	(StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e))
+}
+
+fn to_response<T: Serialize>(res: Result<T, (StatusCode, String)>) -> ResponseFuture {
+	match res {
+		Ok(t) => api::json_response(&t),
+		Err((status, msg)) => api::response(status, msg),
+	}
+}
+
+impl BlockTemplate {
+	fn from_block(
+		template_id: u64,
+		b: &Block,
+		head: &BlockHeader,
+	) -> Result<BlockTemplate, (StatusCode, String)> {
+		let bh = &b.header;
+		let mut header_buf = vec![];
+		{
+			let mut writer = ser::BinWriter::new(&mut header_buf);
+			bh.write_pre_pow(&mut writer).map_err(internal)?;
+			bh.pow
+				.write_pre_pow(bh.version, &mut writer)
+				.map_err(internal)?;
+		}
+		let kernels: Vec<TemplateKernel> = b
+			.kernels()
+			.iter()
+			.filter(|k| !k.features.contains(KernelFeatures::COINBASE_KERNEL))
+			.map(|k| TemplateKernel {
+				excess: util::to_hex(k.excess.0.to_vec()),
+				fee: k.fee,
+				lock_height: k.lock_height,
+			}).collect();
+		let fees = kernels.iter().map(|k| k.fee).sum();
+		Ok(BlockTemplate {
+			template_id,
+			height: bh.height,
+			prev_hash: bh.prev_hash.to_hex(),
+			version: bh.version,
+			timestamp: bh.timestamp.timestamp(),
+			difficulty: (bh.total_difficulty() - head.total_difficulty()).to_num(),
+			secondary_scaling: bh.pow.secondary_scaling,
+			min_edge_bits: global::min_edge_bits(),
+			proof_size: global::proofsize(),
+			pre_pow: util::to_hex(header_buf),
+			kernels,
+			fees,
+			reward: consensus::reward(fees),
+		})
+	}
+}
+
+impl Handler for BlockTemplateHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		if req.uri().path().trim_right_matches('/') == BLOCK_TEMPLATE_PATH {
+			return to_response(self.template());
+		}
+		let handler = self.clone_handler();
+		Box::new(api::parse_body(req).then(move |res| match res {
+			Ok(submission) => to_response(handler.submit(submission)),
+			Err(e) => api::response(StatusCode::BAD_REQUEST, format!("{}", e)),
+		}))
+	}
+}
@@ -24,6 +24,7 @@ use util::RwLock;
 
 use chain;
 use common::types::Error;
+use core::core::hash::{Hash, Hashed};
 use core::core::verifier_cache::VerifierCache;
 use core::{consensus, core, ser};
 use keychain::{ExtKeychain, Identifier, Keychain};
@@ -31,24 +32,137 @@ use pool;
 use util;
 use wallet::{self, BlockFees};
 
-// Ensure a block suitable for mining is built and returned
-// If a wallet listener URL is not provided the reward will be "burnt"
+/// Key a wallet listener derived for a block reward, handed back to that
+/// listener only when building another version of the block, as it means
+/// nothing to the others
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoinbaseKey {
+	/// Wallet listener the key was derived by
+	pub listener: String,
+	/// Identifier of the key
+	pub key_id: Identifier,
+}
+
+/// The block last built for mining. It's only rebuilt once the transactions
+/// taken from the pool change, its coinbase only asked for again once their
+/// fees do, and the difficulty worked out once per chain head.
+#[derive(Default)]
+pub struct TemplateCache {
+	/// The chain head the block builds on and the difficulty on top of it
+	prev: Option<(Hash, consensus::HeaderInfo)>,
+	/// Coinbase of the block
+	coinbase: Option<Coinbase>,
+	/// The block, along with the transactions it was built from
+	block: Option<(Vec<Hash>, core::Block)>,
+}
+
+struct Coinbase {
+	output: core::Output,
+	kernel: core::TxKernel,
+	block_fees: BlockFees,
+	key: Option<CoinbaseKey>,
+	/// Wallet listeners it was asked from
+	listeners: Vec<String>,
+}
+
+impl TemplateCache {
+	/// Builds a block on the chain head with the transactions mineable now,
+	/// reusing whatever still holds of the last one built
+	pub fn build(
+		&mut self,
+		chain: &Arc<chain::Chain>,
+		tx_pool: &Arc<RwLock<pool::TransactionPool>>,
+		verifier_cache: Arc<RwLock<VerifierCache>>,
+		key: Option<CoinbaseKey>,
+		wallet_listener_urls: &[String],
+	) -> Result<(core::Block, BlockFees, Option<CoinbaseKey>), Error> {
+		let head = chain.head_header()?;
+		if self.prev.as_ref().map(|p| p.0) != Some(head.hash()) {
+			// Note: do not keep the difficulty_iter in scope (it has an active batch).
+			let difficulty = consensus::next_difficulty(head.height + 1, chain.difficulty_iter());
+			*self = TemplateCache {
+				prev: Some((head.hash(), difficulty)),
+				coinbase: None,
+				block: None,
+			};
+		}
+		let difficulty = self.prev.as_ref().unwrap().1.clone();
+
+		// extract current transaction from the pool
+		let txs = tx_pool.read().prepare_mineable_transactions()?;
+		let tx_hashes = txs.iter().map(|tx| tx.hash()).collect::<Vec<_>>();
+		let fees = txs.iter().map(|tx| tx.fee()).sum();
+
+		let cached = self.coinbase.take().filter(|cb| {
+			cb.block_fees.fees == fees && cb.key == key && &cb.listeners[..] == wallet_listener_urls
+		});
+		let coinbase = match cached {
+			Some(cb) => cb,
+			None => {
+				// a new coinbase makes for a new block
+				self.block = None;
+				let block_fees = BlockFees {
+					fees,
+					key_id: None,
+					height: head.height + 1,
+				};
+				let (output, kernel, block_fees, key) =
+					get_coinbase(wallet_listener_urls, key, block_fees)?;
+				Coinbase {
+					output,
+					kernel,
+					block_fees,
+					key,
+					listeners: wallet_listener_urls.to_vec(),
+				}
+			}
+		};
+		let mut b = match self.block.take() {
+			// the same block, only given a fresh timestamp and nonce
+			Some((ref hashes, ref b)) if *hashes == tx_hashes => b.clone(),
+			// on failure the coinbase is dropped, the next attempt asking
+			// for another one
+			_ => build_block_with(
+				chain,
+				&head,
+				txs,
+				&difficulty,
+				coinbase.output.clone(),
+				coinbase.kernel.clone(),
+				verifier_cache,
+			)?,
+		};
+		self.block = Some((tx_hashes, b.clone()));
+		let res = (coinbase.block_fees.clone(), coinbase.key.clone());
+		self.coinbase = Some(coinbase);
+
+		b.header.pow.nonce = thread_rng().gen();
+		b.header.timestamp = header_timestamp(&head);
+		Ok((b, res.0, res.1))
+	}
+}
+
+// Ensure a block suitable for mining is built and returned, reusing what
+// holds of the cached one
+// If no wallet listener URL is provided the reward will be "burnt", the
+// following ones are only asked when the previous ones can't be reached
 // Warning: This call does not return until/unless a new block can be built
 pub fn get_block(
+	cache: &mut TemplateCache,
 	chain: &Arc<chain::Chain>,
 	tx_pool: &Arc<RwLock<pool::TransactionPool>>,
 	verifier_cache: Arc<RwLock<VerifierCache>>,
-	key_id: Option<Identifier>,
-	wallet_listener_url: Option<String>,
-) -> (core::Block, BlockFees) {
+	key: Option<CoinbaseKey>,
+	wallet_listener_urls: Vec<String>,
+) -> (core::Block, BlockFees, Option<CoinbaseKey>) {
 	let wallet_retry_interval = 5;
 	// get the latest chain state and build a block on top of it
-	let mut result = build_block(
+	let mut result = cache.build(
 		chain,
 		tx_pool,
 		verifier_cache.clone(),
-		key_id.clone(),
-		wallet_listener_url.clone(),
+		key.clone(),
+		&wallet_listener_urls,
 	);
 	while let Err(e) = result {
 		match e {
@@ -64,8 +178,8 @@ pub fn get_block(
 			},
 			self::Error::Wallet(_) => {
 				error!(
-					"Error building new block: Can't connect to wallet listener at {:?}; will retry",
-					wallet_listener_url.as_ref().unwrap()
+					"Error building new block: Can't connect to wallet listeners at {:?}; will retry",
+					wallet_listener_urls
 				);
 				thread::sleep(Duration::from_secs(wallet_retry_interval));
 			}
@@ -74,60 +188,70 @@ pub fn get_block(
 			}
 		}
 		thread::sleep(Duration::from_millis(100));
-		result = build_block(
+		result = cache.build(
 			chain,
 			tx_pool,
 			verifier_cache.clone(),
-			key_id.clone(),
-			wallet_listener_url.clone(),
+			key.clone(),
+			&wallet_listener_urls,
 		);
 	}
 	return result.unwrap();
 }
 
+/// Cheap summary of the transaction pool, as its number of transactions and
+/// their total fees, changing whenever transactions enter or leave it.
+pub fn pool_state(tx_pool: &Arc<RwLock<pool::TransactionPool>>) -> (usize, u64) {
+	let tx_pool = tx_pool.read();
+	let entries = &tx_pool.txpool.entries;
+	(entries.len(), entries.iter().map(|e| e.tx.fee()).sum())
+}
+
+/// Total fees of the transactions a block built now would include.
+pub fn mineable_fees(tx_pool: &Arc<RwLock<pool::TransactionPool>>) -> Result<u64, Error> {
+	let txs = tx_pool.read().prepare_mineable_transactions()?;
+	Ok(txs.iter().map(|tx| tx.fee()).sum())
+}
+
 /// Builds a new block with the chain head as previous and eligible
 /// transactions from the pool.
-fn build_block(
+pub fn build_block(
 	chain: &Arc<chain::Chain>,
 	tx_pool: &Arc<RwLock<pool::TransactionPool>>,
 	verifier_cache: Arc<RwLock<VerifierCache>>,
-	key_id: Option<Identifier>,
-	wallet_listener_url: Option<String>,
-) -> Result<(core::Block, BlockFees), Error> {
-	let head = chain.head_header()?;
+	key: Option<CoinbaseKey>,
+	wallet_listener_urls: &[String],
+) -> Result<(core::Block, BlockFees, Option<CoinbaseKey>), Error> {
+	TemplateCache::default().build(chain, tx_pool, verifier_cache, key, wallet_listener_urls)
+}
 
-	// prepare the block header timestamp
+// The header timestamp of a block built now on the head
+fn header_timestamp(head: &core::BlockHeader) -> DateTime<Utc> {
 	let mut now_sec = Utc::now().timestamp();
 	let head_sec = head.timestamp.timestamp();
 	if now_sec <= head_sec {
 		now_sec = head_sec + 1;
 	}
+	DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(now_sec, 0), Utc)
+}
 
-	// Determine the difficulty our block should be at.
-	// Note: do not keep the difficulty_iter in scope (it has an active batch).
-	let difficulty = consensus::next_difficulty(head.height + 1, chain.difficulty_iter());
-
-	// extract current transaction from the pool
-	let txs = tx_pool.read().prepare_mineable_transactions()?;
-
-	// build the coinbase and the block itself
-	let fees = txs.iter().map(|tx| tx.fee()).sum();
-	let height = head.height + 1;
-	let block_fees = BlockFees {
-		fees,
-		key_id,
-		height,
-	};
-
-	let (output, kernel, block_fees) = get_coinbase(wallet_listener_url, block_fees)?;
-	let mut b = core::Block::with_reward(&head, txs, output, kernel, difficulty.difficulty)?;
+// Builds a block on the head with the transactions and coinbase, its
+// txhashset roots set
+fn build_block_with(
+	chain: &Arc<chain::Chain>,
+	head: &core::BlockHeader,
+	txs: Vec<core::Transaction>,
+	difficulty: &consensus::HeaderInfo,
+	output: core::Output,
+	kernel: core::TxKernel,
+	verifier_cache: Arc<RwLock<VerifierCache>>,
+) -> Result<core::Block, Error> {
+	let mut b = core::Block::with_reward(head, txs, output, kernel, difficulty.difficulty)?;
 
 	// making sure we're not spending time mining a useless block
 	b.validate(&head.total_kernel_offset, verifier_cache)?;
 
-	b.header.pow.nonce = thread_rng().gen();
 	b.header.pow.secondary_scaling = difficulty.secondary_scaling;
-	b.header.timestamp = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(now_sec, 0), Utc);
 
 	let b_difficulty = (b.header.total_difficulty() - head.total_difficulty()).to_num();
 	debug!(
@@ -142,7 +266,7 @@ fn build_block(
 	let roots_result = chain.set_txhashset_roots(&mut b);
 
 	match roots_result {
-		Ok(_) => Ok((b, block_fees)),
+		Ok(_) => Ok(b),
 
 		// If it's a duplicate commitment, it's likely trying to use
 		// a key that's already been derived but not in the wallet
@@ -168,42 +292,74 @@ fn build_block(
 ///
 /// Probably only want to do this when testing.
 ///
-fn burn_reward(block_fees: BlockFees) -> Result<(core::Output, core::TxKernel, BlockFees), Error> {
+fn burn_reward(
+	block_fees: BlockFees,
+) -> Result<(core::Output, core::TxKernel, BlockFees, Option<CoinbaseKey>), Error> {
 	warn!("Burning block fees: {:?}", block_fees);
 	let keychain = ExtKeychain::from_random_seed().unwrap();
 	let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
 	let (out, kernel) =
 		wallet::libtx::reward::output(&keychain, &key_id, block_fees.fees, block_fees.height)
 			.unwrap();
-	Ok((out, kernel, block_fees))
+	Ok((out, kernel, block_fees, None))
 }
 
-// Connect to the wallet listener and get coinbase.
-// Warning: If a wallet listener URL is not provided the reward will be "burnt"
+// Connect to the first reachable wallet listener and get coinbase, along with
+// the key it derived.
+// Warning: If no wallet listener URL is provided the reward will be "burnt"
 fn get_coinbase(
-	wallet_listener_url: Option<String>,
+	wallet_listener_urls: &[String],
+	key: Option<CoinbaseKey>,
 	block_fees: BlockFees,
-) -> Result<(core::Output, core::TxKernel, BlockFees), Error> {
-	match wallet_listener_url {
-		None => {
-			// Burn it
-			return burn_reward(block_fees);
-		}
-		Some(wallet_listener_url) => {
-			let res = wallet::create_coinbase(&wallet_listener_url, &block_fees)?;
-			let out_bin = util::from_hex(res.output).unwrap();
-			let kern_bin = util::from_hex(res.kernel).unwrap();
-			let key_id_bin = util::from_hex(res.key_id).unwrap();
-			let output = ser::deserialize(&mut &out_bin[..]).unwrap();
-			let kernel = ser::deserialize(&mut &kern_bin[..]).unwrap();
-			let key_id = ser::deserialize(&mut &key_id_bin[..]).unwrap();
-			let block_fees = BlockFees {
-				key_id: Some(key_id),
-				..block_fees
-			};
+) -> Result<(core::Output, core::TxKernel, BlockFees, Option<CoinbaseKey>), Error> {
+	if wallet_listener_urls.is_empty() {
+		// Burn it
+		return burn_reward(block_fees);
+	}
+
+	let mut last_err = None;
+	for (i, wallet_listener_url) in wallet_listener_urls.iter().enumerate() {
+		// only the wallet that derived the key can reuse it
+		let block_fees = BlockFees {
+			key_id: key
+				.as_ref()
+				.filter(|k| &k.listener == wallet_listener_url)
+				.map(|k| k.key_id.clone()),
+			..block_fees.clone()
+		};
+		match wallet::create_coinbase(wallet_listener_url, &block_fees) {
+			Ok(res) => {
+				let out_bin = util::from_hex(res.output).unwrap();
+				let kern_bin = util::from_hex(res.kernel).unwrap();
+				let key_id_bin = util::from_hex(res.key_id).unwrap();
+				let output = ser::deserialize(&mut &out_bin[..]).unwrap();
+				let kernel = ser::deserialize(&mut &kern_bin[..]).unwrap();
+				let key_id: Identifier = ser::deserialize(&mut &key_id_bin[..]).unwrap();
+				let block_fees = BlockFees {
+					key_id: Some(key_id.clone()),
+					..block_fees
+				};
 
-			debug!("get_coinbase: {:?}", block_fees);
-			return Ok((output, kernel, block_fees));
+				debug!(
+					"get_coinbase: {:?} from {}",
+					block_fees, wallet_listener_url
+				);
+				let key = CoinbaseKey {
+					listener: wallet_listener_url.clone(),
+					key_id,
+				};
+				return Ok((output, kernel, block_fees, Some(key)));
+			}
+			Err(e) => {
+				if i + 1 < wallet_listener_urls.len() {
+					warn!(
+						"Can't get coinbase from {}, failing over to the next wallet listener",
+						wallet_listener_url
+					);
+				}
+				last_err = Some(e);
+			}
 		}
 	}
+	Err(last_err.unwrap().into())
 }
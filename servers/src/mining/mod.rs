@@ -14,6 +14,11 @@
 
 //! Mining + Mining server
 
+pub mod block_template;
 mod mine_block;
+pub mod stratum_api;
 pub mod stratumserver;
 pub mod test_miner;
+mod wallet_listeners;
+
+pub use self::mine_block::{CoinbaseKey, TemplateCache};
@@ -0,0 +1,182 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stratum worker statistics, served by the node API
+
+use hyper::{Body, Request, StatusCode};
+use std::sync::{Arc, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+use util::RwLock;
+
+use api::{self, Handler, ResponseFuture};
+use common::stats::{StratumStats, WorkerStats};
+
+/// Path the stratum worker stats are served at
+pub const STRATUM_WORKERS_PATH: &'static str = "/v1/stratum/workers";
+
+/// A stratum worker, as served by the API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkerInfo {
+	/// Worker id, unique to the server run
+	pub id: String,
+	/// Login the worker gave, if any
+	pub login: Option<String>,
+	/// Agent the worker gave when logging in
+	pub agent: String,
+	/// Whether it's still connected
+	pub is_connected: bool,
+	/// Share difficulty asked of it
+	pub difficulty: u64,
+	/// Valid shares submitted
+	pub accepted: u64,
+	/// Invalid shares submitted
+	pub rejected: u64,
+	/// Shares submitted too late
+	pub stale: u64,
+	/// Accepted shares that were full solutions
+	pub blocks_found: u64,
+	/// Estimated graphs per second
+	pub graph_rate: f64,
+	/// Unix time of the last accepted share
+	pub last_share: Option<u64>,
+	/// Unix time it was last heard from
+	pub last_seen: u64,
+	/// Seconds it has been, or was, connected
+	pub uptime: u64,
+}
+
+/// Stratum workers added up by login, as served by the API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginInfo {
+	/// The login
+	pub login: String,
+	/// Connected workers using it
+	pub workers: usize,
+	/// Valid shares submitted
+	pub accepted: u64,
+	/// Invalid shares submitted
+	pub rejected: u64,
+	/// Shares submitted too late
+	pub stale: u64,
+	/// Accepted shares that were full solutions
+	pub blocks_found: u64,
+	/// Estimated graphs per second of the connected workers
+	pub graph_rate: f64,
+	/// Unix time of the last accepted share
+	pub last_share: Option<u64>,
+}
+
+/// Answer of the stratum workers endpoint
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StratumWorkers {
+	/// Whether the stratum server is running
+	pub is_running: bool,
+	/// Connected workers
+	pub num_workers: usize,
+	/// Every worker seen since the server started
+	pub workers: Vec<WorkerInfo>,
+	/// The workers added up by login
+	pub logins: Vec<LoginInfo>,
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+	t.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+impl WorkerInfo {
+	fn from_stats(stats: &WorkerStats, edge_bits: u16) -> WorkerInfo {
+		WorkerInfo {
+			id: stats.id.clone(),
+			login: stats.login.clone(),
+			agent: stats.agent.clone(),
+			is_connected: stats.is_connected,
+			difficulty: stats.pow_difficulty,
+			accepted: stats.num_accepted,
+			rejected: stats.num_rejected,
+			stale: stats.num_stale,
+			blocks_found: stats.num_blocks_found,
+			graph_rate: stats.graph_rate(edge_bits),
+			last_share: stats.last_share.map(unix_secs),
+			last_seen: unix_secs(stats.last_seen),
+			uptime: stats.uptime().as_secs(),
+		}
+	}
+}
+
+impl StratumWorkers {
+	/// Builds the API view of the stratum stats
+	pub fn from_stats(stats: &StratumStats) -> StratumWorkers {
+		StratumWorkers {
+			is_running: stats.is_running,
+			num_workers: stats.num_workers,
+			workers: stats
+				.worker_stats
+				.iter()
+				.map(|w| WorkerInfo::from_stats(w, stats.edge_bits))
+				.collect(),
+			logins: stats
+				.login_stats()
+				.into_iter()
+				.map(|l| LoginInfo {
+					login: l.login,
+					workers: l.num_workers,
+					accepted: l.num_accepted,
+					rejected: l.num_rejected,
+					stale: l.num_stale,
+					blocks_found: l.num_blocks_found,
+					graph_rate: l.graph_rate,
+					last_share: l.last_share.map(unix_secs),
+				}).collect(),
+		}
+	}
+}
+
+/// Per worker and per login stratum share statistics. Only the connected
+/// workers are listed with `?connected`.
+/// GET /v1/stratum/workers
+/// GET /v1/stratum/workers?connected
+pub struct StratumWorkersHandler {
+	pub stratum_stats: Weak<RwLock<StratumStats>>,
+}
+
+impl StratumWorkersHandler {
+	/// The handler, as added to the node API routes
+	pub fn route(stratum_stats: &Arc<RwLock<StratumStats>>) -> (&'static str, api::HandlerObj) {
+		let handler = StratumWorkersHandler {
+			stratum_stats: Arc::downgrade(stratum_stats),
+		};
+		(STRATUM_WORKERS_PATH, Arc::new(handler))
+	}
+}
+
+impl Handler for StratumWorkersHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let stats = match self.stratum_stats.upgrade() {
+			Some(stats) => stats,
+			None => return api::response(StatusCode::SERVICE_UNAVAILABLE, "shutting down"),
+		};
+		let mut workers = StratumWorkers::from_stats(&stats.read());
+		let connected_only = req
+			.uri()
+			.query()
+			.map(|q| q.split('&').any(|p| p == "connected"))
+			.unwrap_or(false);
+		if connected_only {
+			workers.workers.retain(|w| w.is_connected);
+		}
+		api::json_response(&workers)
+	}
+}
@@ -15,25 +15,27 @@
 //! Mining Stratum Server
 use bufstream::BufStream;
 use chrono::prelude::Utc;
+use rustls::{self, ServerSession, Session, StreamOwned};
 use serde;
 use serde_json;
 use serde_json::Value;
 use std::error::Error;
-use std::io::{BufRead, ErrorKind, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use std::{cmp, thread};
 use util::{Mutex, RwLock};
 
 use chain;
-use common::stats::{StratumStats, WorkerStats};
+use common::stats::{FoundBlock, StratumStats, WorkerStats};
 use common::types::{StratumServerConfig, SyncState};
 use core::core::verifier_cache::VerifierCache;
 use core::core::Block;
 use core::{pow, ser};
-use keychain;
-use mining::mine_block;
+use mining::mine_block::{self, CoinbaseKey, TemplateCache};
+use mining::wallet_listeners::WalletListeners;
 use pool;
 use util;
 
@@ -88,6 +90,11 @@ pub struct JobTemplate {
 	pre_pow: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct DifficultyParams {
+	difficulty: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WorkerStatus {
 	id: String,
@@ -101,10 +108,94 @@ pub struct WorkerStatus {
 // ----------------------------------------
 // Worker Factory Thread Function
 
-// Run in a thread. Adds new connections to the workers list
+/// Seconds a connecting worker has to complete its TLS handshake
+const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// Connection to a worker, in the clear or over TLS
+pub enum WorkerStream {
+	/// Plaintext connection
+	Plain(TcpStream),
+	/// TLS connection, with its handshake completed
+	Tls(StreamOwned<ServerSession, TcpStream>),
+}
+
+impl Read for WorkerStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match *self {
+			WorkerStream::Plain(ref mut s) => s.read(buf),
+			WorkerStream::Tls(ref mut s) => s.read(buf),
+		}
+	}
+}
+
+impl Write for WorkerStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match *self {
+			WorkerStream::Plain(ref mut s) => s.write(buf),
+			WorkerStream::Tls(ref mut s) => s.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match *self {
+			WorkerStream::Plain(ref mut s) => s.flush(),
+			WorkerStream::Tls(ref mut s) => s.flush(),
+		}
+	}
+}
+
+// Completes the TLS handshake on a freshly accepted connection, blocking
+// for at most TLS_HANDSHAKE_TIMEOUT_SECS, then makes it non-blocking like
+// plaintext ones.
+fn tls_handshake(
+	tls_config: &Arc<rustls::ServerConfig>,
+	mut stream: TcpStream,
+) -> Result<WorkerStream, io::Error> {
+	let timeout = Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SECS);
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+	let mut session = ServerSession::new(tls_config);
+	while session.is_handshaking() {
+		session.complete_io(&mut stream)?;
+	}
+	stream.set_nonblocking(true)?;
+	Ok(WorkerStream::Tls(StreamOwned::new(session, stream)))
+}
+
+// Adds a connected worker to the workers list, with its stats
+fn add_worker(
+	worker_id: u32,
+	stream: WorkerStream,
+	initial_difficulty: u64,
+	workers: &Arc<Mutex<Vec<Worker>>>,
+	stratum_stats: &Arc<RwLock<StratumStats>>,
+) {
+	let worker = Worker::new(
+		worker_id.to_string(),
+		BufStream::new(stream),
+		initial_difficulty,
+	);
+	workers.lock().push(worker);
+	// stats for this worker (worker stat objects are added and updated but never
+	// removed)
+	let mut worker_stats = WorkerStats::default();
+	worker_stats.is_connected = true;
+	worker_stats.id = worker_id.to_string();
+	worker_stats.pow_difficulty = initial_difficulty;
+	let mut stratum_stats = stratum_stats.write();
+	stratum_stats.worker_stats.push(worker_stats);
+}
+
+// Run in a thread. Adds new connections to the workers list, after their TLS
+// handshake when the server has a certificate. Handshakes run in threads of
+// their own so slow clients don't hold up the others. Connections past the
+// most workers configured are closed right away.
 fn accept_workers(
 	id: String,
 	address: String,
+	initial_difficulty: u64,
+	max_workers: u32,
+	tls_config: Option<Arc<rustls::ServerConfig>>,
 	workers: &mut Arc<Mutex<Vec<Worker>>>,
 	stratum_stats: &mut Arc<RwLock<StratumStats>>,
 ) {
@@ -113,24 +204,51 @@ fn accept_workers(
 	for stream in listener.incoming() {
 		match stream {
 			Ok(stream) => {
-				warn!(
-					"(Server ID: {}) New connection: {}",
-					id,
-					stream.peer_addr().unwrap()
-				);
-				stream
-					.set_nonblocking(true)
-					.expect("set_nonblocking call failed");
-				let mut worker = Worker::new(worker_id.to_string(), BufStream::new(stream));
-				workers.lock().push(worker);
-				// stats for this worker (worker stat objects are added and updated but never
-				// removed)
-				let mut worker_stats = WorkerStats::default();
-				worker_stats.is_connected = true;
-				worker_stats.id = worker_id.to_string();
-				worker_stats.pow_difficulty = 1; // XXX TODO
-				let mut stratum_stats = stratum_stats.write();
-				stratum_stats.worker_stats.push(worker_stats);
+				let peer_addr = stream.peer_addr().unwrap();
+				if max_workers > 0 && workers.lock().len() >= max_workers as usize {
+					error!(
+						"(Server ID: {}) Refusing connection from {}, already {} workers \
+						 connected (max_workers)",
+						id, peer_addr, max_workers
+					);
+					let _ = stream.shutdown(Shutdown::Both);
+					continue;
+				}
+				warn!("(Server ID: {}) New connection: {}", id, peer_addr);
+				match tls_config.clone() {
+					None => {
+						stream
+							.set_nonblocking(true)
+							.expect("set_nonblocking call failed");
+						add_worker(
+							worker_id,
+							WorkerStream::Plain(stream),
+							initial_difficulty,
+							workers,
+							stratum_stats,
+						);
+					}
+					Some(tls_config) => {
+						let id = id.clone();
+						let workers = workers.clone();
+						let stratum_stats = stratum_stats.clone();
+						let _ = thread::Builder::new()
+							.name("stratum_tls".to_string())
+							.spawn(move || match tls_handshake(&tls_config, stream) {
+								Ok(stream) => add_worker(
+									worker_id,
+									stream,
+									initial_difficulty,
+									&workers,
+									&stratum_stats,
+								),
+								Err(e) => warn!(
+									"(Server ID: {}) TLS handshake with {} failed: {}",
+									id, peer_addr, e
+								),
+							});
+					}
+				}
 				worker_id = worker_id + 1;
 			}
 			Err(e) => {
@@ -142,6 +260,69 @@ fn accept_workers(
 	drop(listener);
 }
 
+// ----------------------------------------
+// Variable difficulty - keeps each worker's share rate near a target
+
+/// Most a single retarget moves a worker's difficulty, up or down
+const VARDIFF_MAX_STEP: u64 = 4;
+
+/// Share rate tracking for a single worker, retargeting its difficulty
+/// toward the configured number of shares per minute.
+#[derive(Debug, Clone)]
+pub struct VarDiff {
+	window_start: Instant,
+	shares: u64,
+}
+
+impl VarDiff {
+	/// Starts tracking at the given instant
+	pub fn new(now: Instant) -> VarDiff {
+		VarDiff {
+			window_start: now,
+			shares: 0,
+		}
+	}
+
+	/// Counts an accepted share
+	pub fn add_share(&mut self) {
+		self.shares += 1;
+	}
+
+	/// Once the retarget window is over, the difficulty that would have
+	/// brought the worker to the target rate, moved by at most a factor of
+	/// VARDIFF_MAX_STEP and kept within bounds. Starts a new window.
+	pub fn retarget(
+		&mut self,
+		now: Instant,
+		window: Duration,
+		target_per_minute: u64,
+		difficulty: u64,
+		min_difficulty: u64,
+		max_difficulty: u64,
+	) -> Option<u64> {
+		let elapsed = now.duration_since(self.window_start);
+		if target_per_minute == 0 || elapsed < window {
+			return None;
+		}
+		let elapsed_ms = cmp::max(elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64, 1);
+		// difficulty * actual rate / target rate, in shares per minute
+		let wanted = (difficulty as u128 * self.shares as u128 * 60_000)
+			/ (elapsed_ms as u128 * target_per_minute as u128);
+		let wanted = cmp::min(wanted, u64::max_value() as u128) as u64;
+		let wanted = cmp::max(wanted, difficulty / VARDIFF_MAX_STEP);
+		let wanted = cmp::min(wanted, difficulty.saturating_mul(VARDIFF_MAX_STEP));
+		let wanted = cmp::max(cmp::min(wanted, max_difficulty), min_difficulty);
+
+		self.window_start = now;
+		self.shares = 0;
+		if wanted != difficulty {
+			Some(wanted)
+		} else {
+			None
+		}
+	}
+}
+
 // ----------------------------------------
 // Worker Object - a connected stratum client - a miner, pool, proxy, etc...
 
@@ -149,14 +330,19 @@ pub struct Worker {
 	id: String,
 	agent: String,
 	login: Option<String>,
-	stream: BufStream<TcpStream>,
+	stream: BufStream<WorkerStream>,
 	error: bool,
 	authenticated: bool,
+	// share difficulty asked of this worker, and the one before the last
+	// retarget, still accepted for shares already in flight
+	difficulty: u64,
+	prev_difficulty: u64,
+	vardiff: VarDiff,
 }
 
 impl Worker {
 	/// Creates a new Stratum Worker.
-	pub fn new(id: String, stream: BufStream<TcpStream>) -> Worker {
+	pub fn new(id: String, stream: BufStream<WorkerStream>, difficulty: u64) -> Worker {
 		Worker {
 			id: id,
 			agent: String::from(""),
@@ -164,6 +350,9 @@ impl Worker {
 			stream: stream,
 			error: false,
 			authenticated: false,
+			difficulty,
+			prev_difficulty: difficulty,
+			vardiff: VarDiff::new(Instant::now()),
 		}
 	}
 
@@ -226,42 +415,66 @@ impl Worker {
 pub struct StratumServer {
 	id: String,
 	config: StratumServerConfig,
+	// updated by configuration reloads, copied over `config` on every run of
+	// the main loop
+	shared_config: Arc<RwLock<StratumServerConfig>>,
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	verifier_cache: Arc<RwLock<VerifierCache>>,
 	current_block_versions: Vec<Block>,
+	// what still holds of the last block built, reused for the next version
+	template_cache: TemplateCache,
 	current_difficulty: u64,
 	minimum_share_difficulty: u64,
-	current_key_id: Option<keychain::Identifier>,
+	// reward key of the current block, with the wallet receiver it's from
+	current_key: Option<CoinbaseKey>,
+	// wallet receivers to request the reward of the current block from, in
+	// failover order
+	current_listeners: Vec<String>,
+	wallet_listeners: WalletListeners,
 	workers: Arc<Mutex<Vec<Worker>>>,
 	sync_state: Arc<SyncState>,
+	stop: Arc<AtomicBool>,
 }
 
 impl StratumServer {
 	/// Creates a new Stratum Server.
 	pub fn new(
-		config: StratumServerConfig,
+		shared_config: Arc<RwLock<StratumServerConfig>>,
 		chain: Arc<chain::Chain>,
 		tx_pool: Arc<RwLock<pool::TransactionPool>>,
 		verifier_cache: Arc<RwLock<VerifierCache>>,
+		stop: Arc<AtomicBool>,
 	) -> StratumServer {
+		let config = shared_config.read().clone();
 		StratumServer {
 			id: String::from("StratumServer"),
 			minimum_share_difficulty: config.minimum_share_difficulty,
+			wallet_listeners: WalletListeners::new(&config),
 			config,
+			shared_config,
 			chain,
 			tx_pool,
 			verifier_cache,
 			current_block_versions: Vec::new(),
+			template_cache: TemplateCache::default(),
 			current_difficulty: <u64>::max_value(),
-			current_key_id: None,
+			current_key: None,
+			current_listeners: vec![],
 			workers: Arc::new(Mutex::new(Vec::new())),
 			sync_state: Arc::new(SyncState::new()),
+			stop,
 		}
 	}
 
+	// Share difficulty currently asked of the worker, never above the
+	// network's
+	fn worker_difficulty(&self, worker: &Worker) -> u64 {
+		cmp::min(worker.difficulty, self.current_difficulty)
+	}
+
 	// Build and return a JobTemplate for mining the current block
-	fn build_block_template(&self) -> JobTemplate {
+	fn build_block_template(&self, difficulty: u64) -> JobTemplate {
 		let bh = self.current_block_versions.last().unwrap().header.clone();
 		// Serialize the block header into pre and post nonce strings
 		let mut header_buf = vec![];
@@ -274,7 +487,7 @@ impl StratumServer {
 		let job_template = JobTemplate {
 			height: bh.height,
 			job_id: (self.current_block_versions.len() - 1) as u64,
-			difficulty: self.minimum_share_difficulty,
+			difficulty,
 			pre_pow,
 		};
 		return job_template;
@@ -312,7 +525,11 @@ impl StratumServer {
 
 					// Call the handler function for requested method
 					let response = match request.method.as_str() {
-						"login" => self.handle_login(request.params, &mut workers_l[num]),
+						"login" => self.handle_login(
+							request.params,
+							&mut workers_l[num],
+							&mut stratum_stats.worker_stats[worker_stats_id],
+						),
 						"submit" => {
 							let res = self.handle_submit(
 								request.params,
@@ -320,8 +537,9 @@ impl StratumServer {
 								&mut stratum_stats.worker_stats[worker_stats_id],
 							);
 							// this key_id has been used now, reset
-							if let Ok((_, true)) = res {
-								self.current_key_id = None;
+							if let Ok((_, Some(ref found))) = res {
+								self.current_key = None;
+								stratum_stats.block_found(found.clone());
 							}
 							res.map(|(v, _)| v)
 						}
@@ -334,7 +552,7 @@ impl StratumServer {
 								};
 								Err(serde_json::to_value(e).unwrap())
 							} else {
-								self.handle_getjobtemplate()
+								self.handle_getjobtemplate(&workers_l[num])
 							}
 						}
 						"status" => {
@@ -399,9 +617,9 @@ impl StratumServer {
 	}
 
 	// Handle GETJOBTEMPLATE message
-	fn handle_getjobtemplate(&self) -> Result<Value, Value> {
+	fn handle_getjobtemplate(&self, worker: &Worker) -> Result<Value, Value> {
 		// Build a JobTemplate from a BlockHeader and return JSON
-		let job_template = self.build_block_template();
+		let job_template = self.build_block_template(self.worker_difficulty(worker));
 		let response = serde_json::to_value(&job_template).unwrap();
 		debug!(
 			"(Server ID: {}) sending block {} with id {} to single worker",
@@ -416,12 +634,19 @@ impl StratumServer {
 	}
 
 	// Handle LOGIN message
-	fn handle_login(&self, params: Option<Value>, worker: &mut Worker) -> Result<Value, Value> {
+	fn handle_login(
+		&self,
+		params: Option<Value>,
+		worker: &mut Worker,
+		worker_stats: &mut WorkerStats,
+	) -> Result<Value, Value> {
 		let params: LoginParams = parse_params(params)?;
 		worker.login = Some(params.login);
 		// XXX TODO Future - Validate password?
 		worker.agent = params.agent;
 		worker.authenticated = true;
+		worker_stats.login = worker.login.clone();
+		worker_stats.agent = worker.agent.clone();
 		return Ok(serde_json::to_value("ok".to_string()).unwrap());
 	}
 
@@ -435,7 +660,7 @@ impl StratumServer {
 		params: Option<Value>,
 		worker: &mut Worker,
 		worker_stats: &mut WorkerStats,
-	) -> Result<(Value, bool), Value> {
+	) -> Result<(Value, Option<FoundBlock>), Value> {
 		// Validate parameters
 		let params: SubmitParams = parse_params(params)?;
 
@@ -477,11 +702,15 @@ impl StratumServer {
 		// Get share difficulty
 		share_difficulty = b.header.pow.to_difficulty().to_num();
 		// If the difficulty is too low its an error
-		if share_difficulty < self.minimum_share_difficulty {
+		let worker_difficulty = cmp::min(
+			self.worker_difficulty(worker),
+			cmp::min(worker.prev_difficulty, self.current_difficulty),
+		);
+		if share_difficulty < worker_difficulty {
 			// Return error status
 			error!(
 				"(Server ID: {}) Share at height {} with {} edge_bits rejected due to low difficulty: {}/{}",
-				self.id, params.height, params.edge_bits, share_difficulty, self.minimum_share_difficulty,
+				self.id, params.height, params.edge_bits, share_difficulty, worker_difficulty,
 			);
 			worker_stats.num_rejected += 1;
 			let e = RpcError {
@@ -554,16 +783,27 @@ impl StratumServer {
 			submitted_by,
 		);
 		worker_stats.num_accepted += 1;
+		worker_stats.accepted_difficulty += worker_difficulty;
+		worker_stats.last_share = Some(SystemTime::now());
+		if share_is_block {
+			worker_stats.num_blocks_found += 1;
+		}
+		worker.vardiff.add_share();
 		let submit_response;
+		let mut found = None;
 		if share_is_block {
 			submit_response = format!("blockfound - {}", b.hash().to_hex());
+			found = Some(FoundBlock {
+				height: b.header.height,
+				hash: b.hash().to_hex(),
+				fees: b.total_fees(),
+				found_at: SystemTime::now(),
+				found_by: submitted_by,
+			});
 		} else {
 			submit_response = "ok".to_string();
 		}
-		return Ok((
-			serde_json::to_value(submit_response).unwrap(),
-			share_is_block,
-		));
+		return Ok((serde_json::to_value(submit_response).unwrap(), found));
 	} // handle submit a solution
 
 	// Purge dead/sick workers - remove all workers marked in error state
@@ -603,27 +843,110 @@ impl StratumServer {
 	// expected
 	fn broadcast_job(&mut self) {
 		// Package new block into RpcRequest
-		let job_template = self.build_block_template();
-		let job_template_json = serde_json::to_string(&job_template).unwrap();
-		// Issue #1159 - use a serde_json Value type to avoid extra quoting
-		let job_template_value: Value = serde_json::from_str(&job_template_json).unwrap();
-		let job_request = RpcRequest {
-			id: String::from("Stratum"),
-			jsonrpc: String::from("2.0"),
-			method: String::from("job"),
-			params: Some(job_template_value),
-		};
-		let job_request_json = serde_json::to_string(&job_request).unwrap();
+		let mut job_template = self.build_block_template(self.minimum_share_difficulty);
 		debug!(
 			"(Server ID: {}) sending block {} with id {} to stratum clients",
 			self.id, job_template.height, job_template.job_id,
 		);
-		// Push the new block to all connected clients
+		// Push the new block to all connected clients, each at its own share
+		// difficulty
 		// NOTE: We do not give a unique nonce (should we?) so miners need
 		//       to choose one for themselves
 		let mut workers_l = self.workers.lock();
 		for num in 0..workers_l.len() {
-			workers_l[num].write_message(job_request_json.clone());
+			job_template.difficulty = self.worker_difficulty(&workers_l[num]);
+			// the new job is what shares will be checked against from now
+			workers_l[num].prev_difficulty = workers_l[num].difficulty;
+			let job_template_json = serde_json::to_string(&job_template).unwrap();
+			// Issue #1159 - use a serde_json Value type to avoid extra quoting
+			let job_template_value: Value = serde_json::from_str(&job_template_json).unwrap();
+			let job_request = RpcRequest {
+				id: String::from("Stratum"),
+				jsonrpc: String::from("2.0"),
+				method: String::from("job"),
+				params: Some(job_template_value),
+			};
+			workers_l[num].write_message(serde_json::to_string(&job_request).unwrap());
+		}
+	}
+
+	// Retarget the share difficulty of the workers whose vardiff window is
+	// over, telling them with a mining.set_difficulty request - no response
+	// expected
+	fn retarget_workers(&mut self, stratum_stats: &mut Arc<RwLock<StratumStats>>) {
+		let target = self.config.vardiff_target_shares_per_minute;
+		if target == 0 {
+			return;
+		}
+		let window = Duration::from_secs(self.config.vardiff_retarget_secs);
+		let now = Instant::now();
+		let mut workers_l = self.workers.lock();
+		for num in 0..workers_l.len() {
+			let worker = &mut workers_l[num];
+			let difficulty = worker.difficulty;
+			let new_difficulty = match worker.vardiff.retarget(
+				now,
+				window,
+				target,
+				difficulty,
+				self.config.minimum_share_difficulty,
+				cmp::max(
+					self.current_difficulty,
+					self.config.minimum_share_difficulty,
+				),
+			) {
+				Some(d) => d,
+				None => continue,
+			};
+			debug!(
+				"(Server ID: {}) Retargeting worker {} share difficulty {} -> {}",
+				self.id, worker.id, difficulty, new_difficulty,
+			);
+			worker.prev_difficulty = difficulty;
+			worker.difficulty = new_difficulty;
+			let request = RpcRequest {
+				id: String::from("Stratum"),
+				jsonrpc: String::from("2.0"),
+				method: String::from("mining.set_difficulty"),
+				params: Some(
+					serde_json::to_value(DifficultyParams {
+						difficulty: cmp::min(new_difficulty, self.current_difficulty),
+					}).unwrap(),
+				),
+			};
+			worker.write_message(serde_json::to_string(&request).unwrap());
+
+			let mut stratum_stats = stratum_stats.write();
+			if let Some(worker_stats) = stratum_stats
+				.worker_stats
+				.iter_mut()
+				.find(|r| r.id == worker.id)
+			{
+				worker_stats.pow_difficulty = new_difficulty;
+			}
+		}
+	}
+
+	// Whether the pool changed since it was last looked at, such that a block
+	// built now would hold at least `job_min_fee_increase` more fees than
+	// the current job.
+	fn fees_improved(&self, seen_pool: &mut (usize, u64), current_fees: u64) -> bool {
+		let pool = mine_block::pool_state(&self.tx_pool);
+		if pool == *seen_pool {
+			return false;
+		}
+		*seen_pool = pool;
+		match mine_block::mineable_fees(&self.tx_pool) {
+			Ok(fees) => {
+				fees > current_fees && fees - current_fees >= self.config.job_min_fee_increase
+			}
+			Err(e) => {
+				debug!(
+					"(Server ID: {}) Could not evaluate pool fees: {:?}",
+					self.id, e
+				);
+				false
+			}
 		}
 	}
 
@@ -647,7 +970,6 @@ impl StratumServer {
 		self.sync_state = sync_state;
 
 		// "globals" for this function
-		let attempt_time_per_block = self.config.attempt_time_per_block;
 		let mut deadline: i64 = 0;
 		// to prevent the wallet from generating a new HD key derivation for each
 		// iteration, we keep the returned derivation to provide it back when
@@ -657,15 +979,50 @@ impl StratumServer {
 		let mut head = self.chain.head().unwrap();
 		let mut current_hash = head.prev_block_h;
 		let mut latest_hash;
+		// fees of the current job and the pool state it was last compared
+		// against, so the pool is only re-examined when it changes
+		let mut current_fees: u64 = 0;
+		let mut seen_pool = (0, 0);
+		let mut next_refresh: i64 = 0;
 		let listen_addr = self.config.stratum_server_addr.clone().unwrap();
+		let tls_config = match self.config.tls_config() {
+			Ok(None) => None,
+			Ok(Some(conf)) => match conf.build_server_config() {
+				Ok(c) => Some(c),
+				Err(e) => {
+					error!(
+						"(Server ID: {}) Failed to load the stratum TLS certificate, not starting: {}",
+						self.id, e
+					);
+					return;
+				}
+			},
+			Err(e) => {
+				error!(
+					"(Server ID: {}) Bad stratum TLS configuration, not starting: {:?}",
+					self.id, e
+				);
+				return;
+			}
+		};
 		self.current_block_versions.push(Block::default());
 
 		// Start a thread to accept new worker connections
 		let mut workers_th = self.workers.clone();
 		let id_th = self.id.clone();
 		let mut stats_th = stratum_stats.clone();
+		let initial_difficulty = self.config.minimum_share_difficulty;
+		let max_workers = self.config.max_workers;
 		let _listener_th = thread::spawn(move || {
-			accept_workers(id_th, listen_addr, &mut workers_th, &mut stats_th);
+			accept_workers(
+				id_th,
+				listen_addr,
+				initial_difficulty,
+				max_workers,
+				tls_config,
+				&mut workers_th,
+				&mut stats_th,
+			);
 		});
 
 		// We have started
@@ -676,12 +1033,21 @@ impl StratumServer {
 		}
 
 		warn!(
-			"Stratum server started on {}",
-			self.config.stratum_server_addr.clone().unwrap()
+			"Stratum server started on {}{}",
+			self.config.stratum_server_addr.clone().unwrap(),
+			if self.config.tls_certificate_file.is_some() {
+				" (TLS)"
+			} else {
+				""
+			},
 		);
 
-		// Main Loop
-		loop {
+		// Main Loop, until the server stops
+		while !self.stop.load(Ordering::Relaxed) {
+			// mining policy may have been reloaded
+			self.config = self.shared_config.read().clone();
+			self.wallet_listeners.update(&self.config);
+
 			// If we're fallen into sync mode, (or are just starting up,
 			// tell connected clients to stop what they're doing
 			let mining_stopped = self.sync_state.is_syncing();
@@ -695,32 +1061,40 @@ impl StratumServer {
 
 			// Build a new block if:
 			//    There is a new block on the chain
-			// or We are rebuilding the current one to include new transactions
+			// or The current one is too old and gets rebuilt with fresh transactions
+			// or New transactions would improve its fees enough, and it was
+			//    not refreshed too recently
 			// and we're not synching
 			// and there is at least one worker connected
-			if (current_hash != latest_hash || Utc::now().timestamp() >= deadline)
-				&& !mining_stopped
+			let now = Utc::now().timestamp();
+			if !mining_stopped
 				&& num_workers > 0
+				&& (current_hash != latest_hash
+					|| now >= deadline
+					|| (now >= next_refresh && self.fees_improved(&mut seen_pool, current_fees)))
 			{
-				let mut wallet_listener_url: Option<String> = None;
-				if !self.config.burn_reward {
-					wallet_listener_url = Some(self.config.wallet_listener_url.clone());
-				}
 				// If this is a new block, clear the current_block version history
+				// and pick the wallet its reward goes to, versions of the same
+				// block rewarding the same wallet
 				if current_hash != latest_hash {
 					self.current_block_versions.clear();
+					self.current_listeners = self.wallet_listeners.next();
 				}
-				// Build the new block (version)
-				let (new_block, block_fees) = mine_block::get_block(
+				// Build the new block (version), the reward key only reused by
+				// the wallet that derived it
+				seen_pool = mine_block::pool_state(&self.tx_pool);
+				let (new_block, block_fees, key) = mine_block::get_block(
+					&mut self.template_cache,
 					&self.chain,
 					&self.tx_pool,
 					self.verifier_cache.clone(),
-					self.current_key_id.clone(),
-					wallet_listener_url,
+					self.current_key.clone(),
+					self.current_listeners.clone(),
 				);
 				self.current_difficulty =
 					(new_block.header.total_difficulty() - head.total_difficulty).to_num();
-				self.current_key_id = block_fees.key_id();
+				self.current_key = key;
+				current_fees = block_fees.fees;
 				current_hash = latest_hash;
 				// set the minimum acceptable share difficulty for this block
 				self.minimum_share_difficulty = cmp::min(
@@ -728,12 +1102,17 @@ impl StratumServer {
 					self.current_difficulty,
 				);
 				// set a new deadline for rebuilding with fresh transactions
-				deadline = Utc::now().timestamp() + attempt_time_per_block as i64;
+				deadline = Utc::now().timestamp() + self.config.attempt_time_per_block as i64;
+				next_refresh =
+					Utc::now().timestamp() + self.config.job_refresh_interval_secs as i64;
 
 				{
 					let mut stratum_stats = stratum_stats.write();
 					stratum_stats.block_height = new_block.header.height;
 					stratum_stats.network_difficulty = self.current_difficulty;
+					// all kernels but the coinbase one come from transactions
+					stratum_stats.template_tx_count = new_block.kernels().len().saturating_sub(1);
+					stratum_stats.template_fees = block_fees.fees;
 				}
 				// Add this new block version to our current block map
 				self.current_block_versions.push(new_block);
@@ -744,10 +1123,37 @@ impl StratumServer {
 			// Handle any messages from the workers
 			self.handle_rpc_requests(&mut stratum_stats.clone());
 
+			// Move workers' share difficulty toward the target share rate
+			if !mining_stopped {
+				self.retarget_workers(&mut stratum_stats.clone());
+			}
+
 			// sleep before restarting loop
 			thread::sleep(Duration::from_millis(50));
 		} // Main Loop
+
+		self.disconnect_workers(&stratum_stats);
+		warn!("(Server ID: {}) Stratum server stopped", self.id);
 	} // fn run_loop()
+
+	// Drops all worker connections, once the server stops. Shares already
+	// submitted have been handled in the last run of the main loop.
+	fn disconnect_workers(&mut self, stratum_stats: &Arc<RwLock<StratumStats>>) {
+		let mut workers_l = self.workers.lock();
+		let mut stratum_stats = stratum_stats.write();
+		for worker in workers_l.iter() {
+			if let Some(worker_stats) = stratum_stats
+				.worker_stats
+				.iter_mut()
+				.find(|r| r.id == worker.id)
+			{
+				worker_stats.is_connected = false;
+			}
+		}
+		workers_l.clear();
+		stratum_stats.num_workers = 0;
+		stratum_stats.is_running = false;
+	}
 } // StratumServer
 
 // Utility function to parse a JSON RPC parameter object, returning a proper
@@ -767,3 +1173,50 @@ where
 		})
 }
 
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn window() -> Duration {
+		Duration::from_secs(60)
+	}
+
+	fn retarget(shares: u64, difficulty: u64) -> Option<u64> {
+		let start = Instant::now();
+		let mut vardiff = VarDiff::new(start);
+		for _ in 0..shares {
+			vardiff.add_share();
+		}
+		vardiff.retarget(start + window(), window(), 10, difficulty, 1, 1_000_000)
+	}
+
+	#[test]
+	fn vardiff_retargets_toward_share_rate() {
+		// on target, nothing to do
+		assert_eq!(retarget(10, 100), None);
+		// twice too many shares, twice the difficulty
+		assert_eq!(retarget(20, 100), Some(200));
+		// half the shares, half the difficulty
+		assert_eq!(retarget(5, 100), Some(50));
+		// moves at most by VARDIFF_MAX_STEP at once
+		assert_eq!(retarget(1000, 100), Some(400));
+		assert_eq!(retarget(0, 100), Some(25));
+		// and stays within bounds
+		assert_eq!(retarget(0, 1), None);
+		assert_eq!(retarget(1000, 500_000), Some(1_000_000));
+	}
+
+	#[test]
+	fn vardiff_waits_for_window() {
+		let start = Instant::now();
+		let mut vardiff = VarDiff::new(start);
+		vardiff.add_share();
+		let early = start + Duration::from_secs(30);
+		assert_eq!(vardiff.retarget(early, window(), 10, 100, 1, 1000), None);
+		// disabled with no target
+		assert_eq!(
+			vardiff.retarget(start + window(), window(), 0, 100, 1, 1000),
+			None
+		);
+	}
+}
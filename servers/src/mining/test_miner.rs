@@ -29,7 +29,7 @@ use core::core::verifier_cache::VerifierCache;
 use core::core::{Block, BlockHeader};
 use core::global;
 use core::pow::PoWContext;
-use mining::mine_block;
+use mining::mine_block::{self, TemplateCache};
 use pool;
 
 pub struct Miner {
@@ -130,21 +130,23 @@ impl Miner {
 
 		// iteration, we keep the returned derivation to provide it back when
 		// nothing has changed. We only want to create a new key_id for each new block.
-		let mut key_id = None;
+		let mut key = None;
+		let mut template_cache = TemplateCache::default();
 
 		while !self.stop.load(Ordering::Relaxed) {
-			trace!("in miner loop. key_id: {:?}", key_id);
+			trace!("in miner loop. key: {:?}", key);
 
 			// get the latest chain state and build a block on top of it
 			let head = self.chain.head_header().unwrap();
 			let mut latest_hash = self.chain.head().unwrap().last_block_h;
 
-			let (mut b, block_fees) = mine_block::get_block(
+			let (mut b, block_fees, block_key) = mine_block::get_block(
+				&mut template_cache,
 				&self.chain,
 				&self.tx_pool,
 				self.verifier_cache.clone(),
-				key_id.clone(),
-				wallet_listener_url.clone(),
+				key.clone(),
+				wallet_listener_url.clone().into_iter().collect(),
 			);
 
 			let sol = self.inner_mining_loop(
@@ -170,13 +172,13 @@ impl Miner {
 					);
 				}
 				trace!("resetting key_id in miner to None");
-				key_id = None;
+				key = None;
 			} else {
 				debug!(
 					"setting pubkey in miner to pubkey from block_fees - {:?}",
 					block_fees
 				);
-				key_id = block_fees.key_id();
+				key = block_key;
 			}
 		}
 
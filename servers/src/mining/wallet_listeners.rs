@@ -0,0 +1,138 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Spreads block rewards across several wallet receivers, by weight.
+
+use common::types::{StratumServerConfig, WalletListener};
+
+/// Picks the wallet receiver each new block rewards, with a smooth weighted
+/// round-robin: over any run of blocks each receiver gets its share, without
+/// long streaks of the same one.
+pub struct WalletListeners {
+	listeners: Vec<WalletListener>,
+	current: Vec<i64>,
+}
+
+impl WalletListeners {
+	/// Receivers from the stratum configuration, the single
+	/// `wallet_listener_url` when no weighted list is set, none when burning
+	/// the rewards
+	pub fn new(config: &StratumServerConfig) -> WalletListeners {
+		let listeners = WalletListeners::configured(config);
+		WalletListeners {
+			current: vec![0; listeners.len()],
+			listeners,
+		}
+	}
+
+	fn configured(config: &StratumServerConfig) -> Vec<WalletListener> {
+		if config.burn_reward {
+			vec![]
+		} else if config.wallet_listeners.is_empty() {
+			vec![WalletListener {
+				url: config.wallet_listener_url.clone(),
+				weight: 1,
+			}]
+		} else {
+			config.wallet_listeners.clone()
+		}
+	}
+
+	/// Takes a reloaded configuration into account, starting the rotation
+	/// over only if the receivers changed
+	pub fn update(&mut self, config: &StratumServerConfig) {
+		if WalletListeners::configured(config) != self.listeners {
+			*self = WalletListeners::new(config);
+		}
+	}
+
+	/// Receivers to ask for the next block reward: the one whose turn it is
+	/// first, then the others by decreasing weight to fail over to. Empty
+	/// when the reward gets burnt.
+	pub fn next(&mut self) -> Vec<String> {
+		let total: i64 = self.listeners.iter().map(|l| l.weight as i64).sum();
+		let mut order: Vec<usize> = (0..self.listeners.len()).collect();
+		order.sort_by_key(|&i| -(self.listeners[i].weight as i64));
+
+		if total > 0 {
+			for (i, l) in self.listeners.iter().enumerate() {
+				self.current[i] += l.weight as i64;
+			}
+			let current = &self.current;
+			let chosen = (0..current.len())
+				.max_by_key(|&i| (current[i], -(i as i64)))
+				.unwrap();
+			self.current[chosen] -= total;
+			order.retain(|&i| i != chosen);
+			order.insert(0, chosen);
+		}
+		order
+			.into_iter()
+			.map(|i| self.listeners[i].url.clone())
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn config(weights: &[u32]) -> StratumServerConfig {
+		StratumServerConfig {
+			wallet_listeners: weights
+				.iter()
+				.enumerate()
+				.map(|(i, w)| WalletListener {
+					url: format!("http://127.0.0.1:{}", 13415 + i),
+					weight: *w,
+				}).collect(),
+			..StratumServerConfig::default()
+		}
+	}
+
+	#[test]
+	fn spread_by_weight() {
+		let mut listeners = WalletListeners::new(&config(&[50, 30, 20]));
+		let mut counts = vec![0; 3];
+		for _ in 0..100 {
+			let order = listeners.next();
+			assert_eq!(order.len(), 3);
+			let port: usize = order[0].rsplit(':').next().unwrap().parse().unwrap();
+			counts[port - 13415] += 1;
+		}
+		assert_eq!(counts, vec![50, 30, 20]);
+	}
+
+	#[test]
+	fn failover_only() {
+		let mut listeners = WalletListeners::new(&config(&[1, 0]));
+		for _ in 0..5 {
+			assert_eq!(
+				listeners.next(),
+				vec!["http://127.0.0.1:13415", "http://127.0.0.1:13416"]
+			);
+		}
+	}
+
+	#[test]
+	fn single_or_burnt() {
+		let mut c = StratumServerConfig::default();
+		assert_eq!(
+			WalletListeners::new(&c).next(),
+			vec![c.wallet_listener_url.clone()]
+		);
+		c.burn_reward = true;
+		assert!(WalletListeners::new(&c).next().is_empty());
+	}
+}
@@ -23,13 +23,19 @@ extern crate grin_p2p as p2p;
 extern crate grin_servers as servers;
 extern crate grin_util as util;
 extern crate grin_wallet as wallet;
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
 
 mod framework;
 
+use std::net::TcpListener;
 use std::sync::Arc;
 use std::{thread, time};
 use util::Mutex;
 
+use core::core::hash::Hashed;
+use core::core::pmmr;
 use core::global::{self, ChainTypes};
 
 use framework::{LocalServerContainer, LocalServerContainerConfig};
@@ -88,8 +94,17 @@ fn simple_server_wallet() {
 	assert!(tip.is_ok());
 
 	warn!("Testing status handler");
-	let status = get_status(&base_addr, api_server_port);
-	assert!(status.is_ok());
+	let status = get_status(&base_addr, api_server_port).unwrap();
+	assert!(status.sync_stage.is_some());
+	assert!(status.disk_usage.unwrap() > 0);
+	assert_eq!(
+		status.inbound_peers + status.outbound_peers,
+		status.connections
+	);
+
+	warn!("Testing sync status handler");
+	let sync_info = get_sync_status(&base_addr, api_server_port).unwrap();
+	assert!(["waiting", "header", "body", "synced"].contains(&sync_info.stage.as_str()));
 
 	// Be sure that at least a block is mined by Travis
 	let mut current_tip = get_tip(&base_addr, api_server_port).unwrap();
@@ -112,6 +127,95 @@ fn simple_server_wallet() {
 		get_block_by_hash_compact(&base_addr, api_server_port, &block_hash);
 	assert!(last_block_by_hash_compact.is_ok());
 
+	// the binary serializations, for clients linking core
+	let url = format!(
+		"http://{}:{}/v1/blocks/{}",
+		base_addr, api_server_port, block_hash
+	);
+	let block: core::core::Block = api::client::get_binary(&url, None).unwrap();
+	assert_eq!(block.hash().to_hex(), block_hash);
+	let url = format!("{}?compact", url);
+	let compact: core::core::CompactBlock = api::client::get_binary(&url, None).unwrap();
+	assert_eq!(compact.hash(), block.hash());
+	let url = format!(
+		"http://{}:{}/v1/headers/{}",
+		base_addr, api_server_port, current_tip.height
+	);
+	let header: core::core::BlockHeader = api::client::get_binary(&url, None).unwrap();
+	assert_eq!(header, block.header);
+	let url = format!(
+		"http://{}:{}/v2/headers/{}",
+		base_addr, api_server_port, block_hash
+	);
+	let header: core::core::BlockHeader = api::client::get_binary(&url, None).unwrap();
+	assert_eq!(header, block.header);
+
+	warn!("Testing typed node client");
+	let node = api::NodeClient::new(&format!("http://{}:{}/", base_addr, api_server_port), None);
+	assert_eq!(node.get_tip().unwrap().height, current_tip.height);
+	let by_height = node
+		.get_full_block(api::BlockRef::Height(current_tip.height))
+		.unwrap();
+	assert_eq!(by_height.hash(), block.hash());
+	let printable = node.get_block(api::BlockRef::Hash(block.hash())).unwrap();
+	assert_eq!(printable.header.hash, block_hash);
+	let header = node.get_header(api::BlockRef::Hash(block.hash())).unwrap();
+	assert_eq!(header.height, current_tip.height);
+	let commits: Vec<_> = block.outputs().iter().map(|o| o.commitment()).collect();
+	assert_eq!(node.get_outputs(&commits).unwrap().len(), commits.len());
+	let excesses: Vec<_> = block.kernels().iter().map(|k| k.excess).collect();
+	let found = node.lookup(&excesses, &commits).unwrap();
+	let height = current_tip.height;
+	assert!(found.kernels.iter().all(|k| k.height == Some(height)));
+	assert!(found.outputs.iter().all(|o| o.found));
+	// a commitment is no kernel excess
+	let found = node.lookup(&commits, &[]).unwrap();
+	assert!(found.kernels.iter().all(|k| !k.found && k.height.is_none()));
+	assert!(node.get_connected_peers().unwrap().is_empty());
+
+	let range = format!("start_height=0&end_height={}", current_tip.height);
+	let headers = get_headers_range(&base_addr, api_server_port, &range).unwrap();
+	assert_eq!(headers.items.len() as u64, current_tip.height + 1);
+	assert_eq!(headers.items[0].height, 0);
+	assert_eq!(headers.next_height, None);
+	let headers =
+		get_headers_range(&base_addr, api_server_port, &(range.clone() + "&max=1")).unwrap();
+	assert_eq!(headers.items.len(), 1);
+	assert_eq!(headers.next_height, Some(1));
+	let blocks =
+		get_blocks_range::<api::BlockPrintable>(&base_addr, api_server_port, &range).unwrap();
+	assert_eq!(blocks.items.len() as u64, current_tip.height + 1);
+	assert_eq!(blocks.items[0].header.height, 0);
+	let blocks = get_blocks_range::<api::CompactBlockPrintable>(
+		&base_addr,
+		api_server_port,
+		&(range.clone() + "&compact"),
+	).unwrap();
+	assert_eq!(blocks.items.len() as u64, current_tip.height + 1);
+	assert!(get_headers_range(&base_addr, api_server_port, "end_height=1").is_err());
+
+	// outputs and kernels of a range of blocks, at their MMR positions
+	let contents = get_contents_range(&base_addr, api_server_port, &range).unwrap();
+	assert_eq!(contents.items.len() as u64, current_tip.height + 1);
+	let tip_contents = contents.items.last().unwrap();
+	assert_eq!(tip_contents.outputs.len(), block.outputs().len());
+	assert_eq!(tip_contents.kernels.len(), block.kernels().len());
+	let mut output_pos = 0;
+	let mut kernel_pos = 0;
+	for contents in &contents.items {
+		for output in &contents.outputs {
+			assert!(output.mmr_index > output_pos);
+			output_pos = output.mmr_index;
+		}
+		for kernel in &contents.kernels {
+			assert!(kernel.mmr_index > kernel_pos);
+			kernel_pos = kernel.mmr_index;
+		}
+	}
+	let last_leaf = |size| pmmr::insertion_to_pmmr_index(pmmr::n_leaves(size));
+	assert_eq!(output_pos, last_leaf(block.header.output_mmr_size));
+	assert_eq!(kernel_pos, last_leaf(block.header.kernel_mmr_size));
+
 	warn!("Testing chain output handler");
 	let start_height = 0;
 	let end_height = current_tip.height;
@@ -123,6 +227,55 @@ fn simple_server_wallet() {
 	assert!(outputs_by_ids1.is_ok());
 	let outputs_by_ids2 = get_outputs_by_ids2(&base_addr, api_server_port, ids.clone());
 	assert!(outputs_by_ids2.is_ok());
+	assert!(get_outputs_by_height(&base_addr, api_server_port, 0, 5000).is_err());
+
+	// paging through the unspent outputs gives them all, once
+	let mut unspent = vec![];
+	let mut cursor = Some(1);
+	while let Some(c) = cursor {
+		let page = get_outputs_unspent(&base_addr, api_server_port, c, "&max=2&coinbase").unwrap();
+		assert!(page.outputs.len() <= 2);
+		unspent.extend(page.outputs.into_iter().map(|o| util::to_hex(o.commit.0.to_vec())));
+		cursor = page.next_cursor;
+	}
+	for id in &ids {
+		assert_eq!(unspent.iter().filter(|c| *c == id).count(), 1);
+	}
+	let page = get_outputs_unspent(&base_addr, api_server_port, 1, "&start_height=1&end_height=1")
+		.unwrap();
+	assert_eq!(page.outputs.len(), 1);
+	assert_eq!(page.outputs[0].block_height, Some(1));
+
+	warn!("Testing JSON-RPC handler");
+	let rpc = |req: serde_json::Value| -> serde_json::Value {
+		let url = format!("http://{}:{}/v1/jsonrpc", base_addr, api_server_port);
+		api::client::post(url.as_str(), None, &req).unwrap()
+	};
+	let res = rpc(json!({"jsonrpc": "2.0", "method": "get_tip", "id": 1}));
+	assert_eq!(res["id"], 1);
+	assert!(res["result"]["height"].as_u64().unwrap() >= current_tip.height);
+	let res = rpc(json!([
+		{"jsonrpc": "2.0", "method": "get_header", "params": [1], "id": "a"},
+		{"jsonrpc": "2.0", "method": "get_block", "params": {"id": 1, "compact": true}, "id": "b"},
+		{"jsonrpc": "2.0", "method": "get_pool_size"},
+		{"jsonrpc": "2.0", "method": "no_such_method", "id": "c"},
+		{"jsonrpc": "2.0", "method": "get_header", "params": [[]], "id": "d"},
+		{"method": "get_tip", "id": "e"},
+	]));
+	let res = res.as_array().unwrap();
+	// the notification gets no answer
+	assert_eq!(res.len(), 5);
+	assert_eq!(res[0]["result"]["height"], 1);
+	assert_eq!(res[1]["result"]["header"]["height"], 1);
+	assert_eq!(res[2]["error"]["code"], -32601);
+	assert_eq!(res[3]["error"]["code"], -32602);
+	assert_eq!(res[4]["error"]["code"], -32600);
+	let res = rpc(json!({"jsonrpc": "2.0", "method": "rpc.methods", "id": 2}));
+	assert!(res["result"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.any(|m| m["name"] == "push_transaction" && m["owner_only"] == true));
 
 	warn!("Testing txhashset handler");
 	let roots = get_txhashset_roots(&base_addr, api_server_port);
@@ -205,6 +358,12 @@ fn test_p2p() {
 	let pc = peers_connected.unwrap();
 	assert_eq!(pc.len(), 1);
 
+	// Synced and connected, so ready
+	let readiness = get_readiness(&base_addr, api_server_port).unwrap();
+	assert!(readiness.ready);
+	assert_eq!(readiness.peer_count, 1);
+	assert!(readiness.db_writable);
+
 	// Check that the peer status is Healthy
 	let addr = format!(
 		"{}:{}",
@@ -229,6 +388,41 @@ fn test_p2p() {
 	assert!(peers_all.is_ok());
 	assert_eq!(peers_all.unwrap().len(), 2);
 
+	// Without peers, the node isn't ready anymore
+	assert!(get_readiness(&base_addr, api_server_port).is_err());
+
+	// The ban list has it, for the configured ban window
+	let banned = get_banned_peers(&base_addr, api_server_port).unwrap();
+	assert_eq!(banned.len(), 1);
+	assert_eq!(banned[0].addr.to_string(), addr);
+	assert_eq!(banned[0].ban_reason, p2p::ReasonForBan::ManualBan);
+	assert_eq!(banned[0].expires_at - banned[0].banned_at, 10800);
+	assert!(banned[0].note.is_empty());
+
+	// Peers never heard of can be banned too, with a reason and a duration,
+	// and aren't connected to anymore
+	let unknown = "10.0.0.9:13414".to_string();
+	let url = format!(
+		"http://{}:{}/v1/peers/{}/ban?reason=BadBlock&duration=60&note=bad%20block%20at%2012",
+		base_addr, api_server_port, unknown
+	);
+	assert!(api::client::post_no_ret(url.as_str(), None, &"").is_ok());
+	let banned = get_banned_peers(&base_addr, api_server_port).unwrap();
+	let banned = banned
+		.iter()
+		.find(|b| b.addr.to_string() == unknown)
+		.unwrap();
+	assert_eq!(banned.ban_reason, p2p::ReasonForBan::BadBlock);
+	assert_eq!(banned.expires_at - banned.banned_at, 60);
+	assert_eq!(banned.note, "bad block at 12");
+	assert!(connect_peer(&base_addr, api_server_port, &unknown).is_err());
+	let url = format!(
+		"http://{}:{}/v1/peers/{}/ban?reason=Whatever",
+		base_addr, api_server_port, unknown
+	);
+	assert!(api::client::post_no_ret(url.as_str(), None, &"").is_err());
+	assert!(unban_peer(&base_addr, api_server_port, &unknown).is_ok());
+
 	// Unban
 	let unban_result = unban_peer(&base_addr, api_server_port, &addr);
 	assert!(unban_result.is_ok());
@@ -242,6 +436,39 @@ fn test_p2p() {
 	let peer = get_peer(&base_addr, api_server_port, &addr);
 	assert!(peer.is_ok());
 	assert_eq!(peer.unwrap().flags, p2p::State::Healthy);
+	assert!(get_banned_peers(&base_addr, api_server_port)
+		.unwrap()
+		.is_empty());
+
+	// Dial it back
+	let peer = connect_peer(&base_addr, api_server_port, &addr);
+	assert_eq!(peer.unwrap().addr.to_string(), addr);
+	let peers_connected = get_connected_peers(&base_addr, api_server_port);
+	assert_eq!(peers_connected.unwrap().len(), 1);
+}
+
+#[test]
+fn api_tls_config() {
+	let mut config = servers::ServerConfig::default();
+	assert!(config.tls_config().unwrap().is_none());
+	assert_eq!(config.api_url(), "http://127.0.0.1:13413");
+
+	config.tls_certificate_key = Some("privkey.pem".to_owned());
+	assert!(config.tls_config().is_err());
+	config.tls_certificate_key = None;
+	config.tls_certificate_file = Some("fullchain.pem".to_owned());
+	assert!(config.tls_config().is_err());
+	config.tls_certificate_key = Some("privkey.pem".to_owned());
+	assert!(config.tls_config().unwrap().is_some());
+	assert_eq!(config.api_url(), "https://127.0.0.1:13413");
+
+	// a node that can't serve its api doesn't start, nor listen for peers
+	config.db_root = "target/tmp/api_tls_config".to_owned();
+	config.p2p_config.seeding_type = p2p::Seeding::None;
+	config.p2p_config.port = 40110;
+	config.api_http_addr = "127.0.0.1:40111".to_owned();
+	assert!(servers::Server::new(config).is_err());
+	assert!(TcpListener::bind("0.0.0.0:40110").is_ok());
 }
 
 // Tip handler function
@@ -256,6 +483,19 @@ fn get_status(base_addr: &String, api_server_port: u16) -> Result<api::Status, E
 	api::client::get::<api::Status>(url.as_str(), None).map_err(|e| Error::API(e))
 }
 
+fn get_sync_status(
+	base_addr: &String,
+	api_server_port: u16,
+) -> Result<servers::SyncInfo, Error> {
+	let url = format!(
+		"http://{}:{}{}",
+		base_addr,
+		api_server_port,
+		servers::SYNC_STATUS_PATH
+	);
+	api::client::get::<servers::SyncInfo>(url.as_str(), None).map_err(Error::API)
+}
+
 // Block handler functions
 fn get_block_by_height(
 	base_addr: &String,
@@ -350,6 +590,59 @@ fn get_outputs_by_height(
 	api::client::get::<Vec<api::BlockOutputs>>(url.as_str(), None).map_err(|e| Error::API(e))
 }
 
+fn get_outputs_unspent(
+	base_addr: &String,
+	api_server_port: u16,
+	cursor: u64,
+	params: &str,
+) -> Result<api::OutputPage, Error> {
+	let url = format!(
+		"http://{}:{}/v1/chain/outputs/unspent?cursor={}{}",
+		base_addr, api_server_port, cursor, params
+	);
+	api::client::get::<api::OutputPage>(url.as_str(), None).map_err(Error::API)
+}
+
+fn get_headers_range(
+	base_addr: &String,
+	api_server_port: u16,
+	params: &str,
+) -> Result<api::HeightPage<api::BlockHeaderPrintable>, Error> {
+	let url = format!(
+		"http://{}:{}/v1/headers?{}",
+		base_addr, api_server_port, params
+	);
+	api::client::get::<api::HeightPage<api::BlockHeaderPrintable>>(url.as_str(), None)
+		.map_err(Error::API)
+}
+
+fn get_blocks_range<T>(
+	base_addr: &String,
+	api_server_port: u16,
+	params: &str,
+) -> Result<api::HeightPage<T>, Error>
+where
+	for<'de> T: serde::Deserialize<'de>,
+{
+	let url = format!(
+		"http://{}:{}/v1/blocks?{}",
+		base_addr, api_server_port, params
+	);
+	api::client::get::<api::HeightPage<T>>(url.as_str(), None).map_err(Error::API)
+}
+
+fn get_contents_range(
+	base_addr: &String,
+	api_server_port: u16,
+	params: &str,
+) -> Result<api::HeightPage<api::BlockContents>, Error> {
+	let url = format!(
+		"http://{}:{}/v1/chain/contents?{}",
+		base_addr, api_server_port, params
+	);
+	api::client::get::<api::HeightPage<api::BlockContents>>(url.as_str(), None).map_err(Error::API)
+}
+
 // TxHashSet handler functions
 fn get_txhashset_roots(base_addr: &String, api_server_port: u16) -> Result<api::TxHashSet, Error> {
 	let url = format!(
@@ -452,6 +745,31 @@ pub fn unban_peer(
 	api::client::post_no_ret(url.as_str(), None, &"").map_err(|e| Error::API(e))
 }
 
+pub fn connect_peer(
+	base_addr: &String,
+	api_server_port: u16,
+	peer_addr: &String,
+) -> Result<p2p::types::PeerInfoDisplay, Error> {
+	let url = format!(
+		"http://{}:{}/v1/peers/{}/connect",
+		base_addr, api_server_port, peer_addr
+	);
+	api::client::post(url.as_str(), None, &"").map_err(Error::API)
+}
+
+pub fn get_readiness(base_addr: &String, api_server_port: u16) -> Result<api::Readiness, Error> {
+	let url = format!("http://{}:{}{}", base_addr, api_server_port, api::READY_PATH);
+	api::client::get::<api::Readiness>(url.as_str(), None).map_err(Error::API)
+}
+
+pub fn get_banned_peers(
+	base_addr: &String,
+	api_server_port: u16,
+) -> Result<Vec<api::BannedPeer>, Error> {
+	let url = format!("http://{}:{}/v1/peers/banned", base_addr, api_server_port);
+	api::client::get::<Vec<api::BannedPeer>>(url.as_str(), None).map_err(Error::API)
+}
+
 pub fn get_peer(
 	base_addr: &String,
 	api_server_port: u16,
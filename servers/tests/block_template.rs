@@ -0,0 +1,256 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_pool as pool;
+extern crate grin_servers as servers;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate hyper;
+extern crate serde_json;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use hyper::{Body, Request, StatusCode};
+
+use core::core::hash::Hashed;
+use core::core::verifier_cache::LruVerifierCache;
+use core::global::{self, ChainTypes};
+use core::pow::{self, PoWContext};
+use core::ser;
+use keychain::{ExtKeychain, Identifier, Keychain};
+use servers::common::adapters::PoolToChainAdapter;
+use servers::common::types::{SyncState, SyncStatus};
+use servers::{
+	BlockSubmission, BlockTemplate, BlockTemplateHandler, StratumServerConfig, SubmittedBlock,
+	WalletListener, BLOCK_SUBMIT_PATH, BLOCK_TEMPLATE_PATH,
+};
+use util::{MemoryBudget, RwLock};
+use wallet::{BlockFees, CbData};
+
+/// A wallet listener building coinbases with its own keys, answering the
+/// first `failures` requests with a 500, handing over the key ids asked for
+fn wallet_listener(failures: usize) -> (String, mpsc::Receiver<Option<Identifier>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}", listener.local_addr().unwrap());
+	let (tx, rx) = mpsc::channel();
+	thread::spawn(move || {
+		let keychain = ExtKeychain::from_random_seed().unwrap();
+		for (n, stream) in listener.incoming().enumerate() {
+			let mut stream = BufReader::new(stream.unwrap());
+			let mut len = 0;
+			loop {
+				let mut line = String::new();
+				stream.read_line(&mut line).unwrap();
+				if line.trim().is_empty() {
+					break;
+				}
+				let line = line.to_lowercase();
+				if line.starts_with("content-length:") {
+					len = line[15..].trim().parse().unwrap();
+				}
+			}
+			let mut body = vec![0; len];
+			stream.read_exact(&mut body).unwrap();
+			let block_fees: BlockFees = serde_json::from_slice(&body).unwrap();
+			tx.send(block_fees.key_id()).unwrap();
+
+			let res = if n < failures {
+				"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\
+				 Connection: close\r\n\r\n"
+					.to_owned()
+			} else {
+				let key_id = block_fees
+					.key_id()
+					.unwrap_or(ExtKeychain::derive_key_id(1, n as u32, 0, 0, 0));
+				let (output, kernel) = wallet::libtx::reward::output(
+					&keychain,
+					&key_id,
+					block_fees.fees,
+					block_fees.height,
+				).unwrap();
+				let cb_data = serde_json::to_string(&CbData {
+					output: util::to_hex(ser::ser_vec(&output).unwrap()),
+					kernel: util::to_hex(ser::ser_vec(&kernel).unwrap()),
+					key_id: util::to_hex(ser::ser_vec(&key_id).unwrap()),
+				}).unwrap();
+				format!(
+					"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+					 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+					cb_data.len(),
+					cb_data
+				)
+			};
+			stream.get_mut().write_all(res.as_bytes()).unwrap();
+		}
+	});
+	(url, rx)
+}
+
+fn post(handler: &api::HandlerObj, path: &str, body: String) -> (StatusCode, Vec<u8>) {
+	let req = Request::post(format!("http://127.0.0.1{}", path))
+		.body(Body::from(body))
+		.unwrap();
+	let res = handler.post(req).wait().unwrap();
+	let status = res.status();
+	(status, res.into_body().concat2().wait().unwrap().to_vec())
+}
+
+fn template(handler: &api::HandlerObj) -> BlockTemplate {
+	let (status, body) = post(handler, BLOCK_TEMPLATE_PATH, String::new());
+	assert_eq!(status, StatusCode::OK);
+	serde_json::from_slice(&body).unwrap()
+}
+
+fn submit(handler: &api::HandlerObj, submission: &BlockSubmission) -> (StatusCode, Vec<u8>) {
+	post(
+		handler,
+		BLOCK_SUBMIT_PATH,
+		serde_json::to_string(submission).unwrap(),
+	)
+}
+
+// Looks for a proof of work on the template, as a mining controller would
+fn solve(template: &BlockTemplate) -> BlockSubmission {
+	let pre_pow = util::from_hex(template.pre_pow.clone()).unwrap();
+	let edge_bits = template.min_edge_bits;
+	let mut nonce = 0u64;
+	loop {
+		let mut header = pre_pow.clone();
+		{
+			let mut writer = ser::BinWriter::new(&mut header);
+			ser::Writer::write_u64(&mut writer, nonce).unwrap();
+		}
+		let mut ctx =
+			global::create_pow_context::<u32>(edge_bits, template.proof_size, 10).unwrap();
+		ctx.set_header_nonce(header, None, true).unwrap();
+		if let Ok(proofs) = ctx.find_cycles() {
+			return BlockSubmission {
+				template_id: template.template_id,
+				nonce,
+				edge_bits,
+				pow: proofs[0].nonces.clone(),
+			};
+		}
+		nonce += 1;
+	}
+}
+
+// Templates for the same block reuse the reward key of the wallet listener
+// that derived it, never handing it to another one, and a solved template
+// gets its block added to the chain
+#[test]
+fn block_template() {
+	util::init_test_logger();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let dir = "target/block_template";
+	let _ = fs::remove_dir_all(dir);
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let chain = Arc::new(
+		chain::Chain::init(
+			dir.to_string(),
+			Arc::new(store::new_env(dir.to_string())),
+			Arc::new(chain::types::NoopAdapter {}),
+			pow::mine_genesis_block().unwrap(),
+			pow::verify_size,
+			verifier_cache.clone(),
+			false,
+			Arc::new(MemoryBudget::unlimited()),
+		).unwrap(),
+	);
+	let pool_adapter = Arc::new(PoolToChainAdapter::new());
+	pool_adapter.set_chain(chain.clone());
+	let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
+		pool::PoolConfig::default(),
+		pool_adapter,
+		verifier_cache.clone(),
+		Arc::new(pool::types::NoopAdapter {}),
+		Arc::new(MemoryBudget::unlimited()),
+	)));
+	let sync_state = Arc::new(SyncState::new());
+	sync_state.update(SyncStatus::NoSync);
+
+	// the first listener fails over to the second once, then recovers
+	let (first_url, first) = wallet_listener(1);
+	let (second_url, second) = wallet_listener(0);
+	let stratum_config = Arc::new(RwLock::new(StratumServerConfig {
+		wallet_listeners: vec![
+			WalletListener {
+				url: first_url,
+				weight: 1,
+			},
+			WalletListener {
+				url: second_url,
+				weight: 0,
+			},
+		],
+		..StratumServerConfig::default()
+	}));
+	let routes = BlockTemplateHandler::routes(
+		&chain,
+		&tx_pool,
+		verifier_cache,
+		&stratum_config,
+		&sync_state,
+	);
+	let handler = routes[0].1.clone();
+	let recv =
+		|rx: &mpsc::Receiver<Option<Identifier>>| rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+	// the second listener derives the key while the first is down
+	template(&handler);
+	assert_eq!(recv(&first), None);
+	assert_eq!(recv(&second), None);
+	// the first one back, it isn't handed the key of the second
+	template(&handler);
+	assert_eq!(recv(&first), None);
+	// but gets its own key back for the next version of the block
+	let t = template(&handler);
+	let key_id = recv(&first);
+	assert!(key_id.is_some());
+	assert!(second.try_recv().is_err());
+	assert_eq!(t.height, 1);
+
+	let (status, body) = submit(&handler, &solve(&t));
+	assert_eq!(status, StatusCode::OK);
+	let submitted: SubmittedBlock = serde_json::from_slice(&body).unwrap();
+	assert_eq!(submitted.height, 1);
+	assert_eq!(submitted.hash, chain.head_header().unwrap().hash().to_hex());
+
+	// solved already, or never handed out
+	let (status, _) = submit(&handler, &solve(&t));
+	assert_eq!(status, StatusCode::CONFLICT);
+	let unknown = BlockSubmission {
+		template_id: 100,
+		..solve(&t)
+	};
+	assert_eq!(submit(&handler, &unknown).0, StatusCode::BAD_REQUEST);
+
+	// a new block gets a new key
+	let t = template(&handler);
+	assert_eq!(t.height, 2);
+	assert_eq!(recv(&first), None);
+}
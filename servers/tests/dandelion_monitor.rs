@@ -0,0 +1,142 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate blake2_rfc as blake2;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_pool as pool;
+extern crate grin_servers as servers;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+
+extern crate chrono;
+extern crate rand;
+
+#[path = "../../pool/tests/common/mod.rs"]
+pub mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+use util::{MemoryBudget, Mutex, RwLock};
+
+use common::*;
+use core::core::verifier_cache::LruVerifierCache;
+use core::core::{Block, BlockHeader, Transaction};
+use core::pow::Difficulty;
+use keychain::{ExtKeychain, Keychain};
+use pool::{DandelionConfig, PoolAdapter, PoolConfig, PoolEntryState, PoolError};
+use wallet::libtx;
+
+// Keeps the txs sent along the stem
+struct StemAdapter {
+	stemmed: Mutex<Vec<Transaction>>,
+}
+
+impl PoolAdapter for StemAdapter {
+	fn tx_accepted(&self, _tx: &Transaction) {}
+	fn stem_tx_accepted(&self, tx: &Transaction) -> Result<(), PoolError> {
+		self.stemmed.lock().push(tx.clone());
+		Ok(())
+	}
+}
+
+/// Stem txs received within the aggregation period are held and sent along
+/// the stem together, as a single aggregated tx.
+#[test]
+fn test_dandelion_aggregation() {
+	let keychain: ExtKeychain = Keychain::from_random_seed().unwrap();
+
+	let db_root = ".grin_dandelion_aggregation".to_string();
+	clean_output_dir(db_root.clone());
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let adapter = Arc::new(StemAdapter {
+		stemmed: Mutex::new(vec![]),
+	});
+	let pool = Arc::new(RwLock::new(pool::TransactionPool::new(
+		PoolConfig {
+			accept_fee_base: 0,
+			max_pool_size: 50,
+			max_stempool_size: 50,
+			min_input_confirmations: 0,
+			tx_ttl_blocks: 0,
+		},
+		chain.clone(),
+		verifier_cache.clone(),
+		adapter.clone(),
+		Arc::new(MemoryBudget::unlimited()),
+	)));
+
+	let header = {
+		let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+		let reward = libtx::reward::output(&keychain, &key_id, 0, 1).unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+		chain.update_db_for_block(&block);
+		block.header
+	};
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700]);
+	pool.write()
+		.add_to_pool(test_source(), initial_tx, false, &header)
+		.unwrap();
+
+	let config = DandelionConfig {
+		relay_secs: Some(1),
+		embargo_secs: Some(30),
+		embargo_jitter_secs: Some(0),
+		patience_secs: Some(1),
+		aggregation_secs: Some(3),
+		stem_probability: Some(100),
+	};
+	let stop = Arc::new(AtomicBool::new(false));
+	servers::monitor_transactions(
+		Arc::new(RwLock::new(config)),
+		pool.clone(),
+		verifier_cache.clone(),
+		stop.clone(),
+	);
+
+	// a second stem tx comes along while the first one is held
+	let tx1 = test_transaction(&keychain, vec![500], vec![499]);
+	let tx2 = test_transaction(&keychain, vec![600, 700], vec![1299]);
+	pool.write()
+		.add_to_pool(test_source(), tx1, true, &header)
+		.unwrap();
+	thread::sleep(time::Duration::from_millis(1500));
+	assert!(adapter.stemmed.lock().is_empty());
+	pool.write()
+		.add_to_pool(test_source(), tx2, true, &header)
+		.unwrap();
+
+	for _ in 0..100 {
+		if !adapter.stemmed.lock().is_empty() {
+			break;
+		}
+		thread::sleep(time::Duration::from_millis(100));
+	}
+	stop.store(true, Ordering::Relaxed);
+
+	let stemmed = adapter.stemmed.lock();
+	assert_eq!(stemmed.len(), 1);
+	assert_eq!(stemmed[0].kernels().len(), 2);
+	let pool = pool.read();
+	assert_eq!(pool.stempool.size(), 2);
+	assert!(pool
+		.stempool
+		.entries
+		.iter()
+		.all(|e| e.state == PoolEntryState::Stemmed));
+}
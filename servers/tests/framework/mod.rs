@@ -17,11 +17,15 @@ extern crate grin_chain as chain;
 extern crate grin_core as core;
 extern crate grin_keychain as keychain;
 extern crate grin_p2p as p2p;
+extern crate grin_pool as pool;
 extern crate grin_servers as servers;
 extern crate grin_util as util;
 extern crate grin_wallet as wallet;
 
 extern crate blake2_rfc as blake2;
+extern crate chrono;
+
+pub mod simulation;
 
 use std::default::Default;
 use std::ops::Deref;
@@ -280,7 +284,7 @@ impl LocalServerContainer {
 			});
 
 		wallet::controller::foreign_listener(
-			Box::new(wallet),
+			Arc::new(Mutex::new(Box::new(wallet))),
 			&self.wallet_config.api_listen_addr(),
 			None,
 		).unwrap_or_else(|e| {
@@ -608,6 +612,14 @@ pub fn stratum_config() -> servers::common::types::StratumServerConfig {
 		stratum_server_addr: Some(String::from("127.0.0.1:13416")),
 		attempt_time_per_block: 60,
 		minimum_share_difficulty: 1,
+		vardiff_target_shares_per_minute: 0,
+		vardiff_retarget_secs: 90,
+		job_refresh_interval_secs: 5,
+		job_min_fee_increase: 1_000_000,
+		max_workers: 0,
+		tls_certificate_file: None,
+		tls_certificate_key: None,
+		wallet_listeners: vec![],
 		wallet_listener_url: String::from("http://127.0.0.1:13415"),
 		burn_reward: false,
 	}
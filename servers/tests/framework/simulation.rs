@@ -0,0 +1,338 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulation of a small network of full nodes, all running in the test
+//! process and talking to each other over loopback. Nothing happens on its
+//! own: peers only connect when asked to (programmatic seeding), blocks only
+//! get mined when asked to and links get cut and restored at will, so
+//! propagation, reorgs and Dandelion can be scripted step by step.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::{cmp, thread, time};
+
+use framework::chain::Options;
+use framework::chrono::prelude::{DateTime, NaiveDateTime, Utc};
+use framework::core::core::hash::{Hash, Hashed};
+use framework::core::core::{Block, Transaction};
+use framework::core::{consensus, global, pow};
+use framework::keychain::{ExtKeychain, Identifier, Keychain};
+use framework::pool::TxSource;
+use framework::servers::common::types::SyncStatus;
+use framework::servers::{self, ServerConfig};
+use framework::wallet::libtx::{self, build};
+use framework::{clean_all_output, config, p2p};
+
+/// How long to wait for the network to get somewhere before giving up
+pub const WAIT_SECS: u64 = 60;
+
+/// Configuration of the `n`th node of a simulation. Peers are only
+/// connected to explicitly and transactions always get stemmed, aggregated
+/// every second.
+pub fn sim_config(n: u16, test_name_dir: &str) -> ServerConfig {
+	let mut config = config(n, test_name_dir, n);
+	config.p2p_config.seeding_type = p2p::Seeding::Programmatic;
+	config.p2p_config.seeds = None;
+	config.dandelion_config.patience_secs = Some(1);
+	config.dandelion_config.stem_probability = Some(100);
+	config.shutdown_drain_timeout_secs = 5;
+	config
+}
+
+/// A network of nodes, each known by its index
+pub struct Simulation {
+	nodes: Vec<servers::Server>,
+	// links between nodes, dialing node first, and whether they're cut
+	links: Vec<(usize, usize, bool)>,
+	// owns the rewards of the blocks mined, to build transactions from
+	keychain: ExtKeychain,
+	next_key: u32,
+	// reward key and value of the blocks mined, by block hash
+	rewards: HashMap<Hash, (Identifier, u64)>,
+}
+
+impl Simulation {
+	/// Starts `count` nodes with the default simulation configuration, the
+	/// first one numbered `base_n`
+	pub fn new(test_name_dir: &str, base_n: u16, count: u16) -> Simulation {
+		let configs = (base_n..base_n + count)
+			.map(|n| sim_config(n, test_name_dir))
+			.collect();
+		Simulation::with_configs(test_name_dir, configs)
+	}
+
+	/// Starts a node for each of the provided configurations and waits for
+	/// them to be done looking for peers to sync from
+	pub fn with_configs(test_name_dir: &str, configs: Vec<ServerConfig>) -> Simulation {
+		global::set_mining_mode(global::ChainTypes::AutomatedTesting);
+		clean_all_output(test_name_dir);
+
+		let nodes = configs
+			.into_iter()
+			.map(|c| servers::Server::new(c).unwrap())
+			.collect();
+		let sim = Simulation {
+			nodes,
+			links: vec![],
+			keychain: ExtKeychain::from_random_seed().unwrap(),
+			next_key: 1,
+			rewards: HashMap::new(),
+		};
+		let all = sim.all();
+		sim.wait_for("nodes to start", || {
+			all.iter().all(|&n| {
+				let stats = sim.node(n).get_server_stats().unwrap();
+				stats.sync_status == SyncStatus::NoSync
+			})
+		});
+		sim
+	}
+
+	/// Indices of all the nodes
+	pub fn all(&self) -> Vec<usize> {
+		(0..self.nodes.len()).collect()
+	}
+
+	/// The server of a node
+	pub fn node(&self, n: usize) -> &servers::Server {
+		&self.nodes[n]
+	}
+
+	/// Address a node can be reached at by its peers
+	pub fn addr(&self, n: usize) -> SocketAddr {
+		format!("127.0.0.1:{}", self.nodes[n].config.p2p_config.port)
+			.parse()
+			.unwrap()
+	}
+
+	/// Whether two nodes are connected to each other, on both ends
+	pub fn connected(&self, a: usize, b: usize) -> bool {
+		let peers_a = &self.nodes[a].p2p.peers;
+		let peers_b = &self.nodes[b].p2p.peers;
+		peers_a.get_connected_peer(&self.addr(b)).is_some()
+			&& peers_b.get_connected_peer(&self.addr(a)).is_some()
+	}
+
+	/// Has node `a` connect to node `b`, waiting for both ends to be done
+	/// with the handshake. `b` becomes a candidate Dandelion relay of `a`,
+	/// the only one if `a` dials no other node.
+	pub fn connect(&mut self, a: usize, b: usize) {
+		self.dial(a, b);
+		self.links.push((a, b, false));
+	}
+
+	/// Connects the nodes one after the other, each dialing the next
+	pub fn connect_line(&mut self, nodes: &[usize]) {
+		for pair in nodes.windows(2) {
+			self.connect(pair[0], pair[1]);
+		}
+	}
+
+	/// Connects all the provided nodes to each other
+	pub fn connect_all(&mut self, nodes: &[usize]) {
+		for (i, &a) in nodes.iter().enumerate() {
+			for &b in &nodes[i + 1..] {
+				self.connect(a, b);
+			}
+		}
+	}
+
+	fn dial(&self, a: usize, b: usize) {
+		self.nodes[a].connect_peer(self.addr(b)).unwrap();
+		self.wait_for("nodes to connect", || self.connected(a, b));
+		self.nodes[a].p2p.peers.update_dandelion_relay();
+	}
+
+	/// Cuts all the links between two groups of nodes, as if the network
+	/// got split. Nothing reconnects them until `heal`.
+	pub fn partition(&mut self, side_a: &[usize], side_b: &[usize]) {
+		for link in self.links.iter_mut() {
+			let (a, b, _) = *link;
+			let across = (side_a.contains(&a) && side_b.contains(&b))
+				|| (side_a.contains(&b) && side_b.contains(&a));
+			if across {
+				link.2 = true;
+			}
+		}
+		for &a in side_a {
+			for &b in side_b {
+				self.nodes[a].p2p.peers.disconnect_peer(&self.addr(b));
+				self.nodes[b].p2p.peers.disconnect_peer(&self.addr(a));
+			}
+		}
+		for &a in side_a {
+			for &b in side_b {
+				self.wait_for("nodes to disconnect", || {
+					let peers_a = &self.nodes[a].p2p.peers;
+					let peers_b = &self.nodes[b].p2p.peers;
+					peers_a.get_connected_peer(&self.addr(b)).is_none()
+						&& peers_b.get_connected_peer(&self.addr(a)).is_none()
+				});
+			}
+		}
+	}
+
+	/// Restores all the links cut by partitions
+	pub fn heal(&mut self) {
+		let cut: Vec<(usize, usize)> = self
+			.links
+			.iter()
+			.filter(|l| l.2)
+			.map(|l| (l.0, l.1))
+			.collect();
+		for (a, b) in cut {
+			self.dial(a, b);
+		}
+		for link in self.links.iter_mut() {
+			link.2 = false;
+		}
+	}
+
+	/// Mines a block on top of the head of a node, with the provided
+	/// transactions, and has the node process it as its own. The node then
+	/// propagates it as it would any block it mined.
+	pub fn mine(&mut self, n: usize, txs: Vec<Transaction>) -> Block {
+		let key_id = ExtKeychain::derive_key_id(1, self.next_key, 0, 0, 0);
+		self.next_key += 1;
+
+		let chain = self.nodes[n].chain.clone();
+		let prev = chain.head_header().unwrap();
+		let next_header_info = consensus::next_difficulty(prev.height + 1, chain.difficulty_iter());
+		let fees = txs.iter().map(|tx| tx.fee()).sum();
+		let reward = libtx::reward::output(&self.keychain, &key_id, fees, prev.height).unwrap();
+		let mut b = Block::new(&prev, txs, next_header_info.difficulty, reward).unwrap();
+		// never in the past of the previous block, nor too far in the future
+		let secs = cmp::max(Utc::now().timestamp(), prev.timestamp.timestamp() + 1);
+		b.header.timestamp = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(secs, 0), Utc);
+		b.header.pow.secondary_scaling = next_header_info.secondary_scaling;
+		chain.set_txhashset_roots(&mut b).unwrap();
+
+		let edge_bits = global::min_edge_bits();
+		b.header.pow.proof.edge_bits = edge_bits;
+		pow::pow_size(
+			&mut b.header,
+			next_header_info.difficulty,
+			global::proofsize(),
+			edge_bits,
+		).unwrap();
+		b.header.pow.proof.edge_bits = edge_bits;
+
+		self.rewards.insert(b.hash(), (key_id, consensus::reward(fees)));
+		chain.process_block(b.clone(), Options::MINE).unwrap();
+		b
+	}
+
+	/// Mines a number of empty blocks on a node, one on top of the other
+	pub fn mine_blocks(&mut self, n: usize, count: u64) -> Vec<Block> {
+		(0..count).map(|_| self.mine(n, vec![])).collect()
+	}
+
+	/// A transaction spending the reward of a block mined in the simulation,
+	/// to a new output of ours. The reward has to be mature on the chain the
+	/// transaction gets pushed on.
+	pub fn spend_reward(&mut self, block: &Block, fee: u64) -> Transaction {
+		let (key_id, value) = self.rewards[&block.hash()].clone();
+		let out_id = ExtKeychain::derive_key_id(1, self.next_key, 0, 0, 0);
+		self.next_key += 1;
+		build::transaction(
+			vec![
+				build::coinbase_input(value, key_id),
+				build::output(value - fee, out_id),
+				build::with_fee(fee),
+			],
+			&self.keychain,
+		).unwrap()
+	}
+
+	/// Pushes a transaction to the pool of a node, to be stemmed along the
+	/// Dandelion relays unless fluffed right away
+	pub fn push_tx(&self, n: usize, tx: Transaction, fluff: bool) {
+		let source = TxSource {
+			debug_name: "simulation".to_string(),
+			identifier: format!("node {}", n),
+		};
+		let tx_pool = self.nodes[n].tx_pool();
+		let mut tx_pool = tx_pool.write();
+		let header = tx_pool.chain_head().unwrap();
+		tx_pool.add_to_pool(source, tx, !fluff, &header).unwrap();
+	}
+
+	/// Whether a node has a transaction in its stempool
+	pub fn in_stempool(&self, n: usize, tx: &Transaction) -> bool {
+		let tx_pool = self.nodes[n].tx_pool();
+		let in_pool = tx_pool.read().stempool.contains_tx(tx.hash());
+		in_pool
+	}
+
+	/// Whether a node has a transaction in its pool, fluffed
+	pub fn in_txpool(&self, n: usize, tx: &Transaction) -> bool {
+		let tx_pool = self.nodes[n].tx_pool();
+		let in_pool = tx_pool.read().txpool.contains_tx(tx.hash());
+		in_pool
+	}
+
+	/// Where a node got a transaction in its pool from, as named in the pool
+	pub fn txpool_source(&self, n: usize, tx: &Transaction) -> Option<String> {
+		let tx_pool = self.nodes[n].tx_pool();
+		let tx_pool = tx_pool.read();
+		let entry = tx_pool
+			.txpool
+			.entries
+			.iter()
+			.find(|e| e.tx.hash() == tx.hash());
+		entry.map(|e| e.src.debug_name.clone())
+	}
+
+	/// Hash of the head of a node
+	pub fn head(&self, n: usize) -> Hash {
+		self.nodes[n].head().last_block_h
+	}
+
+	/// Waits for all the provided nodes to have the provided block as head
+	pub fn wait_for_head(&self, nodes: &[usize], h: Hash) {
+		self.wait_for("nodes to agree on the head", || {
+			nodes.iter().all(|&n| self.head(n) == h)
+		});
+	}
+
+	/// Polls a condition until it holds, panicking if it doesn't within
+	/// `WAIT_SECS`
+	pub fn wait_for<F>(&self, what: &str, cond: F)
+	where
+		F: Fn() -> bool,
+	{
+		let deadline = time::Instant::now() + time::Duration::from_secs(WAIT_SECS);
+		while !cond() {
+			if time::Instant::now() > deadline {
+				panic!("Waited {}s for {}, giving up", WAIT_SECS, what);
+			}
+			thread::sleep(time::Duration::from_millis(100));
+		}
+	}
+
+	/// Stops all the nodes
+	pub fn stop(&self) {
+		for node in &self.nodes {
+			node.stop();
+		}
+	}
+}
+
+impl Drop for Simulation {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
@@ -0,0 +1,96 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_core as core;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+
+mod framework;
+
+use core::core::hash::Hashed;
+use core::{consensus, global};
+
+use framework::simulation::Simulation;
+
+/// Blocks mined at either end of a line of nodes make it to the other end
+#[test]
+fn block_propagation() {
+	util::init_test_logger();
+	let mut sim = Simulation::new("sim_block_propagation", 100, 4);
+	sim.connect_line(&[0, 1, 2, 3]);
+
+	let blocks = sim.mine_blocks(0, 3);
+	sim.wait_for_head(&sim.all(), blocks[2].hash());
+
+	let b = sim.mine(3, vec![]);
+	sim.wait_for_head(&sim.all(), b.hash());
+	assert_eq!(sim.node(0).head().height, 4);
+}
+
+/// Both sides of a split network mine their own fork, the side with the least
+/// work reorgs to the other fork once the network is whole again
+#[test]
+fn reorg_after_partition() {
+	util::init_test_logger();
+	let mut sim = Simulation::new("sim_reorg_after_partition", 110, 4);
+	sim.connect_all(&[0, 1, 2, 3]);
+
+	let common = sim.mine_blocks(0, 2);
+	sim.wait_for_head(&sim.all(), common[1].hash());
+
+	sim.partition(&[0, 1], &[2, 3]);
+	let lost = sim.mine(0, vec![]);
+	sim.wait_for_head(&[0, 1], lost.hash());
+	// enough work ahead for the other side to sync rather than wait for
+	// the next block
+	let won = sim.mine_blocks(2, 10);
+	let head = won.last().unwrap().hash();
+	sim.wait_for_head(&[2, 3], head);
+	assert_eq!(sim.head(1), lost.hash());
+
+	sim.heal();
+	sim.wait_for_head(&sim.all(), head);
+	let header = sim.node(0).chain.get_header_by_height(3).unwrap();
+	assert_eq!(header.hash(), won[0].hash());
+}
+
+/// A transaction is stemmed along the line of relays, fluffed by the last
+/// node that has no relay, then mined
+#[test]
+fn dandelion_stem_then_fluff() {
+	util::init_test_logger();
+	let mut sim = Simulation::new("sim_dandelion_stem_then_fluff", 120, 3);
+	sim.connect_line(&[0, 1, 2]);
+
+	// the first reward gets mature
+	let blocks = sim.mine_blocks(0, global::coinbase_maturity() + 1);
+	sim.wait_for_head(&sim.all(), blocks.last().unwrap().hash());
+
+	let tx = sim.spend_reward(&blocks[0], 10 * consensus::MILLI_GRIN);
+	sim.push_tx(0, tx.clone(), false);
+	assert!(sim.in_stempool(0, &tx));
+	assert!(!sim.in_txpool(0, &tx));
+
+	sim.wait_for("the transaction to be fluffed", || {
+		sim.all().iter().all(|&n| sim.in_txpool(n, &tx))
+	});
+	// only the end of the line, with no relay to stem to, fluffs
+	assert_eq!(sim.txpool_source(2, &tx), Some("no_relay".to_string()));
+
+	let b = sim.mine(1, vec![tx.clone()]);
+	sim.wait_for_head(&sim.all(), b.hash());
+	sim.wait_for("the pools to let go of the transaction", || {
+		sim.all().iter().all(|&n| !sim.in_txpool(n, &tx))
+	});
+}
@@ -0,0 +1,178 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate chrono;
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_pool as pool;
+extern crate grin_servers as servers;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate hyper;
+extern crate serde_json;
+
+use chrono::Duration;
+use std::fs;
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Request, StatusCode};
+
+use api::Handler;
+use core::consensus;
+use core::core::hash::Hashed;
+use core::core::verifier_cache::LruVerifierCache;
+use core::core::{Block, Transaction};
+use core::global::{self, ChainTypes};
+use core::pow::{self, Difficulty};
+use core::ser;
+use keychain::{ExtKeychain, ExtKeychainPath, Identifier, Keychain};
+use servers::common::adapters::PoolToChainAdapter;
+use util::{MemoryBudget, RwLock};
+use wallet::libtx::{build, reward};
+
+fn key_id(n: u32) -> Identifier {
+	ExtKeychainPath::new(1, n, 0, 0, 0).to_identifier()
+}
+
+// Adds a block rewarding the keychain, at the n-th key
+fn add_block(chain: &chain::Chain, keychain: &ExtKeychain, n: u32) {
+	let prev = chain.head_header().unwrap();
+	let reward = reward::output(keychain, &key_id(n), 0, prev.height).unwrap();
+	let mut b = Block::new(&prev, vec![], Difficulty::min(), reward).unwrap();
+	b.header.timestamp = prev.timestamp + Duration::seconds(60);
+	b.header.pow.proof = pow::Proof::random(global::proofsize());
+	chain.set_txhashset_roots(&mut b).unwrap();
+	chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+}
+
+// Spends the coinbase at the n-th key
+fn spend(keychain: &ExtKeychain, n: u32) -> Transaction {
+	build::transaction(
+		vec![
+			build::coinbase_input(consensus::REWARD, key_id(n)),
+			build::output(consensus::REWARD - 20000, key_id(n + 100)),
+			build::with_fee(20000),
+		],
+		keychain,
+	).unwrap()
+}
+
+fn submit(
+	handler: &api::PoolSubmitHandler,
+	query: &str,
+	content_type: &str,
+	body: Vec<u8>,
+) -> (StatusCode, Vec<u8>) {
+	let req = Request::post(format!("http://127.0.0.1/v1/pool/submit{}", query))
+		.header(CONTENT_TYPE, content_type)
+		.body(Body::from(body))
+		.unwrap();
+	let res = handler.post(req).wait().unwrap();
+	let status = res.status();
+	(status, res.into_body().concat2().wait().unwrap().to_vec())
+}
+
+// Transactions are taken in their binary serialization or as JSON, stemmed
+// unless fluffed, and answered with the kernels to follow them by
+#[test]
+fn pool_submit() {
+	util::init_test_logger();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let dir = "target/pool_submit";
+	let _ = fs::remove_dir_all(dir);
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let chain = Arc::new(
+		chain::Chain::init(
+			dir.to_string(),
+			Arc::new(store::new_env(dir.to_string())),
+			Arc::new(chain::types::NoopAdapter {}),
+			pow::mine_genesis_block().unwrap(),
+			pow::verify_size,
+			verifier_cache.clone(),
+			false,
+			Arc::new(MemoryBudget::unlimited()),
+		).unwrap(),
+	);
+	let pool_adapter = Arc::new(PoolToChainAdapter::new());
+	pool_adapter.set_chain(chain.clone());
+	let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
+		pool::PoolConfig {
+			accept_fee_base: 0,
+			..pool::PoolConfig::default()
+		},
+		pool_adapter,
+		verifier_cache,
+		Arc::new(pool::types::NoopAdapter {}),
+		Arc::new(MemoryBudget::unlimited()),
+	)));
+	let keychain = ExtKeychain::from_random_seed().unwrap();
+	for n in 1..6 {
+		add_block(&chain, &keychain, n);
+	}
+	let handler = api::PoolSubmitHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+
+	// binary, fluffed
+	let tx = spend(&keychain, 1);
+	let (status, body) = submit(
+		&handler,
+		"?fluff",
+		"application/octet-stream",
+		ser::ser_vec(&tx).unwrap(),
+	);
+	assert_eq!(status, StatusCode::OK);
+	let submitted: api::PoolSubmit = serde_json::from_slice(&body).unwrap();
+	assert_eq!(submitted.hash, tx.hash().to_hex());
+	assert_eq!(
+		submitted.kernels,
+		vec![util::to_hex(tx.kernels()[0].excess.0.to_vec())]
+	);
+	assert!(!submitted.stem);
+	assert_eq!(tx_pool.read().txpool.size(), 1);
+
+	// JSON, stemmed
+	let tx = spend(&keychain, 2);
+	let (status, body) = submit(
+		&handler,
+		"",
+		"application/json",
+		serde_json::to_vec(&tx).unwrap(),
+	);
+	assert_eq!(status, StatusCode::OK);
+	let submitted: api::PoolSubmit = serde_json::from_slice(&body).unwrap();
+	assert_eq!(submitted.hash, tx.hash().to_hex());
+	assert!(submitted.stem);
+	assert_eq!(tx_pool.read().txpool.size(), 1);
+	assert_eq!(tx_pool.read().stempool.size(), 1);
+
+	// neither
+	let (status, _) = submit(
+		&handler,
+		"",
+		"application/octet-stream",
+		b"not a transaction".to_vec(),
+	);
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	let (status, _) = submit(&handler, "", "application/json", b"{}".to_vec());
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert_eq!(tx_pool.read().total_size(), 1);
+}
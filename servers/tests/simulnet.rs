@@ -449,7 +449,7 @@ fn replicate_tx_fluff_failure() {
 	let client1 = HTTPWalletClient::new("http://127.0.0.1:23003", None);
 	let wallet1 = create_wallet("target/tmp/tx_fluff/wallet1", client1.clone());
 	let wallet1_handle = thread::spawn(move || {
-		controller::foreign_listener(wallet1, "127.0.0.1:33000", None)
+		controller::foreign_listener(Arc::new(Mutex::new(wallet1)), "127.0.0.1:33000", None)
 			.unwrap_or_else(|e| panic!("Error creating wallet1 listener: {:?}", e,));
 	});
 
@@ -457,7 +457,7 @@ fn replicate_tx_fluff_failure() {
 	let client2 = HTTPWalletClient::new("http://127.0.0.1:23001", None);
 	let wallet2 = create_wallet("target/tmp/tx_fluff/wallet2", client2.clone());
 	let wallet2_handle = thread::spawn(move || {
-		controller::foreign_listener(wallet2, "127.0.0.1:33001", None)
+		controller::foreign_listener(Arc::new(Mutex::new(wallet2)), "127.0.0.1:33001", None)
 			.unwrap_or_else(|e| panic!("Error creating wallet2 listener: {:?}", e,));
 	});
 
@@ -465,6 +465,7 @@ fn replicate_tx_fluff_failure() {
 	let mut s1_config = framework::config(3000, "tx_fluff", 3000);
 	s1_config.test_miner_wallet_url = Some("http://127.0.0.1:33000".to_owned());
 	s1_config.dandelion_config.embargo_secs = Some(10);
+	s1_config.dandelion_config.aggregation_secs = Some(2);
 	s1_config.dandelion_config.patience_secs = Some(1);
 	s1_config.dandelion_config.relay_secs = Some(1);
 	let s1 = servers::Server::new(s1_config.clone()).unwrap();
@@ -476,6 +477,7 @@ fn replicate_tx_fluff_failure() {
 	let mut s2_config = framework::config(3001, "tx_fluff", 3001);
 	s2_config.p2p_config.seeds = Some(vec!["127.0.0.1:13000".to_owned()]);
 	s2_config.dandelion_config.embargo_secs = Some(10);
+	s2_config.dandelion_config.aggregation_secs = Some(2);
 	s2_config.dandelion_config.patience_secs = Some(1);
 	s2_config.dandelion_config.relay_secs = Some(1);
 	let _s2 = servers::Server::new(s2_config.clone()).unwrap();
@@ -487,6 +489,7 @@ fn replicate_tx_fluff_failure() {
 		let mut s_config = framework::config(3002 + i, "tx_fluff", 3002 + i);
 		s_config.p2p_config.seeds = Some(vec!["127.0.0.1:13000".to_owned()]);
 		s_config.dandelion_config.embargo_secs = Some(10);
+		s_config.dandelion_config.aggregation_secs = Some(2);
 		s_config.dandelion_config.patience_secs = Some(1);
 		s_config.dandelion_config.relay_secs = Some(1);
 		let _ = servers::Server::new(s_config.clone()).unwrap();
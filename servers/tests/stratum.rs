@@ -141,6 +141,23 @@ fn basic_stratum_server() {
 	assert_eq!(response.as_str(), ok_resp);
 	info!("worker doesnotexist test ok");
 
+	// login - shows in the worker stats served by the API
+	let mut response = String::new();
+	let login_req = "{\"id\":\"5\",\"jsonrpc\":\"2.0\",\"method\":\"login\",\"params\":{\"login\":\"rig1\",\"pass\":\"\",\"agent\":\"test\"}}\n";
+	workers[2].write(login_req.as_bytes()).unwrap();
+	workers[2].flush().unwrap();
+	thread::sleep(time::Duration::from_secs(1)); // Wait for the server to reply
+	let _st = workers[2].read_line(&mut response);
+	let r: Value = serde_json::from_str(&response).unwrap();
+	assert_eq!(r["result"], "ok");
+	let url = "http://127.0.0.1:24000/v1/stratum/workers?connected";
+	let stratum_workers: servers::StratumWorkers = api::client::get(url, None).unwrap();
+	assert_eq!(stratum_workers.workers.len(), 4);
+	assert_eq!(stratum_workers.logins.len(), 1);
+	assert_eq!(stratum_workers.logins[0].login, "rig1");
+	assert_eq!(stratum_workers.logins[0].workers, 1);
+	info!("worker stats api test ok");
+
 	// Verify stratum server and worker stats
 	let stats = s.get_server_stats().unwrap();
 	assert_eq!(stats.stratum_stats.block_height, 1); // just 1 genesis block
@@ -185,3 +202,26 @@ fn basic_stratum_server() {
 	assert_ne!(stats.stratum_stats.block_height, 1);
 	info!("basic_stratum_server test done and ok.");
 }
+
+#[test]
+fn stratum_tls_config() {
+	util::init_test_logger();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+
+	let mut stratum_cfg = stratum_config();
+	assert!(stratum_cfg.tls_config().unwrap().is_none());
+	stratum_cfg.tls_certificate_file = Some("fullchain.pem".to_owned());
+	assert!(stratum_cfg.tls_config().is_err());
+	stratum_cfg.tls_certificate_key = Some("privkey.pem".to_owned());
+	assert!(stratum_cfg.tls_config().unwrap().is_some());
+
+	// a stratum server that can't load its certificate doesn't listen,
+	// rather than falling back to plaintext
+	let test_name_dir = "stratum_server_tls";
+	framework::clean_all_output(test_name_dir);
+	let s = servers::Server::new(config(4010, test_name_dir, 0)).unwrap();
+	stratum_cfg.stratum_server_addr = Some(String::from("127.0.0.1:11111"));
+	s.start_stratum_server(stratum_cfg);
+	thread::sleep(time::Duration::from_secs(2));
+	assert!(TcpStream::connect("127.0.0.1:11111").is_err());
+}
@@ -0,0 +1,145 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate chrono;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_pool as pool;
+extern crate grin_servers as servers;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+
+use chrono::Duration;
+use std::fs;
+use std::sync::Arc;
+
+use core::consensus;
+use core::core::hash::Hashed;
+use core::core::verifier_cache::LruVerifierCache;
+use core::core::Block;
+use core::global::{self, ChainTypes};
+use core::pow::{self, Difficulty};
+use keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use servers::common::adapters::PoolToChainAdapter;
+use servers::TemplateCache;
+use util::{MemoryBudget, RwLock};
+use wallet::libtx::{build, reward};
+
+// Adds a block rewarding the keychain, at the n-th key
+fn add_block(chain: &chain::Chain, keychain: &ExtKeychain, n: u32) {
+	let prev = chain.head_header().unwrap();
+	let key_id = ExtKeychainPath::new(1, n, 0, 0, 0).to_identifier();
+	let reward = reward::output(keychain, &key_id, 0, prev.height).unwrap();
+	let mut b = Block::new(&prev, vec![], Difficulty::min(), reward).unwrap();
+	b.header.timestamp = prev.timestamp + Duration::seconds(60);
+	b.header.pow.proof = pow::Proof::random(global::proofsize());
+	chain.set_txhashset_roots(&mut b).unwrap();
+	chain.process_block(b, chain::Options::SKIP_POW).unwrap();
+}
+
+// Versions of a block keep its coinbase as long as the fees are the same,
+// and all of it as long as the transactions are, a new block starting over
+#[test]
+fn template_cache() {
+	util::init_test_logger();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let dir = "target/template_cache";
+	let _ = fs::remove_dir_all(dir);
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let chain = Arc::new(
+		chain::Chain::init(
+			dir.to_string(),
+			Arc::new(store::new_env(dir.to_string())),
+			Arc::new(chain::types::NoopAdapter {}),
+			pow::mine_genesis_block().unwrap(),
+			pow::verify_size,
+			verifier_cache.clone(),
+			false,
+			Arc::new(MemoryBudget::unlimited()),
+		).unwrap(),
+	);
+	let pool_adapter = Arc::new(PoolToChainAdapter::new());
+	pool_adapter.set_chain(chain.clone());
+	let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
+		pool::PoolConfig {
+			accept_fee_base: 0,
+			..pool::PoolConfig::default()
+		},
+		pool_adapter,
+		verifier_cache.clone(),
+		Arc::new(pool::types::NoopAdapter {}),
+		Arc::new(MemoryBudget::unlimited()),
+	)));
+
+	let keychain = ExtKeychain::from_random_seed().unwrap();
+	for n in 1..6 {
+		add_block(&chain, &keychain, n);
+	}
+
+	// burnt rewards get a new random key each time they're built
+	let mut cache = TemplateCache::default();
+	let mut build = || {
+		cache
+			.build(&chain, &tx_pool, verifier_cache.clone(), None, &[])
+			.unwrap()
+	};
+	let (b1, fees, _) = build();
+	assert_eq!(b1.header.height, 6);
+	assert_eq!(fees.fees, 0);
+	let (b2, _, _) = build();
+	assert_eq!(b2.outputs(), b1.outputs());
+	assert_eq!(b2.header.output_root, b1.header.output_root);
+
+	// a transaction brings fees in, and so a new coinbase
+	let key_id = |n| ExtKeychainPath::new(1, n, 0, 0, 0).to_identifier();
+	let tx = build::transaction(
+		vec![
+			build::coinbase_input(consensus::REWARD, key_id(1)),
+			build::output(consensus::REWARD - 20000, key_id(30)),
+			build::with_fee(20000),
+		],
+		&keychain,
+	).unwrap();
+	let head = chain.head_header().unwrap();
+	tx_pool
+		.write()
+		.add_to_pool(
+			pool::TxSource {
+				debug_name: "test".to_owned(),
+				identifier: "127.0.0.1".to_owned(),
+			},
+			tx,
+			false,
+			&head,
+		).unwrap();
+	let (mut b3, fees, _) = build();
+	assert_eq!(fees.fees, 20000);
+	assert_eq!(b3.kernels().len(), 2);
+	assert!(b3.outputs().iter().all(|o| !b1.outputs().contains(o)));
+
+	// once the block is in, the next one starts over
+	b3.header.pow.proof = pow::Proof::random(global::proofsize());
+	chain
+		.process_block(b3.clone(), chain::Options::SKIP_POW)
+		.unwrap();
+	tx_pool.write().reconcile_block(&b3).unwrap();
+	let (b4, fees, _) = build();
+	assert_eq!(b4.header.height, 7);
+	assert_eq!(b4.header.prev_hash, b3.hash());
+	assert_eq!(fees.fees, 0);
+	assert_eq!(b4.kernels().len(), 1);
+}
@@ -0,0 +1,110 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_servers as servers;
+extern crate grin_util as util;
+extern crate serde_json;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use servers::common::webhooks::{TxConfirmed, WebhookEvent, WebhookPayload};
+use servers::{WebhookConfig, WebhookDispatcher};
+
+/// Answers the first `failures` requests with a 500 and the others with a
+/// 200, handing over the bodies it got
+fn receiver(failures: usize) -> (String, mpsc::Receiver<String>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/hook", listener.local_addr().unwrap());
+	let (tx, rx) = mpsc::channel();
+	thread::spawn(move || {
+		for (n, stream) in listener.incoming().enumerate() {
+			let mut stream = BufReader::new(stream.unwrap());
+			let mut len = 0;
+			loop {
+				let mut line = String::new();
+				stream.read_line(&mut line).unwrap();
+				if line.trim().is_empty() {
+					break;
+				}
+				let line = line.to_lowercase();
+				if line.starts_with("content-length:") {
+					len = line[15..].trim().parse().unwrap();
+				}
+			}
+			let mut body = vec![0; len];
+			stream.read_exact(&mut body).unwrap();
+			tx.send(String::from_utf8(body).unwrap()).unwrap();
+			let status = if n < failures {
+				"500 Internal Server Error"
+			} else {
+				"200 OK"
+			};
+			let res = format!(
+				"HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+				status
+			);
+			stream.get_mut().write_all(res.as_bytes()).unwrap();
+		}
+	});
+	(url, rx)
+}
+
+#[test]
+fn webhook_retries() {
+	util::init_test_logger();
+	let (url, rx) = receiver(2);
+	let dispatcher = WebhookDispatcher::new(WebhookConfig {
+		tx_confirmed: vec![url],
+		retry_delay_secs: 0,
+		..WebhookConfig::default()
+	});
+	let confirmed = TxConfirmed {
+		kernel: "08aa".to_owned(),
+		block_hash: "00ff".to_owned(),
+		height: 12,
+	};
+	dispatcher.notify(WebhookEvent::TxConfirmed, &confirmed);
+	// nothing configured for it, nothing sent
+	dispatcher.notify(WebhookEvent::Reorg, &confirmed);
+
+	// failed twice, delivered the third time
+	for _ in 0..3 {
+		let body = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+		let payload: WebhookPayload<TxConfirmed> = serde_json::from_str(&body).unwrap();
+		assert_eq!(payload.event, WebhookEvent::TxConfirmed);
+		assert_eq!(payload.data, confirmed);
+	}
+	assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+}
+
+#[test]
+fn webhook_gives_up() {
+	util::init_test_logger();
+	let (url, rx) = receiver(10);
+	let dispatcher = WebhookDispatcher::new(WebhookConfig {
+		block_accepted: vec![url],
+		max_attempts: 2,
+		retry_delay_secs: 0,
+		..WebhookConfig::default()
+	});
+	dispatcher.notify(WebhookEvent::BlockAccepted, &"header");
+	for _ in 0..2 {
+		assert!(rx.recv_timeout(Duration::from_secs(10)).is_ok());
+	}
+	assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+}
@@ -13,34 +13,102 @@
 // limitations under the License.
 
 /// Grin client commands processing
+use std::fs::{self, File};
+use std::io::Write;
 use std::net::SocketAddr;
 
+use chrono::prelude::*;
 use clap::ArgMatches;
 
 use api;
+use api::{BlockRef, NodeClient};
 use config::GlobalConfig;
+use core::core::hash::Hash;
 use p2p;
-use servers::ServerConfig;
+use serde_json;
+use servers::{self, ServerConfig};
 use term;
+use util;
 use util::file::get_first_line;
+use util::secp::pedersen::Commitment;
 
 pub fn client_command(client_args: &ArgMatches, global_config: GlobalConfig) -> i32 {
 	// just get defaults from the global config
 	let server_config = global_config.members.unwrap().server;
 	let api_secret = get_first_line(server_config.api_secret_path.clone());
+	// queries only need the read-only secret
+	let api_read_secret =
+		get_first_line(server_config.api_read_secret_path.clone()).or_else(|| api_secret.clone());
 
 	match client_args.subcommand() {
 		("status", Some(_)) => {
-			show_status(&server_config, api_secret);
+			show_status(&server_config, api_read_secret);
+		}
+		("sync", Some(_)) => {
+			show_sync_status(&server_config, api_read_secret);
+		}
+		("compact", Some(_)) => {
+			compact_chain(&server_config, api_secret);
+		}
+		("pool", Some(_)) => {
+			list_pool_txs(&server_config, api_read_secret);
+		}
+		("block", Some(block_args)) => {
+			let block = match (block_args.value_of("height"), block_args.value_of("hash")) {
+				(Some(height), _) => {
+					BlockRef::Height(height.parse().expect("Invalid block height"))
+				}
+				(None, Some(hash)) => {
+					BlockRef::Hash(Hash::from_hex(hash).expect("Invalid block hash"))
+				}
+				(None, None) => panic!("Either a block height or hash is needed"),
+			};
+			show_block(&server_config, block, api_read_secret);
+		}
+		("output", Some(output_args)) => {
+			let commit = output_args.value_of("commit").unwrap();
+			let commit = match util::from_hex(commit.to_owned()) {
+				Ok(bytes) => Commitment::from_vec(bytes),
+				Err(_) => panic!("Invalid output commitment, should be in hex"),
+			};
+			show_output(&server_config, commit, api_read_secret);
+		}
+		("reorgs", Some(reorg_args)) => {
+			let count = reorg_args
+				.value_of("count")
+				.unwrap()
+				.parse()
+				.expect("Invalid count of reorgs");
+			list_reorgs(&server_config, count, api_read_secret);
 		}
 		("listconnectedpeers", Some(_)) => {
-			list_connected_peers(&server_config, api_secret);
+			list_connected_peers(&server_config, api_read_secret);
+		}
+		("listbannedpeers", Some(_)) => {
+			list_banned_peers(&server_config, api_read_secret);
+		}
+		("connect", Some(peer_args)) => {
+			let peer = peer_args.value_of("peer").unwrap();
+
+			if let Ok(addr) = peer.parse() {
+				connect_peer(&server_config, &addr, api_secret);
+			} else {
+				panic!("Invalid peer address format");
+			}
 		}
 		("ban", Some(peer_args)) => {
 			let peer = peer_args.value_of("peer").unwrap();
+			let reason = peer_args.value_of("reason").unwrap();
+			let duration = match peer_args.value_of("duration") {
+				Some(d) => d
+					.parse()
+					.expect("Invalid ban duration, should be in seconds"),
+				None => 0,
+			};
+			let note = peer_args.value_of("note");
 
 			if let Ok(addr) = peer.parse() {
-				ban_peer(&server_config, &addr, api_secret);
+				ban_peer(&server_config, &addr, reason, duration, note, api_secret);
 			} else {
 				panic!("Invalid peer address format");
 			}
@@ -54,6 +122,28 @@ pub fn client_command(client_args: &ArgMatches, global_config: GlobalConfig) ->
 				panic!("Invalid peer address format");
 			}
 		}
+		("peers", Some(peers_args)) => match peers_args.subcommand() {
+			("export", Some(export_args)) => {
+				let count = export_args
+					.value_of("count")
+					.map(|c| c.parse().expect("Invalid count of peers"));
+				export_peers(
+					&server_config,
+					count,
+					export_args.value_of("file"),
+					api_read_secret,
+				);
+			}
+			("import", Some(import_args)) => {
+				let file = import_args.value_of("file").unwrap();
+				let max_age = import_args
+					.value_of("max_age")
+					.map(|a| a.parse().expect("Invalid max age, should be in seconds"));
+				let signer = import_args.value_of("signer");
+				import_peers(&server_config, file, max_age, signer, api_secret);
+			}
+			_ => panic!("Unknown peers command, use 'grin help client peers' for details"),
+		},
 		_ => panic!("Unknown client command, use 'grin help client' for details"),
 	}
 	0
@@ -77,6 +167,14 @@ pub fn show_status(config: &ServerConfig, api_secret: Option<String>) {
 			writeln!(e, "Last block hash: {}", status.tip.last_block_pushed).unwrap();
 			writeln!(e, "Previous block hash: {}", status.tip.prev_block_to_last).unwrap();
 			writeln!(e, "Total difficulty: {}", status.tip.total_difficulty).unwrap();
+			let dandelion = status.dandelion;
+			writeln!(
+				e,
+				"Dandelion: stem probability {}%, embargo {}s (+ up to {}s)",
+				dandelion.stem_probability.unwrap_or(0),
+				dandelion.embargo_secs.unwrap_or(0),
+				dandelion.embargo_jitter_secs(),
+			).unwrap();
 		}
 		Err(_) => writeln!(
 			e,
@@ -87,15 +185,176 @@ pub fn show_status(config: &ServerConfig, api_secret: Option<String>) {
 	println!()
 }
 
-pub fn ban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Option<String>) {
-	let params = "";
+pub fn show_sync_status(config: &ServerConfig, api_secret: Option<String>) {
 	let mut e = term::stdout().unwrap();
-	let url = format!(
-		"http://{}/v1/peers/{}/ban",
-		config.api_http_addr,
-		peer_addr.to_string()
-	);
-	match api::client::post_no_ret(url.as_str(), api_secret, &params).map_err(|e| Error::API(e)) {
+	let url = format!("{}{}", config.api_url(), servers::SYNC_STATUS_PATH);
+	match api::client::get::<servers::SyncInfo>(&url, api_secret) {
+		Ok(sync) => {
+			writeln!(e, "Stage: {}", sync.stage).unwrap();
+			writeln!(e, "Status: {}", sync.status).unwrap();
+			if let (Some(current), Some(total)) = (sync.current, sync.total) {
+				let unit = sync.unit.unwrap_or("items".to_owned());
+				writeln!(e, "Progress: {} / {} {}", current, total, unit).unwrap();
+			}
+			if let Some(rate) = sync.rate {
+				writeln!(e, "Rate: {:.1}/s", rate).unwrap();
+			}
+			if let Some(eta) = sync.eta {
+				writeln!(e, "ETA: {}s", eta).unwrap();
+			}
+			writeln!(e, "In this status for: {}s", sync.elapsed).unwrap();
+			for peer in sync.peers {
+				writeln!(e, "Syncing from: {} at height {}", peer.addr, peer.height).unwrap();
+			}
+		}
+		Err(_) => writeln!(e, "Failed to get the sync status").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn compact_chain(config: &ServerConfig, api_secret: Option<String>) {
+	let mut e = term::stdout().unwrap();
+	let res = node_client(config, api_secret).compact_chain();
+	match res.map_err(Error::API) {
+		Ok(_) => writeln!(e, "Chain compacted").unwrap(),
+		Err(_) => writeln!(e, "Failed to compact the chain").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn list_pool_txs(config: &ServerConfig, api_secret: Option<String>) {
+	let mut e = term::stdout().unwrap();
+	let txs = node_client(config, api_secret).get_pool_txs();
+
+	match txs.map_err(Error::API) {
+		Ok(txs) => {
+			writeln!(e, "{} transaction(s) in the pool", txs.len()).unwrap();
+			println!();
+			for pool_tx in txs {
+				writeln!(e, "Transaction: {}", pool_tx.hash).unwrap();
+				writeln!(e, "Source: {}", pool_tx.source).unwrap();
+				writeln!(e, "Received at: {}", Utc.timestamp(pool_tx.received_at, 0)).unwrap();
+				writeln!(e, "Fee: {}", pool_tx.tx.fee).unwrap();
+				writeln!(
+					e,
+					"Inputs: {}, outputs: {}, kernels: {}",
+					pool_tx.tx.inputs.len(),
+					pool_tx.tx.outputs.len(),
+					pool_tx.tx.kernels.len()
+				).unwrap();
+				println!();
+			}
+		}
+		Err(_) => writeln!(e, "Failed to get the pool transactions").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn show_block(config: &ServerConfig, block: BlockRef, api_secret: Option<String>) {
+	let mut e = term::stdout().unwrap();
+	let res = node_client(config, api_secret).get_block(block);
+
+	match res.map_err(Error::API) {
+		Ok(block) => {
+			let header = &block.header;
+			writeln!(e, "Block hash: {}", header.hash).unwrap();
+			writeln!(e, "Height: {}", header.height).unwrap();
+			writeln!(e, "Previous block hash: {}", header.previous).unwrap();
+			writeln!(e, "Timestamp: {}", header.timestamp).unwrap();
+			writeln!(e, "Total difficulty: {}", header.total_difficulty).unwrap();
+			writeln!(e, "Inputs: {}", block.inputs.len()).unwrap();
+			writeln!(e, "Outputs: {}", block.outputs.len()).unwrap();
+			for kernel in &block.kernels {
+				writeln!(
+					e,
+					"Kernel: {} ({}, fee {})",
+					kernel.excess, kernel.features, kernel.fee
+				).unwrap();
+			}
+		}
+		Err(_) => writeln!(e, "Failed to get block {:?}", block).unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn show_output(config: &ServerConfig, commit: Commitment, api_secret: Option<String>) {
+	let mut e = term::stdout().unwrap();
+	let res = node_client(config, api_secret).get_outputs(&[commit]);
+
+	match res.map_err(Error::API) {
+		Ok(outputs) => match outputs.first() {
+			Some(output) => writeln!(
+				e,
+				"Output {} unspent, in the block at height {}",
+				util::to_hex(output.commit.to_vec()),
+				output.height
+			).unwrap(),
+			None => writeln!(e, "No unspent output with that commitment").unwrap(),
+		},
+		Err(_) => writeln!(e, "Failed to get the output").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn list_reorgs(config: &ServerConfig, count: usize, api_secret: Option<String>) {
+	let mut e = term::stdout().unwrap();
+	let url = format!("{}{}", config.api_url(), servers::REORGS_PATH);
+
+	match api::client::get::<Vec<servers::Reorg>>(&url, api_secret) {
+		Ok(reorgs) => {
+			if reorgs.is_empty() {
+				writeln!(e, "No reorg since the node started").unwrap();
+			}
+			// most recent last, as a tail
+			for reorg in reorgs.iter().take(count).rev() {
+				writeln!(
+					e,
+					"{}: forked at {}, from {} at {} to {} at {}",
+					Utc.timestamp(reorg.at, 0),
+					reorg.fork_height,
+					reorg.old_head,
+					reorg.old_height,
+					reorg.new_head,
+					reorg.new_height
+				).unwrap();
+			}
+		}
+		Err(_) => writeln!(e, "Failed to get the recent reorgs").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn connect_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Option<String>) {
+	let mut e = term::stdout().unwrap();
+	let res = node_client(config, api_secret).connect_peer(peer_addr);
+	match res.map_err(Error::API) {
+		Ok(peer) => writeln!(
+			e,
+			"Connected to peer {}, {} at height {}",
+			peer_addr, peer.user_agent, peer.height
+		).unwrap(),
+		Err(_) => writeln!(e, "Failed to connect to peer {}", peer_addr).unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn ban_peer(
+	config: &ServerConfig,
+	peer_addr: &SocketAddr,
+	reason: &str,
+	duration: u64,
+	note: Option<&str>,
+	api_secret: Option<String>,
+) {
+	let mut e = term::stdout().unwrap();
+	let reason: p2p::types::ReasonForBan =
+		match serde_json::from_value(serde_json::Value::String(reason.to_owned())) {
+			Ok(r) => r,
+			Err(_) => panic!("Invalid ban reason {}", reason),
+		};
+	let duration = if duration > 0 { Some(duration) } else { None };
+	let res = node_client(config, api_secret).ban_peer(peer_addr, reason, duration, note);
+	match res.map_err(Error::API) {
 		Ok(_) => writeln!(e, "Successfully banned peer {}", peer_addr.to_string()).unwrap(),
 		Err(_) => writeln!(e, "Failed to ban peer {}", peer_addr).unwrap(),
 	};
@@ -103,17 +362,10 @@ pub fn ban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Optio
 }
 
 pub fn unban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Option<String>) {
-	let params = "";
 	let mut e = term::stdout().unwrap();
-	let url = format!(
-		"http://{}/v1/peers/{}/unban",
-		config.api_http_addr,
-		peer_addr.to_string()
-	);
-	let res: Result<(), api::Error>;
-	res = api::client::post_no_ret(url.as_str(), api_secret, &params);
-
-	match res.map_err(|e| Error::API(e)) {
+	let res = node_client(config, api_secret).unban_peer(peer_addr);
+
+	match res.map_err(Error::API) {
 		Ok(_) => writeln!(e, "Successfully unbanned peer {}", peer_addr).unwrap(),
 		Err(_) => writeln!(e, "Failed to unban peer {}", peer_addr).unwrap(),
 	};
@@ -122,10 +374,7 @@ pub fn unban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Opt
 
 pub fn list_connected_peers(config: &ServerConfig, api_secret: Option<String>) {
 	let mut e = term::stdout().unwrap();
-	let url = format!("http://{}/v1/peers/connected", config.api_http_addr);
-	// let peers_info: Result<Vec<p2p::PeerInfoDisplay>, api::Error>;
-
-	let peers_info = api::client::get::<Vec<p2p::types::PeerInfoDisplay>>(url.as_str(), api_secret);
+	let peers_info = node_client(config, api_secret).get_connected_peers();
 
 	match peers_info.map_err(|e| Error::API(e)) {
 		Ok(connected_peers) => {
@@ -149,12 +398,104 @@ pub fn list_connected_peers(config: &ServerConfig, api_secret: Option<String>) {
 	e.reset().unwrap();
 }
 
+pub fn list_banned_peers(config: &ServerConfig, api_secret: Option<String>) {
+	let mut e = term::stdout().unwrap();
+	let banned_peers = node_client(config, api_secret).get_banned_peers();
+
+	match banned_peers.map_err(Error::API) {
+		Ok(banned_peers) => {
+			for banned_peer in banned_peers {
+				writeln!(e, "Peer address: {}", banned_peer.addr).unwrap();
+				writeln!(e, "Reason: {:?}", banned_peer.ban_reason).unwrap();
+				if !banned_peer.note.is_empty() {
+					writeln!(e, "Note: {}", banned_peer.note).unwrap();
+				}
+				writeln!(e, "Banned at: {}", Utc.timestamp(banned_peer.banned_at, 0)).unwrap();
+				writeln!(
+					e,
+					"Expires at: {}",
+					Utc.timestamp(banned_peer.expires_at, 0)
+				).unwrap();
+				println!();
+			}
+		}
+		Err(_) => writeln!(e, "Failed to get banned peers").unwrap(),
+	};
+
+	e.reset().unwrap();
+}
+
+pub fn export_peers(
+	config: &ServerConfig,
+	count: Option<usize>,
+	file: Option<&str>,
+	api_secret: Option<String>,
+) {
+	let mut e = term::stdout().unwrap();
+	match node_client(config, api_secret)
+		.export_peers(count)
+		.map_err(Error::API)
+	{
+		Ok(export) => {
+			let json = serde_json::to_string_pretty(&export).unwrap();
+			match file {
+				Some(f) => {
+					let written =
+						File::create(f).and_then(|mut out| out.write_all(json.as_bytes()));
+					if let Err(err) = written {
+						writeln!(e, "Failed to write the peers to {}: {}", f, err).unwrap();
+					} else {
+						let (count, signer) = (export.peers.len(), &export.public_key);
+						writeln!(e, "Exported {} peers signed by {} to {}", count, signer, f)
+							.unwrap();
+					}
+				}
+				None => println!("{}", json),
+			}
+		}
+		Err(_) => writeln!(e, "Failed to export peers").unwrap(),
+	};
+	e.reset().unwrap();
+}
+
+pub fn import_peers(
+	config: &ServerConfig,
+	file: &str,
+	max_age: Option<i64>,
+	signer: Option<&str>,
+	api_secret: Option<String>,
+) {
+	let mut e = term::stdout().unwrap();
+	let export: p2p::PeerExport = match fs::read_to_string(file)
+		.map_err(|err| err.to_string())
+		.and_then(|json| serde_json::from_str(&json).map_err(|err| err.to_string()))
+	{
+		Ok(export) => export,
+		Err(err) => panic!("Invalid peer export {}: {}", file, err),
+	};
+	let res = node_client(config, api_secret).import_peers(&export, max_age, signer);
+	match res.map_err(Error::API) {
+		Ok(import) => writeln!(
+			e,
+			"Imported {} of the {} peers exported by {}",
+			import.imported, import.exported, export.public_key
+		).unwrap(),
+		Err(Error::API(err)) => writeln!(e, "Failed to import peers: {}", err).unwrap(),
+	};
+	e.reset().unwrap();
+}
+
 fn get_status_from_node(
 	config: &ServerConfig,
 	api_secret: Option<String>,
 ) -> Result<api::Status, Error> {
-	let url = format!("http://{}/v1/status", config.api_http_addr);
-	api::client::get::<api::Status>(url.as_str(), api_secret).map_err(|e| Error::API(e))
+	node_client(config, api_secret)
+		.get_status()
+		.map_err(Error::API)
+}
+
+fn node_client(config: &ServerConfig, api_secret: Option<String>) -> NodeClient {
+	NodeClient::new(&config.api_url(), api_secret)
 }
 
 /// Error type wrapping underlying module errors.
@@ -14,11 +14,15 @@
 
 /// Grin configuration file output command
 use config::{GlobalConfig, GlobalWalletConfig};
+use core::global::ChainTypes;
 use std::env;
 
-/// Create a config file in the current directory
-pub fn config_command_server(file_name: &str) {
+/// Create a config file in the current directory, for the given network if any
+pub fn config_command_server(file_name: &str, network: Option<&ChainTypes>) {
 	let mut default_config = GlobalConfig::default();
+	if let Some(n) = network {
+		default_config.set_network(n);
+	}
 	let current_dir = env::current_dir().unwrap_or_else(|e| {
 		panic!("Error creating config file: {}", e);
 	});
@@ -43,9 +47,12 @@ pub fn config_command_server(file_name: &str) {
 	);
 }
 
-/// Create a config file in the current directory
-pub fn config_command_wallet(file_name: &str) {
+/// Create a config file in the current directory, for the given network if any
+pub fn config_command_wallet(file_name: &str, network: Option<&ChainTypes>) {
 	let mut default_config = GlobalWalletConfig::default();
+	if let Some(n) = network {
+		default_config.set_network(n);
+	}
 	let current_dir = env::current_dir().unwrap_or_else(|e| {
 		panic!("Error creating config file: {}", e);
 	});
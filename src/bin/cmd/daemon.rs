@@ -0,0 +1,123 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Process management for the server running in the background: pid file,
+//! signalling the running process and systemd readiness notifications
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use servers::ServerConfig;
+
+/// Default name of the pid file, under the db root when none is configured
+const DEFAULT_PID_FILE: &'static str = "grin.pid";
+
+/// Location of the pid file for the given server configuration, the same
+/// whatever directory the server is run or stopped from
+pub fn pid_file(config: &ServerConfig) -> PathBuf {
+	match config.pid_file {
+		Some(ref path) => PathBuf::from(path),
+		None => Path::new(&config.db_root).join(DEFAULT_PID_FILE),
+	}
+}
+
+/// Reads the process id recorded in the pid file, if any
+pub fn read_pid(path: &Path) -> Option<i32> {
+	let mut content = String::new();
+	File::open(path)
+		.and_then(|mut f| f.read_to_string(&mut content))
+		.ok()?;
+	content.trim().parse().ok()
+}
+
+/// Records the current process id in the pid file
+pub fn write_pid(path: &Path) -> Result<(), String> {
+	let mut f = File::create(path).map_err(|e| format!("{}", e))?;
+	writeln!(f, "{}", process::id()).map_err(|e| format!("{}", e))
+}
+
+/// Removes the pid file, only if it still records the current process
+pub fn remove_pid(path: &Path) {
+	if read_pid(path) == Some(process::id() as i32) {
+		if let Err(e) = fs::remove_file(path) {
+			warn!("Could not remove pid file {:?}: {}", path, e);
+		}
+	}
+}
+
+/// Id of the server process recorded in the pid file, if it's still alive.
+/// A stale pid file, left behind by a crash, gets removed.
+pub fn running_pid(path: &Path) -> Option<i32> {
+	let pid = read_pid(path)?;
+	if is_alive(pid) {
+		Some(pid)
+	} else {
+		let _ = fs::remove_file(path);
+		None
+	}
+}
+
+#[cfg(unix)]
+fn is_alive(pid: i32) -> bool {
+	use libc;
+	// signal 0 only checks the process exists and may be signalled
+	unsafe { libc::kill(pid, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: i32) -> bool {
+	false
+}
+
+/// Asks the process to shut down cleanly, as on Ctrl+C
+#[cfg(unix)]
+pub fn terminate(pid: i32) -> Result<(), String> {
+	use libc;
+	if unsafe { libc::kill(pid, libc::SIGTERM) } == 0 {
+		Ok(())
+	} else {
+		Err(format!("{}", ::std::io::Error::last_os_error()))
+	}
+}
+
+#[cfg(not(unix))]
+pub fn terminate(_pid: i32) -> Result<(), String> {
+	Err("stopping the server is only supported on unix".to_string())
+}
+
+/// Reports the server state to systemd when running as a `Type=notify`
+/// service, does nothing otherwise
+#[cfg(unix)]
+pub fn notify_systemd(state: &str) {
+	use std::env;
+	use std::os::unix::net::UnixDatagram;
+
+	let path = match env::var("NOTIFY_SOCKET") {
+		Ok(p) => p,
+		Err(_) => return,
+	};
+	// abstract socket names aren't supported by the standard library
+	if path.starts_with('@') {
+		debug!("Abstract NOTIFY_SOCKET {} not supported, skipping.", path);
+		return;
+	}
+	let res = UnixDatagram::unbound().and_then(|s| s.send_to(state.as_bytes(), &path));
+	if let Err(e) = res {
+		warn!("Could not notify systemd ({}): {}", state, e);
+	}
+}
+
+#[cfg(not(unix))]
+pub fn notify_systemd(_state: &str) {}
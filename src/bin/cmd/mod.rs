@@ -14,6 +14,7 @@
 
 mod client;
 mod config;
+mod daemon;
 mod server;
 mod wallet;
 
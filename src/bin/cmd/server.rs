@@ -14,41 +14,311 @@
 
 /// Grin server commands processing
 use std::env::current_dir;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use clap::ArgMatches;
 use ctrlc;
 use daemonize::Daemonize;
+#[cfg(unix)]
+use signal_hook;
 
-use config::GlobalConfig;
+use super::client::show_status;
+use super::daemon;
+use api;
+use config::{self, GlobalConfig};
 use core::global;
 use p2p::Seeding;
 use servers;
 use tui::ui;
+use util::file::get_first_line;
+use util::LoggingConfig;
+
+/// Server settings given on the command line, which take precedence over the
+/// config file, on start as on reload
+#[derive(Clone, Default)]
+struct ConfigOverrides {
+	port: Option<u16>,
+	api_port: Option<String>,
+	wallet_url: Option<String>,
+	seeds: Option<Vec<String>>,
+}
+
+impl ConfigOverrides {
+	fn from_args(a: &ArgMatches) -> ConfigOverrides {
+		ConfigOverrides {
+			port: a.value_of("port").map(|port| port.parse().unwrap()),
+			api_port: a.value_of("api_port").map(|p| p.to_string()),
+			wallet_url: a.value_of("wallet_url").map(|u| u.to_string()),
+			seeds: a
+				.values_of("seed")
+				.map(|seeds| seeds.map(|s| s.to_string()).collect()),
+		}
+	}
+
+	fn apply(&self, server_config: &mut servers::ServerConfig) {
+		if let Some(port) = self.port {
+			server_config.p2p_config.port = port;
+		}
+
+		if let Some(ref api_port) = self.api_port {
+			let default_ip = "0.0.0.0";
+			server_config.api_http_addr = format!("{}:{}", default_ip, api_port);
+		}
+
+		if let Some(ref wallet_url) = self.wallet_url {
+			let stratum_config = server_config.stratum_mining_config.as_mut().unwrap();
+			stratum_config.wallet_listener_url = wallet_url.clone();
+			// the one given on the command line gets all the rewards
+			stratum_config.wallet_listeners.clear();
+		}
+
+		if let Some(ref seeds) = self.seeds {
+			server_config.p2p_config.seeding_type = Seeding::List;
+			server_config.p2p_config.seeds = Some(seeds.clone());
+		}
+	}
+}
+
+/// Where the running configuration came from, to reload it
+#[derive(Clone)]
+struct ConfigSource {
+	path: Option<PathBuf>,
+	overrides: ConfigOverrides,
+	logging: Option<LoggingConfig>,
+}
+
+impl ConfigSource {
+	/// Lets the server reload its configuration, through the API or on SIGHUP
+	fn watch(&self, serv: &Arc<servers::Server>) {
+		let path = match self.path {
+			Some(ref p) => p.clone(),
+			None => return,
+		};
+		let overrides = self.overrides.clone();
+		serv.set_config_loader(
+			Box::new(move || load_config(&path, &overrides)),
+			self.logging.clone(),
+		);
+		reload_on_sighup(serv.clone());
+	}
+}
+
+fn load_config(
+	path: &PathBuf,
+	overrides: &ConfigOverrides,
+) -> Result<(servers::ServerConfig, Option<LoggingConfig>), String> {
+	let mut global_config =
+		GlobalConfig::new(path.to_str().unwrap()).map_err(|e| format!("{}", e))?;
+	let members = global_config.members.take().unwrap();
+	let mut server_config = members.server;
+	overrides.apply(&mut server_config);
+	Ok((server_config, members.logging))
+}
+
+#[cfg(unix)]
+fn reload_on_sighup(serv: Arc<servers::Server>) {
+	let hup = Arc::new(AtomicBool::new(false));
+	if let Err(e) = signal_hook::flag::register(signal_hook::SIGHUP, hup.clone()) {
+		error!(
+			"Error setting handler for SIGHUP, reload through the API only: {}",
+			e
+		);
+		return;
+	}
+	let _ = thread::Builder::new()
+		.name("config_reload".to_string())
+		.spawn(move || {
+			while !serv.stop.load(Ordering::Relaxed) {
+				if hup.swap(false, Ordering::SeqCst) {
+					warn!("Received SIGHUP, reloading the configuration.");
+					if let Err(e) = serv.reload_config() {
+						error!("Error reloading the configuration: {:?}", e);
+					}
+				}
+				thread::sleep(Duration::from_secs(1));
+			}
+		});
+}
+
+#[cfg(not(unix))]
+fn reload_on_sighup(_serv: Arc<servers::Server>) {}
 
 /// wrap below to allow UI to clean up on stop
-fn start_server(config: servers::ServerConfig) {
-	start_server_tui(config);
-	// Just kill process for now, otherwise the process
-	// hangs around until sigint because the API server
-	// currently has no shutdown facility
-	warn!("Shutting down...");
-	thread::sleep(Duration::from_millis(1000));
+fn start_server(config: servers::ServerConfig, source: ConfigSource) {
+	let pid_file = daemon::pid_file(&config);
+	start_server_tui(config, source);
+	daemon::remove_pid(&pid_file);
+	// The server is stopped and its state flushed by now, just kill the
+	// process as the API server currently has no shutdown facility
 	warn!("Shutdown complete.");
 	exit(0);
 }
 
-fn start_server_tui(config: servers::ServerConfig) {
+/// Refuses to start a second server over the same pid file
+fn check_not_running(pid_file: &Path) {
+	if let Some(pid) = daemon::running_pid(pid_file) {
+		println!(
+			"Grin server already running with pid {} (pid file {}).",
+			pid,
+			pid_file.display()
+		);
+		exit(1);
+	}
+}
+
+/// Sends the server daemon a shutdown signal and waits for it to exit
+fn stop_server(config: &servers::ServerConfig) -> i32 {
+	let pid_file = daemon::pid_file(config);
+	let pid = match daemon::running_pid(&pid_file) {
+		Some(pid) => pid,
+		None => {
+			println!(
+				"Grin server not running (no live pid in {}).",
+				pid_file.display()
+			);
+			return 1;
+		}
+	};
+	if let Err(e) = daemon::terminate(pid) {
+		println!("Could not signal the Grin server (pid {}): {}", pid, e);
+		return 1;
+	}
+	println!("Stopping Grin server (pid {})...", pid);
+
+	// leave the server its drain timeout, plus some to flush and close
+	let timeout = Duration::from_secs(config.shutdown_drain_timeout_secs + 10);
+	let start = Instant::now();
+	while daemon::running_pid(&pid_file) == Some(pid) {
+		if start.elapsed() > timeout {
+			println!("Grin server still running after {}s.", timeout.as_secs());
+			return 1;
+		}
+		thread::sleep(Duration::from_millis(500));
+	}
+	println!("Grin server stopped.");
+	0
+}
+
+/// Reports whether the server daemon runs and, if so, its chain status
+fn server_status(config: &servers::ServerConfig) -> i32 {
+	let pid_file = daemon::pid_file(config);
+	match daemon::running_pid(&pid_file) {
+		Some(pid) => println!("Grin server running with pid {}.", pid),
+		None => {
+			println!(
+				"Grin server not running (no live pid in {}).",
+				pid_file.display()
+			);
+			return 1;
+		}
+	}
+	let api_read_secret = get_first_line(config.api_read_secret_path.clone())
+		.or_else(|| get_first_line(config.api_secret_path.clone()));
+	show_status(config, api_read_secret);
+	0
+}
+
+/// Asks the running server to back its data up to the provided directory
+fn backup_server(config: &servers::ServerConfig, dest: &Path) -> i32 {
+	// relative paths are taken from the backup directory of the server
+	let req = servers::BackupRequest {
+		dest: dest.display().to_string(),
+	};
+	let url = format!("{}{}", config.api_url(), servers::BACKUP_PATH);
+	let api_secret = get_first_line(config.api_secret_path.clone());
+	match api::client::post::<_, servers::BackupReport>(&url, api_secret, &req) {
+		Ok(report) => {
+			println!(
+				"Backed up the Grin server data at height {} ({}) to {}.",
+				report.height, report.last_block_h, report.dest
+			);
+			0
+		}
+		Err(e) => {
+			println!("Could not back up the Grin server data: {}", e);
+			1
+		}
+	}
+}
+
+/// Restores a backup in place of the data of a stopped server
+fn restore_server(config: &servers::ServerConfig, src: &Path) -> i32 {
+	let pid_file = daemon::pid_file(config);
+	if let Some(pid) = daemon::running_pid(&pid_file) {
+		println!(
+			"Grin server running with pid {}, stop it before restoring.",
+			pid
+		);
+		return 1;
+	}
+	match servers::restore(src, &config.db_root) {
+		Ok(moved) => {
+			if let Some(moved) = moved {
+				println!("Previous Grin server data moved to {}.", moved.display());
+			}
+			println!("Restored {} to {}.", src.display(), config.db_root);
+			0
+		}
+		Err(e) => {
+			println!("Could not restore {}: {:?}", src.display(), e);
+			1
+		}
+	}
+}
+
+/// Rebuilds the chain indices of a stopped server from its chain data
+fn reindex_server(config: &servers::ServerConfig) -> i32 {
+	let pid_file = daemon::pid_file(config);
+	if let Some(pid) = daemon::running_pid(&pid_file) {
+		println!(
+			"Grin server running with pid {}, stop it before reindexing.",
+			pid
+		);
+		return 1;
+	}
+	match servers::reindex(config) {
+		Ok(report) => {
+			println!(
+				"Reindexed {} output positions and {} header heights, {} stale entries dropped.",
+				report.chain.output_positions, report.chain.header_heights, report.chain.dropped
+			);
+			if report.peers_pruned > 0 {
+				println!("Deleted {} unreadable peer records.", report.peers_pruned);
+			}
+			0
+		}
+		Err(e) => {
+			println!("Could not reindex {}: {:?}", config.db_root, e);
+			1
+		}
+	}
+}
+
+/// Catches SIGINT (Ctrl+C) and SIGTERM (kill), clearing the returned flag so
+/// the server gets stopped cleanly instead of being killed mid-write
+fn signal_flag() -> Arc<AtomicBool> {
+	let running = Arc::new(AtomicBool::new(true));
+	let r = running.clone();
+	ctrlc::set_handler(move || {
+		r.store(false, Ordering::SeqCst);
+	}).expect("Error setting handler for both SIGINT (Ctrl+C) and SIGTERM (kill)");
+	running
+}
+
+fn start_server_tui(config: servers::ServerConfig, source: ConfigSource) {
 	// Run the UI controller.. here for now for simplicity to access
 	// everything it might need
 	if config.run_tui.is_some() && config.run_tui.unwrap() {
 		warn!("Starting GRIN in UI mode...");
 		servers::Server::start(config, |serv: Arc<servers::Server>| {
-			let running = Arc::new(AtomicBool::new(true));
+			source.watch(&serv);
+			let running = signal_flag();
+			daemon::notify_systemd("READY=1");
 			let _ = thread::Builder::new()
 				.name("ui".to_string())
 				.spawn(move || {
@@ -61,15 +331,14 @@ fn start_server_tui(config: servers::ServerConfig) {
 	} else {
 		warn!("Starting GRIN w/o UI...");
 		servers::Server::start(config, |serv: Arc<servers::Server>| {
-			let running = Arc::new(AtomicBool::new(true));
-			let r = running.clone();
-			ctrlc::set_handler(move || {
-				r.store(false, Ordering::SeqCst);
-			}).expect("Error setting handler for both SIGINT (Ctrl+C) and SIGTERM (kill)");
+			source.watch(&serv);
+			let running = signal_flag();
+			daemon::notify_systemd("READY=1");
 			while running.load(Ordering::SeqCst) {
 				thread::sleep(Duration::from_secs(1));
 			}
 			warn!("Received SIGINT (Ctrl+C) or SIGTERM (kill).");
+			daemon::notify_systemd("STOPPING=1");
 			serv.stop();
 		}).unwrap();
 	}
@@ -93,29 +362,21 @@ pub fn server_command(server_args: Option<&ArgMatches>, mut global_config: Globa
 	// just get defaults from the global config
 	let mut server_config = global_config.members.as_ref().unwrap().server.clone();
 
-	if let Some(a) = server_args {
-		if let Some(port) = a.value_of("port") {
-			server_config.p2p_config.port = port.parse().unwrap();
-		}
-
-		if let Some(api_port) = a.value_of("api_port") {
-			let default_ip = "0.0.0.0";
-			server_config.api_http_addr = format!("{}:{}", default_ip, api_port);
-		}
-
-		if let Some(wallet_url) = a.value_of("wallet_url") {
-			server_config
-				.stratum_mining_config
-				.as_mut()
-				.unwrap()
-				.wallet_listener_url = wallet_url.to_string();
-		}
-
-		if let Some(seeds) = a.values_of("seed") {
-			server_config.p2p_config.seeding_type = Seeding::List;
-			server_config.p2p_config.seeds = Some(seeds.map(|s| s.to_string()).collect());
-		}
+	let overrides = server_args
+		.map(ConfigOverrides::from_args)
+		.unwrap_or_default();
+	overrides.apply(&mut server_config);
+	if let Some(ref path) = server_config.network_definition {
+		config::apply_network_definition(path).unwrap_or_else(|e| {
+			panic!("Error loading the network definition: {}", e);
+		});
+		warn!("Consensus parameters overridden by {}", path);
 	}
+	let source = ConfigSource {
+		path: global_config.config_file_path.clone(),
+		overrides,
+		logging: global_config.members.as_ref().unwrap().logging.clone(),
+	};
 
 	/*if let Some(true) = server_config.run_wallet_listener {
 		let mut wallet_config = global_config.members.as_ref().unwrap().wallet.clone();
@@ -155,15 +416,24 @@ pub fn server_command(server_args: Option<&ArgMatches>, mut global_config: Globa
 	if let Some(a) = server_args {
 		match a.subcommand() {
 			("run", _) => {
-				start_server(server_config);
+				let pid_file = daemon::pid_file(&server_config);
+				check_not_running(&pid_file);
+				if let Err(e) = daemon::write_pid(&pid_file) {
+					warn!("Could not write pid file {}: {}", pid_file.display(), e);
+				}
+				start_server(server_config, source);
 			}
 			("start", _) => {
+				let pid_file = daemon::pid_file(&server_config);
+				check_not_running(&pid_file);
+				// nothing to draw the UI on once detached
+				server_config.run_tui = Some(false);
 				let daemonize = Daemonize::new()
-					.pid_file("/tmp/grin.pid")
+					.pid_file(pid_file)
 					.chown_pid_file(true)
 					.working_directory(current_dir().unwrap())
 					.privileged_action(move || {
-						start_server(server_config.clone());
+						start_server(server_config.clone(), source.clone());
 						loop {
 							thread::sleep(Duration::from_secs(60));
 						}
@@ -173,7 +443,15 @@ pub fn server_command(server_args: Option<&ArgMatches>, mut global_config: Globa
 					Err(e) => error!("Error starting: {}", e),
 				}
 			}
-			("stop", _) => println!("TODO. Just 'kill $pid' for now. Maybe /tmp/grin.pid is $pid"),
+			("stop", _) => return stop_server(&server_config),
+			("status", _) => return server_status(&server_config),
+			("backup", Some(args)) => {
+				return backup_server(&server_config, Path::new(args.value_of("dest").unwrap()))
+			}
+			("restore", Some(args)) => {
+				return restore_server(&server_config, Path::new(args.value_of("src").unwrap()))
+			}
+			("reindex", _) => return reindex_server(&server_config),
 			(cmd, _) => {
 				println!(":: {:?}", server_args);
 				panic!(
@@ -183,7 +461,7 @@ pub fn server_command(server_args: Option<&ArgMatches>, mut global_config: Globa
 			}
 		}
 	} else {
-		start_server(server_config);
+		start_server(server_config, source);
 	}
 	0
 }
@@ -12,26 +12,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use chrono::prelude::*;
+use chrono::Duration as ChronoDuration;
 use clap::ArgMatches;
-use serde_json as json;
-use std::fs::File;
-use std::io::Read;
-use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
 /// Wallet commands processing
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Duration;
 use util::Mutex;
 
 use api::TLSConfig;
-use config::GlobalWalletConfig;
+use config::{self, GlobalWalletConfig};
 use core::{core, global};
+use grin_wallet::adapters::{self, FileSlateAdapter, SlateAdapters, TorSlateAdapter};
+use grin_wallet::libwallet::types::{CheckFixes, SigningRequest, WatchedOutput};
 use grin_wallet::libwallet::ErrorKind;
-use grin_wallet::{self, controller, display, libwallet};
+use grin_wallet::{self, controller, display, export, libwallet, tor, watcher};
 use grin_wallet::{
-	HTTPWalletClient, LMDBBackend, WalletBackend, WalletConfig, WalletInst, WalletSeed,
+	HTTPWalletClient, LMDBBackend, SlateAdapter, WalletBackend, WalletConfig, WalletInst,
+	WalletSeed,
 };
 use keychain;
+use serde_json;
 use servers::start_webwallet_server;
 use util::file::get_first_line;
 
@@ -74,6 +79,109 @@ pub fn instantiate_wallet(
 	Box::new(db_wallet)
 }
 
+/// Parse a YYYY-MM-DD date given to export_txs
+fn parse_export_date(date: &str, arg: &str) -> Result<Date<Utc>, libwallet::Error> {
+	let d = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+		ErrorKind::GenericError(format!(
+			"Unable to parse argument '{}' as a date (YYYY-MM-DD)",
+			arg
+		))
+	})?;
+	Ok(Utc.from_utc_date(&d))
+}
+
+/// Runs `f` with the terminal no longer echoing what's typed, still echoing
+/// the final newline
+#[cfg(unix)]
+fn without_echo<T, F: FnOnce() -> T>(f: F) -> T {
+	use libc;
+	use std::mem;
+	unsafe {
+		let mut term: libc::termios = mem::zeroed();
+		if libc::isatty(libc::STDIN_FILENO) != 1
+			|| libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0
+		{
+			return f();
+		}
+		let saved = term;
+		term.c_lflag &= !libc::ECHO;
+		term.c_lflag |= libc::ECHONL;
+		libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term);
+		let res = f();
+		libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+		res
+	}
+}
+
+#[cfg(not(unix))]
+fn without_echo<T, F: FnOnce() -> T>(f: F) -> T {
+	f()
+}
+
+/// Read a passphrase from the terminal, without echoing it
+fn prompt_passphrase(prompt: &str) -> Result<String, io::Error> {
+	print!("{}", prompt);
+	io::stdout().flush()?;
+	let mut passphrase = String::new();
+	without_echo(|| io::stdin().read_line(&mut passphrase))?;
+	while passphrase.ends_with('\n') || passphrase.ends_with('\r') {
+		passphrase.pop();
+	}
+	Ok(passphrase)
+}
+
+/// Read a passphrase about to encrypt the wallet database, twice as a typo
+/// would lock the wallet out of it
+fn prompt_new_passphrase() -> Result<String, io::Error> {
+	let passphrase = prompt_passphrase("Wallet passphrase: ")?;
+	if prompt_passphrase("Confirm wallet passphrase: ")? != passphrase {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"passphrases don't match",
+		));
+	}
+	Ok(passphrase)
+}
+
+/// The wallet passphrase, from the command line or the terminal. It also
+/// encrypts the wallet database, so is only asked for when the database is
+/// encrypted with one, or is about to be: when created, or when it was
+/// written before encryption was introduced.
+fn wallet_passphrase(
+	wallet_args: &ArgMatches,
+	wallet_config: &WalletConfig,
+) -> Result<String, String> {
+	let plaintext = grin_wallet::wallet_db_plaintext(wallet_config)
+		.map_err(|e| format!("Failed to open wallet database: {}", e))?;
+	let new_db = wallet_args.subcommand_name() == Some("init");
+	let passphrase = match wallet_args.value_of("pass") {
+		Some(p) => p.to_owned(),
+		None if plaintext => {
+			println!("The wallet database isn't encrypted yet, enter the wallet passphrase to encrypt it with.");
+			prompt_new_passphrase().map_err(|e| format!("Failed to read passphrase: {}", e))?
+		}
+		None if new_db => {
+			prompt_new_passphrase().map_err(|e| format!("Failed to read passphrase: {}", e))?
+		}
+		None => {
+			let locked = grin_wallet::wallet_db_locked(wallet_config)
+				.map_err(|e| format!("Failed to open wallet database: {}", e))?;
+			if locked {
+				prompt_passphrase("Wallet passphrase: ")
+					.map_err(|e| format!("Failed to read passphrase: {}", e))?
+			} else {
+				String::new()
+			}
+		}
+	};
+	if plaintext {
+		info!("Encrypting the wallet database");
+		grin_wallet::encrypt_wallet_db(wallet_config, &passphrase)
+			.map_err(|e| format!("Failed to encrypt wallet database: {}", e))?;
+	}
+	Ok(passphrase)
+}
+
 pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i32 {
 	// just get defaults from the global config
 	let mut wallet_config = config.members.unwrap().wallet;
@@ -81,6 +189,11 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 	if let Some(t) = wallet_config.chain_type.clone() {
 		global::set_mining_mode(t);
 	}
+	if let Some(ref path) = wallet_config.network_definition {
+		config::apply_network_definition(path).unwrap_or_else(|e| {
+			panic!("Error loading the network definition: {}", e);
+		});
+	}
 
 	if wallet_args.is_present("external") {
 		wallet_config.api_listen_interface = "0.0.0.0".to_string();
@@ -100,20 +213,42 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 	}
 	let node_api_secret = get_first_line(wallet_config.node_api_secret_path.clone());
 
+	let passphrase = match wallet_passphrase(wallet_args, &wallet_config) {
+		Ok(p) => p,
+		Err(e) => {
+			error!("{}", e);
+			return 1;
+		}
+	};
+	let passphrase = passphrase.as_str();
+
 	// Derive the keychain based on seed from seed file and specified passphrase.
 	// Generate the initial wallet seed if we are running "wallet init".
-	if let ("init", Some(_)) = wallet_args.subcommand() {
-		WalletSeed::init_file(&wallet_config).expect("Failed to init wallet seed file.");
-		info!("Wallet seed file created");
+	if let ("init", Some(init_args)) = wallet_args.subcommand() {
+		// a cold wallet's seed lives on the offline machine signing for it
+		let cold = init_args.is_present("cold");
+		if !cold {
+			WalletSeed::init_file(&wallet_config).expect("Failed to init wallet seed file.");
+			info!("Wallet seed file created");
+		}
 		let client =
 			HTTPWalletClient::new(&wallet_config.check_node_api_http_addr, node_api_secret);
-		let _: LMDBBackend<HTTPWalletClient, keychain::ExtKeychain> =
-			LMDBBackend::new(wallet_config.clone(), "", client).unwrap_or_else(|e| {
+		let db: LMDBBackend<HTTPWalletClient, keychain::ExtKeychain> =
+			LMDBBackend::new(wallet_config.clone(), passphrase, client).unwrap_or_else(|e| {
 				panic!(
 					"Error creating DB for wallet: {} Config: {:?}",
 					e, wallet_config
 				);
 			});
+		if cold {
+			db.init_watch_only()
+				.expect("Failed to set up watch-only wallet database.");
+			info!("Watch-only wallet created, import outputs from the signing wallet with 'import_outputs'");
+		} else if init_args.is_present("bip44") {
+			db.set_key_derivation(keychain::KeyDerivation::Bip44)
+				.expect("Failed to set the wallet key derivation.");
+			info!("Wallet keys derived along BIP44 paths");
+		}
 		info!("Wallet database backend created");
 		// give logging thread a moment to catch up
 		thread::sleep(Duration::from_millis(200));
@@ -121,13 +256,35 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 		return 0;
 	}
 
-	let passphrase = match wallet_args.value_of("pass") {
-		None => {
-			error!("Failed to read passphrase.");
+	// The derivation scheme applies to the whole wallet, whatever the account
+	if let ("derivation", Some(deriv_args)) = wallet_args.subcommand() {
+		let client =
+			HTTPWalletClient::new(&wallet_config.check_node_api_http_addr, node_api_secret);
+		let db: LMDBBackend<HTTPWalletClient, keychain::ExtKeychain> =
+			match LMDBBackend::new(wallet_config.clone(), passphrase, client) {
+				Ok(db) => db,
+				Err(e) => {
+					error!("Error opening wallet database: {}", e);
+					return 1;
+				}
+			};
+		let res = match deriv_args.value_of("set") {
+			Some(d) => db.set_key_derivation(d.parse().unwrap()),
+			None => Ok(()),
+		};
+		if let Err(e) = res {
+			error!("Could not switch the key derivation: {}", e);
 			return 1;
 		}
-		Some(p) => p,
-	};
+		match db.key_derivation() {
+			Ok(d) => println!("Wallet keys derivation: {}", d),
+			Err(e) => {
+				error!("Could not read the key derivation: {}", e);
+				return 1;
+			}
+		}
+		return 0;
+	}
 
 	let account = match wallet_args.value_of("account") {
 		None => {
@@ -164,6 +321,41 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 				if let Some(port) = listen_args.value_of("port") {
 					wallet_config.api_listen_port = port.parse().unwrap();
 				}
+				// kept until the listener exits, tor being stopped when dropped
+				let (_onion_service, tls_conf) = if listen_args.is_present("tor") {
+					if tls_conf.is_some() {
+						warn!("Onion services are reached over plain http, ignoring TLS settings");
+					}
+					let tor_binary = wallet_config.tor_binary.clone().unwrap_or("tor".to_owned());
+					// tor reaches the listener on loopback when it listens on
+					// all interfaces
+					let local_addr = match wallet_config.api_listen_interface.as_str() {
+						"0.0.0.0" | "::" => format!("127.0.0.1:{}", wallet_config.api_listen_port),
+						_ => wallet_config.api_listen_addr(),
+					};
+					let service = tor::publish_onion_service(
+						&tor_binary,
+						&wallet_config.data_file_dir,
+						&local_addr,
+					).unwrap_or_else(|e| {
+						panic!("Error publishing onion service: {}", e);
+					});
+					warn!("Receiving at onion address {}", service.address());
+					(Some(service), None)
+				} else {
+					(None, tls_conf)
+				};
+				let wallet = Arc::new(Mutex::new(wallet));
+				if listen_args.is_present("watch") {
+					let notifier = watcher::Notifier::new(&wallet_config);
+					if !notifier.has_hooks() {
+						warn!("No notify_command or notify_url set, only logging payments");
+					}
+					let interval = wallet_config
+						.watch_interval_secs
+						.unwrap_or(watcher::WATCH_INTERVAL_SECS);
+					watcher::start(wallet.clone(), notifier, Duration::from_secs(interval));
+				}
 				controller::foreign_listener(wallet, &wallet_config.api_listen_addr(), tls_conf)
 					.unwrap_or_else(|e| {
 						panic!(
@@ -205,6 +397,18 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 		node_api_secret,
 	)));
 	let res = controller::owner_single_use(wallet.clone(), |api| {
+		// expire stale sends first, so their outputs are available again
+		if let Some(ttl) = wallet_config.pending_tx_ttl_hours {
+			match wallet_args.subcommand() {
+				("cancel_expired", _) | ("restore", _) | ("check", _) => {}
+				_ => match api.cancel_expired_txs(ChronoDuration::hours(ttl as i64), false) {
+					Ok(txs) => for t in txs {
+						warn!("Cancelled transaction {}, not finalized within {} hours", t.id, ttl);
+					},
+					Err(e) => warn!("Could not cancel expired transactions: {}", e),
+				},
+			}
+		}
 		match wallet_args.subcommand() {
 			("account", Some(acct_args)) => {
 				let create = acct_args.value_of("create");
@@ -236,6 +440,27 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 				}
 				Ok(())
 			}
+			("contacts", Some(contact_args)) => match contact_args.subcommand() {
+				("add", Some(add_args)) => {
+					let name = add_args.value_of("name").unwrap();
+					let dest = add_args.value_of("dest").unwrap();
+					let method = add_args.value_of("method").unwrap();
+					api.add_contact(name, method, dest)?;
+					println!("Contact: '{}' Added!", name);
+					Ok(())
+				}
+				("remove", Some(remove_args)) => {
+					let name = remove_args.value_of("name").unwrap();
+					api.remove_contact(name)?;
+					println!("Contact: '{}' Removed!", name);
+					Ok(())
+				}
+				_ => {
+					let contacts = api.contacts()?;
+					display::contacts(contacts);
+					Ok(())
+				}
+			},
 			("send", Some(send_args)) => {
 				let amount = send_args.value_of("amount").ok_or_else(|| {
 					ErrorKind::GenericError("Amount to send required".to_string())
@@ -267,20 +492,6 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 				let method = send_args.value_of("method").ok_or_else(|| {
 					ErrorKind::GenericError("Payment method required".to_string())
 				})?;
-				let dest = {
-					if method == "self" {
-						match send_args.value_of("dest") {
-							Some(d) => d,
-							None => "default",
-						}
-					} else {
-						send_args.value_of("dest").ok_or_else(|| {
-							ErrorKind::GenericError(
-								"Destination wallet address required".to_string(),
-							)
-						})?
-					}
-				};
 				let change_outputs = send_args
 					.value_of("change_outputs")
 					.ok_or_else(|| ErrorKind::GenericError("Change outputs required".to_string()))
@@ -294,59 +505,74 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 					})?;
 				let fluff = send_args.is_present("fluff");
 				let max_outputs = 500;
-				if method == "http" {
-					if dest.starts_with("http://") || dest.starts_with("https://") {
-						let result = api.issue_send_tx(
+				let change_outputs = match send_args.value_of("change_target") {
+					Some(t) => {
+						let target = core::amount_from_hr_string(t).map_err(|e| {
+							ErrorKind::GenericError(format!(
+								"Could not parse change target as a number with optional decimal point. e={:?}",
+								e
+							))
+						})?;
+						api.change_outputs_for_target(
 							amount,
 							minimum_confirmations,
-							dest,
 							max_outputs,
-							change_outputs,
 							selection_strategy == "all",
-						);
-						let slate = match result {
-							Ok(s) => {
-								info!(
-									"Tx created: {} grin to {} (strategy '{}')",
-									core::amount_to_hr_string(amount, false),
-									dest,
-									selection_strategy,
-								);
-								s
-							}
-							Err(e) => {
-								error!("Tx not created: {}", e);
-								match e.kind() {
-									// user errors, don't backtrace
-									libwallet::ErrorKind::NotEnoughFunds { .. } => {}
-									libwallet::ErrorKind::FeeDispute { .. } => {}
-									libwallet::ErrorKind::FeeExceedsAmount { .. } => {}
-									_ => {
-										// otherwise give full dump
-										error!("Backtrace: {}", e.backtrace().unwrap());
-									}
-								};
-								return Err(e);
-							}
-						};
-						let result = api.post_tx(&slate, fluff);
-						match result {
-							Ok(_) => {
-								info!("Tx sent",);
-								Ok(())
-							}
-							Err(e) => {
-								error!("Tx not sent: {}", e);
-								Err(e)
-							}
+							target,
+						)?
+					}
+					None => change_outputs,
+				};
+				if send_args.is_present("estimate_only") {
+					let estimate = api.estimate_send_tx(
+						amount,
+						minimum_confirmations,
+						max_outputs,
+						change_outputs,
+						selection_strategy == "all",
+					)?;
+					display::send_estimate(
+						account,
+						&estimate,
+						wallet_config.dark_background_color_scheme.unwrap_or(true),
+					);
+					return Ok(());
+				}
+				let dest = {
+					if method == "self" {
+						match send_args.value_of("dest") {
+							Some(d) => d,
+							None => "default",
 						}
 					} else {
-						return Err(ErrorKind::GenericError(format!(
-							"HTTP Destination should start with http://: or https://: {}",
-							dest
-						)).into());
+						send_args.value_of("dest").ok_or_else(|| {
+							ErrorKind::GenericError(
+								"Destination wallet address required".to_string(),
+							)
+						})?
 					}
-				} else if method == "self" {
+				};
+				// resolve address book contacts, whose own send method applies
+				// unless one was given
+				let contact = if method == "self" {
+					None
+				} else {
+					api.resolve_contact(dest)?
+				};
+				let (method, dest) = match contact {
+					Some(c) => {
+						info!("Sending to contact '{}' at {}", c.name, c.dest);
+						if send_args.occurrences_of("method") == 0 {
+							(c.method, c.dest)
+						} else {
+							(method.to_owned(), c.dest)
+						}
+					}
+					None => (method.to_owned(), dest.to_owned()),
+				};
+				let method = method.as_str();
+				let dest = dest.as_str();
+				if method == "self" {
 					let result = api.issue_self_tx(
 						amount,
 						minimum_confirmations,
@@ -393,22 +619,68 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 							Err(e)
 						}
 					}
-				} else if method == "file" {
-					api.send_tx(
-						true,
+				} else {
+					let mut adapters = SlateAdapters::default();
+					if let Some(ref proxy) = wallet_config.tor_socks_proxy {
+						adapters.register("tor", Box::new(TorSlateAdapter::new(proxy)));
+					}
+					if send_args.is_present("armor") {
+						adapters.register("file", Box::new(FileSlateAdapter::new(true)));
+					}
+					let adapter = adapters.for_dest(dest, method).ok_or_else(|| {
+						ErrorKind::UnknownSlateAdapter(
+							adapters::scheme(dest).unwrap_or(method).to_owned(),
+						)
+					})?;
+					let result = api.send_tx_via_adapter(
+						adapter,
 						amount,
 						minimum_confirmations,
 						dest,
 						max_outputs,
 						change_outputs,
 						selection_strategy == "all",
-					).map_err(|e| ErrorKind::GenericError(format!("Send failed. e={:?}", e)))?;
-					Ok(())
-				} else {
-					return Err(ErrorKind::GenericError(format!(
-						"unsupported payment method: {}",
-						method
-					)).into());
+					);
+					let slate = match result {
+						Ok(s) => {
+							info!(
+								"Tx created: {} grin to {} (strategy '{}')",
+								core::amount_to_hr_string(amount, false),
+								dest,
+								selection_strategy,
+							);
+							s
+						}
+						Err(e) => {
+							error!("Tx not created: {}", e);
+							match e.kind() {
+								// user errors, don't backtrace
+								libwallet::ErrorKind::NotEnoughFunds { .. } => {}
+								libwallet::ErrorKind::FeeDispute { .. } => {}
+								libwallet::ErrorKind::FeeExceedsAmount { .. } => {}
+								_ => {
+									// otherwise give full dump
+									error!("Backtrace: {}", e.backtrace().unwrap());
+								}
+							};
+							return Err(e);
+						}
+					};
+					if !adapter.supports_sync() {
+						// the response comes back for finalize
+						return Ok(());
+					}
+					let result = api.post_tx(&slate, fluff);
+					match result {
+						Ok(_) => {
+							info!("Tx sent",);
+							Ok(())
+						}
+						Err(e) => {
+							error!("Tx not sent: {}", e);
+							Err(e)
+						}
+					}
 				}
 			}
 			("receive", Some(send_args)) => {
@@ -416,40 +688,32 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 				let tx_file = send_args.value_of("input").ok_or_else(|| {
 					ErrorKind::GenericError("Transaction file required".to_string())
 				})?;
-				if !Path::new(tx_file).is_file() {
-					return Err(
-						ErrorKind::GenericError(format!("File {} not found.", tx_file)).into(),
-					);
-				}
+				let (mut slate, armored) = FileSlateAdapter::read_slate(tx_file)?;
 				let res = controller::foreign_single_use(wallet, |api| {
-					receive_result = api.file_receive_tx(tx_file);
+					receive_result = api.receive_tx(&mut slate);
 					Ok(())
 				});
-				if res.is_err() {
-					return res;
+				res?;
+				receive_result?;
+				let adapter = FileSlateAdapter::new(armored || send_args.is_present("armor"));
+				if tx_file == adapters::STDIO_PATH {
+					adapter.send_tx_async(tx_file, &slate)
 				} else {
+					let response_file = format!("{}.response", tx_file);
+					adapter.send_tx_async(&response_file, &slate)?;
 					info!(
-						"Response file {}.response generated, sending it back to the transaction originator.",
-						tx_file,
+						"Response file {} generated, sending it back to the transaction originator.",
+						response_file,
 					);
+					Ok(())
 				}
-				receive_result
 			}
 			("finalize", Some(send_args)) => {
 				let fluff = send_args.is_present("fluff");
 				let tx_file = send_args.value_of("input").ok_or_else(|| {
 					ErrorKind::GenericError("Receiver's transaction file required".to_string())
 				})?;
-				if !Path::new(tx_file).is_file() {
-					return Err(
-						ErrorKind::GenericError(format!("File {} not found.", tx_file)).into(),
-					);
-				}
-				let mut pub_tx_f = File::open(tx_file)?;
-				let mut content = String::new();
-				pub_tx_f.read_to_string(&mut content)?;
-				let mut slate: grin_wallet::libtx::slate::Slate = json::from_str(&content)
-					.map_err(|_| grin_wallet::libwallet::ErrorKind::Format)?;
+				let mut slate = FileSlateAdapter::new(false).receive_tx_async(tx_file)?;
 				let _ = api.finalize_tx(&mut slate).expect("Finalize failed");
 
 				let result = api.post_tx(&slate, fluff);
@@ -459,7 +723,11 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 						Ok(())
 					}
 					Err(e) => {
-						error!("Tx not sent: {}", e);
+						error!(
+							"Tx not sent: {}. It's stored finalized, \
+							 post it again with `grin wallet repost`.",
+							e
+						);
 						Err(e)
 					}
 				}
@@ -561,6 +829,45 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 				};
 				Ok(())
 			}
+			("balance_history", Some(history_args)) => {
+				if let Some(record) = history_args.value_of("record_per_block") {
+					api.set_snapshots_per_block(record == "on")?;
+					println!("Per block balance snapshots turned {}", record);
+					return Ok(());
+				}
+				let per_block = history_args.is_present("per_block");
+				let (validated, snapshots) = api.retrieve_balance_history(true, per_block)?;
+				display::balance_history(account, snapshots, per_block, validated);
+				Ok(())
+			}
+			("export_txs", Some(export_args)) => {
+				let from = match export_args.value_of("from") {
+					Some(d) => Some(parse_export_date(d, "from")?.and_hms(0, 0, 0)),
+					None => None,
+				};
+				let to = match export_args.value_of("to") {
+					Some(d) => Some(parse_export_date(d, "to")?.and_hms(23, 59, 59)),
+					None => None,
+				};
+				let (validated, txs) = api.export_txs(true, from, to)?;
+				if !validated {
+					warn!("Wallet failed to verify data against a live chain, exporting from local cache.");
+				}
+				let content = match export_args.value_of("format") {
+					Some("json") => export::txs_json(&txs)?,
+					_ => export::txs_csv(&txs),
+				};
+				match export_args.value_of("dest") {
+					Some(dest) => {
+						let mut f = File::create(dest)?;
+						f.write_all(content.as_bytes())?;
+						f.sync_all()?;
+						info!("Exported {} transactions to {}", txs.len(), dest);
+					}
+					None => print!("{}", content),
+				}
+				Ok(())
+			}
 			("repost", Some(repost_args)) => {
 				let tx_id = repost_args
 					.value_of("id")
@@ -630,8 +937,218 @@ pub fn wallet_command(wallet_args: &ArgMatches, config: GlobalWalletConfig) -> i
 					}
 				}
 			}
+			("cold_send", Some(send_args)) => {
+				let amount = send_args.value_of("amount").unwrap();
+				let amount = core::amount_from_hr_string(amount).map_err(|e| {
+					ErrorKind::GenericError(format!(
+						"Could not parse amount as a number with optional decimal point. e={:?}",
+						e
+					))
+				})?;
+				let parse_arg = |name: &str| -> Result<u64, ErrorKind> {
+					send_args.value_of(name).unwrap().parse().map_err(|e| {
+						ErrorKind::GenericError(format!(
+							"Could not parse {} as a whole number. e={:?}",
+							name, e
+						))
+					})
+				};
+				let minimum_confirmations = parse_arg("minimum_confirmations")?;
+				let change_outputs = parse_arg("change_outputs")? as usize;
+				let max_outputs = 500;
+				let use_all = send_args.value_of("selection_strategy") == Some("all");
+				let dest = send_args.value_of("dest").unwrap();
+				let req = api.signing_request(
+					amount,
+					minimum_confirmations,
+					max_outputs,
+					change_outputs,
+					use_all,
+				)?;
+				let mut file = File::create(dest)?;
+				file.write_all(serde_json::to_string_pretty(&req).unwrap().as_bytes())?;
+				info!(
+					"Signing request {} written to {}, sign it with the offline wallet",
+					req.id, dest
+				);
+				Ok(())
+			}
+			("sign", Some(sign_args)) => {
+				let input = sign_args.value_of("input").unwrap();
+				let content = FileSlateAdapter::read(input)?;
+				// either the request to start a send, or the recipient's
+				// response to the slate it produced
+				let slate = match serde_json::from_str::<SigningRequest>(&content) {
+					Ok(req) => api.sign_request(&req)?,
+					Err(req_err) => {
+						let (mut slate, _) =
+							FileSlateAdapter::parse_slate(&content).map_err(|e| {
+								ErrorKind::GenericError(format!(
+									"{} is neither a signing request ({}) nor a slate ({})",
+									input, req_err, e
+								))
+							})?;
+						api.sign_response(&mut slate)?;
+						slate
+					}
+				};
+				let output = match sign_args.value_of("output") {
+					Some(o) => o.to_owned(),
+					None if input == adapters::STDIO_PATH => input.to_owned(),
+					None => format!("{}.signed", input),
+				};
+				FileSlateAdapter::new(false).send_tx_async(&output, &slate)?;
+				info!("Signed slate written to {}", output);
+				Ok(())
+			}
+			("cold_finalize", Some(fin_args)) => {
+				let input = fin_args.value_of("input").unwrap();
+				let fluff = fin_args.is_present("fluff");
+				let (mut slate, _) = FileSlateAdapter::read_slate(input)?;
+				api.finalize_signed(&mut slate)?;
+				match api.post_tx(&slate, fluff) {
+					Ok(_) => {
+						info!("Tx sent",);
+						Ok(())
+					}
+					Err(e) => {
+						error!("Tx not sent: {}", e);
+						Err(e)
+					}
+				}
+			}
+			("export_outputs", Some(exp_args)) => {
+				let output = exp_args.value_of("output").unwrap();
+				let outputs = api.export_outputs()?;
+				let mut file = File::create(output)?;
+				file.write_all(serde_json::to_string_pretty(&outputs).unwrap().as_bytes())?;
+				info!("{} outputs exported to {}", outputs.len(), output);
+				Ok(())
+			}
+			("import_outputs", Some(imp_args)) => {
+				let input = imp_args.value_of("input").unwrap();
+				let outputs: Vec<WatchedOutput> = serde_json::from_str(&fs::read_to_string(input)?)
+					.map_err(|e| {
+						ErrorKind::GenericError(format!("Invalid outputs file {}: {}", input, e))
+					})?;
+				let count = api.import_outputs(outputs)?;
+				info!("{} new outputs imported from {}", count, input);
+				Ok(())
+			}
+			("consolidate", Some(cons_args)) => {
+				let threshold = match cons_args.value_of("below") {
+					Some(b) => Some(core::amount_from_hr_string(b).map_err(|e| {
+						ErrorKind::GenericError(format!(
+							"Could not parse below as a number with optional decimal point. e={:?}",
+							e
+						))
+					})?),
+					None => None,
+				};
+				let parse_arg = |name: &str| -> Result<u64, ErrorKind> {
+					cons_args.value_of(name).unwrap().parse().map_err(|e| {
+						ErrorKind::GenericError(format!(
+							"Could not parse {} as a whole number. e={:?}",
+							name, e
+						))
+					})
+				};
+				let min_outputs = parse_arg("min_outputs")? as usize;
+				let max_inputs = parse_arg("max_inputs")? as usize;
+				let minimum_confirmations = parse_arg("minimum_confirmations")?;
+				let fluff = cons_args.is_present("fluff");
+
+				let slates =
+					api.consolidate(minimum_confirmations, threshold, min_outputs, max_inputs)?;
+				if slates.is_empty() {
+					println!("No outputs to consolidate");
+					return Ok(());
+				}
+				for slate in &slates {
+					if let Err(e) = api.post_tx(slate, fluff) {
+						error!("Consolidation tx not sent: {}", e);
+						return Err(e);
+					}
+					info!(
+						"Consolidation tx sent, merging {} outputs",
+						slate.tx.inputs().len()
+					);
+				}
+				Ok(())
+			}
+			("cancel_expired", Some(expire_args)) => {
+				let ttl = match expire_args.value_of("ttl") {
+					Some(t) => t.parse().map_err(|e| {
+						ErrorKind::GenericError(format!("Could not parse ttl parameter. e={:?}", e))
+					})?,
+					None => wallet_config.pending_tx_ttl_hours.ok_or_else(|| {
+						ErrorKind::GenericError(
+							"'ttl' argument (-t) is required when pending_tx_ttl_hours isn't configured"
+								.to_string(),
+						)
+					})?,
+				};
+				let dry_run = expire_args.is_present("dry_run");
+				let txs = api.cancel_expired_txs(ChronoDuration::hours(ttl as i64), dry_run)?;
+				if txs.is_empty() {
+					println!("No pending transactions older than {} hours", ttl);
+					return Ok(());
+				}
+				let (height, _) = api.node_height()?;
+				display::txs(
+					account,
+					height,
+					true,
+					txs.clone(),
+					false,
+					wallet_config.dark_background_color_scheme.unwrap_or(true),
+				)?;
+				if dry_run {
+					println!("{} transaction(s) would be cancelled", txs.len());
+				} else {
+					info!("{} expired transaction(s) cancelled", txs.len());
+				}
+				Ok(())
+			}
+			("check", Some(check_args)) => {
+				let mut fixes = CheckFixes::default();
+				if let Some(values) = check_args.values_of("fix") {
+					for v in values {
+						match v {
+							"all" => fixes = CheckFixes::all(),
+							"missing_on_chain" => fixes.missing_on_chain = true,
+							"missing_from_wallet" => fixes.missing_from_wallet = true,
+							"confirmed_locked" => fixes.confirmed_locked = true,
+							"duplicate_indices" => fixes.duplicate_indices = true,
+							_ => {}
+						}
+					}
+				}
+				let (tx, rx) = mpsc::channel();
+				let progress_thread = thread::spawn(move || {
+					for p in rx {
+						display::check_progress(&p);
+					}
+				});
+				let result = api.check_repair_with_progress(fixes, tx);
+				let _ = progress_thread.join();
+				let report = result.map_err(|e| {
+					error!("Wallet check failed: {}", e);
+					e
+				})?;
+				display::check_report(&report);
+				Ok(())
+			}
 			("restore", Some(_)) => {
-				let result = api.restore();
+				let (tx, rx) = mpsc::channel();
+				let progress_thread = thread::spawn(move || {
+					for p in rx {
+						display::restore_progress(&p);
+					}
+				});
+				let result = api.restore_with_progress(tx);
+				// sender is dropped once restore returns, ending the display
+				let _ = progress_thread.join();
 				match result {
 					Ok(_) => {
 						info!("Wallet restore complete",);
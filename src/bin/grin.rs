@@ -21,8 +21,12 @@ extern crate clap;
 extern crate ctrlc;
 extern crate cursive;
 extern crate daemonize;
+#[cfg(unix)]
+extern crate libc;
 extern crate serde;
 extern crate serde_json;
+#[cfg(unix)]
+extern crate signal_hook;
 #[macro_use]
 extern crate log;
 extern crate term;
@@ -85,6 +89,11 @@ fn real_main() -> i32 {
 		.version(crate_version!())
 		.author("The Grin Team")
 		.about("Lightweight implementation of the MimbleWimble protocol.")
+		.arg(Arg::with_name("network")
+			.long("network")
+			.help("Network to use, selecting its genesis, ports and data directory (under ~/.grin/<network>)")
+			.possible_values(&["mainnet", "floonet", "usernet"])
+			.takes_value(true))
     // specification of all the server commands and options
     .subcommand(SubCommand::with_name("server")
                 .about("Control the Grin server")
@@ -119,16 +128,79 @@ fn real_main() -> i32 {
                             .about("Start the Grin server as a daemon"))
                 .subcommand(SubCommand::with_name("stop")
                             .about("Stop the Grin server daemon"))
+                .subcommand(SubCommand::with_name("status")
+                            .about("Show whether the Grin server daemon runs, and its chain status"))
                 .subcommand(SubCommand::with_name("run")
-                            .about("Run the Grin server in this console")))
+                            .about("Run the Grin server in this console"))
+                .subcommand(SubCommand::with_name("backup")
+                            .about("Back up the data of the running Grin server")
+                            .arg(Arg::with_name("dest")
+                                 .help("Directory to write the backup to, under the configured backup_dir, must not exist or be empty")
+                                 .short("d")
+                                 .long("dest")
+                                 .required(true)
+                                 .takes_value(true)))
+                .subcommand(SubCommand::with_name("restore")
+                            .about("Restore a backup, the Grin server being stopped")
+                            .arg(Arg::with_name("src")
+                                 .help("Directory holding the backup")
+                                 .short("s")
+                                 .long("src")
+                                 .required(true)
+                                 .takes_value(true)))
+                .subcommand(SubCommand::with_name("reindex")
+                            .about("Rebuild the chain indices from the block and txhashset data, the Grin server being stopped")))
 
     // specification of all the client commands and options
     .subcommand(SubCommand::with_name("client")
                 .about("Communicates with the Grin server")
                 .subcommand(SubCommand::with_name("status")
                             .about("Current status of the Grin chain"))
+				.subcommand(SubCommand::with_name("sync")
+							.about("Where the Grin server is at syncing with the network"))
+				.subcommand(SubCommand::with_name("compact")
+							.about("Compact the chain, removing old blocks and pruned data"))
+				.subcommand(SubCommand::with_name("pool")
+							.about("Print the transactions in the pool"))
+				.subcommand(SubCommand::with_name("block")
+							.about("Print a block, by height or hash")
+							.arg(Arg::with_name("height")
+								.long("height")
+								.help("Height of the block on the main chain")
+								.takes_value(true))
+							.arg(Arg::with_name("hash")
+								.long("hash")
+								.help("Hash of the block")
+								.conflicts_with("height")
+								.takes_value(true)))
+				.subcommand(SubCommand::with_name("output")
+							.about("Look up an unspent output")
+							.arg(Arg::with_name("commit")
+								.short("c")
+								.long("commit")
+								.help("Commitment of the output, in hex")
+								.required(true)
+								.takes_value(true)))
+				.subcommand(SubCommand::with_name("reorgs")
+							.about("Print the last reorgs the Grin server went through")
+							.arg(Arg::with_name("count")
+								.short("n")
+								.long("count")
+								.help("How many reorgs to print")
+								.default_value("10")
+								.takes_value(true)))
 				.subcommand(SubCommand::with_name("listconnectedpeers")
 							.about("Print a list of currently connected peers"))
+				.subcommand(SubCommand::with_name("listbannedpeers")
+							.about("Print a list of currently banned peers, and when their ban runs out"))
+				.subcommand(SubCommand::with_name("connect")
+							.about("Connect to a peer")
+							.arg(Arg::with_name("peer")
+								.short("p")
+								.long("peer")
+								.help("Peer ip and port (e.g. 10.12.12.13:13414)")
+								.required(true)
+								.takes_value(true)))
 				.subcommand(SubCommand::with_name("ban")
 							.about("Ban peer")
 							.arg(Arg::with_name("peer")
@@ -136,6 +208,23 @@ fn real_main() -> i32 {
 								.long("peer")
 								.help("Peer ip and port (e.g. 10.12.12.13:13414)")
 								.required(true)
+								.takes_value(true))
+							.arg(Arg::with_name("reason")
+								.short("r")
+								.long("reason")
+								.help("Reason for the ban")
+								.possible_values(&["ManualBan", "BadBlock", "BadCompactBlock", "BadBlockHeader", "BadTxHashSet", "Abusive"])
+								.default_value("ManualBan")
+								.takes_value(true))
+							.arg(Arg::with_name("duration")
+								.short("d")
+								.long("duration")
+								.help("How long the ban lasts in seconds, the configured ban window if not given")
+								.takes_value(true))
+							.arg(Arg::with_name("note")
+								.short("n")
+								.long("note")
+								.help("What the ban is about, kept with it")
 								.takes_value(true)))
 				.subcommand(SubCommand::with_name("unban")
 							.about("Unban peer")
@@ -144,7 +233,39 @@ fn real_main() -> i32 {
 								.long("peer")
 								.help("Peer ip and port (e.g. 10.12.12.13:13414)")
 								.required(true)
-								.takes_value(true))))
+								.takes_value(true)))
+				.subcommand(SubCommand::with_name("peers")
+							.about("Export known-good peers, or import those exported by another node")
+							.subcommand(SubCommand::with_name("export")
+								.about("Export the known-good peers, signed by the node, as JSON")
+								.arg(Arg::with_name("file")
+									.short("f")
+									.long("file")
+									.help("File to write the export to, printed if not given")
+									.takes_value(true))
+								.arg(Arg::with_name("count")
+									.short("n")
+									.long("count")
+									.help("How many peers to export at most, the best ones first")
+									.takes_value(true)))
+							.subcommand(SubCommand::with_name("import")
+								.about("Import the peers exported by another node")
+								.arg(Arg::with_name("file")
+									.short("f")
+									.long("file")
+									.help("File of the export")
+									.required(true)
+									.takes_value(true))
+								.arg(Arg::with_name("max_age")
+									.short("a")
+									.long("max_age")
+									.help("How old in seconds the export can be, a week if not given")
+									.takes_value(true))
+								.arg(Arg::with_name("signer")
+									.short("s")
+									.long("signer")
+									.help("Public key the export has to be signed by, in hex")
+									.takes_value(true)))))
 
 
 	// specification of the wallet commands and options
@@ -153,9 +274,8 @@ fn real_main() -> i32 {
 		.arg(Arg::with_name("pass")
 			.short("p")
 			.long("pass")
-			.help("Wallet passphrase used to generate the private key seed")
-			.takes_value(true)
-			.default_value(""))
+			.help("Wallet passphrase used to generate the private key seed and encrypt the wallet database, prompted for if needed and not given")
+			.takes_value(true))
 		.arg(Arg::with_name("account")
 			.short("a")
 			.long("account")
@@ -192,13 +312,49 @@ fn real_main() -> i32 {
 				.help("Name of new wallet account")
 				.takes_value(true)))
 
+		.subcommand(SubCommand::with_name("contacts")
+			.about("Manage the address book of named send destinations, usable as `send -d @name`")
+			.subcommand(SubCommand::with_name("add")
+				.about("Add a contact")
+				.arg(Arg::with_name("name")
+					.help("Name of the contact")
+					.required(true)
+					.index(1))
+				.arg(Arg::with_name("dest")
+					.help("Destination to send to (http(s) listener address or file name)")
+					.required(true)
+					.index(2))
+				.arg(Arg::with_name("method")
+					.help("Method for sending to this contact.")
+					.short("m")
+					.long("method")
+					.possible_values(&["http", "file"])
+					.default_value("http")
+					.takes_value(true)))
+			.subcommand(SubCommand::with_name("list")
+				.about("List contacts"))
+			.subcommand(SubCommand::with_name("remove")
+				.about("Remove a contact")
+				.arg(Arg::with_name("name")
+					.help("Name of the contact")
+					.required(true)
+					.index(1))))
+
 		.subcommand(SubCommand::with_name("listen")
 			.about("Runs the wallet in listening mode waiting for transactions.")
 			.arg(Arg::with_name("port")
 				.short("l")
 				.long("port")
 				.help("Port on which to run the wallet listener")
-				.takes_value(true)))
+				.takes_value(true))
+			.arg(Arg::with_name("tor")
+				.long("tor")
+				.help("Also publish the listener as a Tor onion service, running tor in the background")
+				.takes_value(false))
+			.arg(Arg::with_name("watch")
+				.long("watch")
+				.help("Also watch for payments received, running notify_command and posting to notify_url for each once confirmed")
+				.takes_value(false)))
 
 		.subcommand(SubCommand::with_name("owner_api")
 			.about("Runs the wallet's local web API."))
@@ -231,6 +387,10 @@ fn real_main() -> i32 {
 				.long("change_outputs")
 				.default_value("1")
 				.takes_value(true))
+			.arg(Arg::with_name("change_target")
+				.help("Split change into as many outputs of at least this size as it allows (overrides change_outputs), e.g. 10.5")
+				.long("change_target")
+				.takes_value(true))
 			.arg(Arg::with_name("method")
 				.help("Method for sending this transaction.")
 				.short("m")
@@ -239,10 +399,17 @@ fn real_main() -> i32 {
 				.default_value("http")
 				.takes_value(true))
 			.arg(Arg::with_name("dest")
-				.help("Send the transaction to the provided server (start with http://) or save as file.")
+				.help("Send the transaction to the provided server (start with http://), to an onion address (xyz.onion, through tor), save as file ('-' for stdout), or send to an address book contact (@name).")
 				.short("d")
 				.long("dest")
 				.takes_value(true))
+			.arg(Arg::with_name("armor")
+				.help("Write the transaction file as a compact armored slate, for pasting into a chat or email")
+				.long("armor"))
+			.arg(Arg::with_name("estimate_only")
+				.help("Only show the fee, outputs spent and resulting balance, without sending")
+				.short("e")
+				.long("estimate-only"))
 			.arg(Arg::with_name("fluff")
 				.help("Fluff the transaction (ignore Dandelion relay protocol)")
 				.short("f")
@@ -256,15 +423,18 @@ fn real_main() -> i32 {
 		.subcommand(SubCommand::with_name("receive")
 			.about("Processes a transaction file to accept a transfer from a sender.")
 			.arg(Arg::with_name("input")
-				.help("Partial transaction to process, expects the sender's transaction file.")
+				.help("Partial transaction to process, expects the sender's transaction file, JSON or armored ('-' for stdin).")
 				.short("i")
 				.long("input")
-				.takes_value(true)))
+				.takes_value(true))
+			.arg(Arg::with_name("armor")
+				.help("Write the response as a compact armored slate (always the case for armored input)")
+				.long("armor")))
 
 		.subcommand(SubCommand::with_name("finalize")
 			.about("Processes a receiver's transaction file to finalize a transfer.")
 			.arg(Arg::with_name("input")
-				.help("Partial transaction to process, expects the receiver's transaction file.")
+				.help("Partial transaction to process, expects the receiver's transaction file, JSON or armored ('-' for stdin).")
 				.short("i")
 				.long("input")
 				.takes_value(true))
@@ -298,6 +468,42 @@ fn real_main() -> i32 {
 				.long("id")
 				.takes_value(true)))
 
+		.subcommand(SubCommand::with_name("balance_history")
+			.about("Display the balance of the account over time, from the snapshots taken as the wallet refreshes")
+			.arg(Arg::with_name("per_block")
+				.help("Show a snapshot per block instead of per day (only recorded once turned on with --record_per_block)")
+				.short("b")
+				.long("per_block")
+				.takes_value(false))
+			.arg(Arg::with_name("record_per_block")
+				.help("Turn recording a snapshot for every block on or off")
+				.long("record_per_block")
+				.possible_values(&["on", "off"])
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("export_txs")
+			.about("Export transaction history for accounting")
+			.arg(Arg::with_name("format")
+				.help("Export format")
+				.short("f")
+				.long("format")
+				.possible_values(&["csv", "json"])
+				.default_value("csv")
+				.takes_value(true))
+			.arg(Arg::with_name("from")
+				.help("Only export transactions created on or after this date (YYYY-MM-DD)")
+				.long("from")
+				.takes_value(true))
+			.arg(Arg::with_name("to")
+				.help("Only export transactions created on or before this date (YYYY-MM-DD)")
+				.long("to")
+				.takes_value(true))
+			.arg(Arg::with_name("dest")
+				.help("Name of destination file (prints to stdout if not given)")
+				.short("d")
+				.long("dest")
+				.takes_value(true)))
+
 		.subcommand(SubCommand::with_name("repost")
 			.about("Reposts a stored, completed but unconfirmed transaction to the chain, or dumps it to a file")
 			.arg(Arg::with_name("id")
@@ -323,6 +529,125 @@ fn real_main() -> i32 {
 				.long("id")
 				.takes_value(true)))
 
+		.subcommand(SubCommand::with_name("cold_send")
+			.about("From a watch-only wallet, writes a request for the offline wallet to sign a send")
+			.arg(Arg::with_name("amount")
+				.help("Number of coins to send with optional fraction, e.g. 12.423")
+				.index(1)
+				.required(true))
+			.arg(Arg::with_name("dest")
+				.help("File to write the signing request to")
+				.short("d")
+				.long("dest")
+				.required(true)
+				.takes_value(true))
+			.arg(Arg::with_name("minimum_confirmations")
+				.help("Minimum number of confirmations required for an output to be spendable.")
+				.short("c")
+				.long("min_conf")
+				.default_value("1")
+				.takes_value(true))
+			.arg(Arg::with_name("selection_strategy")
+				.help("Coin/Output selection strategy.")
+				.short("s")
+				.long("selection")
+				.possible_values(&["all", "smallest"])
+				.default_value("all")
+				.takes_value(true))
+			.arg(Arg::with_name("change_outputs")
+				.help("Number of change outputs to generate (mainly for testing).")
+				.short("o")
+				.long("change_outputs")
+				.default_value("1")
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("sign")
+			.about("On the offline wallet, signs a watch-only wallet's signing request, giving the slate for the recipient, or the recipient's response to it, giving the slate to finalize")
+			.arg(Arg::with_name("input")
+				.help("Signing request or response slate file, '-' for stdin")
+				.short("i")
+				.long("input")
+				.required(true)
+				.takes_value(true))
+			.arg(Arg::with_name("output")
+				.help("File to write the signed slate to, '-' for stdout, defaults to the input with a .signed extension")
+				.short("o")
+				.long("output")
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("cold_finalize")
+			.about("From a watch-only wallet, finalizes and posts a slate signed by the offline wallet")
+			.arg(Arg::with_name("input")
+				.help("Signed slate file, '-' for stdin")
+				.short("i")
+				.long("input")
+				.required(true)
+				.takes_value(true))
+			.arg(Arg::with_name("fluff")
+				.help("Fluff the transaction (ignore Dandelion relay protocol)")
+				.short("f")
+				.long("fluff")))
+
+		.subcommand(SubCommand::with_name("export_outputs")
+			.about("Exports the wallet's outputs with their commitments, for a watch-only wallet to track")
+			.arg(Arg::with_name("output")
+				.help("File to write the outputs to")
+				.short("o")
+				.long("output")
+				.required(true)
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("import_outputs")
+			.about("Imports outputs exported by the offline wallet into a watch-only wallet")
+			.arg(Arg::with_name("input")
+				.help("File of exported outputs")
+				.short("i")
+				.long("input")
+				.required(true)
+				.takes_value(true)))
+
+		.subcommand(SubCommand::with_name("consolidate")
+			.about("Merges many small outputs into fewer larger ones by sending them to self, lowering the fees of future sends")
+			.arg(Arg::with_name("below")
+				.help("Only consolidate outputs worth less than this amount, in grins")
+				.short("b")
+				.long("below")
+				.takes_value(true))
+			.arg(Arg::with_name("min_outputs")
+				.help("Only consolidate if there are at least this many outputs to merge")
+				.short("n")
+				.long("min_outputs")
+				.default_value("2")
+				.takes_value(true))
+			.arg(Arg::with_name("max_inputs")
+				.help("Maximum number of outputs merged by each transaction")
+				.short("o")
+				.long("max_inputs")
+				.default_value("500")
+				.takes_value(true))
+			.arg(Arg::with_name("minimum_confirmations")
+				.help("Minimum number of confirmations required for an output to be consolidated.")
+				.short("c")
+				.long("min_conf")
+				.default_value("1")
+				.takes_value(true))
+			.arg(Arg::with_name("fluff")
+				.help("Fluff the transactions (ignore Dandelion relay protocol)")
+				.short("f")
+				.long("fluff")))
+
+		.subcommand(SubCommand::with_name("cancel_expired")
+			.about("Cancels sends that were never finalized within a time limit, freeing their locked outputs")
+			.arg(Arg::with_name("ttl")
+				.help("Hours after which a pending send expires, defaults to pending_tx_ttl_hours from the wallet config")
+				.short("t")
+				.long("ttl")
+				.takes_value(true))
+			.arg(Arg::with_name("dry_run")
+				.help("Only list the transactions that would be cancelled")
+				.short("d")
+				.long("dry_run")))
+
 		.subcommand(SubCommand::with_name("info")
 			.about("basic wallet contents summary"))
 
@@ -332,22 +657,59 @@ fn real_main() -> i32 {
 				.short("h")
 				.long("here")
 				.help("Create wallet files in the current directory instead of the default ~/.grin directory")
+				.takes_value(false))
+			.arg(Arg::with_name("cold")
+				.long("cold")
+				.help("Create a watch-only wallet without a seed, for funds whose keys are held by an offline wallet that signs its transactions")
+				.takes_value(false))
+			.arg(Arg::with_name("bip44")
+				.long("bip44")
+				.help("Derive the wallet keys along standard BIP44 paths (m/44'/592'/...) instead of Grin's original scheme")
+				.conflicts_with("cold")
 				.takes_value(false)))
 
+		.subcommand(SubCommand::with_name("derivation")
+			.about("Shows how the wallet keys are derived from its seed, or switches it once the wallet holds no outputs")
+			.arg(Arg::with_name("set")
+				.help("Key derivation scheme to switch to")
+				.short("s")
+				.long("set")
+				.possible_values(&["legacy", "bip44"])
+				.takes_value(true)))
+
 		.subcommand(SubCommand::with_name("restore")
 			.about("Attempt to restore wallet contents from the chain using seed and password. \
-				NOTE: Backup wallet.* and run `wallet listen` before running restore.")))
+				NOTE: Backup wallet.* and run `wallet listen` before running restore."))
+
+		.subcommand(SubCommand::with_name("check")
+			.about("Scans the chain for the wallet's outputs, reporting any discrepancy with the wallet's contents")
+			.arg(Arg::with_name("fix")
+				.help("Categories of discrepancy to repair, comma separated")
+				.short("f")
+				.long("fix")
+				.takes_value(true)
+				.use_delimiter(true)
+				.possible_values(&[
+					"all",
+					"missing_on_chain",
+					"missing_from_wallet",
+					"confirmed_locked",
+					"duplicate_indices",
+				]))))
 
 	.get_matches();
 	let mut wallet_config = None;
 	let mut node_config = None;
+	let network = args
+		.value_of("network")
+		.map(|n| global::ChainTypes::from_network_name(n).unwrap());
 
 	// Deal with configuration file creation
 	match args.subcommand() {
 		("server", Some(server_args)) => {
 			// If it's just a server config command, do it and exit
 			if let ("config", Some(_)) = server_args.subcommand() {
-				cmd::config_command_server(SERVER_CONFIG_FILE_NAME);
+				cmd::config_command_server(SERVER_CONFIG_FILE_NAME, network.as_ref());
 				return 0;
 			}
 		}
@@ -356,7 +718,7 @@ fn real_main() -> i32 {
 			// (if desired)
 			if let ("init", Some(init_args)) = wallet_args.subcommand() {
 				if init_args.is_present("here") {
-					cmd::config_command_wallet(WALLET_CONFIG_FILE_NAME);
+					cmd::config_command_wallet(WALLET_CONFIG_FILE_NAME, network.as_ref());
 				}
 			}
 		}
@@ -366,7 +728,7 @@ fn real_main() -> i32 {
 	match args.subcommand() {
 		// If it's a wallet command, try and load a wallet config file
 		("wallet", Some(wallet_args)) => {
-			let mut w = config::initial_setup_wallet().unwrap_or_else(|e| {
+			let mut w = config::initial_setup_wallet(network.as_ref()).unwrap_or_else(|e| {
 				panic!("Error loading wallet configuration: {}", e);
 			});
 			if !cmd::seed_exists(w.members.as_ref().unwrap().wallet.clone()) {
@@ -387,7 +749,7 @@ fn real_main() -> i32 {
 		}
 		// Otherwise load up the node config as usual
 		_ => {
-			let mut s = config::initial_setup_server().unwrap_or_else(|e| {
+			let mut s = config::initial_setup_server(network.as_ref()).unwrap_or_else(|e| {
 				panic!("Error loading server configuration: {}", e);
 			});
 			let mut l = s.members.as_mut().unwrap().logging.clone().unwrap();
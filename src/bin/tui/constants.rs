@@ -27,6 +27,13 @@ pub const VIEW_MINING: &str = "mining_view";
 pub const SUBMENU_MINING_BUTTON: &str = "mining_submenu_button";
 pub const TABLE_MINING_STATUS: &str = "mining_status_table";
 pub const TABLE_MINING_DIFF_STATUS: &str = "mining_diff_status_table";
+pub const TABLE_MINING_FOUND_BLOCKS: &str = "mining_found_blocks_table";
+
+// Logs View
+pub const VIEW_LOGS: &str = "logs_view";
+pub const VIEW_LOGS_LEVEL: &str = "logs_level_select";
+pub const VIEW_LOGS_SEARCH: &str = "logs_search_edit";
+pub const VIEW_LOGS_TEXT: &str = "logs_text";
 
 // Mining View
 pub const VIEW_VERSION: &str = "version_view";
@@ -0,0 +1,64 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text graphs of the traffic rates
+
+use std::cmp;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One bar per value, scaled to the largest one. Zero stays blank so idle
+/// periods stand out.
+pub fn sparkline(values: &[u64]) -> String {
+	let max = values.iter().cloned().max().unwrap_or(0);
+	values
+		.iter()
+		.map(|&v| {
+			if v == 0 || max == 0 {
+				' '
+			} else {
+				let i = ((v - 1) * BARS.len() as u64 / max) as usize;
+				BARS[cmp::min(i, BARS.len() - 1)]
+			}
+		}).collect()
+}
+
+/// Adds series of the same period up, aligned on their latest value
+pub fn sum_series<'a, I>(series: I) -> Vec<u64>
+where
+	I: Iterator<Item = &'a Vec<u64>>,
+{
+	let mut sum: Vec<u64> = vec![];
+	for s in series {
+		if s.len() > sum.len() {
+			let mut padded = vec![0; s.len() - sum.len()];
+			padded.extend(sum);
+			sum = padded;
+		}
+		let offset = sum.len() - s.len();
+		for (i, v) in s.iter().enumerate() {
+			sum[offset + i] += v;
+		}
+	}
+	sum
+}
+
+/// Average of the values, 0 when empty
+pub fn average(values: &[u64]) -> u64 {
+	if values.is_empty() {
+		0
+	} else {
+		values.iter().sum::<u64>() / values.len() as u64
+	}
+}
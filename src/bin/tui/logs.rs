@@ -0,0 +1,102 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Log view, the most recent lines of the node log
+
+use cursive::direction::Orientation;
+use cursive::traits::{Boxable, Identifiable};
+use cursive::view::{ScrollStrategy, View};
+use cursive::views::{BoxView, Dialog, EditView, LinearLayout, ScrollView, SelectView, TextView};
+use cursive::Cursive;
+
+use tui::constants::{VIEW_LOGS, VIEW_LOGS_LEVEL, VIEW_LOGS_SEARCH, VIEW_LOGS_TEXT};
+use tui::types::TUIStatusListener;
+
+use servers::ServerStats;
+use util::{self, LogLevel};
+
+pub struct TUILogsView;
+
+impl TUIStatusListener for TUILogsView {
+	/// Create the log view
+	fn create() -> Box<View> {
+		let level_select = SelectView::new()
+			.item("Error", LogLevel::Error)
+			.item("Warning", LogLevel::Warning)
+			.item("Info", LogLevel::Info)
+			.item("Debug", LogLevel::Debug)
+			.item("Trace", LogLevel::Trace)
+			.selected(2)
+			.popup()
+			.with_id(VIEW_LOGS_LEVEL);
+
+		let filter_bar = LinearLayout::new(Orientation::Horizontal)
+			.child(TextView::new("Level: "))
+			.child(level_select)
+			.child(TextView::new("   Search: "))
+			.child(EditView::new().with_id(VIEW_LOGS_SEARCH).min_width(30));
+
+		let log_text = ScrollView::new(TextView::new("").with_id(VIEW_LOGS_TEXT))
+			.scroll_strategy(ScrollStrategy::StickToBottom);
+
+		let logs_view = BoxView::with_full_screen(
+			LinearLayout::new(Orientation::Vertical)
+				.child(filter_bar)
+				.child(TextView::new("   "))
+				.child(BoxView::with_full_screen(
+					Dialog::around(log_text).title("Node Log"),
+				)),
+		).with_id(VIEW_LOGS);
+		Box::new(logs_view)
+	}
+
+	/// update
+	fn update(c: &mut Cursive, _stats: &ServerStats) {
+		let level = c
+			.call_on_id(VIEW_LOGS_LEVEL, |s: &mut SelectView<LogLevel>| {
+				s.selection().map(|l| (*l).clone())
+			}).and_then(|l| l)
+			.unwrap_or(LogLevel::Info);
+		let search = c
+			.call_on_id(VIEW_LOGS_SEARCH, |e: &mut EditView| {
+				e.get_content().to_lowercase()
+			}).unwrap_or_default();
+
+		let lines = util::recent_logs()
+			.into_iter()
+			.filter(|e| severity(&e.level) <= severity(&level))
+			.filter(|e| search.is_empty() || e.line.to_lowercase().contains(&search))
+			.map(|e| e.line)
+			.collect::<Vec<_>>();
+		let content = if lines.is_empty() {
+			"No matching log lines.".to_string()
+		} else {
+			lines.join("\n")
+		};
+		c.call_on_id(VIEW_LOGS_TEXT, |t: &mut TextView| {
+			t.set_content(content);
+		});
+	}
+}
+
+// Error being the most severe, the lowest
+fn severity(level: &LogLevel) -> u8 {
+	match *level {
+		LogLevel::Error => 0,
+		LogLevel::Warning => 1,
+		LogLevel::Info => 2,
+		LogLevel::Debug => 3,
+		LogLevel::Trace => 4,
+	}
+}
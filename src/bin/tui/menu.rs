@@ -25,8 +25,8 @@ use cursive::views::{
 use cursive::Cursive;
 
 use tui::constants::{
-	MAIN_MENU, ROOT_STACK, SUBMENU_MINING_BUTTON, VIEW_BASIC_STATUS, VIEW_MINING, VIEW_PEER_SYNC,
-	VIEW_VERSION,
+	MAIN_MENU, ROOT_STACK, SUBMENU_MINING_BUTTON, VIEW_BASIC_STATUS, VIEW_LOGS, VIEW_LOGS_SEARCH,
+	VIEW_MINING, VIEW_PEER_SYNC, VIEW_VERSION,
 };
 
 pub fn create() -> Box<View> {
@@ -38,6 +38,7 @@ pub fn create() -> Box<View> {
 		.get_mut()
 		.add_item("Peers and Sync", VIEW_PEER_SYNC);
 	main_menu.get_mut().add_item("Mining", VIEW_MINING);
+	main_menu.get_mut().add_item("Logs", VIEW_LOGS);
 	main_menu.get_mut().add_item("Version Info", VIEW_VERSION);
 	let change_view = |s: &mut Cursive, v: &&str| {
 		if *v == "" {
@@ -57,6 +58,9 @@ pub fn create() -> Box<View> {
 			if v == VIEW_MINING {
 				let _ = c.focus_id(SUBMENU_MINING_BUTTON);
 			}
+			if v == VIEW_LOGS {
+				let _ = c.focus_id(VIEW_LOGS_SEARCH);
+			}
 		});
 	let main_menu = OnEventView::new(main_menu)
 		.on_pre_event('j', move |c| {
@@ -28,72 +28,117 @@ use std::time;
 use tui::chrono::prelude::{DateTime, NaiveDateTime, Utc};
 
 use tui::constants::{
-	MAIN_MENU, SUBMENU_MINING_BUTTON, TABLE_MINING_DIFF_STATUS, TABLE_MINING_STATUS, VIEW_MINING,
+	MAIN_MENU, SUBMENU_MINING_BUTTON, TABLE_MINING_DIFF_STATUS, TABLE_MINING_FOUND_BLOCKS,
+	TABLE_MINING_STATUS, VIEW_MINING,
 };
 use tui::types::TUIStatusListener;
 
-use servers::{DiffBlock, ServerStats, WorkerStats};
+use core::core::amount_to_hr_string;
+use servers::{DiffBlock, FoundBlock, ServerStats, WorkerStats};
 use tui::table::{TableView, TableViewItem};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 enum StratumWorkerColumn {
 	Id,
+	Login,
 	IsConnected,
-	LastSeen,
+	LastShare,
+	Uptime,
 	PowDifficulty,
 	NumAccepted,
 	NumRejected,
 	NumStale,
+	ShareRate,
+	GraphRate,
 }
 
 impl StratumWorkerColumn {
 	fn _as_str(&self) -> &str {
 		match *self {
 			StratumWorkerColumn::Id => "Worker ID",
+			StratumWorkerColumn::Login => "Login",
 			StratumWorkerColumn::IsConnected => "Connected",
-			StratumWorkerColumn::LastSeen => "Last Seen",
+			StratumWorkerColumn::LastShare => "Last Share",
+			StratumWorkerColumn::Uptime => "Uptime",
 			StratumWorkerColumn::PowDifficulty => "PowDifficulty",
 			StratumWorkerColumn::NumAccepted => "Num Accepted",
 			StratumWorkerColumn::NumRejected => "Num Rejected",
 			StratumWorkerColumn::NumStale => "Num Stale",
+			StratumWorkerColumn::ShareRate => "Shares/min",
+			StratumWorkerColumn::GraphRate => "Graphs/s",
 		}
 	}
 }
 
-impl TableViewItem<StratumWorkerColumn> for WorkerStats {
+/// A worker's stats along with its estimated graph rate, which needs the
+/// server's cuckoo size
+#[derive(Clone)]
+struct WorkerRow {
+	stats: WorkerStats,
+	graph_rate: f64,
+}
+
+impl TableViewItem<StratumWorkerColumn> for WorkerRow {
 	fn to_column(&self, column: StratumWorkerColumn) -> String {
-		let naive_datetime = NaiveDateTime::from_timestamp(
-			self.last_seen
-				.duration_since(time::UNIX_EPOCH)
-				.unwrap()
-				.as_secs() as i64,
-			0,
-		);
-		let datetime: DateTime<Utc> = DateTime::from_utc(naive_datetime, Utc);
+		let stats = &self.stats;
+		let last_share = match stats.last_share {
+			Some(t) => {
+				let naive_datetime = NaiveDateTime::from_timestamp(
+					t.duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64,
+					0,
+				);
+				let datetime: DateTime<Utc> = DateTime::from_utc(naive_datetime, Utc);
+				datetime.to_string()
+			}
+			None => "-".to_string(),
+		};
+		let uptime = stats.uptime().as_secs();
 
 		match column {
-			StratumWorkerColumn::Id => self.id.clone(),
-			StratumWorkerColumn::IsConnected => self.is_connected.to_string(),
-			StratumWorkerColumn::LastSeen => datetime.to_string(),
-			StratumWorkerColumn::PowDifficulty => self.pow_difficulty.to_string(),
-			StratumWorkerColumn::NumAccepted => self.num_accepted.to_string(),
-			StratumWorkerColumn::NumRejected => self.num_rejected.to_string(),
-			StratumWorkerColumn::NumStale => self.num_stale.to_string(),
+			StratumWorkerColumn::Id => stats.id.clone(),
+			StratumWorkerColumn::Login => stats.login.clone().unwrap_or("-".to_string()),
+			StratumWorkerColumn::IsConnected => stats.is_connected.to_string(),
+			StratumWorkerColumn::LastShare => last_share,
+			StratumWorkerColumn::Uptime => format!(
+				"{}:{:02}:{:02}",
+				uptime / 3600,
+				(uptime / 60) % 60,
+				uptime % 60
+			),
+			StratumWorkerColumn::PowDifficulty => stats.pow_difficulty.to_string(),
+			StratumWorkerColumn::NumAccepted => stats.num_accepted.to_string(),
+			StratumWorkerColumn::NumRejected => stats.num_rejected.to_string(),
+			StratumWorkerColumn::NumStale => stats.num_stale.to_string(),
+			StratumWorkerColumn::ShareRate => format!("{:.2}", stats.share_rate()),
+			StratumWorkerColumn::GraphRate => format!("{:.2}", self.graph_rate),
 		}
 	}
 
-	fn cmp(&self, _other: &Self, column: StratumWorkerColumn) -> Ordering
+	fn cmp(&self, other: &Self, column: StratumWorkerColumn) -> Ordering
 	where
 		Self: Sized,
 	{
 		match column {
 			StratumWorkerColumn::Id => Ordering::Equal,
+			StratumWorkerColumn::Login => self.stats.login.cmp(&other.stats.login),
 			StratumWorkerColumn::IsConnected => Ordering::Equal,
-			StratumWorkerColumn::LastSeen => Ordering::Equal,
+			StratumWorkerColumn::LastShare => self.stats.last_share.cmp(&other.stats.last_share),
+			StratumWorkerColumn::Uptime => self.stats.uptime().cmp(&other.stats.uptime()),
 			StratumWorkerColumn::PowDifficulty => Ordering::Equal,
-			StratumWorkerColumn::NumAccepted => Ordering::Equal,
+			StratumWorkerColumn::NumAccepted => {
+				self.stats.num_accepted.cmp(&other.stats.num_accepted)
+			}
 			StratumWorkerColumn::NumRejected => Ordering::Equal,
 			StratumWorkerColumn::NumStale => Ordering::Equal,
+			StratumWorkerColumn::ShareRate => self
+				.stats
+				.share_rate()
+				.partial_cmp(&other.stats.share_rate())
+				.unwrap_or(Ordering::Equal),
+			StratumWorkerColumn::GraphRate => self
+				.graph_rate
+				.partial_cmp(&other.graph_rate)
+				.unwrap_or(Ordering::Equal),
 		}
 	}
 }
@@ -153,6 +198,62 @@ impl TableViewItem<DiffColumn> for DiffBlock {
 		}
 	}
 }
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum FoundBlockColumn {
+	Height,
+	Hash,
+	Fees,
+	Time,
+	FoundBy,
+}
+
+impl FoundBlockColumn {
+	fn _as_str(&self) -> &str {
+		match *self {
+			FoundBlockColumn::Height => "Height",
+			FoundBlockColumn::Hash => "Hash",
+			FoundBlockColumn::Fees => "Fees",
+			FoundBlockColumn::Time => "Found At",
+			FoundBlockColumn::FoundBy => "Found By",
+		}
+	}
+}
+
+impl TableViewItem<FoundBlockColumn> for FoundBlock {
+	fn to_column(&self, column: FoundBlockColumn) -> String {
+		match column {
+			FoundBlockColumn::Height => self.height.to_string(),
+			FoundBlockColumn::Hash => self.hash.clone(),
+			FoundBlockColumn::Fees => amount_to_hr_string(self.fees, true),
+			FoundBlockColumn::Time => {
+				let naive_datetime = NaiveDateTime::from_timestamp(
+					self.found_at
+						.duration_since(time::UNIX_EPOCH)
+						.unwrap()
+						.as_secs() as i64,
+					0,
+				);
+				let datetime: DateTime<Utc> = DateTime::from_utc(naive_datetime, Utc);
+				datetime.to_string()
+			}
+			FoundBlockColumn::FoundBy => self.found_by.clone(),
+		}
+	}
+
+	fn cmp(&self, other: &Self, column: FoundBlockColumn) -> Ordering
+	where
+		Self: Sized,
+	{
+		match column {
+			FoundBlockColumn::Height => self.height.cmp(&other.height),
+			FoundBlockColumn::Hash => Ordering::Equal,
+			FoundBlockColumn::Fees => self.fees.cmp(&other.fees),
+			FoundBlockColumn::Time => self.found_at.cmp(&other.found_at),
+			FoundBlockColumn::FoundBy => self.found_by.cmp(&other.found_by),
+		}
+	}
+}
+
 /// Mining status view
 pub struct TUIMiningView;
 
@@ -171,29 +272,40 @@ impl TUIStatusListener for TUIMiningView {
 				sv.move_to_front(pos);
 			});
 		});
+		let found_blocks_button = Button::new_raw("Found Blocks", |s| {
+			let _ = s.call_on_id("mining_stack_view", |sv: &mut StackView| {
+				let pos = sv.find_layer_from_id("mining_found_blocks_view").unwrap();
+				sv.move_to_front(pos);
+			});
+		});
 		let mining_submenu = LinearLayout::new(Orientation::Horizontal)
 			.child(Panel::new(devices_button))
-			.child(Panel::new(difficulty_button));
+			.child(Panel::new(difficulty_button))
+			.child(Panel::new(found_blocks_button));
 
 		let mining_submenu = OnEventView::new(mining_submenu).on_pre_event(Key::Esc, move |c| {
 			let _ = c.focus_id(MAIN_MENU);
 		});
 
-		let table_view = TableView::<WorkerStats, StratumWorkerColumn>::new()
-			.column(StratumWorkerColumn::Id, "Worker ID", |c| {
-				c.width_percent(10)
-			}).column(StratumWorkerColumn::IsConnected, "Connected", |c| {
-				c.width_percent(10)
-			}).column(StratumWorkerColumn::LastSeen, "Last Seen", |c| {
-				c.width_percent(16)
-			}).column(StratumWorkerColumn::PowDifficulty, "Pow Difficulty", |c| {
+		let table_view = TableView::<WorkerRow, StratumWorkerColumn>::new()
+			.column(StratumWorkerColumn::Id, "Worker ID", |c| c.width_percent(6))
+			.column(StratumWorkerColumn::Login, "Login", |c| c.width_percent(10))
+			.column(StratumWorkerColumn::IsConnected, "Connected", |c| {
+				c.width_percent(8)
+			}).column(StratumWorkerColumn::LastShare, "Last Share", |c| {
 				c.width_percent(14)
-			}).column(StratumWorkerColumn::NumAccepted, "Num Accepted", |c| {
-				c.width_percent(10)
-			}).column(StratumWorkerColumn::NumRejected, "Num Rejected", |c| {
-				c.width_percent(10)
-			}).column(StratumWorkerColumn::NumStale, "Num Stale", |c| {
+			}).column(StratumWorkerColumn::Uptime, "Uptime", |c| c.width_percent(8))
+			.column(StratumWorkerColumn::PowDifficulty, "Pow Difficulty", |c| {
 				c.width_percent(10)
+			}).column(StratumWorkerColumn::NumAccepted, "Accepted", |c| {
+				c.width_percent(8)
+			}).column(StratumWorkerColumn::NumRejected, "Rejected", |c| {
+				c.width_percent(8)
+			}).column(StratumWorkerColumn::NumStale, "Stale", |c| c.width_percent(6))
+			.column(StratumWorkerColumn::ShareRate, "Shares/min", |c| {
+				c.width_percent(6)
+			}).column(StratumWorkerColumn::GraphRate, "Graphs/s", |c| {
+				c.width_percent(8)
 			});
 
 		let status_view = LinearLayout::new(Orientation::Vertical)
@@ -218,6 +330,12 @@ impl TUIStatusListener for TUIMiningView {
 			).child(
 				LinearLayout::new(Orientation::Horizontal)
 					.child(TextView::new("  ").with_id("stratum_edge_bits_status")),
+			).child(
+				LinearLayout::new(Orientation::Horizontal)
+					.child(TextView::new("  ").with_id("stratum_template_status")),
+			).child(
+				LinearLayout::new(Orientation::Horizontal)
+					.child(TextView::new("  ").with_id("stratum_blocks_found_status")),
 			);
 
 		let mining_device_view = LinearLayout::new(Orientation::Vertical)
@@ -267,7 +385,25 @@ impl TUIStatusListener for TUIMiningView {
 				).title("Mining Difficulty Data"),
 			)).with_id("mining_difficulty_view");
 
+		let found_blocks_table_view = TableView::<FoundBlock, FoundBlockColumn>::new()
+			.column(FoundBlockColumn::Height, "Height", |c| c.width_percent(10))
+			.column(FoundBlockColumn::Hash, "Hash", |c| c.width_percent(40))
+			.column(FoundBlockColumn::Fees, "Fees", |c| c.width_percent(10))
+			.column(FoundBlockColumn::Time, "Found At", |c| c.width_percent(25))
+			.column(FoundBlockColumn::FoundBy, "Found By", |c| {
+				c.width_percent(15)
+			});
+
+		let mining_found_blocks_view = BoxView::with_full_screen(
+			Dialog::around(
+				found_blocks_table_view
+					.with_id(TABLE_MINING_FOUND_BLOCKS)
+					.min_size((50, 20)),
+			).title("Blocks Found by this Node"),
+		).with_id("mining_found_blocks_view");
+
 		let view_stack = StackView::new()
+			.layer(mining_found_blocks_view)
 			.layer(mining_difficulty_view)
 			.layer(mining_device_view)
 			.with_id("mining_stack_view");
@@ -305,11 +441,17 @@ impl TUIStatusListener for TUIMiningView {
 		);
 		let stratum_stats = stats.stratum_stats.clone();
 		let stratum_network_hashrate = format!(
-			"Network Hashrate: {:.*}",
-			2,
+			"Network Graph Rate (est.): {:.2} graphs/s",
 			stratum_stats.network_hashrate()
 		);
-		let worker_stats = stratum_stats.worker_stats;
+		let edge_bits = stratum_stats.edge_bits;
+		let worker_rows: Vec<WorkerRow> = stratum_stats
+			.worker_stats
+			.into_iter()
+			.map(|stats| WorkerRow {
+				graph_rate: stats.graph_rate(edge_bits),
+				stats,
+			}).collect();
 		let stratum_enabled = format!("Mining server enabled: {}", stratum_stats.is_enabled);
 		let stratum_is_running = format!("Mining server running: {}", stratum_stats.is_running);
 		let stratum_num_workers = format!("Number of workers: {}", stratum_stats.num_workers);
@@ -317,6 +459,20 @@ impl TUIStatusListener for TUIMiningView {
 		let stratum_network_difficulty =
 			format!("Network Difficulty: {}", stratum_stats.network_difficulty);
 		let stratum_edge_bits = format!("Cuckoo Size: {}", stratum_stats.edge_bits);
+		let stratum_template = format!(
+			"Block Template: {} transactions, {} fees",
+			stratum_stats.template_tx_count,
+			amount_to_hr_string(stratum_stats.template_fees, true)
+		);
+		let stratum_blocks_found = match stratum_stats.blocks_found.last() {
+			Some(b) => format!(
+				"Blocks Found: {}, last at height {}",
+				stratum_stats.num_blocks_found, b.height
+			),
+			None => "Blocks Found: 0".to_string(),
+		};
+		let mut found_blocks = stratum_stats.blocks_found.clone();
+		found_blocks.reverse();
 
 		c.call_on_id("stratum_config_status", |t: &mut TextView| {
 			t.set_content(stratum_enabled);
@@ -339,10 +495,22 @@ impl TUIStatusListener for TUIMiningView {
 		c.call_on_id("stratum_edge_bits_status", |t: &mut TextView| {
 			t.set_content(stratum_edge_bits);
 		});
+		c.call_on_id("stratum_template_status", |t: &mut TextView| {
+			t.set_content(stratum_template);
+		});
+		c.call_on_id("stratum_blocks_found_status", |t: &mut TextView| {
+			t.set_content(stratum_blocks_found);
+		});
+		let _ = c.call_on_id(
+			TABLE_MINING_FOUND_BLOCKS,
+			|t: &mut TableView<FoundBlock, FoundBlockColumn>| {
+				t.set_items(found_blocks);
+			},
+		);
 		let _ = c.call_on_id(
 			TABLE_MINING_STATUS,
-			|t: &mut TableView<WorkerStats, StratumWorkerColumn>| {
-				t.set_items(worker_stats);
+			|t: &mut TableView<WorkerRow, StratumWorkerColumn>| {
+				t.set_items(worker_rows);
 			},
 		);
 	}
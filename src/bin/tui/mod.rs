@@ -17,6 +17,8 @@ extern crate chrono;
 extern crate humansize;
 
 mod constants;
+mod graph;
+mod logs;
 mod menu;
 mod mining;
 mod peers;
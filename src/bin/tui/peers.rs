@@ -15,8 +15,10 @@
 //! TUI peer display
 
 use std::cmp::Ordering;
+use std::net::SocketAddr;
+use std::sync::mpsc;
 
-use servers::{PeerStats, ServerStats};
+use servers::{BanStats, PeerStats, ServerStats};
 
 use chrono::prelude::*;
 use tui::humansize::{file_size_opts::CONVENTIONAL, FileSize};
@@ -28,14 +30,17 @@ use cursive::views::{BoxView, Dialog, LinearLayout, TextView};
 use cursive::Cursive;
 
 use tui::constants::{TABLE_PEER_STATUS, VIEW_PEER_SYNC};
+use tui::graph::{sparkline, sum_series};
 use tui::table::{TableView, TableViewItem};
 use tui::types::TUIStatusListener;
+use tui::ui::ControllerMessage;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 enum PeerColumn {
 	Address,
 	State,
 	UsedBandwidth,
+	Traffic,
 	TotalDifficulty,
 	Direction,
 	Version,
@@ -48,6 +53,7 @@ impl PeerColumn {
 			PeerColumn::Address => "Address",
 			PeerColumn::State => "State",
 			PeerColumn::UsedBandwidth => "Used bandwidth",
+			PeerColumn::Traffic => "Traffic",
 			PeerColumn::Version => "Version",
 			PeerColumn::TotalDifficulty => "Total Difficulty",
 			PeerColumn::Direction => "Direction",
@@ -71,6 +77,7 @@ impl TableViewItem<PeerColumn> for PeerStats {
 				size_to_string(self.sent_bytes_per_sec),
 				size_to_string(self.received_bytes_per_sec),
 			).to_string(),
+			PeerColumn::Traffic => sparkline(&traffic(self)),
 			PeerColumn::TotalDifficulty => format!(
 				"{} D @ {} H ({}s)",
 				self.total_difficulty,
@@ -104,6 +111,10 @@ impl TableViewItem<PeerColumn> for PeerStats {
 			PeerColumn::Address => self.addr.cmp(&other.addr),
 			PeerColumn::State => self.state.cmp(&other.state),
 			PeerColumn::UsedBandwidth => cmp_used_bandwidth(&self, &other),
+			PeerColumn::Traffic => traffic(self)
+				.iter()
+				.sum::<u64>()
+				.cmp(&traffic(other).iter().sum()),
 			PeerColumn::TotalDifficulty => self.total_difficulty.cmp(&other.total_difficulty),
 			PeerColumn::Direction => self.direction.cmp(&other.direction),
 			PeerColumn::Version => self.version.cmp(&other.version),
@@ -112,18 +123,90 @@ impl TableViewItem<PeerColumn> for PeerStats {
 	}
 }
 
+// Bytes sent to and received from the peer every second
+fn traffic(peer: &PeerStats) -> Vec<u64> {
+	sum_series(vec![&peer.sent_bytes_history, &peer.received_bytes_history].into_iter())
+}
+
 pub struct TUIPeerView;
 
+impl TUIPeerView {
+	/// Shows the details of the peer selected with <Enter>, with buttons to
+	/// ban or disconnect it
+	pub fn set_actions(c: &mut Cursive, controller_tx: mpsc::Sender<ControllerMessage>) {
+		c.call_on_id(
+			TABLE_PEER_STATUS,
+			move |t: &mut TableView<PeerStats, PeerColumn>| {
+				t.set_on_submit(move |s, _row, index| {
+					let peer = s
+						.call_on_id(
+							TABLE_PEER_STATUS,
+							|t: &mut TableView<PeerStats, PeerColumn>| {
+								t.borrow_item(index).cloned()
+							},
+						).and_then(|p| p);
+					if let Some(peer) = peer {
+						show_peer(s, peer, controller_tx.clone());
+					}
+				});
+			},
+		);
+	}
+}
+
+fn show_peer(c: &mut Cursive, peer: PeerStats, controller_tx: mpsc::Sender<ControllerMessage>) {
+	let size_to_string = |size: u64| size.file_size(CONVENTIONAL).unwrap_or("-".to_string());
+	let rtt = match peer.rtt_ms {
+		Some(ms) => format!("{} ms", ms),
+		None => "-".to_string(),
+	};
+	let details = format!(
+		"Address: {}\nState: {}\nDirection: {}\nVersion: {}\nUser Agent: {}\n\
+		 Capabilities: {}\nRound Trip: {}\nTotal Difficulty: {} D @ {} H\n\
+		 Sent: {}/s (5m {}/s)\nReceived: {}/s (5m {}/s)\nTraffic (1 min): {}",
+		peer.addr,
+		peer.state,
+		peer.direction,
+		peer.version,
+		peer.user_agent,
+		peer.capabilities,
+		rtt,
+		peer.total_difficulty,
+		peer.height,
+		size_to_string(peer.sent_bytes_per_sec),
+		size_to_string(peer.sent_bytes_per_sec_5m),
+		size_to_string(peer.received_bytes_per_sec),
+		size_to_string(peer.received_bytes_per_sec_5m),
+		sparkline(&traffic(&peer)),
+	);
+
+	let mut dialog = Dialog::around(TextView::new(details)).title("Peer");
+	if let Ok(addr) = peer.addr.parse::<SocketAddr>() {
+		let ban_tx = controller_tx.clone();
+		dialog = dialog
+			.button("Ban", move |s| {
+				let _ = ban_tx.send(ControllerMessage::BanPeer(addr));
+				s.pop_layer();
+			}).button("Disconnect", move |s| {
+				let _ = controller_tx.send(ControllerMessage::DisconnectPeer(addr));
+				s.pop_layer();
+			});
+	}
+	c.add_layer(dialog.dismiss_button("Close"));
+}
+
 impl TUIStatusListener for TUIPeerView {
 	fn create() -> Box<View> {
 		let table_view = TableView::<PeerStats, PeerColumn>::new()
-			.column(PeerColumn::Address, "Address", |c| c.width_percent(16))
+			.column(PeerColumn::Address, "Address", |c| c.width_percent(14))
 			.column(PeerColumn::State, "State", |c| c.width_percent(8))
 			.column(PeerColumn::UsedBandwidth, "Used bandwidth", |c| {
-				c.width_percent(16)
+				c.width_percent(14)
+			}).column(PeerColumn::Traffic, "Traffic (1 min)", |c| {
+				c.width_percent(12)
 			}).column(PeerColumn::Direction, "Direction", |c| c.width_percent(8))
 			.column(PeerColumn::TotalDifficulty, "Total Difficulty", |c| {
-				c.width_percent(24)
+				c.width_percent(20)
 			}).column(PeerColumn::Version, "Version", |c| c.width_percent(8))
 			.column(PeerColumn::UserAgent, "User Agent", |c| c.width_percent(16));
 		let peer_status_view = BoxView::with_full_screen(
@@ -139,7 +222,10 @@ impl TUIStatusListener for TUIPeerView {
 				).child(TextView::new("   "))
 				.child(
 					Dialog::around(table_view.with_id(TABLE_PEER_STATUS).min_size((50, 20)))
-						.title("Connected Peers"),
+						.title("Connected Peers (Enter for details and actions)"),
+				).child(
+					Dialog::around(TextView::new("None").with_id("banned_peers"))
+						.title("Banned Peers"),
 				),
 		).with_id(VIEW_PEER_SYNC);
 		Box::new(peer_status_view)
@@ -169,5 +255,30 @@ impl TUIStatusListener for TUIPeerView {
 		let _ = c.call_on_id("longest_work_peer", |t: &mut TextView| {
 			t.set_content(lp_str);
 		});
+		let _ = c.call_on_id("banned_peers", |t: &mut TextView| {
+			t.set_content(banned_peers(&stats.banned_peers));
+		});
+	}
+}
+
+// one line per ban, with when it runs out and the note if any
+fn banned_peers(banned: &[BanStats]) -> String {
+	if banned.is_empty() {
+		return "None".to_string();
 	}
+	banned
+		.iter()
+		.map(|b| {
+			let mut line = format!(
+				"{}  {}  until {}",
+				b.addr,
+				b.reason,
+				b.expires_at.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+			);
+			if !b.note.is_empty() {
+				line = format!("{}  ({})", line, b.note);
+			}
+			line
+		}).collect::<Vec<_>>()
+		.join("\n")
 }
@@ -22,6 +22,8 @@ use cursive::views::{BoxView, LinearLayout, TextView};
 use cursive::Cursive;
 
 use tui::constants::VIEW_BASIC_STATUS;
+use tui::graph::{average, sparkline, sum_series};
+use tui::humansize::{file_size_opts::CONVENTIONAL, FileSize};
 use tui::types::TUIStatusListener;
 
 use servers::common::types::SyncStatus;
@@ -40,10 +42,26 @@ impl TUIStatusListener for TUIStatusView {
 					LinearLayout::new(Orientation::Horizontal)
 						.child(TextView::new("Current Status: "))
 						.child(TextView::new("Starting").with_id("basic_current_status")),
+				).child(
+					LinearLayout::new(Orientation::Horizontal)
+						.child(TextView::new("Sync Progress: "))
+						.child(TextView::new("-").with_id("basic_sync_progress")),
+				).child(
+					LinearLayout::new(Orientation::Horizontal)
+						.child(TextView::new("Syncing From: "))
+						.child(TextView::new("-").with_id("basic_sync_peers")),
 				).child(
 					LinearLayout::new(Orientation::Horizontal)
 						.child(TextView::new("Connected Peers: "))
 						.child(TextView::new("0").with_id("connected_peers")),
+				).child(
+					LinearLayout::new(Orientation::Horizontal)
+						.child(TextView::new("Sent: "))
+						.child(TextView::new("-").with_id("basic_sent_traffic")),
+				).child(
+					LinearLayout::new(Orientation::Horizontal)
+						.child(TextView::new("Received: "))
+						.child(TextView::new("-").with_id("basic_received_traffic")),
 				).child(
 					LinearLayout::new(Orientation::Horizontal)
 						.child(TextView::new("------------------------")),
@@ -215,12 +233,26 @@ impl TUIStatusListener for TUIStatusView {
 				(" ".to_string(), " ".to_string())
 			}
 		};*/
+		let (sync_progress, sync_peers) = sync_details(stats);
+		let (sent_traffic, received_traffic) = traffic(stats);
 		c.call_on_id("basic_current_status", |t: &mut TextView| {
 			t.set_content(basic_status);
 		});
+		c.call_on_id("basic_sync_progress", |t: &mut TextView| {
+			t.set_content(sync_progress);
+		});
+		c.call_on_id("basic_sync_peers", |t: &mut TextView| {
+			t.set_content(sync_peers);
+		});
 		c.call_on_id("connected_peers", |t: &mut TextView| {
 			t.set_content(stats.peer_count.to_string());
 		});
+		c.call_on_id("basic_sent_traffic", |t: &mut TextView| {
+			t.set_content(sent_traffic);
+		});
+		c.call_on_id("basic_received_traffic", |t: &mut TextView| {
+			t.set_content(received_traffic);
+		});
 		c.call_on_id("tip_hash", |t: &mut TextView| {
 			t.set_content(stats.head.last_block_h.to_string());
 		});
@@ -250,3 +282,70 @@ impl TUIStatusListener for TUIStatusView {
 		});*/
 	}
 }
+
+// Rate and ETA of the current sync stage, and the peers it downloads from
+fn sync_details(stats: &ServerStats) -> (String, String) {
+	let info = &stats.sync_info;
+	if info.stage == "synced" {
+		return ("-".to_string(), "-".to_string());
+	}
+	let unit = match info.unit.as_ref().map(|u| u.as_str()) {
+		Some("height") => "blocks",
+		Some(unit) => unit,
+		None => "",
+	};
+	let progress = match (info.rate, info.eta) {
+		(Some(rate), Some(eta)) => format!(
+			"{} stage, {:.1} {}/s, about {} left",
+			info.stage,
+			rate,
+			unit,
+			format_secs(eta)
+		),
+		(Some(rate), None) => format!("{} stage, {:.1} {}/s", info.stage, rate, unit),
+		_ => format!("{} stage, {} elapsed", info.stage, format_secs(info.elapsed)),
+	};
+	let peers = if info.peers.is_empty() {
+		"-".to_string()
+	} else {
+		info.peers
+			.iter()
+			.map(|p| p.addr.clone())
+			.collect::<Vec<_>>()
+			.join(", ")
+	};
+	(progress, peers)
+}
+
+// Sparklines of the bytes sent and received every second, over all peers,
+// with the 5 minutes average rates
+fn traffic(stats: &ServerStats) -> (String, String) {
+	let size = |bytes: u64| bytes.file_size(CONVENTIONAL).unwrap_or("-".to_string());
+	let line = |series: Vec<u64>, rate_5m: u64| {
+		format!(
+			"{} {}/s (5m {}/s)",
+			sparkline(&series),
+			size(average(&series)),
+			size(rate_5m)
+		)
+	};
+	let sent = sum_series(stats.peer_stats.iter().map(|p| &p.sent_bytes_history));
+	let received = sum_series(stats.peer_stats.iter().map(|p| &p.received_bytes_history));
+	let sent_5m = stats.peer_stats.iter().map(|p| p.sent_bytes_per_sec_5m).sum();
+	let received_5m = stats
+		.peer_stats
+		.iter()
+		.map(|p| p.received_bytes_per_sec_5m)
+		.sum();
+	(line(sent, sent_5m), line(received, received_5m))
+}
+
+fn format_secs(secs: u64) -> String {
+	if secs >= 3600 {
+		format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+	} else if secs >= 60 {
+		format!("{}m{:02}s", secs / 60, secs % 60)
+	} else {
+		format!("{}s", secs)
+	}
+}
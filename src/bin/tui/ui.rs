@@ -16,6 +16,7 @@
 //! of various subsystems
 
 use chrono::prelude::Utc;
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc};
 
@@ -32,11 +33,12 @@ use cursive::utils::markup::StyledString;
 use cursive::views::{LinearLayout, Panel, StackView, TextView, ViewBox};
 use cursive::Cursive;
 
+use p2p::ReasonForBan;
 use servers::Server;
 
 use tui::constants::ROOT_STACK;
 use tui::types::{TUIStatusListener, UIMessage};
-use tui::{menu, mining, peers, status, version};
+use tui::{logs, menu, mining, peers, status, version};
 
 use built_info;
 
@@ -75,11 +77,13 @@ impl UI {
 		let mining_view = mining::TUIMiningView::create();
 		let peer_view = peers::TUIPeerView::create();
 		let version_view = version::TUIVersionView::create();
+		let logs_view = logs::TUILogsView::create();
 
 		let main_menu = menu::create();
 
 		let root_stack = StackView::new()
 			.layer(version_view)
+			.layer(logs_view)
 			.layer(mining_view)
 			.layer(peer_view)
 			.layer(status_view)
@@ -105,6 +109,7 @@ impl UI {
 		modify_theme(&mut theme);
 		grin_ui.cursive.set_theme(theme);
 		grin_ui.cursive.add_fullscreen_layer(main_layer);
+		peers::TUIPeerView::set_actions(&mut grin_ui.cursive, grin_ui.controller_tx.clone());
 
 		// Configure a callback (shutdown, for the first test)
 		let controller_tx_clone = grin_ui.controller_tx.clone();
@@ -132,6 +137,7 @@ impl UI {
 					mining::TUIMiningView::update(&mut self.cursive, &update);
 					peers::TUIPeerView::update(&mut self.cursive, &update);
 					version::TUIVersionView::update(&mut self.cursive, &update);
+					logs::TUILogsView::update(&mut self.cursive, &update);
 				}
 			}
 		}
@@ -154,6 +160,8 @@ pub struct Controller {
 
 pub enum ControllerMessage {
 	Shutdown,
+	BanPeer(SocketAddr),
+	DisconnectPeer(SocketAddr),
 }
 
 impl Controller {
@@ -171,7 +179,7 @@ impl Controller {
 		let mut next_stat_update = Utc::now().timestamp() + stat_update_interval;
 		while self.ui.step() {
 			if !running.load(Ordering::SeqCst) {
-				warn!("Received SIGINT (Ctrl+C).");
+				warn!("Received SIGINT (Ctrl+C) or SIGTERM (kill).");
 				server.stop();
 				self.ui.stop();
 			}
@@ -186,6 +194,14 @@ impl Controller {
 							.send(UIMessage::UpdateOutput("update".to_string()))
 							.unwrap();*/
 					}
+					ControllerMessage::BanPeer(addr) => {
+						warn!("Banning peer {} from the TUI.", addr);
+						server.p2p.peers.ban_peer(&addr, ReasonForBan::ManualBan);
+					}
+					ControllerMessage::DisconnectPeer(addr) => {
+						warn!("Disconnecting peer {} from the TUI.", addr);
+						server.p2p.peers.disconnect_peer(&addr);
+					}
 				}
 			}
 
@@ -0,0 +1,114 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of appending to the MMRs stored on disk and rewinding them, as
+//! done for each block applied or rolled back
+#[macro_use]
+extern crate criterion;
+extern crate croaring;
+extern crate grin_core as core;
+extern crate grin_store as store;
+
+use std::fs;
+
+use criterion::Criterion;
+use croaring::Bitmap;
+
+use core::core::pmmr::PMMR;
+use core::ser::{Error, FixedLength, PMMRable, Readable, Reader, Writeable, Writer};
+use store::pmmr::PMMRBackend;
+
+/// Elements appended per iteration, about the outputs of a full block
+const ELEMS: u32 = 1000;
+
+fn backend(tag: &str) -> PMMRBackend<TestElem> {
+	let data_dir = format!("target/bench_pmmr/{}", tag);
+	let _ = fs::remove_dir_all(&data_dir);
+	fs::create_dir_all(&data_dir).unwrap();
+	PMMRBackend::new(data_dir, true, None).unwrap()
+}
+
+fn pmmr_append(c: &mut Criterion) {
+	let mut backend = backend("append");
+	let mut size = 0;
+	let mut n = 0;
+	c.bench_function("pmmr append", move |b| {
+		b.iter(|| {
+			{
+				let mut pmmr = PMMR::at(&mut backend, size);
+				for _ in 0..ELEMS {
+					n += 1;
+					pmmr.push(TestElem(n)).unwrap();
+				}
+				size = pmmr.unpruned_size();
+			}
+			backend.sync().unwrap();
+		})
+	});
+}
+
+// appending and rewinding back to where it started, the rewind taking the
+// difference with the append benchmark
+fn pmmr_append_rewind(c: &mut Criterion) {
+	let mut backend = backend("rewind");
+	let size = {
+		let mut pmmr = PMMR::at(&mut backend, 0);
+		for n in 0..ELEMS {
+			pmmr.push(TestElem(n)).unwrap();
+		}
+		pmmr.unpruned_size()
+	};
+	backend.sync().unwrap();
+
+	c.bench_function("pmmr append and rewind", move |b| {
+		b.iter(|| {
+			{
+				let mut pmmr = PMMR::at(&mut backend, size);
+				for n in 0..ELEMS {
+					pmmr.push(TestElem(n)).unwrap();
+				}
+			}
+			backend.sync().unwrap();
+			{
+				let mut pmmr: PMMR<TestElem, _> = PMMR::at(&mut backend, size);
+				pmmr.rewind(size, &Bitmap::create()).unwrap();
+			}
+			backend.sync().unwrap();
+		})
+	});
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct TestElem(u32);
+
+impl FixedLength for TestElem {
+	const LEN: usize = 4;
+}
+
+impl PMMRable for TestElem {}
+
+impl Writeable for TestElem {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_u32(self.0)
+	}
+}
+
+impl Readable for TestElem {
+	fn read(reader: &mut Reader) -> Result<TestElem, Error> {
+		Ok(TestElem(reader.read_u32()?))
+	}
+}
+
+criterion_group!(benches, pmmr_append, pmmr_append_rewind);
+criterion_main!(benches);
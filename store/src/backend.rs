@@ -0,0 +1,66 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key/value backends the store can sit on. LMDB is the default, others
+//! (in-memory for tests, or tuned for write-heavy archive nodes) plug in
+//! through `Store::with_backend` without the store users knowing.
+
+use std::path::Path;
+
+use lmdb::Error;
+
+/// Raw key/value pairs, in key order
+pub type RawIterator = Box<Iterator<Item = (Vec<u8>, Vec<u8>)>>;
+
+/// An ordered key/value store, with atomic batches of writes
+pub trait KVBackend: Send + Sync {
+	/// Gets a value, provided its key
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Whether the provided key exists
+	fn exists(&self, key: &[u8]) -> Result<bool, Error>;
+
+	/// Iterates over the pairs in key order, from the first key greater or
+	/// equal to the provided one
+	fn iter(&self, from: &[u8]) -> Result<RawIterator, Error>;
+
+	/// Flushes everything committed so far to disk
+	fn sync(&self) -> Result<(), Error>;
+
+	/// Writes a consistent copy of everything committed to the provided
+	/// existing directory, while still in use
+	fn copy_to(&self, dest: &Path) -> Result<(), Error>;
+
+	/// Starts a new batch of writes
+	fn batch<'a>(&'a self) -> Result<Box<KVBatch + 'a>, Error>;
+}
+
+/// Writes to a backend, all done on commit or none at all
+pub trait KVBatch {
+	/// Gets a value, taking the writes of the batch into account
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Writes a key/value pair
+	fn put(&self, key: &[u8], value: Vec<u8>) -> Result<(), Error>;
+
+	/// Deletes a key/value pair, failing if the key doesn't exist
+	fn delete(&self, key: &[u8]) -> Result<(), Error>;
+
+	/// Applies the writes of the batch
+	fn commit(self: Box<Self>) -> Result<(), Error>;
+
+	/// Creates a child batch, its writes merged with the parent ones on
+	/// commit, abandoned otherwise
+	fn child<'b>(&'b mut self) -> Result<Box<KVBatch + 'b>, Error>;
+}
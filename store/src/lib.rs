@@ -36,8 +36,10 @@ extern crate failure_derive;
 extern crate grin_core as core;
 extern crate grin_util as util;
 
+pub mod backend;
 pub mod leaf_set;
 mod lmdb;
+pub mod memory;
 pub mod pmmr;
 pub mod prune_list;
 pub mod types;
@@ -46,7 +48,9 @@ const SEP: u8 = b':';
 
 use byteorder::{BigEndian, WriteBytesExt};
 
+pub use backend::{KVBackend, KVBatch, RawIterator};
 pub use lmdb::*;
+pub use memory::MemBackend;
 
 /// Build a db key from a prefix and a byte vector identifier.
 pub fn to_key(prefix: u8, k: &mut Vec<u8>) -> Vec<u8> {
@@ -12,17 +12,54 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Storage of core types using LMDB.
+//! Storage of core types, using LMDB by default.
 
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
 use std::fs;
 use std::marker;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 
 use lmdb_zero as lmdb;
 use lmdb_zero::traits::CreateCursor;
 use lmdb_zero::LmdbResultExt;
 
+use backend::{KVBackend, KVBatch, RawIterator};
 use core::ser;
+use util::{Mutex, RwLock, RwLockReadGuard};
+
+/// Size the LMDB map starts at, and grows by each time it fills up
+pub const DEFAULT_MAP_INCREMENT: usize = 1 << 30;
+
+/// Size the LMDB map never grows past
+pub const DEFAULT_MAP_MAX: usize = 549755813888;
+
+static MAP_INCREMENT: AtomicUsize = AtomicUsize::new(DEFAULT_MAP_INCREMENT);
+static MAP_MAX: AtomicUsize = AtomicUsize::new(DEFAULT_MAP_MAX);
+
+// How long growing the map waits for the transactions in progress to be done
+const RESIZE_WAIT_SECS: u64 = 30;
+
+/// Sets the size, in bytes, the LMDB maps start at and grow by each time
+/// they fill up, as well as the size they can't grow past. Applies to the
+/// environments created afterward.
+pub fn set_map_growth(increment: usize, max: usize) {
+	MAP_INCREMENT.store(increment, Ordering::Relaxed);
+	MAP_MAX.store(cmp::max(increment, max), Ordering::Relaxed);
+}
+
+fn is_map_full(e: &lmdb::Error) -> bool {
+	*e == lmdb::Error::Code(lmdb::error::MAP_FULL)
+}
+
+fn map_full() -> Error {
+	Error::LmdbErr(lmdb::Error::Code(lmdb::error::MAP_FULL))
+}
 
 /// Main error type for this lmdb
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
@@ -37,6 +74,9 @@ pub enum Error {
 	/// Wraps a serialization error for Writeable or Readable
 	#[fail(display = "Serialization Error")]
 	SerErr(String),
+	/// Operation the backend doesn't support
+	#[fail(display = "Unsupported: {}", _0)]
+	Unsupported(String),
 }
 
 impl From<lmdb::error::Error> for Error {
@@ -54,27 +94,31 @@ pub fn option_to_not_found<T>(res: Result<Option<T>, Error>, field_name: &str) -
 	}
 }
 
+/// Name of the environment `new_env` creates
+pub const DEFAULT_ENV_NAME: &'static str = "lmdb";
+
 /// Create a new LMDB env under the provided directory.
 /// By default creates an environment named "lmdb".
 /// Be aware of transactional semantics in lmdb
 /// (transactions are per environment, not per database).
 pub fn new_env(path: String) -> lmdb::Environment {
-	new_named_env(path, "lmdb".into())
+	new_named_env(path, DEFAULT_ENV_NAME.into())
 }
 
-/// TODO - We probably need more flexibility here, 500GB probably too big for peers...
 /// Create a new LMDB env under the provided directory with the provided name.
+/// The map starts at the configured increment (or the size of the existing
+/// data if larger) and grows as needed, see `set_map_growth`.
 pub fn new_named_env(path: String, name: String) -> lmdb::Environment {
 	let full_path = [path, name].join("/");
 	fs::create_dir_all(&full_path).unwrap();
 	unsafe {
 		let mut env_builder = lmdb::EnvBuilder::new().unwrap();
 		env_builder.set_maxdbs(8).unwrap();
-		// half a TB should give us plenty room, will be an issue on 32 bits
-		// (which we don't support anyway)
-		env_builder.set_mapsize(549755813888).unwrap_or_else(|e| {
-			panic!("Unable to allocate LMDB space: {:?}", e);
-		});
+		env_builder
+			.set_mapsize(MAP_INCREMENT.load(Ordering::Relaxed))
+			.unwrap_or_else(|e| {
+				panic!("Unable to allocate LMDB space: {:?}", e);
+			});
 
 		env_builder
 			.open(&full_path, lmdb::open::Flags::empty(), 0o600)
@@ -82,106 +126,174 @@ pub fn new_named_env(path: String, name: String) -> lmdb::Environment {
 	}
 }
 
-/// LMDB-backed store facilitating data access and serialization. All writes
-/// are done through a Batch abstraction providing atomicity.
+/// Store facilitating data access and serialization, on top of LMDB or any
+/// other key/value backend. All writes are done through a Batch abstraction
+/// providing atomicity.
 pub struct Store {
-	env: Arc<lmdb::Environment>,
-	db: Arc<lmdb::Database<'static>>,
+	backend: Box<KVBackend>,
 }
 
 impl Store {
 	/// Creates a new store with the provided name under the specified
 	/// environment
 	pub fn open(env: Arc<lmdb::Environment>, name: &str) -> Store {
-		let db = Arc::new(
-			lmdb::Database::open(
-				env.clone(),
-				Some(name),
-				&lmdb::DatabaseOptions::new(lmdb::db::CREATE),
-			).unwrap(),
-		);
-		Store { env, db }
+		Store::with_backend(Box::new(LmdbBackend::open(env, name)))
+	}
+
+	/// Creates a new store on the provided backend
+	pub fn with_backend(backend: Box<KVBackend>) -> Store {
+		Store { backend }
 	}
 
 	/// Gets a value from the db, provided its key
 	pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
-		let access = txn.access();
-		let res = access.get(&self.db, key);
-		res.map(|res: &[u8]| res.to_vec())
-			.to_opt()
-			.map_err(From::from)
+		self.backend.get(key)
 	}
 
 	/// Gets a `Readable` value from the db, provided its key. Encapsulates
 	/// serialization.
 	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
-		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
-		let access = txn.access();
-		self.get_ser_access(key, &access)
-	}
-
-	fn get_ser_access<T: ser::Readable>(
-		&self,
-		key: &[u8],
-		access: &lmdb::ConstAccessor,
-	) -> Result<Option<T>, Error> {
-		let res: lmdb::error::Result<&[u8]> = access.get(&self.db, key);
-		match res.to_opt() {
-			Ok(Some(mut res)) => match ser::deserialize(&mut res) {
-				Ok(res) => Ok(Some(res)),
-				Err(e) => Err(Error::SerErr(format!("{}", e))),
-			},
-			Ok(None) => Ok(None),
-			Err(e) => Err(From::from(e)),
-		}
+		deser_opt(self.backend.get(key)?)
 	}
 
 	/// Whether the provided key exists
 	pub fn exists(&self, key: &[u8]) -> Result<bool, Error> {
-		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
-		let access = txn.access();
-		let res: lmdb::error::Result<&lmdb::Ignore> = access.get(&self.db, key);
-		res.to_opt().map(|r| r.is_some()).map_err(From::from)
+		self.backend.exists(key)
 	}
 
 	/// Produces an iterator of `Readable` types moving forward from the
 	/// provided key.
 	pub fn iter<T: ser::Readable>(&self, from: &[u8]) -> Result<SerIterator<T>, Error> {
-		let tx = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
-		let cursor = Arc::new(tx.cursor(self.db.clone()).unwrap());
 		Ok(SerIterator {
-			tx,
-			cursor,
-			seek: false,
+			inner: self.backend.iter(from)?,
 			prefix: from.to_vec(),
 			_marker: marker::PhantomData,
 		})
 	}
 
+	/// All raw key/value pairs whose key starts with the provided prefix, for
+	/// callers that need the keys as well as the values.
+	pub fn raw_entries(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, Error> {
+		Ok(self
+			.backend
+			.iter(prefix)?
+			.take_while(|&(ref k, _)| k.starts_with(prefix))
+			.collect())
+	}
+
+	/// A page of at most `limit` `Readable` values whose key starts with the
+	/// provided prefix, in key order. Starts at the continuation token of a
+	/// previous page if provided, at the first key of the prefix otherwise.
+	/// Only reads what the page holds, whatever the prefix size.
+	pub fn page<T: ser::Readable>(
+		&self,
+		prefix: &[u8],
+		from: Option<&[u8]>,
+		limit: usize,
+	) -> Result<Page<T>, Error> {
+		let mut start = prefix.to_vec();
+		if let Some(from) = from {
+			start.extend_from_slice(from);
+		}
+		let mut page = Page {
+			items: vec![],
+			next: None,
+		};
+		for (k, v) in self
+			.backend
+			.iter(&start)?
+			.take_while(|&(ref k, _)| k.starts_with(prefix))
+		{
+			if page.items.len() == limit {
+				page.next = Some(k[prefix.len()..].to_vec());
+				break;
+			}
+			match ser::deserialize(&mut &v[..]) {
+				Ok(item) => page.items.push(item),
+				Err(e) => return Err(Error::SerErr(format!("{}", e))),
+			}
+		}
+		Ok(page)
+	}
+
+	/// Forces the environment to flush everything committed so far to disk,
+	/// used before closing down.
+	pub fn sync(&self) -> Result<(), Error> {
+		self.backend.sync()
+	}
+
+	/// All raw key/value pairs of the store as they are now, whatever gets
+	/// written while they're read.
+	pub fn snapshot(&self) -> Result<RawIterator, Error> {
+		self.backend.iter(&[])
+	}
+
+	/// Writes a consistent copy of the store to the provided existing
+	/// directory, while it's still in use.
+	pub fn copy_to(&self, dest: &Path) -> Result<(), Error> {
+		self.backend.copy_to(dest)
+	}
+
 	/// Builds a new batch to be used with this store.
 	pub fn batch(&self) -> Result<Batch, Error> {
-		let txn = lmdb::WriteTransaction::new(self.env.clone())?;
 		Ok(Batch {
 			store: self,
-			tx: txn,
+			inner: self.backend.batch()?,
+			on_commit: RefCell::new(vec![]),
+			parent_on_commit: None,
 		})
 	}
+
+	/// Runs the provided writes in a new batch, all committed together if
+	/// they succeed and all discarded otherwise, so a crash can never leave
+	/// only some of them written.
+	pub fn atomic<F, T, E>(&self, f: F) -> Result<T, E>
+	where
+		F: FnOnce(&Batch) -> Result<T, E>,
+		E: From<Error>,
+	{
+		let batch = self.batch()?;
+		let res = f(&batch)?;
+		batch.commit()?;
+		Ok(res)
+	}
 }
 
+fn deser_opt<T: ser::Readable>(value: Option<Vec<u8>>) -> Result<Option<T>, Error> {
+	match value {
+		Some(v) => match ser::deserialize(&mut &v[..]) {
+			Ok(res) => Ok(Some(res)),
+			Err(e) => Err(Error::SerErr(format!("{}", e))),
+		},
+		None => Ok(None),
+	}
+}
+
+/// A page of a prefix scan, and where the next one starts
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+	/// Values of the page, in key order
+	pub items: Vec<T>,
+	/// Continuation token, the key the next page starts at without the
+	/// prefix. None when this page is the last one.
+	pub next: Option<Vec<u8>>,
+}
+
+type CommitHook = Box<FnMut()>;
+
 /// Batch to write multiple Writeables to db in an atomic manner.
 pub struct Batch<'a> {
 	store: &'a Store,
-	tx: lmdb::WriteTransaction<'a>,
+	inner: Box<KVBatch + 'a>,
+	// run once the writes are committed, handed to the parent by children
+	on_commit: RefCell<Vec<CommitHook>>,
+	parent_on_commit: Option<&'a RefCell<Vec<CommitHook>>>,
 }
 
 impl<'a> Batch<'a> {
 	/// Writes a single key/value pair to the db
 	pub fn put(&self, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
-		self.tx
-			.access()
-			.put(&self.store.db, key, &value, lmdb::put::Flags::empty())?;
-		Ok(())
+		self.inner.put(key, value)
 	}
 
 	/// Writes a single key and its `Writeable` value to the db. Encapsulates
@@ -213,19 +325,41 @@ impl<'a> Batch<'a> {
 	/// Gets a `Readable` value from the db, provided its key, taking the
 	/// content of the current batch into account.
 	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
-		let access = self.tx.access();
-		self.store.get_ser_access(key, &access)
+		deser_opt(self.inner.get(key)?)
 	}
 
 	/// Deletes a key/value pair from the db
 	pub fn delete(&self, key: &[u8]) -> Result<(), Error> {
-		self.tx.access().del_key(&self.store.db, key)?;
-		Ok(())
+		self.inner.delete(key)
+	}
+
+	/// Registers something to run once the batch is committed, like updating
+	/// a cache of what it writes. Dropped if the batch is abandoned. For a
+	/// child batch, runs when the top level batch is committed.
+	pub fn on_commit<F>(&self, f: F)
+	where
+		F: FnOnce() + 'static,
+	{
+		let mut f = Some(f);
+		self.on_commit.borrow_mut().push(Box::new(move || {
+			if let Some(f) = f.take() {
+				f()
+			}
+		}));
 	}
 
 	/// Writes the batch to db
 	pub fn commit(self) -> Result<(), Error> {
-		self.tx.commit()?;
+		let hooks = self.on_commit.into_inner();
+		self.inner.commit()?;
+		match self.parent_on_commit {
+			Some(parent) => parent.borrow_mut().extend(hooks),
+			None => {
+				for mut hook in hooks {
+					hook();
+				}
+			}
+		}
 		Ok(())
 	}
 
@@ -234,20 +368,20 @@ impl<'a> Batch<'a> {
 	pub fn child(&mut self) -> Result<Batch, Error> {
 		Ok(Batch {
 			store: self.store,
-			tx: self.tx.child_tx()?,
+			inner: self.inner.child()?,
+			on_commit: RefCell::new(vec![]),
+			parent_on_commit: Some(&self.on_commit),
 		})
 	}
 }
 
 /// An iterator thad produces Readable instances back. Wraps the lower level
-/// DBIterator and deserializes the returned values.
+/// backend iterator and deserializes the returned values.
 pub struct SerIterator<T>
 where
 	T: ser::Readable,
 {
-	tx: Arc<lmdb::ReadTransaction<'static>>,
-	cursor: Arc<lmdb::Cursor<'static, 'static>>,
-	seek: bool,
+	inner: RawIterator,
 	prefix: Vec<u8>,
 	_marker: marker::PhantomData<T>,
 }
@@ -259,34 +393,455 @@ where
 	type Item = T;
 
 	fn next(&mut self) -> Option<T> {
+		match self.inner.next() {
+			Some((k, v)) => {
+				if k.starts_with(&self.prefix) {
+					ser::deserialize(&mut &v[..]).ok()
+				} else {
+					None
+				}
+			}
+			None => None,
+		}
+	}
+}
+
+/// The default backend, a database in an LMDB environment. When the map
+/// fills up, it's grown and the failed batch replayed, transparently.
+pub struct LmdbBackend {
+	env: Arc<lmdb::Environment>,
+	db: Arc<lmdb::Database<'static>>,
+	// held for reading by transactions, for writing while resizing the map
+	resize_lock: RwLock<()>,
+	// iterators still holding a read transaction, by thread that opened them
+	iterators: Arc<Mutex<HashMap<ThreadId, usize>>>,
+}
+
+impl LmdbBackend {
+	/// Opens the database with the provided name in the environment,
+	/// creating it if needed
+	pub fn open(env: Arc<lmdb::Environment>, name: &str) -> LmdbBackend {
+		let db = Arc::new(
+			lmdb::Database::open(
+				env.clone(),
+				Some(name),
+				&lmdb::DatabaseOptions::new(lmdb::db::CREATE),
+			).unwrap(),
+		);
+		LmdbBackend {
+			env,
+			db,
+			resize_lock: RwLock::new(()),
+			iterators: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	// Grows the map by the configured increment, up to the maximum. LMDB
+	// requires no transaction to be in progress while resizing, so waits for
+	// them to be done. Fails right away if the calling thread holds some
+	// iterators, as it would wait for itself.
+	fn grow_map(&self) -> Result<(), Error> {
+		let own = self
+			.iterators
+			.lock()
+			.get(&thread::current().id())
+			.cloned()
+			.unwrap_or(0);
+		if own > 0 {
+			error!(
+				"LMDB map full, {} iterators still open in the writing thread, not resizing.",
+				own
+			);
+			return Err(map_full());
+		}
+
+		let wait = Duration::from_secs(RESIZE_WAIT_SECS);
+		let _lock = match self.resize_lock.try_write_for(wait) {
+			Some(lock) => lock,
+			None => {
+				warn!("LMDB map full, transactions still in progress, not resizing.");
+				return Err(map_full());
+			}
+		};
+		let start = Instant::now();
+		while !self.iterators.lock().is_empty() {
+			if start.elapsed() > wait {
+				warn!("LMDB map full, iterators still in progress, not resizing.");
+				return Err(map_full());
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+
+		let current = self.env.info()?.mapsize;
+		let max = MAP_MAX.load(Ordering::Relaxed);
+		if current >= max {
+			error!("LMDB map full at its maximum of {} MB.", max >> 20);
+			return Err(map_full());
+		}
+		let size = cmp::min(current + MAP_INCREMENT.load(Ordering::Relaxed), max);
+		unsafe {
+			self.env.set_mapsize(size)?;
+		}
+		info!(
+			"LMDB map full, grown from {} MB to {} MB.",
+			current >> 20,
+			size >> 20
+		);
+		Ok(())
+	}
+}
+
+impl KVBackend for LmdbBackend {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		let _resize = self.resize_lock.read_recursive();
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let access = txn.access();
+		let res = access.get(&self.db, key);
+		res.map(|res: &[u8]| res.to_vec())
+			.to_opt()
+			.map_err(From::from)
+	}
+
+	fn exists(&self, key: &[u8]) -> Result<bool, Error> {
+		let _resize = self.resize_lock.read_recursive();
+		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
+		let access = txn.access();
+		let res: lmdb::error::Result<&lmdb::Ignore> = access.get(&self.db, key);
+		res.to_opt().map(|r| r.is_some()).map_err(From::from)
+	}
+
+	fn iter(&self, from: &[u8]) -> Result<RawIterator, Error> {
+		let _resize = self.resize_lock.read_recursive();
+		// counted before the transaction starts, released after it ends
+		let open = OpenIterator::new(self.iterators.clone());
+		let tx = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
+		let cursor = Arc::new(tx.cursor(self.db.clone())?);
+		Ok(Box::new(LmdbIterator {
+			tx,
+			cursor,
+			seek: false,
+			from: from.to_vec(),
+			_open: open,
+		}))
+	}
+
+	fn sync(&self) -> Result<(), Error> {
+		self.env.sync(true)?;
+		Ok(())
+	}
+
+	fn copy_to(&self, dest: &Path) -> Result<(), Error> {
+		let _resize = self.resize_lock.read_recursive();
+		self.env.copy(dest.to_str().unwrap(), lmdb::copy::COMPACT)?;
+		Ok(())
+	}
+
+	fn batch<'a>(&'a self) -> Result<Box<KVBatch + 'a>, Error> {
+		let resize_guard = self.resize_lock.read_recursive();
+		let txn = lmdb::WriteTransaction::new(self.env.clone())?;
+		Ok(Box::new(LmdbBatch {
+			backend: self,
+			tx: RefCell::new(Some(txn)),
+			log: RefCell::new(vec![]),
+			full: RefCell::new(None),
+			parent: None,
+			resize_guard: RefCell::new(Some(resize_guard)),
+		}))
+	}
+}
+
+// A write done in a batch, to replay it
+enum Op {
+	Put(Vec<u8>, Vec<u8>),
+	Delete(Vec<u8>),
+}
+
+/// Batch of writes in an LMDB write transaction
+pub struct LmdbBatch<'a> {
+	backend: &'a LmdbBackend,
+	// None after the map filled up in a child batch, or a failed attempt at
+	// growing the map
+	tx: RefCell<Option<lmdb::WriteTransaction<'a>>>,
+	// writes so far, including the ones of committed child batches, replayed
+	// in a new transaction when the map fills up
+	log: RefCell<Vec<Op>>,
+	// the map filled up in a child batch. A child only logs its writes from
+	// then on, for its parent to replay them once it's committed.
+	full: RefCell<Option<lmdb::Error>>,
+	// log and full map of the parent of a child batch
+	parent: Option<(&'a RefCell<Vec<Op>>, &'a RefCell<Option<lmdb::Error>>)>,
+	// keeps the map from being resized under a top level batch
+	resize_guard: RefCell<Option<RwLockReadGuard<'a, ()>>>,
+}
+
+impl<'a> KVBatch for LmdbBatch<'a> {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		self.replay_pending()?;
+		if let Some(ref e) = *self.full.borrow() {
+			// the writes of a full child are only logged
+			return match self.logged(key) {
+				Some(value) => Ok(value),
+				None => Err(Error::LmdbErr(e.clone())),
+			};
+		}
+		self.with_tx(|tx| {
+			let access = tx.access();
+			let res = access.get(&self.backend.db, key);
+			res.map(|res: &[u8]| res.to_vec())
+				.to_opt()
+				.map_err(From::from)
+		})
+	}
+
+	fn put(&self, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
+		self.write(Op::Put(key.to_vec(), value))
+	}
+
+	fn delete(&self, key: &[u8]) -> Result<(), Error> {
+		self.write(Op::Delete(key.to_vec()))
+	}
+
+	fn commit(self: Box<Self>) -> Result<(), Error> {
+		if let Some((parent_log, parent_full)) = self.parent {
+			// the writes of a child are merged into its parent, to be
+			// replayed along with the parent's
+			let full = self.full.borrow_mut().take();
+			let full = match full {
+				Some(e) => Some(e),
+				None => {
+					let tx = self.tx.borrow_mut().take();
+					match tx {
+						Some(tx) => match tx.commit() {
+							Ok(()) => None,
+							Err(ref e) if is_map_full(e) => Some(e.clone()),
+							Err(e) => return Err(From::from(e)),
+						},
+						None => return Err(bad_txn()),
+					}
+				}
+			};
+			parent_log
+				.borrow_mut()
+				.extend(self.log.borrow_mut().drain(..));
+			if full.is_some() {
+				*parent_full.borrow_mut() = full;
+			}
+			return Ok(());
+		}
+
+		self.replay_pending()?;
+		loop {
+			let tx = self.tx.borrow_mut().take();
+			let res = match tx {
+				Some(tx) => tx.commit(),
+				None => return Err(bad_txn()),
+			};
+			match res {
+				Ok(()) => return Ok(()),
+				Err(ref e) if is_map_full(e) => self.grow_and_replay()?,
+				Err(e) => return Err(From::from(e)),
+			}
+		}
+	}
+
+	fn child<'b>(&'b mut self) -> Result<Box<KVBatch + 'b>, Error> {
+		self.replay_pending()?;
+		if self.full.borrow().is_some() {
+			return Err(bad_txn());
+		}
+		let LmdbBatch {
+			backend,
+			ref mut tx,
+			ref log,
+			ref full,
+			..
+		} = *self;
+		let tx = match *tx.get_mut() {
+			Some(ref mut tx) => tx.child_tx()?,
+			None => return Err(bad_txn()),
+		};
+		Ok(Box::new(LmdbBatch {
+			backend,
+			tx: RefCell::new(Some(tx)),
+			log: RefCell::new(vec![]),
+			full: RefCell::new(None),
+			parent: Some((log, full)),
+			resize_guard: RefCell::new(None),
+		}))
+	}
+}
+
+fn bad_txn() -> Error {
+	Error::LmdbErr(lmdb::Error::Code(lmdb::error::BAD_TXN))
+}
+
+impl<'a> LmdbBatch<'a> {
+	fn with_tx<F, T>(&self, f: F) -> Result<T, Error>
+	where
+		F: FnOnce(&lmdb::WriteTransaction<'a>) -> Result<T, Error>,
+	{
+		match *self.tx.borrow() {
+			Some(ref tx) => f(tx),
+			None => Err(bad_txn()),
+		}
+	}
+
+	fn apply(&self, op: &Op) -> Result<(), Error> {
+		self.with_tx(|tx| {
+			let mut access = tx.access();
+			match *op {
+				Op::Put(ref key, ref value) => access.put(
+					&self.backend.db,
+					&key[..],
+					&value[..],
+					lmdb::put::Flags::empty(),
+				)?,
+				Op::Delete(ref key) => access.del_key(&self.backend.db, &key[..])?,
+			}
+			Ok(())
+		})
+	}
+
+	// Last value logged for a key, if any write to it was logged
+	fn logged(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+		self.log
+			.borrow()
+			.iter()
+			.rev()
+			.filter_map(|op| match *op {
+				Op::Put(ref k, ref v) if &k[..] == key => Some(Some(v.clone())),
+				Op::Delete(ref k) if &k[..] == key => Some(None),
+				_ => None,
+			}).next()
+	}
+
+	// Applies and logs a write, growing the map if it's full
+	fn write(&self, op: Op) -> Result<(), Error> {
+		self.replay_pending()?;
+		if self.full.borrow().is_some() {
+			self.log.borrow_mut().push(op);
+			return Ok(());
+		}
+		let res = self.apply(&op);
+		let full = match res {
+			Ok(()) => None,
+			Err(Error::LmdbErr(ref e)) if is_map_full(e) => Some(e.clone()),
+			Err(e) => return Err(e),
+		};
+		self.log.borrow_mut().push(op);
+		match full {
+			Some(e) => self.map_full(e),
+			None => Ok(()),
+		}
+	}
+
+	// A top level batch grows the map and replays its writes. A child can't
+	// without its parent, its transaction is aborted (leaving the parent's
+	// usable) and its writes only logged from then on.
+	fn map_full(&self, e: lmdb::Error) -> Result<(), Error> {
+		if self.parent.is_some() {
+			self.tx.borrow_mut().take();
+			*self.full.borrow_mut() = Some(e);
+			Ok(())
+		} else {
+			self.grow_and_replay()
+		}
+	}
+
+	// A child batch committed after the map filled up, its writes need
+	// replaying in a grown map before anything else
+	fn replay_pending(&self) -> Result<(), Error> {
+		if self.parent.is_some() {
+			return Ok(());
+		}
+		let full = self.full.borrow_mut().take();
+		match full {
+			Some(_) => self.grow_and_replay(),
+			None => Ok(()),
+		}
+	}
+
+	// Aborts the transaction, grows the map and replays the writes logged so
+	// far in a new transaction, growing again if they still don't fit.
+	fn grow_and_replay(&self) -> Result<(), Error> {
+		loop {
+			self.tx.borrow_mut().take();
+			self.resize_guard.borrow_mut().take();
+			self.backend.grow_map()?;
+
+			*self.resize_guard.borrow_mut() = Some(self.backend.resize_lock.read_recursive());
+			let tx = lmdb::WriteTransaction::new(self.backend.env.clone())?;
+			*self.tx.borrow_mut() = Some(tx);
+
+			let replayed = self
+				.log
+				.borrow()
+				.iter()
+				.map(|op| self.apply(op))
+				.collect::<Result<(), Error>>();
+			match replayed {
+				Ok(()) => return Ok(()),
+				Err(Error::LmdbErr(ref e)) if is_map_full(e) => continue,
+				Err(e) => return Err(e),
+			}
+		}
+	}
+}
+
+// Iterates with a cursor in its own read transaction
+struct LmdbIterator {
+	tx: Arc<lmdb::ReadTransaction<'static>>,
+	cursor: Arc<lmdb::Cursor<'static, 'static>>,
+	seek: bool,
+	from: Vec<u8>,
+	// last, to be dropped after the transaction
+	_open: OpenIterator,
+}
+
+impl Iterator for LmdbIterator {
+	type Item = (Vec<u8>, Vec<u8>);
+
+	fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
 		let access = self.tx.access();
-		let kv = if self.seek {
-			Arc::get_mut(&mut self.cursor).unwrap().next(&access)
+		let cursor = Arc::get_mut(&mut self.cursor).unwrap();
+		let kv: Result<(&[u8], &[u8]), lmdb::Error> = if self.seek {
+			cursor.next(&access)
 		} else {
 			self.seek = true;
-			Arc::get_mut(&mut self.cursor)
-				.unwrap()
-				.seek_range_k(&access, &self.prefix[..])
+			if self.from.is_empty() {
+				cursor.first(&access)
+			} else {
+				cursor.seek_range_k(&access, &self.from[..])
+			}
 		};
-		self.deser_if_prefix_match(kv)
+		kv.ok().map(|(k, v)| (k.to_vec(), v.to_vec()))
 	}
 }
 
-impl<T> SerIterator<T>
-where
-	T: ser::Readable,
-{
-	fn deser_if_prefix_match(&self, kv: Result<(&[u8], &[u8]), lmdb::Error>) -> Option<T> {
-		match kv {
-			Ok((k, v)) => {
-				let plen = self.prefix.len();
-				if plen == 0 || k[0..plen] == self.prefix[..] {
-					ser::deserialize(&mut &v[..]).ok()
-				} else {
-					None
-				}
+// Counts an iterator as open by the thread creating it, for as long as it
+// lives (wherever it's dropped)
+struct OpenIterator(Arc<Mutex<HashMap<ThreadId, usize>>>, ThreadId);
+
+impl OpenIterator {
+	fn new(counts: Arc<Mutex<HashMap<ThreadId, usize>>>) -> OpenIterator {
+		let id = thread::current().id();
+		*counts.lock().entry(id).or_insert(0) += 1;
+		OpenIterator(counts, id)
+	}
+}
+
+impl Drop for OpenIterator {
+	fn drop(&mut self) {
+		let mut counts = self.0.lock();
+		let last = match counts.get_mut(&self.1) {
+			Some(count) => {
+				*count -= 1;
+				*count == 0
 			}
-			Err(_) => None,
+			None => false,
+		};
+		if last {
+			counts.remove(&self.1);
 		}
 	}
 }
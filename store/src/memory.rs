@@ -0,0 +1,139 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory backend, nothing is persisted. Mostly useful for tests.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use backend::{KVBackend, KVBatch, RawIterator};
+use lmdb::Error;
+use util::{Mutex, MutexGuard, RwLock};
+
+/// Keeps everything in an ordered map. As with LMDB, batches are written one
+/// at a time.
+pub struct MemBackend {
+	data: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+	writer: Mutex<()>,
+}
+
+impl MemBackend {
+	/// An empty backend
+	pub fn new() -> MemBackend {
+		MemBackend {
+			data: RwLock::new(BTreeMap::new()),
+			writer: Mutex::new(()),
+		}
+	}
+}
+
+impl KVBackend for MemBackend {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		Ok(self.data.read().get(key).cloned())
+	}
+
+	fn exists(&self, key: &[u8]) -> Result<bool, Error> {
+		Ok(self.data.read().contains_key(key))
+	}
+
+	fn iter(&self, from: &[u8]) -> Result<RawIterator, Error> {
+		// a snapshot, as an LMDB read transaction would see
+		let entries: Vec<(Vec<u8>, Vec<u8>)> = self
+			.data
+			.read()
+			.range(from.to_vec()..)
+			.map(|(k, v)| (k.clone(), v.clone()))
+			.collect();
+		Ok(Box::new(entries.into_iter()))
+	}
+
+	fn sync(&self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn copy_to(&self, _dest: &Path) -> Result<(), Error> {
+		Err(Error::Unsupported("copy of an in-memory store".to_owned()))
+	}
+
+	fn batch<'a>(&'a self) -> Result<Box<KVBatch + 'a>, Error> {
+		Ok(Box::new(MemBatch {
+			backend: self,
+			parent: None,
+			writes: RefCell::new(BTreeMap::new()),
+			_writer: Some(self.writer.lock()),
+		}))
+	}
+}
+
+/// Batch of writes kept aside until commit, deletions as `None`
+pub struct MemBatch<'a> {
+	backend: &'a MemBackend,
+	parent: Option<&'a MemBatch<'a>>,
+	writes: RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+	// top level batches hold the writer lock until done
+	_writer: Option<MutexGuard<'a, ()>>,
+}
+
+impl<'a> KVBatch for MemBatch<'a> {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		if let Some(value) = self.writes.borrow().get(key) {
+			return Ok(value.clone());
+		}
+		match self.parent {
+			Some(parent) => parent.get(key),
+			None => self.backend.get(key),
+		}
+	}
+
+	fn put(&self, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
+		self.writes.borrow_mut().insert(key.to_vec(), Some(value));
+		Ok(())
+	}
+
+	fn delete(&self, key: &[u8]) -> Result<(), Error> {
+		if self.get(key)?.is_none() {
+			return Err(Error::NotFoundErr(format!("{:?}", key)));
+		}
+		self.writes.borrow_mut().insert(key.to_vec(), None);
+		Ok(())
+	}
+
+	fn commit(self: Box<Self>) -> Result<(), Error> {
+		let batch = *self;
+		let writes = batch.writes.into_inner();
+		match batch.parent {
+			Some(parent) => parent.writes.borrow_mut().extend(writes),
+			None => {
+				let mut data = batch.backend.data.write();
+				for (k, v) in writes {
+					match v {
+						Some(v) => data.insert(k, v),
+						None => data.remove(&k),
+					};
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn child<'b>(&'b mut self) -> Result<Box<KVBatch + 'b>, Error> {
+		Ok(Box::new(MemBatch {
+			backend: self.backend,
+			parent: Some(&*self),
+			writes: RefCell::new(BTreeMap::new()),
+			_writer: None,
+		}))
+	}
+}
@@ -25,10 +25,14 @@ use leaf_set::LeafSet;
 use prune_list::PruneList;
 use types::{prune_noop, AppendOnlyFile, HashFile};
 
-const PMMR_HASH_FILE: &str = "pmmr_hash.bin";
-const PMMR_DATA_FILE: &str = "pmmr_data.bin";
-const PMMR_LEAF_FILE: &str = "pmmr_leaf.bin";
-const PMMR_PRUN_FILE: &str = "pmmr_prun.bin";
+/// File holding the hashes of the MMR
+pub const PMMR_HASH_FILE: &str = "pmmr_hash.bin";
+/// File holding the data of the MMR leaves
+pub const PMMR_DATA_FILE: &str = "pmmr_data.bin";
+/// File holding the set of unspent leaves
+pub const PMMR_LEAF_FILE: &str = "pmmr_leaf.bin";
+/// File holding the roots of the pruned subtrees
+pub const PMMR_PRUN_FILE: &str = "pmmr_prun.bin";
 
 /// The list of PMMR_Files for internal purposes
 pub const PMMR_FILES: [&str; 4] = [
@@ -0,0 +1,137 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate chrono;
+extern crate grin_store as store;
+
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::prelude::Utc;
+
+fn key(i: u32) -> Vec<u8> {
+	format!("key{:08}", i).into_bytes()
+}
+
+fn write(store: &store::Store, from: u32, count: u32) -> Result<(), store::Error> {
+	let batch = store.batch()?;
+	for i in from..from + count {
+		batch.put(&key(i), vec![(i % 256) as u8; 1024])?;
+	}
+	batch.commit()
+}
+
+#[test]
+fn grow_full_map() {
+	let t = Utc::now();
+	let data_dir = format!(
+		"./target/tmp/{}.{}-lmdb_resize",
+		t.timestamp(),
+		t.timestamp_subsec_nanos(),
+	);
+	store::set_map_growth(1 << 20, 8 << 20);
+	{
+		let env = Arc::new(store::new_env(data_dir.clone()));
+		let store = store::Store::open(env, "test");
+
+		// about 2MB in a single batch, twice the initial map
+		write(&store, 0, 2000).unwrap();
+		assert_eq!(store.get(&key(0)).unwrap(), Some(vec![0; 1024]));
+		assert_eq!(store.get(&key(1999)).unwrap(), Some(vec![207; 1024]));
+
+		// way past the maximum, the batch fails and nothing is written
+		assert!(write(&store, 2000, 8000).is_err());
+		assert_eq!(store.get(&key(2000)).unwrap(), None);
+
+		// what still fits can be written
+		write(&store, 2000, 100).unwrap();
+		assert!(store.exists(&key(2099)).unwrap());
+	}
+	fs::remove_dir_all(data_dir).unwrap();
+}
+
+// Writes through a child batch are replayed along with the parent's when the
+// map fills up, in the child or in the parent once the child is committed
+#[test]
+fn grow_full_map_child_batch() {
+	let t = Utc::now();
+	let data_dir = format!(
+		"./target/tmp/{}.{}-lmdb_resize_child",
+		t.timestamp(),
+		t.timestamp_subsec_nanos(),
+	);
+	store::set_map_growth(1 << 20, 8 << 20);
+	{
+		let env = Arc::new(store::new_env(data_dir.clone()));
+		let store = store::Store::open(env, "test");
+
+		let mut batch = store.batch().unwrap();
+		{
+			// about 1.5MB, filling the initial map in the child
+			let child = batch.child().unwrap();
+			for i in 0..1500 {
+				child.put(&key(i), vec![(i % 256) as u8; 1024]).unwrap();
+			}
+			child.commit().unwrap();
+		}
+		{
+			// abandoned, nothing of it is written
+			let child = batch.child().unwrap();
+			child.put(&key(9000), vec![0; 1024]).unwrap();
+		}
+		// and filling it again in the parent
+		for i in 1500..2500 {
+			batch.put(&key(i), vec![(i % 256) as u8; 1024]).unwrap();
+		}
+		batch.commit().unwrap();
+
+		assert_eq!(store.get(&key(0)).unwrap(), Some(vec![0; 1024]));
+		assert_eq!(store.get(&key(1499)).unwrap(), Some(vec![219; 1024]));
+		assert_eq!(store.get(&key(2499)).unwrap(), Some(vec![195; 1024]));
+		assert_eq!(store.get(&key(9000)).unwrap(), None);
+	}
+	fs::remove_dir_all(data_dir).unwrap();
+}
+
+// A batch filling the map while its own thread holds an iterator can't wait
+// for it to be dropped, it fails right away
+#[test]
+fn full_map_own_iterator() {
+	let t = Utc::now();
+	let data_dir = format!(
+		"./target/tmp/{}.{}-lmdb_resize_iter",
+		t.timestamp(),
+		t.timestamp_subsec_nanos(),
+	);
+	store::set_map_growth(1 << 20, 8 << 20);
+	{
+		let env = Arc::new(store::new_env(data_dir.clone()));
+		let store = store::Store::open(env, "test");
+		write(&store, 0, 10).unwrap();
+
+		let start = Instant::now();
+		{
+			let _iter = store.iter::<u64>(&key(0)).unwrap();
+			assert!(write(&store, 10, 2000).is_err());
+		}
+		assert!(start.elapsed() < Duration::from_secs(10));
+		assert_eq!(store.get(&key(10)).unwrap(), None);
+
+		// once dropped, the map grows again
+		write(&store, 10, 2000).unwrap();
+		assert!(store.exists(&key(2009)).unwrap());
+	}
+	fs::remove_dir_all(data_dir).unwrap();
+}
@@ -0,0 +1,134 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_store as store;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use store::{MemBackend, Store};
+
+#[test]
+fn mem_batches() {
+	let store = Store::with_backend(Box::new(MemBackend::new()));
+
+	{
+		let batch = store.batch().unwrap();
+		batch.put(b"a:1", vec![1]).unwrap();
+		batch.put(b"a:2", vec![2]).unwrap();
+		batch.put(b"b:1", vec![3]).unwrap();
+		// nothing visible outside the batch until committed
+		assert_eq!(store.get(b"a:1").unwrap(), None);
+		batch.commit().unwrap();
+	}
+	assert_eq!(store.get(b"a:1").unwrap(), Some(vec![1]));
+	assert_eq!(
+		store.raw_entries(b"a:").unwrap(),
+		vec![(b"a:1".to_vec(), vec![1]), (b"a:2".to_vec(), vec![2])]
+	);
+
+	// abandoned batch
+	{
+		let batch = store.batch().unwrap();
+		batch.delete(b"a:1").unwrap();
+		assert!(batch.delete(b"a:3").is_err());
+	}
+	assert!(store.exists(b"a:1").unwrap());
+
+	// child merged into its parent on commit only
+	{
+		let mut batch = store.batch().unwrap();
+		{
+			let child = batch.child().unwrap();
+			child.delete(b"a:1").unwrap();
+			child.commit().unwrap();
+		}
+		{
+			let child = batch.child().unwrap();
+			child.put(b"a:3", vec![4]).unwrap();
+		}
+		batch.commit().unwrap();
+	}
+	assert_eq!(
+		store.raw_entries(b"a:").unwrap(),
+		vec![(b"a:2".to_vec(), vec![2])]
+	);
+	assert_eq!(store.raw_entries(&[]).unwrap().len(), 2);
+}
+
+#[test]
+fn commit_hooks() {
+	let store = Store::with_backend(Box::new(MemBackend::new()));
+	let count = Rc::new(Cell::new(0));
+
+	// abandoned, the hook never runs
+	{
+		let batch = store.batch().unwrap();
+		let c = count.clone();
+		batch.on_commit(move || c.set(c.get() + 1));
+	}
+	assert_eq!(count.get(), 0);
+
+	// a committed child hook waits for its parent
+	{
+		let mut batch = store.batch().unwrap();
+		{
+			let child = batch.child().unwrap();
+			let c = count.clone();
+			child.on_commit(move || c.set(c.get() + 1));
+			child.commit().unwrap();
+		}
+		assert_eq!(count.get(), 0);
+		batch.commit().unwrap();
+	}
+	assert_eq!(count.get(), 1);
+
+	// all or nothing
+	let res: Result<(), store::Error> = store.atomic(|batch| {
+		batch.put(b"a", vec![1])?;
+		batch.delete(b"b")
+	});
+	assert!(res.is_err());
+	assert!(!store.exists(b"a").unwrap());
+	store.atomic(|batch| batch.put(b"a", vec![1])).unwrap();
+	assert!(store.exists(b"a").unwrap());
+}
+
+#[test]
+fn prefix_pages() {
+	let store = Store::with_backend(Box::new(MemBackend::new()));
+	store
+		.atomic(|batch| {
+			for i in 0..5u64 {
+				batch.put_ser(format!("a:{}", i).as_bytes(), &i)?;
+			}
+			batch.put_ser(b"b:0", &10u64)
+		}).unwrap();
+
+	let page = store.page::<u64>(b"a:", None, 2).unwrap();
+	assert_eq!(page.items, vec![0, 1]);
+	assert_eq!(page.next, Some(b"2".to_vec()));
+	let next = page.next.unwrap();
+	let page = store.page::<u64>(b"a:", Some(&next), 2).unwrap();
+	assert_eq!(page.items, vec![2, 3]);
+	// the last page stops at the prefix end
+	let next = page.next.unwrap();
+	let page = store.page::<u64>(b"a:", Some(&next), 2).unwrap();
+	assert_eq!(page.items, vec![4]);
+	assert_eq!(page.next, None);
+
+	let page = store.page::<u64>(b"a:", None, 5).unwrap();
+	assert_eq!(page.items.len(), 5);
+	assert_eq!(page.next, None);
+}
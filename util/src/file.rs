@@ -58,6 +58,18 @@ pub fn list_files(path: String) -> Vec<String> {
 	return files_vec;
 }
 
+/// Total size of the files under a directory, in bytes
+pub fn dir_size(path: &Path) -> io::Result<u64> {
+	let mut size = 0;
+	for entry in WalkDir::new(path) {
+		let entry = entry?;
+		if entry.file_type().is_file() {
+			size += entry.metadata()?.len();
+		}
+	}
+	Ok(size)
+}
+
 fn copy_to(src: &Path, src_type: &fs::FileType, dst: &Path) -> io::Result<u64> {
 	if src_type.is_file() {
 		fs::copy(src, dst)
@@ -24,6 +24,7 @@
 extern crate backtrace;
 extern crate base64;
 extern crate byteorder;
+extern crate chrono;
 extern crate rand;
 #[macro_use]
 extern crate log;
@@ -34,26 +35,31 @@ extern crate lazy_static;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate walkdir;
 extern crate zip as zip_rs;
 // Re-export so only has to be included once
 extern crate parking_lot;
 pub use parking_lot::Mutex;
+pub use parking_lot::MutexGuard;
 pub use parking_lot::RwLock;
+pub use parking_lot::RwLockReadGuard;
 
 // Re-export so only has to be included once
 pub extern crate secp256k1zkp as secp;
 
 // Logging related
 pub mod logger;
-pub use logger::{init_logger, init_test_logger};
+pub use logger::{
+	init_logger, init_test_logger, log_fields, recent_logs, update_log_levels, LogEntry, LogFields,
+};
 
 // Static secp instance
 pub mod secp_static;
 pub use secp_static::static_secp_instance;
 
 pub mod types;
-pub use types::{LogLevel, LoggingConfig};
+pub use types::{LogFormat, LogLevel, LoggingConfig};
 
 pub mod macros;
 
@@ -72,7 +78,19 @@ pub mod file;
 pub mod zip;
 
 mod rate_counter;
-pub use rate_counter::RateCounter;
+pub use rate_counter::{RateCounter, RATE_HISTORY_SECS};
+
+/// Resources used by the process
+pub mod resources;
+pub use resources::{process_limits, process_usage, ProcessLimits, ProcessUsage};
+
+/// Soft limit on the memory held by orphan pools and caches
+pub mod memory;
+pub use memory::{MemoryBudget, MemoryConsumer};
+
+/// Wiping of secrets from memory
+pub mod zeroize;
+pub use zeroize::{Zeroing, Zeroize};
 
 /// Encapsulation of a RwLock<Option<T>> for one-time initialization.
 /// This implementation will purposefully fail hard if not used
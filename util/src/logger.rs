@@ -16,22 +16,30 @@ use std::ops::Deref;
 use Mutex;
 
 use backtrace::Backtrace;
-use std::{panic, thread};
-
-use types::{LogLevel, LoggingConfig};
-
-use log::{LevelFilter, Record};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, SystemTime};
+use std::{cmp, panic, thread};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use types::{LogFormat, LogLevel, LoggingConfig, DEFAULT_LOG_MAX_FILES};
+
+use log::{Level, LevelFilter, Record};
 use log4rs;
 use log4rs::append::console::ConsoleAppender;
 use log4rs::append::file::FileAppender;
 use log4rs::append::rolling_file::{
-	policy::compound::roll::fixed_window::FixedWindowRoller,
-	policy::compound::trigger::size::SizeTrigger, policy::compound::CompoundPolicy,
-	RollingFileAppender,
+	policy::compound::roll::fixed_window::FixedWindowRoller, policy::compound::trigger::Trigger,
+	policy::compound::CompoundPolicy, LogFile, RollingFileAppender,
 };
 use log4rs::append::Append;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::{self, Encode};
 use log4rs::filter::{threshold::ThresholdFilter, Filter, Response};
 
 fn convert_log_level(in_level: &LogLevel) -> LevelFilter {
@@ -52,10 +60,203 @@ lazy_static! {
 	static ref TUI_RUNNING: Mutex<bool> = Mutex::new(false);
 	/// Static Logging configuration, should only be set once, before first logging call
 	static ref LOGGING_CONFIG: Mutex<LoggingConfig> = Mutex::new(LoggingConfig::default());
+	/// Handle to the logger set up by init_logger, to change its levels later on
+	static ref LOG_HANDLE: Mutex<Option<log4rs::Handle>> = Mutex::new(None);
+	/// Most recent log lines, kept for the tui while it's running
+	static ref LOG_BUFFER: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
 }
 
 const LOGGING_PATTERN: &str = "{d(%Y%m%d %H:%M:%S%.3f)} {h({l})} {M} - {m}{n}";
 
+// Same as the above, without colors or line ending
+const BUFFER_PATTERN: &str = "{d(%Y%m%d %H:%M:%S%.3f)} {l} {M} - {m}";
+
+/// Number of log lines kept in memory for the tui
+pub const LOG_BUFFER_SIZE: usize = 2000;
+
+/// A log line kept in memory
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+	/// Level the line was logged at
+	pub level: LogLevel,
+	/// The formatted line
+	pub line: String,
+}
+
+/// The most recent log lines, oldest first. Only kept while the tui is
+/// running, as it has no other way to show them.
+pub fn recent_logs() -> Vec<LogEntry> {
+	LOG_BUFFER.lock().iter().cloned().collect()
+}
+
+/// Keeps the last lines logged in LOG_BUFFER
+#[derive(Debug)]
+struct BufferAppender {
+	encoder: PatternEncoder,
+}
+
+impl Append for BufferAppender {
+	fn append(&self, record: &Record) -> Result<(), Box<Error + Sync + Send>> {
+		let mut line = SimpleWriter(vec![]);
+		self.encoder.encode(&mut line, record)?;
+		let entry = LogEntry {
+			level: match record.level() {
+				Level::Error => LogLevel::Error,
+				Level::Warn => LogLevel::Warning,
+				Level::Info => LogLevel::Info,
+				Level::Debug => LogLevel::Debug,
+				Level::Trace => LogLevel::Trace,
+			},
+			line: String::from_utf8_lossy(&line.0).into_owned(),
+		};
+		let mut buffer = LOG_BUFFER.lock();
+		buffer.push_back(entry);
+		while buffer.len() > LOG_BUFFER_SIZE {
+			buffer.pop_front();
+		}
+		Ok(())
+	}
+
+	fn flush(&self) {}
+}
+
+thread_local! {
+	/// Structured fields of the lines logged by this thread, see `log_fields`
+	static LOG_FIELDS: RefCell<Vec<(&'static str, String)>> = RefCell::new(vec![]);
+}
+
+/// Attaches structured fields, like the address of the peer or the hash and
+/// height of the block being processed, to everything the current thread
+/// logs until the returned guard is dropped. Only the JSON format outputs
+/// them, text lines stay as they are.
+pub fn log_fields(fields: Vec<(&'static str, String)>) -> LogFields {
+	let count = fields.len();
+	LOG_FIELDS.with(|f| f.borrow_mut().extend(fields));
+	LogFields { count }
+}
+
+/// Removes the fields set by `log_fields` when dropped
+#[must_use]
+pub struct LogFields {
+	count: usize,
+}
+
+impl Drop for LogFields {
+	fn drop(&mut self) {
+		LOG_FIELDS.with(|f| {
+			let mut f = f.borrow_mut();
+			let len = f.len();
+			f.truncate(len.saturating_sub(self.count));
+		});
+	}
+}
+
+/// Writes each record as a JSON object on its own line, with timestamp,
+/// level, target, message and the fields set by `log_fields`
+#[derive(Debug)]
+struct JsonEncoder;
+
+impl Encode for JsonEncoder {
+	fn encode(
+		&self,
+		w: &mut encode::Write,
+		record: &Record,
+	) -> Result<(), Box<Error + Sync + Send>> {
+		let mut line = json_line(record);
+		line.push('\n');
+		w.write_all(line.as_bytes())?;
+		Ok(())
+	}
+}
+
+fn json_line(record: &Record) -> String {
+	let mut line = Map::new();
+	line.insert(
+		"timestamp".to_owned(),
+		Value::String(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+	);
+	line.insert(
+		"level".to_owned(),
+		Value::String(record.level().to_string()),
+	);
+	line.insert(
+		"target".to_owned(),
+		Value::String(record.target().to_owned()),
+	);
+	line.insert(
+		"message".to_owned(),
+		Value::String(format!("{}", record.args())),
+	);
+	let fields = LOG_FIELDS.with(|f| {
+		f.borrow()
+			.iter()
+			.map(|(k, v)| (k.to_string(), Value::String(v.clone())))
+			.collect::<Map<String, Value>>()
+	});
+	if !fields.is_empty() {
+		line.insert("fields".to_owned(), Value::Object(fields));
+	}
+	Value::Object(line).to_string()
+}
+
+/// Rolls the log file over once it reaches the maximum size or gets older
+/// than the maximum age, whichever comes first
+#[derive(Debug)]
+struct RotationTrigger {
+	max_size: Option<u64>,
+	max_age: Option<Duration>,
+	// when the current log file was started
+	started: Mutex<SystemTime>,
+}
+
+impl RotationTrigger {
+	fn new(
+		path: &str,
+		append: bool,
+		max_size: Option<u64>,
+		max_age: Option<Duration>,
+	) -> RotationTrigger {
+		// an existing file we keep appending to is as old as its creation
+		let started = match fs::metadata(path).and_then(|m| m.created()) {
+			Ok(created) if append => created,
+			_ => SystemTime::now(),
+		};
+		RotationTrigger {
+			max_size,
+			max_age,
+			started: Mutex::new(started),
+		}
+	}
+}
+
+impl Trigger for RotationTrigger {
+	fn trigger(&self, file: &LogFile) -> Result<bool, Box<Error + Sync + Send>> {
+		if let Some(max_size) = self.max_size {
+			if file.len() > max_size {
+				*self.started.lock() = SystemTime::now();
+				return Ok(true);
+			}
+		}
+		if let Some(max_age) = self.max_age {
+			let mut started = self.started.lock();
+			// a clock going backward doesn't age the file
+			let age = started.elapsed().unwrap_or(Duration::from_secs(0));
+			if age >= max_age && file.len() > 0 {
+				*started = SystemTime::now();
+				return Ok(true);
+			}
+		}
+		Ok(false)
+	}
+}
+
+fn encoder(format: &LogFormat) -> Box<Encode> {
+	match *format {
+		LogFormat::Text => Box::new(PatternEncoder::new(&LOGGING_PATTERN)),
+		LogFormat::Json => Box::new(JsonEncoder),
+	}
+}
+
 /// This filter is rejecting messages that doesn't start with "grin"
 /// in order to save log space for only Grin-related records
 #[derive(Debug)]
@@ -83,99 +284,149 @@ pub fn init_logger(config: Option<LoggingConfig>) {
 		}
 
 		// Save current logging configuration
-		let mut config_ref = LOGGING_CONFIG.lock();
-		*config_ref = c.clone();
+		{
+			let mut config_ref = LOGGING_CONFIG.lock();
+			*config_ref = c.clone();
+		}
 
 		let level_stdout = convert_log_level(&c.stdout_log_level);
 		let level_file = convert_log_level(&c.file_log_level);
-		let level_minimum;
+		let config = build_config(c, tui_running);
 
-		// Determine minimum logging level for Root logger
-		if level_stdout > level_file {
-			level_minimum = level_stdout;
-		} else {
-			level_minimum = level_file;
-		}
+		let handle = log4rs::init_config(config).unwrap();
+		*LOG_HANDLE.lock() = Some(handle);
+
+		info!(
+			"log4rs is initialized, file level: {:?}, stdout level: {:?}, min. level: {:?}",
+			level_file,
+			level_stdout,
+			cmp::max(level_stdout, level_file)
+		);
 
-		// Start logger
-		let stdout = ConsoleAppender::builder()
-			.encoder(Box::new(PatternEncoder::new(&LOGGING_PATTERN)))
-			.build();
+		// Mark logger as initialized
+		let mut was_init_ref = WAS_INIT.lock();
+		*was_init_ref = true;
+	}
 
-		let mut root = Root::builder();
+	send_panic_to_log();
+}
 
-		let mut appenders = vec![];
+/// Changes the stdout and file log levels of the logger initialized by
+/// `init_logger`, as when reloading the configuration. Returns false if
+/// there's no such logger.
+pub fn update_log_levels(stdout_log_level: LogLevel, file_log_level: LogLevel) -> bool {
+	let handle = LOG_HANDLE.lock();
+	let handle = match *handle {
+		Some(ref h) => h,
+		None => return false,
+	};
+	let mut config_ref = LOGGING_CONFIG.lock();
+	config_ref.stdout_log_level = stdout_log_level;
+	config_ref.file_log_level = file_log_level;
+
+	// keep on writing to the same log file
+	let mut c = config_ref.clone();
+	c.log_file_append = true;
+	let tui_running = *TUI_RUNNING.lock();
+	handle.set_config(build_config(c, tui_running));
+	info!(
+		"log levels updated, file level: {:?}, stdout level: {:?}",
+		config_ref.file_log_level, config_ref.stdout_log_level
+	);
+	true
+}
 
-		if c.log_to_stdout && !tui_running {
-			let filter = Box::new(ThresholdFilter::new(level_stdout));
-			appenders.push(
-				Appender::builder()
-					.filter(filter)
-					.filter(Box::new(GrinFilter))
-					.build("stdout", Box::new(stdout)),
-			);
+// The log4rs configuration out of our own
+fn build_config(c: LoggingConfig, tui_running: bool) -> Config {
+	let level_stdout = convert_log_level(&c.stdout_log_level);
+	let level_file = convert_log_level(&c.file_log_level);
 
-			root = root.appender("stdout");
-		}
+	// Determine minimum logging level for Root logger
+	let level_minimum = cmp::max(level_stdout, level_file);
 
-		if c.log_to_file {
-			// If maximum log size is specified, use rolling file appender
-			// or use basic one otherwise
-			let filter = Box::new(ThresholdFilter::new(level_file));
-			let file: Box<Append> = {
-				if let Some(size) = c.log_max_size {
-					let roller = FixedWindowRoller::builder()
-						.build(&format!("{}.{{}}.gz", c.log_file_path), 32)
-						.unwrap();
-					let trigger = SizeTrigger::new(size);
-
-					let policy = CompoundPolicy::new(Box::new(trigger), Box::new(roller));
-
-					Box::new(
-						RollingFileAppender::builder()
-							.append(c.log_file_append)
-							.encoder(Box::new(PatternEncoder::new(&LOGGING_PATTERN)))
-							.build(c.log_file_path, Box::new(policy))
-							.unwrap(),
-					)
-				} else {
-					Box::new(
-						FileAppender::builder()
-							.append(c.log_file_append)
-							.encoder(Box::new(PatternEncoder::new(&LOGGING_PATTERN)))
-							.build(c.log_file_path)
-							.unwrap(),
-					)
-				}
-			};
-
-			appenders.push(
-				Appender::builder()
-					.filter(filter)
-					.filter(Box::new(GrinFilter))
-					.build("file", file),
-			);
-			root = root.appender("file");
-		}
+	let stdout = ConsoleAppender::builder()
+		.encoder(encoder(&c.log_format))
+		.build();
 
-		let config = Config::builder()
-			.appenders(appenders)
-			.build(root.build(level_minimum))
-			.unwrap();
+	let mut root = Root::builder();
 
-		let _ = log4rs::init_config(config).unwrap();
+	let mut appenders = vec![];
 
-		info!(
-			"log4rs is initialized, file level: {:?}, stdout level: {:?}, min. level: {:?}",
-			level_file, level_stdout, level_minimum
+	if c.log_to_stdout && !tui_running {
+		let filter = Box::new(ThresholdFilter::new(level_stdout));
+		appenders.push(
+			Appender::builder()
+				.filter(filter)
+				.filter(Box::new(GrinFilter))
+				.build("stdout", Box::new(stdout)),
 		);
 
-		// Mark logger as initialized
-		let mut was_init_ref = WAS_INIT.lock();
-		*was_init_ref = true;
+		root = root.appender("stdout");
 	}
 
-	send_panic_to_log();
+	if tui_running {
+		// keep the lines the tui shows, up to the most detailed level logged
+		appenders.push(Appender::builder().filter(Box::new(GrinFilter)).build(
+			"buffer",
+			Box::new(BufferAppender {
+				encoder: PatternEncoder::new(&BUFFER_PATTERN),
+			}),
+		));
+		root = root.appender("buffer");
+	}
+
+	if c.log_to_file {
+		// If maximum log size or age is specified, use rolling file appender
+		// or use basic one otherwise
+		let filter = Box::new(ThresholdFilter::new(level_file));
+		let file: Box<Append> = {
+			if c.log_max_size.is_some() || c.log_max_age_hours.is_some() {
+				let roller = FixedWindowRoller::builder()
+					.build(
+						&format!("{}.{{}}.gz", c.log_file_path),
+						c.log_max_files.unwrap_or(DEFAULT_LOG_MAX_FILES),
+					)
+					.unwrap();
+				let trigger = RotationTrigger::new(
+					&c.log_file_path,
+					c.log_file_append,
+					c.log_max_size,
+					c.log_max_age_hours.map(|h| Duration::from_secs(h * 3600)),
+				);
+
+				let policy = CompoundPolicy::new(Box::new(trigger), Box::new(roller));
+
+				Box::new(
+					RollingFileAppender::builder()
+						.append(c.log_file_append)
+						.encoder(encoder(&c.log_format))
+						.build(c.log_file_path, Box::new(policy))
+						.unwrap(),
+				)
+			} else {
+				Box::new(
+					FileAppender::builder()
+						.append(c.log_file_append)
+						.encoder(encoder(&c.log_format))
+						.build(c.log_file_path)
+						.unwrap(),
+				)
+			}
+		};
+
+		appenders.push(
+			Appender::builder()
+				.filter(filter)
+				.filter(Box::new(GrinFilter))
+				.build("file", file),
+		);
+		root = root.appender("file");
+	}
+
+	Config::builder()
+		.appenders(appenders)
+		.build(root.build(level_minimum))
+		.unwrap()
 }
 
 /// Initializes the logger for unit and integration tests
@@ -272,3 +523,46 @@ fn send_panic_to_log() {
 		}
 	}));
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use log::Level;
+	use serde_json;
+
+	fn line(message: &str) -> Value {
+		// format_args! temporaries only live until the end of the statement
+		let json = json_line(
+			&Record::builder()
+				.args(format_args!("{}", message))
+				.level(Level::Info)
+				.target("grin_servers::common::adapters")
+				.build(),
+		);
+		serde_json::from_str(&json).unwrap()
+	}
+
+	#[test]
+	fn json_line_with_fields() {
+		let l = line("no fields");
+		assert_eq!(l["level"], "INFO");
+		assert_eq!(l["target"], "grin_servers::common::adapters");
+		assert_eq!(l["message"], "no fields");
+		assert!(l["timestamp"].is_string());
+		assert!(l.get("fields").is_none());
+
+		{
+			let _peer = log_fields(vec![("peer", "10.0.0.1:3414".to_owned())]);
+			{
+				let _block = log_fields(vec![("height", "12".to_owned())]);
+				let l = line("block received");
+				assert_eq!(l["fields"]["peer"], "10.0.0.1:3414");
+				assert_eq!(l["fields"]["height"], "12");
+			}
+			let l = line("block processed");
+			assert_eq!(l["fields"]["peer"], "10.0.0.1:3414");
+			assert!(l["fields"].get("height").is_none());
+		}
+		assert!(line("done").get("fields").is_none());
+	}
+}
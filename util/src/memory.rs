@@ -0,0 +1,131 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Soft limit on the memory held by the orphan pools and caches of the node.
+//! Each of them registers with a budget shared across the node and, when what
+//! they hold together goes over its cap, is asked to shrink back to its share
+//! of the cap, in proportion to what it holds. The one being flooded, by
+//! large orphan blocks for example, gives up most.
+
+use std::sync::Weak;
+
+use {Mutex, RwLock};
+
+/// An orphan pool or cache whose memory is accounted for in a budget
+pub trait MemoryConsumer: Send + Sync {
+	/// Name of the consumer, for logging
+	fn name(&self) -> &'static str;
+
+	/// Rough estimate of the memory held, in bytes
+	fn memory_used(&self) -> usize;
+
+	/// Evicts entries until no more than `target` bytes are held, returning
+	/// how many bytes were freed
+	fn shrink_to(&self, target: usize) -> usize;
+}
+
+/// Memory budget shared by the orphan pools and caches of the node
+pub struct MemoryBudget {
+	cap: usize,
+	consumers: RwLock<Vec<Weak<MemoryConsumer>>>,
+	// held while enforcing, so concurrent inserts don't all evict at once
+	enforcing: Mutex<()>,
+}
+
+impl MemoryBudget {
+	/// Budget capped at `cap` bytes, 0 for no cap
+	pub fn new(cap: usize) -> MemoryBudget {
+		MemoryBudget {
+			cap,
+			consumers: RwLock::new(vec![]),
+			enforcing: Mutex::new(()),
+		}
+	}
+
+	/// Budget that never evicts, each consumer keeping its own limits only
+	pub fn unlimited() -> MemoryBudget {
+		MemoryBudget::new(0)
+	}
+
+	/// The cap, in bytes, 0 if none
+	pub fn cap(&self) -> usize {
+		self.cap
+	}
+
+	/// Accounts for the memory of a consumer from now on, for as long as it
+	/// lives
+	pub fn register<C: MemoryConsumer + 'static>(&self, consumer: Weak<C>) {
+		let mut consumers = self.consumers.write();
+		consumers.retain(|c| c.upgrade().is_some());
+		consumers.push(consumer as Weak<MemoryConsumer>);
+	}
+
+	/// Memory held by each consumer, in bytes
+	pub fn usage(&self) -> Vec<(&'static str, usize)> {
+		self.consumers
+			.read()
+			.iter()
+			.filter_map(|c| c.upgrade())
+			.map(|c| (c.name(), c.memory_used()))
+			.collect()
+	}
+
+	/// Memory held by all consumers, in bytes
+	pub fn used(&self) -> usize {
+		self.usage().iter().map(|&(_, used)| used).sum()
+	}
+
+	/// Brings the consumers back under the cap if they went over it, each
+	/// shrinking in proportion to what it holds. To be called by consumers
+	/// after they grew, not holding any of their locks. Returns how many
+	/// bytes were freed.
+	pub fn enforce(&self) -> usize {
+		if self.cap == 0 {
+			return 0;
+		}
+		let _enforcing = match self.enforcing.try_lock() {
+			Some(guard) => guard,
+			// already being brought back under the cap
+			None => return 0,
+		};
+
+		let consumers = self
+			.consumers
+			.read()
+			.iter()
+			.filter_map(|c| c.upgrade())
+			.collect::<Vec<_>>();
+		let usage = consumers
+			.iter()
+			.map(|c| c.memory_used())
+			.collect::<Vec<_>>();
+		let total: usize = usage.iter().sum();
+		if total <= self.cap {
+			return 0;
+		}
+
+		let mut freed = 0;
+		for (c, used) in consumers.iter().zip(usage) {
+			let target = (used as u128 * self.cap as u128 / total as u128) as usize;
+			if target < used {
+				freed += c.shrink_to(target);
+			}
+		}
+		info!(
+			"Memory budget: {} bytes held over the {} bytes cap, freed {}",
+			total, self.cap, freed
+		);
+		freed
+	}
+}
@@ -13,54 +13,176 @@
 // limitations under the License.
 
 /// Utility to track the rate of data transfers
+use std::cmp;
 use std::time::{Duration, SystemTime};
 
-/// A rate counter tracks the number of transfers, the amount of data
-/// exchanged and the rate of transfer (via a few timers) over the last
-/// minute. The counter does not try to be accurate and update times
-/// proactively, instead it only does so lazily. As a result, produced
-/// rates are worst-case estimates.
+/// How many seconds back a rate counter goes by default
+pub const RATE_HISTORY_SECS: u64 = 600;
+
+/// Transfers counted within a second
+#[derive(Clone, Copy, Debug, Default)]
+struct Bucket {
+	/// Second since epoch the bucket is for
+	sec: u64,
+	bytes: u64,
+	count: u64,
+}
+
+/// A rate counter tracks the number of transfers and the amount of data
+/// exchanged, second by second, over a sliding window of the last minutes.
+/// Rates can be had over any window within it, short (the last seconds) or
+/// long (the last minutes). Counts of the current second are included as
+/// they are, so it doesn't look idle until the second is over.
 pub struct RateCounter {
-	last_min_bytes: Vec<u64>,
-	last_min_times: Vec<u64>,
+	// ring of per second buckets, a bucket is reused once its second falls
+	// out of the window
+	buckets: Vec<Bucket>,
 }
 
 impl RateCounter {
-	/// Instantiate a new rate counter
+	/// Instantiate a new rate counter, covering the default history
 	pub fn new() -> RateCounter {
+		RateCounter::with_history(RATE_HISTORY_SECS)
+	}
+
+	/// Instantiate a new rate counter going the provided number of seconds
+	/// back
+	pub fn with_history(secs: u64) -> RateCounter {
 		RateCounter {
-			last_min_bytes: vec![],
-			last_min_times: vec![],
+			buckets: vec![Bucket::default(); cmp::max(secs, 1) as usize],
 		}
 	}
 
+	/// How many seconds back the counter goes
+	pub fn history_secs(&self) -> u64 {
+		self.buckets.len() as u64
+	}
+
 	/// Increments number of bytes transferred, updating counts and rates.
 	pub fn inc(&mut self, bytes: u64) {
-		let now_millis = millis_since_epoch();
-		self.last_min_times.push(now_millis);
-		self.last_min_bytes.push(bytes);
-		while self.last_min_times.len() > 0 && self.last_min_times[0] + 60000 < now_millis {
-			self.last_min_times.remove(0);
-			self.last_min_bytes.remove(0);
+		self.inc_at(secs_since_epoch(), bytes);
+	}
+
+	fn inc_at(&mut self, now: u64, bytes: u64) {
+		let len = self.buckets.len() as u64;
+		let bucket = &mut self.buckets[(now % len) as usize];
+		if bucket.sec != now {
+			*bucket = Bucket {
+				sec: now,
+				bytes: 0,
+				count: 0,
+			};
 		}
+		bucket.bytes += bytes;
+		bucket.count += 1;
+	}
+
+	/// The buckets of the last `secs` seconds, oldest first, `None` for
+	/// seconds nothing was counted in
+	fn window_at(&self, now: u64, secs: u64) -> Vec<Option<Bucket>> {
+		let len = self.buckets.len() as u64;
+		let secs = cmp::min(secs, len);
+		(0..secs)
+			.rev()
+			.map(|ago| {
+				let sec = now.saturating_sub(ago);
+				let bucket = self.buckets[(sec % len) as usize];
+				if bucket.sec == sec && bucket.count > 0 {
+					Some(bucket)
+				} else {
+					None
+				}
+			}).collect()
+	}
+
+	/// Number of bytes counted over the last `secs` seconds
+	pub fn bytes_in(&self, secs: u64) -> u64 {
+		self.window_at(secs_since_epoch(), secs)
+			.iter()
+			.filter_map(|b| b.map(|b| b.bytes))
+			.sum()
+	}
+
+	/// Count of increases over the last `secs` seconds
+	pub fn count_in(&self, secs: u64) -> u64 {
+		self.window_at(secs_since_epoch(), secs)
+			.iter()
+			.filter_map(|b| b.map(|b| b.count))
+			.sum()
+	}
+
+	/// Average bytes per second over the last `secs` seconds, like 1, 10 or
+	/// 300 for short to long term rates
+	pub fn bytes_rate(&self, secs: u64) -> f64 {
+		let secs = cmp::max(cmp::min(secs, self.history_secs()), 1);
+		self.bytes_in(secs) as f64 / secs as f64
+	}
+
+	/// Average count of increases per second over the last `secs` seconds
+	pub fn count_rate(&self, secs: u64) -> f64 {
+		let secs = cmp::max(cmp::min(secs, self.history_secs()), 1);
+		self.count_in(secs) as f64 / secs as f64
 	}
 
 	/// Number of bytes counted in the last minute
 	pub fn bytes_per_min(&self) -> u64 {
-		self.last_min_bytes.iter().sum()
+		self.bytes_in(60)
 	}
 
 	/// Count of increases in the last minute
 	pub fn count_per_min(&self) -> u64 {
-		self.last_min_bytes.len() as u64
+		self.count_in(60)
+	}
+
+	/// Number of bytes counted in each of the last `secs` seconds, oldest
+	/// first, going back as far as the counter does at most
+	pub fn bytes_per_sec(&self, secs: u64) -> Vec<u64> {
+		self.window_at(secs_since_epoch(), secs)
+			.iter()
+			.map(|b| b.map(|b| b.bytes).unwrap_or(0))
+			.collect()
 	}
 }
 
-// turns out getting the millisecs since epoch in Rust isn't as easy as it
-// could be
-fn millis_since_epoch() -> u64 {
-	let since_epoch = SystemTime::now()
+fn secs_since_epoch() -> u64 {
+	SystemTime::now()
 		.duration_since(SystemTime::UNIX_EPOCH)
-		.unwrap_or(Duration::new(0, 0));
-	since_epoch.as_secs() * 1000 + since_epoch.subsec_millis() as u64
+		.unwrap_or(Duration::new(0, 0))
+		.as_secs()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn sliding_window() {
+		let mut rc = RateCounter::with_history(300);
+		let start = 1_000_000;
+		// 10 bytes a second for 5 minutes, 100 a second for the last 10s
+		for sec in start..start + 300 {
+			let bytes = if sec >= start + 290 { 100 } else { 10 };
+			rc.inc_at(sec, bytes);
+		}
+		let now = start + 299;
+		{
+			let sum = |secs| -> u64 {
+				rc.window_at(now, secs)
+					.iter()
+					.filter_map(|b| b.map(|b| b.bytes))
+					.sum()
+			};
+			assert_eq!(sum(1), 100);
+			assert_eq!(sum(10), 1000);
+			assert_eq!(sum(300), 290 * 10 + 1000);
+		}
+		// no further back than the history
+		assert_eq!(rc.window_at(now, 600).len(), 300);
+
+		// older seconds drop out as buckets get reused
+		rc.inc_at(now + 5, 1);
+		let window = rc.window_at(now + 5, 300);
+		assert_eq!(window.iter().filter(|b| b.is_some()).count(), 296);
+		assert!(rc.window_at(now + 5, 5).iter().take(4).all(|b| b.is_none()));
+	}
 }
@@ -0,0 +1,110 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resources used by the running process: memory, open file descriptors and
+//! threads, and the limits the OS puts on them. Read from `/proc` on Linux,
+//! unknown elsewhere.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+
+/// A sample of the resources used by the process, `None` for what can't be
+/// told on this platform
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ProcessUsage {
+	/// Resident memory, in bytes
+	pub memory_bytes: Option<u64>,
+	/// Virtual memory, in bytes
+	pub virtual_memory_bytes: Option<u64>,
+	/// Number of open file descriptors, sockets included
+	pub open_fds: Option<u64>,
+	/// Number of threads
+	pub threads: Option<u64>,
+}
+
+/// The soft limits the OS enforces on the resources of the process, `None`
+/// when unlimited or unknown on this platform
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ProcessLimits {
+	/// Most file descriptors the process can have open, sockets included
+	pub max_open_fds: Option<u64>,
+	/// Most threads the process can run. Counted across all processes of
+	/// the same user by the OS.
+	pub max_threads: Option<u64>,
+}
+
+/// Samples the resources currently used by the process
+pub fn process_usage() -> ProcessUsage {
+	sample()
+}
+
+/// Reads the limits on the resources of the process
+pub fn process_limits() -> ProcessLimits {
+	limits()
+}
+
+#[cfg(target_os = "linux")]
+fn sample() -> ProcessUsage {
+	let mut usage = ProcessUsage::default();
+	if let Ok(status) = fs::read_to_string("/proc/self/status") {
+		for line in status.lines() {
+			let mut parts = line.split_whitespace();
+			let field = parts.next();
+			let value = parts.next().and_then(|v| v.parse::<u64>().ok());
+			match field {
+				Some("VmRSS:") => usage.memory_bytes = value.map(|kb| kb * 1024),
+				Some("VmSize:") => usage.virtual_memory_bytes = value.map(|kb| kb * 1024),
+				Some("Threads:") => usage.threads = value,
+				_ => {}
+			}
+		}
+	}
+	usage.open_fds = fs::read_dir("/proc/self/fd")
+		.ok()
+		.map(|entries| entries.count() as u64);
+	usage
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sample() -> ProcessUsage {
+	ProcessUsage::default()
+}
+
+#[cfg(target_os = "linux")]
+fn limits() -> ProcessLimits {
+	let mut limits = ProcessLimits::default();
+	if let Ok(content) = fs::read_to_string("/proc/self/limits") {
+		for line in content.lines() {
+			// the soft limit is the first column after the name, "unlimited"
+			// failing to parse
+			let soft = |name: &str| {
+				line[name.len()..]
+					.split_whitespace()
+					.next()
+					.and_then(|v| v.parse::<u64>().ok())
+			};
+			if line.starts_with("Max open files") {
+				limits.max_open_fds = soft("Max open files");
+			} else if line.starts_with("Max processes") {
+				limits.max_threads = soft("Max processes");
+			}
+		}
+	}
+	limits
+}
+
+#[cfg(not(target_os = "linux"))]
+fn limits() -> ProcessLimits {
+	ProcessLimits::default()
+}
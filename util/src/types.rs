@@ -29,6 +29,24 @@ pub enum LogLevel {
 	Trace,
 }
 
+/// Number of rotated logs kept by default
+pub const DEFAULT_LOG_MAX_FILES: u32 = 32;
+
+/// Format of the log lines
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum LogFormat {
+	/// Human readable lines
+	Text,
+	/// One JSON object per line, for log collectors
+	Json,
+}
+
+impl Default for LogFormat {
+	fn default() -> LogFormat {
+		LogFormat::Text
+	}
+}
+
 /// Logging config
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LoggingConfig {
@@ -46,8 +64,17 @@ pub struct LoggingConfig {
 	pub log_file_append: bool,
 	/// Size of the log in bytes to rotate over (optional)
 	pub log_max_size: Option<u64>,
+	/// Age of the log in hours to rotate over (optional)
+	#[serde(default)]
+	pub log_max_age_hours: Option<u64>,
+	/// Number of compressed rotated logs to keep, 32 if unset
+	#[serde(default)]
+	pub log_max_files: Option<u32>,
 	/// Whether the tui is running (optional)
 	pub tui_running: Option<bool>,
+	/// Format of the lines written to stdout and the log file
+	#[serde(default)]
+	pub log_format: LogFormat,
 }
 
 impl Default for LoggingConfig {
@@ -60,7 +87,10 @@ impl Default for LoggingConfig {
 			log_file_path: String::from("grin.log"),
 			log_file_append: true,
 			log_max_size: Some(1024 * 1024 * 16), // 16 megabytes default
+			log_max_age_hours: None,
+			log_max_files: Some(DEFAULT_LOG_MAX_FILES),
 			tui_running: None,
+			log_format: LogFormat::Text,
 		}
 	}
 }
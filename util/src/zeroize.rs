@@ -0,0 +1,151 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wiping of secrets (seeds, keys, blinding factors, nonces) from memory once
+//! done with, so they don't linger in freed memory or end up in core dumps.
+//! Writes are volatile so the compiler can't elide them as dead stores.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+use secp::key::{SecretKey, ZERO_KEY};
+
+/// Overwrites a value in place, without the write being optimized out
+pub fn volatile_overwrite<T: Copy>(dest: &mut T, value: T) {
+	unsafe {
+		ptr::write_volatile(dest, value);
+	}
+	atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// Secrets that can be wiped in place
+pub trait Zeroize {
+	/// Overwrites the secret with zeros
+	fn zeroize(&mut self);
+}
+
+impl Zeroize for [u8] {
+	fn zeroize(&mut self) {
+		for b in self.iter_mut() {
+			unsafe {
+				ptr::write_volatile(b, 0);
+			}
+		}
+		atomic::compiler_fence(Ordering::SeqCst);
+	}
+}
+
+impl Zeroize for Vec<u8> {
+	fn zeroize(&mut self) {
+		// up to the capacity, what a shrink or truncate left behind included
+		let cap = self.capacity();
+		self.resize(cap, 0);
+		self.as_mut_slice().zeroize();
+		self.clear();
+	}
+}
+
+impl Zeroize for String {
+	fn zeroize(&mut self) {
+		// all zeros stays valid utf-8
+		unsafe { self.as_mut_vec() }.zeroize();
+	}
+}
+
+impl Zeroize for SecretKey {
+	fn zeroize(&mut self) {
+		volatile_overwrite(self, ZERO_KEY);
+	}
+}
+
+impl<T: Zeroize> Zeroize for Option<T> {
+	fn zeroize(&mut self) {
+		if let Some(ref mut v) = *self {
+			v.zeroize();
+		}
+	}
+}
+
+/// Holds a secret, wiping it when dropped. Reads and writes go through as
+/// with the secret itself.
+#[derive(Clone, Default, PartialEq)]
+pub struct Zeroing<T: Zeroize>(T);
+
+impl<T: Zeroize> Zeroing<T> {
+	/// Takes ownership of a secret, to be wiped when dropped
+	pub fn new(secret: T) -> Zeroing<T> {
+		Zeroing(secret)
+	}
+}
+
+impl<T: Zeroize> From<T> for Zeroing<T> {
+	fn from(secret: T) -> Zeroing<T> {
+		Zeroing(secret)
+	}
+}
+
+impl<T: Zeroize> Deref for Zeroing<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.0
+	}
+}
+
+impl<T: Zeroize> DerefMut for Zeroing<T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.0
+	}
+}
+
+impl<T: Zeroize> Drop for Zeroing<T> {
+	fn drop(&mut self) {
+		self.0.zeroize();
+	}
+}
+
+// never let the secret itself out in logs
+impl<T: Zeroize> fmt::Debug for Zeroing<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Zeroing(..)")
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn zeroize_secrets() {
+		let mut bytes = vec![1u8, 2, 3];
+		bytes.truncate(1);
+		bytes.zeroize();
+		assert!(bytes.is_empty());
+		assert!(bytes.capacity() >= 3);
+
+		let mut s = String::from("secret");
+		s.zeroize();
+		assert_eq!(s, "");
+
+		let mut a = [7u8; 4];
+		a.zeroize();
+		assert_eq!(a, [0; 4]);
+
+		let z = Zeroing::new(vec![9u8; 8]);
+		assert_eq!(z.len(), 8);
+		assert_eq!(format!("{:?}", z), "Zeroing(..)");
+	}
+}
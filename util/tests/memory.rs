@@ -0,0 +1,80 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_util as util;
+
+use std::sync::Arc;
+
+use util::{MemoryBudget, MemoryConsumer, Mutex};
+
+// entries of 10 bytes each
+struct Cache {
+	name: &'static str,
+	entries: Mutex<usize>,
+}
+
+impl Cache {
+	fn new(name: &'static str, entries: usize) -> Arc<Cache> {
+		Arc::new(Cache {
+			name,
+			entries: Mutex::new(entries),
+		})
+	}
+}
+
+impl MemoryConsumer for Cache {
+	fn name(&self) -> &'static str {
+		self.name
+	}
+
+	fn memory_used(&self) -> usize {
+		*self.entries.lock() * 10
+	}
+
+	fn shrink_to(&self, target: usize) -> usize {
+		let mut entries = self.entries.lock();
+		let before = *entries;
+		*entries = before.min(target / 10);
+		(before - *entries) * 10
+	}
+}
+
+#[test]
+fn memory_budget() {
+	let budget = MemoryBudget::new(1_000);
+	let orphans = Cache::new("orphans", 90);
+	let headers = Cache::new("headers", 10);
+	budget.register(Arc::downgrade(&orphans));
+	budget.register(Arc::downgrade(&headers));
+
+	// under the cap, nothing to do
+	assert_eq!(budget.used(), 1_000);
+	assert_eq!(budget.enforce(), 0);
+
+	// over it, each shrinks in proportion to what it holds
+	*orphans.entries.lock() = 190;
+	assert_eq!(budget.enforce(), 1_000);
+	assert_eq!(orphans.memory_used(), 950);
+	assert_eq!(headers.memory_used(), 50);
+
+	// gone consumers aren't accounted for anymore
+	drop(orphans);
+	assert_eq!(budget.usage(), vec![("headers", 50)]);
+
+	// no cap, no eviction
+	let unlimited = MemoryBudget::unlimited();
+	let headers = Cache::new("headers", 1_000_000);
+	unlimited.register(Arc::downgrade(&headers));
+	assert_eq!(unlimited.enforce(), 0);
+}
@@ -0,0 +1,41 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_util as util;
+
+use util::{RateCounter, RATE_HISTORY_SECS};
+
+#[test]
+fn bytes_per_sec() {
+	let mut rc = RateCounter::new();
+	assert_eq!(rc.bytes_per_sec(5), vec![0; 5]);
+
+	rc.inc(100);
+	rc.inc(50);
+	let per_sec = rc.bytes_per_sec(5);
+	assert_eq!(per_sec.len(), 5);
+	// all just counted, in the current second unless it just ticked over
+	assert_eq!(per_sec.iter().sum::<u64>(), 150);
+	assert!(per_sec[4] > 0);
+	assert_eq!(rc.bytes_per_min(), 150);
+
+	assert_eq!(rc.bytes_in(300), 150);
+	assert_eq!(rc.bytes_rate(10), 15.0);
+
+	// no further back than the history
+	assert_eq!(
+		rc.bytes_per_sec(RATE_HISTORY_SECS * 2).len() as u64,
+		RATE_HISTORY_SECS
+	);
+}
@@ -0,0 +1,29 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate grin_util as util;
+
+#[cfg(target_os = "linux")]
+#[test]
+fn process_usage() {
+	let before = util::process_usage();
+	assert!(before.memory_bytes.unwrap() > 0);
+	assert!(before.virtual_memory_bytes.unwrap() >= before.memory_bytes.unwrap());
+	assert!(before.threads.unwrap() >= 1);
+
+	// an open file shows
+	let _f = std::fs::File::open("Cargo.toml").unwrap();
+	let after = util::process_usage();
+	assert!(after.open_fds.unwrap() > before.open_fds.unwrap());
+}
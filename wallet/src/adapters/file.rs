@@ -0,0 +1,114 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Slate exchange through files, JSON or armored, for the recipient to
+//! carry over by whatever means
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use serde_json;
+
+use libtx::armor;
+use libtx::slate::Slate;
+use libwallet::types::SlateAdapter;
+use libwallet::{Error, ErrorKind};
+
+/// Path standing for stdin or stdout rather than a file
+pub const STDIO_PATH: &'static str = "-";
+
+/// Path of a destination, with any `file://` prefix removed
+fn file_path(dest: &str) -> &str {
+	if dest.starts_with("file://") {
+		&dest["file://".len()..]
+	} else {
+		dest
+	}
+}
+
+/// Reads and writes slates as files, or on stdin/stdout given "-". Files
+/// may be given with or without a `file://` prefix.
+#[derive(Clone)]
+pub struct FileSlateAdapter {
+	armored: bool,
+}
+
+impl FileSlateAdapter {
+	/// Create a new file adapter, writing armored slates if `armored`
+	pub fn new(armored: bool) -> FileSlateAdapter {
+		FileSlateAdapter { armored }
+	}
+
+	/// Read a JSON or armored slate, also returning whether it was armored
+	pub fn read_slate(source: &str) -> Result<(Slate, bool), Error> {
+		FileSlateAdapter::parse_slate(&FileSlateAdapter::read(source)?)
+	}
+
+	/// Read the whole content of a file, or of stdin given "-"
+	pub fn read(source: &str) -> Result<String, Error> {
+		let path = file_path(source);
+		let mut content = String::new();
+		if path == STDIO_PATH {
+			io::stdin().read_to_string(&mut content)?;
+		} else {
+			if !Path::new(path).is_file() {
+				return Err(ErrorKind::GenericError(format!("File {} not found.", path)).into());
+			}
+			File::open(path)?.read_to_string(&mut content)?;
+		}
+		Ok(content)
+	}
+
+	/// Parse a JSON or armored slate, also returning whether it was armored
+	pub fn parse_slate(content: &str) -> Result<(Slate, bool), Error> {
+		let slate = armor::slate_from_str(content)?;
+		Ok((slate, armor::is_armored(content)))
+	}
+}
+
+impl SlateAdapter for FileSlateAdapter {
+	fn supports_sync(&self) -> bool {
+		false
+	}
+
+	fn send_tx_sync(&self, _dest: &str, _slate: &Slate) -> Result<Slate, Error> {
+		Err(ErrorKind::GenericError("file slates can't be sent synchronously".to_owned()).into())
+	}
+
+	fn send_tx_async(&self, dest: &str, slate: &Slate) -> Result<(), Error> {
+		let path = file_path(dest);
+		let content = if self.armored {
+			armor::encode(slate)?
+		} else {
+			serde_json::to_string(slate).map_err(|_| ErrorKind::Format)?
+		};
+		if path == STDIO_PATH {
+			let stdout = io::stdout();
+			let mut out = stdout.lock();
+			out.write_all(content.as_bytes())?;
+			out.flush()?;
+		} else {
+			let mut f = File::create(path)?;
+			f.write_all(content.as_bytes())?;
+			f.sync_all()?;
+		}
+		Ok(())
+	}
+
+	fn receive_tx_async(&self, source: &str) -> Result<Slate, Error> {
+		let (slate, _) = FileSlateAdapter::read_slate(source)?;
+		Ok(slate)
+	}
+}
@@ -0,0 +1,73 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Slate exchange with a listening wallet's foreign API over http
+
+use failure::ResultExt;
+
+use api;
+use libtx::slate::{Slate, SLATE_VERSION};
+use libwallet::types::{SlateAdapter, VersionInfo};
+use libwallet::{Error, ErrorKind};
+
+/// Posts slates to the `receive_tx` endpoint of a wallet listener, once its
+/// `check_version` tells it can take them
+#[derive(Clone, Default)]
+pub struct HTTPSlateAdapter {}
+
+impl HTTPSlateAdapter {
+	/// Create a new http adapter
+	pub fn new() -> HTTPSlateAdapter {
+		HTTPSlateAdapter {}
+	}
+}
+
+impl SlateAdapter for HTTPSlateAdapter {
+	fn supports_sync(&self) -> bool {
+		true
+	}
+
+	fn send_tx_sync(&self, dest: &str, slate: &Slate) -> Result<Slate, Error> {
+		if !dest.starts_with("http") {
+			error!(
+				"dest formatted as {} but send -d expected stdout or http://IP:port",
+				dest
+			);
+			return Err(ErrorKind::Uri.into());
+		}
+		let url = format!("{}/v1/wallet/foreign/check_version", dest);
+		match api::client::get::<VersionInfo>(url.as_str(), None) {
+			Ok(info) => info.check_receive(SLATE_VERSION)?,
+			// listeners predating the check can still receive
+			Err(e) => debug!("Could not check the version of {}: {}", dest, e),
+		}
+
+		let url = format!("{}/v1/wallet/foreign/receive_tx", dest);
+		debug!("Posting transaction slate to {}", url);
+
+		let res = api::client::post(url.as_str(), None, slate)
+			.context(ErrorKind::ClientCallback("Posting transaction slate"))?;
+		Ok(res)
+	}
+
+	fn send_tx_async(&self, _dest: &str, _slate: &Slate) -> Result<(), Error> {
+		Err(ErrorKind::GenericError("http slates are sent synchronously".to_owned()).into())
+	}
+
+	fn receive_tx_async(&self, _source: &str) -> Result<Slate, Error> {
+		Err(ErrorKind::GenericError(
+			"http slates are received by the wallet listener".to_owned(),
+		).into())
+	}
+}
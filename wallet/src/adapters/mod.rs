@@ -0,0 +1,85 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Slate exchange adapters, carrying slates between wallets over the
+//! transport a destination's scheme names. Further transports can be
+//! added by registering another `SlateAdapter`.
+
+mod file;
+mod http;
+mod tor;
+
+pub use self::file::{FileSlateAdapter, STDIO_PATH};
+pub use self::http::HTTPSlateAdapter;
+pub use self::tor::{is_onion_address, TorSlateAdapter, DEFAULT_SOCKS_PROXY};
+
+use std::collections::HashMap;
+
+use libwallet::types::SlateAdapter;
+
+/// Scheme used for destinations that don't name one, i.e. plain file paths
+pub const DEFAULT_SCHEME: &'static str = "file";
+
+/// The scheme of a destination URI like `http://127.0.0.1:13415`, if any
+pub fn scheme(dest: &str) -> Option<&str> {
+	dest.find("://").map(|i| &dest[..i])
+}
+
+/// Registered slate adapters, keyed by the scheme they handle
+pub struct SlateAdapters {
+	adapters: HashMap<String, Box<SlateAdapter>>,
+}
+
+impl SlateAdapters {
+	/// An empty registry
+	pub fn new() -> SlateAdapters {
+		SlateAdapters {
+			adapters: HashMap::new(),
+		}
+	}
+
+	/// Register an adapter for a scheme, replacing any already registered
+	pub fn register(&mut self, scheme: &str, adapter: Box<SlateAdapter>) {
+		self.adapters.insert(scheme.to_lowercase(), adapter);
+	}
+
+	/// The adapter registered for a scheme
+	pub fn get(&self, scheme: &str) -> Option<&SlateAdapter> {
+		self.adapters.get(&scheme.to_lowercase()).map(|a| &**a)
+	}
+
+	/// The adapter for a destination, by its scheme or the given default
+	/// scheme when it has none. Onion addresses go through the `tor` adapter,
+	/// whatever their scheme.
+	pub fn for_dest(&self, dest: &str, default_scheme: &str) -> Option<&SlateAdapter> {
+		if is_onion_address(dest) {
+			if let Some(adapter) = self.get("tor") {
+				return Some(adapter);
+			}
+		}
+		self.get(scheme(dest).unwrap_or(default_scheme))
+	}
+}
+
+impl Default for SlateAdapters {
+	/// The built in http, tor and file adapters
+	fn default() -> SlateAdapters {
+		let mut adapters = SlateAdapters::new();
+		adapters.register("http", Box::new(HTTPSlateAdapter::new()));
+		adapters.register("https", Box::new(HTTPSlateAdapter::new()));
+		adapters.register("tor", Box::new(TorSlateAdapter::new(DEFAULT_SOCKS_PROXY)));
+		adapters.register("file", Box::new(FileSlateAdapter::new(false)));
+		adapters
+	}
+}
@@ -0,0 +1,205 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Slate exchange with a wallet listening as a Tor onion service, through
+//! the SOCKS5 proxy of a running tor
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde_json;
+
+use libtx::slate::Slate;
+use libwallet::types::SlateAdapter;
+use libwallet::{Error, ErrorKind};
+
+/// Address of the SOCKS proxy of a tor running with its default settings
+pub const DEFAULT_SOCKS_PROXY: &'static str = "127.0.0.1:9050";
+
+/// Length of a v3 onion address, without the `.onion` suffix
+const ONION_V3_LEN: usize = 56;
+
+/// Connecting through tor can take a while, the circuit being built first
+const TIMEOUT_SECS: u64 = 120;
+
+/// The host and port of a destination like `http://xyz.onion:8080/`
+fn host_and_port(dest: &str) -> (&str, u16) {
+	let rest = match dest.find("://") {
+		Some(i) => &dest[i + 3..],
+		None => dest,
+	};
+	let rest = rest.split('/').next().unwrap_or("");
+	match rest.rfind(':') {
+		Some(i) => match rest[i + 1..].parse() {
+			Ok(port) => (&rest[..i], port),
+			Err(_) => (rest, 80),
+		},
+		None => (rest, 80),
+	}
+}
+
+/// Whether a destination is an onion address, e.g. `xyz.onion` or
+/// `http://xyz.onion`
+pub fn is_onion_address(dest: &str) -> bool {
+	host_and_port(dest).0.to_lowercase().ends_with(".onion")
+}
+
+/// Posts slates to the `receive_tx` endpoint of a wallet listening as an
+/// onion service
+#[derive(Clone)]
+pub struct TorSlateAdapter {
+	socks_proxy: String,
+}
+
+impl TorSlateAdapter {
+	/// Create a new tor adapter, connecting through the given SOCKS5 proxy
+	pub fn new(socks_proxy: &str) -> TorSlateAdapter {
+		TorSlateAdapter {
+			socks_proxy: socks_proxy.to_owned(),
+		}
+	}
+
+	/// Open a connection to a host through the SOCKS5 proxy, leaving the
+	/// name resolution to tor
+	fn connect(&self, host: &str, port: u16) -> Result<TcpStream, Error> {
+		let mut stream = TcpStream::connect(&self.socks_proxy[..]).map_err(|e| {
+			ErrorKind::WalletComms(format!(
+				"Could not connect to the tor SOCKS proxy at {}, is tor running? {}",
+				self.socks_proxy, e
+			))
+		})?;
+		stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+		stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+
+		// no authentication
+		stream.write_all(&[5, 1, 0])?;
+		let mut res = [0u8; 2];
+		stream.read_exact(&mut res)?;
+		if res != [5, 0] {
+			return Err(ErrorKind::WalletComms("SOCKS proxy refused to connect".to_owned()).into());
+		}
+
+		let mut req = vec![5, 1, 0, 3, host.len() as u8];
+		req.extend_from_slice(host.as_bytes());
+		req.push((port >> 8) as u8);
+		req.push(port as u8);
+		stream.write_all(&req)?;
+		let mut res = [0u8; 4];
+		stream.read_exact(&mut res)?;
+		if res[1] != 0 {
+			return Err(ErrorKind::WalletComms(format!(
+				"Could not reach {} through tor (SOCKS error {})",
+				host, res[1]
+			)).into());
+		}
+		// skip the bound address, which tells us nothing
+		let addr_len = match res[3] {
+			1 => 4,
+			4 => 16,
+			_ => {
+				let mut len = [0u8; 1];
+				stream.read_exact(&mut len)?;
+				len[0] as usize
+			}
+		};
+		let mut bound = vec![0u8; addr_len + 2];
+		stream.read_exact(&mut bound)?;
+		Ok(stream)
+	}
+}
+
+impl SlateAdapter for TorSlateAdapter {
+	fn supports_sync(&self) -> bool {
+		true
+	}
+
+	fn send_tx_sync(&self, dest: &str, slate: &Slate) -> Result<Slate, Error> {
+		let (host, port) = host_and_port(dest);
+		let host = host.to_lowercase();
+		if !host.ends_with(".onion") || host.len() != ONION_V3_LEN + ".onion".len() {
+			error!("dest formatted as {} but expected a v3 onion address", dest);
+			return Err(ErrorKind::Uri.into());
+		}
+		let body = serde_json::to_string(slate).map_err(|_| ErrorKind::Format)?;
+		debug!("Posting transaction slate to {} through tor", host);
+
+		let stream = self.connect(&host, port)?;
+		let mut writer = stream.try_clone()?;
+		write!(
+			writer,
+			"POST /v1/wallet/foreign/receive_tx HTTP/1.1\r\n\
+			 Host: {}\r\n\
+			 Content-Type: application/json\r\n\
+			 Content-Length: {}\r\n\
+			 Connection: close\r\n\r\n{}",
+			host,
+			body.len(),
+			body
+		)?;
+
+		let mut reader = BufReader::new(stream);
+		let mut status = String::new();
+		reader.read_line(&mut status)?;
+		let code = status.split_whitespace().nth(1).unwrap_or("");
+		let mut chunked = false;
+		loop {
+			let mut line = String::new();
+			if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+				break;
+			}
+			let line = line.to_lowercase();
+			if line.starts_with("transfer-encoding:") && line.contains("chunked") {
+				chunked = true;
+			}
+		}
+		let mut body = vec![];
+		if chunked {
+			loop {
+				let mut size = String::new();
+				reader.read_line(&mut size)?;
+				let size = usize::from_str_radix(size.trim(), 16).unwrap_or(0);
+				if size == 0 {
+					break;
+				}
+				let mut chunk = vec![0u8; size + 2];
+				reader.read_exact(&mut chunk)?;
+				body.extend_from_slice(&chunk[..size]);
+			}
+		} else {
+			reader.read_to_end(&mut body)?;
+		}
+
+		if code != "200" {
+			error!(
+				"Posting transaction slate to {} failed: {} {}",
+				host,
+				status.trim(),
+				String::from_utf8_lossy(&body)
+			);
+			return Err(ErrorKind::ClientCallback("Posting transaction slate").into());
+		}
+		serde_json::from_slice(&body).map_err(|_| ErrorKind::Format.into())
+	}
+
+	fn send_tx_async(&self, _dest: &str, _slate: &Slate) -> Result<(), Error> {
+		Err(ErrorKind::GenericError("tor slates are sent synchronously".to_owned()).into())
+	}
+
+	fn receive_tx_async(&self, _source: &str) -> Result<Slate, Error> {
+		Err(ErrorKind::GenericError(
+			"tor slates are received by the wallet listener".to_owned(),
+		).into())
+	}
+}
@@ -16,13 +16,14 @@
 //! specific to the FileWallet
 
 use failure::ResultExt;
-use futures::{stream, Stream};
 
 use libwallet::types::*;
 use std::collections::HashMap;
-use tokio::runtime::Runtime;
 
+use adapters::HTTPSlateAdapter;
 use api;
+use core::core::hash::Hash;
+use core::core::BlockHeader;
 use error::{Error, ErrorKind};
 use libtx::slate::Slate;
 use libwallet;
@@ -33,6 +34,7 @@ use util::secp::pedersen;
 pub struct HTTPWalletClient {
 	node_url: String,
 	node_api_secret: Option<String>,
+	node: api::NodeClient,
 }
 
 impl HTTPWalletClient {
@@ -40,7 +42,8 @@ impl HTTPWalletClient {
 	pub fn new(node_url: &str, node_api_secret: Option<String>) -> HTTPWalletClient {
 		HTTPWalletClient {
 			node_url: node_url.to_owned(),
-			node_api_secret: node_api_secret,
+			node_api_secret: node_api_secret.clone(),
+			node: api::NodeClient::new(node_url, node_api_secret),
 		}
 	}
 }
@@ -80,33 +83,12 @@ impl WalletClient for HTTPWalletClient {
 
 	/// Send the slate to a listening wallet instance
 	fn send_tx_slate(&self, dest: &str, slate: &Slate) -> Result<Slate, libwallet::Error> {
-		if &dest[..4] != "http" {
-			let err_str = format!(
-				"dest formatted as {} but send -d expected stdout or http://IP:port",
-				dest
-			);
-			error!("{}", err_str,);
-			Err(libwallet::ErrorKind::Uri)?
-		}
-		let url = format!("{}/v1/wallet/foreign/receive_tx", dest);
-		debug!("Posting transaction slate to {}", url);
-
-		let res = api::client::post(url.as_str(), None, slate).context(
-			libwallet::ErrorKind::ClientCallback("Posting transaction slate"),
-		)?;
-		Ok(res)
+		HTTPSlateAdapter::new().send_tx_sync(dest, slate)
 	}
 
 	/// Posts a transaction to a grin node
 	fn post_tx(&self, tx: &TxWrapper, fluff: bool) -> Result<(), libwallet::Error> {
-		let url;
-		let dest = self.node_url();
-		if fluff {
-			url = format!("{}/v1/pool/push?fluff", dest);
-		} else {
-			url = format!("{}/v1/pool/push", dest);
-		}
-		api::client::post_no_ret(url.as_str(), self.node_api_secret(), tx).context(
+		self.node.push_tx_hex(&tx.tx_hex, fluff).context(
 			libwallet::ErrorKind::ClientCallback("Posting transaction to node"),
 		)?;
 		Ok(())
@@ -114,11 +96,9 @@ impl WalletClient for HTTPWalletClient {
 
 	/// Return the chain tip from a given node
 	fn get_chain_height(&self) -> Result<u64, libwallet::Error> {
-		let addr = self.node_url();
-		let url = format!("{}/v1/chain", addr);
-		let res = api::client::get::<api::Tip>(url.as_str(), self.node_api_secret()).context(
-			libwallet::ErrorKind::ClientCallback("Getting chain height from node"),
-		)?;
+		let res = self.node.get_tip().context(libwallet::ErrorKind::ClientCallback(
+			"Getting chain height from node",
+		))?;
 		Ok(res.height)
 	}
 
@@ -127,30 +107,10 @@ impl WalletClient for HTTPWalletClient {
 		&self,
 		wallet_outputs: Vec<pedersen::Commitment>,
 	) -> Result<HashMap<pedersen::Commitment, (String, u64)>, libwallet::Error> {
-		let addr = self.node_url();
-		// build the necessary query params -
-		// ?id=xxx&id=yyy&id=zzz
-		let query_params: Vec<String> = wallet_outputs
-			.iter()
-			.map(|commit| format!("id={}", util::to_hex(commit.as_ref().to_vec())))
-			.collect();
-
 		// build a map of api outputs by commit so we can look them up efficiently
 		let mut api_outputs: HashMap<pedersen::Commitment, (String, u64)> = HashMap::new();
-		let mut tasks = Vec::new();
-
-		for query_chunk in query_params.chunks(500) {
-			let url = format!("{}/v1/chain/outputs/byids?{}", addr, query_chunk.join("&"),);
-			tasks.push(api::client::get_async::<Vec<api::Output>>(
-				url.as_str(),
-				self.node_api_secret(),
-			));
-		}
 
-		let task = stream::futures_unordered(tasks).collect();
-
-		let mut rt = Runtime::new().unwrap();
-		let results = match rt.block_on(task) {
+		let results = match self.node.get_outputs(&wallet_outputs) {
 			Ok(outputs) => outputs,
 			Err(e) => {
 				error!("Outputs by id failed: {}", e);
@@ -158,13 +118,11 @@ impl WalletClient for HTTPWalletClient {
 			}
 		};
 
-		for res in results {
-			for out in res {
-				api_outputs.insert(
-					out.commit.commit(),
-					(util::to_hex(out.commit.to_vec()), out.height),
-				);
-			}
+		for out in results {
+			api_outputs.insert(
+				out.commit.commit(),
+				(util::to_hex(out.commit.to_vec()), out.height),
+			);
 		}
 		Ok(api_outputs)
 	}
@@ -182,14 +140,10 @@ impl WalletClient for HTTPWalletClient {
 		libwallet::Error,
 	> {
 		let addr = self.node_url();
-		let query_param = format!("start_index={}&max={}", start_height, max_outputs);
-
-		let url = format!("{}/v1/txhashset/outputs?{}", addr, query_param,);
-
 		let mut api_outputs: Vec<(pedersen::Commitment, pedersen::RangeProof, bool, u64)> =
 			Vec::new();
 
-		match api::client::get::<api::OutputListing>(url.as_str(), self.node_api_secret()) {
+		match self.node.get_outputs_by_pmmr_index(start_height, max_outputs) {
 			Ok(o) => {
 				for out in o.outputs {
 					let is_coinbase = match out.output_type {
@@ -218,6 +172,75 @@ impl WalletClient for HTTPWalletClient {
 			}
 		}
 	}
+
+	/// Get a header from the node, in its binary serialization
+	fn get_header(&self, height: u64) -> Result<BlockHeader, libwallet::Error> {
+		let header = self
+			.node
+			.get_full_header(api::BlockRef::Height(height))
+			.context(libwallet::ErrorKind::ClientCallback(
+				"Getting header from node",
+			))?;
+		Ok(header)
+	}
+
+	/// Get a header from another node, with no api secret as it's not ours
+	fn get_header_from(
+		&self,
+		node_url: &str,
+		height: Option<u64>,
+	) -> Result<BlockHeader, libwallet::Error> {
+		let node = api::NodeClient::new(node_url, None);
+		let block = match height {
+			Some(h) => api::BlockRef::Height(h),
+			None => {
+				let tip = node.get_tip().context(libwallet::ErrorKind::ClientCallback(
+					"Getting chain head from node",
+				))?;
+				let hash = Hash::from_hex(&tip.last_block_pushed).context(
+					libwallet::ErrorKind::ClientCallback("Parsing chain head from node"),
+				)?;
+				api::BlockRef::Hash(hash)
+			}
+		};
+		let header = node
+			.get_full_header(block)
+			.context(libwallet::ErrorKind::ClientCallback(
+				"Getting header from node",
+			))?;
+		Ok(header)
+	}
+
+	/// Get Merkle proofs of the unspent outputs from the node
+	fn get_output_proofs(
+		&self,
+		wallet_outputs: Vec<pedersen::Commitment>,
+	) -> Result<Vec<OutputProofs>, libwallet::Error> {
+		let res = self.node.get_output_proofs(&wallet_outputs).context(
+			libwallet::ErrorKind::ClientCallback("Getting output proofs from node"),
+		)?;
+		let mut proofs = vec![];
+		for p in res {
+			let header_hash = Hash::from_hex(&p.header.hash).context(
+				libwallet::ErrorKind::ClientCallback("Parsing output proofs header"),
+			)?;
+			let mut outputs = vec![];
+			for o in p.outputs {
+				let proof = o
+					.merkle_proof()
+					.context(libwallet::ErrorKind::ClientCallback(
+						"Parsing output merkle proof",
+					))?;
+				outputs.push((o.commit.commit(), o.height, o.mmr_index, proof));
+			}
+			proofs.push(OutputProofs {
+				header_hash,
+				header_height: p.header.height,
+				outputs,
+			});
+		}
+		Ok(proofs)
+	}
 }
 /// Call the wallet API to create a coinbase output for the given block_fees.
 /// Will retry based on default "retry forever with backoff" behavior.
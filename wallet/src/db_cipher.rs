@@ -0,0 +1,274 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encryption of the wallet database at rest. Values are sealed with
+//! ChaCha20-Poly1305 under a key derived from the wallet password, and the
+//! identifying part of each key (ids, labels, contact names) is replaced by
+//! a keyed hash, leaving only the one byte record type in the clear. Values
+//! are bound to the stored key of their record, so they can't be moved to
+//! another one.
+
+use blake2::blake2b::blake2b;
+use rand::{thread_rng, Rng};
+use ring::aead::{self, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::{digest, pbkdf2};
+
+use core::ser::{self, Readable, Writeable};
+use libwallet::{Error, ErrorKind};
+use store;
+use util::Zeroize;
+
+/// Key of the (plaintext) record holding the key derivation salt and the
+/// password check
+const DB_CIPHER_KEY: [u8; 1] = ['e' as u8];
+/// Sealed into the password check, to tell a wrong password on open
+const PASSWORD_CHECK: &'static [u8] = b"grin wallet db";
+
+const SALT_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+/// Key derivation rounds, slowing down guessing the password from a stolen
+/// database
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Encrypts and decrypts wallet database keys and values, wiping its keys
+/// when dropped
+pub struct DbCipher {
+	/// Key sealing values
+	enc_key: [u8; KEY_SIZE],
+	/// Key hashing record keys
+	hash_key: [u8; KEY_SIZE],
+}
+
+impl DbCipher {
+	fn derive(password: &str, salt: &[u8]) -> DbCipher {
+		let mut out = [0u8; KEY_SIZE * 2];
+		pbkdf2::derive(
+			&digest::SHA512,
+			PBKDF2_ITERATIONS,
+			salt,
+			password.as_bytes(),
+			&mut out,
+		);
+		let mut enc_key = [0u8; KEY_SIZE];
+		let mut hash_key = [0u8; KEY_SIZE];
+		enc_key.copy_from_slice(&out[..KEY_SIZE]);
+		hash_key.copy_from_slice(&out[KEY_SIZE..]);
+		out.zeroize();
+		DbCipher { enc_key, hash_key }
+	}
+
+	/// Opens the cipher of a wallet database with its password, checked
+	/// against the one the database was encrypted with. A new database is
+	/// set up for encryption with it, while one written before encryption was
+	/// introduced is left alone: see `encrypt`.
+	pub fn open(db: &store::Store, password: &str) -> Result<DbCipher, Error> {
+		match db.get(&DB_CIPHER_KEY)? {
+			Some(params) => DbCipher::check(&params, password),
+			None => {
+				if !db.raw_entries(&[])?.is_empty() {
+					return Err(ErrorKind::DbNotEncrypted.into());
+				}
+				DbCipher::encrypt(db, password)
+			}
+		}
+	}
+
+	/// Whether the database is encrypted with a password other than the
+	/// empty one, and so can't be opened without asking for it
+	pub fn is_locked(db: &store::Store) -> Result<bool, Error> {
+		match db.get(&DB_CIPHER_KEY)? {
+			Some(params) => Ok(DbCipher::check(&params, "").is_err()),
+			None => Ok(false),
+		}
+	}
+
+	/// Whether the database holds records written before encryption was
+	/// introduced, to encrypt before it can be opened
+	pub fn is_plaintext(db: &store::Store) -> Result<bool, Error> {
+		Ok(db.get(&DB_CIPHER_KEY)?.is_none() && !db.raw_entries(&[])?.is_empty())
+	}
+
+	/// Encrypts a database written before encryption was introduced in place,
+	/// with a password the caller is expected to have confirmed. An already
+	/// encrypted database is just opened.
+	pub fn encrypt(db: &store::Store, password: &str) -> Result<DbCipher, Error> {
+		if let Some(params) = db.get(&DB_CIPHER_KEY)? {
+			return DbCipher::check(&params, password);
+		}
+		let salt: [u8; SALT_SIZE] = thread_rng().gen();
+		let cipher = DbCipher::derive(password, &salt);
+		let entries = db.raw_entries(&[])?;
+		let batch = db.batch()?;
+		if !entries.is_empty() {
+			info!("Encrypting {} wallet db entries", entries.len());
+		}
+		for (k, v) in entries {
+			let key = cipher.key(&k);
+			batch.delete(&k)?;
+			batch.put(&key, cipher.seal_bytes(&v, &key)?)?;
+		}
+		let mut params = salt.to_vec();
+		params.extend(cipher.seal_bytes(PASSWORD_CHECK, &DB_CIPHER_KEY)?);
+		batch.put(&DB_CIPHER_KEY, params)?;
+		batch.commit()?;
+		Ok(cipher)
+	}
+
+	/// Derives the cipher from the password and the stored parameters, if it
+	/// opens the password check
+	fn check(params: &[u8], password: &str) -> Result<DbCipher, Error> {
+		if params.len() < SALT_SIZE {
+			return Err(ErrorKind::Backend("Corrupted wallet db cipher".to_owned()).into());
+		}
+		let cipher = DbCipher::derive(password, &params[..SALT_SIZE]);
+		match cipher.open_bytes(&params[SALT_SIZE..], &DB_CIPHER_KEY) {
+			Ok(ref check) if &check[..] == PASSWORD_CHECK => Ok(cipher),
+			_ => Err(ErrorKind::InvalidDbPassword.into()),
+		}
+	}
+
+	/// The stored form of a record key: its type prefix, followed by a keyed
+	/// hash of the whole key
+	pub fn key(&self, key: &[u8]) -> Vec<u8> {
+		let mut res = vec![key[0]];
+		res.extend(blake2b(KEY_SIZE, &self.hash_key, key).as_bytes());
+		res
+	}
+
+	// sealed along with the stored key of the record as additional data
+	fn seal_bytes(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, Error> {
+		let sealing_key = SealingKey::new(&CHACHA20_POLY1305, &self.enc_key)
+			.map_err(|_| ErrorKind::Backend("Invalid db cipher key".to_owned()))?;
+		let nonce: [u8; NONCE_SIZE] = thread_rng().gen();
+		let mut in_out = data.to_vec();
+		in_out.extend_from_slice(&[0u8; TAG_SIZE]);
+		let len = aead::seal_in_place(&sealing_key, &nonce, key, &mut in_out, TAG_SIZE)
+			.map_err(|_| ErrorKind::Backend("Failed to encrypt db entry".to_owned()))?;
+		let mut res = nonce.to_vec();
+		res.extend_from_slice(&in_out[..len]);
+		Ok(res)
+	}
+
+	fn open_bytes(&self, data: &[u8], key: &[u8]) -> Result<Vec<u8>, Error> {
+		if data.len() < NONCE_SIZE + TAG_SIZE {
+			return Err(ErrorKind::Backend("Truncated db entry".to_owned()).into());
+		}
+		let opening_key = OpeningKey::new(&CHACHA20_POLY1305, &self.enc_key)
+			.map_err(|_| ErrorKind::Backend("Invalid db cipher key".to_owned()))?;
+		let (nonce, sealed) = data.split_at(NONCE_SIZE);
+		let mut in_out = sealed.to_vec();
+		let plain = aead::open_in_place(&opening_key, nonce, key, 0, &mut in_out)
+			.map_err(|_| ErrorKind::Backend("Failed to decrypt db entry".to_owned()))?;
+		Ok(plain.to_vec())
+	}
+
+	/// Encrypts a value for storage under the provided stored key
+	pub fn seal<W: Writeable>(&self, key: &[u8], value: &W) -> Result<Sealed, Error> {
+		let data = ser::ser_vec(value)
+			.map_err(|e| ErrorKind::Backend(format!("Failed to serialize db entry: {}", e)))?;
+		Ok(Sealed(self.seal_bytes(&data, key)?))
+	}
+
+	/// Decrypts a value stored under the provided stored key
+	pub fn unseal<T: Readable>(&self, key: &[u8], sealed: &Sealed) -> Result<T, Error> {
+		let data = self.open_bytes(&sealed.0, key)?;
+		ser::deserialize(&mut &data[..]).map_err(|e| {
+			ErrorKind::Backend(format!("Failed to deserialize db entry: {}", e)).into()
+		})
+	}
+
+	/// Gets and decrypts a value, provided its (plaintext) key
+	pub fn get_ser<T: Readable>(&self, db: &store::Store, key: &[u8]) -> Result<Option<T>, Error> {
+		let key = self.key(key);
+		match db.get(&key)? {
+			Some(data) => Ok(Some(self.unseal(&key, &Sealed(data))?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Gets and decrypts a value, taking the content of the batch into account
+	pub fn batch_get_ser<T: Readable>(
+		&self,
+		batch: &store::Batch,
+		key: &[u8],
+	) -> Result<Option<T>, Error> {
+		let key = self.key(key);
+		match batch.get_ser::<Sealed>(&key)? {
+			Some(sealed) => Ok(Some(self.unseal(&key, &sealed)?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Encrypts and writes a value
+	pub fn put_ser<W: Writeable>(
+		&self,
+		batch: &store::Batch,
+		key: &[u8],
+		value: &W,
+	) -> Result<(), Error> {
+		let key = self.key(key);
+		batch.put(&key, self.seal(&key, value)?.0)?;
+		Ok(())
+	}
+
+	/// Deletes a value, provided its (plaintext) key
+	pub fn delete(&self, batch: &store::Batch, key: &[u8]) -> Result<(), Error> {
+		batch.delete(&self.key(key))?;
+		Ok(())
+	}
+
+	/// Decrypts the values of all records of a type, failing on the first
+	/// one that can't be
+	pub fn iter<T: Readable + 'static>(
+		&self,
+		db: &store::Store,
+		prefix: u8,
+	) -> Result<Box<Iterator<Item = T>>, Error> {
+		let values = db
+			.raw_entries(&[prefix])?
+			.into_iter()
+			.map(|(k, v)| self.unseal(&k, &Sealed(v)))
+			.collect::<Result<Vec<T>, Error>>()?;
+		Ok(Box::new(values.into_iter()))
+	}
+}
+
+impl Drop for DbCipher {
+	fn drop(&mut self) {
+		self.enc_key.zeroize();
+		self.hash_key.zeroize();
+	}
+}
+
+/// An encrypted value as stored in the database: nonce, ciphertext and tag
+pub struct Sealed(Vec<u8>);
+
+impl Writeable for Sealed {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_fixed_bytes(&self.0)
+	}
+}
+
+impl Readable for Sealed {
+	fn read(reader: &mut ser::Reader) -> Result<Sealed, ser::Error> {
+		// values take up the whole record, so read until the reader runs dry
+		let mut data = vec![];
+		while let Ok(b) = reader.read_u8() {
+			data.push(b);
+		}
+		Ok(Sealed(data))
+	}
+}
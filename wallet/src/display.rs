@@ -13,9 +13,13 @@
 // limitations under the License.
 
 use core::core::{self, amount_to_hr_string};
-use libwallet::types::{AcctPathMapping, OutputData, OutputStatus, TxLogEntry, WalletInfo};
+use libwallet::types::{
+	AcctPathMapping, BalanceSnapshot, CheckReport, Contact, OutputData, OutputStatus, ScanProgress,
+	SendEstimate, TxLogEntry, WalletInfo,
+};
 use libwallet::Error;
 use prettytable;
+use std::io;
 use std::io::prelude::Write;
 use term;
 use util;
@@ -134,6 +138,7 @@ pub fn txs(
 	table.set_titles(row![
 		bMG->"Id",
 		bMG->"Type",
+		bMG->"Contact",
 		bMG->"Shared Transaction Id",
 		bMG->"Creation Time",
 		bMG->"Confirmed?",
@@ -154,6 +159,10 @@ pub fn txs(
 			None => "None".to_owned(),
 		};
 		let entry_type = format!("{}", t.tx_type);
+		let contact = match t.contact {
+			Some(ref c) => c.clone(),
+			None => "".to_owned(),
+		};
 		let creation_ts = format!("{}", t.creation_ts.format("%Y-%m-%d %H:%M:%S"));
 		let confirmation_ts = match t.confirmation_ts {
 			Some(m) => format!("{}", m.format("%Y-%m-%d %H:%M:%S")),
@@ -184,6 +193,7 @@ pub fn txs(
 			table.add_row(row![
 				bFC->id,
 				bFC->entry_type,
+				bFC->contact,
 				bFC->slate_id,
 				bFB->creation_ts,
 				bFC->confirmed,
@@ -201,6 +211,7 @@ pub fn txs(
 				table.add_row(row![
 					bFD->id,
 					bFb->entry_type,
+					bFD->contact,
 					bFD->slate_id,
 					bFB->creation_ts,
 					bFg->confirmed,
@@ -217,6 +228,7 @@ pub fn txs(
 				table.add_row(row![
 					bFD->id,
 					bFb->entry_type,
+					bFD->contact,
 					bFD->slate_id,
 					bFB->creation_ts,
 					bFR->confirmed,
@@ -261,6 +273,7 @@ pub fn info(
 		table!(
 			[bFG->"Total", FG->amount_to_hr_string(wallet_info.total, false)],
 			[bFY->"Awaiting Confirmation", FY->amount_to_hr_string(wallet_info.amount_awaiting_confirmation, false)],
+			[FY->"  (Unconfirmed change)", FY->amount_to_hr_string(wallet_info.amount_unconfirmed_change, false)],
 			[bFY->"Immature Coinbase", FY->amount_to_hr_string(wallet_info.amount_immature, false)],
 			[bFG->"Currently Spendable", FG->amount_to_hr_string(wallet_info.amount_currently_spendable, false)],
 			[Fw->"--------------------------------", Fw->"-------------"],
@@ -270,6 +283,7 @@ pub fn info(
 		table!(
 			[bFG->"Total", FG->amount_to_hr_string(wallet_info.total, false)],
 			[bFB->"Awaiting Confirmation", FB->amount_to_hr_string(wallet_info.amount_awaiting_confirmation, false)],
+			[FB->"  (Unconfirmed change)", FB->amount_to_hr_string(wallet_info.amount_unconfirmed_change, false)],
 			[bFB->"Immature Coinbase", FB->amount_to_hr_string(wallet_info.amount_immature, false)],
 			[bFG->"Currently Spendable", FG->amount_to_hr_string(wallet_info.amount_currently_spendable, false)],
 			[Fw->"--------------------------------", Fw->"-------------"],
@@ -279,6 +293,26 @@ pub fn info(
 	table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
 	table.printstd();
 	println!();
+	if !wallet_info.immature_coinbases.is_empty() {
+		let mut table = table!();
+		table.set_titles(row![
+			bMG->"Mined at",
+			bMG->"Spendable at",
+			bMG->"Blocks Left",
+			bMG->"Value",
+		]);
+		for c in &wallet_info.immature_coinbases {
+			table.add_row(row![
+				bFC->c.height,
+				bFC->c.lock_height,
+				bFY->c.blocks_to_maturity,
+				bFG->amount_to_hr_string(c.value, true),
+			]);
+		}
+		table.set_format(*prettytable::format::consts::FORMAT_NO_COLSEP);
+		table.printstd();
+		println!();
+	}
 	if !validated {
 		println!(
 			"\nWARNING: Wallet failed to verify data against a live chain. \
@@ -287,6 +321,97 @@ pub fn info(
 		);
 	}
 }
+/// Display the balance history of an account, one row per snapshot
+pub fn balance_history(
+	account: &str,
+	snapshots: Vec<BalanceSnapshot>,
+	per_block: bool,
+	validated: bool,
+) {
+	let per = if per_block { "Block" } else { "Day" };
+	println!(
+		"\n____ Balance History per {} - Account '{}' ____\n",
+		per, account
+	);
+	let mut table = table!();
+
+	table.set_titles(row![
+		bMG->"Height",
+		bMG->"Time",
+		bMG->"Total",
+		bMG->"Awaiting \nConfirmation",
+		bMG->"Immature \nCoinbase",
+		bMG->"Currently \nSpendable",
+		bMG->"Locked",
+	]);
+	for s in snapshots {
+		table.add_row(row![
+			bFC->s.height,
+			bFB->s.timestamp.format("%Y-%m-%d %H:%M:%S"),
+			bFG->amount_to_hr_string(s.total, true),
+			bFY->amount_to_hr_string(s.amount_awaiting_confirmation, true),
+			bFY->amount_to_hr_string(s.amount_immature, true),
+			bFG->amount_to_hr_string(s.amount_currently_spendable, true),
+			bFR->amount_to_hr_string(s.amount_locked, true),
+		]);
+	}
+	table.set_format(*prettytable::format::consts::FORMAT_NO_COLSEP);
+	table.printstd();
+	println!();
+	if !validated {
+		println!(
+			"\nWARNING: Wallet failed to verify data against a live chain. \
+			 The latest snapshot is from local cache! \
+			 (is your `grin server` offline or broken?)"
+		);
+	}
+}
+/// Display the estimated outcome of a send in a pretty way
+pub fn send_estimate(account: &str, estimate: &SendEstimate, dark_background_color_scheme: bool) {
+	println!(
+		"\n____ Send Estimate - Account '{}' - Amount {} ____\n",
+		account,
+		amount_to_hr_string(estimate.amount, false)
+	);
+	let mut table = if dark_background_color_scheme {
+		table!(
+			[bFY->"Fee", FY->amount_to_hr_string(estimate.fee, false)],
+			[bFY->"Inputs Spent", FY->estimate.inputs.len()],
+			[bFY->"Change", FY->format!("{} ({} outputs)", amount_to_hr_string(estimate.change, false), estimate.num_change_outputs)],
+			[Fw->"--------------------------------", Fw->"-------------"],
+			[bFG->"Total After Send", FG->amount_to_hr_string(estimate.total_after, false)],
+			[bFG->"Spendable After Send", FG->amount_to_hr_string(estimate.spendable_after, false)]
+		)
+	} else {
+		table!(
+			[bFB->"Fee", FB->amount_to_hr_string(estimate.fee, false)],
+			[bFB->"Inputs Spent", FB->estimate.inputs.len()],
+			[bFB->"Change", FB->format!("{} ({} outputs)", amount_to_hr_string(estimate.change, false), estimate.num_change_outputs)],
+			[Fw->"--------------------------------", Fw->"-------------"],
+			[bFG->"Total After Send", FG->amount_to_hr_string(estimate.total_after, false)],
+			[bFG->"Spendable After Send", FG->amount_to_hr_string(estimate.spendable_after, false)]
+		)
+	};
+	table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+	table.printstd();
+	println!();
+
+	println!("Outputs that would be spent:");
+	let mut table = table!();
+	table.set_titles(row![bMG->"Key Id", bMG->"Block Height", bMG->"Coinbase?", bMG->"Value"]);
+	for out in &estimate.inputs {
+		table.add_row(row![
+			bFC->out.key_id.to_string(),
+			bFB->out.height.to_string(),
+			bFY->format!("{}", out.is_coinbase),
+			bFG->amount_to_hr_string(out.value, false),
+		]);
+	}
+	table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+	table.printstd();
+	println!();
+}
+
 /// Display list of wallet accounts in a pretty way
 pub fn accounts(acct_mappings: Vec<AcctPathMapping>) {
 	println!("\n____ Wallet Accounts ____\n",);
@@ -306,3 +431,145 @@ pub fn accounts(acct_mappings: Vec<AcctPathMapping>) {
 	table.printstd();
 	println!();
 }
+
+/// Display restore progress as a single line progress bar, redrawn in place
+/// each time it's called
+pub fn restore_progress(progress: &ScanProgress) {
+	scan_progress("Restoring", progress)
+}
+
+/// Display the progress of a wallet check as restore_progress does
+pub fn check_progress(progress: &ScanProgress) {
+	scan_progress("Checking", progress)
+}
+
+fn scan_progress(action: &str, progress: &ScanProgress) {
+	let width = 40;
+	let pct = progress.percentage_complete() as usize;
+	let filled = width * pct / 100;
+	let eta = match progress.eta_secs {
+		Some(secs) => format!("{}m{:02}s", secs / 60, secs % 60),
+		None => "--".to_owned(),
+	};
+	print!(
+		"\r{} [{}{}] {:>3}% {}/{} outputs, {} found, ETA {}   ",
+		action,
+		"=".repeat(filled),
+		" ".repeat(width - filled),
+		pct,
+		progress.outputs_scanned,
+		progress.outputs_total,
+		progress.matches_found,
+		eta,
+	);
+	if progress.complete {
+		println!();
+	}
+	let _ = io::stdout().flush();
+}
+
+/// Display the address book in a pretty way
+pub fn contacts(contacts: Vec<Contact>) {
+	println!("\n____ Wallet Contacts ____\n",);
+	let mut table = table!();
+
+	table.set_titles(row![
+		mMG->"Name",
+		bMG->"Method",
+		bMG->"Destination",
+	]);
+	for c in contacts {
+		table.add_row(row![
+			bFC->c.name,
+			bFB->c.method,
+			bGC->c.dest,
+		]);
+	}
+	table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+	table.printstd();
+	println!();
+}
+
+/// Display the result of a wallet check, one table per category of
+/// discrepancy found
+pub fn check_report(report: &CheckReport) {
+	let categories = [
+		(
+			"Unspent in wallet, not on chain",
+			&report.missing_on_chain,
+			report.fixed.missing_on_chain,
+		),
+		(
+			"On chain, not unspent in wallet",
+			&report.missing_from_wallet,
+			report.fixed.missing_from_wallet,
+		),
+		(
+			"Locked, spend confirmed",
+			&report.confirmed_locked,
+			report.fixed.confirmed_locked,
+		),
+	];
+	for (title, outputs, fixed) in categories.iter() {
+		if outputs.is_empty() {
+			continue;
+		}
+		println!(
+			"\n____ {}{} ____\n",
+			title,
+			if *fixed { " (fixed)" } else { "" }
+		);
+		let mut table = table!();
+		table.set_titles(row![
+			bMG->"Key Id",
+			bMG->"Block Height",
+			bMG->"Status",
+			bMG->"Value",
+			bMG->"Tx",
+		]);
+		for out in outputs.iter() {
+			let tx = match out.tx_log_entry {
+				None => "".to_owned(),
+				Some(t) => t.to_string(),
+			};
+			table.add_row(row![
+				bFC->format!("{:?}", out.key_id),
+				bFB->out.height,
+				bFR->out.status,
+				bFG->amount_to_hr_string(out.value, false),
+				bFD->tx,
+			]);
+		}
+		table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+		table.printstd();
+	}
+
+	if !report.duplicate_indices.is_empty() {
+		let fixed = if report.fixed.duplicate_indices {
+			" (fixed)"
+		} else {
+			""
+		};
+		println!("\n____ Derivation indices in use{} ____\n", fixed);
+		let mut table = table!();
+		table.set_titles(row![
+			bMG->"Parent BIP-32 Derivation Path",
+			bMG->"Next Index",
+			bMG->"Highest Used",
+		]);
+		for d in &report.duplicate_indices {
+			table.add_row(row![
+				bGC->d.parent_key_id.to_bip_32_string(),
+				bFR->d.child_index,
+				bFB->d.max_used_index,
+			]);
+		}
+		table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+		table.printstd();
+	}
+
+	if report.is_clean() {
+		println!("\nWallet outputs match the chain, nothing to repair.");
+	}
+	println!();
+}
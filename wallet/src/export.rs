@@ -0,0 +1,72 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of wallet transaction history to CSV and JSON
+
+use libwallet::types::TxExportEntry;
+use libwallet::{Error, ErrorKind};
+use serde_json;
+
+/// Column headers of the CSV export, in the same order as the
+/// `TxExportEntry` fields
+pub const CSV_HEADER: &'static str = "id,tx_slate_id,tx_type,account,creation_ts,\
+	confirmation_ts,confirmed,confirmations,amount_credited,amount_debited,fee,\
+	kernel_excess,num_inputs,num_outputs,contact";
+
+/// Quote a CSV field if it contains anything that would break the row
+fn csv_field(s: &str) -> String {
+	if s.contains(',') || s.contains('"') || s.contains('\n') {
+		format!("\"{}\"", s.replace("\"", "\"\""))
+	} else {
+		s.to_owned()
+	}
+}
+
+/// Render transactions as CSV, one row per transaction, with a header row.
+/// Amounts are in nanogrins, timestamps RFC 3339 and multiple kernel excesses
+/// separated by ';'.
+pub fn txs_csv(txs: &Vec<TxExportEntry>) -> String {
+	let mut out = String::from(CSV_HEADER);
+	out.push('\n');
+	for t in txs {
+		let row = vec![
+			t.id.to_string(),
+			t.tx_slate_id.map(|id| id.to_string()).unwrap_or_default(),
+			format!("{:?}", t.tx_type),
+			t.account.clone(),
+			t.creation_ts.to_rfc3339(),
+			t.confirmation_ts
+				.map(|ts| ts.to_rfc3339())
+				.unwrap_or_default(),
+			t.confirmed.to_string(),
+			t.confirmations.to_string(),
+			t.amount_credited.to_string(),
+			t.amount_debited.to_string(),
+			t.fee.map(|f| f.to_string()).unwrap_or_default(),
+			t.kernel_excess.join(";"),
+			t.num_inputs.to_string(),
+			t.num_outputs.to_string(),
+			t.contact.clone().unwrap_or_default(),
+		];
+		let row: Vec<String> = row.iter().map(|f| csv_field(f)).collect();
+		out.push_str(&row.join(","));
+		out.push('\n');
+	}
+	out
+}
+
+/// Render transactions as a pretty-printed JSON array
+pub fn txs_json(txs: &Vec<TxExportEntry>) -> Result<String, Error> {
+	serde_json::to_string_pretty(txs).map_err(|_| ErrorKind::Format.into())
+}
@@ -14,11 +14,14 @@
 
 //! Library module for the main wallet functionalities provided by Grin.
 
+extern crate base64;
 extern crate blake2_rfc as blake2;
 extern crate byteorder;
+extern crate num_cpus;
 #[macro_use]
 extern crate prettytable;
 extern crate rand;
+extern crate ring;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -45,19 +48,27 @@ extern crate grin_keychain as keychain;
 extern crate grin_store as store;
 extern crate grin_util as util;
 
+pub mod adapters;
 mod client;
+mod db_cipher;
 pub mod display;
 mod error;
+pub mod export;
 pub mod libtx;
 pub mod libwallet;
 pub mod lmdb_wallet;
+pub mod tor;
 mod types;
+pub mod watcher;
 
 pub use client::{create_coinbase, HTTPWalletClient};
 pub use error::{Error, ErrorKind};
 pub use libwallet::controller;
 pub use libwallet::types::{
-	BlockFees, CbData, WalletBackend, WalletClient, WalletInfo, WalletInst,
+	BlockFees, CbData, ImmatureCoinbase, SlateAdapter, WalletBackend, WalletClient, WalletInfo,
+	WalletInst,
+};
+pub use lmdb_wallet::{
+	encrypt_wallet_db, wallet_db_exists, wallet_db_locked, wallet_db_plaintext, LMDBBackend,
 };
-pub use lmdb_wallet::{wallet_db_exists, LMDBBackend};
 pub use types::{WalletConfig, WalletSeed, SEED_FILE};
@@ -15,10 +15,10 @@
 //! interface into the underlying secp library
 use keychain::{BlindingFactor, Identifier, Keychain};
 use libtx::error::{Error, ErrorKind};
-use util::kernel_sig_msg;
 use util::secp::key::{PublicKey, SecretKey};
 use util::secp::pedersen::Commitment;
 use util::secp::{self, aggsig, Message, Secp256k1, Signature};
+use util::{kernel_sig_msg, Zeroize};
 
 /// exports a secure nonce guaranteed to be usable
 /// in aggsig creation
@@ -92,7 +92,7 @@ pub fn sign_from_key_id<K>(
 where
 	K: Keychain,
 {
-	let skey = k.derive_key(key_id)?;
+	let mut skey = k.derive_key(key_id)?;
 	let sig = aggsig::sign_single(
 		secp,
 		&msg,
@@ -102,8 +102,9 @@ where
 		None,
 		blind_sum,
 		None,
-	)?;
-	Ok(sig)
+	);
+	skey.zeroize();
+	Ok(sig?)
 }
 
 /// Verifies a sig given a commitment
@@ -173,8 +174,9 @@ pub fn sign_with_blinding(
 	blinding: &BlindingFactor,
 	pubkey_sum: Option<&PublicKey>,
 ) -> Result<Signature, Error> {
-	let skey = &blinding.secret_key(&secp)?;
+	let mut skey = blinding.secret_key(&secp)?;
 	//let pubkey_sum = PublicKey::from_secret_key(&secp, &skey)?;
-	let sig = aggsig::sign_single(secp, &msg, skey, None, None, None, pubkey_sum, None)?;
-	Ok(sig)
+	let sig = aggsig::sign_single(secp, &msg, &skey, None, None, None, pubkey_sum, None);
+	skey.zeroize();
+	Ok(sig?)
 }
@@ -0,0 +1,116 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact armored encoding of slates, small enough to paste into a chat or
+//! email. The binary slate is prefixed with a format version, followed by a
+//! checksum, base64 encoded and wrapped between a header and footer line:
+//!
+//! ```text
+//! BEGIN GRIN SLATE.
+//! AQAAAAAAAAACkP...
+//! END GRIN SLATE.
+//! ```
+
+use base64;
+use blake2::blake2b::blake2b;
+use serde_json;
+
+use core::ser;
+use libtx::error::{Error, ErrorKind};
+use libtx::slate::Slate;
+
+/// First line of an armored slate
+pub const ARMOR_HEADER: &'static str = "BEGIN GRIN SLATE.";
+/// Last line of an armored slate
+pub const ARMOR_FOOTER: &'static str = "END GRIN SLATE.";
+
+/// Version of the binary slate format
+const ARMOR_VERSION: u8 = 1;
+/// Number of checksum bytes appended to the binary slate
+const CHECKSUM_SIZE: usize = 4;
+/// Characters per line of the base64 body
+const LINE_LENGTH: usize = 64;
+
+fn checksum(data: &[u8]) -> Vec<u8> {
+	blake2b(CHECKSUM_SIZE, &[], data).as_bytes().to_vec()
+}
+
+/// Encode a slate in the armored format
+pub fn encode(slate: &Slate) -> Result<String, Error> {
+	let mut data = vec![ARMOR_VERSION];
+	data.extend(
+		ser::ser_vec(slate)
+			.map_err(|e| ErrorKind::SlateFormat(format!("Could not serialize slate: {:?}", e)))?,
+	);
+	let sum = checksum(&data);
+	data.extend(sum);
+
+	let body = base64::encode(&data);
+	let mut out = String::from(ARMOR_HEADER);
+	out.push('\n');
+	for line in body.as_bytes().chunks(LINE_LENGTH) {
+		// base64 is ascii, so any chunk is valid utf8
+		out.push_str(&String::from_utf8_lossy(line));
+		out.push('\n');
+	}
+	out.push_str(ARMOR_FOOTER);
+	out.push('\n');
+	Ok(out)
+}
+
+/// Decode an armored slate, verifying its checksum. Any text around the
+/// header and footer, as often comes along when pasting, is ignored.
+pub fn decode(armored: &str) -> Result<Slate, Error> {
+	let start = armored.find(ARMOR_HEADER);
+	let end = armored.rfind(ARMOR_FOOTER);
+	let body = match (start, end) {
+		(Some(s), Some(e)) if s + ARMOR_HEADER.len() <= e => &armored[s + ARMOR_HEADER.len()..e],
+		_ => {
+			return Err(ErrorKind::SlateFormat("Missing armor header or footer".to_owned()).into())
+		}
+	};
+	let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+	let data = base64::decode(&body)
+		.map_err(|_| ErrorKind::SlateFormat("Invalid base64 in armored slate".to_owned()))?;
+	if data.len() <= CHECKSUM_SIZE + 1 {
+		return Err(ErrorKind::SlateFormat("Armored slate is truncated".to_owned()).into());
+	}
+	let (data, sum) = data.split_at(data.len() - CHECKSUM_SIZE);
+	if checksum(data) != sum {
+		return Err(ErrorKind::SlateFormat("Armored slate checksum mismatch".to_owned()).into());
+	}
+	if data[0] != ARMOR_VERSION {
+		return Err(ErrorKind::SlateFormat(format!(
+			"Unsupported armored slate version {}",
+			data[0]
+		)).into());
+	}
+	ser::deserialize(&mut &data[1..])
+		.map_err(|e| ErrorKind::SlateFormat(format!("Could not deserialize slate: {:?}", e)).into())
+}
+
+/// Whether the content looks like an armored slate
+pub fn is_armored(content: &str) -> bool {
+	content.contains(ARMOR_HEADER)
+}
+
+/// Read a slate that's either armored or JSON
+pub fn slate_from_str(content: &str) -> Result<Slate, Error> {
+	if is_armored(content) {
+		decode(content)
+	} else {
+		serde_json::from_str(content)
+			.map_err(|e| ErrorKind::SlateFormat(format!("Invalid slate JSON: {}", e)).into())
+	}
+}
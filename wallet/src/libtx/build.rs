@@ -25,7 +25,7 @@
 //! build::transaction(vec![input_rand(75), output_rand(42), output_rand(32),
 //!   with_fee(1)])
 
-use util::{kernel_sig_msg, secp};
+use util::{kernel_sig_msg, secp, Zeroing};
 
 use core::core::{Input, Output, OutputFeatures, Transaction, TxKernel};
 use keychain::{self, BlindSum, BlindingFactor, Identifier, Keychain};
@@ -219,19 +219,19 @@ where
 		(Transaction::empty(), TxKernel::empty(), BlindSum::new()),
 		|acc, elem| elem(&mut ctx, acc),
 	);
-	let blind_sum = ctx.keychain.blind_sum(&sum)?;
+	let blind_sum = Zeroing::new(ctx.keychain.blind_sum(&sum)?);
 
 	// Split the key so we can generate an offset for the tx.
 	let split = blind_sum.split(&keychain.secp())?;
-	let k1 = split.blind_1;
+	let k1 = Zeroing::new(split.blind_1);
 	let k2 = split.blind_2;
 
 	// Construct the message to be signed.
 	let msg = secp::Message::from_slice(&kernel_sig_msg(kern.fee, kern.lock_height))?;
 
 	// Generate kernel excess and excess_sig using the split key k1.
-	let skey = k1.secret_key(&keychain.secp())?;
-	kern.excess = ctx.keychain.secp().commit(0, skey)?;
+	let skey = Zeroing::new(k1.secret_key(&keychain.secp())?);
+	kern.excess = ctx.keychain.secp().commit(0, *skey)?;
 	let pubkey = &kern.excess.to_pubkey(&keychain.secp())?;
 	kern.excess_sig =
 		aggsig::sign_with_blinding(&keychain.secp(), &msg, &k1, Some(&pubkey)).unwrap();
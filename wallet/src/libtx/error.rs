@@ -50,6 +50,9 @@ pub enum ErrorKind {
 	/// Error from summing commitments via committed trait.
 	#[fail(display = "Committed Error")]
 	Committed(committed::Error),
+	/// Slate could not be encoded or decoded
+	#[fail(display = "Slate Format Error: {}", _0)]
+	SlateFormat(String),
 }
 
 impl Fail for Error {
@@ -22,6 +22,7 @@
 #![warn(missing_docs)]
 
 pub mod aggsig;
+pub mod armor;
 pub mod build;
 mod error;
 pub mod proof;
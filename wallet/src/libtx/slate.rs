@@ -23,13 +23,14 @@ use uuid::Uuid;
 use core::core::committed::Committed;
 use core::core::verifier_cache::LruVerifierCache;
 use core::core::{amount_to_hr_string, Transaction};
+use core::ser::{self, Readable, Reader, Writeable, Writer};
 use keychain::{BlindSum, BlindingFactor, Keychain};
 use libtx::error::{Error, ErrorKind};
 use libtx::{aggsig, build, tx_fee};
 
-use util::secp;
 use util::secp::key::{PublicKey, SecretKey};
 use util::secp::Signature;
+use util::{secp, static_secp_instance, Zeroing};
 
 /// Public data for each participant in the slate
 
@@ -62,6 +63,55 @@ impl ParticipantData {
 	}
 }
 
+impl Writeable for ParticipantData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		writer.write_u64(self.id)?;
+		writer.write_fixed_bytes(&self.public_blind_excess.serialize_vec(&secp, true).to_vec())?;
+		writer.write_fixed_bytes(&self.public_nonce.serialize_vec(&secp, true).to_vec())?;
+		match self.part_sig {
+			Some(ref sig) => {
+				writer.write_u8(1)?;
+				sig.write(writer)
+			}
+			None => writer.write_u8(0),
+		}
+	}
+}
+
+impl Readable for ParticipantData {
+	fn read(reader: &mut Reader) -> Result<ParticipantData, ser::Error> {
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		let id = reader.read_u64()?;
+		let public_blind_excess = PublicKey::from_slice(
+			&secp,
+			&reader.read_fixed_bytes(secp::constants::COMPRESSED_PUBLIC_KEY_SIZE)?,
+		).map_err(|_| ser::Error::CorruptedData)?;
+		let public_nonce = PublicKey::from_slice(
+			&secp,
+			&reader.read_fixed_bytes(secp::constants::COMPRESSED_PUBLIC_KEY_SIZE)?,
+		).map_err(|_| ser::Error::CorruptedData)?;
+		let part_sig = match reader.read_u8()? {
+			0 => None,
+			1 => Some(Signature::read(reader)?),
+			_ => return Err(ser::Error::CorruptedData),
+		};
+		Ok(ParticipantData {
+			id,
+			public_blind_excess,
+			public_nonce,
+			part_sig,
+		})
+	}
+}
+
+/// Version of the slate format built and accepted by this wallet. The slate
+/// doesn't carry it yet, so a sender checks it against the versions the
+/// receiver supports before sending.
+pub const SLATE_VERSION: u16 = 0;
+
 /// A 'Slate' is passed around to all parties to build up all of the public
 /// transaction data needed to create a finalized transaction. Callers can pass
 /// the slate around by whatever means they choose, (but we can provide some
@@ -90,6 +140,56 @@ pub struct Slate {
 	pub participant_data: Vec<ParticipantData>,
 }
 
+/// Compact binary serialization, used by the armored slate encoding
+impl Writeable for Slate {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.num_participants as u64)?;
+		writer.write_fixed_bytes(&self.id.as_bytes().to_vec())?;
+		self.tx.write(writer)?;
+		writer.write_u64(self.amount)?;
+		writer.write_u64(self.fee)?;
+		writer.write_u64(self.height)?;
+		writer.write_u64(self.lock_height)?;
+		writer.write_u64(self.participant_data.len() as u64)?;
+		for p in &self.participant_data {
+			p.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for Slate {
+	fn read(reader: &mut Reader) -> Result<Slate, ser::Error> {
+		let num_participants = reader.read_u64()? as usize;
+		let id = Uuid::from_bytes(&reader.read_fixed_bytes(16)?)
+			.map_err(|_| ser::Error::CorruptedData)?;
+		let tx = Transaction::read(reader)?;
+		let amount = reader.read_u64()?;
+		let fee = reader.read_u64()?;
+		let height = reader.read_u64()?;
+		let lock_height = reader.read_u64()?;
+		let num_data = reader.read_u64()?;
+		// no more participants than the slate is meant for
+		if num_data > num_participants as u64 {
+			return Err(ser::Error::CorruptedData);
+		}
+		let mut participant_data = vec![];
+		for _ in 0..num_data {
+			participant_data.push(ParticipantData::read(reader)?);
+		}
+		Ok(Slate {
+			num_participants,
+			id,
+			tx,
+			amount,
+			fee,
+			height,
+			lock_height,
+			participant_data,
+		})
+	}
+}
+
 impl Slate {
 	/// Create a new slate
 	pub fn blank(num_participants: usize) -> Slate {
@@ -257,11 +357,11 @@ impl Slate {
 		// the aggsig context with the "split" key
 		self.tx.offset =
 			BlindingFactor::from_secret_key(SecretKey::new(&keychain.secp(), &mut thread_rng()));
-		let blind_offset = keychain.blind_sum(
+		let blind_offset = Zeroing::new(keychain.blind_sum(
 			&BlindSum::new()
 				.add_blinding_factor(BlindingFactor::from_secret_key(sec_key.clone()))
 				.sub_blinding_factor(self.tx.offset),
-		)?;
+		)?);
 		*sec_key = blind_offset.secret_key(&keychain.secp())?;
 		Ok(())
 	}
@@ -17,9 +17,12 @@
 //! vs. functions to interact with someone else)
 //! Still experimental, not sure this is the best way to do this
 
+use chrono::prelude::*;
+use chrono::Duration;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::marker::PhantomData;
+use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use util::Mutex;
 
@@ -29,11 +32,14 @@ use core::core::hash::Hashed;
 use core::core::Transaction;
 use core::ser;
 use keychain::{Identifier, Keychain};
-use libtx::slate::Slate;
-use libwallet::internal::{keys, selection, tx, updater};
+use libtx::armor;
+use libtx::slate::{Slate, SLATE_VERSION};
+use libwallet::internal::{cold, contacts, history, keys, selection, tx, updater};
 use libwallet::types::{
-	AcctPathMapping, BlockFees, CbData, OutputData, TxLogEntry, TxWrapper, WalletBackend,
-	WalletClient, WalletInfo,
+	AcctPathMapping, BalanceSnapshot, BlockFees, CbData, CheckFixes, CheckReport, Contact,
+	Features, OutputData, ReceiveStatus, ScanProgress, SendEstimate, SigningRequest, SlateAdapter,
+	TxExportEntry, TxLogEntry, TxWrapper, VersionInfo, WalletBackend, WalletClient, WalletInfo,
+	WatchedOutput, FOREIGN_API_VERSION,
 };
 use libwallet::{Error, ErrorKind};
 use util;
@@ -121,6 +127,32 @@ where
 		res
 	}
 
+	/// Export the transaction log, optionally limited to transactions
+	/// created within the given time range, for accounting purposes
+	pub fn export_txs(
+		&self,
+		refresh_from_node: bool,
+		from: Option<DateTime<Utc>>,
+		to: Option<DateTime<Utc>>,
+	) -> Result<(bool, Vec<TxExportEntry>), Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+
+		let mut validated = false;
+		if refresh_from_node {
+			validated = self.update_outputs(&mut w);
+		}
+
+		let res = Ok((
+			validated,
+			updater::export_txs(&mut **w, from, to, &parent_key_id)?,
+		));
+
+		w.close()?;
+		res
+	}
+
 	/// Retrieve summary info for wallet
 	pub fn retrieve_summary_info(
 		&mut self,
@@ -142,6 +174,37 @@ where
 		res
 	}
 
+	/// Retrieve the balance history of the current account, oldest first.
+	/// One snapshot per day, or per block if per_block is set (only recorded
+	/// once turned on with `set_snapshots_per_block`).
+	pub fn retrieve_balance_history(
+		&mut self,
+		refresh_from_node: bool,
+		per_block: bool,
+	) -> Result<(bool, Vec<BalanceSnapshot>), Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+
+		let mut validated = false;
+		if refresh_from_node {
+			validated = self.update_outputs(&mut w);
+		}
+
+		let snapshots = history::balance_history(&mut **w, &parent_key_id, per_block)?;
+		let res = Ok((validated, snapshots));
+
+		w.close()?;
+		res
+	}
+
+	/// Sets whether to record a balance snapshot for every block the wallet
+	/// refreshes at, on top of the daily ones
+	pub fn set_snapshots_per_block(&mut self, per_block: bool) -> Result<(), Error> {
+		let mut w = self.wallet.lock();
+		history::set_snapshots_per_block(&mut **w, per_block)
+	}
+
 	/// Return list of existing account -> Path mappings
 	pub fn accounts(&mut self) -> Result<Vec<AcctPathMapping>, Error> {
 		let mut w = self.wallet.lock();
@@ -154,6 +217,84 @@ where
 		keys::new_acct_path(&mut **w, label)
 	}
 
+	/// Estimate the fee, inputs spent and resulting balance of a send
+	/// without locking any outputs or creating a slate
+	pub fn estimate_send_tx(
+		&mut self,
+		amount: u64,
+		minimum_confirmations: u64,
+		max_outputs: usize,
+		num_change_outputs: usize,
+		selection_strategy_is_use_all: bool,
+	) -> Result<SendEstimate, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = tx::estimate_send_tx(
+			&mut **w,
+			amount,
+			minimum_confirmations,
+			max_outputs,
+			num_change_outputs,
+			selection_strategy_is_use_all,
+			&parent_key_id,
+		);
+		w.close()?;
+		res
+	}
+
+	/// Number of change outputs a send of the given amount should be split
+	/// into for each to be at least `target` in size, for passing as
+	/// `num_change_outputs` when sending
+	pub fn change_outputs_for_target(
+		&mut self,
+		amount: u64,
+		minimum_confirmations: u64,
+		max_outputs: usize,
+		selection_strategy_is_use_all: bool,
+		target: u64,
+	) -> Result<usize, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = tx::change_outputs_for_target(
+			&mut **w,
+			amount,
+			minimum_confirmations,
+			max_outputs,
+			selection_strategy_is_use_all,
+			&parent_key_id,
+			target,
+		);
+		w.close()?;
+		res
+	}
+
+	/// Returns all contacts in the wallet's address book
+	pub fn contacts(&mut self) -> Result<Vec<Contact>, Error> {
+		let mut w = self.wallet.lock();
+		contacts::contacts(&mut **w)
+	}
+
+	/// Add a named destination to the address book, which can then be sent
+	/// to as `@name`
+	pub fn add_contact(&mut self, name: &str, method: &str, dest: &str) -> Result<(), Error> {
+		let mut w = self.wallet.lock();
+		contacts::add_contact(&mut **w, name, method, dest)
+	}
+
+	/// Remove a contact from the address book
+	pub fn remove_contact(&mut self, name: &str) -> Result<(), Error> {
+		let mut w = self.wallet.lock();
+		contacts::remove_contact(&mut **w, name)
+	}
+
+	/// Look up the contact a send destination refers to, if any
+	pub fn resolve_contact(&mut self, dest: &str) -> Result<Option<Contact>, Error> {
+		let mut w = self.wallet.lock();
+		contacts::resolve(&mut **w, dest)
+	}
+
 	/// Issues a send transaction and sends to recipient
 	pub fn issue_send_tx(
 		&mut self,
@@ -164,15 +305,83 @@ where
 		num_change_outputs: usize,
 		selection_strategy_is_use_all: bool,
 	) -> Result<Slate, Error> {
+		let client = self.wallet.lock().client().clone();
+		self.issue_send_tx_with(
+			amount,
+			minimum_confirmations,
+			dest,
+			max_outputs,
+			num_change_outputs,
+			selection_strategy_is_use_all,
+			|dest, slate| client.send_tx_slate(dest, slate),
+		)
+	}
+
+	/// Issues a send transaction, carrying the slate to the recipient with a
+	/// slate adapter. The response from a synchronous adapter is completed
+	/// straight away as with `issue_send_tx`, while with an asynchronous one
+	/// it comes back later through `finalize_tx`, as with `send_tx`.
+	pub fn send_tx_via_adapter(
+		&mut self,
+		adapter: &SlateAdapter,
+		amount: u64,
+		minimum_confirmations: u64,
+		dest: &str,
+		max_outputs: usize,
+		num_change_outputs: usize,
+		selection_strategy_is_use_all: bool,
+	) -> Result<Slate, Error> {
+		if adapter.supports_sync() {
+			self.issue_send_tx_with(
+				amount,
+				minimum_confirmations,
+				dest,
+				max_outputs,
+				num_change_outputs,
+				selection_strategy_is_use_all,
+				|dest, slate| adapter.send_tx_sync(dest, slate),
+			)
+		} else {
+			self.send_tx_with(
+				amount,
+				minimum_confirmations,
+				dest,
+				max_outputs,
+				num_change_outputs,
+				selection_strategy_is_use_all,
+				|dest, slate| adapter.send_tx_async(dest, slate),
+			)
+		}
+	}
+
+	/// Creates a send transaction, exchanges the slate with the recipient
+	/// through `send` and completes it with their response
+	fn issue_send_tx_with<F>(
+		&mut self,
+		amount: u64,
+		minimum_confirmations: u64,
+		dest: &str,
+		max_outputs: usize,
+		num_change_outputs: usize,
+		selection_strategy_is_use_all: bool,
+		send: F,
+	) -> Result<Slate, Error>
+	where
+		F: FnOnce(&str, &Slate) -> Result<Slate, Error>,
+	{
 		let mut w = self.wallet.lock();
 		w.open_with_credentials()?;
 		let parent_key_id = w.parent_key_id();
 
-		let client;
+		let contact = contacts::resolve(&mut **w, dest)?;
+		let dest = match contact {
+			Some(ref c) => c.dest.clone(),
+			None => dest.to_owned(),
+		};
+
 		let mut slate_out: Slate;
 		let lock_fn_out;
 
-		client = w.client().clone();
 		let (slate, context, lock_fn) = tx::create_send_tx(
 			&mut **w,
 			amount,
@@ -185,22 +394,29 @@ where
 		)?;
 
 		lock_fn_out = lock_fn;
-		slate_out = match client.send_tx_slate(dest, &slate) {
+		slate_out = match send(&dest, &slate) {
 			Ok(s) => s,
 			Err(e) => {
 				error!(
 				"Communication with receiver failed on SenderInitiation send. Aborting transaction {:?}",
 				e,
 			);
+				selection::release_output_locks(&mut **w, &slate.id)?;
 				return Err(e)?;
 			}
 		};
 
-		tx::complete_tx(&mut **w, &mut slate_out, &context)?;
+		if let Err(e) = tx::complete_tx(&mut **w, &mut slate_out, &context) {
+			selection::release_output_locks(&mut **w, &slate.id)?;
+			return Err(e);
+		}
 		let tx_hex = util::to_hex(ser::ser_vec(&slate_out.tx).unwrap());
 
 		// lock our inputs
 		lock_fn_out(&mut **w, &tx_hex)?;
+		if let Some(c) = contact {
+			contacts::set_tx_contact(&mut **w, &slate_out.id, &c.name)?;
+		}
 		w.close()?;
 		Ok(slate_out)
 	}
@@ -249,6 +465,111 @@ where
 		Ok(slate)
 	}
 
+	/// Merges many small outputs into fewer larger ones by sending them to
+	/// self, in as many transactions as it takes to stay within `max_inputs`
+	/// per transaction (see `selection::consolidation_batches`). Returns the
+	/// completed slates, to be posted. If any batch fails, the transactions
+	/// of the ones already completed are cancelled, so nothing stays locked.
+	pub fn consolidate(
+		&mut self,
+		minimum_confirmations: u64,
+		threshold: Option<u64>,
+		min_outputs: usize,
+		max_inputs: usize,
+	) -> Result<Vec<Slate>, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = tx::consolidate(
+			&mut **w,
+			minimum_confirmations,
+			threshold,
+			min_outputs,
+			max_inputs,
+			&parent_key_id,
+		);
+		w.close()?;
+		res
+	}
+
+	/// Selects and locks the inputs of a send for the offline wallet holding
+	/// the keys to sign, as the first step of a watch-only send (see
+	/// `internal::cold`)
+	pub fn signing_request(
+		&mut self,
+		amount: u64,
+		minimum_confirmations: u64,
+		max_outputs: usize,
+		num_change_outputs: usize,
+		selection_strategy_is_use_all: bool,
+	) -> Result<SigningRequest, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = cold::signing_request(
+			&mut **w,
+			amount,
+			minimum_confirmations,
+			max_outputs,
+			num_change_outputs,
+			selection_strategy_is_use_all,
+			&parent_key_id,
+		);
+		w.close()?;
+		res
+	}
+
+	/// Signs a watch-only wallet's signing request, returning the slate to
+	/// send to the recipient. Doesn't need a node.
+	pub fn sign_request(&mut self, req: &SigningRequest) -> Result<Slate, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = cold::sign_request(&mut **w, req, &parent_key_id);
+		w.close()?;
+		res
+	}
+
+	/// Adds our signature to the recipient's response to a slate created by
+	/// `sign_request`, for the watch-only wallet to finalize
+	pub fn sign_response(&mut self, slate: &mut Slate) -> Result<(), Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let res = cold::sign_response(&mut **w, slate);
+		w.close()?;
+		res
+	}
+
+	/// Finalizes a slate signed by the offline wallet, ready to be posted
+	pub fn finalize_signed(&mut self, slate: &mut Slate) -> Result<(), Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = cold::finalize_signed(&mut **w, slate, &parent_key_id);
+		w.close()?;
+		res
+	}
+
+	/// Exports our outputs with their commitments, for a watch-only wallet
+	pub fn export_outputs(&mut self) -> Result<Vec<WatchedOutput>, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = cold::export_outputs(&mut **w, &parent_key_id);
+		w.close()?;
+		res
+	}
+
+	/// Imports the outputs exported by the offline wallet, returning how many
+	/// are new
+	pub fn import_outputs(&mut self, outputs: Vec<WatchedOutput>) -> Result<usize, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let res = cold::import_outputs(&mut **w, outputs);
+		w.close()?;
+		res
+	}
+
 	/// Write a transaction to send to file so a user can transmit it to the
 	/// receiver in whichever way they see fit (aka carrier pigeon mode).
 	pub fn send_tx(
@@ -261,10 +582,49 @@ where
 		num_change_outputs: usize,
 		selection_strategy_is_use_all: bool,
 	) -> Result<Slate, Error> {
+		self.send_tx_with(
+			amount,
+			minimum_confirmations,
+			dest,
+			max_outputs,
+			num_change_outputs,
+			selection_strategy_is_use_all,
+			|dest, slate| {
+				if write_to_disk {
+					let mut pub_tx = File::create(dest)?;
+					pub_tx.write_all(json::to_string(slate).unwrap().as_bytes())?;
+					pub_tx.sync_all()?;
+				}
+				Ok(())
+			},
+		)
+	}
+
+	/// Creates a send transaction, handing the slate to `send` for delivery
+	/// to the recipient, whose response is finalized later
+	fn send_tx_with<F>(
+		&mut self,
+		amount: u64,
+		minimum_confirmations: u64,
+		dest: &str,
+		max_outputs: usize,
+		num_change_outputs: usize,
+		selection_strategy_is_use_all: bool,
+		send: F,
+	) -> Result<Slate, Error>
+	where
+		F: FnOnce(&str, &Slate) -> Result<(), Error>,
+	{
 		let mut w = self.wallet.lock();
 		w.open_with_credentials()?;
 		let parent_key_id = w.parent_key_id();
 
+		let contact = contacts::resolve(&mut **w, dest)?;
+		let dest = match contact {
+			Some(ref c) => c.dest.clone(),
+			None => dest.to_owned(),
+		};
+
 		let (slate, context, lock_fn) = tx::create_send_tx(
 			&mut **w,
 			amount,
@@ -275,11 +635,7 @@ where
 			&parent_key_id,
 			false,
 		)?;
-		if write_to_disk {
-			let mut pub_tx = File::create(dest)?;
-			pub_tx.write_all(json::to_string(&slate).unwrap().as_bytes())?;
-			pub_tx.sync_all()?;
-		}
+		send(&dest, &slate)?;
 
 		{
 			let mut batch = w.batch()?;
@@ -291,6 +647,9 @@ where
 
 		// lock our inputs
 		lock_fn(&mut **w, &tx_hex)?;
+		if let Some(c) = contact {
+			contacts::set_tx_contact(&mut **w, &slate.id, &c.name)?;
+		}
 		w.close()?;
 		Ok(slate)
 	}
@@ -305,6 +664,7 @@ where
 
 		let context = w.get_private_context(slate.id.as_bytes())?;
 		tx::complete_tx(&mut **w, slate, &context)?;
+		tx::update_stored_tx(&mut **w, slate)?;
 		{
 			let mut batch = w.batch()?;
 			batch.delete_private_context(slate.id.as_bytes())?;
@@ -334,6 +694,50 @@ where
 		Ok(())
 	}
 
+	/// Cancel pending sends whose slates haven't been finalized within `ttl`
+	/// of their creation, unlocking their outputs. With `dry_run` only the
+	/// transactions that would be cancelled are returned.
+	pub fn cancel_expired_txs(
+		&mut self,
+		ttl: Duration,
+		dry_run: bool,
+	) -> Result<Vec<TxLogEntry>, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		if !self.update_outputs(&mut w) {
+			return Err(ErrorKind::TransactionCancellationError(
+				"Can't contact running Grin node. Not Cancelling.",
+			))?;
+		}
+		let res = tx::cancel_expired_txs(&mut **w, &parent_key_id, ttl, dry_run);
+		w.close()?;
+		res
+	}
+
+	/// Payments received and confirmed on the chain that weren't notified
+	/// about yet, after refreshing the wallet from the node. The boolean
+	/// tells whether the refresh went through.
+	pub fn unnotified_receipts(&mut self) -> Result<(bool, Vec<TxLogEntry>), Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let refreshed = self.update_outputs(&mut w);
+		let res = tx::unnotified_receipts(&mut **w, &parent_key_id);
+		w.close()?;
+		Ok((refreshed, res?))
+	}
+
+	/// Marks a payment received as notified about, so it's not again
+	pub fn mark_notified(&mut self, tx_id: u32) -> Result<(), Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		let res = tx::mark_notified(&mut **w, &parent_key_id, tx_id);
+		w.close()?;
+		res
+	}
+
 	/// Issue a burn TX
 	pub fn issue_burn_tx(
 		&mut self,
@@ -385,13 +789,19 @@ where
 		write_to_disk: bool,
 		dest: &str,
 	) -> Result<Transaction, Error> {
-		let (confirmed, tx_hex) = {
+		let (confirmed, tx) = {
 			let mut w = self.wallet.lock();
 			w.open_with_credentials()?;
 			let parent_key_id = w.parent_key_id();
-			let res = tx::retrieve_tx_hex(&mut **w, &parent_key_id, tx_id)?;
+			let res = tx::retrieve_stored_tx(&mut **w, &parent_key_id, tx_id);
 			w.close()?;
-			res
+			match res {
+				Ok(r) => r,
+				Err(e) => {
+					error!("api: dump_stored_tx: can't get stored tx {}: {}", tx_id, e);
+					return Err(e);
+				}
+			}
 		};
 		if confirmed {
 			warn!(
@@ -399,15 +809,6 @@ where
 				tx_id
 			);
 		}
-		if tx_hex.is_none() {
-			error!(
-				"api: dump_stored_tx: completed transaction at {} does not exist.",
-				tx_id
-			);
-			return Err(ErrorKind::TransactionBuildingNotCompleted(tx_id))?;
-		}
-		let tx_bin = util::from_hex(tx_hex.unwrap()).unwrap();
-		let tx = ser::deserialize::<Transaction>(&mut &tx_bin[..])?;
 		if write_to_disk {
 			let mut tx_file = File::create(dest)?;
 			tx_file.write_all(json::to_string(&tx).unwrap().as_bytes())?;
@@ -416,17 +817,24 @@ where
 		Ok(tx)
 	}
 
-	/// (Re)Posts a transaction that's already been stored to the chain
+	/// (Re)Posts a transaction that's already been stored to the chain, the
+	/// finalized transaction being rebuilt from the tx log and checked first
 	pub fn post_stored_tx(&self, tx_id: u32, fluff: bool) -> Result<(), Error> {
 		let client;
-		let (confirmed, tx_hex) = {
+		let (confirmed, tx) = {
 			let mut w = self.wallet.lock();
 			w.open_with_credentials()?;
 			let parent_key_id = w.parent_key_id();
 			client = w.client().clone();
-			let res = tx::retrieve_tx_hex(&mut **w, &parent_key_id, tx_id)?;
+			let res = tx::retrieve_stored_tx(&mut **w, &parent_key_id, tx_id);
 			w.close()?;
-			res
+			match res {
+				Ok(r) => r,
+				Err(e) => {
+					error!("api: repost_tx: can't get stored tx {}: {}", tx_id, e);
+					return Err(e);
+				}
+			}
 		};
 		if confirmed {
 			error!(
@@ -435,27 +843,18 @@ where
 			);
 			return Err(ErrorKind::TransactionAlreadyConfirmed)?;
 		}
-		if tx_hex.is_none() {
-			error!(
-				"api: repost_tx: completed transaction at {} does not exist.",
-				tx_id
-			);
-			return Err(ErrorKind::TransactionBuildingNotCompleted(tx_id))?;
-		}
 
-		let res = client.post_tx(
-			&TxWrapper {
-				tx_hex: tx_hex.unwrap(),
-			},
-			fluff,
-		);
+		let tx_hex = util::to_hex(ser::ser_vec(&tx).unwrap());
+		let res = client.post_tx(&TxWrapper { tx_hex: tx_hex }, fluff);
 		if let Err(e) = res {
 			error!("api: repost_tx: failed with error: {}", e);
 			Err(e)
 		} else {
 			debug!(
-				"api: repost_tx: successfully posted tx at: {}, fluff? {}",
-				tx_id, fluff
+				"api: repost_tx: successfully posted tx {} at: {}, fluff? {}",
+				tx.hash(),
+				tx_id,
+				fluff
 			);
 			Ok(())
 		}
@@ -465,7 +864,44 @@ where
 	pub fn restore(&mut self) -> Result<(), Error> {
 		let mut w = self.wallet.lock();
 		w.open_with_credentials()?;
-		let res = w.restore();
+		let res = w.restore(None);
+		w.close()?;
+		res
+	}
+
+	/// Attempt to restore contents of wallet, sending a progress report
+	/// down the given channel as the node's output set is scanned
+	pub fn restore_with_progress(&mut self, progress: Sender<ScanProgress>) -> Result<(), Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let res = w.restore(Some(progress));
+		w.close()?;
+		res
+	}
+
+	/// Check the wallet's outputs against the chain, reporting outputs
+	/// missing on either side, locked outputs whose spend confirmed and
+	/// derivation indices about to be reused. The categories set in `fixes`
+	/// are repaired.
+	pub fn check_repair(&mut self, fixes: CheckFixes) -> Result<CheckReport, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let res = w.check_repair(&fixes, None);
+		w.close()?;
+		res
+	}
+
+	/// Check the wallet's outputs against the chain as `check_repair` does,
+	/// sending a progress report down the given channel after each chunk of
+	/// the output set scanned
+	pub fn check_repair_with_progress(
+		&mut self,
+		fixes: CheckFixes,
+		progress: Sender<ScanProgress>,
+	) -> Result<CheckReport, Error> {
+		let mut w = self.wallet.lock();
+		w.open_with_credentials()?;
+		let res = w.check_repair(&fixes, Some(progress));
 		w.close()?;
 		res
 	}
@@ -530,6 +966,27 @@ where
 		})
 	}
 
+	/// Versions and features of the foreign API the wallet accepts, and
+	/// whether it can receive at all, for a sender to check first
+	pub fn check_version(&mut self) -> Result<VersionInfo, Error> {
+		let mut w = self.wallet.lock();
+		let receive_status = if w.is_watch_only() {
+			ReceiveStatus::WatchOnly
+		} else if w.open_with_credentials().is_err() {
+			ReceiveStatus::Locked
+		} else {
+			w.close()?;
+			ReceiveStatus::Ready
+		};
+		Ok(VersionInfo {
+			foreign_api_version: FOREIGN_API_VERSION,
+			supported_slate_versions: vec![SLATE_VERSION],
+			// neither flow is implemented yet
+			features: Features::default(),
+			receive_status,
+		})
+	}
+
 	/// Build a new (potential) coinbase transaction in the wallet
 	pub fn build_coinbase(&mut self, block_fees: &BlockFees) -> Result<CbData, Error> {
 		let mut w = self.wallet.lock();
@@ -541,12 +998,12 @@ where
 
 	/// A sender provided a transaction file with appropriate public keys and
 	/// metadata. Complete the receivers' end of it to generate another file
-	/// to send back.
+	/// to send back, armored if the sender's file was.
 	pub fn file_receive_tx(&mut self, source: &str) -> Result<(), Error> {
 		let mut pub_tx_f = File::open(source)?;
 		let mut content = String::new();
 		pub_tx_f.read_to_string(&mut content)?;
-		let mut slate: Slate = json::from_str(&content).map_err(|_| ErrorKind::Format)?;
+		let mut slate = armor::slate_from_str(&content)?;
 
 		let mut wallet = self.wallet.lock();
 		wallet.open_with_credentials()?;
@@ -572,8 +1029,13 @@ where
 		let _ = slate.fill_round_2(wallet.keychain(), &context.sec_key, &context.sec_nonce, 1)?;
 
 		// save to file
+		let response = if armor::is_armored(&content) {
+			armor::encode(&slate)?
+		} else {
+			json::to_string(&slate).unwrap()
+		};
 		let mut pub_tx = File::create(source.to_owned() + ".response")?;
-		pub_tx.write_all(json::to_string(&slate).unwrap().as_bytes())?;
+		pub_tx.write_all(response.as_bytes())?;
 
 		// Save output in wallet
 		let _ = receiver_create_fn(&mut wallet);
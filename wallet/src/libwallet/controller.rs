@@ -24,8 +24,11 @@ use hyper::{Body, Request, Response, StatusCode};
 use keychain::Keychain;
 use libtx::slate::Slate;
 use libwallet::api::{APIForeign, APIOwner};
+use libwallet::events::{self, TxEventHub, TX_EVENTS_REFRESH_SECS};
+use libwallet::jsonrpc;
 use libwallet::types::{
-	CbData, OutputData, SendTXArgs, TxLogEntry, WalletBackend, WalletClient, WalletInfo,
+	BalanceSnapshot, CbData, OutputData, ScanProgress, SendEstimate, SendTXArgs, TxLogEntry,
+	VersionInfo, WalletBackend, WalletClient, WalletInfo,
 };
 use libwallet::{Error, ErrorKind};
 use serde::{Deserialize, Serialize};
@@ -33,11 +36,14 @@ use serde_json;
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
 use url::form_urlencoded;
 use util::secp::pedersen;
 use util::to_base64;
-use util::Mutex;
+use util::{Mutex, RwLock};
 
 /// Instantiate wallet Owner API for a single-use (command line) call
 /// Return a function containing a loaded API context to call
@@ -80,7 +86,12 @@ where
 	K: Keychain + 'static,
 {
 	let wallet_arc = Arc::new(Mutex::new(wallet));
-	let api_handler = OwnerAPIHandler::new(wallet_arc);
+	let api_handler = OwnerAPIHandler::new(wallet_arc.clone());
+	events::start(
+		wallet_arc,
+		api_handler.tx_events.clone(),
+		Duration::from_secs(TX_EVENTS_REFRESH_SECS),
+	)?;
 
 	let mut router = Router::new();
 	if api_secret.is_some() {
@@ -108,9 +119,10 @@ where
 }
 
 /// Listener version, providing same API but listening for requests on a
-/// port and wrapping the calls
+/// port and wrapping the calls. The wallet can be shared with a watcher
+/// checking for the payments received.
 pub fn foreign_listener<T: ?Sized, C, K>(
-	wallet: Box<T>,
+	wallet: Arc<Mutex<Box<T>>>,
 	addr: &str,
 	tls_config: Option<TLSConfig>,
 ) -> Result<(), Error>
@@ -119,7 +131,7 @@ where
 	C: WalletClient + 'static,
 	K: Keychain + 'static,
 {
-	let api_handler = ForeignAPIHandler::new(Arc::new(Mutex::new(wallet)));
+	let api_handler = ForeignAPIHandler::new(wallet);
 
 	let mut router = Router::new();
 	router
@@ -142,6 +154,12 @@ where
 
 type WalletResponseFuture = Box<Future<Item = Response<Body>, Error = Error> + Send>;
 
+/// Seconds a request for transaction events waits for one by default
+const TX_EVENTS_TIMEOUT_SECS: u64 = 30;
+
+/// Most seconds a request for transaction events can wait for one
+const MAX_TX_EVENTS_TIMEOUT_SECS: u64 = 120;
+
 /// API Handler/Wrapper for owner functions
 pub struct OwnerAPIHandler<T: ?Sized, C, K>
 where
@@ -151,6 +169,10 @@ where
 {
 	/// Wallet instance
 	pub wallet: Arc<Mutex<Box<T>>>,
+	/// Latest progress report from a restore started through this handler
+	restore_progress: Arc<RwLock<Option<ScanProgress>>>,
+	/// Changes to the transactions of the wallet, for clients to wait on
+	pub tx_events: Arc<TxEventHub>,
 	phantom: PhantomData<K>,
 	phantom_c: PhantomData<C>,
 }
@@ -165,6 +187,8 @@ where
 	pub fn new(wallet: Arc<Mutex<Box<T>>>) -> OwnerAPIHandler<T, C, K> {
 		OwnerAPIHandler {
 			wallet,
+			restore_progress: Arc::new(RwLock::new(None)),
+			tx_events: Arc::new(TxEventHub::new()),
 			phantom: PhantomData,
 			phantom_c: PhantomData,
 		}
@@ -253,6 +277,16 @@ where
 		api.retrieve_summary_info(update_from_node)
 	}
 
+	fn retrieve_balance_history(
+		&self,
+		req: &Request<Body>,
+		mut api: APIOwner<T, C, K>,
+	) -> Result<(bool, Vec<BalanceSnapshot>), Error> {
+		let update_from_node = param_exists(req, "refresh");
+		let per_block = param_exists(req, "per_block");
+		api.retrieve_balance_history(update_from_node, per_block)
+	}
+
 	fn node_height(
 		&self,
 		_req: &Request<Body>,
@@ -261,6 +295,32 @@ where
 		api.node_height()
 	}
 
+	fn restore_progress(&self, _req: &Request<Body>) -> Option<ScanProgress> {
+		self.restore_progress.read().clone()
+	}
+
+	fn tx_events(&self, req: &Request<Body>) -> ResponseFuture {
+		let params = parse_params(req);
+		let since = params
+			.get("since")
+			.and_then(|v| v[0].parse().ok())
+			.unwrap_or(0);
+		let timeout = params
+			.get("timeout")
+			.and_then(|v| v[0].parse().ok())
+			.unwrap_or(TX_EVENTS_TIMEOUT_SECS)
+			.min(MAX_TX_EVENTS_TIMEOUT_SECS);
+		let tx_events = self.tx_events.clone();
+		let timeout = Delay::new(Instant::now() + Duration::from_secs(timeout));
+		Box::new(
+			tx_events
+				.wait(since)
+				.map_err(|_| ())
+				.select(timeout.map_err(|_| ()))
+				.then(move |_| ok(json_response(&tx_events.since(since)))),
+		)
+	}
+
 	fn handle_get_request(&self, req: &Request<Body>) -> Result<Response<Body>, Error> {
 		let api = APIOwner::new(self.wallet.clone());
 
@@ -274,9 +334,13 @@ where
 		{
 			"retrieve_outputs" => json_response(&self.retrieve_outputs(req, api)?),
 			"retrieve_summary_info" => json_response(&self.retrieve_summary_info(req, api)?),
+			"retrieve_balance_history" => {
+				json_response(&self.retrieve_balance_history(req, api)?)
+			}
 			"node_height" => json_response(&self.node_height(req, api)?),
 			"retrieve_txs" => json_response(&self.retrieve_txs(req, api)?),
 			"dump_stored_tx" => json_response(&self.dump_stored_tx(req, api)?),
+			"restore_progress" => json_response(&self.restore_progress(req)),
 			_ => response(StatusCode::BAD_REQUEST, ""),
 		})
 	}
@@ -287,13 +351,14 @@ where
 		mut api: APIOwner<T, C, K>,
 	) -> Box<Future<Item = Slate, Error = Error> + Send> {
 		Box::new(parse_body(req).and_then(move |args: SendTXArgs| {
+			let num_change_outputs = change_outputs(&mut api, &args)?;
 			if args.method == "http" {
 				api.issue_send_tx(
 					args.amount,
 					args.minimum_confirmations,
 					&args.dest,
 					args.max_outputs,
-					args.num_change_outputs,
+					num_change_outputs,
 					args.selection_strategy_is_use_all,
 				)
 			} else if args.method == "file" {
@@ -303,7 +368,7 @@ where
 					args.minimum_confirmations,
 					&args.dest,
 					args.max_outputs,
-					args.num_change_outputs,
+					num_change_outputs,
 					args.selection_strategy_is_use_all,
 				)
 			} else {
@@ -313,14 +378,35 @@ where
 		}))
 	}
 
+	fn estimate_send_tx(
+		&self,
+		req: Request<Body>,
+		mut api: APIOwner<T, C, K>,
+	) -> Box<Future<Item = SendEstimate, Error = Error> + Send> {
+		Box::new(parse_body(req).and_then(move |args: SendTXArgs| {
+			let num_change_outputs = change_outputs(&mut api, &args)?;
+			api.estimate_send_tx(
+				args.amount,
+				args.minimum_confirmations,
+				args.max_outputs,
+				num_change_outputs,
+				args.selection_strategy_is_use_all,
+			)
+		}))
+	}
+
 	fn finalize_tx(
 		&self,
 		req: Request<Body>,
 		mut api: APIOwner<T, C, K>,
 	) -> Box<Future<Item = Slate, Error = Error> + Send> {
+		let tx_events = self.tx_events.clone();
 		Box::new(
 			parse_body(req).and_then(move |mut slate| match api.finalize_tx(&mut slate) {
-				Ok(_) => ok(slate.clone()),
+				Ok(_) => {
+					publish_tx_events(&api, &tx_events);
+					ok(slate.clone())
+				}
 				Err(e) => {
 					error!("finalize_tx: failed with error: {}", e);
 					err(e)
@@ -338,7 +424,10 @@ where
 		if let Some(id_string) = params.get("id") {
 			Box::new(match id_string[0].parse() {
 				Ok(id) => match api.cancel_tx(id) {
-					Ok(_) => ok(()),
+					Ok(_) => {
+						publish_tx_events(&api, &self.tx_events);
+						ok(())
+					}
 					Err(e) => {
 						error!("cancel_tx: failed with error: {}", e);
 						err(e)
@@ -373,9 +462,16 @@ where
 			None => HashMap::new(),
 		};
 		let fluff = params.get("fluff").is_some();
+		let tx_events = self.tx_events.clone();
 		Box::new(
-			parse_body(req).and_then(move |slate| match api.post_tx(&slate, fluff) {
-				Ok(_) => ok(()),
+			parse_body(req).and_then(move |slate: Slate| match api.post_tx(&slate, fluff) {
+				Ok(_) => {
+					match api.retrieve_txs(false, None) {
+						Ok((_, txs)) => tx_events.posted(slate.id, &txs),
+						Err(e) => warn!("post_tx: could not publish the broadcast: {}", e),
+					}
+					ok(())
+				}
 				Err(e) => {
 					error!("post_tx: failed with error: {}", e);
 					err(e)
@@ -396,6 +492,51 @@ where
 		})
 	}
 
+	fn restore(
+		&self,
+		_req: Request<Body>,
+		mut api: APIOwner<T, C, K>,
+	) -> Box<Future<Item = (), Error = Error> + Send> {
+		// Restores can take a long time, so run in the background and let
+		// the caller poll restore_progress to see how far along it is
+		let (tx, rx) = mpsc::channel();
+		let progress = self.restore_progress.clone();
+		*progress.write() = None;
+		let res = thread::Builder::new()
+			.name("wallet_restore_progress".to_string())
+			.spawn(move || {
+				for p in rx {
+					*progress.write() = Some(p);
+				}
+			});
+		if res.is_err() {
+			return Box::new(err(ErrorKind::GenericError(
+				"Failed to start restore progress thread".to_owned(),
+			).into()));
+		}
+		let res = thread::Builder::new()
+			.name("wallet_restore".to_string())
+			.spawn(move || {
+				if let Err(e) = api.restore_with_progress(tx) {
+					error!("restore: failed with error: {}", e);
+				}
+			});
+		Box::new(match res {
+			Ok(_) => ok(()),
+			Err(_) => err(ErrorKind::GenericError("Failed to start restore thread".to_owned()).into()),
+		})
+	}
+
+	fn jsonrpc(&self, req: Request<Body>, mut api: APIOwner<T, C, K>) -> WalletResponseFuture {
+		Box::new(read_body(req).and_then(move |body| {
+			ok(jsonrpc_response(jsonrpc::handle(
+				&body,
+				jsonrpc::OWNER_METHODS,
+				|method, params| jsonrpc::owner_call(&mut api, method, params),
+			)))
+		}))
+	}
+
 	fn handle_post_request(&self, req: Request<Body>) -> WalletResponseFuture {
 		let api = APIOwner::new(self.wallet.clone());
 		match req
@@ -410,6 +551,10 @@ where
 				self.issue_send_tx(req, api)
 					.and_then(|slate| ok(json_response_pretty(&slate))),
 			),
+			"estimate_send_tx" => Box::new(
+				self.estimate_send_tx(req, api)
+					.and_then(|estimate| ok(json_response_pretty(&estimate))),
+			),
 			"finalize_tx" => Box::new(
 				self.finalize_tx(req, api)
 					.and_then(|slate| ok(json_response_pretty(&slate))),
@@ -426,6 +571,11 @@ where
 				self.issue_burn_tx(req, api)
 					.and_then(|_| ok(response(StatusCode::OK, ""))),
 			),
+			"restore" => Box::new(
+				self.restore(req, api)
+					.and_then(|_| ok(response(StatusCode::ACCEPTED, ""))),
+			),
+			"jsonrpc" => self.jsonrpc(req, api),
 			_ => Box::new(err(ErrorKind::GenericError(
 				"Unknown error handling post request".to_owned(),
 			).into())),
@@ -433,6 +583,41 @@ where
 	}
 }
 
+/// Publishes the changes an owner call made to the transactions
+fn publish_tx_events<T: ?Sized, C, K>(api: &APIOwner<T, C, K>, tx_events: &TxEventHub)
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	if let Err(e) = events::refresh(api, tx_events, false) {
+		warn!("Could not publish transaction events: {}", e);
+	}
+}
+
+/// Number of change outputs to use for a send, worked out from the target
+/// output size if one was given
+fn change_outputs<T: ?Sized, C, K>(
+	api: &mut APIOwner<T, C, K>,
+	args: &SendTXArgs,
+) -> Result<usize, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	match args.change_output_target {
+		Some(target) => api.change_outputs_for_target(
+			args.amount,
+			args.minimum_confirmations,
+			args.max_outputs,
+			args.selection_strategy_is_use_all,
+			target,
+		),
+		None => Ok(args.num_change_outputs),
+	}
+}
+
 impl<T: ?Sized, C, K> Handler for OwnerAPIHandler<T, C, K>
 where
 	T: WalletBackend<C, K> + Send + Sync + 'static,
@@ -440,6 +625,15 @@ where
 	K: Keychain + 'static,
 {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		// long-polls, answered once there are events or it timed out
+		if req
+			.uri()
+			.path()
+			.trim_right_matches("/")
+			.ends_with("/tx_events")
+		{
+			return self.tx_events(&req);
+		}
 		match self.handle_get_request(&req) {
 			Ok(r) => Box::new(ok(r)),
 			Err(e) => {
@@ -518,6 +712,40 @@ where
 		)
 	}
 
+	fn check_version(
+		&self,
+		_req: &Request<Body>,
+		mut api: APIForeign<T, C, K>,
+	) -> Result<VersionInfo, Error> {
+		api.check_version()
+	}
+
+	fn jsonrpc(&self, req: Request<Body>, mut api: APIForeign<T, C, K>) -> WalletResponseFuture {
+		Box::new(read_body(req).and_then(move |body| {
+			ok(jsonrpc_response(jsonrpc::handle(
+				&body,
+				jsonrpc::FOREIGN_METHODS,
+				|method, params| jsonrpc::foreign_call(&mut api, method, params),
+			)))
+		}))
+	}
+
+	fn handle_get_request(&self, req: &Request<Body>) -> Result<Response<Body>, Error> {
+		let api = *APIForeign::new(self.wallet.clone());
+
+		Ok(match req
+			.uri()
+			.path()
+			.trim_right_matches("/")
+			.rsplit("/")
+			.next()
+			.unwrap()
+		{
+			"check_version" => json_response(&self.check_version(req, api)?),
+			_ => response(StatusCode::BAD_REQUEST, ""),
+		})
+	}
+
 	fn handle_request(&self, req: Request<Body>) -> WalletResponseFuture {
 		let api = *APIForeign::new(self.wallet.clone());
 		match req
@@ -536,6 +764,7 @@ where
 				self.receive_tx(req, api)
 					.and_then(|res| ok(json_response(&res))),
 			),
+			"jsonrpc" => self.jsonrpc(req, api),
 			_ => Box::new(ok(response(StatusCode::BAD_REQUEST, "unknown action"))),
 		}
 	}
@@ -546,6 +775,16 @@ where
 	C: WalletClient + Send + Sync + 'static,
 	K: Keychain + 'static,
 {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		match self.handle_get_request(&req) {
+			Ok(r) => Box::new(ok(r)),
+			Err(e) => {
+				error!("Request Error: {:?}", e);
+				Box::new(ok(create_error_response(e)))
+			}
+		}
+	}
+
 	fn post(&self, req: Request<Body>) -> ResponseFuture {
 		Box::new(self.handle_request(req).and_then(|r| ok(r)).or_else(|e| {
 			error!("Request Error: {:?}", e);
@@ -577,6 +816,20 @@ where
 	}
 }
 
+// JSON-RPC always answers with a 200, errors being in the body, unless it
+// was only sent notifications
+fn jsonrpc_response(res: Option<String>) -> Response<Body> {
+	match res {
+		Some(json) => Response::builder()
+			.status(StatusCode::OK)
+			.header("access-control-allow-origin", "*")
+			.header("content-type", "application/json")
+			.body(json.into())
+			.unwrap(),
+		None => response(StatusCode::NO_CONTENT, ""),
+	}
+}
+
 fn create_error_response(e: Error) -> Response<Body> {
 	Response::builder()
 		.status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -618,6 +871,15 @@ fn param_exists(req: &Request<Body>, param: &str) -> bool {
 	parse_params(req).get(param).is_some()
 }
 
+fn read_body(req: Request<Body>) -> Box<Future<Item = Vec<u8>, Error = Error> + Send> {
+	Box::new(
+		req.into_body()
+			.concat2()
+			.map_err(|_| ErrorKind::GenericError("Failed to read request".to_owned()).into())
+			.map(|body| body.to_vec()),
+	)
+}
+
 fn parse_body<T>(req: Request<Body>) -> Box<Future<Item = T, Error = Error> + Send>
 where
 	for<'de> T: Deserialize<'de> + Send + 'static,
@@ -176,6 +176,10 @@ pub enum ErrorKind {
 	#[fail(display = "Invalid BIP32 Depth (must be 1 or greater)")]
 	InvalidBIP32Depth,
 
+	/// Output already locked by another in-progress transaction
+	#[fail(display = "Output {} is locked by transaction {}", _0, _1)]
+	OutputLocked(String, String),
+
 	/// Attempt to add an account that exists
 	#[fail(display = "Account Label '{}' already exists", _0)]
 	AccountLabelAlreadyExists(String),
@@ -184,6 +188,45 @@ pub enum ErrorKind {
 	#[fail(display = "Unknown Account Label '{}'", _0)]
 	UnknownAccountLabel(String),
 
+	/// Attempt to add a contact that exists
+	#[fail(display = "Contact '{}' already exists", _0)]
+	ContactAlreadyExists(String),
+
+	/// Reference unknown contact
+	#[fail(display = "Unknown Contact '{}'", _0)]
+	UnknownContact(String),
+
+	/// Wallet database can't be decrypted with the password given
+	#[fail(display = "Incorrect password for the wallet database")]
+	InvalidDbPassword,
+
+	/// Wallet database was written before encryption and must be encrypted
+	/// before use
+	#[fail(display = "Wallet database isn't encrypted yet")]
+	DbNotEncrypted,
+
+	/// No slate adapter is registered for a destination's scheme
+	#[fail(display = "No slate adapter for '{}'", _0)]
+	UnknownSlateAdapter(String),
+
+	/// Receiving wallet can't take the transaction, as told by its
+	/// `check_version`
+	#[fail(display = "Receiving wallet can't accept the transaction: {}", _0)]
+	IncompatibleReceiver(String),
+
+	/// Error running tor or talking to its control port
+	#[fail(display = "Tor error: {}", _0)]
+	Tor(String),
+
+	/// What the node reported doesn't check out against the proofs and
+	/// headers the wallet verifies it with
+	#[fail(display = "Node verification failed: {}", _0)]
+	NodeVerification(String),
+
+	/// A payment received couldn't be notified about
+	#[fail(display = "Payment notification failed: {}", _0)]
+	Notification(String),
+
 	/// Other
 	#[fail(display = "Generic error: {}", _0)]
 	GenericError(String),
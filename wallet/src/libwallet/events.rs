@@ -0,0 +1,299 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Events on the transactions of the wallet, for clients of the owner API to
+//! follow its transactions without polling `retrieve_txs`. The transaction
+//! log is compared with what it was the last time, after each call of the
+//! owner API changing it and, while clients are waiting, every so often
+//! after refreshing from the node, so slates received by a listener running
+//! apart and confirmations show as well. Events are numbered and the last few kept, clients long-polling for
+//! the ones past the last they got.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::{DateTime, Utc};
+use futures::sync::oneshot;
+use uuid::Uuid;
+
+use keychain::Keychain;
+use libwallet::api::APIOwner;
+use libwallet::types::{TxLogEntry, TxLogEntryType, WalletBackend, WalletClient};
+use libwallet::Error;
+use util::Mutex;
+
+/// Most past events kept for clients to catch up with
+pub const TX_EVENT_HISTORY: usize = 1000;
+
+/// Seconds between two refreshes of the wallet from the node by default, to
+/// notice confirmations
+pub const TX_EVENTS_REFRESH_SECS: u64 = 10;
+
+/// How a transaction of the wallet changed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxEventKind {
+	/// A slate was received and the transaction added to the wallet
+	Received,
+	/// A slate sent was finalized, the complete transaction stored
+	Finalized,
+	/// The transaction was posted to the node
+	Broadcast,
+	/// The transaction was confirmed on the chain
+	Confirmed,
+	/// The transaction was cancelled
+	Cancelled,
+}
+
+/// A change to a transaction of the wallet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxEvent {
+	/// Number of the event, increasing
+	pub id: u64,
+	/// What happened
+	pub kind: TxEventKind,
+	/// Id of the transaction in the wallet
+	pub tx_id: u32,
+	/// Id of its slate, if any
+	pub tx_slate_id: Option<Uuid>,
+	/// When the change was noticed
+	pub at: DateTime<Utc>,
+}
+
+/// Events past the one a client asked from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxEvents {
+	/// Id of the last event published, to ask from next time
+	pub last_id: u64,
+	/// The events, oldest first. Some may be missing if the client asked
+	/// from further back than kept.
+	pub events: Vec<TxEvent>,
+}
+
+/// What's compared of a transaction from one time to the next
+struct TxState {
+	tx_type: TxLogEntryType,
+	confirmed: bool,
+	tx_hash: Option<u64>,
+}
+
+impl TxState {
+	fn from_tx(tx: &TxLogEntry) -> TxState {
+		TxState {
+			tx_type: tx.tx_type.clone(),
+			confirmed: tx.confirmed,
+			tx_hash: tx.tx_hex.as_ref().map(|hex| {
+				let mut hasher = DefaultHasher::new();
+				hex.hash(&mut hasher);
+				hasher.finish()
+			}),
+		}
+	}
+}
+
+struct History {
+	next_id: u64,
+	events: VecDeque<TxEvent>,
+	// state of the transactions the last time, none before the first
+	txs: Option<HashMap<u32, TxState>>,
+	// long-polling clients waiting on the next event
+	waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// Publishes the changes to the transactions of a wallet to the clients
+/// waiting on them
+pub struct TxEventHub {
+	history: Mutex<History>,
+}
+
+impl TxEventHub {
+	/// A hub that hasn't seen the transactions yet
+	pub fn new() -> TxEventHub {
+		TxEventHub {
+			history: Mutex::new(History {
+				next_id: 1,
+				events: VecDeque::new(),
+				txs: None,
+				waiters: vec![],
+			}),
+		}
+	}
+
+	/// Publishes how the transactions changed since the last update, nothing
+	/// on the first one
+	pub fn update(&self, txs: &[TxLogEntry]) {
+		let mut history = self.history.lock();
+		let mut events = vec![];
+		if let Some(ref prev) = history.txs {
+			for tx in txs {
+				let cur = TxState::from_tx(tx);
+				match prev.get(&tx.id) {
+					None => {
+						if tx.tx_type == TxLogEntryType::TxReceived {
+							events.push((TxEventKind::Received, tx));
+						}
+						if cur.confirmed {
+							events.push((TxEventKind::Confirmed, tx));
+						}
+					}
+					Some(prev) => {
+						if cur.tx_type != prev.tx_type && is_cancelled(&cur.tx_type) {
+							events.push((TxEventKind::Cancelled, tx));
+						}
+						if is_sent(&cur.tx_type)
+							&& prev.tx_hash.is_some()
+							&& cur.tx_hash != prev.tx_hash
+						{
+							events.push((TxEventKind::Finalized, tx));
+						}
+						if cur.confirmed && !prev.confirmed {
+							events.push((TxEventKind::Confirmed, tx));
+						}
+					}
+				}
+			}
+		}
+		for (kind, tx) in events {
+			push(&mut history, kind, tx);
+		}
+		history.txs = Some(txs.iter().map(|tx| (tx.id, TxState::from_tx(tx))).collect());
+	}
+
+	/// Publishes the broadcast of the transaction of a slate, along with the
+	/// changes to the transactions
+	pub fn posted(&self, slate_id: Uuid, txs: &[TxLogEntry]) {
+		self.update(txs);
+		let mut history = self.history.lock();
+		if let Some(tx) = txs.iter().find(|tx| tx.tx_slate_id == Some(slate_id)) {
+			push(&mut history, TxEventKind::Broadcast, tx);
+		}
+	}
+
+	/// The events kept past the given one
+	pub fn since(&self, since: u64) -> TxEvents {
+		let history = self.history.lock();
+		TxEvents {
+			last_id: history.next_id - 1,
+			events: history
+				.events
+				.iter()
+				.filter(|e| e.id > since)
+				.cloned()
+				.collect(),
+		}
+	}
+
+	/// Resolves once there are events past the given one, straight away if
+	/// there are already
+	pub fn wait(&self, since: u64) -> oneshot::Receiver<()> {
+		let (tx, rx) = oneshot::channel();
+		let mut history = self.history.lock();
+		if history.next_id - 1 > since {
+			let _ = tx.send(());
+		} else {
+			history.waiters.retain(|w| !w.is_canceled());
+			history.waiters.push(tx);
+		}
+		rx
+	}
+
+	/// Whether clients are waiting on the next event, forgetting the ones
+	/// that gave up
+	pub fn has_waiters(&self) -> bool {
+		let mut history = self.history.lock();
+		history.waiters.retain(|w| !w.is_canceled());
+		!history.waiters.is_empty()
+	}
+}
+
+impl Default for TxEventHub {
+	fn default() -> TxEventHub {
+		TxEventHub::new()
+	}
+}
+
+fn push(history: &mut History, kind: TxEventKind, tx: &TxLogEntry) {
+	let id = history.next_id;
+	history.next_id += 1;
+	history.events.push_back(TxEvent {
+		id,
+		kind,
+		tx_id: tx.id,
+		tx_slate_id: tx.tx_slate_id,
+		at: Utc::now(),
+	});
+	if history.events.len() > TX_EVENT_HISTORY {
+		history.events.pop_front();
+	}
+	for waiter in history.waiters.drain(..) {
+		let _ = waiter.send(());
+	}
+}
+
+fn is_cancelled(t: &TxLogEntryType) -> bool {
+	*t == TxLogEntryType::TxSentCancelled || *t == TxLogEntryType::TxReceivedCancelled
+}
+
+fn is_sent(t: &TxLogEntryType) -> bool {
+	*t == TxLogEntryType::TxSent || *t == TxLogEntryType::TxSentSelf
+}
+
+/// Publishes the changes to the transactions of the wallet, refreshing it
+/// from the node first if asked to
+pub fn refresh<T: ?Sized, C, K>(
+	api: &APIOwner<T, C, K>,
+	hub: &TxEventHub,
+	from_node: bool,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let (_, txs) = api.retrieve_txs(from_node, None)?;
+	hub.update(&txs);
+	Ok(())
+}
+
+/// Takes the current state of the transactions, then refreshes the wallet
+/// from the node and publishes the changes every `interval` while clients are
+/// waiting on them, in a thread of its own until the process exits
+pub fn start<T: ?Sized, C, K>(
+	wallet: Arc<Mutex<Box<T>>>,
+	hub: Arc<TxEventHub>,
+	interval: Duration,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K> + Send + 'static,
+	C: WalletClient + 'static,
+	K: Keychain + 'static,
+{
+	refresh(&APIOwner::new(wallet.clone()), &hub, false)?;
+	let _ = thread::Builder::new()
+		.name("wallet_tx_events".to_string())
+		.spawn(move || loop {
+			thread::sleep(interval);
+			if !hub.has_waiters() {
+				continue;
+			}
+			if let Err(e) = refresh(&APIOwner::new(wallet.clone()), &hub, true) {
+				warn!("Refreshing the wallet for transaction events: {}", e);
+			}
+		});
+	Ok(())
+}
@@ -0,0 +1,263 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sends split between an online watch-only wallet, which selects inputs and
+//! finalizes, and an offline wallet holding the keys, which signs. A send
+//! goes:
+//!
+//! * online: `signing_request`, locking the selected inputs
+//! * offline: `sign_request`, giving the slate for the recipient
+//! * recipient: receives the slate as usual
+//! * offline: `sign_response`, adding the sender's partial signature
+//! * online: `finalize_signed`, building the transaction to post
+//!
+//! As the online wallet can't work out commitments, it learns about outputs
+//! through `export_outputs` and `import_outputs`.
+
+use keychain::{Identifier, Keychain};
+use libtx::build;
+use libtx::slate::Slate;
+use libwallet::error::{Error, ErrorKind};
+use libwallet::internal::{selection, updater};
+use libwallet::types::{
+	OutputStatus, SigningRequest, TxLogEntry, TxLogEntryType, WalletBackend, WalletClient,
+	WatchedOutput,
+};
+use util;
+use util::secp::pedersen;
+use uuid::Uuid;
+
+use core::ser;
+
+/// Select inputs for a send and lock them, without needing any keys
+pub fn signing_request<T: ?Sized, C, K>(
+	wallet: &mut T,
+	amount: u64,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	num_change_outputs: usize,
+	selection_strategy_is_use_all: bool,
+	parent_key_id: &Identifier,
+) -> Result<SigningRequest, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let current_height = wallet.client().get_chain_height()?;
+	updater::refresh_outputs(wallet, parent_key_id)?;
+
+	let (coins, _, fee) = selection::select_coins_and_fee(
+		wallet,
+		amount,
+		current_height,
+		minimum_confirmations,
+		max_outputs,
+		num_change_outputs,
+		selection_strategy_is_use_all,
+		parent_key_id,
+	)?;
+	let id = Uuid::new_v4();
+	selection::lock_outputs(wallet, &coins, &id, current_height)?;
+
+	Ok(SigningRequest {
+		id,
+		amount,
+		fee,
+		height: current_height,
+		lock_height: current_height,
+		inputs: coins,
+		num_change_outputs,
+	})
+}
+
+/// Build and sign the sender's side of the slate a signing request asks for,
+/// keeping the private context to sign the recipient's response with
+pub fn sign_request<T: ?Sized, C, K>(
+	wallet: &mut T,
+	req: &SigningRequest,
+	parent_key_id: &Identifier,
+) -> Result<Slate, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	// outputs the online wallet knows about and we don't (i.e. restored there)
+	{
+		let mut batch = wallet.batch()?;
+		for input in &req.inputs {
+			if batch.get(&input.key_id).is_err() {
+				batch.save(input.clone())?;
+			}
+		}
+		batch.commit()?;
+	}
+
+	let (mut elems, change_amounts_derivations) = selection::inputs_and_change(
+		&req.inputs,
+		wallet,
+		req.amount,
+		req.fee,
+		req.num_change_outputs,
+	)?;
+	elems.push(build::with_lock_height(req.lock_height));
+
+	let mut slate = Slate::blank(2);
+	slate.id = req.id;
+	let (mut slate, mut context, lock_fn) = selection::build_send_tx_slate_from(
+		wallet,
+		slate,
+		elems,
+		req.inputs.clone(),
+		change_amounts_derivations,
+		req.amount,
+		req.fee,
+		req.height,
+		req.lock_height,
+		parent_key_id.clone(),
+		false,
+	)?;
+	let _ = slate.fill_round_1(
+		wallet.keychain(),
+		&mut context.sec_key,
+		&context.sec_nonce,
+		0,
+	)?;
+
+	{
+		let mut batch = wallet.batch()?;
+		batch.save_private_context(slate.id.as_bytes(), &context)?;
+		batch.commit()?;
+	}
+	let tx_hex = util::to_hex(ser::ser_vec(&slate.tx).unwrap());
+	lock_fn(wallet, &tx_hex)?;
+	Ok(slate)
+}
+
+/// Add the sender's partial signature to the recipient's response
+pub fn sign_response<T: ?Sized, C, K>(wallet: &mut T, slate: &mut Slate) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let context = wallet.get_private_context(slate.id.as_bytes())?;
+	let _ = slate.fill_round_2(wallet.keychain(), &context.sec_key, &context.sec_nonce, 0)?;
+	let mut batch = wallet.batch()?;
+	batch.delete_private_context(slate.id.as_bytes())?;
+	batch.commit()?;
+	Ok(())
+}
+
+/// Build the final transaction from a slate signed by all parties, which
+/// takes no keys, and record the send along with its spent inputs
+pub fn finalize_signed<T: ?Sized, C, K>(
+	wallet: &mut T,
+	slate: &mut Slate,
+	parent_key_id: &Identifier,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	// finalizing only verifies and aggregates, any keychain would do
+	let keychain = K::from_random_seed()?;
+	slate
+		.finalize(&keychain)
+		.map_err(|e| ErrorKind::LibTX(e.kind()))?;
+
+	let tx_hex = util::to_hex(ser::ser_vec(&slate.tx).unwrap());
+	let mut batch = wallet.batch()?;
+	let log_id = batch.next_tx_log_id(parent_key_id)?;
+	let mut t = TxLogEntry::new(parent_key_id.clone(), TxLogEntryType::TxSent, log_id);
+	t.tx_slate_id = Some(slate.id);
+	t.fee = Some(slate.fee);
+	t.tx_hex = Some(tx_hex);
+	for lock in batch.output_lock_iter()? {
+		if lock.slate_id != slate.id {
+			continue;
+		}
+		let mut coin = batch.get(&lock.key_id)?;
+		coin.tx_log_entry = Some(log_id);
+		t.num_inputs += 1;
+		t.amount_debited += coin.value;
+		batch.lock_output(&mut coin)?;
+		batch.delete_output_lock(&lock.key_id)?;
+	}
+	if t.num_inputs == 0 {
+		return Err(ErrorKind::GenericError(format!(
+			"No inputs locked for slate {}, was its signing request made here?",
+			slate.id
+		)).into());
+	}
+	// change comes back once the offline wallet's outputs are imported
+	t.amount_credited = t.amount_debited - slate.amount - slate.fee;
+	batch.save_tx_log_entry(t, parent_key_id)?;
+	batch.commit()?;
+	Ok(())
+}
+
+/// Outputs along with their commitments, for a watch-only wallet to import
+pub fn export_outputs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+) -> Result<Vec<WatchedOutput>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let outputs = updater::retrieve_outputs(wallet, false, None, parent_key_id)?;
+	Ok(outputs
+		.into_iter()
+		.map(|(output, commit)| WatchedOutput {
+			output,
+			commit: util::to_hex(commit.0.to_vec()),
+		}).collect())
+}
+
+/// Track outputs exported by the offline wallet, returning how many weren't
+/// known yet. Known outputs are left as they are, their status being
+/// refreshed from the node.
+pub fn import_outputs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	outputs: Vec<WatchedOutput>,
+) -> Result<usize, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut count = 0;
+	let mut batch = wallet.batch()?;
+	for w in outputs {
+		if batch.get(&w.output.key_id).is_ok() {
+			continue;
+		}
+		let commit = util::from_hex(w.commit.clone()).map_err(|_| {
+			ErrorKind::GenericError(format!("Invalid commitment {}", w.commit))
+		})?;
+		let mut output = w.output;
+		// spent or not, the node tells on the next refresh
+		if output.status == OutputStatus::Locked {
+			output.status = OutputStatus::Unspent;
+		}
+		batch.save_watched(output, &pedersen::Commitment::from_vec(commit))?;
+		count += 1;
+	}
+	batch.commit()?;
+	Ok(count)
+}
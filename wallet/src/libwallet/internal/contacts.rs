@@ -0,0 +1,127 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Address book of named send destinations
+use keychain::Keychain;
+use libwallet::error::{Error, ErrorKind};
+use libwallet::types::{Contact, WalletBackend, WalletClient};
+use uuid::Uuid;
+
+/// Prefix marking a send destination as a contact name rather than an
+/// address, e.g. `@alice`
+pub const CONTACT_MARKER: char = '@';
+
+/// Returns all contacts in the address book, sorted by name
+pub fn contacts<T: ?Sized, C, K>(wallet: &mut T) -> Result<Vec<Contact>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut contacts: Vec<Contact> = wallet.contact_iter()?.collect();
+	contacts.sort_by(|a, b| a.name.cmp(&b.name));
+	Ok(contacts)
+}
+
+/// Adds a new contact to the address book
+pub fn add_contact<T: ?Sized, C, K>(
+	wallet: &mut T,
+	name: &str,
+	method: &str,
+	dest: &str,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	if name.is_empty() || name.starts_with(CONTACT_MARKER) || name.contains(char::is_whitespace)
+	{
+		return Err(ErrorKind::GenericError(format!(
+			"Invalid contact name '{}'",
+			name
+		)).into());
+	}
+	if wallet.get_contact(name)?.is_some() {
+		return Err(ErrorKind::ContactAlreadyExists(name.to_owned()).into());
+	}
+	let mut batch = wallet.batch()?;
+	batch.save_contact(Contact {
+		name: name.to_owned(),
+		method: method.to_owned(),
+		dest: dest.to_owned(),
+	})?;
+	batch.commit()?;
+	Ok(())
+}
+
+/// Removes a contact from the address book
+pub fn remove_contact<T: ?Sized, C, K>(wallet: &mut T, name: &str) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	if wallet.get_contact(name)?.is_none() {
+		return Err(ErrorKind::UnknownContact(name.to_owned()).into());
+	}
+	let mut batch = wallet.batch()?;
+	batch.delete_contact(name)?;
+	batch.commit()?;
+	Ok(())
+}
+
+/// Looks up the contact a destination refers to, either by name (`@name`) or
+/// because it's the destination of a contact. Unknown names are an error,
+/// while other destinations simply have no contact.
+pub fn resolve<T: ?Sized, C, K>(wallet: &mut T, dest: &str) -> Result<Option<Contact>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	if dest.starts_with(CONTACT_MARKER) {
+		let name = &dest[1..];
+		match wallet.get_contact(name)? {
+			Some(c) => Ok(Some(c)),
+			None => Err(ErrorKind::UnknownContact(name.to_owned()).into()),
+		}
+	} else {
+		Ok(wallet.contact_iter()?.find(|c| c.dest == dest))
+	}
+}
+
+/// Records the contact a transaction was sent to in its log entry
+pub fn set_tx_contact<T: ?Sized, C, K>(
+	wallet: &mut T,
+	slate_id: &Uuid,
+	name: &str,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let tx = wallet
+		.tx_log_iter()?
+		.find(|t| t.tx_slate_id == Some(*slate_id));
+	if let Some(mut t) = tx {
+		let parent_key_id = t.parent_key_id.clone();
+		t.contact = Some(name.to_owned());
+		let batch = wallet.batch()?;
+		batch.save_tx_log_entry(t, &parent_key_id)?;
+		batch.commit()?;
+	}
+	Ok(())
+}
@@ -0,0 +1,83 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Balance history, snapshots of the balance of each account taken as the
+//! wallet refreshes from the node
+use keychain::{Identifier, Keychain};
+use libwallet::error::Error;
+use libwallet::internal::updater;
+use libwallet::types::{BalanceSnapshot, WalletBackend, WalletClient};
+
+/// Records the current balance of the account as the snapshot of the day,
+/// and of the block as well when asked for
+pub fn record_snapshot<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let info = updater::retrieve_info(wallet, parent_key_id)?;
+	let per_block = wallet.snapshots_per_block()?;
+	let mut batch = wallet.batch()?;
+	batch.save_balance_snapshot(BalanceSnapshot::new(parent_key_id, &info, false))?;
+	if per_block {
+		batch.save_balance_snapshot(BalanceSnapshot::new(parent_key_id, &info, true))?;
+	}
+	batch.commit()?;
+	Ok(())
+}
+
+/// Balance history of the account, oldest first: one snapshot per day, or
+/// per block if asked for (and recorded)
+pub fn balance_history<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+	per_block: bool,
+) -> Result<Vec<BalanceSnapshot>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut snapshots: Vec<BalanceSnapshot> = wallet
+		.balance_snapshot_iter()?
+		.filter(|s| s.parent_key_id == *parent_key_id && s.per_block == per_block)
+		.collect();
+	if per_block {
+		snapshots.sort_by_key(|s| s.height);
+	} else {
+		snapshots.sort_by_key(|s| s.timestamp);
+	}
+	Ok(snapshots)
+}
+
+/// Sets whether to record a balance snapshot for every block, on top of
+/// the daily ones
+pub fn set_snapshots_per_block<T: ?Sized, C, K>(
+	wallet: &mut T,
+	per_block: bool,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut batch = wallet.batch()?;
+	batch.save_snapshots_per_block(per_block)?;
+	batch.commit()?;
+	Ok(())
+}
@@ -51,7 +51,7 @@ where
 	C: WalletClient,
 	K: Keychain,
 {
-	Ok(wallet.acct_path_iter().collect())
+	Ok(wallet.acct_path_iter()?.collect())
 }
 
 /// Adds an new parent account path with a given label
@@ -62,7 +62,7 @@ where
 	K: Keychain,
 {
 	let label = label.to_owned();
-	if let Some(_) = wallet.acct_path_iter().find(|l| l.label == label) {
+	if let Some(_) = wallet.acct_path_iter()?.find(|l| l.label == label) {
 		return Err(ErrorKind::AccountLabelAlreadyExists(label.clone()).into());
 	}
 
@@ -70,7 +70,7 @@ where
 	// so find the highest of those, then increment (to conform with external/internal
 	// derivation chains in BIP32 spec)
 
-	let highest_entry = wallet.acct_path_iter().max_by(|a, b| {
+	let highest_entry = wallet.acct_path_iter()?.max_by(|a, b| {
 		<u32>::from(a.path.to_path().path[0]).cmp(&<u32>::from(b.path.to_path().path[0]))
 	});
 
@@ -21,8 +21,12 @@
 #![deny(unused_mut)]
 #![warn(missing_docs)]
 
+pub mod cold;
+pub mod contacts;
+pub mod history;
 pub mod keys;
 pub mod restore;
 pub mod selection;
 pub mod tx;
 pub mod updater;
+pub mod verifier;
@@ -18,8 +18,13 @@ use keychain::{ExtKeychain, Identifier, Keychain};
 use libtx::proof;
 use libwallet::internal::keys;
 use libwallet::types::*;
-use libwallet::Error;
+use libwallet::{Error, ErrorKind};
+use num_cpus;
+use std::cmp;
 use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Instant;
 use util::secp::{key::SecretKey, pedersen};
 
 /// Utility struct for return values from below
@@ -42,27 +47,25 @@ struct OutputResult {
 	pub blinding: SecretKey,
 }
 
-fn identify_utxo_outputs<T, C, K>(
-	wallet: &mut T,
+/// Number of outputs requested from the node per chunk of the scan
+const RESTORE_CHUNK_SIZE: u64 = 1000;
+
+/// Attempt to rewind the range proofs of a set of outputs with our keychain,
+/// returning those that belong to this wallet
+fn identify_utxo_outputs<K>(
+	keychain: &K,
 	outputs: Vec<(pedersen::Commitment, pedersen::RangeProof, bool, u64)>,
 ) -> Result<Vec<OutputResult>, Error>
 where
-	T: WalletBackend<C, K>,
-	C: WalletClient,
 	K: Keychain,
 {
 	let mut wallet_outputs: Vec<OutputResult> = Vec::new();
 
-	info!(
-		"Scanning {} outputs in the current Grin utxo set",
-		outputs.len(),
-	);
-
 	for output in outputs.iter() {
 		let (commit, proof, is_coinbase, height) = output;
 		// attempt to unwind message from the RP and get a value
 		// will fail if it's not ours
-		let info = proof::rewind(wallet.keychain(), *commit, None, *proof)?;
+		let info = proof::rewind(keychain, *commit, None, *proof)?;
 
 		if !info.success {
 			continue;
@@ -97,39 +100,215 @@ where
 	Ok(wallet_outputs)
 }
 
-/// Restore a wallet
-pub fn restore<T, C, K>(wallet: &mut T) -> Result<(), Error>
+/// Split a chunk of outputs across a pool of worker threads, each attempting
+/// to rewind its share of range proofs with a clone of the keychain
+fn identify_utxo_outputs_parallel<K>(
+	keychain: &K,
+	outputs: Vec<(pedersen::Commitment, pedersen::RangeProof, bool, u64)>,
+) -> Result<Vec<OutputResult>, Error>
+where
+	K: Keychain + 'static,
+{
+	if outputs.is_empty() {
+		return Ok(vec![]);
+	}
+
+	let num_workers = cmp::min(cmp::max(num_cpus::get(), 1), outputs.len());
+	let per_worker = outputs.len() / num_workers + 1;
+
+	info!(
+		"Scanning {} outputs in the current Grin utxo set with {} workers",
+		outputs.len(),
+		num_workers,
+	);
+
+	let handles: Vec<_> = outputs
+		.chunks(per_worker)
+		.map(|chunk| {
+			let keychain = keychain.clone();
+			let chunk = chunk.to_vec();
+			thread::spawn(move || identify_utxo_outputs(&keychain, chunk))
+		}).collect();
+
+	let mut wallet_outputs: Vec<OutputResult> = vec![];
+	for handle in handles {
+		let mut res = handle
+			.join()
+			.map_err(|_| ErrorKind::GenericError("Restore worker thread panicked".to_owned()))??;
+		wallet_outputs.append(&mut res);
+	}
+	Ok(wallet_outputs)
+}
+
+/// Save the outputs found in a chunk along with the new checkpoint, in a
+/// single batch so an interrupted restore never records an output twice
+fn save_chunk<T, C, K>(
+	wallet: &mut T,
+	outputs: Vec<OutputResult>,
+	last_scanned_index: u64,
+) -> Result<(), Error>
 where
 	T: WalletBackend<C, K>,
 	C: WalletClient,
 	K: Keychain,
 {
-	// Don't proceed if wallet_data has anything in it
-	let is_empty = wallet.iter().next().is_none();
-	if !is_empty {
+	let mut batch = wallet.batch()?;
+
+	for output in outputs {
+		save_found_output(&mut batch, output)?;
+	}
+
+	batch.save_restore_checkpoint(last_scanned_index)?;
+	batch.commit()?;
+	Ok(())
+}
+
+/// Save an output found on chain as unspent, along with a confirmed tx log
+/// entry receiving it
+fn save_found_output<'a, K>(
+	batch: &mut Box<WalletOutputBatch<K> + 'a>,
+	output: OutputResult,
+) -> Result<(), Error>
+where
+	K: Keychain,
+{
+	let parent_key_id = output.key_id.parent_path();
+
+	let log_id = batch.next_tx_log_id(&parent_key_id)?;
+	let entry_type = match output.is_coinbase {
+		true => TxLogEntryType::ConfirmedCoinbase,
+		false => TxLogEntryType::TxReceived,
+	};
+
+	let mut t = TxLogEntry::new(parent_key_id.clone(), entry_type, log_id);
+	t.confirmed = true;
+	t.amount_credited = output.value;
+	t.num_outputs = 1;
+	t.update_confirmation_ts();
+	// found on the chain, not a new payment
+	t.notified = true;
+	batch.save_tx_log_entry(t, &parent_key_id)?;
+
+	let _ = batch.save(found_output_data(&output, Some(log_id)));
+	Ok(())
+}
+
+fn found_output_data(output: &OutputResult, tx_log_entry: Option<u32>) -> OutputData {
+	OutputData {
+		root_key_id: output.key_id.parent_path(),
+		key_id: output.key_id.clone(),
+		n_child: output.n_child,
+		value: output.value,
+		status: OutputStatus::Unspent,
+		height: output.height,
+		lock_height: output.lock_height,
+		is_coinbase: output.is_coinbase,
+		tx_log_entry,
+	}
+}
+
+/// Build a progress report for the scan so far, estimating time remaining from
+/// the rate outputs have been scanned since `started`
+fn scan_progress(
+	started: Instant,
+	first_index: u64,
+	last_retrieved_index: u64,
+	highest_index: u64,
+	matches_found: u64,
+) -> ScanProgress {
+	let scanned = last_retrieved_index.saturating_sub(first_index - 1);
+	let remaining = highest_index.saturating_sub(last_retrieved_index);
+	ScanProgress {
+		outputs_scanned: last_retrieved_index,
+		outputs_total: highest_index,
+		matches_found,
+		eta_secs: (started.elapsed().as_secs() * remaining).checked_div(scanned),
+		complete: false,
+	}
+}
+
+/// Send a progress report, if anyone's listening
+fn report(progress: &Option<Sender<ScanProgress>>, p: ScanProgress) {
+	if let Some(ref tx) = *progress {
+		// fine if the receiver has gone away, the restore carries on
+		let _ = tx.send(p);
+	}
+}
+
+/// Restore a wallet from the node's UTXO set, sending a progress report
+/// after each chunk scanned if a channel is provided
+/// Outputs are requested from the node in chunks of PMMR indices, and the
+/// last scanned index is checkpointed after each chunk so an interrupted
+/// restore resumes where it left off
+pub fn restore<T, C, K>(wallet: &mut T, progress: Option<Sender<ScanProgress>>) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain + 'static,
+{
+	let checkpoint = wallet.restore_checkpoint()?;
+
+	// Don't proceed if wallet_data has anything in it, unless it was
+	// put there by an interrupted restore
+	let is_empty = wallet.iter()?.next().is_none();
+	if !is_empty && checkpoint.is_none() {
 		error!("Not restoring. Please back up and remove existing db directory first.");
 		return Ok(());
 	}
 
-	info!("Starting restore.");
+	let mut start_index = match checkpoint {
+		Some(index) => {
+			info!("Resuming interrupted restore after index {}.", index);
+			index + 1
+		}
+		None => {
+			info!("Starting restore.");
+			1
+		}
+	};
 
-	let batch_size = 1000;
-	let mut start_index = 1;
-	let mut result_vec: Vec<OutputResult> = vec![];
+	let first_index = start_index;
+	let started = Instant::now();
+	let mut num_found = 0;
+	let mut last_report = ScanProgress {
+		outputs_scanned: start_index - 1,
+		outputs_total: 0,
+		matches_found: 0,
+		eta_secs: None,
+		complete: false,
+	};
 	loop {
 		let (highest_index, last_retrieved_index, outputs) = wallet
 			.client()
-			.get_outputs_by_pmmr_index(start_index, batch_size)?;
+			.get_outputs_by_pmmr_index(start_index, RESTORE_CHUNK_SIZE)?;
 		info!(
 			"Retrieved {} outputs, up to index {}. (Highest index: {})",
 			outputs.len(),
-			highest_index,
 			last_retrieved_index,
+			highest_index,
 		);
 
-		result_vec.append(&mut identify_utxo_outputs(wallet, outputs.clone())?);
+		// nothing left past our checkpoint
+		if outputs.is_empty() {
+			last_report.outputs_total = highest_index;
+			break;
+		}
+
+		let keychain = wallet.keychain().clone();
+		let found = identify_utxo_outputs_parallel(&keychain, outputs)?;
+		num_found += found.len();
+		save_chunk(wallet, found, last_retrieved_index)?;
+
+		last_report = scan_progress(
+			started,
+			first_index,
+			last_retrieved_index,
+			highest_index,
+			num_found as u64,
+		);
+		report(&progress, last_report.clone());
 
-		if highest_index == last_retrieved_index {
+		if last_retrieved_index >= highest_index {
 			break;
 		}
 		start_index = last_retrieved_index + 1;
@@ -137,52 +316,19 @@ where
 
 	info!(
 		"Identified {} wallet_outputs as belonging to this wallet",
-		result_vec.len(),
+		num_found,
 	);
 
+	// Work out the highest child index used under each parent from everything
+	// saved so far, including outputs found before an interruption
 	let mut found_parents: HashMap<Identifier, u32> = HashMap::new();
-	// Now save what we have
-	{
-		let mut batch = wallet.batch()?;
-
-		for output in result_vec {
-			let parent_key_id = output.key_id.parent_path();
-			if !found_parents.contains_key(&parent_key_id) {
-				found_parents.insert(parent_key_id.clone(), 0);
-			}
-
-			let log_id = batch.next_tx_log_id(&parent_key_id)?;
-			let entry_type = match output.is_coinbase {
-				true => TxLogEntryType::ConfirmedCoinbase,
-				false => TxLogEntryType::TxReceived,
-			};
-
-			let mut t = TxLogEntry::new(parent_key_id.clone(), entry_type, log_id);
-			t.confirmed = true;
-			t.amount_credited = output.value;
-			t.num_outputs = 1;
-			t.update_confirmation_ts();
-			batch.save_tx_log_entry(t, &parent_key_id)?;
-
-			let _ = batch.save(OutputData {
-				root_key_id: parent_key_id.clone(),
-				key_id: output.key_id,
-				n_child: output.n_child,
-				value: output.value,
-				status: OutputStatus::Unspent,
-				height: output.height,
-				lock_height: output.lock_height,
-				is_coinbase: output.is_coinbase,
-				tx_log_entry: Some(log_id),
-			});
-
-			let max_child_index = found_parents.get(&parent_key_id).unwrap().clone();
-			if output.n_child >= max_child_index {
-				found_parents.insert(parent_key_id.clone(), output.n_child);
-			};
+	for output in wallet.iter()? {
+		let max_child_index = found_parents.entry(output.root_key_id.clone()).or_insert(0);
+		if output.n_child >= *max_child_index {
+			*max_child_index = output.n_child;
 		}
-		batch.commit()?;
 	}
+
 	// restore labels, account paths and child derivation indices
 	let label_base = "account";
 	let mut index = 1;
@@ -197,7 +343,216 @@ where
 		{
 			let mut batch = wallet.batch()?;
 			batch.save_child_index(path, max_child_index + 1)?;
+			batch.commit()?;
 		}
 	}
+
+	// All done, nothing left to resume
+	{
+		let mut batch = wallet.batch()?;
+		batch.delete_restore_checkpoint()?;
+		batch.commit()?;
+	}
+
+	last_report.outputs_scanned = cmp::max(last_report.outputs_scanned, last_report.outputs_total);
+	last_report.eta_secs = Some(0);
+	last_report.complete = true;
+	report(&progress, last_report);
 	Ok(())
 }
+
+/// Scan the node's whole UTXO set for outputs belonging to this wallet,
+/// sending a progress report after each chunk scanned if a channel is provided
+fn scan_utxo_set<T, C, K>(
+	wallet: &mut T,
+	progress: &Option<Sender<ScanProgress>>,
+) -> Result<HashMap<Identifier, OutputResult>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain + 'static,
+{
+	let mut found = HashMap::new();
+	let mut start_index = 1;
+	let started = Instant::now();
+	let mut last_report = ScanProgress {
+		outputs_scanned: 0,
+		outputs_total: 0,
+		matches_found: 0,
+		eta_secs: None,
+		complete: false,
+	};
+	loop {
+		let (highest_index, last_retrieved_index, outputs) = wallet
+			.client()
+			.get_outputs_by_pmmr_index(start_index, RESTORE_CHUNK_SIZE)?;
+		if outputs.is_empty() {
+			last_report.outputs_total = highest_index;
+			break;
+		}
+		let keychain = wallet.keychain().clone();
+		for output in identify_utxo_outputs_parallel(&keychain, outputs)? {
+			found.insert(output.key_id.clone(), output);
+		}
+
+		last_report = scan_progress(
+			started,
+			1,
+			last_retrieved_index,
+			highest_index,
+			found.len() as u64,
+		);
+		report(progress, last_report.clone());
+
+		if last_retrieved_index >= highest_index {
+			break;
+		}
+		start_index = last_retrieved_index + 1;
+	}
+
+	last_report.outputs_scanned = cmp::max(last_report.outputs_scanned, last_report.outputs_total);
+	last_report.eta_secs = Some(0);
+	last_report.complete = true;
+	report(progress, last_report);
+	Ok(found)
+}
+
+/// Check the wallet's outputs against the node's UTXO set, scanned in full as
+/// during a restore, reporting any discrepancy and repairing those in the
+/// categories asked for. The scan progress is reported as for a restore.
+pub fn check_repair<T, C, K>(
+	wallet: &mut T,
+	fixes: &CheckFixes,
+	progress: Option<Sender<ScanProgress>>,
+) -> Result<CheckReport, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain + 'static,
+{
+	let mut on_chain = scan_utxo_set(wallet, &progress)?;
+	let mut report = CheckReport::default();
+
+	let mut known = HashMap::new();
+	for output in wallet.iter()? {
+		if !on_chain.contains_key(&output.key_id) {
+			match output.status {
+				OutputStatus::Unspent => report.missing_on_chain.push(output.clone()),
+				OutputStatus::Locked => report.confirmed_locked.push(output.clone()),
+				_ => (),
+			}
+		}
+		known.insert(output.key_id.clone(), output);
+	}
+	for (key_id, output) in on_chain.iter() {
+		match known.get(key_id) {
+			None => report
+				.missing_from_wallet
+				.push(found_output_data(output, None)),
+			Some(o) if o.status == OutputStatus::Spent => {
+				report.missing_from_wallet.push(o.clone())
+			}
+			_ => (),
+		}
+	}
+
+	// highest index in use under each parent, on chain or not
+	let mut max_used: HashMap<Identifier, u32> = HashMap::new();
+	for (key_id, n_child) in known
+		.values()
+		.map(|o| (o.key_id.clone(), o.n_child))
+		.chain(on_chain.values().map(|o| (o.key_id.clone(), o.n_child)))
+	{
+		let max = max_used.entry(key_id.parent_path()).or_insert(n_child);
+		*max = cmp::max(*max, n_child);
+	}
+	for (parent_key_id, max_used_index) in max_used {
+		let child_index = wallet.child_index(&parent_key_id)?;
+		if child_index <= max_used_index {
+			report.duplicate_indices.push(DuplicateIndex {
+				parent_key_id,
+				child_index,
+				max_used_index,
+			});
+		}
+	}
+
+	report
+		.missing_on_chain
+		.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+	report
+		.missing_from_wallet
+		.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+	report
+		.confirmed_locked
+		.sort_by(|a, b| a.key_id.cmp(&b.key_id));
+	report
+		.duplicate_indices
+		.sort_by(|a, b| a.parent_key_id.cmp(&b.parent_key_id));
+
+	let confirmed_txs: Vec<(Identifier, u32)> = report
+		.confirmed_locked
+		.iter()
+		.filter_map(|o| o.tx_log_entry.map(|id| (o.root_key_id.clone(), id)))
+		.collect();
+	let mut batch = wallet.batch()?;
+	if fixes.missing_on_chain {
+		for output in &report.missing_on_chain {
+			warn!(
+				"check: marking {:?} as spent, it's not on chain",
+				output.key_id
+			);
+			let mut output = output.clone();
+			output.mark_spent();
+			batch.save(output)?;
+		}
+	}
+	if fixes.missing_from_wallet {
+		for output in &report.missing_from_wallet {
+			warn!("check: restoring {:?} as unspent", output.key_id);
+			if known.contains_key(&output.key_id) {
+				let mut output = output.clone();
+				output.status = OutputStatus::Unspent;
+				batch.save(output)?;
+			} else if let Some(found) = on_chain.remove(&output.key_id) {
+				save_found_output(&mut batch, found)?;
+			}
+		}
+	}
+	if fixes.confirmed_locked {
+		for output in &report.confirmed_locked {
+			warn!("check: marking locked {:?} as spent", output.key_id);
+			let mut output = output.clone();
+			output.mark_spent();
+			if batch.get_output_lock(&output.key_id)?.is_some() {
+				batch.delete_output_lock(&output.key_id)?;
+			}
+			batch.save(output)?;
+		}
+		let txs: Vec<TxLogEntry> = batch
+			.tx_log_iter()?
+			.filter(|t| !t.confirmed && confirmed_txs.contains(&(t.parent_key_id.clone(), t.id)))
+			.collect();
+		for mut t in txs {
+			t.confirmed = true;
+			t.update_confirmation_ts();
+			let parent_key_id = t.parent_key_id.clone();
+			batch.save_tx_log_entry(t, &parent_key_id)?;
+		}
+	}
+	if fixes.duplicate_indices {
+		for d in &report.duplicate_indices {
+			warn!(
+				"check: moving derivation index of {:?} from {} to {}",
+				d.parent_key_id,
+				d.child_index,
+				d.max_used_index + 1
+			);
+			batch.save_child_index(&d.parent_key_id, d.max_used_index + 1)?;
+		}
+	}
+	batch.commit()?;
+
+	report.fixed = fixes.clone();
+	Ok(report)
+}
@@ -14,11 +14,24 @@
 
 //! Selection of inputs for building transactions
 
+use core::consensus;
+use core::core::transaction::TransactionBody;
 use keychain::{Identifier, Keychain};
 use libtx::{build, slate::Slate, tx_fee};
 use libwallet::error::{Error, ErrorKind};
 use libwallet::internal::keys;
 use libwallet::types::*;
+use std::cmp;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Number of blocks outputs stay locked by a slate that's never completed
+/// or cancelled, after which they can be selected again
+pub const OUTPUT_LOCK_EXPIRY_BLOCKS: u64 = 60;
+
+/// Maximum number of change outputs a send's change will be split into when
+/// working to a target output size
+pub const MAX_CHANGE_OUTPUTS: usize = 100;
 
 /// Initialize a transaction on the sender side, returns a corresponding
 /// libwallet transaction slate with the appropriate inputs selected,
@@ -61,15 +74,60 @@ where
 		selection_strategy_is_use_all,
 		&parent_key_id,
 	)?;
+	build_send_tx_slate_from(
+		wallet,
+		Slate::blank(num_participants),
+		elems,
+		inputs,
+		change_amounts_derivations,
+		amount,
+		fee,
+		current_height,
+		lock_height,
+		parent_key_id,
+		is_self,
+	)
+}
 
-	// Create public slate
-	let mut slate = Slate::blank(num_participants);
+/// Initialize a transaction on the sender side of a blank slate from already
+/// selected inputs and change, as `build_send_tx_slate` does once it's made
+/// its selection
+pub fn build_send_tx_slate_from<T: ?Sized, C, K>(
+	wallet: &mut T,
+	mut slate: Slate,
+	elems: Vec<Box<build::Append<K>>>,
+	inputs: Vec<OutputData>,
+	change_amounts_derivations: Vec<(u64, Identifier)>,
+	amount: u64,
+	fee: u64,
+	current_height: u64,
+	lock_height: u64,
+	parent_key_id: Identifier,
+	is_self: bool,
+) -> Result<
+	(
+		Slate,
+		Context,
+		impl FnOnce(&mut T, &str) -> Result<(), Error>,
+	),
+	Error,
+>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	// Fill in public slate
 	slate.amount = amount;
 	slate.height = current_height;
 	slate.lock_height = lock_height;
 	slate.fee = fee;
 	let slate_id = slate.id.clone();
 
+	// lock the selected inputs straight away, so a concurrent send can't
+	// select them while this slate is still in flight
+	lock_outputs(wallet, &inputs, &slate_id, current_height)?;
+
 	let keychain = wallet.keychain().clone();
 
 	let blinding = slate.add_transaction_elements(&keychain, elems)?;
@@ -112,6 +170,8 @@ where
 			coin.tx_log_entry = Some(log_id);
 			amount_debited = amount_debited + coin.value;
 			batch.lock_output(&mut coin)?;
+			// the output's status keeps it from being selected from here on
+			batch.delete_output_lock(&id)?;
 		}
 
 		t.amount_debited = amount_debited;
@@ -140,6 +200,62 @@ where
 	Ok((slate, context, update_sender_wallet_fn))
 }
 
+/// Lock the given outputs for a slate, clearing out any expired locks on the
+/// way. Fails if any of the outputs is still locked by another slate; as the
+/// check happens within the write batch it can't race a concurrent send.
+pub fn lock_outputs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	outputs: &Vec<OutputData>,
+	slate_id: &Uuid,
+	current_height: u64,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut batch = wallet.batch()?;
+	for lock in batch.output_lock_iter()? {
+		if lock.is_expired(current_height) {
+			batch.delete_output_lock(&lock.key_id)?;
+		}
+	}
+	for out in outputs {
+		if let Some(lock) = batch.get_output_lock(&out.key_id)? {
+			if lock.slate_id != *slate_id && !lock.is_expired(current_height) {
+				return Err(ErrorKind::OutputLocked(
+					out.key_id.to_string(),
+					lock.slate_id.to_string(),
+				).into());
+			}
+		}
+		batch.save_output_lock(OutputLock {
+			key_id: out.key_id.clone(),
+			slate_id: *slate_id,
+			expiry_height: current_height + OUTPUT_LOCK_EXPIRY_BLOCKS,
+		})?;
+	}
+	batch.commit()?;
+	Ok(())
+}
+
+/// Release all output locks held by the given slate
+pub fn release_output_locks<T: ?Sized, C, K>(wallet: &mut T, slate_id: &Uuid) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut batch = wallet.batch()?;
+	for lock in batch.output_lock_iter()? {
+		if lock.slate_id == *slate_id {
+			batch.delete_output_lock(&lock.key_id)?;
+		}
+	}
+	batch.commit()?;
+	Ok(())
+}
+
 /// Creates a new output in the wallet for the recipient,
 /// returning the key of the fresh output and a closure
 /// that actually performs the addition of the output to the
@@ -237,13 +353,59 @@ pub fn select_send_tx<T: ?Sized, C, K>(
 	),
 	Error,
 >
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let (coins, _, fee) = select_coins_and_fee(
+		wallet,
+		amount,
+		current_height,
+		minimum_confirmations,
+		max_outputs,
+		change_outputs,
+		selection_strategy_is_use_all,
+		parent_key_id,
+	)?;
+
+	// build transaction skeleton with inputs and change
+	let (mut parts, change_amounts_derivations) =
+		inputs_and_change(&coins, wallet, amount, fee, change_outputs)?;
+
+	// This is more proof of concept than anything but here we set lock_height
+	// on tx being sent (based on current chain height via api).
+	parts.push(build::with_lock_height(lock_height));
+
+	Ok((parts, coins, change_amounts_derivations, amount, fee))
+}
+
+/// Select the coins to spend and work out the fee for sending the given
+/// amount. Doesn't modify the wallet, so can also be used to estimate a send.
+pub fn select_coins_and_fee<T: ?Sized, C, K>(
+	wallet: &mut T,
+	amount: u64,
+	current_height: u64,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	change_outputs: usize,
+	selection_strategy_is_use_all: bool,
+	parent_key_id: &Identifier,
+) -> Result<
+	(
+		Vec<OutputData>,
+		u64, // total
+		u64, // fee
+	),
+	Error,
+>
 where
 	T: WalletBackend<C, K>,
 	C: WalletClient,
 	K: Keychain,
 {
 	// select some spendable coins from the wallet
-	let (max_outputs, coins) = select_coins(
+	let (max_outputs, mut coins) = select_coins(
 		wallet,
 		amount,
 		current_height,
@@ -251,7 +413,7 @@ where
 		max_outputs,
 		selection_strategy_is_use_all,
 		parent_key_id,
-	);
+	)?;
 
 	// sender is responsible for setting the fee on the partial tx
 	// recipient should double check the fee calculation and not blindly trust the
@@ -299,7 +461,7 @@ where
 			}
 
 			// select some spendable coins from the wallet
-			let (_, coins) = select_coins(
+			coins = select_coins(
 				wallet,
 				amount_with_fee,
 				current_height,
@@ -307,22 +469,127 @@ where
 				max_outputs,
 				selection_strategy_is_use_all,
 				parent_key_id,
-			);
+			)?
+			.1;
 			fee = tx_fee(coins.len(), num_outputs, 1, None);
 			total = coins.iter().map(|c| c.value).sum();
 			amount_with_fee = amount + fee;
 		}
 	}
 
-	// build transaction skeleton with inputs and change
-	let (mut parts, change_amounts_derivations) =
-		inputs_and_change(&coins, wallet, amount, fee, change_outputs)?;
+	Ok((coins, total, fee))
+}
 
-	// This is more proof of concept than anything but here we set lock_height
-	// on tx being sent (based on current chain height via api).
-	parts.push(build::with_lock_height(lock_height));
+/// Work out how many change outputs to split a send's change into so that
+/// each is at least the target size (any remainder goes to the last one).
+/// As each extra output increases the fee, keeps trying until the count
+/// settles.
+pub fn change_outputs_for_target<T: ?Sized, C, K>(
+	wallet: &mut T,
+	amount: u64,
+	current_height: u64,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	selection_strategy_is_use_all: bool,
+	parent_key_id: &Identifier,
+	target: u64,
+) -> Result<usize, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	if target == 0 {
+		return Err(ErrorKind::GenericError(
+			"Change output target size must be greater than zero".to_owned(),
+		).into());
+	}
+	let mut num_change_outputs = 1;
+	// counts already tried, more outputs meaning a higher fee and possibly
+	// more inputs, the estimate can otherwise go back and forth for ever
+	let mut tried = vec![];
+	loop {
+		let (_, total, fee) = select_coins_and_fee(
+			wallet,
+			amount,
+			current_height,
+			minimum_confirmations,
+			max_outputs,
+			num_change_outputs,
+			selection_strategy_is_use_all,
+			parent_key_id,
+		)?;
+		let change = total - amount - fee;
+		let fits = cmp::min(cmp::max(change / target, 1), MAX_CHANGE_OUTPUTS as u64) as usize;
+		// only ever come down from the first estimate
+		if fits == num_change_outputs || (fits > num_change_outputs && num_change_outputs > 1) {
+			return Ok(num_change_outputs);
+		}
+		// back to a count already tried, settle on the lower of the two
+		if tried.contains(&fits) {
+			return Ok(cmp::min(fits, num_change_outputs));
+		}
+		tried.push(num_change_outputs);
+		num_change_outputs = fits;
+	}
+}
 
-	Ok((parts, coins, change_amounts_derivations, amount, fee))
+/// Maximum number of inputs a transaction with a single output can spend
+/// while staying within the maximum weight, leaving room for the reward
+/// output and kernel it's checked against as part of a block
+pub fn max_tx_inputs() -> usize {
+	let reserved = TransactionBody::weight_as_block(0, 2, 2) as usize;
+	(consensus::MAX_BLOCK_WEIGHT - reserved) / consensus::BLOCK_INPUT_WEIGHT
+}
+
+/// Split the spendable outputs worth consolidating, smallest first, into
+/// batches of at most `max_inputs` (and never more than a transaction can
+/// hold). Only outputs below `threshold` are considered if one is given, and
+/// nothing is returned unless there are at least `min_outputs` of them.
+/// Batches of a single output, which there's nothing to merge with, are
+/// left out.
+pub fn consolidation_batches<T: ?Sized, C, K>(
+	wallet: &mut T,
+	current_height: u64,
+	minimum_confirmations: u64,
+	threshold: Option<u64>,
+	min_outputs: usize,
+	max_inputs: usize,
+	parent_key_id: &Identifier,
+) -> Result<Vec<Vec<OutputData>>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let locked = wallet
+		.output_lock_iter()?
+		.filter(|l| !l.is_expired(current_height))
+		.map(|l| l.key_id)
+		.collect::<HashSet<Identifier>>();
+	let below_threshold = |out: &OutputData| match threshold {
+		Some(t) => out.value < t,
+		None => true,
+	};
+	let mut eligible = wallet
+		.iter()?
+		.filter(|out| {
+			out.root_key_id == *parent_key_id
+				&& out.eligible_to_spend(current_height, minimum_confirmations)
+				&& !locked.contains(&out.key_id)
+				&& below_threshold(out)
+		}).collect::<Vec<OutputData>>();
+	if eligible.len() < cmp::max(min_outputs, 2) {
+		return Ok(vec![]);
+	}
+	eligible.sort_by_key(|out| out.value);
+
+	let batch_size = cmp::max(cmp::min(max_inputs, max_tx_inputs()), 2);
+	Ok(eligible
+		.chunks(batch_size)
+		.filter(|batch| batch.len() > 1)
+		.map(|batch| batch.to_vec())
+		.collect())
 }
 
 /// Selects inputs and change for a transaction
@@ -370,7 +637,7 @@ where
 		);
 
 		let part_change = change / num_change_outputs as u64;
-		let remainder_change = change % part_change;
+		let remainder_change = change % num_change_outputs as u64;
 
 		for x in 0..num_change_outputs {
 			// n-1 equal change_outputs and a final one accounting for any remainder
@@ -405,19 +672,27 @@ pub fn select_coins<T: ?Sized, C, K>(
 	max_outputs: usize,
 	select_all: bool,
 	parent_key_id: &Identifier,
-) -> (usize, Vec<OutputData>)
+) -> Result<(usize, Vec<OutputData>), Error>
 //    max_outputs_available, Outputs
 where
 	T: WalletBackend<C, K>,
 	C: WalletClient,
 	K: Keychain,
 {
+	// outputs locked by other slates still in flight can't be used
+	let locked = wallet
+		.output_lock_iter()?
+		.filter(|l| !l.is_expired(current_height))
+		.map(|l| l.key_id)
+		.collect::<HashSet<Identifier>>();
+
 	// first find all eligible outputs based on number of confirmations
 	let mut eligible = wallet
-		.iter()
+		.iter()?
 		.filter(|out| {
 			out.root_key_id == *parent_key_id
 				&& out.eligible_to_spend(current_height, minimum_confirmations)
+				&& !locked.contains(&out.key_id)
 		}).collect::<Vec<OutputData>>();
 
 	let max_available = eligible.len();
@@ -437,7 +712,7 @@ where
 		for window in eligible.windows(max_outputs) {
 			let windowed_eligibles = window.iter().cloned().collect::<Vec<_>>();
 			if let Some(outputs) = select_from(amount, select_all, windowed_eligibles) {
-				return (max_available, outputs);
+				return Ok((max_available, outputs));
 			}
 		}
 		// Not exist in any window of which total amount >= amount.
@@ -448,11 +723,11 @@ where
 				"Extending maximum number of outputs. {} outputs selected.",
 				outputs.len()
 			);
-			return (max_available, outputs);
+			return Ok((max_available, outputs));
 		}
 	} else {
 		if let Some(outputs) = select_from(amount, select_all, eligible.clone()) {
-			return (max_available, outputs);
+			return Ok((max_available, outputs));
 		}
 	}
 
@@ -460,10 +735,10 @@ where
 	// so return the largest amount we can so we can provide guidance on what is
 	// possible
 	eligible.reverse();
-	(
+	Ok((
 		max_available,
 		eligible.iter().take(max_outputs).cloned().collect(),
-	)
+	))
 }
 
 fn select_from(amount: u64, select_all: bool, outputs: Vec<OutputData>) -> Option<Vec<OutputData>> {
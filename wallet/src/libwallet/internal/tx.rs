@@ -14,17 +14,25 @@
 
 //! Transaction building functions
 
+use chrono::prelude::*;
+use chrono::Duration;
+use failure::ResultExt;
 use std::sync::Arc;
 use util::RwLock;
 
 use core::core::verifier_cache::LruVerifierCache;
 use core::core::Transaction;
+use core::ser;
 use keychain::{Identifier, Keychain};
 use libtx::slate::Slate;
 use libtx::{build, tx_fee};
 use libwallet::internal::{selection, updater};
-use libwallet::types::{Context, TxLogEntryType, WalletBackend, WalletClient};
+use libwallet::types::{
+	Context, OutputData, SendEstimate, TxLogEntry, TxLogEntryType, WalletBackend, WalletClient,
+};
 use libwallet::{Error, ErrorKind};
+use util;
+use uuid::Uuid;
 
 /// Receive a transaction, modifying the slate accordingly (which can then be
 /// sent back to sender for posting)
@@ -129,6 +137,201 @@ where
 	Ok((slate, context, sender_lock_fn))
 }
 
+/// Issue a transaction to self spending exactly the given outputs into a
+/// single one, less the fee
+pub fn create_consolidation_tx<T: ?Sized, C, K>(
+	wallet: &mut T,
+	coins: Vec<OutputData>,
+	parent_key_id: &Identifier,
+) -> Result<
+	(
+		Slate,
+		Context,
+		impl FnOnce(&mut T, &str) -> Result<(), Error>,
+	),
+	Error,
+>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let current_height = wallet.client().get_chain_height()?;
+	let lock_height = current_height;
+
+	let fee = tx_fee(coins.len(), 1, 1, None);
+	let total: u64 = coins.iter().map(|c| c.value).sum();
+	if total <= fee {
+		return Err(ErrorKind::NotEnoughFunds {
+			available: total,
+			needed: fee + 1,
+		}.into());
+	}
+	let amount = total - fee;
+	let (mut elems, change_amounts_derivations) =
+		selection::inputs_and_change(&coins, wallet, amount, fee, 1)?;
+	elems.push(build::with_lock_height(lock_height));
+
+	let (mut slate, mut context, sender_lock_fn) = selection::build_send_tx_slate_from(
+		wallet,
+		Slate::blank(2),
+		elems,
+		coins,
+		change_amounts_derivations,
+		amount,
+		fee,
+		current_height,
+		lock_height,
+		parent_key_id.clone(),
+		true,
+	)?;
+	let _ = slate.fill_round_1(
+		wallet.keychain(),
+		&mut context.sec_key,
+		&context.sec_nonce,
+		0,
+	)?;
+
+	Ok((slate, context, sender_lock_fn))
+}
+
+/// Merges small outputs by sending them to self, one transaction per batch
+/// of `selection::consolidation_batches`, returning the completed slates. If
+/// any batch fails, the transactions of the ones already completed are
+/// cancelled, so nothing stays locked.
+pub fn consolidate<T: ?Sized, C, K>(
+	wallet: &mut T,
+	minimum_confirmations: u64,
+	threshold: Option<u64>,
+	min_outputs: usize,
+	max_inputs: usize,
+	parent_key_id: &Identifier,
+) -> Result<Vec<Slate>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let current_height = wallet.client().get_chain_height()?;
+	updater::refresh_outputs(wallet, parent_key_id)?;
+	let batches = selection::consolidation_batches(
+		wallet,
+		current_height,
+		minimum_confirmations,
+		threshold,
+		min_outputs,
+		max_inputs,
+		parent_key_id,
+	)?;
+
+	let mut slate_ids = vec![];
+	let mut slates = vec![];
+	let mut res = Ok(());
+	for coins in batches {
+		let (mut slate, context, lock_fn) =
+			match create_consolidation_tx(wallet, coins, parent_key_id) {
+				Ok(created) => created,
+				Err(e) => {
+					res = Err(e);
+					break;
+				}
+			};
+		slate_ids.push(slate.id);
+		res = receive_tx(wallet, &mut slate, parent_key_id, true)
+			.and_then(|_| complete_tx(wallet, &mut slate, &context))
+			.and_then(|_| {
+				let tx_hex = util::to_hex(ser::ser_vec(&slate.tx).unwrap());
+				lock_fn(wallet, &tx_hex)
+			});
+		if res.is_err() {
+			break;
+		}
+		slates.push(slate);
+	}
+	if res.is_err() {
+		for id in &slate_ids {
+			if let Err(e) = cancel_slate_txs(wallet, parent_key_id, id) {
+				error!("Failed to cancel consolidation {}: {:?}", id, e);
+			}
+		}
+	}
+	res.map(|_| slates)
+}
+
+/// Work out the fee, inputs and effect on the wallet balance of a send
+/// without locking anything or creating a slate
+pub fn estimate_send_tx<T: ?Sized, C, K>(
+	wallet: &mut T,
+	amount: u64,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	num_change_outputs: usize,
+	selection_strategy_is_use_all: bool,
+	parent_key_id: &Identifier,
+) -> Result<SendEstimate, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let current_height = wallet.client().get_chain_height()?;
+	// ensure outputs we're selecting are up to date
+	updater::refresh_outputs(wallet, parent_key_id)?;
+
+	let (coins, total, fee) = selection::select_coins_and_fee(
+		wallet,
+		amount,
+		current_height,
+		minimum_confirmations,
+		max_outputs,
+		num_change_outputs,
+		selection_strategy_is_use_all,
+		parent_key_id,
+	)?;
+	let info = updater::retrieve_info(wallet, parent_key_id)?;
+	let change = total - amount - fee;
+
+	Ok(SendEstimate {
+		amount,
+		fee,
+		inputs: coins,
+		change,
+		num_change_outputs: if change == 0 { 0 } else { num_change_outputs },
+		total_after: info.total.saturating_sub(amount + fee),
+		spendable_after: info.amount_currently_spendable.saturating_sub(total),
+	})
+}
+
+/// Number of change outputs needed for each to be at least the given target
+/// size when sending the given amount
+pub fn change_outputs_for_target<T: ?Sized, C, K>(
+	wallet: &mut T,
+	amount: u64,
+	minimum_confirmations: u64,
+	max_outputs: usize,
+	selection_strategy_is_use_all: bool,
+	parent_key_id: &Identifier,
+	target: u64,
+) -> Result<usize, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let current_height = wallet.client().get_chain_height()?;
+	updater::refresh_outputs(wallet, parent_key_id)?;
+	selection::change_outputs_for_target(
+		wallet,
+		amount,
+		current_height,
+		minimum_confirmations,
+		max_outputs,
+		selection_strategy_is_use_all,
+		parent_key_id,
+		target,
+	)
+}
+
 /// Complete a transaction as the sender
 pub fn complete_tx<T: ?Sized, C, K>(
 	wallet: &mut T,
@@ -149,6 +352,28 @@ where
 	Ok(())
 }
 
+/// Stores the transaction of a finalized slate in its log entry, replacing
+/// the partial one stored when the slate was created, so it can be reposted
+/// if the broadcast gets lost
+pub fn update_stored_tx<T: ?Sized, C, K>(wallet: &mut T, slate: &Slate) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let tx = wallet
+		.tx_log_iter()?
+		.find(|t| t.tx_slate_id == Some(slate.id));
+	if let Some(mut t) = tx {
+		let parent_key_id = t.parent_key_id.clone();
+		t.tx_hex = Some(util::to_hex(ser::ser_vec(&slate.tx).unwrap()));
+		let batch = wallet.batch()?;
+		batch.save_tx_log_entry(t, &parent_key_id)?;
+		batch.commit()?;
+	}
+	Ok(())
+}
+
 /// Rollback outputs associated with a transaction in the wallet
 pub fn cancel_tx<T: ?Sized, C, K>(
 	wallet: &mut T,
@@ -174,7 +399,136 @@ where
 	// get outputs associated with tx
 	let res = updater::retrieve_outputs(wallet, false, Some(tx_id), &parent_key_id)?;
 	let outputs = res.iter().map(|(out, _)| out).cloned().collect();
+	let slate_id = tx.tx_slate_id;
 	updater::cancel_tx_and_outputs(wallet, tx, outputs, parent_key_id)?;
+	// and anything still reserved for the slate
+	if let Some(id) = slate_id {
+		selection::release_output_locks(wallet, &id)?;
+	}
+	Ok(())
+}
+
+/// Cancel the unconfirmed transactions of a slate, on both the sending and
+/// receiving end when sent to self, unlocking their outputs
+pub fn cancel_slate_txs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+	slate_id: &Uuid,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let txs = updater::retrieve_txs(wallet, None, parent_key_id)?
+		.into_iter()
+		.filter(|t| t.tx_slate_id == Some(*slate_id) && !t.confirmed)
+		.collect::<Vec<_>>();
+	for tx in txs {
+		let res = updater::retrieve_outputs(wallet, false, Some(tx.id), parent_key_id)?;
+		let outputs = res.iter().map(|(out, _)| out).cloned().collect();
+		updater::cancel_tx_and_outputs(wallet, tx, outputs, parent_key_id)?;
+	}
+	selection::release_output_locks(wallet, slate_id)?;
+	Ok(())
+}
+
+/// Pending sends created more than `ttl` ago whose slate was never
+/// finalized, i.e. whose private context is still around
+pub fn expired_txs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+	ttl: Duration,
+) -> Result<Vec<TxLogEntry>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let cutoff = Utc::now() - ttl;
+	let pending = updater::retrieve_txs(wallet, None, parent_key_id)?
+		.into_iter()
+		.filter(|t| t.tx_type == TxLogEntryType::TxSent && !t.confirmed && t.creation_ts <= cutoff);
+	let mut expired = vec![];
+	for t in pending {
+		if let Some(id) = t.tx_slate_id {
+			if wallet.get_private_context(id.as_bytes()).is_ok() {
+				expired.push(t);
+			}
+		}
+	}
+	Ok(expired)
+}
+
+/// Cancel all expired pending sends (see `expired_txs`), unlocking their
+/// outputs. Only lists them without cancelling anything if `dry_run` is set.
+pub fn cancel_expired_txs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+	ttl: Duration,
+	dry_run: bool,
+) -> Result<Vec<TxLogEntry>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let expired = expired_txs(wallet, parent_key_id, ttl)?;
+	if dry_run {
+		return Ok(expired);
+	}
+	for t in &expired {
+		cancel_tx(wallet, parent_key_id, t.id)?;
+		// the slate can't be finalized anymore
+		if let Some(id) = t.tx_slate_id {
+			let mut batch = wallet.batch()?;
+			batch.delete_private_context(id.as_bytes())?;
+			batch.commit()?;
+		}
+		info!("Cancelled expired transaction {}", t.id);
+	}
+	Ok(expired)
+}
+
+/// Payments received and confirmed that weren't notified about yet, oldest
+/// first
+pub fn unnotified_receipts<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+) -> Result<Vec<TxLogEntry>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut txs = updater::retrieve_txs(wallet, None, parent_key_id)?
+		.into_iter()
+		.filter(|t| t.tx_type == TxLogEntryType::TxReceived && t.confirmed && !t.notified)
+		.collect::<Vec<_>>();
+	txs.sort_by_key(|t| t.id);
+	Ok(txs)
+}
+
+/// Marks a payment received as notified about
+pub fn mark_notified<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+	tx_id: u32,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let tx_vec = updater::retrieve_txs(wallet, Some(tx_id), parent_key_id)?;
+	if tx_vec.len() != 1 {
+		return Err(ErrorKind::TransactionDoesntExist(tx_id))?;
+	}
+	let mut tx = tx_vec[0].clone();
+	tx.notified = true;
+	let batch = wallet.batch()?;
+	batch.save_tx_log_entry(tx, parent_key_id)?;
+	batch.commit()?;
 	Ok(())
 }
 
@@ -198,6 +552,31 @@ where
 	Ok((tx.confirmed, tx.tx_hex))
 }
 
+/// Retrieve the stored transaction with the given id, checking it's complete
+/// and valid before it's posted again. Transactions finalized before their
+/// log entry got updated on finalization only have the partial transaction
+/// stored, which doesn't validate.
+pub fn retrieve_stored_tx<T: ?Sized, C, K>(
+	wallet: &mut T,
+	parent_key_id: &Identifier,
+	tx_id: u32,
+) -> Result<(bool, Transaction), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let (confirmed, tx_hex) = retrieve_tx_hex(wallet, parent_key_id, tx_id)?;
+	let tx_hex = tx_hex.ok_or_else(|| ErrorKind::TransactionBuildingNotCompleted(tx_id))?;
+	let tx_bin = util::from_hex(tx_hex).context(ErrorKind::Format)?;
+	let tx = ser::deserialize::<Transaction>(&mut &tx_bin[..])?;
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	if tx.validate(verifier_cache).is_err() {
+		return Err(ErrorKind::TransactionBuildingNotCompleted(tx_id))?;
+	}
+	Ok((confirmed, tx))
+}
+
 /// Issue a burn tx
 pub fn issue_burn_tx<T: ?Sized, C, K>(
 	wallet: &mut T,
@@ -229,7 +608,7 @@ where
 		max_outputs,
 		false,
 		parent_key_id,
-	);
+	)?;
 
 	debug!("selected some coins - {}", coins.len());
 
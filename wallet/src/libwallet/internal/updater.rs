@@ -15,20 +15,21 @@
 //! Utilities to check the status of all the outputs we have stored in
 //! the wallet storage and update them.
 
+use chrono::prelude::*;
 use failure::ResultExt;
 use std::collections::HashMap;
 
 use core::consensus::reward;
-use core::core::{Output, TxKernel};
+use core::core::{Output, Transaction, TxKernel};
 use core::{global, ser};
 use keychain::{Identifier, Keychain};
 use libtx::reward;
 use libwallet;
 use libwallet::error::{Error, ErrorKind};
-use libwallet::internal::keys;
+use libwallet::internal::{history, keys, verifier};
 use libwallet::types::{
-	BlockFees, CbData, OutputData, OutputStatus, TxLogEntry, TxLogEntryType, WalletBackend,
-	WalletClient, WalletInfo,
+	BlockFees, CbData, ImmatureCoinbase, NodeTrust, OutputData, OutputStatus, TxExportEntry,
+	TxLogEntry, TxLogEntryType, WalletBackend, WalletClient, WalletInfo,
 };
 use util;
 use util::secp::pedersen;
@@ -47,7 +48,7 @@ where
 {
 	// just read the wallet here, no need for a write lock
 	let mut outputs = wallet
-		.iter()
+		.iter()?
 		.filter(|out| out.root_key_id == *parent_key_id)
 		.filter(|out| {
 			if show_spent {
@@ -89,7 +90,7 @@ where
 {
 	// just read the wallet here, no need for a write lock
 	let mut txs = if let Some(id) = tx_id {
-		let tx = wallet.tx_log_iter().find(|t| t.id == id);
+		let tx = wallet.tx_log_iter()?.find(|t| t.id == id);
 		if let Some(t) = tx {
 			vec![t]
 		} else {
@@ -97,13 +98,89 @@ where
 		}
 	} else {
 		wallet
-			.tx_log_iter()
+			.tx_log_iter()?
 			.filter(|t| t.parent_key_id == *parent_key_id)
 			.collect::<Vec<_>>()
 	};
 	txs.sort_by_key(|tx| tx.creation_ts);
 	Ok(txs)
 }
+
+/// Build export entries for all transactions of the given parent created
+/// within the (inclusive) time range, oldest first
+pub fn export_txs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	from: Option<DateTime<Utc>>,
+	to: Option<DateTime<Utc>>,
+	parent_key_id: &Identifier,
+) -> Result<Vec<TxExportEntry>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let height = wallet.last_confirmed_height()?;
+	let account = match wallet.acct_path_iter()?.find(|a| a.path == *parent_key_id) {
+		Some(a) => a.label,
+		None => parent_key_id.to_bip_32_string(),
+	};
+
+	// height each tx was confirmed at, from the outputs it created or spent
+	let mut tx_heights: HashMap<u32, u64> = HashMap::new();
+	for out in wallet.iter()?.filter(|o| o.root_key_id == *parent_key_id) {
+		if let Some(id) = out.tx_log_entry {
+			let h = tx_heights.entry(id).or_insert(0);
+			if out.height > *h {
+				*h = out.height;
+			}
+		}
+	}
+
+	let txs = retrieve_txs(wallet, None, parent_key_id)?;
+	let mut entries = vec![];
+	for t in txs {
+		if let Some(f) = from {
+			if t.creation_ts < f {
+				continue;
+			}
+		}
+		if let Some(e) = to {
+			if t.creation_ts > e {
+				continue;
+			}
+		}
+		let confirmations = match tx_heights.get(&t.id) {
+			Some(h) if t.confirmed && height >= *h => height - h + 1,
+			_ => 0,
+		};
+		let mut kernel_excess = vec![];
+		if let Some(ref tx_hex) = t.tx_hex {
+			let tx_bin = util::from_hex(tx_hex.clone()).context(ErrorKind::Format)?;
+			let tx = ser::deserialize::<Transaction>(&mut &tx_bin[..])?;
+			for k in tx.kernels() {
+				kernel_excess.push(util::to_hex(k.excess.0.to_vec()));
+			}
+		}
+		entries.push(TxExportEntry {
+			id: t.id,
+			tx_slate_id: t.tx_slate_id,
+			tx_type: t.tx_type,
+			account: account.clone(),
+			creation_ts: t.creation_ts,
+			confirmation_ts: t.confirmation_ts,
+			confirmed: t.confirmed,
+			confirmations,
+			amount_credited: t.amount_credited,
+			amount_debited: t.amount_debited,
+			fee: t.fee,
+			kernel_excess,
+			num_inputs: t.num_inputs,
+			num_outputs: t.num_outputs,
+			contact: t.contact,
+		});
+	}
+	Ok(entries)
+}
 /// Refreshes the outputs in a wallet with the latest information
 /// from a node
 pub fn refresh_outputs<T: ?Sized, C, K>(
@@ -117,6 +194,7 @@ where
 {
 	let height = wallet.client().get_chain_height()?;
 	refresh_output_state(wallet, height, parent_key_id)?;
+	history::record_snapshot(wallet, parent_key_id)?;
 	Ok(())
 }
 
@@ -132,12 +210,13 @@ where
 	K: Keychain,
 {
 	let mut wallet_outputs: HashMap<pedersen::Commitment, Identifier> = HashMap::new();
-	let keychain = wallet.keychain().clone();
-	let unspents = wallet
-		.iter()
-		.filter(|x| x.root_key_id == *parent_key_id && x.status != OutputStatus::Spent);
+	let unspents: Vec<OutputData> = wallet
+		.iter()?
+		.filter(|x| x.root_key_id == *parent_key_id && x.status != OutputStatus::Spent)
+		.collect();
 	for out in unspents {
-		let commit = keychain.commit(out.value, &out.key_id)?;
+		// stored commitments let a watch-only wallet refresh without keys
+		let commit = wallet.get_commitment(&out.key_id)?;
 		wallet_outputs.insert(commit, out.key_id.clone());
 	}
 	Ok(wallet_outputs)
@@ -168,10 +247,10 @@ where
 		}
 	}
 	let mut tx = tx.clone();
-	if tx.tx_type == TxLogEntryType::TxSent {
+	if tx.tx_type == TxLogEntryType::TxSent || tx.tx_type == TxLogEntryType::TxSentSelf {
 		tx.tx_type = TxLogEntryType::TxSentCancelled;
 	}
-	if tx.tx_type == TxLogEntryType::TxReceived {
+	if tx.tx_type == TxLogEntryType::TxReceived || tx.tx_type == TxLogEntryType::TxReceivedSelf {
 		tx.tx_type = TxLogEntryType::TxReceivedCancelled;
 	}
 	batch.save_tx_log_entry(tx, parent_key_id)?;
@@ -232,7 +311,7 @@ where
 						// note that one involved input/output confirmation SHOULD be enough
 						// to reliably confirm the tx
 						if !output.is_coinbase && output.status == OutputStatus::Unconfirmed {
-							let tx = batch.tx_log_iter().find(|t| {
+							let tx = batch.tx_log_iter()?.find(|t| {
 								Some(t.id) == output.tx_log_entry
 									&& t.parent_key_id == *parent_key_id
 							});
@@ -276,9 +355,13 @@ where
 	// and a list of outputs we want to query the node for
 	let wallet_outputs = map_wallet_outputs(wallet, parent_key_id)?;
 
-	let wallet_output_keys = wallet_outputs.keys().map(|commit| commit.clone()).collect();
-
-	let api_outputs = wallet.client().get_outputs_from_node(wallet_output_keys)?;
+	let api_outputs = match wallet.node_trust() {
+		NodeTrust::Trusted => {
+			let wallet_output_keys = wallet_outputs.keys().map(|commit| commit.clone()).collect();
+			wallet.client().get_outputs_from_node(wallet_output_keys)?
+		}
+		NodeTrust::Verified => verifier::verified_outputs(wallet, &wallet_outputs, height)?,
+	};
 	apply_api_outputs(wallet, &wallet_outputs, &api_outputs, height, parent_key_id)?;
 	clean_old_unconfirmed(wallet, height)?;
 	Ok(())
@@ -294,7 +377,7 @@ where
 		return Ok(());
 	}
 	let mut ids_to_del = vec![];
-	for out in wallet.iter() {
+	for out in wallet.iter()? {
 		if out.status == OutputStatus::Unconfirmed && out.height > 0 && out.height < height - 500 {
 			ids_to_del.push(out.key_id.clone())
 		}
@@ -319,28 +402,47 @@ where
 	K: Keychain,
 {
 	let current_height = wallet.last_confirmed_height()?;
+	// the transactions we sent, their unconfirmed outputs being our change
+	let sent_txs: Vec<u32> = wallet
+		.tx_log_iter()?
+		.filter(|t| t.parent_key_id == *parent_key_id)
+		.filter(|t| t.tx_type == TxLogEntryType::TxSent || t.tx_type == TxLogEntryType::TxSentSelf)
+		.map(|t| t.id)
+		.collect();
 	let outputs = wallet
-		.iter()
+		.iter()?
 		.filter(|out| out.root_key_id == *parent_key_id);
 
 	let mut unspent_total = 0;
 	let mut immature_total = 0;
 	let mut unconfirmed_total = 0;
+	let mut change_total = 0;
 	let mut locked_total = 0;
+	let mut immature_coinbases = vec![];
 	for out in outputs {
 		if out.status == OutputStatus::Unspent && out.lock_height <= current_height {
 			unspent_total += out.value;
 		}
 		if out.status == OutputStatus::Unspent && out.lock_height > current_height {
 			immature_total += out.value;
+			immature_coinbases.push(ImmatureCoinbase {
+				value: out.value,
+				height: out.height,
+				lock_height: out.lock_height,
+				blocks_to_maturity: out.lock_height - current_height,
+			});
 		}
 		if out.status == OutputStatus::Unconfirmed && !out.is_coinbase {
 			unconfirmed_total += out.value;
+			if out.tx_log_entry.map_or(false, |id| sent_txs.contains(&id)) {
+				change_total += out.value;
+			}
 		}
 		if out.status == OutputStatus::Locked {
 			locked_total += out.value;
 		}
 	}
+	immature_coinbases.sort_by_key(|c| c.lock_height);
 
 	Ok(WalletInfo {
 		last_confirmed_height: current_height,
@@ -349,6 +451,8 @@ where
 		amount_immature: immature_total,
 		amount_locked: locked_total,
 		amount_currently_spendable: unspent_total,
+		amount_unconfirmed_change: change_total,
+		immature_coinbases,
 	})
 }
 
@@ -0,0 +1,490 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification of the outputs a node reports, for wallets not trusting it.
+//! The wallet tracks the header chain of the node from genesis or a
+//! checkpoint, checking each header builds on the previous one at the
+//! difficulty the chain sets, and checks Merkle proofs of its outputs up to
+//! the output roots of those headers.
+//!
+//! A node can still leave unspent outputs out, which then show as spent
+//! until refreshed against another node. Checking the tracked headers
+//! against other nodes as well tells when the node lags behind or follows
+//! another chain than theirs.
+
+use std::collections::HashMap;
+
+use core::consensus::{self, HeaderInfo};
+use core::core::hash::{Hash, Hashed};
+use core::core::{pmmr, BlockHeader, OutputFeatures, OutputIdentifier};
+use core::genesis;
+use core::global::{self, ChainTypes};
+use core::pow::{self, Difficulty};
+use keychain::{Identifier, Keychain};
+use libwallet::error::{Error, ErrorKind};
+use libwallet::types::{OutputStatus, WalletBackend, WalletClient};
+use util;
+use util::secp::pedersen;
+
+/// Headers kept behind the last one tracked, a day of blocks. Reorgs deeper
+/// than that need the tracking to start over.
+pub const TRACKED_HEADERS: u64 = 1440;
+
+/// Blocks the node can be behind another one before warning about it, as
+/// new blocks take a while to get around
+pub const LAGGING_BLOCKS: u64 = 5;
+
+/// Headers below one its difficulty is computed over, the window it adjusts
+/// over and the header before, for the difficulty of the first
+const DIFFICULTY_HEADERS: u64 = consensus::DIFFICULTY_ADJUST_WINDOW + 2;
+
+/// Outputs the node reports as unspent among the provided ones, keyed by
+/// commit as `WalletClient::get_outputs_from_node` returns them, once their
+/// Merkle proofs check out against the headers the wallet tracks
+pub fn verified_outputs<T: ?Sized, C, K>(
+	wallet: &mut T,
+	wallet_outputs: &HashMap<pedersen::Commitment, Identifier>,
+	height: u64,
+) -> Result<HashMap<pedersen::Commitment, (String, u64)>, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let commits = wallet_outputs.keys().cloned().collect();
+	let proofs = wallet.client().get_output_proofs(commits)?;
+
+	// the node may have moved on since telling its height
+	let sync_height = proofs
+		.iter()
+		.map(|p| p.header_height)
+		.fold(height, |max, h| max.max(h));
+	sync_headers(wallet, sync_height)?;
+	check_header_nodes(wallet)?;
+
+	let mut api_outputs = HashMap::new();
+	for p in proofs {
+		let header = match wallet.tracked_header(p.header_height)? {
+			Some(ref h) if h.hash() == p.header_hash => h.clone(),
+			_ => {
+				return Err(ErrorKind::NodeVerification(format!(
+					"proofs against header {} at {}, not on the tracked chain",
+					p.header_hash, p.header_height
+				)))?
+			}
+		};
+		for (commit, out_height, pos, proof) in p.outputs {
+			// the features hashed are the ones of our output, not the node's
+			let id = match wallet_outputs.get(&commit) {
+				Some(id) => id,
+				None => continue,
+			};
+			let features = if wallet.get(id)?.is_coinbase {
+				OutputFeatures::COINBASE_OUTPUT
+			} else {
+				OutputFeatures::DEFAULT_OUTPUT
+			};
+			let out_id = OutputIdentifier::new(features, &commit);
+			let valid = pmmr::is_leaf(pos)
+				&& proof.mmr_size == header.output_mmr_size
+				&& proof.verify(header.output_root, &out_id, pos).is_ok();
+			if !valid {
+				return Err(ErrorKind::NodeVerification(format!(
+					"invalid merkle proof for output {:?}",
+					commit
+				)))?;
+			}
+			api_outputs.insert(commit, (util::to_hex(commit.0.to_vec()), out_height));
+		}
+	}
+	Ok(api_outputs)
+}
+
+/// Brings the header chain the wallet tracks up to the node's at the given
+/// height, dropping the headers the node's chain doesn't have anymore first.
+/// The tracking starts from the checkpoint configured or the genesis of the
+/// network, and every new header has to build on the previous one with a
+/// valid proof of work, at the difficulty the chain sets for it.
+pub fn sync_headers<T: ?Sized, C, K>(wallet: &mut T, height: u64) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let client = wallet.client().clone();
+	let last = match wallet.last_tracked_header()? {
+		Some(h) => h,
+		None => {
+			let checkpoint = match wallet.header_checkpoint() {
+				Some(c) => Some(parse_checkpoint(&c)?),
+				None => None,
+			};
+			let window = anchor_headers(&client, checkpoint, height)?;
+			info!(
+				"Tracking the node's headers from {} at {}",
+				window.last().unwrap().hash(),
+				window.last().unwrap().height
+			);
+			return save_headers(wallet, window, vec![], height);
+		}
+	};
+
+	// walk back to where the node's chain and ours meet, a node behind ours
+	// just not having the last headers
+	let mut fork_height = last.height.min(height);
+	let fork = loop {
+		let tracked = wallet.tracked_header(fork_height)?.ok_or_else(|| {
+			ErrorKind::NodeVerification(format!(
+				"node's chain forked off more than {} headers back",
+				TRACKED_HEADERS
+			))
+		})?;
+		if client.get_header(fork_height)?.hash() == tracked.hash() {
+			break tracked;
+		}
+		if fork_height == 0 {
+			return Err(ErrorKind::NodeVerification(
+				"node's chain has another genesis".to_owned(),
+			))?;
+		}
+		fork_height -= 1;
+	};
+	let mut dropped = vec![];
+	if fork.height < last.height.min(height) {
+		warn!(
+			"Node's chain forked off the tracked headers at {}",
+			fork.height
+		);
+		dropped.extend(fork.height + 1..last.height + 1);
+		warn_reorged_txs(wallet, fork.height)?;
+	}
+
+	// the headers below the fork the difficulty of the next one depends on
+	let mut window = vec![fork];
+	while (window.len() as u64) < DIFFICULTY_HEADERS && window[0].height > 0 {
+		match wallet.tracked_header(window[0].height - 1)? {
+			Some(h) => window.insert(0, h),
+			None => break,
+		}
+	}
+	save_headers(wallet, window, dropped, height)
+}
+
+/// Verifies the node's headers following the window up to the given height
+/// and saves them, deleting the dropped ones and the ones no longer kept.
+/// The window headers are saved as well when starting the tracking.
+fn save_headers<T: ?Sized, C, K>(
+	wallet: &mut T,
+	mut window: Vec<BlockHeader>,
+	dropped: Vec<u64>,
+	height: u64,
+) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let client = wallet.client().clone();
+	let start = dropped.is_empty() && wallet.last_tracked_header()?.is_none();
+	let mut batch = wallet.batch()?;
+	for h in dropped {
+		batch.delete_tracked_header(h)?;
+	}
+	if start {
+		for header in &window {
+			batch.save_tracked_header(header)?;
+		}
+	}
+	for h in window.last().unwrap().height + 1..height + 1 {
+		let header = client.get_header(h)?;
+		verify_header(&header, &window)?;
+		if header.height > TRACKED_HEADERS {
+			batch.delete_tracked_header(header.height - TRACKED_HEADERS - 1)?;
+		}
+		batch.save_tracked_header(&header)?;
+		window.push(header);
+		if window.len() as u64 > DIFFICULTY_HEADERS {
+			window.remove(0);
+		}
+	}
+	batch.commit()?;
+	Ok(())
+}
+
+/// Headers the tracking starts from, the node's at the checkpoint or at
+/// genesis, and the ones below needed to check the difficulty of the next.
+/// The headers below are known from the hashes they're referred by. Test
+/// networks mine their genesis at startup, taken from the node when no
+/// checkpoint is set.
+fn anchor_headers<C>(
+	client: &C,
+	checkpoint: Option<(u64, Hash)>,
+	height: u64,
+) -> Result<Vec<BlockHeader>, Error>
+where
+	C: WalletClient,
+{
+	let (anchor_height, anchor_hash) = match checkpoint {
+		Some((h, hash)) => (h, Some(hash)),
+		None => (0, network_genesis()),
+	};
+	if height < anchor_height {
+		return Err(ErrorKind::NodeVerification(format!(
+			"node at {} is behind the checkpoint at {}",
+			height, anchor_height
+		)))?;
+	}
+	let anchor = client.get_header(anchor_height)?;
+	match anchor_hash {
+		Some(hash) => {
+			if anchor.hash() != hash {
+				return Err(ErrorKind::NodeVerification(format!(
+					"node has header {} at {} instead of {}",
+					anchor.hash(),
+					anchor_height,
+					hash
+				)))?;
+			}
+		}
+		None => {
+			verify_pow(&anchor)?;
+			warn!(
+				"No genesis known for the network, tracking from the node's {}",
+				anchor.hash()
+			);
+		}
+	}
+
+	let mut window = vec![anchor];
+	while (window.len() as u64) < DIFFICULTY_HEADERS && window[0].height > 0 {
+		let header = client.get_header(window[0].height - 1)?;
+		if header.hash() != window[0].prev_hash {
+			return Err(ErrorKind::NodeVerification(format!(
+				"header {} at {} isn't the one {} builds on",
+				header.hash(),
+				header.height,
+				window[0].hash()
+			)))?;
+		}
+		window.insert(0, header);
+	}
+	Ok(window)
+}
+
+/// Genesis hash of the network, as the node starts its chain from, none on
+/// the test networks
+fn network_genesis() -> Option<Hash> {
+	let genesis = match *global::CHAIN_TYPE.read() {
+		ChainTypes::Testnet1 => genesis::genesis_testnet1(),
+		ChainTypes::Testnet2 => genesis::genesis_testnet2(),
+		ChainTypes::Testnet3 => genesis::genesis_testnet3(),
+		ChainTypes::Testnet4 => genesis::genesis_testnet4(),
+		ChainTypes::Mainnet => genesis::genesis_testnet2(),
+		ChainTypes::AutomatedTesting | ChainTypes::UserTesting => return None,
+	};
+	Some(genesis.hash())
+}
+
+/// Parses a `<height>:<hash>` checkpoint
+fn parse_checkpoint(checkpoint: &str) -> Result<(u64, Hash), Error> {
+	let mut parts = checkpoint.splitn(2, ':');
+	let height = parts.next().and_then(|h| h.trim().parse::<u64>().ok());
+	let hash = parts.next().and_then(|h| Hash::from_hex(h.trim()).ok());
+	match (height, hash) {
+		(Some(height), Some(hash)) => Ok((height, hash)),
+		_ => Err(ErrorKind::NodeVerification(format!(
+			"invalid header checkpoint {}, expected <height>:<hash>",
+			checkpoint
+		)))?,
+	}
+}
+
+/// Compares the tracked header chain with the chain of each of the other
+/// nodes configured, at the height of the lower of both heads. Another
+/// header there fails the check, the node we query having either stayed on
+/// a fork or made it up, whichever has the most work. A node that can't be
+/// reached or lags behind the headers tracked is skipped, and a node lagging
+/// behind the other warned about.
+pub fn check_header_nodes<T: ?Sized, C, K>(wallet: &mut T) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let nodes = wallet.header_nodes();
+	let head = match wallet.last_tracked_header()? {
+		Some(h) => h,
+		None => return Ok(()),
+	};
+	let client = wallet.client().clone();
+	for node in nodes {
+		let other = match client.get_header_from(&node, None) {
+			Ok(h) => h,
+			Err(e) => {
+				warn!("Couldn't get the chain head of {}: {}", node, e);
+				continue;
+			}
+		};
+		let height = other.height.min(head.height);
+		let ours = match wallet.tracked_header(height)? {
+			Some(h) => h,
+			None => {
+				warn!(
+					"{} lags more than {} blocks behind the node",
+					node, TRACKED_HEADERS
+				);
+				continue;
+			}
+		};
+		let theirs = if other.height == height {
+			other.clone()
+		} else {
+			match client.get_header_from(&node, Some(height)) {
+				Ok(h) => h,
+				Err(e) => {
+					warn!("Couldn't get header {} of {}: {}", height, node, e);
+					continue;
+				}
+			}
+		};
+		if theirs.hash() != ours.hash() {
+			return Err(ErrorKind::NodeVerification(format!(
+				"{} has header {} at {} instead of {}",
+				node,
+				theirs.hash(),
+				height,
+				ours.hash()
+			)))?;
+		}
+		if other.height > head.height + LAGGING_BLOCKS {
+			warn!(
+				"Node lags {} blocks behind {}",
+				other.height - head.height,
+				node
+			);
+		} else if head.height > other.height + LAGGING_BLOCKS {
+			warn!(
+				"{} lags {} blocks behind the node",
+				node,
+				head.height - other.height
+			);
+		}
+	}
+	Ok(())
+}
+
+/// Warns about the transactions with outputs confirmed above the height the
+/// node's chain forked off at, which may not be confirmed anymore
+fn warn_reorged_txs<T: ?Sized, C, K>(wallet: &mut T, fork_height: u64) -> Result<(), Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut tx_ids = wallet
+		.iter()?
+		.filter(|o| o.status != OutputStatus::Unconfirmed && o.height > fork_height)
+		.filter_map(|o| o.tx_log_entry)
+		.collect::<Vec<_>>();
+	tx_ids.sort();
+	tx_ids.dedup();
+	for id in tx_ids {
+		warn!(
+			"Transaction {} was confirmed above {}, where the node's chain forked off, \
+			 and may have to be confirmed again",
+			id, fork_height
+		);
+	}
+	Ok(())
+}
+
+fn verify_header(header: &BlockHeader, window: &[BlockHeader]) -> Result<(), Error> {
+	let prev = window.last().unwrap();
+	if header.prev_hash != prev.hash() || header.height != prev.height + 1 {
+		return Err(ErrorKind::NodeVerification(format!(
+			"header {} at {} doesn't build on {}",
+			header.hash(),
+			header.height,
+			prev.hash()
+		)))?;
+	}
+	if header.total_difficulty() <= prev.total_difficulty()
+		|| header.pow.to_difficulty() < header.total_difficulty() - prev.total_difficulty()
+	{
+		return Err(ErrorKind::NodeVerification(format!(
+			"header {} at {} lacks the work it claims",
+			header.hash(),
+			header.height
+		)))?;
+	}
+
+	// the difficulty it adds and its secondary scaling, as the chain
+	// computes them from the window below
+	let difficulty = header.total_difficulty() - prev.total_difficulty();
+	let next = next_difficulty(header.height, window)?;
+	if difficulty != next.difficulty || header.pow.secondary_scaling != next.secondary_scaling {
+		return Err(ErrorKind::NodeVerification(format!(
+			"header {} at {} has difficulty {} and scaling {} instead of {} and {}",
+			header.hash(),
+			header.height,
+			difficulty,
+			header.pow.secondary_scaling,
+			next.difficulty,
+			next.secondary_scaling
+		)))?;
+	}
+	verify_pow(header)
+}
+
+/// Difficulty of the header at the given height, over the window of headers
+/// below it. The window has to start at genesis or hold all the headers the
+/// difficulty adjusts over.
+fn next_difficulty(height: u64, window: &[BlockHeader]) -> Result<HeaderInfo, Error> {
+	if (window.len() as u64) < DIFFICULTY_HEADERS && window[0].height > 0 {
+		return Err(ErrorKind::NodeVerification(format!(
+			"not enough headers tracked below {} to check its difficulty",
+			height
+		)))?;
+	}
+	let mut infos = vec![];
+	for (i, h) in window.iter().enumerate().rev() {
+		let prev_difficulty = if i > 0 {
+			window[i - 1].total_difficulty()
+		} else if h.height == 0 {
+			Difficulty::zero()
+		} else {
+			break;
+		};
+		infos.push(HeaderInfo::new(
+			h.timestamp.timestamp() as u64,
+			h.total_difficulty() - prev_difficulty,
+			h.pow.secondary_scaling,
+			h.pow.is_secondary(),
+		));
+	}
+	Ok(consensus::next_difficulty(height, infos))
+}
+
+fn verify_pow(header: &BlockHeader) -> Result<(), Error> {
+	let valid = (header.pow.is_primary() || header.pow.is_secondary())
+		&& pow::verify_size(header, header.pow.edge_bits()).is_ok();
+	if !valid {
+		return Err(ErrorKind::NodeVerification(format!(
+			"invalid proof of work for header {} at {}",
+			header.hash(),
+			header.height
+		)))?;
+	}
+	Ok(())
+}
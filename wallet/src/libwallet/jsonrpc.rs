@@ -0,0 +1,344 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON-RPC 2.0 interface to the owner and foreign APIs, served by the
+//! listeners at `/v1/wallet/owner/jsonrpc` and `/v1/wallet/foreign/jsonrpc`
+//! next to the REST-ish endpoints. Params are listed in positional order in
+//! `OWNER_METHODS` and `FOREIGN_METHODS`. Wallet errors come back with a code
+//! from the `-32000` to `-32099` range reserved for implementations.
+
+use keychain::Keychain;
+use libtx::slate::Slate;
+use libwallet::api::{APIForeign, APIOwner};
+use libwallet::types::{BlockFees, WalletBackend, WalletClient};
+use libwallet::{Error, ErrorKind};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use util;
+
+pub use api::jsonrpc::{
+	handle, RpcError, RpcResponse, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR,
+};
+
+/// Any wallet error without a more specific code
+pub const WALLET_ERROR: i64 = -32000;
+/// Not enough funds for a send, with `available` and `needed` as data
+pub const NOT_ENOUGH_FUNDS: i64 = -32001;
+/// The transaction doesn't exist, with its id as data
+pub const TX_NOT_FOUND: i64 = -32002;
+/// The transaction can't be cancelled, with its id as data
+pub const TX_NOT_CANCELLABLE: i64 = -32003;
+/// Sender and recipient don't agree on the fee, or it exceeds the amount
+pub const FEE_ERROR: i64 = -32004;
+/// The node or another wallet couldn't be contacted
+pub const COMMS_ERROR: i64 = -32005;
+/// An output is locked by another transaction in progress
+pub const OUTPUT_LOCKED: i64 = -32006;
+/// Unknown or duplicate account label
+pub const ACCOUNT_ERROR: i64 = -32007;
+
+/// Methods of the owner API along with their params, in positional order
+pub const OWNER_METHODS: &'static [(&'static str, &'static [&'static str])] = &[
+	("accounts", &[]),
+	("cancel_tx", &["tx_id"]),
+	("contacts", &[]),
+	("dump_stored_tx", &["tx_id"]),
+	(
+		"estimate_send_tx",
+		&[
+			"amount",
+			"minimum_confirmations",
+			"max_outputs",
+			"num_change_outputs",
+			"selection_strategy_is_use_all",
+			"change_output_target",
+		],
+	),
+	("finalize_tx", &["slate"]),
+	(
+		"issue_send_tx",
+		&[
+			"amount",
+			"dest",
+			"minimum_confirmations",
+			"max_outputs",
+			"num_change_outputs",
+			"selection_strategy_is_use_all",
+			"change_output_target",
+		],
+	),
+	("node_height", &[]),
+	("post_stored_tx", &["tx_id", "fluff"]),
+	("post_tx", &["slate", "fluff"]),
+	(
+		"retrieve_outputs",
+		&["include_spent", "refresh_from_node", "tx_id"],
+	),
+	(
+		"retrieve_balance_history",
+		&["refresh_from_node", "per_block"],
+	),
+	("retrieve_summary_info", &["refresh_from_node"]),
+	("retrieve_txs", &["refresh_from_node", "tx_id"]),
+];
+
+/// Methods of the foreign API along with their params, in positional order
+pub const FOREIGN_METHODS: &'static [(&'static str, &'static [&'static str])] = &[
+	("build_coinbase", &["block_fees"]),
+	("check_version", &[]),
+	("receive_tx", &["slate"]),
+];
+
+impl From<Error> for RpcError {
+	fn from(e: Error) -> RpcError {
+		let (code, data) = match e.kind() {
+			ErrorKind::NotEnoughFunds { available, needed } => {
+				let mut m = Map::new();
+				m.insert("available".to_owned(), Value::from(available));
+				m.insert("needed".to_owned(), Value::from(needed));
+				(NOT_ENOUGH_FUNDS, Some(Value::Object(m)))
+			}
+			ErrorKind::TransactionDoesntExist(id) => (TX_NOT_FOUND, Some(Value::from(id))),
+			ErrorKind::TransactionNotCancellable(id) => (TX_NOT_CANCELLABLE, Some(Value::from(id))),
+			ErrorKind::FeeDispute { .. } | ErrorKind::FeeExceedsAmount { .. } => (FEE_ERROR, None),
+			ErrorKind::Node | ErrorKind::ClientCallback(_) | ErrorKind::WalletComms(_) => {
+				(COMMS_ERROR, None)
+			}
+			ErrorKind::OutputLocked(..) => (OUTPUT_LOCKED, None),
+			ErrorKind::UnknownAccountLabel(_) | ErrorKind::AccountLabelAlreadyExists(_) => {
+				(ACCOUNT_ERROR, None)
+			}
+			_ => (WALLET_ERROR, None),
+		};
+		RpcError {
+			code,
+			message: format!("{}", e),
+			data,
+		}
+	}
+}
+
+/// Result of a call that may first refresh from the node
+#[derive(Serialize)]
+struct Refreshed<T> {
+	/// Whether the data is up to date with the node
+	refreshed: bool,
+	data: T,
+}
+
+#[derive(Deserialize)]
+struct TxIdParams {
+	tx_id: u32,
+	#[serde(default)]
+	fluff: bool,
+}
+
+#[derive(Deserialize)]
+struct RefreshParams {
+	#[serde(default)]
+	include_spent: bool,
+	#[serde(default)]
+	refresh_from_node: bool,
+	#[serde(default)]
+	tx_id: Option<u32>,
+	#[serde(default)]
+	per_block: bool,
+}
+
+fn default_min_conf() -> u64 {
+	1
+}
+
+fn default_max_outputs() -> usize {
+	500
+}
+
+fn default_change_outputs() -> usize {
+	1
+}
+
+#[derive(Deserialize)]
+struct SendParams {
+	amount: u64,
+	#[serde(default)]
+	dest: String,
+	#[serde(default = "default_min_conf")]
+	minimum_confirmations: u64,
+	#[serde(default = "default_max_outputs")]
+	max_outputs: usize,
+	#[serde(default = "default_change_outputs")]
+	num_change_outputs: usize,
+	#[serde(default)]
+	selection_strategy_is_use_all: bool,
+	#[serde(default)]
+	change_output_target: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SlateParams {
+	slate: Slate,
+	#[serde(default)]
+	fluff: bool,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseParams {
+	block_fees: BlockFees,
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+	serde_json::from_value(params).map_err(|e| RpcError {
+		code: INVALID_PARAMS,
+		message: "Invalid params".to_owned(),
+		data: Some(Value::from(format!("{}", e))),
+	})
+}
+
+fn to_result<T: Serialize>(res: Result<T, Error>) -> Result<Value, RpcError> {
+	let v = res?;
+	serde_json::to_value(v).map_err(|e| RpcError::new(WALLET_ERROR, &format!("{}", e)))
+}
+
+/// Call an owner API method with named params
+pub fn owner_call<T: ?Sized, C, K>(
+	api: &mut APIOwner<T, C, K>,
+	method: &str,
+	params: Value,
+) -> Result<Value, RpcError>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	match method {
+		"accounts" => to_result(api.accounts()),
+		"contacts" => to_result(api.contacts()),
+		"node_height" => {
+			let (data, refreshed) = api.node_height()?;
+			to_result(Ok(Refreshed { refreshed, data }))
+		}
+		"retrieve_outputs" => {
+			let p: RefreshParams = parse(params)?;
+			let (refreshed, outputs) =
+				api.retrieve_outputs(p.include_spent, p.refresh_from_node, p.tx_id)?;
+			let data: Vec<Value> = outputs
+				.into_iter()
+				.map(|(output, commit)| {
+					let mut m = Map::new();
+					m.insert("output".to_owned(), serde_json::to_value(output).unwrap());
+					m.insert(
+						"commit".to_owned(),
+						Value::from(util::to_hex(commit.0.to_vec())),
+					);
+					Value::Object(m)
+				}).collect();
+			to_result(Ok(Refreshed { refreshed, data }))
+		}
+		"retrieve_txs" => {
+			let p: RefreshParams = parse(params)?;
+			let (refreshed, data) = api.retrieve_txs(p.refresh_from_node, p.tx_id)?;
+			to_result(Ok(Refreshed { refreshed, data }))
+		}
+		"retrieve_balance_history" => {
+			let p: RefreshParams = parse(params)?;
+			let (refreshed, data) = api.retrieve_balance_history(p.refresh_from_node, p.per_block)?;
+			to_result(Ok(Refreshed { refreshed, data }))
+		}
+		"retrieve_summary_info" => {
+			let p: RefreshParams = parse(params)?;
+			let (refreshed, data) = api.retrieve_summary_info(p.refresh_from_node)?;
+			to_result(Ok(Refreshed { refreshed, data }))
+		}
+		"estimate_send_tx" | "issue_send_tx" => {
+			let p: SendParams = parse(params)?;
+			let num_change_outputs = match p.change_output_target {
+				Some(target) => api.change_outputs_for_target(
+					p.amount,
+					p.minimum_confirmations,
+					p.max_outputs,
+					p.selection_strategy_is_use_all,
+					target,
+				)?,
+				None => p.num_change_outputs,
+			};
+			if method == "estimate_send_tx" {
+				to_result(api.estimate_send_tx(
+					p.amount,
+					p.minimum_confirmations,
+					p.max_outputs,
+					num_change_outputs,
+					p.selection_strategy_is_use_all,
+				))
+			} else {
+				to_result(api.issue_send_tx(
+					p.amount,
+					p.minimum_confirmations,
+					&p.dest,
+					p.max_outputs,
+					num_change_outputs,
+					p.selection_strategy_is_use_all,
+				))
+			}
+		}
+		"finalize_tx" => {
+			let mut p: SlateParams = parse(params)?;
+			api.finalize_tx(&mut p.slate)?;
+			to_result(Ok(p.slate))
+		}
+		"post_tx" => {
+			let p: SlateParams = parse(params)?;
+			to_result(api.post_tx(&p.slate, p.fluff))
+		}
+		"cancel_tx" => {
+			let p: TxIdParams = parse(params)?;
+			to_result(api.cancel_tx(p.tx_id))
+		}
+		"dump_stored_tx" => {
+			let p: TxIdParams = parse(params)?;
+			to_result(api.dump_stored_tx(p.tx_id, false, ""))
+		}
+		"post_stored_tx" => {
+			let p: TxIdParams = parse(params)?;
+			to_result(api.post_stored_tx(p.tx_id, p.fluff))
+		}
+		_ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+	}
+}
+
+/// Call a foreign API method with named params
+pub fn foreign_call<T: ?Sized, C, K>(
+	api: &mut APIForeign<T, C, K>,
+	method: &str,
+	params: Value,
+) -> Result<Value, RpcError>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	match method {
+		"build_coinbase" => {
+			let p: CoinbaseParams = parse(params)?;
+			to_result(api.build_coinbase(&p.block_fees))
+		}
+		"check_version" => to_result(api.check_version()),
+		"receive_tx" => {
+			let mut p: SlateParams = parse(params)?;
+			api.receive_tx(&mut p.slate)?;
+			to_result(Ok(p.slate))
+		}
+		_ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+	}
+}
@@ -25,7 +25,9 @@
 pub mod api;
 pub mod controller;
 mod error;
+pub mod events;
 pub mod internal;
+pub mod jsonrpc;
 pub mod types;
 
 pub use libwallet::error::{Error, ErrorKind};
@@ -16,8 +16,10 @@
 //! implementation
 
 use chrono::prelude::*;
+use std::cmp;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::mpsc::Sender;
 
 use serde;
 use serde_json;
@@ -26,6 +28,8 @@ use failure::ResultExt;
 use uuid::Uuid;
 
 use core::core::hash::Hash;
+use core::core::merkle_proof::MerkleProof;
+use core::core::BlockHeader;
 use core::ser;
 
 use keychain::{Identifier, Keychain};
@@ -36,6 +40,7 @@ use libwallet::error::{Error, ErrorKind};
 
 use util::secp::key::{PublicKey, SecretKey};
 use util::secp::{self, pedersen, Secp256k1};
+use util::{Zeroing, Zeroize};
 
 /// Combined trait to allow dynamic wallet dispatch
 pub trait WalletInst<C, K>: WalletBackend<C, K> + Send + Sync + 'static
@@ -72,6 +77,10 @@ where
 	/// Return the client being used
 	fn client(&mut self) -> &mut C;
 
+	/// Whether the wallet only tracks outputs whose keys are held elsewhere,
+	/// having no keychain to receive with
+	fn is_watch_only(&self) -> bool;
+
 	/// Set parent key id by stored account name
 	fn set_parent_key_id_by_name(&mut self, label: &str) -> Result<(), Error>;
 
@@ -83,7 +92,7 @@ where
 	fn parent_key_id(&mut self) -> Identifier;
 
 	/// Iterate over all output data stored by the backend
-	fn iter<'a>(&'a self) -> Box<Iterator<Item = OutputData> + 'a>;
+	fn iter<'a>(&'a self) -> Result<Box<Iterator<Item = OutputData> + 'a>, Error>;
 
 	/// Get output data by id
 	fn get(&self, id: &Identifier) -> Result<OutputData, Error>;
@@ -98,10 +107,10 @@ where
 	fn get_private_context(&mut self, slate_id: &[u8]) -> Result<Context, Error>;
 
 	/// Iterate over all output data stored by the backend
-	fn tx_log_iter<'a>(&'a self) -> Box<Iterator<Item = TxLogEntry> + 'a>;
+	fn tx_log_iter<'a>(&'a self) -> Result<Box<Iterator<Item = TxLogEntry> + 'a>, Error>;
 
 	/// Iterate over all stored account paths
-	fn acct_path_iter<'a>(&'a self) -> Box<Iterator<Item = AcctPathMapping> + 'a>;
+	fn acct_path_iter<'a>(&'a self) -> Result<Box<Iterator<Item = AcctPathMapping> + 'a>, Error>;
 
 	/// Gets an account path for a given label
 	fn get_acct_path(&self, label: String) -> Result<Option<AcctPathMapping>, Error>;
@@ -115,8 +124,57 @@ where
 	/// last verified height of outputs directly descending from the given parent key
 	fn last_confirmed_height<'a>(&mut self) -> Result<u64, Error>;
 
-	/// Attempt to restore the contents of a wallet from seed
-	fn restore(&mut self) -> Result<(), Error>;
+	/// Gets an address book contact by name
+	fn get_contact(&self, name: &str) -> Result<Option<Contact>, Error>;
+
+	/// Iterate over all address book contacts
+	fn contact_iter<'a>(&'a self) -> Result<Box<Iterator<Item = Contact> + 'a>, Error>;
+
+	/// Iterate over all balance snapshots, of every account
+	fn balance_snapshot_iter<'a>(&'a self) -> Result<Box<Iterator<Item = BalanceSnapshot> + 'a>, Error>;
+
+	/// Whether a balance snapshot is recorded for every block the wallet
+	/// sees, on top of the daily ones
+	fn snapshots_per_block(&self) -> Result<bool, Error>;
+
+	/// Iterate over all output locks held by in-progress slates
+	fn output_lock_iter<'a>(&'a self) -> Result<Box<Iterator<Item = OutputLock> + 'a>, Error>;
+
+	/// Last PMMR index scanned by a restore that hasn't completed yet, if any
+	fn restore_checkpoint(&self) -> Result<Option<u64>, Error>;
+
+	/// Attempt to restore the contents of a wallet from seed, optionally
+	/// reporting progress down the given channel as the output set is scanned
+	fn restore(&mut self, progress: Option<Sender<ScanProgress>>) -> Result<(), Error>;
+
+	/// Next child index to be derived under a parent key
+	fn child_index(&self, parent_key_id: &Identifier) -> Result<u32, Error>;
+
+	/// Check the wallet's outputs against the chain, applying the repairs
+	/// asked for, optionally reporting progress down the given channel as the
+	/// output set is scanned
+	fn check_repair(
+		&mut self,
+		fixes: &CheckFixes,
+		progress: Option<Sender<ScanProgress>>,
+	) -> Result<CheckReport, Error>;
+
+	/// How far what the node reports is trusted
+	fn node_trust(&self) -> NodeTrust;
+
+	/// Other nodes the header chain the wallet tracks is checked against
+	fn header_nodes(&self) -> Vec<String>;
+
+	/// Header the tracking starts from instead of genesis, as
+	/// `<height>:<hash>`
+	fn header_checkpoint(&self) -> Option<String>;
+
+	/// Header at the given height of the chain the wallet tracks, if kept
+	fn tracked_header(&self, height: u64) -> Result<Option<BlockHeader>, Error>;
+
+	/// Last header of the chain the wallet tracks, none until it's tracking
+	/// one
+	fn last_tracked_header(&self) -> Result<Option<BlockHeader>, Error>;
 }
 
 /// Batch trait to update the output data backend atomically. Trying to use a
@@ -134,11 +192,15 @@ where
 	/// Add or update data about an output to the backend
 	fn save(&mut self, out: OutputData) -> Result<(), Error>;
 
+	/// Add data about an output along with its commitment, for a watch-only
+	/// wallet that can't work out the commitment itself
+	fn save_watched(&mut self, out: OutputData, commit: &pedersen::Commitment) -> Result<(), Error>;
+
 	/// Gets output data by id
 	fn get(&self, id: &Identifier) -> Result<OutputData, Error>;
 
 	/// Iterate over all output data stored by the backend
-	fn iter(&self) -> Box<Iterator<Item = OutputData>>;
+	fn iter(&self) -> Result<Box<Iterator<Item = OutputData>>, Error>;
 
 	/// Delete data about an output from the backend
 	fn delete(&mut self, id: &Identifier) -> Result<(), Error>;
@@ -157,7 +219,7 @@ where
 	fn next_tx_log_id(&mut self, parent_key_id: &Identifier) -> Result<u32, Error>;
 
 	/// Iterate over tx log data stored by the backend
-	fn tx_log_iter(&self) -> Box<Iterator<Item = TxLogEntry>>;
+	fn tx_log_iter(&self) -> Result<Box<Iterator<Item = TxLogEntry>>, Error>;
 
 	/// save a tx log entry
 	fn save_tx_log_entry(&self, t: TxLogEntry, parent_id: &Identifier) -> Result<(), Error>;
@@ -166,17 +228,53 @@ where
 	fn save_acct_path(&mut self, mapping: AcctPathMapping) -> Result<(), Error>;
 
 	/// Iterate over account names stored in backend
-	fn acct_path_iter(&self) -> Box<Iterator<Item = AcctPathMapping>>;
+	fn acct_path_iter(&self) -> Result<Box<Iterator<Item = AcctPathMapping>>, Error>;
 
 	/// Save an output as locked in the backend
 	fn lock_output(&mut self, out: &mut OutputData) -> Result<(), Error>;
 
+	/// Add or update an address book contact
+	fn save_contact(&mut self, contact: Contact) -> Result<(), Error>;
+
+	/// Remove an address book contact
+	fn delete_contact(&mut self, name: &str) -> Result<(), Error>;
+
+	/// Add or replace the balance snapshot of its day, or block
+	fn save_balance_snapshot(&mut self, snapshot: BalanceSnapshot) -> Result<(), Error>;
+
+	/// Save whether to record a balance snapshot for every block
+	fn save_snapshots_per_block(&mut self, per_block: bool) -> Result<(), Error>;
+
+	/// Gets the lock held on an output, if any
+	fn get_output_lock(&self, id: &Identifier) -> Result<Option<OutputLock>, Error>;
+
+	/// Iterate over all output locks
+	fn output_lock_iter(&self) -> Result<Box<Iterator<Item = OutputLock>>, Error>;
+
+	/// Save a lock on an output for an in-progress slate
+	fn save_output_lock(&mut self, lock: OutputLock) -> Result<(), Error>;
+
+	/// Release the lock on an output
+	fn delete_output_lock(&mut self, id: &Identifier) -> Result<(), Error>;
+
 	/// Saves the private context associated with a slate id
 	fn save_private_context(&mut self, slate_id: &[u8], ctx: &Context) -> Result<(), Error>;
 
 	/// Delete the private context associated with the slate id
 	fn delete_private_context(&mut self, slate_id: &[u8]) -> Result<(), Error>;
 
+	/// Save the last PMMR index scanned by an in-progress restore
+	fn save_restore_checkpoint(&mut self, index: u64) -> Result<(), Error>;
+
+	/// Delete the restore checkpoint once a restore has completed
+	fn delete_restore_checkpoint(&mut self) -> Result<(), Error>;
+
+	/// Save a header of the chain the wallet tracks, as the last one
+	fn save_tracked_header(&mut self, header: &BlockHeader) -> Result<(), Error>;
+
+	/// Delete the tracked header at the given height
+	fn delete_tracked_header(&mut self, height: u64) -> Result<(), Error>;
+
 	/// Write the wallet data to backend file
 	fn commit(&self) -> Result<(), Error>;
 }
@@ -226,6 +324,67 @@ pub trait WalletClient: Sync + Send + Clone {
 		),
 		Error,
 	>;
+
+	/// Get the header at the given height of the node's chain
+	fn get_header(&self, height: u64) -> Result<BlockHeader, Error>;
+
+	/// Get the header at the given height of the chain of another node, or
+	/// its head if none
+	fn get_header_from(&self, node_url: &str, height: Option<u64>) -> Result<BlockHeader, Error>;
+
+	/// Get Merkle proofs of the unspent outputs among the given ones, in as
+	/// many sets as the node was asked for
+	fn get_output_proofs(
+		&self,
+		wallet_outputs: Vec<pedersen::Commitment>,
+	) -> Result<Vec<OutputProofs>, Error>;
+}
+
+/// How far the wallet trusts what the node it queries reports
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum NodeTrust {
+	/// Outputs are taken as the node reports them
+	Trusted,
+	/// Outputs are checked against Merkle proofs up to the output roots of
+	/// the header chain the wallet tracks, for nodes not run by the user
+	Verified,
+}
+
+impl Default for NodeTrust {
+	fn default() -> NodeTrust {
+		NodeTrust::Trusted
+	}
+}
+
+/// Merkle proofs of unspent outputs, as returned by a node, all against the
+/// output root of the same header
+#[derive(Debug, Clone)]
+pub struct OutputProofs {
+	/// Hash of the header
+	pub header_hash: Hash,
+	/// Height of the header
+	pub header_height: u64,
+	/// Outputs (commit, height of the block including it, position in the
+	/// output MMR, Merkle proof)
+	pub outputs: Vec<(pedersen::Commitment, u64, u64, MerkleProof)>,
+}
+
+/// A transport carrying slates between the wallets taking part in a
+/// transaction, e.g. over http or as files. Adapters are selected by the
+/// scheme of the destination they're given.
+pub trait SlateAdapter {
+	/// Whether the adapter can return the recipient's response to a send, or
+	/// only deliver the slate, leaving the response to come back later
+	fn supports_sync(&self) -> bool;
+
+	/// Send a slate to the recipient and return the slate they responded with
+	fn send_tx_sync(&self, dest: &str, slate: &Slate) -> Result<Slate, Error>;
+
+	/// Deliver a slate without waiting for a response
+	fn send_tx_async(&self, dest: &str, slate: &Slate) -> Result<(), Error>;
+
+	/// Collect a slate delivered asynchronously, e.g. by reading it from file
+	fn receive_tx_async(&self, source: &str) -> Result<Slate, Error>;
 }
 
 /// Information about an output that's being tracked by the wallet. Must be
@@ -423,15 +582,24 @@ impl Context {
 	}
 }
 
+// the keys are wiped once the transaction is done with
+impl Drop for Context {
+	fn drop(&mut self) {
+		self.sec_key.zeroize();
+		self.sec_nonce.zeroize();
+	}
+}
+
 impl ser::Writeable for Context {
 	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
-		writer.write_bytes(&serde_json::to_vec(self).map_err(|_| ser::Error::CorruptedData)?)
+		let data = Zeroing::new(serde_json::to_vec(self).map_err(|_| ser::Error::CorruptedData)?);
+		writer.write_bytes(&*data)
 	}
 }
 
 impl ser::Readable for Context {
 	fn read(reader: &mut ser::Reader) -> Result<Context, ser::Error> {
-		let data = reader.read_vec()?;
+		let data = Zeroing::new(reader.read_vec()?);
 		serde_json::from_slice(&data[..]).map_err(|_| ser::Error::CorruptedData)
 	}
 }
@@ -535,6 +703,117 @@ pub struct WalletInfo {
 	pub amount_currently_spendable: u64,
 	/// amount locked via previous transactions
 	pub amount_locked: u64,
+	/// change of sent transactions, part of the amount awaiting confirmation
+	#[serde(default)]
+	pub amount_unconfirmed_change: u64,
+	/// coinbases waiting for lock height, the first to mature first
+	#[serde(default)]
+	pub immature_coinbases: Vec<ImmatureCoinbase>,
+}
+
+/// A coinbase output of the wallet not spendable yet
+#[derive(Serialize, Eq, PartialEq, Deserialize, Debug, Clone)]
+pub struct ImmatureCoinbase {
+	/// value of the output
+	pub value: u64,
+	/// height of the block it was mined in
+	pub height: u64,
+	/// height it becomes spendable at
+	pub lock_height: u64,
+	/// blocks left until then
+	pub blocks_to_maturity: u64,
+}
+
+/// Progress of a scan through the node's output set (e.g. during restore),
+/// sent periodically so long running operations can report how far along
+/// they are
+#[derive(Serialize, Eq, PartialEq, Deserialize, Debug, Clone)]
+pub struct ScanProgress {
+	/// PMMR index of the last output scanned
+	pub outputs_scanned: u64,
+	/// highest PMMR index on the node, i.e. where the scan will end
+	pub outputs_total: u64,
+	/// number of outputs found to belong to this wallet so far
+	pub matches_found: u64,
+	/// estimated seconds remaining, if enough has been scanned to tell
+	pub eta_secs: Option<u64>,
+	/// whether the scan has finished
+	pub complete: bool,
+}
+
+impl ScanProgress {
+	/// Percentage of the output set scanned so far
+	pub fn percentage_complete(&self) -> u8 {
+		if self.complete || self.outputs_total == 0 {
+			return 100;
+		}
+		let pct = self.outputs_scanned.saturating_mul(100) / self.outputs_total;
+		cmp::min(pct, 100) as u8
+	}
+}
+
+/// Categories of repair a wallet check can apply
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct CheckFixes {
+	/// mark unspent outputs missing from the chain as spent
+	pub missing_on_chain: bool,
+	/// add (or mark unspent again) outputs of ours found on the chain
+	pub missing_from_wallet: bool,
+	/// mark locked outputs whose spending transaction confirmed as spent,
+	/// confirming that transaction
+	pub confirmed_locked: bool,
+	/// move derivation indices past the highest index in use
+	pub duplicate_indices: bool,
+}
+
+impl CheckFixes {
+	/// All categories of repair
+	pub fn all() -> CheckFixes {
+		CheckFixes {
+			missing_on_chain: true,
+			missing_from_wallet: true,
+			confirmed_locked: true,
+			duplicate_indices: true,
+		}
+	}
+}
+
+/// An account whose next derivation index is already in use, so new outputs
+/// would reuse keys
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct DuplicateIndex {
+	/// parent key of the account
+	pub parent_key_id: Identifier,
+	/// next child index to be derived
+	pub child_index: u32,
+	/// highest child index of an output, in the wallet or on chain
+	pub max_used_index: u32,
+}
+
+/// Discrepancies between the wallet and the chain found by a wallet check
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CheckReport {
+	/// outputs the wallet has as unspent that aren't on chain
+	pub missing_on_chain: Vec<OutputData>,
+	/// outputs of ours on chain the wallet doesn't have, or has as spent
+	pub missing_from_wallet: Vec<OutputData>,
+	/// locked outputs no longer on chain, their spending transaction having
+	/// confirmed
+	pub confirmed_locked: Vec<OutputData>,
+	/// accounts about to reuse derivation indices
+	pub duplicate_indices: Vec<DuplicateIndex>,
+	/// categories that were repaired
+	pub fixed: CheckFixes,
+}
+
+impl CheckReport {
+	/// Whether the wallet matches the chain
+	pub fn is_clean(&self) -> bool {
+		self.missing_on_chain.is_empty()
+			&& self.missing_from_wallet.is_empty()
+			&& self.confirmed_locked.is_empty()
+			&& self.duplicate_indices.is_empty()
+	}
 }
 
 /// Types of transactions that can be contained within a TXLog entry
@@ -605,6 +884,18 @@ pub struct TxLogEntry {
 	pub fee: Option<u64>,
 	/// The transaction json itself, stored for reference or resending
 	pub tx_hex: Option<String>,
+	/// Name of the address book contact the transaction was sent to, if any
+	#[serde(default)]
+	pub contact: Option<String>,
+	/// Whether the payment received was notified about once confirmed, when
+	/// watching the wallet. Entries from before notifications count as
+	/// notified already.
+	#[serde(default = "notified_default")]
+	pub notified: bool,
+}
+
+fn notified_default() -> bool {
+	true
 }
 
 impl ser::Writeable for TxLogEntry {
@@ -637,6 +928,8 @@ impl TxLogEntry {
 			num_outputs: 0,
 			fee: None,
 			tx_hex: None,
+			contact: None,
+			notified: false,
 		}
 	}
 
@@ -646,6 +939,43 @@ impl TxLogEntry {
 	}
 }
 
+/// A transaction log entry flattened out with everything needed to account
+/// for it outside the wallet. Field order here is the export schema, so only
+/// ever add new fields at the end.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxExportEntry {
+	/// Local id of the transaction
+	pub id: u32,
+	/// Slate transaction id, if any
+	pub tx_slate_id: Option<Uuid>,
+	/// Transaction type
+	pub tx_type: TxLogEntryType,
+	/// Label of the account the transaction belongs to
+	pub account: String,
+	/// Time the transaction was created
+	pub creation_ts: DateTime<Utc>,
+	/// Time the transaction was confirmed
+	pub confirmation_ts: Option<DateTime<Utc>>,
+	/// Whether the transaction is confirmed
+	pub confirmed: bool,
+	/// Number of confirmations, as of the wallet's last confirmed height
+	pub confirmations: u64,
+	/// Amount credited via this transaction
+	pub amount_credited: u64,
+	/// Amount debited via this transaction
+	pub amount_debited: u64,
+	/// Fee paid, if we paid it
+	pub fee: Option<u64>,
+	/// Hex kernel excess(es) of the stored transaction, if we have it
+	pub kernel_excess: Vec<String>,
+	/// number of inputs involved in TX
+	pub num_inputs: usize,
+	/// number of outputs involved in TX
+	pub num_outputs: usize,
+	/// Address book contact the transaction was sent to, if any
+	pub contact: Option<String>,
+}
+
 /// Map of named accounts to BIP32 paths
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AcctPathMapping {
@@ -668,6 +998,228 @@ impl ser::Readable for AcctPathMapping {
 	}
 }
 
+/// Reservation of an output by a slate that's still being built, so
+/// concurrent sends don't select the same output. Ignored once the chain
+/// passes the expiry height.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutputLock {
+	/// key id of the locked output
+	pub key_id: Identifier,
+	/// slate the output has been selected into
+	pub slate_id: Uuid,
+	/// height after which the lock no longer applies
+	pub expiry_height: u64,
+}
+
+impl OutputLock {
+	/// Whether the lock has lapsed as of the given height
+	pub fn is_expired(&self, current_height: u64) -> bool {
+		current_height > self.expiry_height
+	}
+}
+
+impl ser::Writeable for OutputLock {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_bytes(&serde_json::to_vec(self).map_err(|_| ser::Error::CorruptedData)?)
+	}
+}
+
+impl ser::Readable for OutputLock {
+	fn read(reader: &mut ser::Reader) -> Result<OutputLock, ser::Error> {
+		let data = reader.read_vec()?;
+		serde_json::from_slice(&data[..]).map_err(|_| ser::Error::CorruptedData)
+	}
+}
+
+/// What a send would do if it went ahead, worked out without locking any
+/// outputs or creating a slate
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendEstimate {
+	/// amount to send
+	pub amount: u64,
+	/// fee the transaction would pay
+	pub fee: u64,
+	/// outputs that would be spent
+	pub inputs: Vec<OutputData>,
+	/// total change coming back to the wallet
+	pub change: u64,
+	/// number of change outputs that would be created
+	pub num_change_outputs: usize,
+	/// wallet total once the send is made
+	pub total_after: u64,
+	/// amount currently spendable once the send is made (change isn't
+	/// spendable until confirmed)
+	pub spendable_after: u64,
+}
+
+/// Request from a watch-only wallet to the offline wallet holding its keys
+/// to sign a send, with the inputs and fee already worked out
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SigningRequest {
+	/// id of the slate to create
+	pub id: Uuid,
+	/// amount to send
+	pub amount: u64,
+	/// fee the transaction pays
+	pub fee: u64,
+	/// chain height the transaction is built at
+	pub height: u64,
+	/// lock height of the transaction
+	pub lock_height: u64,
+	/// outputs to spend
+	pub inputs: Vec<OutputData>,
+	/// number of outputs to split the change into
+	pub num_change_outputs: usize,
+}
+
+/// An output along with its commitment, as exported by a wallet holding the
+/// keys for a watch-only wallet to track
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WatchedOutput {
+	/// the output
+	pub output: OutputData,
+	/// its commitment, hex encoded
+	pub commit: String,
+}
+
+/// Version of the foreign API, as given by `check_version`
+pub const FOREIGN_API_VERSION: u16 = 1;
+
+/// Versions and features of the foreign API a wallet accepts, along with
+/// whether it can receive at all, for a sender to check before sending
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VersionInfo {
+	/// version of the foreign API
+	pub foreign_api_version: u16,
+	/// versions of the slate the wallet can receive
+	pub supported_slate_versions: Vec<u16>,
+	/// optional parts of the transaction flow the wallet accepts
+	pub features: Features,
+	/// whether the wallet can receive right now
+	pub receive_status: ReceiveStatus,
+}
+
+impl VersionInfo {
+	/// Checks a slate of the given version can be sent to the wallet
+	pub fn check_receive(&self, slate_version: u16) -> Result<(), Error> {
+		let reason = match self.receive_status {
+			ReceiveStatus::WatchOnly => "it is a watch-only wallet".to_owned(),
+			ReceiveStatus::Locked => "it is locked".to_owned(),
+			ReceiveStatus::Ready if !self.supported_slate_versions.contains(&slate_version) => {
+				format!(
+					"it doesn't support slate version {}, only {:?}",
+					slate_version, self.supported_slate_versions
+				)
+			}
+			ReceiveStatus::Ready => return Ok(()),
+		};
+		Err(ErrorKind::IncompatibleReceiver(reason).into())
+	}
+}
+
+/// Optional parts of the transaction flow a wallet may accept
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Features {
+	/// receiver initiated transactions, paying an invoice
+	pub invoice: bool,
+	/// proofs of payment given back by the receiver
+	pub payment_proofs: bool,
+}
+
+/// Whether a wallet can receive a transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ReceiveStatus {
+	/// can receive
+	Ready,
+	/// tracks outputs whose keys are held elsewhere, can't build its own
+	WatchOnly,
+	/// its keys can't be opened (e.g. missing seed or wrong password)
+	Locked,
+}
+
+/// Address book entry, mapping a name to a destination to send to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Contact {
+	/// name the contact is referred to by (as `@name`)
+	pub name: String,
+	/// send method to use with the destination, e.g. "http" or "file"
+	pub method: String,
+	/// destination to send to
+	pub dest: String,
+}
+
+impl ser::Writeable for Contact {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_bytes(&serde_json::to_vec(self).map_err(|_| ser::Error::CorruptedData)?)
+	}
+}
+
+impl ser::Readable for Contact {
+	fn read(reader: &mut ser::Reader) -> Result<Contact, ser::Error> {
+		let data = reader.read_vec()?;
+		serde_json::from_slice(&data[..]).map_err(|_| ser::Error::CorruptedData)
+	}
+}
+
+/// Balance of an account at some point, recorded as the wallet refreshes
+/// from the node, so it can be charted over time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+	/// account the balance is of
+	pub parent_key_id: Identifier,
+	/// whether kept for its block, rather than as the balance of its day
+	pub per_block: bool,
+	/// last confirmed height of the wallet outputs
+	pub height: u64,
+	/// time the snapshot was taken, the latest of the day (or block)
+	pub timestamp: DateTime<Utc>,
+	/// total amount in the wallet
+	pub total: u64,
+	/// amount awaiting confirmation
+	pub amount_awaiting_confirmation: u64,
+	/// coinbases waiting for lock height
+	pub amount_immature: u64,
+	/// amount currently spendable
+	pub amount_currently_spendable: u64,
+	/// amount locked via previous transactions
+	pub amount_locked: u64,
+}
+
+impl BalanceSnapshot {
+	/// Snapshot of the provided summary info, taken now
+	pub fn new(parent_key_id: &Identifier, info: &WalletInfo, per_block: bool) -> BalanceSnapshot {
+		BalanceSnapshot {
+			parent_key_id: parent_key_id.clone(),
+			per_block,
+			height: info.last_confirmed_height,
+			timestamp: Utc::now(),
+			total: info.total,
+			amount_awaiting_confirmation: info.amount_awaiting_confirmation,
+			amount_immature: info.amount_immature,
+			amount_currently_spendable: info.amount_currently_spendable,
+			amount_locked: info.amount_locked,
+		}
+	}
+
+	/// Days since the unix epoch of the snapshot
+	pub fn day(&self) -> u64 {
+		(self.timestamp.timestamp() / 86_400) as u64
+	}
+}
+
+impl ser::Writeable for BalanceSnapshot {
+	fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_bytes(&serde_json::to_vec(self).map_err(|_| ser::Error::CorruptedData)?)
+	}
+}
+
+impl ser::Readable for BalanceSnapshot {
+	fn read(reader: &mut ser::Reader) -> Result<BalanceSnapshot, ser::Error> {
+		let data = reader.read_vec()?;
+		serde_json::from_slice(&data[..]).map_err(|_| ser::Error::CorruptedData)
+	}
+}
+
 /// Dummy wrapper for the hex-encoded serialized transaction.
 #[derive(Serialize, Deserialize)]
 pub struct TxWrapper {
@@ -692,4 +1244,7 @@ pub struct SendTXArgs {
 	pub num_change_outputs: usize,
 	/// whether to use all outputs (combine)
 	pub selection_strategy_is_use_all: bool,
+	/// If given, split change into outputs of at least this size instead of
+	/// using num_change_outputs
+	pub change_output_target: Option<u64>,
 }
@@ -13,15 +13,18 @@
 // limitations under the License.
 
 use std::cell::RefCell;
+use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::{fs, path};
 
 use failure::ResultExt;
 use uuid::Uuid;
 
-use keychain::{ChildNumber, ExtKeychain, Identifier, Keychain};
-use store::{self, option_to_not_found, to_key, to_key_u64};
+use core::core::BlockHeader;
+use keychain::{ChildNumber, ExtKeychain, Identifier, KeyBackend, KeyDerivation, Keychain};
+use store::{self, to_key, to_key_u64, u64_to_key};
 
+use db_cipher::DbCipher;
 use libwallet::types::*;
 use libwallet::{internal, Error, ErrorKind};
 use types::{WalletConfig, WalletSeed};
@@ -37,6 +40,15 @@ const PRIVATE_TX_CONTEXT_PREFIX: u8 = 'p' as u8;
 const TX_LOG_ENTRY_PREFIX: u8 = 't' as u8;
 const TX_LOG_ID_PREFIX: u8 = 'i' as u8;
 const ACCOUNT_PATH_MAPPING_PREFIX: u8 = 'a' as u8;
+const RESTORE_CHECKPOINT_PREFIX: u8 = 'r' as u8;
+const OUTPUT_LOCK_PREFIX: u8 = 'l' as u8;
+const CONTACT_PREFIX: u8 = 'b' as u8;
+const WATCH_ONLY_PREFIX: u8 = 'w' as u8;
+const KEY_DERIVATION_PREFIX: u8 = 'k' as u8;
+const BALANCE_SNAPSHOT_PREFIX: u8 = 's' as u8;
+const SNAPSHOTS_PER_BLOCK_PREFIX: u8 = 'S' as u8;
+const TRACKED_HEADER_PREFIX: u8 = 'h' as u8;
+const LAST_TRACKED_HEADER_PREFIX: u8 = 'H' as u8;
 
 impl From<store::Error> for Error {
 	fn from(error: store::Error) -> Error {
@@ -44,6 +56,15 @@ impl From<store::Error> for Error {
 	}
 }
 
+/// Like `store::option_to_not_found`, for values read through the cipher
+fn option_to_not_found<T>(res: Result<Option<T>, Error>, field_name: &str) -> Result<T, Error> {
+	match res {
+		Ok(None) => Err(store::Error::NotFoundErr(field_name.to_owned()).into()),
+		Ok(Some(o)) => Ok(o),
+		Err(e) => Err(e),
+	}
+}
+
 /// test to see if database files exist in the current directory. If so,
 /// use a DB backend for all operations
 pub fn wallet_db_exists(config: WalletConfig) -> bool {
@@ -51,13 +72,51 @@ pub fn wallet_db_exists(config: WalletConfig) -> bool {
 	db_path.exists()
 }
 
+fn open_store(config: &WalletConfig) -> store::Store {
+	let db_path = path::Path::new(&config.data_file_dir).join(DB_DIR);
+	fs::create_dir_all(&db_path).expect("Couldn't create wallet backend directory!");
+
+	let lmdb_env = Arc::new(store::new_env(db_path.to_str().unwrap().to_string()));
+	store::Store::open(lmdb_env, DB_DIR)
+}
+
+/// Whether the wallet database is encrypted with a non-empty passphrase,
+/// which must then be asked for to open it
+pub fn wallet_db_locked(config: &WalletConfig) -> Result<bool, Error> {
+	if !wallet_db_exists(config.clone()) {
+		return Ok(false);
+	}
+	DbCipher::is_locked(&open_store(config))
+}
+
+/// Whether the wallet database was written before encryption was introduced,
+/// see `encrypt_wallet_db`
+pub fn wallet_db_plaintext(config: &WalletConfig) -> Result<bool, Error> {
+	if !wallet_db_exists(config.clone()) {
+		return Ok(false);
+	}
+	DbCipher::is_plaintext(&open_store(config))
+}
+
+/// Encrypts a wallet database written before encryption was introduced with
+/// the passphrase, which should have been confirmed as it can't be checked
+/// against anything yet
+pub fn encrypt_wallet_db(config: &WalletConfig, passphrase: &str) -> Result<(), Error> {
+	DbCipher::encrypt(&open_store(config), passphrase)?;
+	Ok(())
+}
+
 pub struct LMDBBackend<C, K> {
 	db: store::Store,
+	/// Encryption of the db contents
+	cipher: DbCipher,
 	config: WalletConfig,
 	/// passphrase: TODO better ways of dealing with this other than storing
 	passphrase: String,
 	/// Keychain
 	pub keychain: Option<K>,
+	/// Holder of the master secret, when not derived from the seed file
+	key_backend: Option<Arc<KeyBackend>>,
 	/// Parent path to use by default for output operations
 	parent_key_id: Identifier,
 	/// client
@@ -66,11 +125,8 @@ pub struct LMDBBackend<C, K> {
 
 impl<C, K> LMDBBackend<C, K> {
 	pub fn new(config: WalletConfig, passphrase: &str, client: C) -> Result<Self, Error> {
-		let db_path = path::Path::new(&config.data_file_dir).join(DB_DIR);
-		fs::create_dir_all(&db_path).expect("Couldn't create wallet backend directory!");
-
-		let lmdb_env = Arc::new(store::new_env(db_path.to_str().unwrap().to_string()));
-		let store = store::Store::open(lmdb_env, DB_DIR);
+		let store = open_store(&config);
+		let cipher = DbCipher::open(&store, passphrase)?;
 
 		// Make sure default wallet derivation path always exists
 		let default_account = AcctPathMapping {
@@ -84,15 +140,17 @@ impl<C, K> LMDBBackend<C, K> {
 
 		{
 			let batch = store.batch()?;
-			batch.put_ser(&acct_key, &default_account)?;
+			cipher.put_ser(&batch, &acct_key, &default_account)?;
 			batch.commit()?;
 		}
 
 		let res = LMDBBackend {
 			db: store,
+			cipher,
 			config: config.clone(),
 			passphrase: String::from(passphrase),
 			keychain: None,
+			key_backend: None,
 			parent_key_id: LMDBBackend::<C, K>::default_path(),
 			client: client,
 		};
@@ -106,6 +164,63 @@ impl<C, K> LMDBBackend<C, K> {
 		ExtKeychain::derive_key_id(2, 0, 0, 0, 0)
 	}
 
+	/// Mark the wallet as watch-only: it has no seed, tracking outputs whose
+	/// keys are held by an offline wallet that signs its transactions
+	pub fn init_watch_only(&self) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		self.cipher.put_ser(&batch, &[WATCH_ONLY_PREFIX], &1u8)?;
+		batch.commit()?;
+		Ok(())
+	}
+
+	/// Keeps the master secret in the provided backend, like an HSM or a
+	/// signing daemon, instead of deriving it from the seed file
+	pub fn set_key_backend(&mut self, backend: Arc<KeyBackend>) {
+		self.key_backend = Some(backend);
+	}
+
+	/// How the wallet keys are derived from its seed. Legacy for wallets
+	/// created before there was a choice.
+	pub fn key_derivation(&self) -> Result<KeyDerivation, Error> {
+		match self
+			.cipher
+			.get_ser::<u8>(&self.db, &[KEY_DERIVATION_PREFIX])?
+		{
+			Some(1) => Ok(KeyDerivation::Bip44),
+			_ => Ok(KeyDerivation::Legacy),
+		}
+	}
+
+	/// Sets how the wallet keys are derived from its seed. The same outputs
+	/// can't be spent with keys derived otherwise, so it's refused while the
+	/// wallet holds any: migrating a wallet is sending its funds away,
+	/// switching, then receiving them back.
+	pub fn set_key_derivation(&self, derivation: KeyDerivation) -> Result<(), Error> {
+		if derivation == self.key_derivation()? {
+			return Ok(());
+		}
+		let held = self
+			.cipher
+			.iter::<OutputData>(&self.db, OUTPUT_PREFIX)?
+			.filter(|o| o.status != OutputStatus::Spent)
+			.count();
+		if held > 0 {
+			return Err(ErrorKind::GenericError(format!(
+				"Wallet still holds {} outputs, spend them before switching to {} keys",
+				held, derivation
+			)).into());
+		}
+		let flag = match derivation {
+			KeyDerivation::Legacy => 0u8,
+			KeyDerivation::Bip44 => 1u8,
+		};
+		let batch = self.db.batch()?;
+		self.cipher
+			.put_ser(&batch, &[KEY_DERIVATION_PREFIX], &flag)?;
+		batch.commit()?;
+		Ok(())
+	}
+
 	/// Just test to see if database files exist in the current directory. If
 	/// so, use a DB backend for all operations
 	pub fn exists(config: WalletConfig) -> bool {
@@ -117,14 +232,26 @@ impl<C, K> LMDBBackend<C, K> {
 impl<C, K> WalletBackend<C, K> for LMDBBackend<C, K>
 where
 	C: WalletClient,
-	K: Keychain,
+	K: Keychain + 'static,
 {
 	/// Initialise with whatever stored credentials we have
 	fn open_with_credentials(&mut self) -> Result<(), Error> {
-		let wallet_seed = WalletSeed::from_file(&self.config)
-			.context(ErrorKind::CallbackImpl("Error opening wallet"))?;
-		let keychain = wallet_seed.derive_keychain(&self.passphrase);
-		self.keychain = Some(keychain.context(ErrorKind::CallbackImpl("Error deriving keychain"))?);
+		// nothing to derive keys from, only stored commitments are used
+		if self.is_watch_only() {
+			return Ok(());
+		}
+		let derivation = self.key_derivation()?;
+		let keychain = match self.key_backend {
+			Some(ref backend) => K::from_backend(backend.clone(), derivation)?,
+			None => {
+				let wallet_seed = WalletSeed::from_file(&self.config)
+					.context(ErrorKind::CallbackImpl("Error opening wallet"))?;
+				wallet_seed
+					.derive_keychain_with(&self.passphrase, derivation)
+					.context(ErrorKind::CallbackImpl("Error deriving keychain"))?
+			}
+		};
+		self.keychain = Some(keychain);
 		// Just blow up password for now after it's been used
 		self.passphrase = String::from("");
 		Ok(())
@@ -146,10 +273,18 @@ where
 		&mut self.client
 	}
 
+	/// Whether the wallet is watch-only, see `init_watch_only`
+	fn is_watch_only(&self) -> bool {
+		self.cipher
+			.get_ser::<u8>(&self.db, &[WATCH_ONLY_PREFIX])
+			.map(|flag| flag.is_some())
+			.unwrap_or(false)
+	}
+
 	/// Set parent path by account name
 	fn set_parent_key_id_by_name(&mut self, label: &str) -> Result<(), Error> {
 		let label = label.to_owned();
-		let res = self.acct_path_iter().find(|l| l.label == label);
+		let res = self.acct_path_iter()?.find(|l| l.label == label);
 		if let Some(a) = res {
 			self.set_parent_key_id(a.path);
 			Ok(())
@@ -169,15 +304,17 @@ where
 
 	fn get(&self, id: &Identifier) -> Result<OutputData, Error> {
 		let key = to_key(OUTPUT_PREFIX, &mut id.to_bytes().to_vec());
-		option_to_not_found(self.db.get_ser(&key), &format!("Key Id: {}", id)).map_err(|e| e.into())
+		option_to_not_found(
+			self.cipher.get_ser(&self.db, &key),
+			&format!("Key Id: {}", id),
+		)
 	}
 
 	fn get_commitment(&mut self, id: &Identifier) -> Result<pedersen::Commitment, Error> {
 		let key = to_key(COMMITMENT_PREFIX, &mut id.to_bytes().to_vec());
 
 		let res: Result<pedersen::Commitment, Error> =
-			option_to_not_found(self.db.get_ser(&key), &format!("Key Id: {}", id))
-				.map_err(|e| e.into());
+			option_to_not_found(self.cipher.get_ser(&self.db, &key), &format!("Key Id: {}", id));
 
 		// "cache hit" and return the commitment
 		if let Ok(commit) = res {
@@ -194,44 +331,46 @@ where
 			}
 
 			// Now retrieve the saved commitment and return it.
-			option_to_not_found(self.db.get_ser(&key), &format!("Key Id: {}", id))
-				.map_err(|e| e.into())
+			option_to_not_found(
+				self.cipher.get_ser(&self.db, &key),
+				&format!("Key Id: {}", id),
+			)
 		}
 	}
 
-	fn iter<'a>(&'a self) -> Box<Iterator<Item = OutputData> + 'a> {
-		Box::new(self.db.iter(&[OUTPUT_PREFIX]).unwrap())
+	fn iter<'a>(&'a self) -> Result<Box<Iterator<Item = OutputData> + 'a>, Error> {
+		self.cipher.iter(&self.db, OUTPUT_PREFIX)
 	}
 
 	fn get_tx_log_entry(&self, u: &Uuid) -> Result<Option<TxLogEntry>, Error> {
 		let key = to_key(TX_LOG_ENTRY_PREFIX, &mut u.as_bytes().to_vec());
-		self.db.get_ser(&key).map_err(|e| e.into())
+		self.cipher.get_ser(&self.db, &key)
 	}
 
-	fn tx_log_iter<'a>(&'a self) -> Box<Iterator<Item = TxLogEntry> + 'a> {
-		Box::new(self.db.iter(&[TX_LOG_ENTRY_PREFIX]).unwrap())
+	fn tx_log_iter<'a>(&'a self) -> Result<Box<Iterator<Item = TxLogEntry> + 'a>, Error> {
+		self.cipher.iter(&self.db, TX_LOG_ENTRY_PREFIX)
 	}
 
 	fn get_private_context(&mut self, slate_id: &[u8]) -> Result<Context, Error> {
 		let ctx_key = to_key(PRIVATE_TX_CONTEXT_PREFIX, &mut slate_id.to_vec());
 		option_to_not_found(
-			self.db.get_ser(&ctx_key),
+			self.cipher.get_ser(&self.db, &ctx_key),
 			&format!("Slate id: {:x?}", slate_id.to_vec()),
-		).map_err(|e| e.into())
+		)
 	}
 
-	fn acct_path_iter<'a>(&'a self) -> Box<Iterator<Item = AcctPathMapping> + 'a> {
-		Box::new(self.db.iter(&[ACCOUNT_PATH_MAPPING_PREFIX]).unwrap())
+	fn acct_path_iter<'a>(&'a self) -> Result<Box<Iterator<Item = AcctPathMapping> + 'a>, Error> {
+		self.cipher.iter(&self.db, ACCOUNT_PATH_MAPPING_PREFIX)
 	}
 
 	fn get_acct_path(&self, label: String) -> Result<Option<AcctPathMapping>, Error> {
 		let acct_key = to_key(ACCOUNT_PATH_MAPPING_PREFIX, &mut label.as_bytes().to_vec());
-		self.db.get_ser(&acct_key).map_err(|e| e.into())
+		self.cipher.get_ser(&self.db, &acct_key)
 	}
 
 	fn batch<'a>(&'a mut self) -> Result<Box<WalletOutputBatch<K> + 'a>, Error> {
 		Ok(Box::new(Batch {
-			_store: self,
+			store: self,
 			db: RefCell::new(Some(self.db.batch()?)),
 			keychain: self.keychain.clone(),
 		}))
@@ -242,7 +381,7 @@ where
 		let mut deriv_idx = {
 			let batch = self.db.batch()?;
 			let deriv_key = to_key(DERIV_PREFIX, &mut self.parent_key_id.to_bytes().to_vec());
-			match batch.get_ser(&deriv_key)? {
+			match self.cipher.batch_get_ser(&batch, &deriv_key)? {
 				Some(idx) => idx,
 				None => 0,
 			}
@@ -263,17 +402,90 @@ where
 			CONFIRMED_HEIGHT_PREFIX,
 			&mut self.parent_key_id.to_bytes().to_vec(),
 		);
-		let last_confirmed_height = match batch.get_ser(&height_key)? {
+		let last_confirmed_height = match self.cipher.batch_get_ser(&batch, &height_key)? {
 			Some(h) => h,
 			None => 0,
 		};
 		Ok(last_confirmed_height)
 	}
 
-	fn restore(&mut self) -> Result<(), Error> {
-		internal::restore::restore(self).context(ErrorKind::Restore)?;
+	fn get_contact(&self, name: &str) -> Result<Option<Contact>, Error> {
+		let key = to_key(CONTACT_PREFIX, &mut name.as_bytes().to_vec());
+		self.cipher.get_ser(&self.db, &key)
+	}
+
+	fn contact_iter<'a>(&'a self) -> Result<Box<Iterator<Item = Contact> + 'a>, Error> {
+		self.cipher.iter(&self.db, CONTACT_PREFIX)
+	}
+
+	fn balance_snapshot_iter<'a>(&'a self) -> Result<Box<Iterator<Item = BalanceSnapshot> + 'a>, Error> {
+		self.cipher.iter(&self.db, BALANCE_SNAPSHOT_PREFIX)
+	}
+
+	fn snapshots_per_block(&self) -> Result<bool, Error> {
+		let flag = self
+			.cipher
+			.get_ser::<u8>(&self.db, &[SNAPSHOTS_PER_BLOCK_PREFIX])?;
+		Ok(flag == Some(1))
+	}
+
+	fn output_lock_iter<'a>(&'a self) -> Result<Box<Iterator<Item = OutputLock> + 'a>, Error> {
+		self.cipher.iter(&self.db, OUTPUT_LOCK_PREFIX)
+	}
+
+	fn restore_checkpoint(&self) -> Result<Option<u64>, Error> {
+		self.cipher.get_ser(&self.db, &[RESTORE_CHECKPOINT_PREFIX])
+	}
+
+	fn restore(&mut self, progress: Option<Sender<ScanProgress>>) -> Result<(), Error> {
+		internal::restore::restore(self, progress).context(ErrorKind::Restore)?;
 		Ok(())
 	}
+
+	fn child_index(&self, parent_key_id: &Identifier) -> Result<u32, Error> {
+		let deriv_key = to_key(DERIV_PREFIX, &mut parent_key_id.to_bytes().to_vec());
+		match self.cipher.get_ser(&self.db, &deriv_key)? {
+			Some(idx) => Ok(idx),
+			None => Ok(0),
+		}
+	}
+
+	fn check_repair(
+		&mut self,
+		fixes: &CheckFixes,
+		progress: Option<Sender<ScanProgress>>,
+	) -> Result<CheckReport, Error> {
+		let report =
+			internal::restore::check_repair(self, fixes, progress).context(ErrorKind::Restore)?;
+		Ok(report)
+	}
+
+	fn node_trust(&self) -> NodeTrust {
+		self.config.node_trust
+	}
+
+	fn header_nodes(&self) -> Vec<String> {
+		self.config.header_nodes.clone()
+	}
+
+	fn header_checkpoint(&self) -> Option<String> {
+		self.config.header_checkpoint.clone()
+	}
+
+	fn tracked_header(&self, height: u64) -> Result<Option<BlockHeader>, Error> {
+		self.cipher
+			.get_ser(&self.db, &u64_to_key(TRACKED_HEADER_PREFIX, height))
+	}
+
+	fn last_tracked_header(&self) -> Result<Option<BlockHeader>, Error> {
+		match self
+			.cipher
+			.get_ser::<u64>(&self.db, &[LAST_TRACKED_HEADER_PREFIX])?
+		{
+			Some(height) => self.tracked_header(height),
+			None => Ok(None),
+		}
+	}
 }
 
 /// An atomic batch in which all changes can be committed all at once or
@@ -283,7 +495,7 @@ where
 	C: WalletClient,
 	K: Keychain,
 {
-	_store: &'a LMDBBackend<C, K>,
+	store: &'a LMDBBackend<C, K>,
 	db: RefCell<Option<store::Batch<'a>>>,
 	/// Keychain
 	keychain: Option<K>,
@@ -303,49 +515,62 @@ where
 		// Save the output data to the db.
 		{
 			let key = to_key(OUTPUT_PREFIX, &mut out.key_id.to_bytes().to_vec());
-			self.db.borrow().as_ref().unwrap().put_ser(&key, &out)?;
+			self.store
+				.cipher
+				.put_ser(self.db.borrow().as_ref().unwrap(), &key, &out)?;
 		}
 
-		// Save the associated output commitment.
-		{
-			let key = to_key(COMMITMENT_PREFIX, &mut out.key_id.to_bytes().to_vec());
-			let commit = self.keychain().commit(out.value, &out.key_id)?;
-			self.db.borrow().as_ref().unwrap().put_ser(&key, &commit)?;
-		}
+		// Save the associated output commitment. Without a keychain (watch-only)
+		// the commitment saved along with the output is kept.
+		let commit = match self.keychain.as_ref() {
+			Some(k) => k.commit(out.value, &out.key_id)?,
+			None => return Ok(()),
+		};
+		let key = to_key(COMMITMENT_PREFIX, &mut out.key_id.to_bytes().to_vec());
+		self.store.cipher.put_ser(self.db.borrow().as_ref().unwrap(), &key, &commit)?;
+
+		Ok(())
+	}
 
+	fn save_watched(&mut self, out: OutputData, commit: &pedersen::Commitment) -> Result<(), Error> {
+		let key = to_key(COMMITMENT_PREFIX, &mut out.key_id.to_bytes().to_vec());
+		self.store.cipher.put_ser(self.db.borrow().as_ref().unwrap(), &key, commit)?;
+		let key = to_key(OUTPUT_PREFIX, &mut out.key_id.to_bytes().to_vec());
+		self.store.cipher.put_ser(self.db.borrow().as_ref().unwrap(), &key, &out)?;
 		Ok(())
 	}
 
 	fn get(&self, id: &Identifier) -> Result<OutputData, Error> {
 		let key = to_key(OUTPUT_PREFIX, &mut id.to_bytes().to_vec());
 		option_to_not_found(
-			self.db.borrow().as_ref().unwrap().get_ser(&key),
+			self.store
+				.cipher
+				.batch_get_ser(self.db.borrow().as_ref().unwrap(), &key),
 			&format!("Key ID: {}", id),
-		).map_err(|e| e.into())
+		)
 	}
 
-	fn iter(&self) -> Box<Iterator<Item = OutputData>> {
-		Box::new(
-			self.db
-				.borrow()
-				.as_ref()
-				.unwrap()
-				.iter(&[OUTPUT_PREFIX])
-				.unwrap(),
-		)
+	fn iter(&self) -> Result<Box<Iterator<Item = OutputData>>, Error> {
+		self.store.cipher.iter(&self.store.db, OUTPUT_PREFIX)
 	}
 
 	fn delete(&mut self, id: &Identifier) -> Result<(), Error> {
 		// Delete the output data.
 		{
 			let key = to_key(OUTPUT_PREFIX, &mut id.to_bytes().to_vec());
-			let _ = self.db.borrow().as_ref().unwrap().delete(&key);
+			let _ = self
+				.store
+				.cipher
+				.delete(self.db.borrow().as_ref().unwrap(), &key);
 		}
 
 		// Delete the associated output commitment.
 		{
 			let key = to_key(COMMITMENT_PREFIX, &mut id.to_bytes().to_vec());
-			let _ = self.db.borrow().as_ref().unwrap().delete(&key);
+			let _ = self
+				.store
+				.cipher
+				.delete(self.db.borrow().as_ref().unwrap(), &key);
 		}
 
 		Ok(())
@@ -353,27 +578,24 @@ where
 
 	fn next_tx_log_id(&mut self, parent_key_id: &Identifier) -> Result<u32, Error> {
 		let tx_id_key = to_key(TX_LOG_ID_PREFIX, &mut parent_key_id.to_bytes().to_vec());
-		let last_tx_log_id = match self.db.borrow().as_ref().unwrap().get_ser(&tx_id_key)? {
+		let last_tx_log_id = match self
+			.store
+			.cipher
+			.batch_get_ser(self.db.borrow().as_ref().unwrap(), &tx_id_key)?
+		{
 			Some(t) => t,
 			None => 0,
 		};
-		self.db
-			.borrow()
-			.as_ref()
-			.unwrap()
-			.put_ser(&tx_id_key, &(last_tx_log_id + 1))?;
+		self.store.cipher.put_ser(
+			self.db.borrow().as_ref().unwrap(),
+			&tx_id_key,
+			&(last_tx_log_id + 1),
+		)?;
 		Ok(last_tx_log_id)
 	}
 
-	fn tx_log_iter(&self) -> Box<Iterator<Item = TxLogEntry>> {
-		Box::new(
-			self.db
-				.borrow()
-				.as_ref()
-				.unwrap()
-				.iter(&[TX_LOG_ENTRY_PREFIX])
-				.unwrap(),
-		)
+	fn tx_log_iter(&self) -> Result<Box<Iterator<Item = TxLogEntry>>, Error> {
+		self.store.cipher.iter(&self.store.db, TX_LOG_ENTRY_PREFIX)
 	}
 
 	fn save_last_confirmed_height(
@@ -385,21 +607,17 @@ where
 			CONFIRMED_HEIGHT_PREFIX,
 			&mut parent_key_id.to_bytes().to_vec(),
 		);
-		self.db
-			.borrow()
-			.as_ref()
-			.unwrap()
-			.put_ser(&height_key, &height)?;
+		self.store
+			.cipher
+			.put_ser(self.db.borrow().as_ref().unwrap(), &height_key, &height)?;
 		Ok(())
 	}
 
 	fn save_child_index(&mut self, parent_id: &Identifier, child_n: u32) -> Result<(), Error> {
 		let deriv_key = to_key(DERIV_PREFIX, &mut parent_id.to_bytes().to_vec());
-		self.db
-			.borrow()
-			.as_ref()
-			.unwrap()
-			.put_ser(&deriv_key, &child_n)?;
+		self.store
+			.cipher
+			.put_ser(self.db.borrow().as_ref().unwrap(), &deriv_key, &child_n)?;
 		Ok(())
 	}
 
@@ -409,11 +627,9 @@ where
 			&mut parent_id.to_bytes().to_vec(),
 			t.id as u64,
 		);
-		self.db
-			.borrow()
-			.as_ref()
-			.unwrap()
-			.put_ser(&tx_log_key, &t)?;
+		self.store
+			.cipher
+			.put_ser(self.db.borrow().as_ref().unwrap(), &tx_log_key, &t)?;
 		Ok(())
 	}
 
@@ -422,23 +638,16 @@ where
 			ACCOUNT_PATH_MAPPING_PREFIX,
 			&mut mapping.label.as_bytes().to_vec(),
 		);
-		self.db
-			.borrow()
-			.as_ref()
-			.unwrap()
-			.put_ser(&acct_key, &mapping)?;
+		self.store
+			.cipher
+			.put_ser(self.db.borrow().as_ref().unwrap(), &acct_key, &mapping)?;
 		Ok(())
 	}
 
-	fn acct_path_iter(&self) -> Box<Iterator<Item = AcctPathMapping>> {
-		Box::new(
-			self.db
-				.borrow()
-				.as_ref()
-				.unwrap()
-				.iter(&[ACCOUNT_PATH_MAPPING_PREFIX])
-				.unwrap(),
-		)
+	fn acct_path_iter(&self) -> Result<Box<Iterator<Item = AcctPathMapping>>, Error> {
+		self.store
+			.cipher
+			.iter(&self.store.db, ACCOUNT_PATH_MAPPING_PREFIX)
 	}
 
 	fn lock_output(&mut self, out: &mut OutputData) -> Result<(), Error> {
@@ -446,20 +655,127 @@ where
 		self.save(out.clone())
 	}
 
+	fn save_contact(&mut self, contact: Contact) -> Result<(), Error> {
+		let key = to_key(CONTACT_PREFIX, &mut contact.name.as_bytes().to_vec());
+		self.store
+			.cipher
+			.put_ser(self.db.borrow().as_ref().unwrap(), &key, &contact)?;
+		Ok(())
+	}
+
+	fn delete_contact(&mut self, name: &str) -> Result<(), Error> {
+		let key = to_key(CONTACT_PREFIX, &mut name.as_bytes().to_vec());
+		self.store
+			.cipher
+			.delete(self.db.borrow().as_ref().unwrap(), &key)
+	}
+
+	fn save_balance_snapshot(&mut self, snapshot: BalanceSnapshot) -> Result<(), Error> {
+		// one snapshot per account and day, or block, the latest replacing
+		// the previous ones
+		let mut id = snapshot.parent_key_id.to_bytes().to_vec();
+		let key = if snapshot.per_block {
+			id.push('b' as u8);
+			to_key_u64(BALANCE_SNAPSHOT_PREFIX, &mut id, snapshot.height)
+		} else {
+			id.push('d' as u8);
+			to_key_u64(BALANCE_SNAPSHOT_PREFIX, &mut id, snapshot.day())
+		};
+		self.store.cipher.put_ser(self.db.borrow().as_ref().unwrap(), &key, &snapshot)?;
+		Ok(())
+	}
+
+	fn save_snapshots_per_block(&mut self, per_block: bool) -> Result<(), Error> {
+		let flag = if per_block { 1u8 } else { 0u8 };
+		self.store.cipher.put_ser(
+			self.db.borrow().as_ref().unwrap(),
+			&[SNAPSHOTS_PER_BLOCK_PREFIX],
+			&flag,
+		)?;
+		Ok(())
+	}
+
+	fn get_output_lock(&self, id: &Identifier) -> Result<Option<OutputLock>, Error> {
+		let key = to_key(OUTPUT_LOCK_PREFIX, &mut id.to_bytes().to_vec());
+		self.store
+			.cipher
+			.batch_get_ser(self.db.borrow().as_ref().unwrap(), &key)
+	}
+
+	fn output_lock_iter(&self) -> Result<Box<Iterator<Item = OutputLock>>, Error> {
+		self.store.cipher.iter(&self.store.db, OUTPUT_LOCK_PREFIX)
+	}
+
+	fn save_output_lock(&mut self, lock: OutputLock) -> Result<(), Error> {
+		let key = to_key(OUTPUT_LOCK_PREFIX, &mut lock.key_id.to_bytes().to_vec());
+		self.store
+			.cipher
+			.put_ser(self.db.borrow().as_ref().unwrap(), &key, &lock)?;
+		Ok(())
+	}
+
+	fn delete_output_lock(&mut self, id: &Identifier) -> Result<(), Error> {
+		let key = to_key(OUTPUT_LOCK_PREFIX, &mut id.to_bytes().to_vec());
+		let _ = self
+			.store
+			.cipher
+			.delete(self.db.borrow().as_ref().unwrap(), &key);
+		Ok(())
+	}
+
 	fn save_private_context(&mut self, slate_id: &[u8], ctx: &Context) -> Result<(), Error> {
 		let ctx_key = to_key(PRIVATE_TX_CONTEXT_PREFIX, &mut slate_id.to_vec());
-		self.db.borrow().as_ref().unwrap().put_ser(&ctx_key, &ctx)?;
+		self.store
+			.cipher
+			.put_ser(self.db.borrow().as_ref().unwrap(), &ctx_key, &ctx)?;
 		Ok(())
 	}
 
 	fn delete_private_context(&mut self, slate_id: &[u8]) -> Result<(), Error> {
 		let ctx_key = to_key(PRIVATE_TX_CONTEXT_PREFIX, &mut slate_id.to_vec());
-		self.db
-			.borrow()
-			.as_ref()
-			.unwrap()
-			.delete(&ctx_key)
-			.map_err(|e| e.into())
+		self.store
+			.cipher
+			.delete(self.db.borrow().as_ref().unwrap(), &ctx_key)
+	}
+
+	fn save_restore_checkpoint(&mut self, index: u64) -> Result<(), Error> {
+		self.store.cipher.put_ser(
+			self.db.borrow().as_ref().unwrap(),
+			&[RESTORE_CHECKPOINT_PREFIX],
+			&index,
+		)?;
+		Ok(())
+	}
+
+	fn delete_restore_checkpoint(&mut self) -> Result<(), Error> {
+		let _ = self.store.cipher.delete(
+			self.db.borrow().as_ref().unwrap(),
+			&[RESTORE_CHECKPOINT_PREFIX],
+		);
+		Ok(())
+	}
+
+	fn save_tracked_header(&mut self, header: &BlockHeader) -> Result<(), Error> {
+		let db = self.db.borrow();
+		let key = u64_to_key(TRACKED_HEADER_PREFIX, header.height);
+		self.store
+			.cipher
+			.put_ser(db.as_ref().unwrap(), &key, header)?;
+		self.store.cipher.put_ser(
+			db.as_ref().unwrap(),
+			&[LAST_TRACKED_HEADER_PREFIX],
+			&header.height,
+		)?;
+		Ok(())
+	}
+
+	fn delete_tracked_header(&mut self, height: u64) -> Result<(), Error> {
+		let key = u64_to_key(TRACKED_HEADER_PREFIX, height);
+		let _ = self
+			.store
+			.cipher
+			.delete(self.db.borrow().as_ref().unwrap(), &key);
+		Ok(())
 	}
 
 	fn commit(&self) -> Result<(), Error> {
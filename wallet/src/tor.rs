@@ -0,0 +1,254 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishing the wallet listener as a Tor v3 onion service, so it can
+//! receive without a public IP. An external tor binary is started with its
+//! own data directory under the wallet's, and driven through its control
+//! port. The service key is kept there too, so the onion address stays the
+//! same across runs.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libwallet::{Error, ErrorKind};
+use util;
+
+/// Directory under the wallet data dir holding tor's files
+pub const TOR_DIR: &'static str = "tor";
+/// File keeping the onion service private key
+const ONION_KEY_FILE: &'static str = "onion_service_key";
+
+/// How long tor gets to open its control port
+const STARTUP_TIMEOUT_SECS: u64 = 60;
+
+/// A running tor publishing an onion service, which stops along with it when
+/// dropped
+pub struct OnionService {
+	child: Child,
+	// the service lasts as long as the control connection that created it
+	_control: TorControl,
+	/// Address of the service, without the `.onion` suffix
+	pub service_id: String,
+}
+
+impl OnionService {
+	/// The onion address senders can use, e.g. `http://xyz.onion`
+	pub fn address(&self) -> String {
+		format!("http://{}.onion", self.service_id)
+	}
+}
+
+impl Drop for OnionService {
+	fn drop(&mut self) {
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
+
+/// Start tor and publish an onion service forwarding its port 80 to the
+/// given local address, e.g. `127.0.0.1:13415`
+pub fn publish_onion_service(
+	tor_binary: &str,
+	data_file_dir: &str,
+	local_addr: &str,
+) -> Result<OnionService, Error> {
+	let tor_dir = Path::new(data_file_dir).join(TOR_DIR);
+	fs::create_dir_all(tor_dir.join("data"))
+		.map_err(|e| ErrorKind::Tor(format!("Could not create {:?}: {}", tor_dir, e)))?;
+	let port_file = tor_dir.join("control_port");
+	let cookie_file = tor_dir.join("control_auth_cookie");
+	let torrc = tor_dir.join("torrc");
+	let _ = fs::remove_file(&port_file);
+	write_file(
+		&torrc,
+		&format!(
+			"SocksPort 0\n\
+			 ControlPort auto\n\
+			 ControlPortWriteToFile {}\n\
+			 CookieAuthentication 1\n\
+			 CookieAuthFile {}\n\
+			 DataDirectory {}\n",
+			port_file.display(),
+			cookie_file.display(),
+			tor_dir.join("data").display(),
+		),
+	)?;
+
+	info!("Starting tor ({})", tor_binary);
+	let mut child = Command::new(tor_binary)
+		.arg("-f")
+		.arg(&torrc)
+		.stdout(Stdio::null())
+		.stderr(Stdio::null())
+		.spawn()
+		.map_err(|e| {
+			ErrorKind::Tor(format!(
+				"Could not run {}, is tor installed? {}",
+				tor_binary, e
+			))
+		})?;
+
+	let control_addr = match wait_for_control_port(&mut child, &port_file) {
+		Ok(addr) => addr,
+		Err(e) => {
+			let _ = child.kill();
+			return Err(e);
+		}
+	};
+	let res = TorControl::connect(&control_addr, &cookie_file).and_then(|mut control| {
+		let service_id = add_onion(&mut control, &tor_dir.join(ONION_KEY_FILE), local_addr)?;
+		Ok((control, service_id))
+	});
+	match res {
+		Ok((control, service_id)) => {
+			let service = OnionService {
+				child,
+				_control: control,
+				service_id,
+			};
+			info!("Onion service published at {}", service.address());
+			Ok(service)
+		}
+		Err(e) => {
+			let _ = child.kill();
+			Err(e)
+		}
+	}
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), Error> {
+	let mut f = File::create(path)
+		.map_err(|e| ErrorKind::Tor(format!("Could not write {:?}: {}", path, e)))?;
+	f.write_all(content.as_bytes())?;
+	Ok(())
+}
+
+/// Wait for tor to write out the address of its control port
+fn wait_for_control_port(child: &mut Child, port_file: &PathBuf) -> Result<String, Error> {
+	let started = Instant::now();
+	while started.elapsed() < Duration::from_secs(STARTUP_TIMEOUT_SECS) {
+		if let Ok(Some(status)) = child.try_wait() {
+			return Err(ErrorKind::Tor(format!("tor exited on startup: {}", status)).into());
+		}
+		if let Ok(content) = fs::read_to_string(port_file) {
+			// written as PORT=127.0.0.1:xxxx
+			let content = content.trim();
+			if let Some(i) = content.find('=') {
+				return Ok(content[i + 1..].to_owned());
+			}
+		}
+		thread::sleep(Duration::from_millis(200));
+	}
+	Err(ErrorKind::Tor("Timed out waiting for tor to start".to_owned()).into())
+}
+
+/// Create the onion service, with the key kept from a previous run if any.
+/// Returns the service id.
+fn add_onion(control: &mut TorControl, key_file: &Path, local_addr: &str) -> Result<String, Error> {
+	let key = fs::read_to_string(key_file)
+		.ok()
+		.map(|k| k.trim().to_owned());
+	let cmd = match key {
+		Some(ref k) => format!("ADD_ONION {} Flags=DiscardPK Port=80,{}", k, local_addr),
+		None => format!("ADD_ONION NEW:ED25519-V3 Port=80,{}", local_addr),
+	};
+	let mut service_id = None;
+	for line in control.command(&cmd)? {
+		let mut kv = line.splitn(2, '=');
+		match (kv.next(), kv.next()) {
+			(Some("ServiceID"), Some(id)) => service_id = Some(id.to_owned()),
+			(Some("PrivateKey"), Some(key)) => write_private(key_file, key)?,
+			_ => (),
+		}
+	}
+	service_id.ok_or_else(|| ErrorKind::Tor("tor didn't give an onion address".to_owned()).into())
+}
+
+/// Write a file only we can read, created that way rather than restricted
+/// once written
+fn write_private(path: &Path, content: &str) -> Result<(), Error> {
+	let mut options = OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	let mut f = options
+		.open(path)
+		.map_err(|e| ErrorKind::Tor(format!("Could not write {:?}: {}", path, e)))?;
+	f.write_all(content.as_bytes())?;
+	Ok(())
+}
+
+/// A connection to tor's control port
+struct TorControl {
+	reader: BufReader<TcpStream>,
+	writer: TcpStream,
+}
+
+impl TorControl {
+	/// Connect and authenticate with the cookie tor wrote out
+	fn connect(addr: &str, cookie_file: &Path) -> Result<TorControl, Error> {
+		let stream = TcpStream::connect(addr)
+			.map_err(|e| ErrorKind::Tor(format!("Could not connect to tor control port: {}", e)))?;
+		let writer = stream.try_clone()?;
+		let mut control = TorControl {
+			reader: BufReader::new(stream),
+			writer,
+		};
+		let mut cookie = vec![];
+		File::open(cookie_file)
+			.and_then(|mut f| f.read_to_end(&mut cookie))
+			.map_err(|e| ErrorKind::Tor(format!("Could not read tor auth cookie: {}", e)))?;
+		control.command(&format!("AUTHENTICATE {}", util::to_hex(cookie)))?;
+		Ok(control)
+	}
+
+	/// Send a command, returning the lines of a successful reply without
+	/// their status code
+	fn command(&mut self, cmd: &str) -> Result<Vec<String>, Error> {
+		write!(self.writer, "{}\r\n", cmd)?;
+		let mut lines = vec![];
+		loop {
+			let mut line = String::new();
+			if self.reader.read_line(&mut line)? == 0 {
+				return Err(ErrorKind::Tor("tor closed the control connection".to_owned()).into());
+			}
+			let line = line.trim();
+			if line.len() < 4 {
+				continue;
+			}
+			let (code, sep, text) = (&line[..3], &line[3..4], &line[4..]);
+			if code != "250" {
+				return Err(ErrorKind::Tor(format!("{} failed: {}", cmd_name(cmd), line)).into());
+			}
+			lines.push(text.to_owned());
+			// the last line of a reply has a space after the code
+			if sep == " " {
+				return Ok(lines);
+			}
+		}
+	}
+}
+
+/// Command name only, keeping keys out of errors
+fn cmd_name(cmd: &str) -> &str {
+	cmd.split_whitespace().next().unwrap_or("")
+}
@@ -24,8 +24,10 @@ use rand::{thread_rng, Rng};
 use core::global::ChainTypes;
 use error::{Error, ErrorKind};
 use failure::ResultExt;
-use keychain::Keychain;
+use keychain::{KeyDerivation, Keychain};
+use libwallet::types::NodeTrust;
 use util;
+use util::{Zeroing, Zeroize};
 
 pub const SEED_FILE: &'static str = "wallet.seed";
 
@@ -33,6 +35,10 @@ pub const SEED_FILE: &'static str = "wallet.seed";
 pub struct WalletConfig {
 	// Chain parameters (default to Testnet3 if none at the moment)
 	pub chain_type: Option<ChainTypes>,
+	/// Network definition file of the private network the wallet is on,
+	/// for the coinbase maturity it may override
+	#[serde(default)]
+	pub network_definition: Option<String>,
 	// The api interface/ip_address that this api server (i.e. this wallet) will run
 	// by default this is 127.0.0.1 (and will not accept connections from external clients)
 	pub api_listen_interface: String,
@@ -54,12 +60,49 @@ pub struct WalletConfig {
 	/// Whether to use the black background color scheme for command line
 	/// if enabled, wallet command output color will be suitable for black background terminal
 	pub dark_background_color_scheme: Option<bool>,
+	/// Hours after which pending sends that were never finalized are
+	/// cancelled automatically, unlocking their outputs. Never if unset.
+	#[serde(default)]
+	pub pending_tx_ttl_hours: Option<u64>,
+	/// Tor binary run to publish the listener as an onion service, `tor`
+	/// from the path if unset
+	#[serde(default)]
+	pub tor_binary: Option<String>,
+	/// SOCKS proxy of a running tor, used to send to onion addresses.
+	/// 127.0.0.1:9050 if unset.
+	#[serde(default)]
+	pub tor_socks_proxy: Option<String>,
+	/// Whether the outputs the node reports are taken on trust or verified
+	/// against Merkle proofs and the header chain, for public nodes
+	#[serde(default)]
+	pub node_trust: NodeTrust,
+	/// Other nodes whose chain the headers tracked when verifying are
+	/// checked against, to notice a node lagging behind or on another chain
+	#[serde(default)]
+	pub header_nodes: Vec<String>,
+	/// Header the tracked header chain starts from when verifying, as
+	/// `<height>:<hash>`, genesis if unset
+	#[serde(default)]
+	pub header_checkpoint: Option<String>,
+	/// Shell command run for each payment received once confirmed, when
+	/// listening with `--watch`
+	#[serde(default)]
+	pub notify_command: Option<String>,
+	/// URL each payment received is posted to once confirmed, when listening
+	/// with `--watch`
+	#[serde(default)]
+	pub notify_url: Option<String>,
+	/// Seconds between two refreshes of the wallet when watching for
+	/// payments received, 60 if unset
+	#[serde(default)]
+	pub watch_interval_secs: Option<u64>,
 }
 
 impl Default for WalletConfig {
 	fn default() -> WalletConfig {
 		WalletConfig {
 			chain_type: Some(ChainTypes::Testnet4),
+			network_definition: None,
 			api_listen_interface: "127.0.0.1".to_string(),
 			api_listen_port: 13415,
 			api_secret_path: Some(".api_secret".to_string()),
@@ -69,6 +112,15 @@ impl Default for WalletConfig {
 			tls_certificate_file: None,
 			tls_certificate_key: None,
 			dark_background_color_scheme: Some(true),
+			pending_tx_ttl_hours: None,
+			tor_binary: None,
+			tor_socks_proxy: None,
+			node_trust: NodeTrust::Trusted,
+			header_nodes: vec![],
+			header_checkpoint: None,
+			notify_command: None,
+			notify_url: None,
+			watch_interval_secs: None,
 		}
 	}
 }
@@ -102,14 +154,32 @@ impl WalletSeed {
 	}
 
 	pub fn derive_keychain<K: Keychain>(&self, password: &str) -> Result<K, Error> {
-		let seed = blake2::blake2b::blake2b(64, &password.as_bytes(), &self.0);
-		let result = K::from_seed(seed.as_bytes())?;
+		self.derive_keychain_with(password, KeyDerivation::Legacy)
+	}
+
+	/// Derives the keychain, its keys derived from the seed as provided.
+	/// BIP44 keychains take the seed as their BIP32 seed, so other wallets
+	/// restore the same keys from it, the original scheme hashes the password
+	/// in first.
+	pub fn derive_keychain_with<K: Keychain>(
+		&self,
+		password: &str,
+		derivation: KeyDerivation,
+	) -> Result<K, Error> {
+		let result = match derivation {
+			KeyDerivation::Legacy => {
+				let seed = blake2::blake2b::blake2b(64, &password.as_bytes(), &self.0);
+				K::from_seed_with(seed.as_bytes(), derivation)?
+			}
+			KeyDerivation::Bip44 => K::from_seed_with(&self.0, derivation)?,
+		};
 		Ok(result)
 	}
 
 	pub fn init_new() -> WalletSeed {
-		let seed: [u8; 32] = thread_rng().gen();
-		WalletSeed(seed)
+		let mut seed = WalletSeed([0; 32]);
+		thread_rng().fill(&mut seed.0);
+		seed
 	}
 
 	pub fn init_file(wallet_config: &WalletConfig) -> Result<WalletSeed, Error> {
@@ -128,8 +198,8 @@ impl WalletSeed {
 		} else {
 			let seed = WalletSeed::init_new();
 			let mut file = File::create(seed_file_path).context(ErrorKind::IO)?;
-			file.write_all(&seed.to_hex().as_bytes())
-				.context(ErrorKind::IO)?;
+			let hex = Zeroing::new(seed.to_hex());
+			file.write_all(&hex.as_bytes()).context(ErrorKind::IO)?;
 			Ok(seed)
 		}
 	}
@@ -147,7 +217,7 @@ impl WalletSeed {
 
 		if Path::new(seed_file_path).exists() {
 			let mut file = File::open(seed_file_path).context(ErrorKind::IO)?;
-			let mut buffer = String::new();
+			let mut buffer = Zeroing::new(String::new());
 			file.read_to_string(&mut buffer).context(ErrorKind::IO)?;
 			let wallet_seed = WalletSeed::from_hex(&buffer)?;
 			Ok(wallet_seed)
@@ -161,3 +231,9 @@ impl WalletSeed {
 		}
 	}
 }
+
+impl Drop for WalletSeed {
+	fn drop(&mut self) {
+		self.0.zeroize();
+	}
+}
@@ -0,0 +1,195 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Watching the wallet for payments received, for "payment received"
+//! automation. The wallet is refreshed from the node every so often and each
+//! payment confirmed since is notified about, by running a shell command,
+//! posting to a webhook or both. A payment is only marked as notified in the
+//! wallet once that went through, so one missed while the watcher or the
+//! hooks were down goes out on the next refresh instead.
+
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::{DateTime, Utc};
+use uuid::Uuid;
+
+use api;
+use keychain::Keychain;
+use libwallet::controller;
+use libwallet::types::{TxLogEntry, WalletBackend, WalletClient};
+use libwallet::{Error, ErrorKind};
+use types::WalletConfig;
+use util::Mutex;
+
+/// Seconds between two refreshes of the wallet by default
+pub const WATCH_INTERVAL_SECS: u64 = 60;
+
+/// Seconds the webhook gets to answer
+const NOTIFY_TIMEOUT_SECS: u64 = 10;
+
+/// A payment received and confirmed, as posted to the webhook
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaymentReceived {
+	/// Id of the transaction in the wallet
+	pub tx_id: u32,
+	/// Id of the slate it was received with, if any
+	pub tx_slate_id: Option<Uuid>,
+	/// Amount received, in nanogrins
+	pub amount: u64,
+	/// When the wallet saw it confirmed
+	pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+impl PaymentReceived {
+	/// The payment received by a transaction
+	pub fn from_tx(tx: &TxLogEntry) -> PaymentReceived {
+		PaymentReceived {
+			tx_id: tx.id,
+			tx_slate_id: tx.tx_slate_id,
+			amount: tx.amount_credited,
+			confirmed_at: tx.confirmation_ts,
+		}
+	}
+}
+
+/// Runs the command and posts to the webhook configured for each payment
+pub struct Notifier {
+	command: Option<String>,
+	url: Option<String>,
+}
+
+impl Notifier {
+	/// Notifier with the hooks of the wallet configuration
+	pub fn new(config: &WalletConfig) -> Notifier {
+		Notifier {
+			command: config.notify_command.clone(),
+			url: config.notify_url.clone(),
+		}
+	}
+
+	/// Whether there's any hook to notify, payments only getting logged
+	/// otherwise
+	pub fn has_hooks(&self) -> bool {
+		self.command.is_some() || self.url.is_some()
+	}
+
+	/// Notifies the hooks of a payment, failing if either does
+	pub fn notify(&self, payment: &PaymentReceived) -> Result<(), Error> {
+		if let Some(ref command) = self.command {
+			run_command(command, payment)?;
+		}
+		if let Some(ref url) = self.url {
+			let timeout = Duration::from_secs(NOTIFY_TIMEOUT_SECS);
+			api::client::post_no_ret_timeout(url, None, payment, timeout)
+				.map_err(|e| ErrorKind::Notification(format!("posting to {}: {}", url, e)))?;
+		}
+		Ok(())
+	}
+}
+
+fn run_command(command: &str, payment: &PaymentReceived) -> Result<(), Error> {
+	let mut cmd = if cfg!(windows) {
+		let mut c = Command::new("cmd");
+		c.arg("/C");
+		c
+	} else {
+		let mut c = Command::new("sh");
+		c.arg("-c");
+		c
+	};
+	let status = cmd
+		.arg(command)
+		.env("GRIN_TX_ID", payment.tx_id.to_string())
+		.env(
+			"GRIN_TX_SLATE_ID",
+			payment
+				.tx_slate_id
+				.map(|id| id.to_string())
+				.unwrap_or_default(),
+		)
+		.env("GRIN_AMOUNT", payment.amount.to_string())
+		.env(
+			"GRIN_CONFIRMED_AT",
+			payment
+				.confirmed_at
+				.map(|t| t.to_rfc3339())
+				.unwrap_or_default(),
+		)
+		.status()
+		.map_err(|e| ErrorKind::Notification(format!("running '{}': {}", command, e)))?;
+	if !status.success() {
+		return Err(ErrorKind::Notification(format!(
+			"'{}' exited with {}",
+			command, status
+		)))?;
+	}
+	Ok(())
+}
+
+/// Refreshes the wallet and notifies about the payments confirmed since the
+/// last time, returning how many were
+pub fn check_payments<T: ?Sized, C, K>(
+	wallet: Arc<Mutex<Box<T>>>,
+	notifier: &Notifier,
+) -> Result<usize, Error>
+where
+	T: WalletBackend<C, K>,
+	C: WalletClient,
+	K: Keychain,
+{
+	let mut receipts = vec![];
+	controller::owner_single_use(wallet.clone(), |api| {
+		let (refreshed, txs) = api.unnotified_receipts()?;
+		if !refreshed {
+			warn!("Couldn't refresh the wallet from the node to watch for payments");
+		}
+		receipts = txs;
+		Ok(())
+	})?;
+
+	// the wallet isn't held while the hooks run
+	let mut notified = 0;
+	for tx in receipts {
+		let payment = PaymentReceived::from_tx(&tx);
+		info!(
+			"Payment of {} received in transaction {}",
+			payment.amount, payment.tx_id
+		);
+		notifier.notify(&payment)?;
+		controller::owner_single_use(wallet.clone(), |api| api.mark_notified(tx.id))?;
+		notified += 1;
+	}
+	Ok(notified)
+}
+
+/// Checks for payments received every `interval` in a thread of its own,
+/// until the process exits
+pub fn start<T: ?Sized, C, K>(wallet: Arc<Mutex<Box<T>>>, notifier: Notifier, interval: Duration)
+where
+	T: WalletBackend<C, K> + Send + 'static,
+	C: WalletClient + 'static,
+	K: Keychain + 'static,
+{
+	let _ = thread::Builder::new()
+		.name("wallet_watcher".to_string())
+		.spawn(move || loop {
+			if let Err(e) = check_payments(wallet.clone(), &notifier) {
+				error!("Watching for payments: {}, retrying", e);
+			}
+			thread::sleep(interval);
+		});
+}
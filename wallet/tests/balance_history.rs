@@ -0,0 +1,134 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests the balance snapshots recorded as the wallet refreshes
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate rand;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate serde;
+extern crate uuid;
+
+mod common;
+use common::testclient::{LocalWalletClient, WalletProxy};
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use core::global;
+use core::global::ChainTypes;
+use keychain::ExtKeychain;
+use wallet::libwallet;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn setup(test_dir: &str) {
+	util::init_test_logger();
+	clean_output_dir(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+}
+
+/// Daily and per block balance snapshots, per account
+fn balance_history_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	// Create a new proxy to simulate server and wallet responses
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+
+	// nothing recorded before the first refresh
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, snapshots) = api.retrieve_balance_history(false, false)?;
+		assert!(snapshots.is_empty());
+		api.set_snapshots_per_block(true)?;
+		api.new_account_path("account1")?;
+		Ok(())
+	})?;
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 3);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (refreshed, _) = api.retrieve_summary_info(true)?;
+		assert!(refreshed);
+		Ok(())
+	})?;
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 2);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (refreshed, snapshots) = api.retrieve_balance_history(true, true)?;
+		assert!(refreshed);
+		assert_eq!(snapshots.len(), 2);
+		assert_eq!(snapshots[0].height, 3);
+		assert_eq!(snapshots[0].total, reward * 3);
+		assert_eq!(snapshots[1].height, 5);
+		assert_eq!(snapshots[1].total, reward * 5);
+
+		// the latest refresh of the day stands for it
+		let (_, days) = api.retrieve_balance_history(false, false)?;
+		let last = days.last().unwrap();
+		assert_eq!(last.height, 5);
+		assert_eq!(last.total, reward * 5);
+		assert!(!last.per_block);
+		Ok(())
+	})?;
+
+	// other accounts have a history of their own
+	{
+		let mut w = wallet1.lock();
+		w.set_parent_key_id_by_name("account1")?;
+	}
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, snapshots) = api.retrieve_balance_history(false, true)?;
+		assert!(snapshots.is_empty());
+		api.set_snapshots_per_block(false)?;
+		let (_, snapshots) = api.retrieve_balance_history(true, true)?;
+		assert!(snapshots.is_empty());
+		let (_, days) = api.retrieve_balance_history(false, false)?;
+		assert_eq!(days.len(), 1);
+		assert_eq!(days[0].total, 0);
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+#[test]
+fn balance_history() {
+	let test_dir = "test_output/balance_history";
+	if let Err(e) = balance_history_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
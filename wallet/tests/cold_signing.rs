@@ -0,0 +1,213 @@
+// Copyright 2018 The Grin Developers
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests for sends split between a watch-only wallet and an offline signer
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate rand;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate serde;
+extern crate uuid;
+
+#[allow(dead_code)]
+mod common;
+use common::testclient::{LocalWalletClient, WalletProxy};
+
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use core::global;
+use core::global::ChainTypes;
+use keychain::ExtKeychain;
+use util::Mutex;
+use wallet::libwallet;
+use wallet::libtx::slate::SLATE_VERSION;
+use wallet::libwallet::types::{OutputStatus, ReceiveStatus, TxLogEntryType, WalletInst};
+use wallet::{LMDBBackend, WalletBackend, WalletConfig};
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn setup(test_dir: &str) {
+	util::init_test_logger();
+	clean_output_dir(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+}
+
+/// A wallet without a seed, as created by `init --cold`
+fn create_watch_only_wallet(
+	dir: &str,
+	client: LocalWalletClient,
+) -> Arc<Mutex<Box<WalletInst<LocalWalletClient, ExtKeychain>>>> {
+	let config = WalletConfig {
+		data_file_dir: dir.to_owned(),
+		..Default::default()
+	};
+	let mut wallet = LMDBBackend::new(config, "", client).unwrap();
+	wallet.init_watch_only().unwrap();
+	assert!(wallet.is_watch_only());
+	wallet.open_with_credentials().unwrap();
+	Arc::new(Mutex::new(Box::new(wallet)))
+}
+
+/// A send made by a watch-only wallet, signed offline
+fn cold_signing_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	// the signer holds the keys and never talks to the node itself
+	let client = LocalWalletClient::new("signer", wallet_proxy.tx.clone());
+	let signer = common::create_wallet(&format!("{}/signer", test_dir), client.clone());
+	wallet_proxy.add_wallet("signer", client.get_send_instance(), signer.clone());
+
+	let client = LocalWalletClient::new("watch", wallet_proxy.tx.clone());
+	let watch = create_watch_only_wallet(&format!("{}/watch", test_dir), client.clone());
+	wallet_proxy.add_wallet("watch", client.get_send_instance(), watch.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, signer.clone(), cm as usize + 3);
+
+	// the watch-only wallet tracks the signer's outputs
+	let mut outputs = vec![];
+	wallet::controller::owner_single_use(signer.clone(), |api| {
+		outputs = api.export_outputs()?;
+		Ok(())
+	})?;
+	wallet::controller::owner_single_use(watch.clone(), |api| {
+		assert_eq!(api.import_outputs(outputs.clone())?, outputs.len());
+		assert_eq!(api.import_outputs(outputs.clone())?, 0);
+		let (refreshed, info) = api.retrieve_summary_info(true)?;
+		assert!(refreshed);
+		assert!(info.amount_currently_spendable >= reward * 3);
+		Ok(())
+	})?;
+
+	// senders are told it can't receive
+	wallet::controller::foreign_single_use(watch.clone(), |api| {
+		let info = api.check_version()?;
+		assert_eq!(info.receive_status, ReceiveStatus::WatchOnly);
+		assert!(info.check_receive(SLATE_VERSION).is_err());
+		Ok(())
+	})?;
+	wallet::controller::foreign_single_use(signer.clone(), |api| {
+		let info = api.check_version()?;
+		assert_eq!(info.receive_status, ReceiveStatus::Ready);
+		assert!(info.check_receive(SLATE_VERSION).is_ok());
+		assert!(info.check_receive(SLATE_VERSION + 1).is_err());
+		Ok(())
+	})?;
+
+	let amount = reward / 2;
+	let mut req = None;
+	wallet::controller::owner_single_use(watch.clone(), |api| {
+		req = Some(api.signing_request(amount, 1, 500, 1, false)?);
+		Ok(())
+	})?;
+	let req = req.unwrap();
+	assert_eq!(req.inputs.len(), 1);
+
+	let mut slate = None;
+	wallet::controller::owner_single_use(signer.clone(), |api| {
+		slate = Some(api.sign_request(&req)?);
+		Ok(())
+	})?;
+	let mut slate = slate.unwrap();
+	assert_eq!(slate.id, req.id);
+
+	wallet::controller::foreign_single_use(wallet2.clone(), |api| {
+		api.receive_tx(&mut slate)?;
+		Ok(())
+	})?;
+	wallet::controller::owner_single_use(signer.clone(), |api| {
+		api.sign_response(&mut slate)?;
+		// the context is used up
+		assert!(api.sign_response(&mut slate.clone()).is_err());
+		Ok(())
+	})?;
+
+	wallet::controller::owner_single_use(watch.clone(), |api| {
+		api.finalize_signed(&mut slate)?;
+		let (_, txs) = api.retrieve_txs(false, None)?;
+		let tx = txs.iter().find(|t| t.tx_slate_id == Some(slate.id)).unwrap();
+		assert_eq!(tx.tx_type, TxLogEntryType::TxSent);
+		assert_eq!(tx.num_inputs, 1);
+		Ok(())
+	})?;
+	// posting through the signer, as the proxy mines the block to the sender
+	wallet::controller::owner_single_use(signer.clone(), |api| {
+		api.post_tx(&slate, false)?;
+		Ok(())
+	})?;
+
+	wallet::controller::owner_single_use(wallet2.clone(), |api| {
+		let (_, info) = api.retrieve_summary_info(true)?;
+		assert_eq!(info.amount_currently_spendable, amount);
+		Ok(())
+	})?;
+
+	// the watch-only wallet sees its input spent, and learns about the change
+	// from the signer
+	wallet::controller::owner_single_use(signer.clone(), |api| {
+		outputs = api.export_outputs()?;
+		Ok(())
+	})?;
+	let input = req.inputs[0].clone();
+	wallet::controller::owner_single_use(watch.clone(), |api| {
+		let (_, outs) = api.retrieve_outputs(true, true, None)?;
+		let (spent, _) = outs.iter().find(|(o, _)| o.key_id == input.key_id).unwrap();
+		assert_eq!(spent.status, OutputStatus::Spent);
+
+		assert!(api.import_outputs(outputs.clone())? > 0);
+		let (_, outs) = api.retrieve_outputs(false, true, None)?;
+		let change = input.value - amount - slate.fee;
+		assert!(
+			outs.iter()
+				.any(|(o, _)| o.value == change && o.status == OutputStatus::Unspent)
+		);
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+#[test]
+fn cold_signing() {
+	let test_dir = "test_output/cold_signing";
+	if let Err(e) = cold_signing_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
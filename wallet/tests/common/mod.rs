@@ -83,6 +83,45 @@ fn get_outputs_by_pmmr_index_local(
 	}
 }
 
+/// Merkle proofs of the unspent outputs among the given ones, as the node
+/// API presents them
+fn get_output_proofs_local(
+	chain: &chain::Chain,
+	commits: &[pedersen::Commitment],
+) -> api::OutputProofs {
+	let mut ids = vec![];
+	for commit in commits {
+		ids.push(OutputIdentifier::new(
+			OutputFeatures::DEFAULT_OUTPUT,
+			commit,
+		));
+		ids.push(OutputIdentifier::new(
+			OutputFeatures::COINBASE_OUTPUT,
+			commit,
+		));
+	}
+	let (header, proofs) = chain.get_merkle_proofs(&ids).unwrap();
+	api::OutputProofs {
+		header: api::BlockHeaderInfo::from_header(&header),
+		outputs: proofs
+			.into_iter()
+			.map(|(out_id, pos, proof)| api::OutputProof {
+				commit: api::PrintableCommitment {
+					commit: out_id.commit,
+				},
+				output_type: if out_id.features.contains(OutputFeatures::COINBASE_OUTPUT) {
+					api::OutputType::Coinbase
+				} else {
+					api::OutputType::Transaction
+				},
+				height: chain.get_header_for_output(&out_id).unwrap().height,
+				mmr_index: pos,
+				merkle_proof: proof.to_hex(),
+			})
+			.collect(),
+	}
+}
+
 /// Adds a block with a given reward to the chain and mines it
 pub fn add_block_with_reward(chain: &Chain, txs: Vec<&Transaction>, reward: CbData) {
 	let prev = chain.head_header().unwrap();
@@ -163,6 +202,18 @@ where
 {
 	let mut wallet_config = WalletConfig::default();
 	wallet_config.data_file_dir = String::from(dir);
+	create_wallet_with_config(wallet_config, client)
+}
+
+/// dispatch a db wallet with the given config
+pub fn create_wallet_with_config<C, K>(
+	wallet_config: WalletConfig,
+	client: C,
+) -> Arc<Mutex<Box<WalletInst<C, K>>>>
+where
+	C: WalletClient + 'static,
+	K: keychain::Keychain + 'static,
+{
 	let _ = wallet::WalletSeed::init_file(&wallet_config);
 	let mut wallet: Box<WalletInst<C, K>> = {
 		let mut wallet: LMDBBackend<C, K> = LMDBBackend::new(wallet_config.clone(), "", client)
@@ -23,7 +23,7 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use util::{Mutex, RwLock};
+use util::{MemoryBudget, Mutex, RwLock};
 
 use common::api;
 use common::serde_json;
@@ -35,8 +35,9 @@ use common::failure::ResultExt;
 
 use chain::types::NoopAdapter;
 use chain::Chain;
+use core::core::hash::{Hash, Hashed};
 use core::core::verifier_cache::LruVerifierCache;
-use core::core::Transaction;
+use core::core::{BlockHeader, Transaction};
 use core::global::{set_mining_mode, ChainTypes};
 use core::{pow, ser};
 use keychain::Keychain;
@@ -72,6 +73,8 @@ where
 	pub chain_dir: String,
 	/// handle to chain itself
 	pub chain: Arc<Chain>,
+	/// chains of other nodes by address, serving the headers asked to them
+	pub node_chains: HashMap<String, Arc<Chain>>,
 	/// list of interested wallets
 	pub wallets: HashMap<
 		String,
@@ -113,11 +116,13 @@ where
 			pow::verify_size,
 			verifier_cache,
 			false,
+			Arc::new(MemoryBudget::unlimited()),
 		).unwrap();
 		let (tx, rx) = channel();
 		let retval = WalletProxy {
 			chain_dir: chain_dir.to_owned(),
 			chain: Arc::new(c),
+			node_chains: HashMap::new(),
 			tx: tx,
 			rx: rx,
 			wallets: HashMap::new(),
@@ -138,6 +143,29 @@ where
 		self.wallets.insert(addr.to_owned(), (tx, wallet));
 	}
 
+	/// Add another node with the given "address", starting from the same
+	/// genesis
+	pub fn add_node(&mut self, addr: &str) -> Arc<Chain> {
+		let genesis = self.chain.get_header_by_height(0).unwrap();
+		let genesis_block = self.chain.get_block(&genesis.hash()).unwrap();
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+		let dir_name = format!("{}/.grin_{}", self.chain_dir, addr);
+		let db_env = Arc::new(store::new_env(dir_name.to_string()));
+		let c = Chain::init(
+			dir_name.to_string(),
+			db_env,
+			Arc::new(NoopAdapter {}),
+			genesis_block,
+			pow::verify_size,
+			verifier_cache,
+			false,
+			Arc::new(MemoryBudget::unlimited()),
+		).unwrap();
+		let c = Arc::new(c);
+		self.node_chains.insert(addr.to_owned(), c.clone());
+		c
+	}
+
 	/// Run the incoming message queue and respond more or less
 	/// synchronously
 	pub fn run(&mut self) -> Result<(), libwallet::Error> {
@@ -151,6 +179,8 @@ where
 				"get_chain_height" => self.get_chain_height(m)?,
 				"get_outputs_from_node" => self.get_outputs_from_node(m)?,
 				"get_outputs_by_pmmr_index" => self.get_outputs_by_pmmr_index(m)?,
+				"get_header" => self.get_header(m)?,
+				"get_output_proofs" => self.get_output_proofs(m)?,
 				"send_tx_slate" => self.send_tx_slate(m)?,
 				"post_tx" => self.post_tx(m)?,
 				_ => panic!("Unknown Wallet Proxy Message"),
@@ -279,6 +309,45 @@ where
 			body: serde_json::to_string(&ol).unwrap(),
 		})
 	}
+
+	/// get header, hex encoded
+	fn get_header(
+		&mut self,
+		m: WalletProxyMessage,
+	) -> Result<WalletProxyMessage, libwallet::Error> {
+		// the head of the chain if no height, of the node it's asked to
+		let chain = self.node_chains.get(&m.dest).unwrap_or(&self.chain);
+		let header = match m.body.parse::<u64>() {
+			Ok(height) => chain.get_header_by_height(height).unwrap(),
+			Err(_) => chain.head_header().unwrap(),
+		};
+		Ok(WalletProxyMessage {
+			sender_id: m.dest.clone(),
+			dest: m.sender_id,
+			method: m.method,
+			body: util::to_hex(ser::ser_vec(&header).unwrap()),
+		})
+	}
+
+	/// get merkle proofs of outputs
+	fn get_output_proofs(
+		&mut self,
+		m: WalletProxyMessage,
+	) -> Result<WalletProxyMessage, libwallet::Error> {
+		let commits: Vec<Commitment> = m
+			.body
+			.split(",")
+			.filter(|o| !o.is_empty())
+			.map(|o| Commitment::from_vec(util::from_hex(o.to_owned()).unwrap()))
+			.collect();
+		let proofs = common::get_output_proofs_local(&self.chain, &commits);
+		Ok(WalletProxyMessage {
+			sender_id: "node".to_owned(),
+			dest: m.sender_id,
+			method: m.method,
+			body: serde_json::to_string(&vec![proofs]).unwrap(),
+		})
+	}
 }
 
 #[derive(Clone)]
@@ -480,4 +549,88 @@ impl WalletClient for LocalWalletClient {
 		}
 		Ok((o.highest_index, o.last_retrieved_index, api_outputs))
 	}
+
+	fn get_header(&self, height: u64) -> Result<BlockHeader, libwallet::Error> {
+		let m = WalletProxyMessage {
+			sender_id: self.id.clone(),
+			dest: self.node_url().to_owned(),
+			method: "get_header".to_owned(),
+			body: height.to_string(),
+		};
+		{
+			let p = self.proxy_tx.lock();
+			p.send(m)
+				.context(libwallet::ErrorKind::ClientCallback("Get header send"))?;
+		}
+		let r = self.rx.lock();
+		let m = r.recv().unwrap();
+		let header_bin = util::from_hex(m.body).unwrap();
+		Ok(ser::deserialize(&mut &header_bin[..]).unwrap())
+	}
+
+	fn get_header_from(
+		&self,
+		node_url: &str,
+		height: Option<u64>,
+	) -> Result<BlockHeader, libwallet::Error> {
+		let m = WalletProxyMessage {
+			sender_id: self.id.clone(),
+			dest: node_url.to_owned(),
+			method: "get_header".to_owned(),
+			body: height.map(|h| h.to_string()).unwrap_or_default(),
+		};
+		{
+			let p = self.proxy_tx.lock();
+			p.send(m)
+				.context(libwallet::ErrorKind::ClientCallback("Get header send"))?;
+		}
+		let r = self.rx.lock();
+		let m = r.recv().unwrap();
+		let header_bin = util::from_hex(m.body).unwrap();
+		Ok(ser::deserialize(&mut &header_bin[..]).unwrap())
+	}
+
+	fn get_output_proofs(
+		&self,
+		wallet_outputs: Vec<pedersen::Commitment>,
+	) -> Result<Vec<OutputProofs>, libwallet::Error> {
+		let query_params: Vec<String> = wallet_outputs
+			.iter()
+			.map(|commit| util::to_hex(commit.as_ref().to_vec()))
+			.collect();
+		let m = WalletProxyMessage {
+			sender_id: self.id.clone(),
+			dest: self.node_url().to_owned(),
+			method: "get_output_proofs".to_owned(),
+			body: query_params.join(","),
+		};
+		{
+			let p = self.proxy_tx.lock();
+			p.send(m).context(libwallet::ErrorKind::ClientCallback(
+				"Get output proofs send",
+			))?;
+		}
+		let r = self.rx.lock();
+		let m = r.recv().unwrap();
+		let res: Vec<api::OutputProofs> = serde_json::from_str(&m.body).unwrap();
+		Ok(res
+			.into_iter()
+			.map(|p| OutputProofs {
+				header_hash: Hash::from_hex(&p.header.hash).unwrap(),
+				header_height: p.header.height,
+				outputs: p
+					.outputs
+					.iter()
+					.map(|o| {
+						(
+							o.commit.commit(),
+							o.height,
+							o.mmr_index,
+							o.merkle_proof().unwrap(),
+						)
+					})
+					.collect(),
+			})
+			.collect())
+	}
 }
@@ -0,0 +1,208 @@
+// Copyright 2018 The Grin Developers
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests encryption of the wallet database at rest
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate rand;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate serde;
+extern crate uuid;
+
+#[allow(dead_code)]
+mod common;
+use common::testclient::LocalWalletClient;
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+
+use core::global;
+use core::global::ChainTypes;
+use keychain::ExtKeychain;
+use wallet::libwallet;
+use wallet::libwallet::types::{Contact, WalletBackend};
+use wallet::lmdb_wallet::DB_DIR;
+use wallet::{LMDBBackend, WalletConfig};
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn setup(test_dir: &str) {
+	util::init_test_logger();
+	clean_output_dir(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+}
+
+fn open_db(
+	dir: &str,
+	passphrase: &str,
+) -> Result<LMDBBackend<LocalWalletClient, ExtKeychain>, libwallet::Error> {
+	let (tx, _) = channel();
+	let config = WalletConfig {
+		data_file_dir: dir.to_owned(),
+		..Default::default()
+	};
+	LMDBBackend::new(config, passphrase, LocalWalletClient::new("wallet1", tx))
+}
+
+/// Everything written to the db files, to check for leaked plaintext
+fn db_contents(dir: &str) -> Vec<u8> {
+	let mut contents = vec![];
+	for entry in fs::read_dir(format!("{}/{}/lmdb", dir, DB_DIR)).unwrap() {
+		let mut f = File::open(entry.unwrap().path()).unwrap();
+		f.read_to_end(&mut contents).unwrap();
+	}
+	contents
+}
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+	haystack
+		.windows(needle.len())
+		.any(|w| w == needle.as_bytes())
+}
+
+/// Contents are only readable with the wallet password
+fn db_encryption_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let dir = format!("{}/wallet1", test_dir);
+	{
+		let mut w = open_db(&dir, "correct horse")?;
+		let mut batch = w.batch()?;
+		batch.save_contact(Contact {
+			name: "alice".to_owned(),
+			method: "http".to_owned(),
+			dest: "http://alice.example:13415".to_owned(),
+		})?;
+		batch.save_contact(Contact {
+			name: "carol".to_owned(),
+			method: "file".to_owned(),
+			dest: "carol.tx".to_owned(),
+		})?;
+		batch.commit()?;
+	}
+
+	let contents = db_contents(&dir);
+	assert!(!contains(&contents, "alice"));
+	assert!(!contains(&contents, "default"));
+
+	let config = WalletConfig {
+		data_file_dir: dir.clone(),
+		..Default::default()
+	};
+	assert!(wallet::wallet_db_locked(&config)?);
+	assert!(open_db(&dir, "wrong horse").is_err());
+	let w = open_db(&dir, "correct horse")?;
+	let contact = w.get_contact("alice")?.unwrap();
+	assert_eq!(contact.dest, "http://alice.example:13415");
+	assert_eq!(w.contact_iter()?.count(), 2);
+	assert_eq!(w.acct_path_iter()?.count(), 1);
+	drop(w);
+
+	// nor can a value be moved under the key of another record
+	{
+		let env = Arc::new(store::new_env(format!("{}/{}", dir, DB_DIR)));
+		let db = store::Store::open(env, DB_DIR);
+		let contacts = db.raw_entries(&['b' as u8])?;
+		assert_eq!(contacts.len(), 2);
+		let batch = db.batch()?;
+		batch.put(&contacts[0].0, contacts[1].1.clone())?;
+		batch.commit()?;
+	}
+	let w = open_db(&dir, "correct horse")?;
+	let readable = ["alice", "carol"]
+		.iter()
+		.filter(|name| w.get_contact(name).is_ok())
+		.count();
+	assert_eq!(readable, 1);
+	drop(w);
+
+	// a record that doesn't decrypt fails the iteration, rather than being
+	// skipped
+	{
+		let env = Arc::new(store::new_env(format!("{}/{}", dir, DB_DIR)));
+		let db = store::Store::open(env, DB_DIR);
+		let batch = db.batch()?;
+		batch.put(&['b' as u8, 0, 1, 2], vec![0; 40])?;
+		batch.commit()?;
+	}
+	let w = open_db(&dir, "correct horse")?;
+	assert!(w.contact_iter().is_err());
+	Ok(())
+}
+
+/// A db written before encryption can't be opened until it's explicitly
+/// encrypted in place
+fn db_encryption_migration_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let dir = format!("{}/wallet1", test_dir);
+	{
+		let db_path = format!("{}/{}", dir, DB_DIR);
+		fs::create_dir_all(&db_path).unwrap();
+		let env = Arc::new(store::new_env(db_path));
+		let db = store::Store::open(env, DB_DIR);
+		let batch = db.batch()?;
+		let contact = Contact {
+			name: "bob".to_owned(),
+			method: "file".to_owned(),
+			dest: "bob.tx".to_owned(),
+		};
+		batch.put_ser(
+			&store::to_key('b' as u8, &mut contact.name.as_bytes().to_vec()),
+			&contact,
+		)?;
+		batch.commit()?;
+	}
+	assert!(contains(&db_contents(&dir), "bob.tx"));
+	let config = WalletConfig {
+		data_file_dir: dir.clone(),
+		..Default::default()
+	};
+	assert!(wallet::wallet_db_plaintext(&config)?);
+	assert!(open_db(&dir, "").is_err());
+
+	wallet::encrypt_wallet_db(&config, "")?;
+	assert!(!wallet::wallet_db_plaintext(&config)?);
+	assert!(!wallet::wallet_db_locked(&config)?);
+	{
+		let w = open_db(&dir, "")?;
+		assert_eq!(w.get_contact("bob")?.unwrap().dest, "bob.tx");
+	}
+	let w = open_db(&dir, "")?;
+	assert_eq!(w.contact_iter()?.count(), 1);
+	Ok(())
+}
+
+#[test]
+fn db_encryption() {
+	let test_dir = "test_output/db_encryption";
+	if let Err(e) = db_encryption_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
+
+#[test]
+fn db_encryption_migration() {
+	let test_dir = "test_output/db_encryption_migration";
+	if let Err(e) = db_encryption_migration_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
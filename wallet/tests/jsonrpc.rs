@@ -0,0 +1,197 @@
+// Copyright 2018 The Grin Developers
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests for the JSON-RPC owner and foreign APIs
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate rand;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate serde;
+extern crate serde_json;
+extern crate uuid;
+
+#[allow(dead_code)]
+mod common;
+use common::testclient::{LocalWalletClient, WalletProxy};
+
+use serde_json::Value;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use core::global;
+use core::global::ChainTypes;
+use keychain::ExtKeychain;
+use wallet::libwallet;
+use wallet::libwallet::api::{APIForeign, APIOwner};
+use wallet::libwallet::jsonrpc;
+use wallet::libwallet::types::WalletInst;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn setup(test_dir: &str) {
+	util::init_test_logger();
+	clean_output_dir(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+}
+
+type Owner = APIOwner<WalletInst<LocalWalletClient, ExtKeychain>, LocalWalletClient, ExtKeychain>;
+
+fn owner_rpc(api: &mut Owner, body: &str) -> Option<Value> {
+	jsonrpc::handle(body.as_bytes(), jsonrpc::OWNER_METHODS, |method, params| {
+		jsonrpc::owner_call(api, method, params)
+	}).map(|res| serde_json::from_str(&res).unwrap())
+}
+
+/// Calls through the JSON-RPC interfaces, single and batched
+fn jsonrpc_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 3);
+
+	let mut api = APIOwner::new(wallet1.clone());
+
+	// positional params
+	let res = owner_rpc(
+		&mut api,
+		r#"{"jsonrpc": "2.0", "method": "retrieve_summary_info", "params": [true], "id": 1}"#,
+	).unwrap();
+	assert_eq!(res["id"], 1);
+	assert_eq!(res["result"]["refreshed"], true);
+	assert_eq!(
+		res["result"]["data"]["amount_currently_spendable"],
+		reward * 3
+	);
+
+	// a batch, mixing failures and a notification
+	let batch = format!(
+		r#"[
+			{{"jsonrpc": "2.0", "method": "issue_send_tx", "params": {{"amount": {}, "dest": "wallet2"}}, "id": "send"}},
+			{{"jsonrpc": "2.0", "method": "issue_send_tx", "params": {{"amount": {}, "dest": "wallet2"}}, "id": "too much"}},
+			{{"jsonrpc": "2.0", "method": "cancel_tx", "params": {{"tx_id": "one"}}, "id": 3}},
+			{{"jsonrpc": "2.0", "method": "steal", "id": 4}},
+			{{"jsonrpc": "1.0", "method": "node_height", "id": 5}},
+			{{"jsonrpc": "2.0", "method": "node_height"}}
+		]"#,
+		reward / 2,
+		reward * 10
+	);
+	let res = owner_rpc(&mut api, &batch).unwrap();
+	let res = res.as_array().unwrap();
+	assert_eq!(res.len(), 5);
+	assert_eq!(res[0]["id"], "send");
+	let slate = res[0]["result"].clone();
+	assert_eq!(slate["amount"], reward / 2);
+	assert_eq!(res[1]["error"]["code"], jsonrpc::NOT_ENOUGH_FUNDS);
+	assert!(res[1]["error"]["data"]["needed"].as_u64().unwrap() > reward * 10);
+	assert_eq!(res[2]["error"]["code"], jsonrpc::INVALID_PARAMS);
+	assert_eq!(res[3]["error"]["code"], jsonrpc::METHOD_NOT_FOUND);
+	assert_eq!(res[4]["error"]["code"], jsonrpc::INVALID_REQUEST);
+	assert_eq!(res[4]["id"], 5);
+
+	// notifications only don't get an answer, garbage does
+	assert!(
+		owner_rpc(&mut api, r#"{"jsonrpc": "2.0", "method": "accounts"}"#).is_none()
+	);
+	let res = owner_rpc(&mut api, "{not json").unwrap();
+	assert_eq!(res["error"]["code"], jsonrpc::PARSE_ERROR);
+	assert_eq!(res["id"], Value::Null);
+	let res = owner_rpc(&mut api, "[]").unwrap();
+	assert_eq!(res["error"]["code"], jsonrpc::INVALID_REQUEST);
+
+	let post = format!(
+		r#"{{"jsonrpc": "2.0", "method": "post_tx", "params": {{"slate": {}}}, "id": 6}}"#,
+		slate
+	);
+	let res = owner_rpc(&mut api, &post).unwrap();
+	assert_eq!(res["result"], Value::Null);
+	assert!(res.get("error").is_none());
+
+	let res = owner_rpc(
+		&mut api,
+		r#"{"jsonrpc": "2.0", "method": "cancel_tx", "params": [999], "id": 7}"#,
+	).unwrap();
+	assert_eq!(res["error"]["code"], jsonrpc::TX_NOT_FOUND);
+	assert_eq!(res["error"]["data"], 999);
+
+	// the foreign API only offers its own methods
+	let mut foreign = APIForeign::new(wallet2.clone());
+	let foreign_rpc = |foreign: &mut APIForeign<_, _, _>, body: &str| -> Value {
+		let res = jsonrpc::handle(body.as_bytes(), jsonrpc::FOREIGN_METHODS, |method, params| {
+			jsonrpc::foreign_call(foreign, method, params)
+		});
+		serde_json::from_str(&res.unwrap()).unwrap()
+	};
+	let res = foreign_rpc(
+		&mut foreign,
+		r#"{"jsonrpc": "2.0", "method": "retrieve_txs", "id": 1}"#,
+	);
+	assert_eq!(res["error"]["code"], jsonrpc::METHOD_NOT_FOUND);
+	let res = foreign_rpc(
+		&mut foreign,
+		r#"{"jsonrpc": "2.0", "method": "build_coinbase", "params": {"block_fees": {"fees": 0, "height": 10, "key_id": null}}, "id": 2}"#,
+	);
+	assert!(res["result"]["kernel"].is_string());
+	let res = foreign_rpc(
+		&mut foreign,
+		r#"{"jsonrpc": "2.0", "method": "check_version", "id": 3}"#,
+	);
+	assert_eq!(res["result"]["supported_slate_versions"][0], 0);
+	assert_eq!(res["result"]["receive_status"], "Ready");
+
+	let mut api = APIOwner::new(wallet2.clone());
+	let res = owner_rpc(
+		&mut api,
+		r#"{"jsonrpc": "2.0", "method": "retrieve_summary_info", "params": {"refresh_from_node": true}, "id": 1}"#,
+	).unwrap();
+	assert_eq!(res["result"]["data"]["amount_currently_spendable"], reward / 2);
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+#[test]
+fn jsonrpc() {
+	let test_dir = "test_output/jsonrpc";
+	if let Err(e) = jsonrpc_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
@@ -0,0 +1,107 @@
+// Copyright 2018 The Grin Developers
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests the per wallet key derivation scheme
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+
+#[allow(dead_code)]
+mod common;
+use common::testclient::LocalWalletClient;
+
+use std::fs;
+use std::sync::mpsc::channel;
+
+use core::global;
+use core::global::ChainTypes;
+use keychain::{ExtKeychain, KeyDerivation, Keychain};
+use wallet::libwallet;
+use wallet::libwallet::types::{OutputData, OutputStatus, WalletBackend};
+use wallet::{LMDBBackend, WalletConfig, WalletSeed};
+
+fn setup(test_dir: &str) {
+	util::init_test_logger();
+	let _ = fs::remove_dir_all(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+}
+
+fn key_derivation_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let config = WalletConfig {
+		data_file_dir: format!("{}/wallet1", test_dir),
+		..Default::default()
+	};
+	WalletSeed::init_file(&config).unwrap();
+	let (tx, _) = channel();
+	let mut w: LMDBBackend<LocalWalletClient, ExtKeychain> =
+		LMDBBackend::new(config.clone(), "", LocalWalletClient::new("wallet1", tx))?;
+
+	// wallets default to the original scheme
+	assert_eq!(w.key_derivation()?, KeyDerivation::Legacy);
+	w.set_key_derivation(KeyDerivation::Bip44)?;
+	assert_eq!(w.key_derivation()?, KeyDerivation::Bip44);
+	w.open_with_credentials()?;
+	assert_eq!(w.keychain().derivation(), KeyDerivation::Bip44);
+
+	// the keys of any BIP32 wallet given the seed
+	let seed = util::from_hex(WalletSeed::from_file(&config).unwrap().to_hex()).unwrap();
+	let standard = ExtKeychain::from_seed_with(&seed, KeyDerivation::Bip44).unwrap();
+	let id = ExtKeychain::derive_key_id(3, 0, 0, 5, 0);
+	assert_eq!(w.keychain().commit(0, &id)?, standard.commit(0, &id)?);
+
+	// no switching while holding outputs
+	let parent_key_id = ExtKeychain::derive_key_id(2, 0, 0, 0, 0);
+	let key_id = w.next_child()?;
+	let mut output = OutputData {
+		root_key_id: parent_key_id,
+		key_id: key_id.clone(),
+		n_child: key_id.to_path().last_path_index(),
+		value: 1,
+		status: OutputStatus::Unspent,
+		height: 1,
+		lock_height: 0,
+		is_coinbase: false,
+		tx_log_entry: None,
+	};
+	{
+		let mut batch = w.batch()?;
+		batch.save(output.clone())?;
+		batch.commit()?;
+	}
+	assert!(w.set_key_derivation(KeyDerivation::Legacy).is_err());
+
+	output.status = OutputStatus::Spent;
+	{
+		let mut batch = w.batch()?;
+		batch.save(output)?;
+		batch.commit()?;
+	}
+	w.set_key_derivation(KeyDerivation::Legacy)?;
+	assert_eq!(w.key_derivation()?, KeyDerivation::Legacy);
+	Ok(())
+}
+
+#[test]
+fn key_derivation() {
+	let test_dir = "test_output/key_derivation";
+	if let Err(e) = key_derivation_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
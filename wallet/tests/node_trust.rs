@@ -0,0 +1,266 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests refreshing a wallet verifying what the node reports
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate rand;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate serde;
+extern crate uuid;
+
+mod common;
+use common::testclient::{LocalWalletClient, WalletProxy};
+
+use std::fs;
+use std::ops::Range;
+use std::thread;
+use std::time::Duration;
+
+use chain::Chain;
+
+use core::core::hash::Hashed;
+use core::global;
+use core::global::ChainTypes;
+use keychain::ExtKeychain;
+use wallet::libwallet;
+use wallet::libwallet::types::NodeTrust;
+use wallet::WalletConfig;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn setup(test_dir: &str) {
+	util::init_test_logger();
+	clean_output_dir(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+}
+
+fn copy_blocks(from: &Chain, to: &Chain, heights: Range<u64>) {
+	for height in heights {
+		let header = from.get_header_by_height(height).unwrap();
+		let block = from.get_block(&header.hash()).unwrap();
+		to.process_block(block, chain::Options::NONE).unwrap();
+	}
+}
+
+/// Outputs checked against merkle proofs and the tracked headers
+fn node_trust_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	// Create a new proxy to simulate server and wallet responses
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let config = WalletConfig {
+		data_file_dir: format!("{}/wallet1", test_dir),
+		node_trust: NodeTrust::Verified,
+		..Default::default()
+	};
+	let wallet1 = common::create_wallet_with_config(config, client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+
+	// headers are tracked from genesis on the first refresh
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 4);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (refreshed, info) = api.retrieve_summary_info(true)?;
+		assert!(refreshed);
+		assert_eq!(info.total, reward * 4);
+		Ok(())
+	})?;
+	{
+		let w = wallet1.lock();
+		let head = chain.head_header().unwrap();
+		assert_eq!(w.last_tracked_header()?.unwrap().hash(), head.hash());
+		let genesis = chain.get_header_by_height(0).unwrap();
+		assert_eq!(w.tracked_header(0)?.unwrap().hash(), genesis.hash());
+	}
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 2);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (refreshed, info) = api.retrieve_summary_info(true)?;
+		assert!(refreshed);
+		assert_eq!(info.total, reward * 6);
+		Ok(())
+	})?;
+
+	// a tracked header the node's chain doesn't have is replaced by the
+	// node's, once it checks out
+	{
+		let mut w = wallet1.lock();
+		for height in 4..7 {
+			let header = chain.get_header_by_height(height).unwrap();
+			assert_eq!(w.tracked_header(height)?.unwrap().hash(), header.hash());
+		}
+		let mut header = chain.head_header().unwrap();
+		header.pow.proof.nonces[0] += 1;
+		let mut batch = w.batch()?;
+		batch.save_tracked_header(&header)?;
+		batch.commit()?;
+	}
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (refreshed, info) = api.retrieve_summary_info(true)?;
+		assert!(refreshed);
+		assert_eq!(info.total, reward * 6);
+		Ok(())
+	})?;
+	{
+		let w = wallet1.lock();
+		let head = chain.head_header().unwrap();
+		assert_eq!(w.tracked_header(6)?.unwrap().hash(), head.hash());
+	}
+
+	// but not when forking off before the headers tracked
+	{
+		let mut w = wallet1.lock();
+		let mut batch = w.batch()?;
+		for height in 0..4 {
+			batch.delete_tracked_header(height)?;
+		}
+		for height in 4..7 {
+			let mut header = chain.get_header_by_height(height).unwrap();
+			header.pow.proof.nonces[0] += 1;
+			batch.save_tracked_header(&header)?;
+		}
+		batch.commit()?;
+	}
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (refreshed, _) = api.retrieve_summary_info(true)?;
+		assert!(!refreshed);
+		Ok(())
+	})?;
+
+	// starting from a checkpoint the node's chain has, or not
+	let genesis = chain.get_header_by_height(0).unwrap();
+	let header = chain.get_header_by_height(2).unwrap();
+	let checkpoints = vec![
+		(format!("2:{}", header.hash().to_hex()), true),
+		(format!("2:{}", genesis.hash().to_hex()), false),
+		(format!("9:{}", header.hash().to_hex()), false),
+	];
+	for (i, (checkpoint, valid)) in checkpoints.into_iter().enumerate() {
+		let config = WalletConfig {
+			data_file_dir: format!("{}/checkpoint{}", test_dir, i),
+			node_trust: NodeTrust::Verified,
+			header_checkpoint: Some(checkpoint),
+			..Default::default()
+		};
+		let wallet = common::create_wallet_with_config::<_, ExtKeychain>(config, client.clone());
+		wallet::controller::owner_single_use(wallet.clone(), |api| {
+			let (refreshed, _) = api.retrieve_summary_info(true)?;
+			assert_eq!(refreshed, valid);
+			Ok(())
+		})?;
+		let w = wallet.lock();
+		assert_eq!(w.tracked_header(2)?.is_some(), valid);
+	}
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Tracked headers checked against the chains of other nodes
+fn header_nodes_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+	let lagging = wallet_proxy.add_node("lagging");
+	let ahead = wallet_proxy.add_node("ahead");
+	let forked = wallet_proxy.add_node("forked");
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 =
+		common::create_wallet::<_, ExtKeychain>(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	// the node at 8, another at 4 on the same chain, one ahead on it and
+	// one forked off at 2 with more work
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 8);
+	copy_blocks(&chain, &lagging, 1..5);
+	copy_blocks(&chain, &ahead, 1..9);
+	let _ = common::award_blocks_to_wallet(&ahead, wallet1.clone(), 10);
+	copy_blocks(&chain, &forked, 1..3);
+	let _ = common::award_blocks_to_wallet(&forked, wallet1.clone(), 10);
+
+	let refresh = |node: &str, dir: &str| -> Result<bool, libwallet::Error> {
+		let config = WalletConfig {
+			data_file_dir: format!("{}/{}", test_dir, dir),
+			node_trust: NodeTrust::Verified,
+			header_nodes: vec![node.to_owned()],
+			..Default::default()
+		};
+		let wallet = common::create_wallet_with_config::<_, ExtKeychain>(config, client.clone());
+		let mut refreshed = false;
+		wallet::controller::owner_single_use(wallet.clone(), |api| {
+			refreshed = api.retrieve_summary_info(true)?.0;
+			Ok(())
+		})?;
+		Ok(refreshed)
+	};
+	assert!(refresh("lagging", "lagging")?);
+	assert!(refresh("ahead", "ahead")?);
+	assert!(!refresh("forked", "forked")?);
+
+	// once the node reorgs onto the fork, the nodes left on the other chain
+	// are the ones disagreeing
+	copy_blocks(&forked, &chain, 3..13);
+	assert_eq!(
+		chain.head_header().unwrap().hash(),
+		forked.head_header().unwrap().hash()
+	);
+	assert!(!refresh("lagging", "lagging")?);
+	assert!(!refresh("ahead", "ahead")?);
+	assert!(refresh("forked", "forked")?);
+
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+#[test]
+fn header_nodes() {
+	let test_dir = "test_output/header_nodes";
+	if let Err(e) = header_nodes_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
+
+#[test]
+fn node_trust() {
+	let test_dir = "test_output/node_trust";
+	if let Err(e) = node_trust_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
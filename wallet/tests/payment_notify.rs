@@ -0,0 +1,126 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests notifying about the payments a watched wallet receives
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate rand;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate serde;
+extern crate uuid;
+
+mod common;
+use common::testclient::{LocalWalletClient, WalletProxy};
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use core::global;
+use core::global::ChainTypes;
+use keychain::ExtKeychain;
+use wallet::libtx::slate::Slate;
+use wallet::libwallet;
+use wallet::watcher::{check_payments, Notifier};
+use wallet::WalletConfig;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn setup(test_dir: &str) {
+	util::init_test_logger();
+	clean_output_dir(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+}
+
+fn notifier(command: &str) -> Notifier {
+	Notifier::new(&WalletConfig {
+		notify_command: Some(command.to_owned()),
+		..Default::default()
+	})
+}
+
+/// A payment is notified about once confirmed, and only once the hook went
+/// through
+fn payment_notify_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 10);
+
+	let amount = 60_000_000_000;
+	let mut slate = Slate::blank(1);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		slate = api.issue_send_tx(amount, 2, "wallet2", 500, 1, true)?;
+		Ok(())
+	})?;
+
+	// nothing to notify until confirmed
+	let payments = format!("{}/payments", test_dir);
+	let record = notifier(&format!(
+		"echo $GRIN_TX_SLATE_ID $GRIN_AMOUNT >> {}",
+		payments
+	));
+	assert_eq!(check_payments(wallet2.clone(), &record)?, 0);
+
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		api.post_tx(&slate, false)?;
+		Ok(())
+	})?;
+
+	// a failing hook leaves the payment to notify about again
+	assert!(check_payments(wallet2.clone(), &notifier("exit 1")).is_err());
+
+	assert_eq!(check_payments(wallet2.clone(), &record)?, 1);
+	assert_eq!(check_payments(wallet2.clone(), &record)?, 0);
+	let content = fs::read_to_string(&payments).unwrap();
+	assert_eq!(content, format!("{} {}\n", slate.id, amount));
+
+	// the sender isn't notified about anything
+	assert_eq!(check_payments(wallet1.clone(), &record)?, 0);
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+#[test]
+fn payment_notify() {
+	let test_dir = "test_output/payment_notify";
+	if let Err(e) = payment_notify_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
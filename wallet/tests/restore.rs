@@ -29,6 +29,7 @@ mod common;
 use common::testclient::{LocalWalletClient, WalletProxy};
 
 use std::fs;
+use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
@@ -37,7 +38,10 @@ use core::global::ChainTypes;
 use keychain::{ExtKeychain, Identifier, Keychain};
 use wallet::libtx::slate::Slate;
 use wallet::libwallet;
-use wallet::libwallet::types::AcctPathMapping;
+use wallet::libwallet::types::{
+	AcctPathMapping, CheckFixes, OutputData, OutputStatus, ScanProgress, TxLogEntryType,
+	WalletClient,
+};
 
 fn clean_output_dir(test_dir: &str) {
 	let _ = fs::remove_dir_all(test_dir);
@@ -71,12 +75,69 @@ fn restore_wallet(base_dir: &str, wallet_dir: &str) -> Result<(), libwallet::Err
 	});
 
 	// perform the restore and update wallet info
+	let (tx, rx) = mpsc::channel();
 	wallet::controller::owner_single_use(wallet.clone(), |api| {
-		let _ = api.restore()?;
+		api.restore_with_progress(tx)?;
 		let _ = api.retrieve_summary_info(true)?;
 		Ok(())
 	})?;
 
+	// should have been told about every chunk, finishing with the whole set
+	let progress: Vec<ScanProgress> = rx.try_iter().collect();
+	assert!(progress.len() >= 2);
+	let last = progress.last().unwrap();
+	assert!(last.complete);
+	assert_eq!(last.percentage_complete(), 100);
+	assert_eq!(last.outputs_scanned, last.outputs_total);
+
+	Ok(())
+}
+
+/// Simulate a restore that was interrupted after scanning the whole output
+/// set, and check that resuming it doesn't scan anything again
+fn resume_restore_wallet(base_dir: &str, wallet_dir: &str) -> Result<(), libwallet::Error> {
+	let source_seed = format!("{}/{}/wallet.seed", base_dir, wallet_dir);
+	let dest_dir = format!("{}/{}_resume", base_dir, wallet_dir);
+	fs::create_dir_all(dest_dir.clone())?;
+	let dest_seed = format!("{}/wallet.seed", dest_dir);
+	fs::copy(source_seed, dest_seed)?;
+
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(base_dir);
+	let client = LocalWalletClient::new(wallet_dir, wallet_proxy.tx.clone());
+
+	let wallet = common::create_wallet(&dest_dir, client.clone());
+
+	wallet_proxy.add_wallet(wallet_dir, client.get_send_instance(), wallet.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	// pretend a previous restore got as far as the last output
+	{
+		let mut w = wallet.lock();
+		let (highest_index, _, _) = w.client().get_outputs_by_pmmr_index(1, 1)?;
+		let mut batch = w.batch()?;
+		batch.save_restore_checkpoint(highest_index)?;
+		batch.commit()?;
+	}
+
+	wallet::controller::owner_single_use(wallet.clone(), |api| {
+		api.restore()?;
+		let (_, outputs) = api.retrieve_outputs(true, false, None)?;
+		assert!(outputs.is_empty());
+		Ok(())
+	})?;
+
+	// checkpoint should be gone once the restore completes
+	{
+		let w = wallet.lock();
+		assert_eq!(w.restore_checkpoint()?, None);
+	}
+
 	Ok(())
 }
 
@@ -351,9 +412,141 @@ fn perform_restore(test_dir: &str) -> Result<(), libwallet::Error> {
 		"wallet3",
 		&ExtKeychain::derive_key_id(2, 0, 0, 0, 0),
 	)?;
+	resume_restore_wallet(test_dir, "wallet1")?;
 	Ok(())
 }
 
+/// A wallet out of step with the chain, checked then repaired
+fn check_repair_test_impl(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 3);
+
+	// a send that's confirmed on chain, without the wallet having refreshed
+	let mut slate = Slate::blank(1);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		slate = api.issue_send_tx(reward / 2, 1, "wallet2", 500, 1, false)?;
+		api.post_tx(&slate, false)?;
+		Ok(())
+	})?;
+
+	// lose an unspent output, make one up and rewind the derivation index
+	let parent_key_id = ExtKeychain::derive_key_id(2, 0, 0, 0, 0);
+	let (lost, made_up) = {
+		let mut w = wallet1.lock();
+		w.open_with_credentials()?;
+		let lost = w
+			.iter()?
+			.find(|o| o.status == OutputStatus::Unspent)
+			.unwrap();
+		let key_id = w.next_child()?;
+		let made_up = OutputData {
+			root_key_id: parent_key_id.clone(),
+			key_id: key_id.clone(),
+			n_child: key_id.to_path().last_path_index(),
+			value: reward,
+			status: OutputStatus::Unspent,
+			height: 1,
+			lock_height: 0,
+			is_coinbase: false,
+			tx_log_entry: None,
+		};
+		let mut batch = w.batch()?;
+		batch.delete(&lost.key_id)?;
+		batch.save(made_up.clone())?;
+		batch.save_child_index(&parent_key_id, 1)?;
+		batch.commit()?;
+		(lost, made_up)
+	};
+
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		// nothing repaired unless asked
+		for _ in 0..2 {
+			let report = api.check_repair(CheckFixes::default())?;
+			assert_eq!(report.missing_from_wallet.len(), 1);
+			assert_eq!(report.missing_from_wallet[0].key_id, lost.key_id);
+			assert_eq!(report.missing_on_chain.len(), 1);
+			assert_eq!(report.missing_on_chain[0].key_id, made_up.key_id);
+			assert!(!report.confirmed_locked.is_empty());
+			assert_eq!(report.duplicate_indices.len(), 1);
+			assert_eq!(report.duplicate_indices[0].child_index, 1);
+			assert!(!report.is_clean());
+		}
+
+		// each category is repaired on its own
+		let fixes = CheckFixes {
+			missing_on_chain: true,
+			..Default::default()
+		};
+		let report = api.check_repair(fixes)?;
+		assert!(report.fixed.missing_on_chain);
+		let report = api.check_repair(CheckFixes::default())?;
+		assert!(report.missing_on_chain.is_empty());
+		assert_eq!(report.missing_from_wallet.len(), 1);
+
+		let report = api.check_repair(CheckFixes::all())?;
+		assert!(!report.is_clean());
+		let report = api.check_repair(CheckFixes::default())?;
+		assert!(report.is_clean());
+
+		// the scan is reported on as it goes, as for a restore
+		let (tx, rx) = mpsc::channel();
+		api.check_repair_with_progress(CheckFixes::default(), tx)?;
+		let progress: Vec<ScanProgress> = rx.try_iter().collect();
+		assert!(progress.len() > 1);
+		let last = progress.last().unwrap();
+		assert!(last.complete);
+		assert_eq!(last.outputs_scanned, last.outputs_total);
+
+		let (_, txs) = api.retrieve_txs(false, None)?;
+		let tx = txs
+			.iter()
+			.find(|t| t.tx_slate_id == Some(slate.id))
+			.unwrap();
+		assert_eq!(tx.tx_type, TxLogEntryType::TxSent);
+		assert!(tx.confirmed);
+		let (_, outputs) = api.retrieve_outputs(false, false, None)?;
+		assert!(outputs.iter().any(|(o, _)| o.key_id == lost.key_id));
+		Ok(())
+	})?;
+
+	// new outputs don't reuse keys of old ones
+	{
+		let mut w = wallet1.lock();
+		let key_id = w.next_child()?;
+		assert!(w.get(&key_id).is_err());
+	}
+
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+#[test]
+fn wallet_check_repair() {
+	let test_dir = "test_output/wallet_check_repair";
+	if let Err(e) = check_repair_test_impl(test_dir) {
+		panic!("Libwallet Error: {} - {}", e, e.backtrace().unwrap());
+	}
+}
+
 #[test]
 fn wallet_restore() {
 	let test_dir = "test_output/wallet_restore";
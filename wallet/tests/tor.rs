@@ -0,0 +1,124 @@
+// Copyright 2018 The Grin Developers
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests for sending slates to onion addresses
+extern crate grin_wallet as wallet;
+extern crate serde_json;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use wallet::adapters::{is_onion_address, SlateAdapters, TorSlateAdapter};
+use wallet::libtx::slate::Slate;
+use wallet::SlateAdapter;
+
+const ONION: &'static str = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+
+/// Acts as tor's SOCKS proxy and the wallet listening behind it, answering
+/// with the slate posted, its amount doubled. Returns the host asked for.
+fn fake_tor(listener: TcpListener) -> thread::JoinHandle<(String, u16)> {
+	thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut greeting = [0u8; 3];
+		stream.read_exact(&mut greeting).unwrap();
+		assert_eq!(greeting, [5, 1, 0]);
+		stream.write_all(&[5, 0]).unwrap();
+
+		let mut req = [0u8; 5];
+		stream.read_exact(&mut req).unwrap();
+		assert_eq!(req[..4], [5, 1, 0, 3]);
+		let mut host = vec![0u8; req[4] as usize];
+		stream.read_exact(&mut host).unwrap();
+		let mut port = [0u8; 2];
+		stream.read_exact(&mut port).unwrap();
+		stream
+			.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80])
+			.unwrap();
+
+		let mut reader = BufReader::new(stream.try_clone().unwrap());
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		assert!(line.starts_with("POST /v1/wallet/foreign/receive_tx "));
+		let mut len = 0;
+		loop {
+			let mut line = String::new();
+			reader.read_line(&mut line).unwrap();
+			if line.trim().is_empty() {
+				break;
+			}
+			if line.to_lowercase().starts_with("content-length:") {
+				len = line[15..].trim().parse().unwrap();
+			}
+		}
+		let mut body = vec![0u8; len];
+		reader.read_exact(&mut body).unwrap();
+		let mut slate: Slate = serde_json::from_slice(&body).unwrap();
+		slate.amount *= 2;
+		let res = serde_json::to_string(&slate).unwrap();
+		write!(
+			stream,
+			"HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+			res.len(),
+			res
+		).unwrap();
+		(
+			String::from_utf8(host).unwrap(),
+			((port[0] as u16) << 8) + port[1] as u16,
+		)
+	})
+}
+
+/// To tell which adapter is which
+fn addr(adapter: &SlateAdapter) -> *const u8 {
+	adapter as *const SlateAdapter as *const u8
+}
+
+#[test]
+fn onion_addresses() {
+	assert!(is_onion_address(ONION));
+	assert!(is_onion_address(&format!("http://{}", ONION)));
+	assert!(is_onion_address(&format!("http://{}:8080/", ONION)));
+	assert!(!is_onion_address("http://127.0.0.1:13415"));
+	assert!(!is_onion_address("tx.onion.json"));
+
+	// onion destinations go through tor, whatever their scheme
+	let adapters = SlateAdapters::default();
+	let tor = addr(adapters.get("tor").unwrap());
+	let http = addr(adapters.get("http").unwrap());
+	let for_dest = |dest: &str| addr(adapters.for_dest(dest, "http").unwrap());
+	assert_eq!(for_dest(ONION), tor);
+	assert_eq!(for_dest(&format!("http://{}", ONION)), tor);
+	assert_eq!(for_dest("http://127.0.0.1:13415"), http);
+}
+
+#[test]
+fn tor_send() {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let proxy = listener.local_addr().unwrap().to_string();
+	let handle = fake_tor(listener);
+
+	let adapter = TorSlateAdapter::new(&proxy);
+	let mut slate = Slate::blank(2);
+	slate.amount = 21;
+	let res = adapter
+		.send_tx_sync(&format!("http://{}:8080", ONION.to_uppercase()), &slate)
+		.unwrap();
+	assert_eq!(res.id, slate.id);
+	assert_eq!(res.amount, 42);
+	assert_eq!(handle.join().unwrap(), (ONION.to_owned(), 8080));
+
+	// only v3 addresses, and only through a proxy that's there
+	assert!(adapter.send_tx_sync("http://expyuzz4wqqyqhjn.onion", &slate).is_err());
+	assert!(adapter.send_tx_sync(ONION, &slate).is_err());
+}
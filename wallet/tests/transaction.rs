@@ -23,21 +23,31 @@ extern crate rand;
 extern crate log;
 extern crate chrono;
 extern crate serde;
+extern crate serde_json;
 extern crate uuid;
 
 mod common;
 use common::testclient::{LocalWalletClient, WalletProxy};
 
+use chrono::prelude::*;
 use std::fs;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+use core::core::hash::Hashed;
 use core::global;
 use core::global::ChainTypes;
 use keychain::ExtKeychain;
+use util::Mutex;
+use wallet::adapters::{FileSlateAdapter, SlateAdapters};
+use wallet::libtx::armor;
 use wallet::libtx::slate::Slate;
 use wallet::libwallet;
-use wallet::libwallet::types::OutputStatus;
+use wallet::libwallet::internal::{selection, tx};
+use wallet::libwallet::types::{
+	OutputLock, OutputStatus, SlateAdapter, TxLogEntryType, WalletInst,
+};
 
 fn clean_output_dir(test_dir: &str) {
 	let _ = fs::remove_dir_all(test_dir);
@@ -95,6 +105,14 @@ fn basic_transaction_api(test_dir: &str) -> Result<(), libwallet::Error> {
 			(wallet1_info.last_confirmed_height - cm) * reward
 		);
 		assert_eq!(wallet1_info.amount_immature, cm * reward);
+		assert_eq!(wallet1_info.amount_unconfirmed_change, 0);
+
+		// each immature coinbase, the next to mature first
+		let coinbases = &wallet1_info.immature_coinbases;
+		assert_eq!(coinbases.len() as u64, cm);
+		assert_eq!(coinbases[0].blocks_to_maturity, 1);
+		assert_eq!(coinbases[0].lock_height, coinbases[0].height + cm);
+		assert_eq!(coinbases.iter().map(|c| c.value).sum::<u64>(), cm * reward);
 		Ok(())
 	})?;
 
@@ -102,7 +120,18 @@ fn basic_transaction_api(test_dir: &str) -> Result<(), libwallet::Error> {
 	// and a single use api for a send command
 	let amount = 60_000_000_000;
 	let mut slate = Slate::blank(1);
+	let mut estimate = None;
 	wallet::controller::owner_single_use(wallet1.clone(), |sender_api| {
+		// estimate first, which shouldn't change anything in the wallet
+		let (_, info_before) = sender_api.retrieve_summary_info(true)?;
+		let est = sender_api.estimate_send_tx(amount, 2, 500, 1, true)?;
+		let (_, info_after) = sender_api.retrieve_summary_info(true)?;
+		assert_eq!(info_before, info_after);
+		let inputs_total: u64 = est.inputs.iter().map(|o| o.value).sum();
+		assert_eq!(inputs_total, amount + est.fee + est.change);
+		assert_eq!(est.total_after, info_before.total - amount - est.fee);
+		estimate = Some(est);
+
 		// note this will increment the block count as part of the transaction "Posting"
 		slate = sender_api.issue_send_tx(
 			amount,    // amount
@@ -134,6 +163,34 @@ fn basic_transaction_api(test_dir: &str) -> Result<(), libwallet::Error> {
 		assert!(tx.confirmation_ts.is_none());
 		assert_eq!(tx.amount_debited - tx.amount_credited, fee + amount);
 		assert_eq!(Some(fee), tx.fee);
+		assert_eq!(Some(estimate.as_ref().unwrap().fee), tx.fee);
+
+		// the change is awaiting confirmation, the inputs locked
+		assert_eq!(
+			wallet1_info.amount_unconfirmed_change,
+			estimate.as_ref().unwrap().change
+		);
+		assert_eq!(
+			wallet1_info.amount_awaiting_confirmation,
+			wallet1_info.amount_unconfirmed_change
+		);
+
+		// and it should be exported with its kernel
+		let (_, export) = api.export_txs(false, None, None)?;
+		assert_eq!(export.len(), txs.len());
+		let e = export.iter().find(|e| e.id == tx.id).unwrap();
+		assert_eq!(e.account, "default");
+		assert_eq!(e.fee, Some(fee));
+		assert_eq!(e.kernel_excess.len(), 1);
+		let csv = wallet::export::txs_csv(&export);
+		assert_eq!(csv.lines().count(), txs.len() + 1);
+		assert!(csv.starts_with(wallet::export::CSV_HEADER));
+
+		// nothing created before the epoch, or after now
+		let (_, export) = api.export_txs(false, None, Some(Utc.timestamp(0, 0)))?;
+		assert!(export.is_empty());
+		let (_, export) = api.export_txs(false, Some(Utc::now()), None)?;
+		assert!(export.is_empty());
 		Ok(())
 	})?;
 
@@ -183,6 +240,7 @@ fn basic_transaction_api(test_dir: &str) -> Result<(), libwallet::Error> {
 			(wallet1_info.last_confirmed_height - cm) * reward - amount - fee
 		);
 		assert_eq!(wallet1_info.amount_immature, cm * reward + fee);
+		assert_eq!(wallet1_info.amount_unconfirmed_change, 0);
 
 		// check tx log entry is confirmed
 		let (refreshed, txs) = api.retrieve_txs(true, None)?;
@@ -450,6 +508,654 @@ fn tx_rollback(test_dir: &str) -> Result<(), libwallet::Error> {
 	Ok(())
 }
 
+/// Two sends in flight at once shouldn't be able to select the same outputs
+fn concurrent_send_locking(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 2);
+
+	{
+		let mut w = wallet1.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+
+		// start two sends, neither of which has been completed yet
+		let (slate1, context1, _) =
+			tx::create_send_tx(&mut **w, reward / 2, 1, 500, 1, false, &parent_key_id, false)?;
+		let (slate2, context2, _) =
+			tx::create_send_tx(&mut **w, reward / 2, 1, 500, 1, false, &parent_key_id, false)?;
+		let inputs1 = context1.get_inputs();
+		let inputs2 = context2.get_inputs();
+		assert!(!inputs1.is_empty());
+		assert!(!inputs2.is_empty());
+		assert!(inputs1.iter().all(|id| !inputs2.contains(id)));
+
+		let locks: Vec<OutputLock> = w.output_lock_iter()?.collect();
+		assert_eq!(locks.len(), inputs1.len() + inputs2.len());
+
+		// trying to lock the first slate's inputs for another fails
+		let outputs1: Vec<_> = inputs1.iter().map(|id| w.get(id).unwrap()).collect();
+		let res = selection::lock_outputs(&mut **w, &outputs1, &slate2.id, 0);
+		assert!(res.is_err());
+
+		// releasing the first slate frees up its inputs only
+		selection::release_output_locks(&mut **w, &slate1.id)?;
+		let locks: Vec<OutputLock> = w.output_lock_iter()?.collect();
+		assert_eq!(locks.len(), inputs2.len());
+		assert!(locks.iter().all(|l| l.slate_id == slate2.id));
+
+		// and locks are ignored once expired
+		let expiry = locks[0].expiry_height;
+		assert!(!locks[0].is_expired(expiry));
+		assert!(locks[0].is_expired(expiry + 1));
+		selection::lock_outputs(&mut **w, &outputs1, &slate1.id, expiry + 1)?;
+		assert_eq!(w.output_lock_iter()?.count(), inputs1.len());
+		w.close()?;
+	}
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Change split into outputs of a target size
+fn change_output_splitting(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 2);
+
+	let amount = reward / 4;
+	let target = reward / 10;
+	let mut slate = Slate::blank(1);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		// a single coinbase output leaves 3/4 of a reward as change, minus fees
+		let num_change = api.change_outputs_for_target(amount, 1, 500, false, target)?;
+		assert_eq!(num_change, 7);
+		let est = api.estimate_send_tx(amount, 1, 500, num_change, false)?;
+		assert_eq!(est.inputs.len(), 1);
+		assert!(est.change / num_change as u64 >= target);
+
+		slate = api.issue_send_tx(amount, 1, "wallet2", 500, num_change, false)?;
+		Ok(())
+	})?;
+
+	// change just over two targets with a single change output, but under
+	// with two as the fee goes up, settles on one rather than looping
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let change = reward - amount - wallet::libtx::tx_fee(1, 2, 1, None);
+		let num_change = api.change_outputs_for_target(amount, 1, 500, false, change / 2)?;
+		assert_eq!(num_change, 1);
+		Ok(())
+	})?;
+
+	// change should have come back as that many outputs, each at least the target
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, txs) = api.retrieve_txs(true, None)?;
+		let tx = txs
+			.iter()
+			.find(|t| t.tx_slate_id == Some(slate.id))
+			.unwrap();
+		assert_eq!(tx.num_outputs, 7);
+		let (_, outputs) = api.retrieve_outputs(false, false, Some(tx.id))?;
+		let change: Vec<_> = outputs
+			.iter()
+			.filter(|(o, _)| o.status == OutputStatus::Unconfirmed)
+			.collect();
+		assert_eq!(change.len(), 7);
+		assert!(change.iter().all(|(o, _)| o.value >= target));
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Sending to an address book contact
+fn contact_send(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 2);
+
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		api.add_contact("bob", "http", "wallet2")?;
+		assert!(api.add_contact("bob", "http", "wallet3").is_err());
+		assert!(api.add_contact("@carol", "http", "wallet3").is_err());
+		let contacts = api.contacts()?;
+		assert_eq!(contacts.len(), 1);
+		assert_eq!(contacts[0].dest, "wallet2");
+
+		// unknown contacts don't resolve
+		assert!(api.resolve_contact("@carol").is_err());
+		assert_eq!(api.resolve_contact("wallet3")?, None);
+
+		// sending to the contact, or its destination, records it
+		let slate = api.issue_send_tx(reward / 2, 1, "@bob", 500, 1, true)?;
+		api.post_tx(&slate, false)?;
+		let slate2 = api.issue_send_tx(reward / 4, 1, "wallet2", 500, 1, true)?;
+		let (_, txs) = api.retrieve_txs(false, None)?;
+		for id in &[slate.id, slate2.id] {
+			let tx = txs.iter().find(|t| t.tx_slate_id == Some(*id)).unwrap();
+			assert_eq!(tx.contact, Some("bob".to_owned()));
+		}
+
+		api.remove_contact("bob")?;
+		assert!(api.remove_contact("bob").is_err());
+		assert!(api.contacts()?.is_empty());
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Exchanging a slate in the armored format, as when pasting into a chat
+fn armored_slate_exchange(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 2);
+
+	let amount = reward / 2;
+	let mut armored = String::new();
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let slate = api.send_tx(false, amount, 1, "unused", 500, 1, true)?;
+		armored = armor::encode(&slate)?;
+		Ok(())
+	})?;
+
+	// much smaller than the JSON slate, and survives being pasted with
+	// surrounding text
+	let slate = armor::slate_from_str(&format!("here you go:\n{}\nthanks", armored))?;
+	assert!(armored.len() < serde_json::to_string(&slate).unwrap().len());
+	assert_eq!(armor::encode(&slate)?, armored);
+
+	// a single altered character fails the checksum
+	let pos = armored.find('\n').unwrap() + 10;
+	let mut corrupted = armored.clone().into_bytes();
+	corrupted[pos] = if corrupted[pos] == b'A' { b'B' } else { b'A' };
+	assert!(armor::decode(&String::from_utf8(corrupted).unwrap()).is_err());
+
+	// receive, respond and finalize using armored slates only
+	let mut slate = armor::decode(&armored)?;
+	wallet::controller::foreign_single_use(wallet2.clone(), |api| {
+		api.receive_tx(&mut slate)?;
+		Ok(())
+	})?;
+	let response = armor::encode(&slate)?;
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let mut slate = armor::decode(&response)?;
+		api.finalize_tx(&mut slate)?;
+		api.post_tx(&slate, false)?;
+		Ok(())
+	})?;
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 1);
+	wallet::controller::owner_single_use(wallet2.clone(), |api| {
+		let (_, wallet2_info) = api.retrieve_summary_info(true)?;
+		assert_eq!(wallet2_info.amount_currently_spendable, amount);
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Reposting a transaction finalized from a slate received back, as when
+/// the broadcast following the finalization got lost
+fn repost_finalized_tx(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 2);
+
+	let amount = reward / 2;
+	let mut sent = None;
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		sent = Some(api.send_tx(false, amount, 1, "unused", 500, 1, true)?);
+		Ok(())
+	})?;
+	let mut slate = sent.unwrap();
+	wallet::controller::foreign_single_use(wallet2.clone(), |api| {
+		api.receive_tx(&mut slate)?;
+		Ok(())
+	})?;
+
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, txs) = api.retrieve_txs(true, None)?;
+		let tx_id = txs
+			.iter()
+			.find(|t| t.tx_slate_id == Some(slate.id))
+			.unwrap()
+			.id;
+
+		// only the partial transaction is stored until finalized
+		assert!(api.post_stored_tx(tx_id, false).is_err());
+
+		// finalized but never posted
+		api.finalize_tx(&mut slate)?;
+		let stored = api.dump_stored_tx(tx_id, false, "")?;
+		assert_eq!(stored.hash(), slate.tx.hash());
+		api.post_stored_tx(tx_id, true)?;
+		Ok(())
+	})?;
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 1);
+	wallet::controller::owner_single_use(wallet2.clone(), |api| {
+		let (_, wallet2_info) = api.retrieve_summary_info(true)?;
+		assert_eq!(wallet2_info.amount_currently_spendable, amount);
+		Ok(())
+	})?;
+
+	// a confirmed transaction isn't reposted
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, txs) = api.retrieve_txs(true, None)?;
+		let tx = txs
+			.iter()
+			.find(|t| t.tx_slate_id == Some(slate.id))
+			.unwrap();
+		assert!(tx.confirmed);
+		assert!(api.post_stored_tx(tx.id, false).is_err());
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Adapter carrying slates straight to another wallet in the same process
+struct LocalSlateAdapter {
+	wallet: Arc<Mutex<Box<WalletInst<LocalWalletClient, ExtKeychain>>>>,
+}
+
+impl SlateAdapter for LocalSlateAdapter {
+	fn supports_sync(&self) -> bool {
+		true
+	}
+
+	fn send_tx_sync(&self, _dest: &str, slate: &Slate) -> Result<Slate, libwallet::Error> {
+		let mut slate = slate.clone();
+		wallet::controller::foreign_single_use(self.wallet.clone(), |api| {
+			api.receive_tx(&mut slate)?;
+			Ok(())
+		})?;
+		Ok(slate)
+	}
+
+	fn send_tx_async(&self, _dest: &str, _slate: &Slate) -> Result<(), libwallet::Error> {
+		unimplemented!()
+	}
+
+	fn receive_tx_async(&self, _source: &str) -> Result<Slate, libwallet::Error> {
+		unimplemented!()
+	}
+}
+
+/// Sending through registered slate adapters, picked by destination scheme
+fn slate_adapter_send(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 2);
+
+	let mut adapters = SlateAdapters::default();
+	adapters.register(
+		"local",
+		Box::new(LocalSlateAdapter {
+			wallet: wallet2.clone(),
+		}),
+	);
+	assert!(adapters.for_dest("local://wallet2", "http").unwrap().supports_sync());
+	assert!(!adapters.for_dest("slate.tx", "file").unwrap().supports_sync());
+	assert!(adapters.for_dest("tor://somewhere.onion", "http").unwrap().supports_sync());
+	assert!(adapters.for_dest("ipfs://somewhere", "http").is_none());
+
+	// synchronous adapter, completed straight away
+	let amount = reward / 4;
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let adapter = adapters.for_dest("local://wallet2", "http").unwrap();
+		let slate = api.send_tx_via_adapter(adapter, amount, 1, "local://wallet2", 500, 1, true)?;
+		api.post_tx(&slate, false)?;
+		Ok(())
+	})?;
+
+	// asynchronous file adapter, finalized once the response comes back
+	let dest = format!("file://{}/slate.tx", test_dir);
+	let response = format!("{}/slate.tx.response", test_dir);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let adapter = adapters.for_dest(&dest, "http").unwrap();
+		api.send_tx_via_adapter(adapter, amount, 1, &dest, 500, 1, true)?;
+		Ok(())
+	})?;
+	let (mut slate, armored) = FileSlateAdapter::read_slate(&dest)?;
+	assert!(!armored);
+	wallet::controller::foreign_single_use(wallet2.clone(), |api| {
+		api.receive_tx(&mut slate)?;
+		Ok(())
+	})?;
+	FileSlateAdapter::new(true).send_tx_async(&response, &slate)?;
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let mut slate = adapters.get("file").unwrap().receive_tx_async(&response)?;
+		api.finalize_tx(&mut slate)?;
+		api.post_tx(&slate, false)?;
+		Ok(())
+	})?;
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 1);
+	wallet::controller::owner_single_use(wallet2.clone(), |api| {
+		let (_, wallet2_info) = api.retrieve_summary_info(true)?;
+		assert_eq!(wallet2_info.amount_currently_spendable, amount * 2);
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Sends that are never finalized expire and are cancelled
+fn pending_tx_expiry(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	let client = LocalWalletClient::new("wallet2", wallet_proxy.tx.clone());
+	let wallet2 = common::create_wallet(&format!("{}/wallet2", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet2", client.get_send_instance(), wallet2.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 2);
+
+	// one send completed straight away but not posted yet, and one whose
+	// response never comes back
+	let dest = format!("{}/slate.tx", test_dir);
+	let mut stale_slate = Slate::blank(1);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		api.issue_send_tx(reward / 4, 1, "wallet2", 500, 1, false)?;
+		stale_slate = api.send_tx(true, reward / 4, 1, &dest, 500, 1, false)?;
+		Ok(())
+	})?;
+
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, info_before) = api.retrieve_summary_info(true)?;
+
+		// nothing is old enough yet
+		let expired = api.cancel_expired_txs(chrono::Duration::hours(1), false)?;
+		assert!(expired.is_empty());
+
+		// a dry run lists the unfinalized send only, without cancelling it
+		let expired = api.cancel_expired_txs(chrono::Duration::zero(), true)?;
+		assert_eq!(expired.len(), 1);
+		assert_eq!(expired[0].tx_slate_id, Some(stale_slate.id));
+		let (_, txs) = api.retrieve_txs(true, None)?;
+		let tx = txs.iter().find(|t| t.tx_slate_id == Some(stale_slate.id));
+		assert_eq!(tx.unwrap().tx_type, TxLogEntryType::TxSent);
+
+		let expired = api.cancel_expired_txs(chrono::Duration::zero(), false)?;
+		assert_eq!(expired.len(), 1);
+		let (_, txs) = api.retrieve_txs(true, None)?;
+		let tx = txs.iter().find(|t| t.tx_slate_id == Some(stale_slate.id));
+		assert_eq!(tx.unwrap().tx_type, TxLogEntryType::TxSentCancelled);
+		assert_eq!(
+			txs.iter()
+				.filter(|t| t.tx_type == TxLogEntryType::TxSent)
+				.count(),
+			1
+		);
+
+		// its inputs are spendable again
+		let (_, info_after) = api.retrieve_summary_info(true)?;
+		assert!(info_after.amount_currently_spendable > info_before.amount_currently_spendable);
+		assert!(api.cancel_expired_txs(chrono::Duration::zero(), false)?.is_empty());
+		Ok(())
+	})?;
+
+	// the cancelled slate can't be finalized anymore
+	let (mut slate, _) = FileSlateAdapter::read_slate(&dest)?;
+	wallet::controller::foreign_single_use(wallet2.clone(), |api| {
+		api.receive_tx(&mut slate)?;
+		Ok(())
+	})?;
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		assert!(api.finalize_tx(&mut slate).is_err());
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
+/// Many outputs merged into few, in batches
+fn output_consolidation(test_dir: &str) -> Result<(), libwallet::Error> {
+	setup(test_dir);
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+
+	// Set the wallet proxy listener running
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+
+	let reward = core::consensus::REWARD;
+	let cm = global::coinbase_maturity();
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), cm as usize + 7);
+
+	// consolidations cancelled, as when a later batch fails, leave nothing
+	// locked behind
+	let mut slate_ids = vec![];
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		slate_ids = api
+			.consolidate(1, None, 2, 3)?
+			.iter()
+			.map(|s| s.id)
+			.collect();
+		Ok(())
+	})?;
+	assert!(!slate_ids.is_empty());
+	{
+		let mut w = wallet1.lock();
+		w.open_with_credentials()?;
+		let parent_key_id = w.parent_key_id();
+		for id in &slate_ids {
+			tx::cancel_slate_txs(&mut **w, &parent_key_id, id)?;
+		}
+		w.close()?;
+	}
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, outputs) = api.retrieve_outputs(false, true, None)?;
+		assert!(outputs
+			.iter()
+			.all(|(o, _)| o.is_coinbase && o.status == OutputStatus::Unspent));
+		let (_, txs) = api.retrieve_txs(false, None)?;
+		let cancelled = txs
+			.iter()
+			.filter(|t| {
+				t.tx_type == TxLogEntryType::TxSentCancelled
+					|| t.tx_type == TxLogEntryType::TxReceivedCancelled
+			})
+			.count();
+		assert_eq!(cancelled, slate_ids.len() * 2);
+		Ok(())
+	})?;
+
+	let mut num_slates = 0;
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, info) = api.retrieve_summary_info(true)?;
+		let spendable = (info.amount_currently_spendable / reward) as usize;
+		assert!(spendable > 3);
+
+		// not enough or not small enough outputs
+		assert!(api.consolidate(1, None, spendable + 1, 3)?.is_empty());
+		assert!(api.consolidate(1, Some(reward), 2, 3)?.is_empty());
+
+		let slates = api.consolidate(1, None, 2, 3)?;
+		// batches of 3, leaving out a final lone output
+		let expected = spendable / 3 + if spendable % 3 == 2 { 1 } else { 0 };
+		assert_eq!(slates.len(), expected);
+		for slate in &slates {
+			assert!(slate.tx.inputs().len() <= 3);
+			assert_eq!(slate.tx.outputs().len(), 1);
+			api.post_tx(slate, false)?;
+		}
+		num_slates = slates.len();
+		Ok(())
+	})?;
+
+	let _ = common::award_blocks_to_wallet(&chain, wallet1.clone(), 1);
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		let (_, txs) = api.retrieve_txs(true, None)?;
+		let count = |tx_type| txs.iter().filter(|t| t.tx_type == tx_type).count();
+		assert_eq!(count(TxLogEntryType::TxSentSelf), num_slates);
+		// the merged output confirms the receiving side
+		let received: Vec<_> = txs
+			.iter()
+			.filter(|t| t.tx_type == TxLogEntryType::TxReceivedSelf)
+			.collect();
+		assert_eq!(received.len(), num_slates);
+		assert!(received.iter().all(|t| t.confirmed));
+		let (_, outputs) = api.retrieve_outputs(false, true, None)?;
+		let merged = outputs
+			.iter()
+			.filter(|(o, _)| !o.is_coinbase && o.status == OutputStatus::Unspent)
+			.count();
+		assert_eq!(merged, num_slates);
+		Ok(())
+	})?;
+
+	// let logging finish
+	thread::sleep(Duration::from_millis(200));
+	Ok(())
+}
+
 #[test]
 fn db_wallet_basic_transaction_api() {
 	let test_dir = "test_output/basic_transaction_api";
@@ -465,3 +1171,67 @@ fn db_wallet_tx_rollback() {
 		println!("Libwallet Error: {}", e);
 	}
 }
+
+#[test]
+fn db_wallet_concurrent_send_locking() {
+	let test_dir = "test_output/concurrent_send_locking";
+	if let Err(e) = concurrent_send_locking(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
+
+#[test]
+fn db_wallet_change_output_splitting() {
+	let test_dir = "test_output/change_output_splitting";
+	if let Err(e) = change_output_splitting(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
+
+#[test]
+fn db_wallet_contact_send() {
+	let test_dir = "test_output/contact_send";
+	if let Err(e) = contact_send(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
+
+#[test]
+fn db_wallet_armored_slate_exchange() {
+	let test_dir = "test_output/armored_slate_exchange";
+	if let Err(e) = armored_slate_exchange(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
+
+#[test]
+fn db_wallet_slate_adapter_send() {
+	let test_dir = "test_output/slate_adapter_send";
+	if let Err(e) = slate_adapter_send(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
+
+#[test]
+fn db_wallet_pending_tx_expiry() {
+	let test_dir = "test_output/pending_tx_expiry";
+	if let Err(e) = pending_tx_expiry(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
+
+#[test]
+fn db_wallet_output_consolidation() {
+	let test_dir = "test_output/output_consolidation";
+	if let Err(e) = output_consolidation(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
+
+#[test]
+fn db_wallet_repost_finalized_tx() {
+	let test_dir = "test_output/repost_finalized_tx";
+	if let Err(e) = repost_finalized_tx(test_dir) {
+		println!("Libwallet Error: {}", e);
+	}
+}
@@ -0,0 +1,177 @@
+// Copyright 2018 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! tests the events published as transactions of the wallet change
+extern crate futures;
+extern crate grin_api as api;
+extern crate grin_chain as chain;
+extern crate grin_core as core;
+extern crate grin_keychain as keychain;
+extern crate grin_store as store;
+extern crate grin_util as util;
+extern crate grin_wallet as wallet;
+extern crate hyper;
+extern crate rand;
+#[macro_use]
+extern crate log;
+extern crate chrono;
+extern crate serde;
+extern crate serde_json;
+extern crate tokio;
+extern crate uuid;
+
+mod common;
+use common::testclient::{LocalWalletClient, WalletProxy};
+
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use api::Handler;
+use core::global;
+use core::global::ChainTypes;
+use futures::{Future, Stream};
+use hyper::{Body, Request};
+use keychain::{ExtKeychain, Identifier};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+use wallet::controller::OwnerAPIHandler;
+use wallet::libwallet::events::{self, TxEventHub, TxEventKind, TxEvents};
+use wallet::libwallet::types::{TxLogEntry, TxLogEntryType};
+
+fn tx(id: u32, tx_type: TxLogEntryType, slate_id: Uuid) -> TxLogEntry {
+	let mut tx = TxLogEntry::new(Identifier::zero(), tx_type, id);
+	tx.tx_slate_id = Some(slate_id);
+	tx
+}
+
+fn kinds(hub: &TxEventHub, since: u64) -> Vec<(TxEventKind, u32)> {
+	hub.since(since)
+		.events
+		.iter()
+		.map(|e| (e.kind, e.tx_id))
+		.collect()
+}
+
+#[test]
+fn tx_events() {
+	let hub = TxEventHub::new();
+	let sent_id = Uuid::new_v4();
+	let mut sent = tx(1, TxLogEntryType::TxSent, sent_id);
+	sent.tx_hex = Some("00".to_owned());
+
+	// what's there to start with isn't an event
+	hub.update(&[sent.clone()]);
+	assert_eq!(hub.since(0).last_id, 0);
+	let waiting = hub.wait(0);
+
+	// a slate received, then the one sent finalized
+	let received = tx(2, TxLogEntryType::TxReceived, Uuid::new_v4());
+	hub.update(&[sent.clone(), received.clone()]);
+	sent.tx_hex = Some("0011".to_owned());
+	hub.update(&[sent.clone(), received.clone()]);
+	assert_eq!(
+		kinds(&hub, 0),
+		vec![(TxEventKind::Received, 2), (TxEventKind::Finalized, 1)]
+	);
+	// clients waiting got woken up
+	assert!(waiting.wait().is_ok());
+
+	// posted and confirmed
+	hub.posted(sent_id, &[sent.clone(), received.clone()]);
+	sent.confirmed = true;
+	hub.update(&[sent.clone(), received.clone()]);
+	assert_eq!(
+		kinds(&hub, 2),
+		vec![(TxEventKind::Broadcast, 1), (TxEventKind::Confirmed, 1)]
+	);
+
+	// the one received cancelled, once only
+	let mut cancelled = received.clone();
+	cancelled.tx_type = TxLogEntryType::TxReceivedCancelled;
+	hub.update(&[sent.clone(), cancelled.clone()]);
+	hub.update(&[sent.clone(), cancelled.clone()]);
+	assert_eq!(kinds(&hub, 4), vec![(TxEventKind::Cancelled, 2)]);
+	assert_eq!(hub.since(5).last_id, 5);
+	assert!(hub.since(5).events.is_empty());
+
+	// already past, no wait
+	assert!(hub.wait(4).wait().is_ok());
+}
+
+// Long-polls the events of the owner API past the given one
+fn get_tx_events<H: Handler>(handler: &H, since: u64, timeout: u64) -> TxEvents {
+	let url = format!(
+		"http://127.0.0.1/v1/wallet/owner/tx_events?since={}&timeout={}",
+		since, timeout
+	);
+	let req = Request::get(url).body(Body::empty()).unwrap();
+	let res = Runtime::new().unwrap().block_on(handler.get(req)).unwrap();
+	let body = res.into_body().concat2().wait().unwrap();
+	serde_json::from_slice(&body).unwrap()
+}
+
+#[test]
+fn tx_events_long_poll() {
+	let test_dir = "test_output/tx_events_long_poll";
+	util::init_test_logger();
+	let _ = fs::remove_dir_all(test_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+
+	let mut wallet_proxy: WalletProxy<LocalWalletClient, ExtKeychain> = WalletProxy::new(test_dir);
+	let chain = wallet_proxy.chain.clone();
+	let client = LocalWalletClient::new("wallet1", wallet_proxy.tx.clone());
+	let wallet1 = common::create_wallet(&format!("{}/wallet1", test_dir), client.clone());
+	wallet_proxy.add_wallet("wallet1", client.get_send_instance(), wallet1.clone());
+	thread::spawn(move || {
+		if let Err(e) = wallet_proxy.run() {
+			error!("Wallet Proxy error: {}", e);
+		}
+	});
+	common::award_blocks_to_wallet(&chain, wallet1.clone(), 2).unwrap();
+	wallet::controller::owner_single_use(wallet1.clone(), |api| {
+		api.retrieve_txs(true, None)?;
+		Ok(())
+	}).unwrap();
+
+	let handler = Arc::new(OwnerAPIHandler::new(wallet1.clone()));
+	events::start(
+		wallet1.clone(),
+		handler.tx_events.clone(),
+		Duration::from_millis(200),
+	).unwrap();
+
+	// nothing happening, answered once timed out and no longer waiting
+	let start = Instant::now();
+	let res = get_tx_events(&*handler, 0, 1);
+	assert!(start.elapsed() >= Duration::from_secs(1));
+	assert_eq!(res.last_id, 0);
+	assert!(res.events.is_empty());
+	assert!(!handler.tx_events.has_waiters());
+
+	// answered as soon as a block is mined to the wallet
+	let waiting = handler.clone();
+	let poll = thread::spawn(move || get_tx_events(&*waiting, 0, 60));
+	thread::sleep(Duration::from_millis(500));
+	assert!(handler.tx_events.has_waiters());
+	let start = Instant::now();
+	common::award_blocks_to_wallet(&chain, wallet1.clone(), 1).unwrap();
+	let res = poll.join().unwrap();
+	assert!(start.elapsed() < Duration::from_secs(30));
+	assert!(res.last_id > 0);
+	assert!(res.events.iter().any(|e| e.kind == TxEventKind::Confirmed));
+	assert!(!handler.tx_events.has_waiters());
+}